@@ -0,0 +1,87 @@
+//! Parsing speed of the JSON backends on large response bodies.
+//!
+//! ```sh
+//! cargo bench --bench json_backend                       # serde_json only
+//! cargo bench --bench json_backend --features simd-json  # serde_json and simd-json
+//! ```
+//!
+//! Each body is parsed into its typed model with every [`JsonBackend`] the build supports,
+//! so the reports of one run compare the backends on the same input.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use gemini_rust::{BatchContentEmbeddingResponse, GenerationResponse, JsonBackend};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::hint::black_box;
+
+/// A structured answer of `rows` records, with a function call per ten records
+fn structured_response(rows: usize) -> Vec<u8> {
+    let records: Vec<Value> = (0..rows)
+        .map(|i| {
+            json!({
+                "id": i,
+                "name": format!("Product {i}"),
+                "description": "A sturdy, water-resistant \"everyday\" bag with two pockets.",
+                "price": 19.99 + i as f64,
+                "tags": ["outdoor", "travel", "gift"],
+                "in_stock": i % 3 != 0
+            })
+        })
+        .collect();
+    let mut parts = vec![json!({"text": serde_json::to_string(&records).unwrap()})];
+    parts.extend((0..rows / 10).map(|i| {
+        json!({"functionCall": {"name": "lookup_inventory", "args": {
+            "sku": format!("SKU-{i:05}"),
+            "warehouses": ["AMS", "FRA", "LIS"],
+            "quantity": i
+        }}})
+    }));
+    serde_json::to_vec(&json!({
+        "candidates": [{
+            "content": {"role": "model", "parts": parts},
+            "finishReason": "STOP",
+            "index": 0
+        }],
+        "usageMetadata": {"promptTokenCount": 812, "candidatesTokenCount": 20480},
+        "modelVersion": "gemini-2.5-flash"
+    }))
+    .unwrap()
+}
+
+/// Embeddings of `texts` texts with 768 dimensions each
+fn embeddings_response(texts: usize) -> Vec<u8> {
+    let embeddings: Vec<Value> = (0..texts)
+        .map(|t| {
+            let values: Vec<f32> = (0..768)
+                .map(|d| ((t * 768 + d) as f32 * 0.618).sin() * 0.05)
+                .collect();
+            json!({"values": values})
+        })
+        .collect();
+    serde_json::to_vec(&json!({"embeddings": embeddings})).unwrap()
+}
+
+fn bench_body<T: DeserializeOwned>(c: &mut Criterion, name: &str, body: &[u8]) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(body.len() as u64));
+    for backend in JsonBackend::available() {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{backend:?}")),
+            body,
+            |b, body| b.iter(|| backend.from_slice::<T>(black_box(body)).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn json_backends(c: &mut Criterion) {
+    bench_body::<GenerationResponse>(c, "generate_content", &structured_response(2_000));
+    bench_body::<BatchContentEmbeddingResponse>(
+        c,
+        "batch_embed_contents",
+        &embeddings_response(100),
+    );
+}
+
+criterion_group!(benches, json_backends);
+criterion_main!(benches);
@@ -0,0 +1,392 @@
+//! A small research agent combining a chat session, registry tools, streaming, and a
+//! structured final report.
+//!
+//! The agent answers a question with two local tools, a calculator and a mock web lookup,
+//! streams a briefing to the terminal with its thoughts set apart from the answer, and ends
+//! with a report read with `execute_as`.
+//!
+//! Usage:
+//!
+//! ```text
+//! GEMINI_API_KEY=... cargo run --example agent -- [question]
+//! cargo run --example agent -- --replay tests/cassettes/agent.json
+//! ```
+//!
+//! With `--replay`, no API key is needed: the client talks to a local server answering every
+//! request with the next interaction of the cassette, and fails the run if a request does not
+//! match it. `tests/agent.rs` runs the agent this way, so the pipeline is verified offline.
+
+use display_error_chain::DisplayErrorChain;
+use futures::TryStreamExt;
+use gemini_rust::{
+    ChatSession, FunctionCall, FunctionDeclaration, Gemini, GeminiBuilder, GenerationConfig,
+    Message, Part, Role, ThinkingConfig, ToolRegistry, ToolResult,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    env,
+    error::Error,
+    io::Write,
+    path::Path,
+    process::ExitCode,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::info;
+use url::Url;
+
+const DEFAULT_QUESTION: &str = "What is the population density of Lisbon?";
+
+const SYSTEM_INSTRUCTION: &str = "You are a research assistant. Look facts up with the \
+    `lookup` tool rather than recalling them, and use the `calculate` tool for arithmetic.";
+
+/// Mock search results, keyed by query
+const FACTS: &[(&str, &str)] = &[
+    (
+        "population of lisbon",
+        "Lisbon had 545,796 residents at the 2021 census.",
+    ),
+    (
+        "area of lisbon",
+        "The municipality of Lisbon covers 100.05 square kilometres.",
+    ),
+    (
+        "population of porto",
+        "Porto had 231,800 residents at the 2021 census.",
+    ),
+];
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+struct Lookup {
+    /// What to search the web for, e.g. "population of Lisbon"
+    query: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum Operation {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+struct Calculation {
+    /// The operation to perform
+    operation: Operation,
+    /// The first operand
+    a: f64,
+    /// The second operand
+    b: f64,
+}
+
+/// The structured outcome of a research run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Report {
+    /// The question researched
+    pub question: String,
+    /// A one-sentence answer
+    pub answer: String,
+    /// The facts the answer relies on, as found by the lookups
+    pub sources: Vec<String>,
+    /// Confidence in the answer, from 0 to 1
+    pub confidence: f64,
+}
+
+async fn lookup(call: FunctionCall) -> ToolResult {
+    let Lookup { query } = serde_json::from_value(call.args)?;
+    let key = query.trim().to_lowercase();
+    let results: Vec<&str> = FACTS
+        .iter()
+        .filter(|(topic, _)| key.contains(topic) || topic.contains(key.as_str()))
+        .map(|(_, fact)| *fact)
+        .collect();
+    Ok(json!({ "query": query, "results": results }))
+}
+
+async fn calculate(call: FunctionCall) -> ToolResult {
+    let Calculation { operation, a, b } = serde_json::from_value(call.args)?;
+    let result = match operation {
+        Operation::Add => a + b,
+        Operation::Subtract => a - b,
+        Operation::Multiply => a * b,
+        Operation::Divide if b == 0.0 => return Err("division by zero".into()),
+        Operation::Divide => a / b,
+    };
+    Ok(json!({ "result": result }))
+}
+
+fn registry() -> ToolRegistry {
+    let lookup_declaration =
+        FunctionDeclaration::new("lookup", "Search the web and return matching facts", None)
+            .with_parameters::<Lookup>();
+    let calculate_declaration =
+        FunctionDeclaration::new("calculate", "Perform an arithmetic operation", None)
+            .with_parameters::<Calculation>();
+    ToolRegistry::new()
+        .with_function(lookup_declaration, lookup)
+        .with_function(calculate_declaration, calculate)
+        .with_max_steps(6)
+}
+
+/// Streams the reply to `prompt`, writing thoughts and answer text under separate headings
+async fn stream_briefing(
+    session: &mut ChatSession,
+    prompt: &str,
+    out: &mut impl Write,
+) -> Result<(), Box<dyn Error>> {
+    let mut stream = session.send_message_stream(prompt).await?;
+    let mut heading = None;
+    while let Some(chunk) = stream.try_next().await? {
+        let parts = chunk
+            .candidates
+            .first()
+            .and_then(|candidate| candidate.content.parts.as_deref())
+            .unwrap_or_default();
+        for part in parts {
+            let Part::Text { text, thought, .. } = part else {
+                continue;
+            };
+            let section = match thought {
+                Some(true) => "thinking",
+                _ => "answer",
+            };
+            if heading != Some(section) {
+                writeln!(out, "\n── {section} ──")?;
+                heading = Some(section);
+            }
+            write!(out, "{text}")?;
+            out.flush()?;
+        }
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Researches `question`: a tool-using turn, a streamed briefing, and a structured report
+pub async fn run(
+    client: &Gemini,
+    question: &str,
+    out: &mut impl Write,
+) -> Result<Report, Box<dyn Error>> {
+    let config = GenerationConfig {
+        thinking_config: Some(ThinkingConfig::dynamic_thinking()),
+        ..Default::default()
+    };
+    let mut session = client
+        .start_chat()
+        .with_system_instruction(SYSTEM_INSTRUCTION)
+        .with_generation_config(config);
+
+    let researched = session
+        .send_message_with_tools(question, &registry())
+        .await?;
+    for exchange in &researched.tool_transcript {
+        let response = exchange.response.response.clone().unwrap_or_default();
+        writeln!(
+            out,
+            "tool {}({}) -> {response}",
+            exchange.call.name, exchange.call.args
+        )?;
+    }
+
+    stream_briefing(
+        &mut session,
+        "Write a two-sentence briefing of your findings for a city planner.",
+        out,
+    )
+    .await?;
+
+    let history = session.history().iter().map(|content| Message {
+        role: content.role.clone().unwrap_or(Role::User),
+        content: content.clone(),
+    });
+    let report = client
+        .generate_content()
+        .with_system_instruction(SYSTEM_INSTRUCTION)
+        .with_messages(history)
+        .with_user_message(format!(
+            "Summarize the research on \"{question}\" as a report, \
+             listing the facts you looked up as sources."
+        ))
+        .with_response_schema_of::<Report>()?
+        .execute_as::<Report>()
+        .await?;
+    info!(turns = session.turns().len(), "research finished");
+    Ok(report)
+}
+
+/// One recorded request and its response
+#[derive(Debug, Clone, Deserialize)]
+pub struct Interaction {
+    /// The API method the request calls, e.g. `generateContent`
+    pub method: String,
+    /// Strings the request body must contain, e.g. the result of a tool call
+    #[serde(default)]
+    pub request_contains: Vec<String>,
+    /// The body of a `generateContent` response
+    #[serde(default)]
+    pub response: Option<Value>,
+    /// The events of a `streamGenerateContent` response
+    #[serde(default)]
+    pub chunks: Vec<Value>,
+}
+
+/// Serves the interactions of the cassette at `path` in order on a local port and returns
+/// the base URL to point a client at.
+///
+/// A request for another method, or missing an expected string, is answered with a `400`
+/// naming the mismatch, as is any request beyond the end of the cassette.
+pub async fn replay(path: &Path) -> Result<Url, Box<dyn Error>> {
+    let interactions: Vec<Interaction> = serde_json::from_slice(&std::fs::read(path)?)?;
+    let queue = Arc::new(Mutex::new(VecDeque::from(interactions)));
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = Url::parse(&format!("http://{}/v1beta/", listener.local_addr()?))?;
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                // A failed exchange shows up as an error on the client side
+                let _ = serve(stream, &queue).await;
+            });
+        }
+    });
+    Ok(url)
+}
+
+/// Answers one request with the next interaction, then closes the connection
+async fn serve(mut stream: TcpStream, queue: &Mutex<VecDeque<Interaction>>) -> std::io::Result<()> {
+    let mut buffer = Vec::new();
+    let header_end = loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        let mut chunk = [0u8; 4096];
+        match stream.read(&mut chunk).await? {
+            0 => return Ok(()),
+            read => buffer.extend_from_slice(&chunk[..read]),
+        }
+    };
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+    let length: usize = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0);
+    while buffer.len() < header_end + 4 + length {
+        let mut chunk = [0u8; 65536];
+        match stream.read(&mut chunk).await? {
+            0 => return Ok(()),
+            read => buffer.extend_from_slice(&chunk[..read]),
+        }
+    }
+    let body = String::from_utf8_lossy(&buffer[header_end + 4..header_end + 4 + length]);
+
+    let next = queue.lock().unwrap().pop_front();
+    let (status, content_type, payload) = match next {
+        None => mismatch(format!("no interaction left for {path}")),
+        Some(interaction) if !path.contains(&format!(":{}", interaction.method)) => mismatch(
+            format!("expected a {} request, got {path}", interaction.method),
+        ),
+        Some(interaction) => match interaction
+            .request_contains
+            .iter()
+            .find(|expected| !body.contains(expected.as_str()))
+        {
+            Some(missing) => mismatch(format!("request body does not contain {missing:?}")),
+            None if interaction.method == "streamGenerateContent" => {
+                let events: String = interaction
+                    .chunks
+                    .iter()
+                    .map(|chunk| format!("data: {chunk}\r\n\r\n"))
+                    .collect();
+                (200, "text/event-stream", events)
+            }
+            None => (
+                200,
+                "application/json",
+                interaction.response.unwrap_or_default().to_string(),
+            ),
+        },
+    };
+    let reason = if status == 200 { "OK" } else { "Bad Request" };
+    let response = format!(
+        "HTTP/1.1 {status} {reason}\r\ncontent-type: {content_type}\r\n\
+         content-length: {}\r\nconnection: close\r\n\r\n{payload}",
+        payload.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// A `400` response in the API's error format
+fn mismatch(message: String) -> (u16, &'static str, String) {
+    let body = json!({
+        "error": {"code": 400, "message": format!("cassette mismatch: {message}"), "status": "INVALID_ARGUMENT"}
+    });
+    (400, "application/json", body.to_string())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    match do_main().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let error_chain = DisplayErrorChain::new(e.as_ref());
+            tracing::error!(error.debug = ?e, error.chained = %error_chain, "execution failed");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn do_main() -> Result<(), Box<dyn Error>> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let cassette = match args.iter().position(|arg| arg == "--replay") {
+        Some(index) if index + 1 < args.len() => {
+            let path = args.remove(index + 1);
+            args.remove(index);
+            Some(path)
+        }
+        Some(_) => return Err("usage: agent [--replay cassette.json] [question]".into()),
+        None => None,
+    };
+    let question = match args.is_empty() {
+        true => DEFAULT_QUESTION.to_string(),
+        false => args.join(" "),
+    };
+
+    let client = match cassette {
+        Some(path) => {
+            info!(cassette = path, "replaying offline");
+            GeminiBuilder::new("replay")
+                .with_base_url(replay(Path::new(&path)).await?)
+                .build()?
+        }
+        None => {
+            let api_key =
+                env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY environment variable not set");
+            Gemini::new(api_key)?
+        }
+    };
+
+    let report = run(&client, &question, &mut std::io::stdout()).await?;
+    println!("\n{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
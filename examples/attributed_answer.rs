@@ -0,0 +1,99 @@
+//! Attributed answers with citation verification.
+//!
+//! This example runs offline: it builds an attributed answer request to show its shape, then
+//! verifies a canned model response in which one of the citations is fabricated.
+
+use gemini_rust::{AttributedResult, CitationStatus, Document, Gemini, GenerationResponse};
+use serde_json::json;
+use std::process::ExitCode;
+use tracing::{info, warn};
+
+fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    match do_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            tracing::error!(error = %e, "execution failed");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn do_main() -> Result<(), Box<dyn std::error::Error>> {
+    let documents = vec![
+        Document::new(
+            "harbour-bridge",
+            "The Sydney Harbour Bridge was opened on 19 March 1932. \
+             Locals nicknamed it \u{201C}the Coathanger\u{201D} because of its arch-based design.",
+        )
+        .with_title("Sydney Harbour Bridge"),
+        Document::new(
+            "opera-house",
+            "The Sydney Opera House was formally opened by Queen Elizabeth II in 1973.",
+        ),
+    ];
+
+    // The API key is never used: no request is sent
+    let client = Gemini::new("offline")?;
+    let request = client.generate_content().attributed_answer(
+        documents.clone(),
+        "When did the bridge open, and what is it called?",
+    );
+    info!(outline = %request.content_builder().debug_outline(), "request built");
+
+    // A canned response; the second quote does not appear in the cited document
+    let canned: GenerationResponse = serde_json::from_value(json!({
+        "candidates": [{
+            "content": {
+                "role": "model",
+                "parts": [{
+                    "text": json!({
+                        "answer": "The bridge opened on 19 March 1932 and is nicknamed the Coathanger.",
+                        "citations": [
+                            {"doc_id": "harbour-bridge", "quote": "opened on 19 March 1932", "start_hint": 31},
+                            {"doc_id": "harbour-bridge", "quote": "opened by Queen Elizabeth II"},
+                            {"doc_id": "harbour-bridge", "quote": "nicknamed it \"the   Coathanger\""}
+                        ]
+                    }).to_string()
+                }]
+            },
+            "finishReason": "STOP"
+        }]
+    }))?;
+
+    let result = AttributedResult::from_response(&canned, &documents)?;
+    info!(answer = result.answer, "answer");
+    for citation in &result.citations {
+        match citation.status {
+            CitationStatus::Verified { offset } => {
+                info!(
+                    doc_id = citation.citation.doc_id,
+                    quote = citation.citation.quote,
+                    offset,
+                    "verified citation"
+                )
+            }
+            status => {
+                warn!(
+                    doc_id = citation.citation.doc_id,
+                    quote = citation.citation.quote,
+                    ?status,
+                    "fabricated citation"
+                )
+            }
+        }
+    }
+    info!(
+        fabricated = result.fabricated().count(),
+        fully_attributed = result.is_fully_attributed(),
+        "verification finished"
+    );
+    Ok(())
+}
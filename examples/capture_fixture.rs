@@ -0,0 +1,92 @@
+//! Records a live API response into the fixture corpus under `tests/fixtures/`.
+//!
+//! Usage: `cargo run --example capture_fixture -- <generate_content|stream_chunk> <name> <prompt>`
+//!
+//! The response is captured untyped, so fields the crate does not model yet are preserved and
+//! show up as failures in the golden tests. API keys and e-mail addresses are scrubbed before
+//! anything is written.
+
+use display_error_chain::DisplayErrorChain;
+use futures::TryStreamExt;
+use gemini_rust::Gemini;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::env;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tracing::info;
+
+const MODEL: &str = "gemini-2.5-flash";
+
+/// Replaces anything that looks like a credential or personal address
+fn scrub(value: Value) -> Value {
+    let keys = Regex::new(r"AIza[0-9A-Za-z_-]{35}").unwrap();
+    let emails = Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap();
+    let text = value.to_string();
+    let text = keys.replace_all(&text, "REDACTED_API_KEY");
+    let text = emails.replace_all(&text, "redacted@example.com");
+    serde_json::from_str(&text).expect("scrubbing keeps the JSON valid")
+}
+
+fn write_fixture(kind: &str, name: &str, value: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(kind);
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{name}.json"));
+    std::fs::write(&path, serde_json::to_string_pretty(&scrub(value))? + "\n")?;
+    info!(path = %path.display(), "fixture written");
+    Ok(())
+}
+
+async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY environment variable not set");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [kind, name, prompt] = args.as_slice() else {
+        return Err(
+            "usage: capture_fixture <generate_content|stream_chunk> <name> <prompt>".into(),
+        );
+    };
+
+    let client = Gemini::new(api_key)?;
+    let body = json!({"contents": [{"role": "user", "parts": [{"text": prompt}]}]});
+
+    match kind.as_str() {
+        "generate_content" => {
+            let response = client.generate_content_raw(MODEL, body).await?;
+            write_fixture(kind, name, response)?;
+        }
+        "stream_chunk" => {
+            let chunks: Vec<Value> = client
+                .generate_content_raw_stream(MODEL, body)
+                .await?
+                .try_collect()
+                .await?;
+            for (i, chunk) in chunks.into_iter().enumerate() {
+                write_fixture(kind, &format!("{name}_{i}"), chunk)?;
+            }
+        }
+        other => return Err(format!("cannot capture fixtures of kind '{other}'").into()),
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    match do_main().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let error_chain = DisplayErrorChain::new(e.as_ref());
+            tracing::error!(error.debug = ?e, error.chained = %error_chain, "execution failed");
+            ExitCode::FAILURE
+        }
+    }
+}
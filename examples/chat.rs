@@ -0,0 +1,111 @@
+use display_error_chain::DisplayErrorChain;
+use futures::TryStreamExt;
+use gemini_rust::{Content, Gemini, Message, Part};
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+
+/// Interactive chat REPL - demonstrates a stateful chat session with streamed replies
+///
+/// Type a message and press Enter. Commands:
+/// - `/history` prints the conversation so far
+/// - `/truncate <turns>` keeps the first turns only
+/// - `/clear` starts over
+/// - `/quit` exits
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(tracing::level_filters::LevelFilter::WARN.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    match do_main().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let error_chain = DisplayErrorChain::new(e.as_ref());
+            tracing::error!(error.debug = ?e, error.chained = %error_chain, "execution failed");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
+    // Get API key from environment variable
+    let api_key = env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY environment variable not set");
+
+    let client = Gemini::new(api_key)?;
+
+    // The system instruction is sent with every message of the session, and the session
+    // starts from a prior exchange
+    let mut chat = client
+        .start_chat()
+        .with_system_instruction("You are a concise, friendly assistant.")
+        .with_history([
+            Message::user("Hi! I'm planning a trip to Lisbon.").content,
+            Message::model("Lovely choice! How can I help with your trip?").content,
+        ]);
+
+    println!("Chat with Gemini. Commands: /history, /truncate <turns>, /clear, /quit");
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        print!("\n> ");
+        io::stdout().flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            break;
+        };
+        let line = line.trim();
+        match line.split_once(' ').unwrap_or((line, "")) {
+            ("", _) => continue,
+            ("/quit", _) => break,
+            ("/history", _) => print_history(chat.history()),
+            ("/clear", _) => {
+                chat.clear();
+                println!("(history cleared)");
+            }
+            ("/truncate", turns) => match turns.trim().parse() {
+                Ok(turns) => {
+                    chat.truncate(turns);
+                    println!("(kept {} turn(s))", chat.turns().len());
+                }
+                Err(_) => println!("usage: /truncate <turns>"),
+            },
+            _ => {
+                // Print the reply as it streams; the exchange is added to the history once
+                // the stream ends
+                let mut stream = chat.send_message_stream(line).await?;
+                while let Some(chunk) = stream.try_next().await? {
+                    print!("{}", chunk.text());
+                    io::stdout().flush()?;
+                }
+                println!();
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_history(history: &[Content]) {
+    for content in history {
+        let role = content
+            .role
+            .as_ref()
+            .map_or("?".to_string(), |role| format!("{role:?}").to_lowercase());
+        for part in content.parts.iter().flatten() {
+            match part {
+                Part::Text { text, .. } => println!("[{role}] {text}"),
+                Part::FunctionCall { function_call, .. } => {
+                    println!("[{role}] calls {}", function_call.name)
+                }
+                Part::FunctionResponse {
+                    function_response, ..
+                } => {
+                    println!("[{role}] result of {}", function_response.name)
+                }
+                _ => println!("[{role}] (other part)"),
+            }
+        }
+    }
+}
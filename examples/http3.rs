@@ -0,0 +1,71 @@
+//! Live smoke test of HTTP/3, for a plain and a streamed request.
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg reqwest_unstable" cargo run --example http3 --features http3
+//! ```
+use display_error_chain::DisplayErrorChain;
+use futures_util::TryStreamExt;
+use gemini_rust::GeminiBuilder;
+use std::env;
+use std::process::ExitCode;
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    match do_main().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let error_chain = DisplayErrorChain::new(e.as_ref());
+            tracing::error!(error.debug = ?e, error.chained = %error_chain, "execution failed");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
+    // Get API key from environment variable
+    let api_key = env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY environment variable not set");
+
+    // Prefer HTTP/3, falling back to TCP if QUIC is blocked
+    let client = GeminiBuilder::new(api_key).prefer_http3(true).build()?;
+
+    let response = client
+        .generate_content()
+        .with_user_message("Say hello in one word.")
+        .execute()
+        .await?;
+    info!(
+        response = response.text(),
+        http.version = ?response.meta.http_version,
+        http3.fallback = response.meta.http3_fallback,
+        http3.status = ?client.http3_status(),
+        "response received"
+    );
+
+    // Server-sent events are streamed over the same connection
+    let mut stream = client
+        .generate_content()
+        .with_user_message("Count from one to five, one number per line.")
+        .execute_stream()
+        .await?;
+    let mut chunks = 0;
+    while let Some(chunk) = stream.try_next().await? {
+        chunks += 1;
+        info!(
+            text = chunk.text(),
+            http.version = ?chunk.meta.http_version,
+            "chunk received"
+        );
+    }
+    info!(chunks, http3.status = ?client.http3_status(), "stream complete");
+
+    Ok(())
+}
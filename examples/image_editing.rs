@@ -138,6 +138,7 @@ fn save_generated_images(
     for candidate in response.candidates.iter() {
         if let Some(parts) = &candidate.content.parts {
             for part in parts.iter() {
+                #[allow(clippy::collapsible_match)]
                 match part {
                     gemini_rust::Part::Text { text, .. } => {
                         if !text.trim().is_empty() {
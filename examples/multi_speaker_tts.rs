@@ -77,6 +77,7 @@ Alice: I couldn't agree more. It's remarkable how far AI-generated speech has co
             for (i, candidate) in response.candidates.iter().enumerate() {
                 if let Some(parts) = &candidate.content.parts {
                     for (j, part) in parts.iter().enumerate() {
+                        #[allow(clippy::collapsible_match)]
                         match part {
                             // Look for inline data with audio MIME type
                             Part::InlineData { inline_data } => {
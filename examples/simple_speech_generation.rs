@@ -65,6 +65,7 @@ async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
             for (i, candidate) in response.candidates.iter().enumerate() {
                 if let Some(parts) = &candidate.content.parts {
                     for (j, part) in parts.iter().enumerate() {
+                        #[allow(clippy::collapsible_match)]
                         match part {
                             // Look for inline data with audio MIME type
                             Part::InlineData { inline_data } => {
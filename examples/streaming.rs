@@ -2,6 +2,7 @@ use display_error_chain::DisplayErrorChain;
 use futures_util::TryStreamExt;
 use gemini_rust::Gemini;
 use std::env;
+use std::io::Write;
 use std::process::ExitCode;
 use tracing::info;
 
@@ -43,16 +44,25 @@ async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
         .execute_stream()
         .await?;
 
-    // pin!(stream);
-
     info!("streaming response chunks");
+    // Errors reported by the API after the stream started end it with an `Err`
     let mut full_response = String::new();
+    let mut usage = None;
     while let Some(chunk) = stream.try_next().await? {
         let chunk_text = chunk.text();
+        print!("{chunk_text}");
+        std::io::stdout().flush()?;
         full_response.push_str(&chunk_text);
         tracing::debug!(chunk = chunk_text, "received chunk");
+        // The final chunk carries the usage of the whole response
+        usage = chunk.usage_metadata.or(usage);
     }
-    info!(response = full_response, "streaming generation completed");
+    println!();
+    info!(
+        response = full_response,
+        tokens.total = usage.and_then(|u| u.total_token_count),
+        "streaming generation completed"
+    );
 
     // Multi-turn conversation
     info!("starting multi-turn conversation example");
@@ -0,0 +1,98 @@
+//! Ask for an answer in TOON and parse it, with and without the TOON spec in the system
+//! instruction, counting how many answers parse either way.
+//!
+//! ```text
+//! GEMINI_API_KEY=... cargo run --example toon_answer -- 5
+//! ```
+
+use display_error_chain::DisplayErrorChain;
+use gemini_rust::{ClientError, Gemini};
+use serde::Deserialize;
+use std::env;
+use std::process::ExitCode;
+use tracing::{info, warn};
+
+/// A trip itinerary, nested enough to exercise tables and lists
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+struct Itinerary {
+    city: String,
+    days: u32,
+    stops: Vec<Stop>,
+    budget: Budget,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+struct Stop {
+    day: u32,
+    place: String,
+    hours: f32,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+struct Budget {
+    currency: String,
+    total: f64,
+}
+
+const PROMPT: &str = "Plan a 2-day trip to Lisbon. Answer in TOON with the fields city, days, \
+    stops (day, place, hours), and budget (currency, total).";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    match do_main().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let error_chain = DisplayErrorChain::new(e.as_ref());
+            tracing::error!(error.debug = ?e, error.chained = %error_chain, "execution failed");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY environment variable not set");
+    let attempts: usize = env::args().nth(1).map_or(Ok(3), |n| n.parse())?;
+    let client = Gemini::new(api_key)?;
+
+    for with_spec in [false, true] {
+        let mut parsed = 0;
+        for attempt in 0..attempts {
+            let builder = client.generate_content().with_user_message(PROMPT);
+            let builder = match with_spec {
+                true => builder.using_toon(),
+                false => builder,
+            };
+            match builder.execute_toon::<Itinerary>().await {
+                Ok(itinerary) => {
+                    parsed += 1;
+                    info!(with_spec, attempt, ?itinerary, "answer parsed");
+                }
+                Err(error @ ClientError::ToonOutput { .. }) => {
+                    let chain = DisplayErrorChain::new(&error);
+                    warn!(with_spec, attempt, error.chained = %chain, "answer did not parse");
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+        println!(
+            "{}: {parsed}/{attempts} answers parsed",
+            match with_spec {
+                true => "with the TOON spec",
+                false => "without the TOON spec",
+            }
+        );
+    }
+
+    Ok(())
+}
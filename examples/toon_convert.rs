@@ -0,0 +1,40 @@
+//! Converts a document between TOON and JSON, reading stdin and writing stdout.
+//!
+//! Usage: `cargo run --example toon_convert -- <--to-json|--to-toon> < input`
+//!
+//! For example, to inspect a logged TOON payload as JSON:
+//!
+//! ```sh
+//! cargo run --example toon_convert -- --to-json < payload.toon
+//! ```
+
+use display_error_chain::DisplayErrorChain;
+use gemini_rust::toon;
+use std::env;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    match do_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", DisplayErrorChain::new(e.as_ref()));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn do_main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let convert: fn(&str) -> Result<String, toon::Error> = match args.as_slice() {
+        [flag] if flag == "--to-json" => toon::to_json_string,
+        [flag] if flag == "--to-toon" => toon::from_json_str,
+        _ => return Err("usage: toon_convert <--to-json|--to-toon> < input".into()),
+    };
+
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+    let output = convert(&input)?;
+    writeln!(io::stdout().lock(), "{output}")?;
+    Ok(())
+}
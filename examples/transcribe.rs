@@ -0,0 +1,57 @@
+use display_error_chain::DisplayErrorChain;
+use gemini_rust::{generation::audio::AUDIO_MIME_TYPES, Gemini};
+use std::env;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    match do_main().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let error_chain = DisplayErrorChain::new(e.as_ref());
+            tracing::error!(error.debug = ?e, error.chained = %error_chain, "execution failed");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY environment variable not set");
+
+    // Transcribe the clip given as the first argument
+    let Some(audio_path) = env::args().nth(1).map(PathBuf::from) else {
+        return Err(format!(
+            "usage: cargo run --example transcribe -- <clip>, one of {}",
+            AUDIO_MIME_TYPES.join(", ")
+        )
+        .into());
+    };
+
+    let client = Gemini::new(api_key).expect("unable to create Gemini API client");
+
+    info!(audio_path = ?audio_path, "starting transcription request");
+    // The instruction and the clip are sent in the same user turn
+    let response = client
+        .generate_content()
+        .with_user_message(
+            "Transcribe this audio clip. Start every sentence on a new line with its \
+             timestamp as [mm:ss], and label the speakers if there are several.",
+        )
+        .with_audio_from_path(&audio_path)?
+        .execute()
+        .await?;
+    info!("transcript received");
+    println!("{}", response.text());
+
+    Ok(())
+}
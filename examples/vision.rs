@@ -0,0 +1,65 @@
+use display_error_chain::DisplayErrorChain;
+use gemini_rust::Gemini;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    match do_main().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let error_chain = DisplayErrorChain::new(e.as_ref());
+            tracing::error!(error.debug = ?e, error.chained = %error_chain, "execution failed");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY environment variable not set");
+
+    // Describe the image given as the first argument, or the example image next to this file
+    let image_path = env::args().nth(1).map(PathBuf::from).unwrap_or_else(|| {
+        Path::new(file!())
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join("image-example.webp")
+    });
+
+    let client = Gemini::new(api_key).expect("unable to create Gemini API client");
+
+    info!(image_path = ?image_path, "starting image description request");
+    // The question and the image are sent in the same user turn
+    let response = client
+        .generate_content()
+        .with_user_message("Describe this image in a few sentences.")
+        .with_image_from_path(&image_path)?
+        .with_max_output_tokens(400)
+        .execute()
+        .await?;
+    info!(response = response.text(), "image description received");
+
+    // Images already in memory need their MIME type
+    let bytes = std::fs::read(&image_path)?;
+    let mime_type = gemini_rust::generation::image::image_mime_type(&image_path)
+        .ok_or("unsupported image type")?;
+    let response = client
+        .generate_content()
+        .with_user_message("List the three most prominent colors of this image.")
+        .with_image_bytes(&bytes, mime_type)?
+        .execute()
+        .await?;
+    info!(response = response.text(), "image colors received");
+
+    Ok(())
+}
@@ -0,0 +1,257 @@
+//! Derive macros for [`gemini-rust`](https://docs.rs/gemini-rust).
+//!
+//! Use them through the re-exports of the `gemini-rust` crate rather than depending on this
+//! crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned, Attribute, Data, DeriveInput, Error, Expr,
+    ExprLit, Fields, FieldsNamed, Lit, LitStr, Meta, Type,
+};
+
+/// Derives `gemini_rust::schema::ToSchema`, see the `gemini_rust::schema` module documentation
+#[proc_macro_derive(GeminiSchema, attributes(gemini))]
+pub fn derive_gemini_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(mut input: DeriveInput) -> Result<TokenStream2, Error> {
+    if let Some(attr) = input.attrs.iter().find(|a| a.path().is_ident("gemini")) {
+        return Err(Error::new(
+            attr.span(),
+            "`#[gemini(...)]` is only supported on fields and variants",
+        ));
+    }
+    let description = docs(&input.attrs);
+    let body = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => object(fields)?,
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                let ty = &fields.unnamed[0].ty;
+                quote!(<#ty as ::gemini_rust::schema::ToSchema>::schema())
+            }
+            fields => {
+                return Err(Error::new(
+                    fields.span(),
+                    "GeminiSchema supports structs with named fields and newtype structs",
+                ))
+            }
+        },
+        Data::Enum(data) => {
+            let mut values = Vec::new();
+            let mut value_docs = Vec::new();
+            let mut objects = Vec::new();
+            for variant in &data.variants {
+                let options = Options::parse(&variant.attrs)?;
+                if let Some(format) = &options.format {
+                    return Err(Error::new(
+                        format.span(),
+                        "`format` is only supported on fields",
+                    ));
+                }
+                if options.skip {
+                    continue;
+                }
+                let name = options
+                    .rename
+                    .unwrap_or_else(|| LitStr::new(&variant.ident.to_string(), variant.span()));
+                let docs = docs(&variant.attrs);
+                let payload = match &variant.fields {
+                    Fields::Unit => {
+                        if let Some(docs) = docs {
+                            value_docs.push(format!("- `{}`: {docs}", name.value()));
+                        }
+                        values.push(name);
+                        continue;
+                    }
+                    Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                        let ty = &fields.unnamed[0].ty;
+                        quote!(<#ty as ::gemini_rust::schema::ToSchema>::schema())
+                    }
+                    Fields::Named(fields) => object(fields)?,
+                    fields => {
+                        return Err(Error::new(
+                            fields.span(),
+                            "GeminiSchema supports unit, newtype, and struct variants",
+                        ))
+                    }
+                };
+                let description = with_description(docs);
+                objects.push(quote! {
+                    ::gemini_rust::schema::Schema::object([(
+                        ::std::string::String::from(#name),
+                        #payload,
+                        false,
+                    )]) #description
+                });
+            }
+            let strings = (!values.is_empty())
+                .then(|| quote!(::gemini_rust::schema::Schema::enumeration([#(#values),*])));
+            match (strings, objects.is_empty()) {
+                (Some(strings), true) => {
+                    // Unit variants have no schema of their own to carry their docs
+                    let description = description
+                        .iter()
+                        .cloned()
+                        .chain((!value_docs.is_empty()).then(|| value_docs.join("\n")))
+                        .collect::<Vec<_>>()
+                        .join("\n\n");
+                    let description =
+                        with_description((!description.is_empty()).then_some(description));
+                    return Ok(impl_to_schema(&mut input, quote!(#strings #description)));
+                }
+                (None, true) => {
+                    return Err(Error::new(
+                        input.ident.span(),
+                        "GeminiSchema needs at least one variant that is not skipped",
+                    ))
+                }
+                (strings, false) => {
+                    let value_docs =
+                        with_description((!value_docs.is_empty()).then(|| value_docs.join("\n")));
+                    let strings = strings.map(|strings| quote!(#strings #value_docs));
+                    let schemas = strings.into_iter().chain(objects);
+                    quote! {
+                        ::gemini_rust::schema::Schema {
+                            any_of: ::std::option::Option::Some(::std::vec![#(#schemas),*]),
+                            ..::std::default::Default::default()
+                        }
+                    }
+                }
+            }
+        }
+        Data::Union(data) => {
+            return Err(Error::new(
+                data.union_token.span(),
+                "GeminiSchema does not support unions",
+            ))
+        }
+    };
+    let description = with_description(description);
+    Ok(impl_to_schema(&mut input, quote!(#body #description)))
+}
+
+/// Wraps the body of `schema()` in an impl for the input type
+fn impl_to_schema(input: &mut DeriveInput, body: TokenStream2) -> TokenStream2 {
+    let params: Vec<_> = input
+        .generics
+        .type_params()
+        .map(|p| p.ident.clone())
+        .collect();
+    let where_clause = input.generics.make_where_clause();
+    for param in params {
+        where_clause
+            .predicates
+            .push(parse_quote!(#param: ::gemini_rust::schema::ToSchema));
+    }
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::gemini_rust::schema::ToSchema for #ident #ty_generics #where_clause {
+            fn schema() -> ::gemini_rust::schema::Schema {
+                #body
+            }
+        }
+    }
+}
+
+/// An object schema of named fields
+fn object(fields: &FieldsNamed) -> Result<TokenStream2, Error> {
+    let mut properties = Vec::new();
+    for field in &fields.named {
+        let options = Options::parse(&field.attrs)?;
+        if options.skip {
+            continue;
+        }
+        let ident = field.ident.as_ref().expect("named fields have identifiers");
+        let name = options.rename.unwrap_or_else(|| {
+            let name = ident.to_string();
+            LitStr::new(name.strip_prefix("r#").unwrap_or(&name), ident.span())
+        });
+        let ty: &Type = &field.ty;
+        let description = with_description(docs(&field.attrs));
+        let format = options.format.map(|format| quote!(.with_format(#format)));
+        properties.push(quote! {
+            (
+                ::std::string::String::from(#name),
+                <#ty as ::gemini_rust::schema::ToSchema>::schema() #format #description,
+                <#ty as ::gemini_rust::schema::ToSchema>::optional(),
+            )
+        });
+    }
+    Ok(quote!(::gemini_rust::schema::Schema::object([#(#properties),*])))
+}
+
+/// A call setting the description, if there is one
+fn with_description(description: Option<String>) -> Option<TokenStream2> {
+    description.map(|description| quote!(.with_description(#description)))
+}
+
+/// The doc comment of an item, with wrapped lines joined and paragraphs kept
+fn docs(attrs: &[Attribute]) -> Option<String> {
+    let lines = attrs.iter().filter_map(|attr| match &attr.meta {
+        Meta::NameValue(meta) if meta.path.is_ident("doc") => match &meta.value {
+            Expr::Lit(ExprLit {
+                lit: Lit::Str(doc), ..
+            }) => Some(doc.value()),
+            _ => None,
+        },
+        _ => None,
+    });
+    let mut paragraphs: Vec<String> = vec![String::new()];
+    for line in lines.flat_map(|doc| doc.split('\n').map(str::to_string).collect::<Vec<_>>()) {
+        let line = line.trim();
+        let paragraph = paragraphs.last_mut().expect("there is always a paragraph");
+        if line.is_empty() {
+            if !paragraph.is_empty() {
+                paragraphs.push(String::new());
+            }
+        } else {
+            if !paragraph.is_empty() {
+                paragraph.push(' ');
+            }
+            paragraph.push_str(line);
+        }
+    }
+    paragraphs.retain(|paragraph| !paragraph.is_empty());
+    (!paragraphs.is_empty()).then(|| paragraphs.join("\n\n"))
+}
+
+/// The `#[gemini(...)]` options of a field or variant
+#[derive(Default)]
+struct Options {
+    rename: Option<LitStr>,
+    skip: bool,
+    /// Only meaningful on fields
+    format: Option<LitStr>,
+}
+
+impl Options {
+    fn parse(attrs: &[Attribute]) -> Result<Self, Error> {
+        let mut options = Self::default();
+        for attr in attrs.iter().filter(|a| a.path().is_ident("gemini")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    options.rename = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("skip") {
+                    options.skip = true;
+                    Ok(())
+                } else if meta.path.is_ident("format") {
+                    options.format = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error(
+                        "unsupported gemini attribute, expected `rename`, `skip`, or `format`",
+                    ))
+                }
+            })?;
+        }
+        Ok(options)
+    }
+}
@@ -0,0 +1,56 @@
+//! Authentication modes of the client.
+//!
+//! By default requests carry an API key in the `x-goog-api-key` header. [`Auth::Bearer`]
+//! sends an OAuth-style `Authorization: Bearer` token fetched from a [`TokenProvider`] before
+//! every request, so providers can refresh expiring tokens. [`Auth::None`] sends no
+//! credentials at all, for gateways that inject authentication themselves and for mock
+//! servers.
+
+use async_trait::async_trait;
+use std::{fmt, sync::Arc};
+
+/// Error returned by a [`TokenProvider`]
+pub type TokenError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Supplies bearer tokens for [`Auth::Bearer`]
+#[async_trait]
+pub trait TokenProvider: Send + Sync {
+    /// Returns the token to send; called before every request
+    async fn token(&self) -> Result<String, TokenError>;
+}
+
+/// A fixed token
+#[async_trait]
+impl TokenProvider for String {
+    async fn token(&self) -> Result<String, TokenError> {
+        Ok(self.clone())
+    }
+}
+
+/// How requests are authenticated
+#[derive(Clone)]
+pub enum Auth {
+    /// An API key sent in the `x-goog-api-key` header
+    ApiKey(String),
+    /// A bearer token sent in the `Authorization` header
+    Bearer(Arc<dyn TokenProvider>),
+    /// No credentials
+    None,
+}
+
+impl Auth {
+    /// Authenticates with bearer tokens from `provider`
+    pub fn bearer(provider: impl TokenProvider + 'static) -> Self {
+        Self::Bearer(Arc::new(provider))
+    }
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::ApiKey(_) => f.write_str("ApiKey(..)"),
+            Auth::Bearer(_) => f.write_str("Bearer(..)"),
+            Auth::None => f.write_str("None"),
+        }
+    }
+}
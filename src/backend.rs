@@ -0,0 +1,288 @@
+//! The API a client talks to.
+//!
+//! Clients talk to the Gemini API (`generativelanguage.googleapis.com`) unless built for
+//! Vertex AI (`aiplatform.googleapis.com`), in one of two modes:
+//!
+//! - [`Gemini::with_vertex()`](crate::Gemini::with_vertex) and
+//!   [`GeminiBuilder::vertex()`](crate::GeminiBuilder::vertex) target the models of a Google
+//!   Cloud project in a location, at
+//!   `projects/{project}/locations/{location}/publishers/google/models/{model}:{verb}`,
+//!   authenticated with OAuth access tokens sent as `Authorization: Bearer`.
+//! - [`Gemini::vertex_express()`](crate::Gemini::vertex_express) and
+//!   [`GeminiBuilder::vertex_express()`](crate::GeminiBuilder::vertex_express) target express
+//!   mode: an API key, without OAuth or a project, at
+//!   `publishers/google/models/{model}:{verb}`.
+//!
+//! A base URL set with [`GeminiBuilder::with_base_url()`](crate::GeminiBuilder::with_base_url),
+//! e.g. of a proxy, replaces the host and version of either mode; the project path is kept.
+//! On Vertex AI:
+//!
+//! - Models are named `publishers/google/models/{model}` instead of `models/{model}`; a
+//!   [`Model`] is mapped by [`Backend::model_resource()`], so the same model constants work on
+//!   all backends.
+//! - `countTokens` takes the contents at the top level rather than a nested request.
+//! - Requests may carry [labels](crate::ContentBuilder::with_label) for billing breakdowns.
+//! - Embeddings are requested with `predict`, whose instances and predictions are mapped from
+//!   and to the same request and response types.
+//!
+//! Express mode lacks features that need a Google Cloud project, such as cached content, and
+//! neither mode offers the Files API. [Preflight checks](crate::GeminiBuilder::with_preflight) reject requests using
+//! them with [`Error::UnsupportedOnBackend`](crate::ClientError::UnsupportedOnBackend) before
+//! they are sent.
+
+#[cfg(feature = "embeddings")]
+use serde::Deserialize;
+use serde::Serialize;
+use std::{fmt, sync::LazyLock};
+use url::Url;
+
+#[cfg(feature = "embeddings")]
+use crate::embedding::{
+    BatchContentEmbeddingResponse, ContentEmbedding, ContentEmbeddingResponse, EmbedContentRequest,
+    TaskType,
+};
+use crate::{
+    client::{Error as ClientError, UnsupportedOnBackendSnafu},
+    GenerateContentRequest, Model, Part,
+};
+
+/// Base URL of Vertex AI in express mode
+pub static VERTEX_EXPRESS_BASE_URL: LazyLock<Url> = LazyLock::new(|| {
+    Url::parse("https://aiplatform.googleapis.com/v1/")
+        .expect("unreachable error: failed to parse URL")
+});
+
+/// Base URL of Vertex AI in `location`, e.g. `us-central1` or `global`
+pub(crate) fn vertex_base_url(location: &str) -> Result<Url, url::ParseError> {
+    match location {
+        "global" => Ok(VERTEX_EXPRESS_BASE_URL.clone()),
+        location => Url::parse(&format!("https://{location}-aiplatform.googleapis.com/v1/")),
+    }
+}
+
+/// The parent of the models of `project` in `location`, if both are valid resource IDs
+pub(crate) fn vertex_parent(project: &str, location: &str) -> Option<String> {
+    let valid = |id: &str, extra: &[char]| {
+        !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || extra.contains(&c))
+    };
+    (valid(project, &['.', ':']) && valid(location, &[]))
+        .then(|| format!("projects/{project}/locations/{location}"))
+}
+
+/// Prefix of the URIs of files uploaded with the Gemini API's Files API
+const FILES_API_URI_PREFIX: &str = "https://generativelanguage.googleapis.com/";
+
+/// The API a client talks to, see the [module documentation](self)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// The Gemini API
+    #[default]
+    GeminiApi,
+    /// Vertex AI in express mode, authenticated with an API key
+    VertexExpress,
+    /// Vertex AI in a Google Cloud project, authenticated with OAuth access tokens
+    Vertex,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backend::GeminiApi => "the Gemini API",
+            Backend::VertexExpress => "Vertex AI express mode",
+            Backend::Vertex => "Vertex AI",
+        })
+    }
+}
+
+impl Backend {
+    /// The resource name of `model` on this backend, e.g.
+    /// `publishers/google/models/gemini-2.5-flash` on Vertex AI.
+    ///
+    /// Names that already carry a collection other than `models/` (such as tuned models under
+    /// `projects/`) are kept. On [`Backend::Vertex`], requests prefix publisher models with the
+    /// project and location of the client.
+    pub fn model_resource(&self, model: &Model) -> String {
+        let name = model.as_str();
+        match self {
+            Backend::GeminiApi => name.to_string(),
+            Backend::VertexExpress | Backend::Vertex => match name.strip_prefix("models/") {
+                Some(id) => format!("publishers/google/models/{id}"),
+                None if name.contains('/') => name.to_string(),
+                None => format!("publishers/google/models/{name}"),
+            },
+        }
+    }
+
+    /// Whether this is one of the Vertex AI backends, whose request bodies differ
+    pub(crate) fn is_vertex(&self) -> bool {
+        matches!(self, Backend::VertexExpress | Backend::Vertex)
+    }
+
+    /// Fails if `request` uses a feature this backend does not offer
+    pub(crate) fn preflight(&self, request: &GenerateContentRequest) -> Result<(), ClientError> {
+        if *self == Backend::GeminiApi {
+            snafu::ensure!(
+                request.labels.is_empty(),
+                UnsupportedOnBackendSnafu {
+                    feature: "request labels",
+                    backend: *self,
+                }
+            );
+            return Ok(());
+        }
+        snafu::ensure!(
+            *self == Backend::Vertex || request.cached_content.is_none(),
+            UnsupportedOnBackendSnafu {
+                feature: "cached content",
+                backend: *self,
+            }
+        );
+        let files_api = request
+            .contents
+            .iter()
+            .chain(&request.system_instruction)
+            .flat_map(|content| content.parts.iter().flatten())
+            .any(|part| match part {
+                Part::FileData { file_data, .. } => {
+                    file_data.file_uri.starts_with(FILES_API_URI_PREFIX)
+                }
+                _ => false,
+            });
+        snafu::ensure!(
+            !files_api,
+            UnsupportedOnBackendSnafu {
+                feature: "Files API file references",
+                backend: *self,
+            }
+        );
+        Ok(())
+    }
+}
+
+/// The body of a Vertex AI `countTokens` request
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct VertexCountTokensRequest<'a> {
+    contents: &'a [crate::Content],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<&'a crate::Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<&'a [crate::Tool]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_config: Option<&'a crate::GenerationConfig>,
+}
+
+impl<'a> From<&'a GenerateContentRequest> for VertexCountTokensRequest<'a> {
+    fn from(request: &'a GenerateContentRequest) -> Self {
+        Self {
+            contents: &request.contents,
+            system_instruction: request.system_instruction.as_ref(),
+            tools: request.tools.as_deref(),
+            generation_config: request.generation_config.as_ref(),
+        }
+    }
+}
+
+/// The body of a Vertex AI `predict` request for embeddings
+#[cfg(feature = "embeddings")]
+#[derive(Debug, Serialize)]
+pub(crate) struct PredictRequest {
+    instances: Vec<EmbedInstance>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parameters: Option<EmbedParameters>,
+}
+
+#[cfg(feature = "embeddings")]
+#[derive(Debug, Serialize)]
+struct EmbedInstance {
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    task_type: Option<TaskType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+}
+
+#[cfg(feature = "embeddings")]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EmbedParameters {
+    output_dimensionality: i32,
+}
+
+#[cfg(feature = "embeddings")]
+impl PredictRequest {
+    /// Maps embed requests to one `predict` request; the output dimensionality of the first
+    /// request applies to all
+    pub(crate) fn embeddings<'a>(
+        requests: impl IntoIterator<Item = &'a EmbedContentRequest>,
+    ) -> Self {
+        let mut dimensionality = None;
+        let instances = requests
+            .into_iter()
+            .map(|request| {
+                dimensionality = dimensionality.or(request.output_dimensionality);
+                EmbedInstance {
+                    content: request
+                        .content
+                        .parts
+                        .iter()
+                        .flatten()
+                        .filter_map(|part| match part {
+                            Part::Text { text, .. } => Some(text.as_str()),
+                            _ => None,
+                        })
+                        .collect(),
+                    task_type: request.task_type.clone(),
+                    title: request.title.clone(),
+                }
+            })
+            .collect();
+        Self {
+            instances,
+            parameters: dimensionality.map(|output_dimensionality| EmbedParameters {
+                output_dimensionality,
+            }),
+        }
+    }
+}
+
+/// The body of a Vertex AI `predict` response for embeddings
+#[cfg(feature = "embeddings")]
+#[derive(Debug, Deserialize)]
+pub(crate) struct PredictResponse {
+    #[serde(default)]
+    predictions: Vec<Prediction>,
+}
+
+#[cfg(feature = "embeddings")]
+#[derive(Debug, Deserialize)]
+struct Prediction {
+    embeddings: ContentEmbedding,
+}
+
+#[cfg(feature = "embeddings")]
+impl From<PredictResponse> for BatchContentEmbeddingResponse {
+    fn from(response: PredictResponse) -> Self {
+        Self {
+            embeddings: response
+                .predictions
+                .into_iter()
+                .map(|prediction| prediction.embeddings)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(feature = "embeddings")]
+impl PredictResponse {
+    /// The first embedding, as the response of a single embed request
+    pub(crate) fn into_single(self) -> Option<ContentEmbeddingResponse> {
+        let prediction = self.predictions.into_iter().next()?;
+        Some(ContentEmbeddingResponse {
+            embedding: prediction.embeddings,
+        })
+    }
+}
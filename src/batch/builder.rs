@@ -17,6 +17,7 @@ pub struct BatchBuilder {
     client: Arc<GeminiClient>,
     display_name: String,
     requests: Vec<GenerateContentRequest>,
+    retry: bool,
 }
 
 impl BatchBuilder {
@@ -26,6 +27,7 @@ impl BatchBuilder {
             client,
             display_name: "RustBatch".to_string(),
             requests: Vec::new(),
+            retry: false,
         }
     }
 
@@ -47,6 +49,16 @@ impl BatchBuilder {
         self
     }
 
+    /// Retries creating the batch, and uploading its input file, after transient failures as
+    /// the client's [`RetryPolicy`](crate::RetryPolicy) says.
+    ///
+    /// Off by default: a request failing after the API accepted it would create a second
+    /// batch, which runs and is billed as well.
+    pub fn with_retries(mut self) -> Self {
+        self.retry = true;
+        self
+    }
+
     /// Constructs the final `BatchGenerateContentRequest` from the builder's configuration.
     ///
     /// This method consumes the builder.
@@ -79,10 +91,10 @@ impl BatchBuilder {
         batch.size = self.requests.len()
     ))]
     pub async fn execute(self) -> Result<BatchHandle, Error> {
-        let client = self.client.clone();
+        let (client, retry) = (self.client.clone(), self.retry);
         let request = self.build();
         let response = client
-            .batch_generate_content(request)
+            .batch_generate_content(request, retry)
             .await
             .context(ClientSnafu)?;
         Ok(BatchHandle::new(response.name, client))
@@ -113,16 +125,18 @@ impl BatchBuilder {
         Span::current().record("file.size", json_bytes.len());
 
         let file_display_name = format!("{}-input.jsonl", self.display_name);
-        let file = crate::files::builder::FileBuilder::new(self.client.clone(), json_bytes)
-            .display_name(file_display_name)
-            .with_mime_type(
-                "application/jsonl"
-                    .parse()
-                    .expect("failed to parse MIME type 'application/jsonl'"),
-            )
-            .upload()
-            .await
-            .context(FileSnafu)?;
+        let mut file =
+            crate::files::builder::FileBuilder::new(self.client.clone(), json_bytes.into())
+                .display_name(file_display_name)
+                .with_mime_type(
+                    "application/jsonl"
+                        .parse()
+                        .expect("failed to parse MIME type 'application/jsonl'"),
+                );
+        if self.retry {
+            file = file.with_retries();
+        }
+        let file = file.upload().await.context(FileSnafu)?;
 
         let request = BatchGenerateContentRequest {
             batch: BatchConfig {
@@ -133,7 +147,7 @@ impl BatchBuilder {
 
         let client = self.client.clone();
         let response = client
-            .batch_generate_content(request)
+            .batch_generate_content(request, self.retry)
             .await
             .context(ClientSnafu)?;
 
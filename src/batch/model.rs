@@ -73,6 +73,8 @@ pub struct InlinedBatchGenerationResponseItem {
 /// An item in a batch generate content response.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+// Boxing the response would change the public type of the variant
+#[allow(clippy::large_enum_variant)]
 pub enum BatchGenerateContentResponseItem {
     /// Successful response item
     Response(GenerationResponse),
@@ -0,0 +1,135 @@
+//! The cached contents API, for reusing a large prompt prefix across requests.
+//!
+//! A cached content holds contents, a system instruction, and tools for a model until it
+//! expires. Requests referencing it with
+//! [`ContentBuilder::with_cached_content()`](crate::ContentBuilder::with_cached_content) only
+//! send what follows the prefix, and the cached tokens are billed at a reduced rate.
+//! [`Gemini::caches()`] returns a [`Caches`] handle that creates, lists, extends, and deletes
+//! them:
+//!
+//! ```no_run
+//! # async fn run(client: gemini_rust::Gemini, report: String) -> Result<(), Box<dyn std::error::Error>> {
+//! use std::time::Duration;
+//!
+//! let cache = client
+//!     .caches()
+//!     .create()
+//!     .with_system_instruction("You answer questions about the report below.")
+//!     .with_user_message(report)
+//!     .with_ttl(Duration::from_secs(3600))
+//!     .execute()
+//!     .await?;
+//! let response = client
+//!     .generate_content()
+//!     .with_cached_content(&cache)
+//!     .with_user_message("What are the key findings?")
+//!     .execute()
+//!     .await?;
+//! let cached_tokens = response
+//!     .usage_metadata
+//!     .and_then(|usage| usage.cached_content_token_count);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`UsageMetadata::cached_content_token_count`](crate::UsageMetadata::cached_content_token_count)
+//! reports how many prompt tokens were served from the cache, which is how to check that it
+//! is hit.
+//!
+//! [`Gemini::caches()`]: crate::Gemini::caches
+
+use futures::Stream;
+#[cfg(feature = "files")]
+use mime::Mime;
+use snafu::ResultExt;
+use std::{sync::Arc, time::Duration};
+
+#[cfg(feature = "files")]
+use super::CacheFromFileBuilder;
+use super::{
+    model::{CacheExpirationRequest, CachedContent, CachedContentSummary},
+    CacheBuilder, CachedContentHandle, ClientSnafu, Error,
+};
+use crate::client::{Error as ClientError, GeminiClient};
+#[cfg(feature = "files")]
+use crate::files::builder::FileSource;
+
+/// Handle to the cached contents API of a client, see the [module documentation](self)
+#[derive(Clone)]
+pub struct Caches {
+    client: Arc<GeminiClient>,
+}
+
+impl Caches {
+    pub(crate) fn new(client: Arc<GeminiClient>) -> Self {
+        Self { client }
+    }
+
+    /// Starts building a cached content for the client's model
+    pub fn create(&self) -> CacheBuilder {
+        CacheBuilder::new(self.client.clone())
+    }
+
+    /// Starts building a cached content holding `source`, a path or bytes, uploaded first, see
+    /// [`Gemini::create_cache_from_file()`](crate::Gemini::create_cache_from_file)
+    #[cfg(feature = "files")]
+    pub fn create_from_file<S: Into<FileSource>>(
+        &self,
+        source: S,
+        mime_type: Mime,
+    ) -> CacheFromFileBuilder {
+        CacheFromFileBuilder::new(self.client.clone(), source.into(), mime_type)
+    }
+
+    /// Get a handle to a cached content by its name, e.g. `cachedContents/abc`.
+    pub fn get(&self, name: &str) -> CachedContentHandle {
+        CachedContentHandle::new(name.to_string(), self.client.clone())
+    }
+
+    /// Lists the cached contents of the project.
+    ///
+    /// This method returns a stream that handles pagination automatically.
+    pub fn list(
+        &self,
+        page_size: impl Into<Option<i32>>,
+    ) -> impl Stream<Item = Result<CachedContentSummary, ClientError>> + Send {
+        let client = self.client.clone();
+        let page_size = page_size.into();
+        async_stream::try_stream! {
+            let mut page_token: Option<String> = None;
+            loop {
+                let response = client
+                    .list_cached_contents(page_size, page_token.clone())
+                    .await?;
+
+                for cached_content in response.cached_contents {
+                    yield cached_content;
+                }
+
+                if let Some(next_page_token) = response.next_page_token {
+                    page_token = Some(next_page_token);
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Sets the cached content `name` to expire `ttl` from now.
+    pub async fn update_ttl(&self, name: &str, ttl: Duration) -> Result<CachedContent, Error> {
+        self.client
+            .update_cached_content(name, CacheExpirationRequest::from_ttl(ttl))
+            .await
+            .map_err(Box::new)
+            .context(ClientSnafu)
+    }
+
+    /// Deletes the cached content `name`.
+    pub async fn delete(&self, name: &str) -> Result<(), Error> {
+        self.client
+            .delete_cached_content(name)
+            .await
+            .map_err(Box::new)
+            .context(ClientSnafu)
+    }
+}
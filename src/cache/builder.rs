@@ -4,6 +4,7 @@ use tracing::instrument;
 
 use snafu::ResultExt;
 
+use crate::cleanup::{ResourceGuard, ResourceKind};
 use crate::client::GeminiClient;
 use crate::models::Content;
 
@@ -24,6 +25,7 @@ pub struct CacheBuilder {
     tools: Vec<Tool>,
     tool_config: Option<ToolConfig>,
     expiration: Option<CacheExpirationRequest>,
+    retry: bool,
 }
 
 impl CacheBuilder {
@@ -37,9 +39,17 @@ impl CacheBuilder {
             tools: Vec::new(),
             tool_config: None,
             expiration: None,
+            retry: false,
         }
     }
 
+    /// Targets another model than the client's.
+    #[cfg(feature = "files")]
+    pub(crate) fn for_model(mut self, model: impl Into<crate::Model>) -> Self {
+        self.client = Arc::new(self.client.for_model(model));
+        self
+    }
+
     /// Set a display name for the cached content.
     /// Maximum 128 Unicode characters.
     pub fn with_display_name<S: Into<String>>(mut self, display_name: S) -> Result<Self, Error> {
@@ -119,6 +129,16 @@ impl CacheBuilder {
         self
     }
 
+    /// Retry creating the cached content after transient failures, as the client's
+    /// [`RetryPolicy`](crate::RetryPolicy) says.
+    ///
+    /// Off by default: a request failing after the API accepted it would create a second
+    /// cached content, billed for its storage until it expires.
+    pub fn with_retries(mut self) -> Self {
+        self.retry = true;
+        self
+    }
+
     /// Execute the cache creation request.
     #[instrument(skip_all, fields(
         display.name = self.display_name,
@@ -150,7 +170,7 @@ impl CacheBuilder {
 
         let response = self
             .client
-            .create_cached_content(cached_content)
+            .create_cached_content(cached_content, self.retry)
             .await
             .map_err(Box::new)
             .context(ClientSnafu)?;
@@ -159,4 +179,13 @@ impl CacheBuilder {
 
         Ok(CachedContentHandle::new(cache_name, self.client))
     }
+
+    /// Execute the cache creation request, returning a guard deleting the cached content once
+    /// closed or dropped, see [`cleanup`](crate::cleanup).
+    pub async fn execute_guarded(self) -> Result<(CachedContentHandle, ResourceGuard), Error> {
+        let client = self.client.clone();
+        let handle = self.execute().await?;
+        let guard = ResourceGuard::new(client, ResourceKind::CachedContent, handle.name.clone());
+        Ok((handle, guard))
+    }
 }
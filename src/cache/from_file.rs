@@ -0,0 +1,134 @@
+use mime::Mime;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::instrument;
+
+use snafu::ResultExt;
+
+use crate::client::{GeminiClient, Model};
+use crate::files::builder::{FileBuilder, FileSource};
+use crate::files::handle::FileHandle;
+use crate::{models::Content, Role};
+
+use super::builder::CacheBuilder;
+use super::handle::CachedContentHandle;
+use super::*;
+
+/// Builder for caching a large file in one call.
+///
+/// Uploads the file with the Files API, streaming it from disk when given a path, waits until it is active, and creates a cached
+/// content referencing it. The uploaded file is deleted again if a later step fails.
+#[derive(Clone)]
+pub struct CacheFromFileBuilder {
+    file: FileBuilder,
+    mime_type: Mime,
+    cache: CacheBuilder,
+    poll_interval: Duration,
+    activation_timeout: Duration,
+}
+
+impl CacheFromFileBuilder {
+    /// Creates a new CacheFromFileBuilder instance.
+    pub(crate) fn new(client: Arc<GeminiClient>, source: FileSource, mime_type: Mime) -> Self {
+        Self {
+            file: FileBuilder::new(client.clone(), source).with_mime_type(mime_type.clone()),
+            cache: CacheBuilder::new(client),
+            mime_type,
+            poll_interval: Duration::from_secs(2),
+            activation_timeout: Duration::from_secs(300),
+        }
+    }
+
+    /// Set the display name of both the uploaded file and the cached content.
+    /// Maximum 128 Unicode characters.
+    pub fn with_display_name<S: Into<String>>(mut self, display_name: S) -> Result<Self, Error> {
+        let display_name = display_name.into();
+        self.cache = self.cache.with_display_name(display_name.clone())?;
+        self.file = self.file.display_name(display_name);
+        Ok(self)
+    }
+
+    /// Call `progress` with the bytes sent so far and the size of the file while it is
+    /// uploaded, see [`FileBuilder::with_progress()`].
+    pub fn with_progress(mut self, progress: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        self.file = self.file.with_progress(progress);
+        self
+    }
+
+    /// Set the system instruction for the cached content.
+    pub fn with_system_instruction<S: Into<String>>(mut self, instruction: S) -> Self {
+        self.cache = self.cache.with_system_instruction(instruction);
+        self
+    }
+
+    /// Set the TTL (Time To Live) for the cached content.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.cache = self.cache.with_ttl(ttl);
+        self
+    }
+
+    /// Set the model the cache is created for, instead of the client's model.
+    pub fn with_model(mut self, model: impl Into<Model>) -> Self {
+        self.cache = self.cache.for_model(model);
+        self
+    }
+
+    /// Set how often the file state is polled while waiting for processing (defaults to 2s).
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Set how long to wait for the file to become active (defaults to 5 minutes).
+    pub fn with_activation_timeout(mut self, timeout: Duration) -> Self {
+        self.activation_timeout = timeout;
+        self
+    }
+
+    /// Upload the file, wait until it is active, and create the cached content.
+    ///
+    /// Returns the handles of both the uploaded file and the cached content.
+    #[instrument(skip_all, fields(mime.type = %self.mime_type))]
+    pub async fn execute(self) -> Result<(FileHandle, CachedContentHandle), Error> {
+        let mut file = self
+            .file
+            .upload()
+            .await
+            .map_err(Box::new)
+            .context(FileSnafu)?;
+
+        let activated = file
+            .wait_until_active(self.poll_interval, self.activation_timeout)
+            .await
+            .map_err(Box::new)
+            .context(FileSnafu);
+        let result = match activated {
+            Ok(()) => {
+                let uri = file
+                    .get_file_meta()
+                    .uri
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .unwrap_or_default();
+                let content =
+                    Content::file_data(self.mime_type.to_string(), uri).with_role(Role::User);
+                self.cache.with_content(content).execute().await
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(cache) => Ok((file, cache)),
+            Err(e) => {
+                if let Err((file, cleanup)) = file.delete().await {
+                    tracing::warn!(
+                        file.name = file.name(),
+                        error = %cleanup,
+                        "failed to delete uploaded file after cache creation failed"
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
+}
@@ -44,6 +44,11 @@ impl CachedContentHandle {
             .context(ClientSnafu)
     }
 
+    /// Sets the cached content to expire `ttl` from now.
+    pub async fn update_ttl(&self, ttl: std::time::Duration) -> Result<CachedContent, Error> {
+        self.update(CacheExpirationRequest::from_ttl(ttl)).await
+    }
+
     /// Deletes the cached content resource from the server.
     pub async fn delete(self) -> Result<(), (Self, Error)> {
         match self
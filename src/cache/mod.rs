@@ -1,7 +1,13 @@
 use snafu::Snafu;
 
+pub mod api;
+pub use api::Caches;
 pub mod builder;
 pub use builder::CacheBuilder;
+#[cfg(feature = "files")]
+pub mod from_file;
+#[cfg(feature = "files")]
+pub use from_file::CacheFromFileBuilder;
 pub mod handle;
 pub use handle::CachedContentHandle;
 pub mod model;
@@ -11,6 +17,10 @@ pub enum Error {
     #[snafu(display("client invocation error"))]
     Client { source: Box<crate::client::Error> },
 
+    #[cfg(feature = "files")]
+    #[snafu(display("file upload for the cache failed"))]
+    File { source: Box<crate::files::Error> },
+
     #[snafu(display(
         "cache display name ('{display_name}') too long ({chars}), must be under 128 characters"
     ))]
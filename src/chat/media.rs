@@ -0,0 +1,205 @@
+//! Compression of media in the requests a session sends.
+//!
+//! Inline images and other blobs are resent with every request of a session, so they keep
+//! costing input tokens long after they stopped mattering. A [`MediaPolicy`] replaces the
+//! media of older turns with a short text placeholder, or drops it, in the outgoing request
+//! only; the session's history keeps the original parts, so exports stay complete.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{Content, Part};
+
+/// What happens to a media part of an old turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaAction {
+    /// Send the part unchanged
+    Keep,
+    /// Replace the part with a text placeholder such as `[image: previously shared image/png,
+    /// 1024x768]`
+    Placeholder,
+    /// Leave the part out; a content left without parts gets a placeholder instead
+    Drop,
+}
+
+/// Which media of a session's history is sent in full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaPolicy {
+    /// Number of most recent turns whose media is always sent unchanged
+    pub keep_turns: usize,
+    /// What happens to inline data (`inlineData` parts) of older turns
+    pub inline_data: MediaAction,
+    /// What happens to file references (`fileData` parts) of older turns
+    pub file_data: MediaAction,
+}
+
+impl MediaPolicy {
+    /// Replaces inline data older than `keep_turns` turns with placeholders and keeps file
+    /// references, which cost little to resend
+    pub fn new(keep_turns: usize) -> Self {
+        Self {
+            keep_turns,
+            inline_data: MediaAction::Placeholder,
+            file_data: MediaAction::Keep,
+        }
+    }
+
+    /// Sets what happens to inline data of older turns
+    pub fn with_inline_data(mut self, action: MediaAction) -> Self {
+        self.inline_data = action;
+        self
+    }
+
+    /// Sets what happens to file references of older turns
+    pub fn with_file_data(mut self, action: MediaAction) -> Self {
+        self.file_data = action;
+        self
+    }
+}
+
+/// What a placeholder says about a media part, captured when the part enters the history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MediaInfo {
+    mime_type: Option<String>,
+    /// Width and height of an image, if its format is recognized
+    dimensions: Option<(u32, u32)>,
+}
+
+impl MediaInfo {
+    /// Describes the media parts of `content`, by part index
+    pub(crate) fn of_content(content: &Content) -> Vec<Option<MediaInfo>> {
+        content
+            .parts
+            .iter()
+            .flatten()
+            .map(|part| match part {
+                Part::InlineData { inline_data } => Some(MediaInfo {
+                    mime_type: Some(inline_data.mime_type.clone()),
+                    dimensions: STANDARD
+                        .decode(&inline_data.data)
+                        .ok()
+                        .and_then(|bytes| image_dimensions(&bytes)),
+                }),
+                Part::FileData { file_data, .. } => Some(MediaInfo {
+                    mime_type: file_data.mime_type.clone(),
+                    dimensions: None,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn placeholder(&self) -> Part {
+        let mime_type = self.mime_type.as_deref().unwrap_or("file");
+        let kind = mime_type
+            .split('/')
+            .next()
+            .filter(|kind| matches!(*kind, "image" | "audio" | "video"))
+            .unwrap_or("file");
+        let text = match self.dimensions {
+            Some((width, height)) => {
+                format!("[{kind}: previously shared {mime_type}, {width}x{height}]")
+            }
+            None => format!("[{kind}: previously shared {mime_type}]"),
+        };
+        Part::Text {
+            text,
+            thought: None,
+            thought_signature: None,
+        }
+    }
+}
+
+/// Applies `policy` to a copy of `history`.
+///
+/// `turns[i]` is the turn `history[i]` belongs to and `media[i]` describes its parts;
+/// `current` is the turn about to be sent.
+pub(crate) fn compress(
+    history: &[Content],
+    turns: &[usize],
+    media: &[Vec<Option<MediaInfo>>],
+    policy: &MediaPolicy,
+    current: usize,
+) -> Vec<Content> {
+    let oldest_kept = current.saturating_sub(policy.keep_turns);
+    history
+        .iter()
+        .zip(turns)
+        .zip(media)
+        .map(|((content, &turn), media)| {
+            if turn >= oldest_kept || media.iter().all(Option::is_none) {
+                return content.clone();
+            }
+            let mut dropped = None;
+            let parts: Vec<Part> = content
+                .parts
+                .iter()
+                .flatten()
+                .zip(media.iter().chain(std::iter::repeat(&None)))
+                .filter_map(|(part, info)| {
+                    let action = match part {
+                        Part::InlineData { .. } => policy.inline_data,
+                        Part::FileData { .. } => policy.file_data,
+                        _ => MediaAction::Keep,
+                    };
+                    match (action, info) {
+                        (MediaAction::Placeholder, Some(info)) => Some(info.placeholder()),
+                        (MediaAction::Drop, Some(info)) => {
+                            dropped.get_or_insert_with(|| info.placeholder());
+                            None
+                        }
+                        _ => Some(part.clone()),
+                    }
+                })
+                .collect();
+            Content {
+                parts: Some(match (parts.is_empty(), dropped) {
+                    (true, Some(placeholder)) => vec![placeholder],
+                    _ => parts,
+                }),
+                role: content.role.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Reads the width and height from the header of a PNG, JPEG, GIF, or WebP image
+pub(crate) fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |at: usize| Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let le16 = |at: usize| Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let be32 = |at: usize| Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+    let le24 = |at: usize| {
+        let b = bytes.get(at..at + 3)?;
+        Some(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16)
+    };
+
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") && bytes.get(12..16) == Some(b"IHDR") {
+        return Some((be32(16)?, be32(20)?));
+    }
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        return Some((le16(6)?, le16(8)?));
+    }
+    if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        return match bytes.get(12..16)? {
+            b"VP8 " => Some((le16(26)? & 0x3fff, le16(28)? & 0x3fff)),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(bytes.get(21..25)?.try_into().ok()?);
+                Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+            }
+            b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+            _ => None,
+        };
+    }
+    if bytes.starts_with(&[0xff, 0xd8]) {
+        // Walk the segments up to the start-of-frame marker
+        let mut at = 2;
+        while *bytes.get(at)? == 0xff {
+            let marker = *bytes.get(at + 1)?;
+            let is_frame = matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc);
+            if is_frame {
+                return Some((be16(at + 7)?, be16(at + 5)?));
+            }
+            at += 2 + be16(at + 2)? as usize;
+        }
+    }
+    None
+}
@@ -0,0 +1,16 @@
+//! # Chat Module
+//!
+//! This module provides [`ChatSession`], a stateful multi-turn conversation that keeps the
+//! history on the client and appends each exchange automatically, and short
+//! [titles and summaries](crate::chat::title) of its conversation.
+
+pub mod media;
+pub mod model;
+pub mod session;
+pub mod title;
+pub mod tree;
+
+pub use media::{MediaAction, MediaPolicy};
+pub use model::{SeedStrategy, SessionExport, TurnRecord};
+pub use session::{ChatSession, SessionView};
+pub use tree::SessionTree;
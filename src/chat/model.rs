@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Content, FinishReason, Model, UsageMetadata};
+
+/// How decoding seeds are assigned to the requests sent by a session
+///
+/// Pinning seeds makes a multi-step run reproducible: replaying the same session with the
+/// same strategy sends the same `generationConfig.seed` on every round trip.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SeedStrategy {
+    /// Leave the seed unset and let the backend pick one
+    #[default]
+    None,
+    /// Use the same seed for every request
+    Fixed(i32),
+    /// Derive a distinct seed per round trip by mixing the turn index into a base seed
+    #[serde(rename_all = "camelCase")]
+    PerTurnDerived {
+        /// The seed the per-turn seeds are derived from
+        base_seed: i32,
+    },
+}
+
+impl SeedStrategy {
+    /// Returns the seed to use for the round trip with the given (zero-based) index
+    ///
+    /// Derived seeds are computed with a fixed mixing function, so they are stable across
+    /// processes, platforms, and crate versions.
+    pub fn seed_for_turn(&self, turn: usize) -> Option<i32> {
+        match *self {
+            SeedStrategy::None => None,
+            SeedStrategy::Fixed(seed) => Some(seed),
+            SeedStrategy::PerTurnDerived { base_seed } => {
+                let mixed = splitmix64((base_seed as u32 as u64) << 32 | turn as u64);
+                // Keep the seed non-negative, as expected by the API
+                Some((mixed >> 33) as i32)
+            }
+        }
+    }
+}
+
+/// The SplitMix64 finalizer
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Record of a single round trip made by a session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TurnRecord {
+    /// Zero-based index of the round trip within the session
+    pub turn: usize,
+    /// The seed sent with the request, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i32>,
+    /// The finish reason of the first candidate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<FinishReason>,
+    /// Token usage reported for the round trip
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_metadata: Option<UsageMetadata>,
+}
+
+/// Serializable snapshot of a session, sufficient to replay it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionExport {
+    /// The model the session talked to
+    ///
+    /// The history's thought signatures are only valid for this model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<Model>,
+    /// The system instruction attached to every request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    /// The conversation history
+    pub history: Vec<Content>,
+    /// The seed strategy of the session
+    pub seed_strategy: SeedStrategy,
+    /// One record per round trip, including the seed actually used
+    pub turns: Vec<TurnRecord>,
+}
+
+impl SessionExport {
+    /// Removes the thought signatures of the history, returning how many were removed.
+    pub fn strip_thought_signatures(&mut self) -> usize {
+        self.history
+            .iter_mut()
+            .map(Content::strip_thought_signatures)
+            .sum()
+    }
+}
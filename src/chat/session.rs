@@ -0,0 +1,666 @@
+#[cfg(feature = "streaming")]
+use futures::{StreamExt, TryStream, TryStreamExt};
+use std::sync::Arc;
+use tracing::instrument;
+
+#[cfg(feature = "streaming")]
+use crate::models::push_merged;
+use crate::{
+    chat::{
+        media::{self, MediaInfo, MediaPolicy},
+        model::{SeedStrategy, SessionExport, TurnRecord},
+        title::{self, DEFAULT_SUMMARY_MODEL},
+        tree::{self, SessionTree, TurnNode},
+    },
+    client::{EmptyConversationSnafu, Error as ClientError, GeminiClient},
+    Content, ContentBuilder, GenerationConfig, GenerationResponse, Message, Model, Part, Role,
+    ToolConfig,
+};
+#[cfg(feature = "tools")]
+use crate::{tools::run_log::RunRecorder, RunLog, RunLogOptions, ToolRegistry};
+
+/// A multi-turn conversation that keeps its history on the client
+///
+/// Every call to [`send_message()`](Self::send_message) sends the full history followed by
+/// the new user message, and appends both the user message and the model reply on success.
+///
+/// Sending takes `&mut self`, so two sends on the same session can never overlap and
+/// interleave their exchanges in the history:
+///
+/// ```compile_fail,E0499
+/// # async fn send_twice(mut session: gemini_rust::ChatSession) {
+/// let first = session.send_message("Hi");
+/// let second = session.send_message("Hello");
+/// let _ = futures::join!(first, second);
+/// # }
+/// ```
+///
+/// To send from several tasks, share the session behind an async mutex such as
+/// `Arc<tokio::sync::Mutex<ChatSession>>`; sends then run one after the other. To render the
+/// conversation while a send is in progress, take a [`view()`](Self::view) first.
+#[derive(Clone)]
+pub struct ChatSession {
+    client: Arc<GeminiClient>,
+    system_instruction: Option<Content>,
+    generation_config: Option<GenerationConfig>,
+    seed_strategy: SeedStrategy,
+    history: Arc<Vec<Content>>,
+    /// The turn each content of the history belongs to
+    history_turns: Vec<usize>,
+    /// The media parts of each content of the history
+    history_media: Vec<Vec<Option<MediaInfo>>>,
+    media_policy: Option<MediaPolicy>,
+    turns: Arc<Vec<TurnRecord>>,
+    nodes: Vec<TurnNode>,
+    /// The tree the session is recorded in, and its branch there
+    tree: Option<(SessionTree, usize)>,
+    /// Model generating titles and summaries
+    summary_model: Model,
+}
+
+impl ChatSession {
+    /// Creates a new empty session.
+    pub(crate) fn new(client: Arc<GeminiClient>) -> Self {
+        Self {
+            client,
+            system_instruction: None,
+            generation_config: None,
+            seed_strategy: SeedStrategy::default(),
+            history: Arc::default(),
+            history_turns: Vec::new(),
+            history_media: Vec::new(),
+            media_policy: None,
+            turns: Arc::default(),
+            nodes: Vec::new(),
+            tree: None,
+            summary_model: DEFAULT_SUMMARY_MODEL,
+        }
+    }
+
+    /// Resumes an exported session.
+    ///
+    /// Thought signatures are bound to the model that produced them. If the session was
+    /// exported from a different model, a warning is logged and the signatures are stripped
+    /// from the history before it is sent.
+    pub(crate) fn resume(client: Arc<GeminiClient>, mut export: SessionExport) -> Self {
+        let exported = export.model.take();
+        if let Some(exported) = exported.filter(|m| m.as_str() != client.model.as_str()) {
+            let stripped = export.strip_thought_signatures();
+            tracing::warn!(
+                exported = %exported,
+                model = %client.model,
+                stripped,
+                "resuming session on a different model, thought signatures stripped"
+            );
+        }
+
+        Self {
+            system_instruction: export.system_instruction,
+            seed_strategy: export.seed_strategy,
+            history_turns: turns_of(&export.history),
+            history_media: export.history.iter().map(MediaInfo::of_content).collect(),
+            history: Arc::new(export.history),
+            turns: Arc::new(export.turns),
+            ..Self::new(client)
+        }
+    }
+
+    /// Seeds the session with prior turns, replacing its history.
+    ///
+    /// A user message other than a function response starts a new turn, so `history` may hold
+    /// function calls and their responses. Each seeded turn gets a [`TurnRecord`] without seed,
+    /// finish reason, or usage.
+    pub fn with_history(mut self, history: impl IntoIterator<Item = Content>) -> Self {
+        let history: Vec<Content> = history.into_iter().collect();
+        self.history_turns = turns_of(&history);
+        self.history_media = history.iter().map(MediaInfo::of_content).collect();
+        let turns = self.history_turns.last().map_or(0, |last| last + 1);
+        self.turns = Arc::new(
+            (0..turns)
+                .map(|turn| TurnRecord {
+                    turn,
+                    seed: None,
+                    finish_reason: None,
+                    usage_metadata: None,
+                })
+                .collect(),
+        );
+        self.nodes = self
+            .turns
+            .iter()
+            .map(|record| {
+                let contents: Vec<Content> = history
+                    .iter()
+                    .zip(&self.history_turns)
+                    .filter(|(_, turn)| **turn == record.turn)
+                    .map(|(content, _)| content.clone())
+                    .collect();
+                TurnNode::new(&contents, record)
+            })
+            .collect();
+        self.history = Arc::new(history);
+        if let Some((tree, branch)) = &mut self.tree {
+            *branch = tree.add_branch(None, self.nodes.clone());
+        }
+        self
+    }
+
+    /// Sets the system instruction sent with every request of the session.
+    pub fn with_system_instruction(mut self, text: impl Into<String>) -> Self {
+        self.system_instruction = Some(Content::text(text));
+        self
+    }
+
+    /// Sets the generation config sent with every request of the session.
+    pub fn with_generation_config(mut self, config: GenerationConfig) -> Self {
+        self.generation_config = Some(config);
+        self
+    }
+
+    /// Sets how decoding seeds are assigned to the requests of the session.
+    ///
+    /// A seed chosen by the strategy overrides any seed in the session's generation config.
+    pub fn with_seed_strategy(mut self, strategy: SeedStrategy) -> Self {
+        self.seed_strategy = strategy;
+        self
+    }
+
+    /// Compresses the media of older turns in every request according to `policy`.
+    ///
+    /// Only the requests are affected: the history keeps every part as it was sent, so
+    /// [`history()`](Self::history) and [`export()`](Self::export) stay complete.
+    pub fn compress_media(mut self, policy: MediaPolicy) -> Self {
+        self.media_policy = Some(policy);
+        self
+    }
+
+    /// Generates titles and summaries with `model` instead of [`DEFAULT_SUMMARY_MODEL`].
+    pub fn with_summary_model(mut self, model: impl Into<Model>) -> Self {
+        self.summary_model = model.into();
+        self
+    }
+
+    /// Records the session, and every session later forked from it, in `tree`.
+    pub fn with_tree(mut self, tree: &SessionTree) -> Self {
+        let branch = tree.add_branch(None, self.nodes.clone());
+        self.tree = Some((tree.clone(), branch));
+        self
+    }
+
+    /// Returns an independent copy of the session to continue in another direction.
+    ///
+    /// If the session is recorded in a [`SessionTree`], the fork is recorded as a branch
+    /// starting after the turns made so far.
+    pub fn fork(&self) -> Self {
+        let mut fork = self.clone();
+        if let Some((tree, branch)) = &self.tree {
+            let child = tree.add_branch(Some((*branch, self.turns.len())), Vec::new());
+            fork.tree = Some((tree.clone(), child));
+        }
+        fork
+    }
+
+    /// Renders the turns of the session as a Mermaid flowchart.
+    ///
+    /// See [`SessionTree::to_mermaid()`] to include forked sessions.
+    pub fn to_mermaid(&self) -> String {
+        tree::session_mermaid(&self.nodes)
+    }
+
+    /// Returns the conversation history.
+    pub fn history(&self) -> &[Content] {
+        &self.history
+    }
+
+    /// Keeps the first `turns` turns of the conversation, dropping the history and records of
+    /// later turns.
+    ///
+    /// If the session is recorded in a [`SessionTree`], later turns are recorded as a new
+    /// branch starting after the kept turns, like a [fork](Self::fork).
+    pub fn truncate(&mut self, turns: usize) {
+        if turns >= self.turns.len() {
+            return;
+        }
+        let kept = self.history_turns.partition_point(|&turn| turn < turns);
+        Arc::make_mut(&mut self.history).truncate(kept);
+        self.history_turns.truncate(kept);
+        self.history_media.truncate(kept);
+        Arc::make_mut(&mut self.turns).truncate(turns);
+        self.nodes.truncate(turns);
+        if let Some((tree, branch)) = &mut self.tree {
+            *branch = tree.add_branch(Some((*branch, turns)), Vec::new());
+        }
+    }
+
+    /// Drops the whole conversation, keeping the session's settings.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Returns one record per round trip made so far.
+    pub fn turns(&self) -> &[TurnRecord] {
+        &self.turns
+    }
+
+    /// Returns a read-only snapshot of the history and turns.
+    ///
+    /// Taking a view is cheap, and the view does not borrow the session: it can be rendered
+    /// from another task while the session sends its next message, and keeps showing the
+    /// state at the time it was taken.
+    pub fn view(&self) -> SessionView {
+        SessionView {
+            history: self.history.clone(),
+            turns: self.turns.clone(),
+        }
+    }
+
+    /// Exports the session, including the seed used for every round trip.
+    pub fn export(&self) -> SessionExport {
+        SessionExport {
+            model: Some(self.client.model.clone()),
+            system_instruction: self.system_instruction.clone(),
+            history: self.history.to_vec(),
+            seed_strategy: self.seed_strategy,
+            turns: self.turns.to_vec(),
+        }
+    }
+
+    /// Generates a title of at most `max_chars` characters from the first exchange, see
+    /// [`title`].
+    ///
+    /// The history is left unchanged. A session without any exchange yet fails with
+    /// [`ClientError::EmptyConversation`].
+    #[instrument(skip_all, fields(max_chars))]
+    pub async fn generate_title(&self, max_chars: usize) -> Result<String, ClientError> {
+        let first = self.history_turns.partition_point(|&turn| turn == 0);
+        let transcript = title::transcript(
+            &self.history[..first],
+            &self.history_turns[..first],
+            &self.history_media[..first],
+        );
+        let raw = self
+            .describe(title::title_instruction(max_chars), transcript)
+            .await?;
+        Ok(title::clean_title(&raw, max_chars))
+    }
+
+    /// Generates a summary of the whole conversation in at most `max_sentences` sentences,
+    /// see [`title`].
+    ///
+    /// The history is left unchanged. A session without any exchange yet fails with
+    /// [`ClientError::EmptyConversation`].
+    #[instrument(skip_all, fields(max_sentences))]
+    pub async fn generate_summary(&self, max_sentences: usize) -> Result<String, ClientError> {
+        let transcript = title::transcript(&self.history, &self.history_turns, &self.history_media);
+        let raw = self
+            .describe(title::summary_instruction(max_sentences), transcript)
+            .await?;
+        Ok(title::clean_summary(&raw, max_sentences))
+    }
+
+    /// Sends `transcript` to the summary model with `instruction`, outside of the session
+    async fn describe(
+        &self,
+        instruction: String,
+        transcript: String,
+    ) -> Result<String, ClientError> {
+        snafu::ensure!(!transcript.is_empty(), EmptyConversationSnafu);
+        let client = Arc::new(self.client.for_model(self.summary_model.clone()));
+        let response = ContentBuilder::new(client)
+            .with_system_instruction(instruction)
+            .with_user_message(transcript)
+            .with_temperature(0.2)
+            .execute()
+            .await?;
+        Ok(response.text())
+    }
+
+    /// Sends a user message and appends the exchange to the history.
+    ///
+    /// A blocked prompt fails with [`ClientError::PromptBlocked`] and leaves the history
+    /// unchanged. If the response carries no candidate for another reason, the history is
+    /// left unchanged too, but the round trip is still recorded.
+    #[instrument(skip_all, fields(turn = self.turns.len()))]
+    pub async fn send_message(
+        &mut self,
+        text: impl Into<String>,
+    ) -> Result<GenerationResponse, ClientError> {
+        self.send(Message::user(text).content, TurnOverrides::default())
+            .await
+    }
+
+    /// Sends a user message and streams the response, appending the exchange to the history
+    /// once the stream ends.
+    ///
+    /// The model's reply is recorded as the merged parts of the first candidate of every
+    /// chunk. If the stream fails or is dropped before it ends, the history is left unchanged
+    /// and no turn is recorded. The stream borrows the session, so no other message can be
+    /// sent until it is dropped.
+    #[cfg(feature = "streaming")]
+    #[instrument(skip_all, fields(turn = self.turns.len()))]
+    pub async fn send_message_stream(
+        &mut self,
+        text: impl Into<String>,
+    ) -> Result<
+        impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send + Unpin + '_,
+        ClientError,
+    > {
+        let (builder, pending) = self.prepare(Message::user(text).content, None, None);
+        let added = builder.contents[pending.sent..].to_vec();
+        let stream = builder.execute_stream().await?;
+        Ok(Box::pin(async_stream::try_stream! {
+            let mut parts = Vec::new();
+            let mut record = TurnRecord {
+                turn: pending.turn,
+                seed: pending.seed,
+                finish_reason: None,
+                usage_metadata: None,
+            };
+            let mut replied = false;
+            let stream = stream.into_stream();
+            futures::pin_mut!(stream);
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                if let Some(candidate) = chunk.candidates.first() {
+                    replied = true;
+                    for part in candidate.content.parts.iter().flatten() {
+                        push_merged(&mut parts, part.clone());
+                    }
+                    if candidate.finish_reason.is_some() {
+                        record.finish_reason = candidate.finish_reason.clone();
+                    }
+                }
+                if chunk.usage_metadata.is_some() {
+                    record.usage_metadata = chunk.usage_metadata.clone();
+                }
+                yield chunk;
+            }
+            let reply = replied.then_some(Content {
+                parts: Some(parts),
+                role: Some(Role::Model),
+            });
+            self.record(added, reply, record);
+        }))
+    }
+
+    /// Sends a user message made of arbitrary parts, e.g. text and inline images, and appends
+    /// the exchange to the history.
+    #[instrument(skip_all, fields(turn = self.turns.len()))]
+    pub async fn send_message_parts(
+        &mut self,
+        parts: Vec<Part>,
+    ) -> Result<GenerationResponse, ClientError> {
+        let message = Content {
+            parts: Some(parts),
+            role: Some(Role::User),
+        };
+        self.send(message, TurnOverrides::default()).await
+    }
+
+    /// Sends a user message, running the registry's handlers for every function call the
+    /// model makes, see [`ContentBuilder::execute_with_tools()`].
+    ///
+    /// On success the history receives the user message, every function call and response,
+    /// and the final answer.
+    #[cfg(feature = "tools")]
+    #[instrument(skip_all, fields(turn = self.turns.len()))]
+    pub async fn send_message_with_tools(
+        &mut self,
+        text: impl Into<String>,
+        registry: &ToolRegistry,
+    ) -> Result<GenerationResponse, ClientError> {
+        self.send_with_tools(
+            Message::user(text).content,
+            registry,
+            TurnOverrides::default(),
+            None,
+        )
+        .await
+    }
+
+    /// Sends a user message like [`send_message_with_tools()`](Self::send_message_with_tools),
+    /// returning a [`RunLog`] of the tool loop alongside the result, see
+    /// [`ContentBuilder::execute_with_tools_logged()`].
+    #[cfg(feature = "tools")]
+    #[instrument(skip_all, fields(turn = self.turns.len()))]
+    pub async fn send_message_with_tools_logged(
+        &mut self,
+        text: impl Into<String>,
+        registry: &ToolRegistry,
+        options: RunLogOptions,
+    ) -> (Result<GenerationResponse, ClientError>, RunLog) {
+        let mut recorder = RunRecorder::new(options);
+        let result = self
+            .send_with_tools(
+                Message::user(text).content,
+                registry,
+                TurnOverrides::default(),
+                Some(&mut recorder),
+            )
+            .await;
+        let log = recorder.finish(&result);
+        (result, log)
+    }
+
+    /// Sends a user message with `config_override` merged over the session's generation
+    /// config, and appends the exchange to the history.
+    ///
+    /// The override applies to this round trip only; fields it leaves unset keep the
+    /// session's values, see [`GenerationConfig::merged_with()`].
+    #[instrument(skip_all, fields(turn = self.turns.len()))]
+    pub async fn send_message_with(
+        &mut self,
+        config_override: GenerationConfig,
+        text: impl Into<String>,
+    ) -> Result<GenerationResponse, ClientError> {
+        let overrides = TurnOverrides {
+            generation_config: Some(config_override),
+            ..Default::default()
+        };
+        self.send(Message::user(text).content, overrides).await
+    }
+
+    /// Sends a user message like [`send_message_with_tools()`](Self::send_message_with_tools),
+    /// with `tool_config` deciding whether the model may call the registry's functions in this
+    /// round trip only.
+    ///
+    /// For example, [`FunctionCallingMode::None`](crate::FunctionCallingMode::None) keeps the
+    /// model from calling any function for one turn.
+    #[cfg(feature = "tools")]
+    #[instrument(skip_all, fields(turn = self.turns.len()))]
+    pub async fn send_message_with_tools_config(
+        &mut self,
+        tool_config: ToolConfig,
+        text: impl Into<String>,
+        registry: &ToolRegistry,
+    ) -> Result<GenerationResponse, ClientError> {
+        let overrides = TurnOverrides {
+            tool_config: Some(tool_config),
+            ..Default::default()
+        };
+        self.send_with_tools(Message::user(text).content, registry, overrides, None)
+            .await
+    }
+
+    async fn send(
+        &mut self,
+        message: Content,
+        overrides: TurnOverrides,
+    ) -> Result<GenerationResponse, ClientError> {
+        let (builder, pending) =
+            self.prepare(message, overrides.generation_config, overrides.tool_config);
+        let added = builder.contents[pending.sent..].to_vec();
+        let response = builder.execute().await?;
+        Ok(self.answered(pending, added, response))
+    }
+
+    /// Sends `message` through the tool loop, the history receiving every function call and
+    /// response
+    #[cfg(feature = "tools")]
+    async fn send_with_tools(
+        &mut self,
+        message: Content,
+        registry: &ToolRegistry,
+        overrides: TurnOverrides,
+        run_log: Option<&mut RunRecorder>,
+    ) -> Result<GenerationResponse, ClientError> {
+        let (mut builder, pending) =
+            self.prepare(message, overrides.generation_config, overrides.tool_config);
+        let response = builder.run_tools(registry, run_log).await?;
+        let added = builder.contents.split_off(pending.sent);
+        Ok(self.answered(pending, added, response))
+    }
+
+    /// Records the round trip of `response`, whose request added `added` to the history
+    fn answered(
+        &mut self,
+        pending: PendingTurn,
+        added: Vec<Content>,
+        response: GenerationResponse,
+    ) -> GenerationResponse {
+        let candidate = response.candidates.first();
+        let record = TurnRecord {
+            turn: pending.turn,
+            seed: pending.seed,
+            finish_reason: candidate.and_then(|c| c.finish_reason.clone()),
+            usage_metadata: response.usage_metadata.clone(),
+        };
+        self.record(added, candidate.map(|c| c.content.clone()), record);
+        response
+    }
+
+    /// Builds the request of the next round trip, ending with `message`
+    fn prepare(
+        &self,
+        message: Content,
+        config_override: Option<GenerationConfig>,
+        tool_config: Option<ToolConfig>,
+    ) -> (ContentBuilder, PendingTurn) {
+        let turn = self.turns.len();
+        tracing::debug!(message = %message, "sending message");
+
+        let mut builder = ContentBuilder::new(self.client.clone());
+        builder.contents = match &self.media_policy {
+            Some(policy) => media::compress(
+                &self.history,
+                &self.history_turns,
+                &self.history_media,
+                policy,
+                turn,
+            ),
+            None => self.history.to_vec(),
+        };
+        builder.contents.push(message);
+        if let Some(instruction) = &self.system_instruction {
+            builder = builder.with_system_instruction_content(instruction.clone());
+        }
+        let config = match config_override {
+            Some(over) => Some(
+                self.generation_config
+                    .clone()
+                    .unwrap_or_default()
+                    .merged_with(&over),
+            ),
+            None => self.generation_config.clone(),
+        };
+        let seed = self
+            .seed_strategy
+            .seed_for_turn(turn)
+            .or_else(|| config.as_ref().and_then(|c| c.seed));
+        if let Some(config) = config {
+            builder = builder.with_generation_config(config);
+        }
+        if let Some(tool_config) = tool_config {
+            builder = builder.with_tool_config(tool_config);
+        }
+        if let Some(seed) = seed {
+            builder = builder.with_seed(seed);
+        }
+
+        // The request may carry compressed media; only what this turn added goes to the history
+        let pending = PendingTurn {
+            turn,
+            seed,
+            sent: self.history.len(),
+        };
+        (builder, pending)
+    }
+
+    /// Records a round trip, appending `added` and the model's `reply` to the history if the
+    /// model replied
+    fn record(&mut self, mut added: Vec<Content>, reply: Option<Content>, record: TurnRecord) {
+        let replied = reply.is_some();
+        if let Some(reply) = reply {
+            added.push(reply.with_role(Role::Model));
+        }
+        let node = TurnNode::new(&added, &record);
+        if let Some((tree, branch)) = &self.tree {
+            tree.record(*branch, node.clone());
+        }
+        self.nodes.push(node);
+        let turn = record.turn;
+        Arc::make_mut(&mut self.turns).push(record);
+        if replied {
+            self.history_turns
+                .extend(std::iter::repeat_n(turn, added.len()));
+            self.history_media
+                .extend(added.iter().map(MediaInfo::of_content));
+            Arc::make_mut(&mut self.history).extend(added);
+        }
+    }
+}
+
+/// A round trip whose request is built but not yet answered
+struct PendingTurn {
+    turn: usize,
+    seed: Option<i32>,
+    /// Number of history contents at the start of the request
+    sent: usize,
+}
+
+/// Settings replacing the session's for a single round trip
+#[derive(Default)]
+struct TurnOverrides {
+    generation_config: Option<GenerationConfig>,
+    tool_config: Option<ToolConfig>,
+}
+
+/// A read-only snapshot of a [`ChatSession`], see [`ChatSession::view()`]
+#[derive(Debug, Clone, Default)]
+pub struct SessionView {
+    history: Arc<Vec<Content>>,
+    turns: Arc<Vec<TurnRecord>>,
+}
+
+impl SessionView {
+    /// Returns the conversation history at the time the view was taken.
+    pub fn history(&self) -> &[Content] {
+        &self.history
+    }
+
+    /// Returns the records of the round trips made at the time the view was taken.
+    pub fn turns(&self) -> &[TurnRecord] {
+        &self.turns
+    }
+}
+
+/// The turn of each content of `history`: a user message other than a function response
+/// starts a new turn
+fn turns_of(history: &[Content]) -> Vec<usize> {
+    let mut turn = 0;
+    history
+        .iter()
+        .enumerate()
+        .map(|(index, content)| {
+            let function_response = content
+                .parts
+                .iter()
+                .flatten()
+                .any(|part| matches!(part, Part::FunctionResponse { .. }));
+            if index > 0 && content.role == Some(Role::User) && !function_response {
+                turn += 1;
+            }
+            turn
+        })
+        .collect()
+}
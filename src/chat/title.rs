@@ -0,0 +1,157 @@
+//! Titles and summaries of sessions.
+//!
+//! [`ChatSession::generate_title()`](crate::ChatSession::generate_title) and
+//! [`ChatSession::generate_summary()`](crate::ChatSession::generate_summary) send a plain-text
+//! transcript of the conversation to [`DEFAULT_SUMMARY_MODEL`], or the model set with
+//! [`ChatSession::with_summary_model()`](crate::ChatSession::with_summary_model). Media is
+//! replaced with placeholders such as `[image: previously shared image/png, 640x480]`, and
+//! function calls and responses are reduced to their names. Neither request is recorded in the
+//! session.
+//!
+//! The model's answer is cleaned up before it is returned: a title is a single line without
+//! quotes or Markdown, cut at a word with an ellipsis if longer than asked; a summary is a
+//! single paragraph of at most the sentences asked for.
+
+use crate::{
+    chat::media::{self, MediaAction, MediaInfo, MediaPolicy},
+    text_utils::{normalize_whitespace, strip_markdown},
+    Content, Model, Part, Role,
+};
+
+/// Model generating titles and summaries, unless set with
+/// [`ChatSession::with_summary_model()`](crate::ChatSession::with_summary_model)
+pub const DEFAULT_SUMMARY_MODEL: Model = Model::Gemini25FlashLite;
+
+/// Instruction of title requests; `{max_chars}` is replaced with the length limit
+const TITLE_INSTRUCTION: &str = "You name chat conversations for a sidebar. Reply with a \
+    title for the conversation below: a few words, at most {max_chars} characters, on one \
+    line, without quotes or trailing punctuation. Reply with the title only.";
+
+/// Instruction of summary requests; `{max_sentences}` is replaced with the sentence limit
+const SUMMARY_INSTRUCTION: &str = "You summarize chat conversations. Reply with a summary \
+    of the conversation below in at most {max_sentences} sentence(s) of plain prose, without \
+    lists or Markdown. Reply with the summary only.";
+
+/// The system instruction of a title request
+pub(crate) fn title_instruction(max_chars: usize) -> String {
+    TITLE_INSTRUCTION.replace("{max_chars}", &max_chars.to_string())
+}
+
+/// The system instruction of a summary request
+pub(crate) fn summary_instruction(max_sentences: usize) -> String {
+    SUMMARY_INSTRUCTION.replace("{max_sentences}", &max_sentences.to_string())
+}
+
+/// Renders `history` as a plain-text transcript, one `User:` or `Model:` paragraph per
+/// content, with media replaced by placeholders.
+///
+/// `turns` and `infos` describe `history` as in the session.
+pub(crate) fn transcript(
+    history: &[Content],
+    turns: &[usize],
+    infos: &[Vec<Option<MediaInfo>>],
+) -> String {
+    let policy = MediaPolicy::new(0).with_file_data(MediaAction::Placeholder);
+    let current = turns.last().map_or(0, |last| last + 1);
+    media::compress(history, turns, infos, &policy, current)
+        .iter()
+        .filter_map(|content| {
+            let text: Vec<String> = content
+                .parts
+                .iter()
+                .flatten()
+                .filter_map(|part| match part {
+                    Part::Text {
+                        thought: Some(true),
+                        ..
+                    } => None,
+                    Part::Text { text, .. } => Some(text.trim().to_string()),
+                    Part::FunctionCall { function_call, .. } => {
+                        Some(format!("[called function {}]", function_call.name))
+                    }
+                    Part::FunctionResponse { function_response } => {
+                        Some(format!("[result of function {}]", function_response.name))
+                    }
+                    _ => None,
+                })
+                .filter(|text| !text.is_empty())
+                .collect();
+            let speaker = match content.role {
+                Some(Role::Model) => "Model",
+                _ => "User",
+            };
+            (!text.is_empty()).then(|| format!("{speaker}: {}", text.join("\n")))
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Cleans a generated title: its first non-empty line without Markdown, a `Title:` label,
+/// surrounding quotes, or trailing punctuation, at most `max_chars` characters long.
+///
+/// Longer titles are cut at the last word that fits and end with `…`.
+pub fn clean_title(raw: &str, max_chars: usize) -> String {
+    let plain = normalize_whitespace(&strip_markdown(raw));
+    let line = plain.lines().find(|line| !line.is_empty()).unwrap_or("");
+    let line = strip_label(line, "title:");
+    let title = line
+        .trim_matches(|c: char| is_quote(c) || c.is_whitespace())
+        .trim_end_matches(|c: char| {
+            matches!(c, '.' | ',' | ';' | ':') || is_quote(c) || c.is_whitespace()
+        });
+    if title.chars().count() <= max_chars {
+        return title.to_string();
+    }
+    if max_chars == 0 {
+        return String::new();
+    }
+    let kept: String = title.chars().take(max_chars - 1).collect();
+    // Drop a word cut in half, unless it is most of what is kept
+    let cut_in_word = !title[kept.len()..].starts_with(' ');
+    let end = match kept.rfind(' ') {
+        Some(space) if cut_in_word && space >= kept.len() / 2 => space,
+        _ => kept.len(),
+    };
+    let kept =
+        kept[..end].trim_end_matches(|c: char| c.is_whitespace() || c.is_ascii_punctuation());
+    format!("{kept}…")
+}
+
+/// Cleans a generated summary: one paragraph of plain text, of at most `max_sentences`
+/// sentences
+pub fn clean_summary(raw: &str, max_sentences: usize) -> String {
+    let plain = normalize_whitespace(&strip_markdown(raw));
+    let paragraph = plain.split_whitespace().collect::<Vec<_>>().join(" ");
+    let paragraph = strip_label(&paragraph, "summary:");
+    if max_sentences == 0 {
+        return String::new();
+    }
+    let mut sentences = 0;
+    let mut chars = paragraph.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        let ends_sentence = matches!(c, '.' | '!' | '?')
+            && chars.peek().is_none_or(|(_, next)| next.is_whitespace());
+        if ends_sentence {
+            sentences += 1;
+            if sentences == max_sentences {
+                return paragraph[..index + c.len_utf8()].to_string();
+            }
+        }
+    }
+    paragraph.to_string()
+}
+
+/// Removes a leading `label` such as `Title:`, in any case
+fn strip_label<'a>(text: &'a str, label: &str) -> &'a str {
+    match text.get(..label.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(label) => text[label.len()..].trim_start(),
+        _ => text,
+    }
+}
+
+fn is_quote(c: char) -> bool {
+    matches!(
+        c,
+        '"' | '\'' | '`' | '\u{2018}' | '\u{2019}' | '\u{201C}' | '\u{201D}' | '«' | '»'
+    )
+}
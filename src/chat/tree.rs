@@ -0,0 +1,230 @@
+//! Mermaid diagrams of sessions and of the branches forked from them.
+//!
+//! Every turn is a node labelled with the (truncated) user message and answer, the finish
+//! reason, and the total token count. Turns of a session are chained with solid edges, tool
+//! calls are dotted edges from the turn to a node per function, and forks are edges labelled
+//! `fork` from the last turn a branch shares with its parent.
+
+use serde_json::Value;
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use crate::{chat::model::TurnRecord, Content, FinishReason, FunctionCall, Part, Role};
+
+/// Maximum number of characters of text shown in a label
+const LABEL_CHARS: usize = 48;
+
+/// What a diagram shows of a turn
+#[derive(Debug, Clone)]
+pub(crate) struct TurnNode {
+    turn: usize,
+    prompt: String,
+    answer: Option<String>,
+    tool_calls: Vec<FunctionCall>,
+    finish_reason: Option<FinishReason>,
+    total_tokens: Option<i32>,
+}
+
+impl TurnNode {
+    /// Summarizes a turn from its contents: the user message, any function calls and
+    /// responses, and the answer if there was one
+    pub(crate) fn new(contents: &[Content], record: &TurnRecord) -> Self {
+        let texts = |content: &Content| -> String {
+            content
+                .parts
+                .iter()
+                .flatten()
+                .filter_map(|part| match part {
+                    Part::Text {
+                        text,
+                        thought: None | Some(false),
+                        ..
+                    } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let tool_calls = contents
+            .iter()
+            .flat_map(|content| content.parts.iter().flatten())
+            .filter_map(|part| match part {
+                Part::FunctionCall { function_call, .. } => Some(function_call.clone()),
+                _ => None,
+            })
+            .collect();
+        let answer = contents
+            .last()
+            .filter(|content| contents.len() > 1 && content.role == Some(Role::Model))
+            .map(texts);
+        Self {
+            turn: record.turn,
+            prompt: contents.first().map(texts).unwrap_or_default(),
+            answer,
+            tool_calls,
+            finish_reason: record.finish_reason.clone(),
+            total_tokens: record
+                .usage_metadata
+                .as_ref()
+                .and_then(|usage| usage.total_token_count),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Branch {
+    /// The branch forked from and the number of turns shared with it
+    parent: Option<(usize, usize)>,
+    turns: Vec<TurnNode>,
+}
+
+/// Collects a session and every session forked from it, to render them as one tree.
+///
+/// Attach it with [`ChatSession::with_tree()`](crate::ChatSession::with_tree); sessions
+/// created by [`ChatSession::fork()`](crate::ChatSession::fork) are then recorded as
+/// branches. Clones share the same tree.
+#[derive(Debug, Clone, Default)]
+pub struct SessionTree {
+    branches: Arc<Mutex<Vec<Branch>>>,
+}
+
+impl SessionTree {
+    /// Creates an empty tree
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a branch, returning its index
+    pub(crate) fn add_branch(&self, parent: Option<(usize, usize)>, turns: Vec<TurnNode>) -> usize {
+        let mut branches = self.branches.lock().expect("session tree lock poisoned");
+        branches.push(Branch { parent, turns });
+        branches.len() - 1
+    }
+
+    /// Records a turn of a branch
+    pub(crate) fn record(&self, branch: usize, turn: TurnNode) {
+        self.branches.lock().expect("session tree lock poisoned")[branch]
+            .turns
+            .push(turn);
+    }
+
+    /// Renders all branches as a Mermaid flowchart
+    pub fn to_mermaid(&self) -> String {
+        render(&self.branches.lock().expect("session tree lock poisoned"))
+    }
+}
+
+/// Renders a single session as a Mermaid flowchart
+pub(crate) fn session_mermaid(turns: &[TurnNode]) -> String {
+    render(&[Branch {
+        parent: None,
+        turns: turns.to_vec(),
+    }])
+}
+
+fn render(branches: &[Branch]) -> String {
+    let mut out = String::from("flowchart TD\n    start((start))\n");
+    let mut tools = BTreeSet::new();
+    for (index, branch) in branches.iter().enumerate() {
+        let shared = branch.parent.map_or(0, |(_, shared)| shared);
+        let mut previous = match branch.parent {
+            Some((parent, shared)) if shared > 0 => format!("s{parent}t{}", shared - 1),
+            _ => "start".to_string(),
+        };
+        let mut edge = match branch.parent {
+            Some(_) => "-->|fork|",
+            None => "-->",
+        };
+        for turn in branch.turns.iter().filter(|turn| turn.turn >= shared) {
+            let id = format!("s{index}t{}", turn.turn);
+            writeln!(out, "    {id}[\"{}\"]", label(turn)).unwrap();
+            writeln!(out, "    {previous} {edge} {id}").unwrap();
+            for call in &turn.tool_calls {
+                let tool = format!("tool_{}", node_id(&call.name));
+                if tools.insert(tool.clone()) {
+                    writeln!(out, "    {tool}[[\"{}\"]]", escape(&call.name)).unwrap();
+                }
+                let args = truncate(&arguments(&call.args));
+                writeln!(out, "    {id} -.->|\"{}\"| {tool}", escape(&args)).unwrap();
+            }
+            previous = id;
+            edge = "-->";
+        }
+    }
+    out
+}
+
+fn label(turn: &TurnNode) -> String {
+    let mut lines = vec![
+        format!("turn {}", turn.turn),
+        format!("user: {}", truncate(&turn.prompt)),
+    ];
+    if let Some(answer) = &turn.answer {
+        lines.push(format!("model: {}", truncate(answer)));
+    }
+    // The wire name, e.g. `MAX_TOKENS`
+    let finish = turn
+        .finish_reason
+        .as_ref()
+        .and_then(|reason| serde_json::to_value(reason).ok())
+        .and_then(|reason| reason.as_str().map(str::to_string))
+        .unwrap_or_else(|| "no answer".to_string());
+    lines.push(match turn.total_tokens {
+        Some(tokens) => format!("{finish} · {tokens} tokens"),
+        None => finish,
+    });
+    lines
+        .iter()
+        .map(|line| escape(line))
+        .collect::<Vec<_>>()
+        .join("<br/>")
+}
+
+/// Renders function call arguments as `name=value` pairs, with strings unquoted
+fn arguments(args: &Value) -> String {
+    match args {
+        Value::Object(args) => args
+            .iter()
+            .map(|(name, value)| match value {
+                Value::String(value) => format!("{name}={value}"),
+                value => format!("{name}={value}"),
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        args => args.to_string(),
+    }
+}
+
+/// Collapses whitespace and shortens text to [`LABEL_CHARS`] characters
+fn truncate(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(LABEL_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+/// Escapes text for a quoted Mermaid label
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("#quot;"),
+            '#' => escaped.push_str("#35;"),
+            '<' => escaped.push_str("#lt;"),
+            '>' => escaped.push_str("#gt;"),
+            '\n' | '\r' | '\t' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A node ID derived from a function name
+fn node_id(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
@@ -0,0 +1,159 @@
+//! Deletion of temporary files and cached contents.
+//!
+//! Files and cached contents created for a single job or test outlive it when the process is
+//! killed or the future deleting them is cancelled. Two mechanisms keep them from piling up:
+//!
+//! - A [`ResourceGuard`], returned by
+//!   [`FileBuilder::upload_guarded()`](crate::files::builder::FileBuilder::upload_guarded) and
+//!   [`CacheBuilder::execute_guarded()`](crate::CacheBuilder::execute_guarded), deletes its
+//!   resource on [`close()`](ResourceGuard::close). A guard dropped without being closed
+//!   deletes the resource in a detached task of the current tokio runtime; without a runtime
+//!   the resource is left behind, with a warning. Until then, the resource is listed by
+//!   [`Gemini::guarded_resources()`](crate::Gemini::guarded_resources).
+//! - [`Gemini::cleanup_orphans()`](crate::Gemini::cleanup_orphans) deletes the files and
+//!   cached contents left over by earlier runs, found by a display name prefix and their age.
+//!   Give temporary resources display names starting with a prefix of your own, e.g.
+//!   `tmp-ci-`, so that the sweep never touches anything else.
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
+
+use crate::client::{Error, GeminiClient};
+
+/// The kind of a guarded resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    /// A file of the Files API
+    #[cfg(feature = "files")]
+    File,
+    /// A cached content
+    #[cfg(feature = "caching")]
+    CachedContent,
+}
+
+/// The names of the resources of a client whose guards have not deleted them yet
+#[derive(Debug, Default)]
+pub(crate) struct ResourceRegistry {
+    names: Mutex<BTreeSet<String>>,
+}
+
+impl ResourceRegistry {
+    fn insert(&self, name: &str) {
+        self.lock().insert(name.to_string());
+    }
+
+    fn remove(&self, name: &str) {
+        self.lock().remove(name);
+    }
+
+    /// The registered names, sorted
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.lock().iter().cloned().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeSet<String>> {
+        self.names.lock().expect("resource registry lock poisoned")
+    }
+}
+
+/// Deletes a file or cached content when closed or dropped, see the
+/// [module documentation](self)
+#[must_use = "dropping the guard deletes the resource"]
+pub struct ResourceGuard {
+    kind: ResourceKind,
+    name: String,
+    client: Option<Arc<GeminiClient>>,
+}
+
+impl ResourceGuard {
+    pub(crate) fn new(client: Arc<GeminiClient>, kind: ResourceKind, name: String) -> Self {
+        client.resources.insert(&name);
+        Self {
+            kind,
+            name,
+            client: Some(client),
+        }
+    }
+
+    /// The resource name, e.g. `files/abc-123`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The kind of the resource
+    pub fn kind(&self) -> ResourceKind {
+        self.kind
+    }
+
+    /// Deletes the resource.
+    ///
+    /// If deleting fails, the resource stays registered and the guard is dropped without
+    /// trying again; [`Gemini::cleanup_orphans()`](crate::Gemini::cleanup_orphans) can sweep
+    /// it later. If the returned future is cancelled, the guard deletes the resource as if
+    /// dropped.
+    pub async fn close(mut self) -> Result<(), Error> {
+        let client = self.client.clone().expect("an open guard has a client");
+        let result = delete(&client, self.kind, &self.name).await;
+        self.client = None;
+        if result.is_ok() {
+            client.resources.remove(&self.name);
+        }
+        result
+    }
+
+    /// Keeps the resource, returning its name
+    pub fn keep(mut self) -> String {
+        if let Some(client) = self.client.take() {
+            client.resources.remove(&self.name);
+        }
+        std::mem::take(&mut self.name)
+    }
+}
+
+impl Drop for ResourceGuard {
+    fn drop(&mut self) {
+        let Some(client) = self.client.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!(
+                resource.name = %self.name,
+                "resource guard dropped outside a tokio runtime, leaving the resource behind"
+            );
+            return;
+        };
+        let (kind, name) = (self.kind, std::mem::take(&mut self.name));
+        runtime.spawn(async move {
+            match delete(&client, kind, &name).await {
+                Ok(()) => client.resources.remove(&name),
+                Err(error) => {
+                    tracing::warn!(resource.name = %name, %error, "deleting dropped resource failed")
+                }
+            }
+        });
+    }
+}
+
+pub(crate) async fn delete(
+    client: &GeminiClient,
+    kind: ResourceKind,
+    name: &str,
+) -> Result<(), Error> {
+    match kind {
+        #[cfg(feature = "files")]
+        ResourceKind::File => client.delete_file(name).await,
+        #[cfg(feature = "caching")]
+        ResourceKind::CachedContent => client.delete_cached_content(name).await,
+    }
+}
+
+/// The result of [`Gemini::cleanup_orphans()`](crate::Gemini::cleanup_orphans)
+#[derive(Debug, Default)]
+pub struct OrphanSweep {
+    /// The names of the resources deleted
+    pub deleted: Vec<String>,
+    /// The resources that matched but could not be deleted
+    pub failed: Vec<(String, Error)>,
+}
@@ -1,35 +1,97 @@
+#[cfg(feature = "embeddings")]
+use crate::backend::{PredictRequest, PredictResponse};
+#[cfg(feature = "batch")]
+use crate::batch::{model::*, BatchBuilder, BatchHandle};
+#[cfg(all(feature = "caching", feature = "files"))]
+use crate::cache::CacheFromFileBuilder;
+#[cfg(feature = "caching")]
+use crate::cache::{model::*, CacheBuilder, CachedContentHandle, Caches};
+#[cfg(any(feature = "files", feature = "caching"))]
+use crate::cleanup::{self, OrphanSweep, ResourceKind, ResourceRegistry};
+#[cfg(feature = "embeddings")]
+use crate::embedding::{
+    BatchContentEmbeddingResponse, BatchEmbedContentsRequest, ChunkingConfig, ChunkingError,
+    ContentEmbeddingResponse, EmbedBatchBuilder, EmbedBuilder, EmbedContentRequest,
+    EmbedDocumentBuilder,
+};
+#[cfg(feature = "files")]
+use crate::files::{
+    api::Files,
+    builder::FileSource,
+    handle::FileHandle,
+    model::{File, ListFilesResponse},
+};
+#[cfg(feature = "streaming")]
+use crate::generation::aggregate::AggregatedPartial;
 use crate::{
-    batch::{BatchBuilder, BatchHandle},
-    cache::{CacheBuilder, CachedContentHandle},
-    embedding::{
-        BatchContentEmbeddingResponse, BatchEmbedContentsRequest, ContentEmbeddingResponse,
-        EmbedBuilder, EmbedContentRequest,
+    auth::{Auth, TokenError, TokenProvider},
+    backend::{
+        vertex_base_url, vertex_parent, Backend, VertexCountTokensRequest, VERTEX_EXPRESS_BASE_URL,
     },
-    files::{
-        handle::FileHandle,
-        model::{File, ListFilesResponse},
+    chat::{ChatSession, SessionExport},
+    clock::{Clock, TokioClock},
+    common::rpc::Status,
+    dns::{self, ResolvedAddrs},
+    generation::{
+        count::CountTokensBuilder,
+        dataset::{DatasetBuilder, TokenBudget},
+        provenance::FieldOrigin,
+        race::{self, RaceAttempt, RaceResult},
+        BlockReason, ContentBuilder, CountTokensResponse, GenerateContentRequest,
+        GenerationResponse, PromptFeedback,
     },
-    generation::{ContentBuilder, GenerateContentRequest, GenerationResponse},
+    health::{self, HealthReport, HealthStatus},
+    json,
+    limits::{ContextGuard, LimitCache, ListModelsResponse, ModelInfo, ModelLimits},
+    metrics::{self, MetricsSink},
+    pricing::{CostEstimate, PriceTable, UsageLedger},
+    retry::{
+        budget::{BudgetUsage, CallContext, RetryBudget},
+        pipeline::{Policy, PolicyError, RateLimit, RateLimiter},
+        policy::parse_retry_after,
+        AttemptsReport, RetryLayer, RetryPolicy,
+    },
+    safety::sanitize::SanitizePolicy,
+    trace_context,
+    transport::{BodyMeter, Http3State, Http3Status, ResponseMeta},
+    HarmProbability, LintWarning, Modality, Part, SafetyFlag, SafetyRating, UserData,
 };
+#[cfg(feature = "streaming")]
 use eventsource_stream::{EventStreamError, Eventsource};
-use futures::{Stream, StreamExt, TryStreamExt};
+use futures::Stream;
+#[cfg(any(feature = "streaming", feature = "files", feature = "caching"))]
+use futures::TryStreamExt;
+#[cfg(feature = "streaming")]
+use futures::{StreamExt, TryStream};
 use mime::Mime;
 use reqwest::{
     header::{HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue},
     Client, ClientBuilder, RequestBuilder, Response,
 };
 use serde::{Deserialize, Serialize};
+#[cfg(any(feature = "batch", feature = "files", feature = "caching"))]
 use serde_json::json;
+use serde_json::Value;
 use snafu::{OptionExt, ResultExt, Snafu};
 use std::{
     fmt::{self, Formatter},
+    net::SocketAddr,
     sync::{Arc, LazyLock},
+    time::Duration,
 };
-use tracing::{instrument, Level, Span};
+use tracing::{instrument, Instrument, Level, Span};
 use url::Url;
 
-use crate::batch::model::*;
-use crate::cache::model::*;
+/// Default limit on the size of a response body, see
+/// [`GeminiBuilder::with_max_response_bytes()`]
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Default limit on the decoded size of a single inline data part of a response, see
+/// [`GeminiBuilder::with_max_inline_data_bytes()`]
+pub const DEFAULT_MAX_INLINE_DATA_BYTES: usize = 32 * 1024 * 1024;
+
+/// Number of characters of an undecodable body kept in [`Error::UnexpectedResponse`]
+pub const UNEXPECTED_BODY_CHARS: usize = 512;
 
 static DEFAULT_BASE_URL: LazyLock<Url> = LazyLock::new(|| {
     Url::parse("https://generativelanguage.googleapis.com/v1beta/")
@@ -89,6 +151,24 @@ pub enum Error {
         source: InvalidHeaderValue,
     },
 
+    #[snafu(display("failed to obtain a bearer token"))]
+    BearerToken {
+        source: TokenError,
+    },
+
+    #[snafu(display(
+        "refusing to send unauthenticated requests to the default endpoint '{base_url}'; set a base URL or call allow_unauthenticated_default_endpoint()"
+    ))]
+    UnauthenticatedDefaultEndpoint {
+        base_url: Url,
+    },
+
+    #[snafu(display("invalid Vertex AI project '{project}' or location '{location}'"))]
+    InvalidVertexProject {
+        project: String,
+        location: String,
+    },
+
     #[snafu(display("failed to construct URL (probably incorrect model name): {suffix}"))]
     ConstructUrl {
         source: url::ParseError,
@@ -99,6 +179,15 @@ pub enum Error {
         source: reqwest::Error,
     },
 
+    #[snafu(display("failed to connect to '{host}' at {}", dns::describe(addresses)))]
+    Connect {
+        source: reqwest::Error,
+        /// The host name of the request
+        host: String,
+        /// The addresses the host was resolved to, empty if the system resolver resolved it
+        addresses: Vec<SocketAddr>,
+    },
+
     #[snafu(display("failed to perform request to '{url}'"))]
     PerformRequest {
         source: reqwest::Error,
@@ -114,12 +203,15 @@ pub enum Error {
         code: u16,
         /// HTTP error description
         description: Option<String>,
+        /// The delay the `Retry-After` header of the response asked for
+        retry_after: Option<Duration>,
     },
 
     MissingResponseHeader {
         header: String,
     },
 
+    #[cfg(feature = "streaming")]
     #[snafu(display("failed to obtain stream SSE part"))]
     BadPart {
         source: EventStreamError<reqwest::Error>,
@@ -130,6 +222,24 @@ pub enum Error {
         source: serde_json::Error,
     },
 
+    /// A successful status with a body that is not what the API sends, e.g. the HTML error
+    /// page of a proxy
+    #[snafu(display(
+        "unexpected response; code {code}; content type {}; {reason}; body: {body:?}",
+        content_type.as_deref().unwrap_or("none")
+    ))]
+    UnexpectedResponse {
+        /// HTTP status code
+        code: u16,
+        /// The `content-type` header, if any
+        content_type: Option<String>,
+        /// Why the body could not be decoded
+        reason: String,
+        /// The first [`UNEXPECTED_BODY_CHARS`] characters of the body, whitespace and control
+        /// characters collapsed to single spaces
+        body: String,
+    },
+
     #[snafu(display("failed to generate content"))]
     DecodeResponse {
         source: reqwest::Error,
@@ -144,6 +254,359 @@ pub enum Error {
     Io {
         source: std::io::Error,
     },
+
+    #[snafu(display(
+        "bad request; code {code}; {}",
+        fields.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    ))]
+    InvalidFields {
+        /// HTTP status code
+        code: u16,
+        /// HTTP error description
+        description: Option<String>,
+        /// Offending fields resolved to the builder calls that produced them
+        fields: Vec<FieldOrigin>,
+    },
+
+    #[snafu(display(
+        "retry budget of {} attempt(s) exhausted; consumed by {usage}",
+        budget.max_attempts
+    ))]
+    RetryBudgetExhausted {
+        /// The exhausted budget
+        budget: RetryBudget,
+        /// What each layer consumed
+        usage: BudgetUsage,
+    },
+
+    #[snafu(display("invalid request policy"))]
+    InvalidPolicy {
+        source: PolicyError,
+    },
+
+    #[cfg(feature = "embeddings")]
+    #[snafu(display("invalid chunking config"))]
+    InvalidChunking {
+        source: ChunkingError,
+    },
+
+    #[snafu(display("the session has no exchange to describe yet"))]
+    EmptyConversation,
+
+    #[snafu(display(
+        "dataset row {row} alone needs ~{estimated_tokens} tokens, more than the budget of \
+         {max_tokens} per request"
+    ))]
+    RowExceedsBudget {
+        /// The index of the row in the dataset
+        row: usize,
+        /// The estimated tokens of the template and the row
+        estimated_tokens: usize,
+        /// The budget of one request
+        max_tokens: usize,
+    },
+
+    #[snafu(display(
+        "structured output could not be parsed at {path}{}",
+        raw.as_ref().map(|raw| format!(" from {raw:?}")).unwrap_or_default()
+    ))]
+    StructuredOutput {
+        source: serde_json::Error,
+        /// The field that failed, as a JSONPath such as `$.events[2].start`
+        path: String,
+        /// The value of the field, if it is a string
+        raw: Option<String>,
+    },
+
+    #[cfg(feature = "toon")]
+    #[snafu(display("value could not be written as TOON"))]
+    ToonInput {
+        source: crate::toon::Error,
+    },
+
+    #[cfg(feature = "toon_wip")]
+    #[snafu(display("TOON output could not be parsed"))]
+    ToonOutput {
+        source: crate::toon::Error,
+        /// The answer text, before fences and prose were stripped
+        raw: String,
+    },
+
+    #[snafu(display("retries exhausted after {attempts}"))]
+    RetriesExhausted {
+        /// The error of the last attempt
+        source: Box<Error>,
+        /// Every attempt of the call
+        attempts: AttemptsReport,
+    },
+
+    #[cfg(feature = "streaming")]
+    #[snafu(display("stream failed after {} chunk(s)", received.chunks))]
+    PartialStream {
+        /// What was received before the failure
+        received: Box<AggregatedPartial>,
+        /// The error that ended the stream
+        source: Box<Error>,
+    },
+
+    #[snafu(display(
+        "request sets {} although cached content '{cached_content}' provides them",
+        fields.join(", ")
+    ))]
+    CachedContentConflict {
+        /// Name of the referenced cached content
+        cached_content: String,
+        /// The request fields that must come from the cached content
+        fields: Vec<&'static str>,
+    },
+
+    #[snafu(display(
+        "none of the {} raced request(s) produced an acceptable response",
+        attempts.len()
+    ))]
+    NoAcceptableResponse {
+        /// How each raced request ended
+        attempts: Vec<RaceAttempt>,
+    },
+
+    #[snafu(display("generationConfig.{field} is {value}, expected {expected}"))]
+    InvalidGenerationConfig {
+        /// The camelCase name of the field
+        field: &'static str,
+        /// The value that was set
+        value: String,
+        /// The values the API accepts
+        expected: &'static str,
+    },
+
+    #[cfg(feature = "tools")]
+    #[snafu(display("tool loop did not finish within {steps} step(s)"))]
+    ToolLoopExhausted {
+        /// The step limit that was reached
+        steps: usize,
+    },
+
+    #[cfg(feature = "tools")]
+    #[snafu(display("model kept making malformed function calls, last: {raw}"))]
+    MalformedFunctionCall {
+        /// The last malformed call, see [`Candidate::malformed_function_call()`](crate::Candidate::malformed_function_call)
+        raw: String,
+    },
+
+    #[snafu(display("failed to decode generated audio"))]
+    DecodeAudio {
+        source: base64::DecodeError,
+    },
+
+    #[snafu(display(
+        "model '{model}' cannot respond with {}; it supports {}",
+        join_modalities(unsupported),
+        join_modalities(supported)
+    ))]
+    UnsupportedModalities {
+        /// The model the request targets
+        model: String,
+        /// The requested response modalities the model does not support
+        unsupported: Vec<Modality>,
+        /// The response modalities the model supports
+        supported: Vec<Modality>,
+    },
+
+    #[snafu(display(
+        "prompt of about {estimated} tokens exceeds the context window of {limit} tokens of {model}"
+    ))]
+    ContextWindowExceeded {
+        /// The counted or estimated number of prompt tokens
+        estimated: u64,
+        /// The input token limit of the model
+        limit: u64,
+        /// The model the request targets
+        model: String,
+    },
+
+    #[snafu(display("response body exceeds the limit of {limit} bytes"))]
+    ResponseTooLarge {
+        /// The limit that was exceeded, see [`GeminiBuilder::with_max_response_bytes()`]
+        limit: usize,
+    },
+
+    #[snafu(display(
+        "inline data part ({mime_type}) of {size} bytes exceeds the limit of {limit} bytes"
+    ))]
+    InlineDataTooLarge {
+        /// MIME type of the part
+        mime_type: String,
+        /// Decoded size of the part
+        size: usize,
+        /// The limit that was exceeded, see [`GeminiBuilder::with_max_inline_data_bytes()`]
+        limit: usize,
+    },
+
+    #[snafu(display(
+        "prompt bundle version {version} is not supported, the newest known version is {supported}"
+    ))]
+    UnsupportedBundleVersion {
+        /// The version of the bundle
+        version: u32,
+        /// The newest version this crate reads
+        supported: u32,
+    },
+
+    #[cfg(feature = "streaming")]
+    #[snafu(display("failed to write streamed text to spill file '{}'", path.display()))]
+    Spill {
+        source: std::io::Error,
+        /// The spill file
+        path: std::path::PathBuf,
+    },
+
+    #[snafu(display("prompt was blocked ({reason}): {}", reason.guidance()))]
+    PromptBlocked {
+        /// Why the prompt was blocked
+        reason: BlockReason,
+        /// The safety ratings of the prompt, naming the category for [`BlockReason::Safety`]
+        safety_ratings: Vec<SafetyRating>,
+    },
+
+    #[cfg(feature = "embeddings")]
+    #[snafu(display("embedding response carried no embedding"))]
+    MissingEmbedding,
+
+    #[cfg(feature = "embeddings")]
+    #[snafu(display("batch embedding response carried {actual} embeddings for {expected} texts"))]
+    EmbeddingCountMismatch {
+        expected: usize,
+        actual: usize,
+    },
+
+    #[snafu(display("{feature} is not supported on {backend}"))]
+    UnsupportedOnBackend {
+        /// The unsupported feature
+        feature: &'static str,
+        /// The backend of the client
+        backend: Backend,
+    },
+
+    #[snafu(display("model output blocked by output screen: {reason}"))]
+    OutputBlocked {
+        /// Why the screen blocked the output
+        reason: String,
+    },
+
+    #[snafu(display(
+        "model output rated at or above {threshold:?} in {} safety rating(s)",
+        flags.len()
+    ))]
+    SafetyThresholdExceeded {
+        /// The threshold set with
+        /// [`reject_safety_above()`](crate::ContentBuilder::reject_safety_above)
+        threshold: HarmProbability,
+        /// The offending ratings
+        flags: Vec<SafetyFlag>,
+    },
+
+    #[snafu(display(
+        "request denied by {} lint warning(s), first {}",
+        warnings.len(),
+        warnings[0]
+    ))]
+    LintDenied {
+        /// The warnings of the kinds denied with
+        /// [`deny_lints()`](crate::ContentBuilder::deny_lints), at least one
+        warnings: Vec<LintWarning>,
+    },
+
+    #[snafu(display("request would exceed the client's rate limit for another {wait:?}"))]
+    RateLimited {
+        /// How long it will take at least until the request may be sent
+        wait: Duration,
+    },
+
+    #[snafu(display("{source}"))]
+    WithUserData {
+        /// The error of the request
+        source: Box<Error>,
+        /// The values attached to the request with
+        /// [`with_user_data()`](crate::ContentBuilder::with_user_data)
+        user_data: UserData,
+    },
+}
+
+impl Error {
+    /// The value of type `T` attached to the failed request, if any, see
+    /// [`user_data`](crate::user_data)
+    pub fn user_data<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        match self {
+            Error::WithUserData { user_data, .. } => user_data.get(),
+            Error::RetriesExhausted { source, .. } => source.user_data(),
+            #[cfg(feature = "streaming")]
+            Error::PartialStream { source, .. } => source.user_data(),
+            _ => None,
+        }
+    }
+
+    /// The error without the user data attached to the failed request
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::WithUserData { source, .. } => source,
+            error => error,
+        }
+    }
+
+    /// Wraps the error with the user data of its request, unless there is none
+    pub(crate) fn with_user_data(self, user_data: &UserData) -> Self {
+        match self {
+            error @ Error::WithUserData { .. } => error,
+            error if user_data.is_empty() => error,
+            error => Error::WithUserData {
+                source: Box::new(error),
+                user_data: user_data.clone(),
+            },
+        }
+    }
+
+    /// Parses the Google RPC status carried in the body of a bad response
+    pub fn api_status(&self) -> Option<Status> {
+        match self {
+            Error::BadResponse {
+                description: Some(body),
+                ..
+            }
+            | Error::InvalidFields {
+                description: Some(body),
+                ..
+            } => Status::from_body(body),
+            Error::RetriesExhausted { source, .. } | Error::WithUserData { source, .. } => {
+                source.api_status()
+            }
+            #[cfg(feature = "streaming")]
+            Error::PartialStream { source, .. } => source.api_status(),
+            _ => None,
+        }
+    }
+
+    /// How long the API asked to wait before sending the request again, from the
+    /// `Retry-After` header or else the `RetryInfo` detail of a bad response
+    pub fn retry_delay(&self) -> Option<Duration> {
+        match self {
+            Error::BadResponse {
+                retry_after: Some(delay),
+                ..
+            } => Some(*delay),
+            Error::RetriesExhausted { source, .. } | Error::WithUserData { source, .. } => {
+                source.retry_delay()
+            }
+            error => error.api_status()?.retry_delay(),
+        }
+    }
+
+    /// Whether the request may succeed if sent again, as judged by [`RetryPolicy`]
+    ///
+    /// Blocked prompts ([`Error::PromptBlocked`]) are never retryable: the same prompt is
+    /// blocked again.
+    pub fn is_retryable(&self) -> bool {
+        RetryPolicy::is_retryable(self)
+    }
 }
 
 /// Internal client for making requests to the Gemini API
@@ -151,23 +614,43 @@ pub struct GeminiClient {
     http_client: Client,
     pub model: Model,
     base_url: Url,
+    retry_policy: Option<RetryPolicy>,
+    auth: Auth,
+    pub(crate) backend: Backend,
+    /// `projects/{project}/locations/{location}` on [`Backend::Vertex`]
+    vertex_parent: Option<String>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) preflight: bool,
+    max_response_bytes: usize,
+    max_inline_data_bytes: usize,
+    resolved: Arc<ResolvedAddrs>,
+    pub(crate) prices: Arc<PriceTable>,
+    /// Usage recorded by the client itself, priced with `prices`
+    pub(crate) ledger: UsageLedger,
+    http3: Arc<Http3State>,
+    metrics: Option<Arc<dyn MetricsSink>>,
+    #[cfg(any(feature = "files", feature = "caching"))]
+    pub(crate) resources: Arc<ResourceRegistry>,
+    pub(crate) context_guard: Option<Arc<ContextGuard>>,
+    pub(crate) model_limits: Arc<LimitCache>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    fallback_models: Arc<[Model]>,
+    /// Budget of calls that do not set their own
+    pub(crate) retry_budget: Option<RetryBudget>,
+    pub(crate) output_sanitizer: Option<SanitizePolicy>,
 }
 
 impl GeminiClient {
     /// Create a new client with custom base URL
-    fn with_base_url<K: AsRef<str>, M: Into<Model>>(
+    fn with_base_url<M: Into<Model>>(
         client_builder: ClientBuilder,
-        api_key: K,
+        auth: Auth,
         model: M,
         base_url: Url,
+        backend: Backend,
     ) -> Result<Self, Error> {
-        let headers = HeaderMap::from_iter([(
-            HeaderName::from_static("x-goog-api-key"),
-            HeaderValue::from_str(api_key.as_ref()).context(InvalidApiKeySnafu)?,
-        )]);
-
         let http_client = client_builder
-            .default_headers(headers)
+            .default_headers(Self::default_headers(&auth, backend)?)
             .build()
             .expect("all parameters must be valid");
 
@@ -175,18 +658,120 @@ impl GeminiClient {
             http_client,
             model: model.into(),
             base_url,
+            retry_policy: None,
+            auth,
+            backend,
+            vertex_parent: None,
+            clock: Arc::new(TokioClock),
+            preflight: false,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            max_inline_data_bytes: DEFAULT_MAX_INLINE_DATA_BYTES,
+            resolved: Arc::default(),
+            prices: Arc::default(),
+            ledger: UsageLedger::default(),
+            http3: Arc::new(Http3State::off()),
+            metrics: None,
+            #[cfg(any(feature = "files", feature = "caching"))]
+            resources: Arc::default(),
+            context_guard: None,
+            model_limits: Arc::default(),
+            rate_limiter: None,
+            fallback_models: Arc::new([]),
+            retry_budget: None,
+            output_sanitizer: None,
+        })
+    }
+
+    /// Headers sent with every request.
+    ///
+    /// API keys never change, so they are sent as a default header, on the Gemini API and on
+    /// Vertex AI in express mode alike.
+    fn default_headers(auth: &Auth, backend: Backend) -> Result<HeaderMap, Error> {
+        Ok(match auth {
+            Auth::ApiKey(api_key)
+                if matches!(backend, Backend::GeminiApi | Backend::VertexExpress) =>
+            {
+                HeaderMap::from_iter([(
+                    HeaderName::from_static("x-goog-api-key"),
+                    HeaderValue::from_str(api_key).context(InvalidApiKeySnafu)?,
+                )])
+            }
+            Auth::ApiKey(_) | Auth::Bearer(_) | Auth::None => HeaderMap::new(),
         })
     }
 
+    /// Adds per-request credentials to a request
+    async fn authorize(&self, request: RequestBuilder) -> Result<RequestBuilder, Error> {
+        match &self.auth {
+            Auth::Bearer(provider) => {
+                let token = provider.token().await.context(BearerTokenSnafu)?;
+                Ok(request.bearer_auth(token))
+            }
+            Auth::ApiKey(_) | Auth::None => Ok(request),
+        }
+    }
+
+    /// Creates a client sharing this client's connection pool and credentials, but targeting
+    /// another model
+    pub(crate) fn for_model(&self, model: impl Into<Model>) -> Self {
+        Self {
+            http_client: self.http_client.clone(),
+            model: model.into(),
+            base_url: self.base_url.clone(),
+            retry_policy: self.retry_policy.clone(),
+            auth: self.auth.clone(),
+            backend: self.backend,
+            vertex_parent: self.vertex_parent.clone(),
+            clock: self.clock.clone(),
+            preflight: self.preflight,
+            max_response_bytes: self.max_response_bytes,
+            max_inline_data_bytes: self.max_inline_data_bytes,
+            resolved: self.resolved.clone(),
+            prices: self.prices.clone(),
+            ledger: self.ledger.clone(),
+            http3: self.http3.clone(),
+            metrics: self.metrics.clone(),
+            #[cfg(any(feature = "files", feature = "caching"))]
+            resources: self.resources.clone(),
+            context_guard: self.context_guard.clone(),
+            model_limits: self.model_limits.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            fallback_models: self.fallback_models.clone(),
+            retry_budget: self.retry_budget,
+            output_sanitizer: self.output_sanitizer,
+        }
+    }
+
+    /// A client of the same model that does not fall back to other models
+    pub(crate) fn without_fallbacks(mut self) -> Self {
+        self.fallback_models = Arc::new([]);
+        self
+    }
+
+    /// The limit on the size of response bodies
+    #[cfg(feature = "files")]
+    pub(crate) fn max_response_bytes(&self) -> usize {
+        self.max_response_bytes
+    }
+
     /// Check the response status code and return an error if it is not successful
     #[tracing::instrument(skip_all, err)]
-    async fn check_response(response: Response) -> Result<Response, Error> {
+    async fn check_response(&self, response: Response) -> Result<Response, Error> {
         let status = response.status();
         if !status.is_success() {
-            let description = response.text().await.ok();
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| parse_retry_after(value, self.clock.now_utc()));
+            let description = read_body(response, self.max_response_bytes)
+                .await
+                .ok()
+                .map(|body| String::from_utf8_lossy(&body).into_owned());
             BadResponseSnafu {
                 code: status.as_u16(),
                 description,
+                retry_after,
             }
             .fail()
         } else {
@@ -230,7 +815,7 @@ impl GeminiClient {
     /// # let request = Value::Null;
     ///
     /// // POST request with JSON payload
-    /// let _response = client
+    /// let _response: Value = client
     ///     .perform_request(
     ///         |c| c.post(url.clone()).json(&request),
     ///         async |r| r.json().await.context(DecodeResponseSnafu),
@@ -238,7 +823,7 @@ impl GeminiClient {
     ///     .await?;
     ///
     /// // GET request with JSON response
-    /// let _response = client
+    /// let _response: Value = client
     ///     .perform_request(
     ///         |c| c.get(url.clone()),
     ///         async |r| r.json().await.context(DecodeResponseSnafu),
@@ -282,13 +867,218 @@ impl GeminiClient {
         builder: B,
         deserializer: D,
     ) -> Result<T, Error> {
-        let request = builder(&self.http_client);
+        self.perform_request_in(&CallContext::default(), builder, deserializer)
+            .await
+    }
+
+    /// Performs an HTTP request within a call context.
+    ///
+    /// Transient failures are retried according to the client's [`RetryPolicy`]. Every
+    /// attempt and every backoff delay is charged to the retry budget of the context, if any.
+    /// Every attempt then waits for the client's rate limit, if any.
+    /// The HTTP version of the response is recorded in the context and the `http.version`
+    /// field of the span.
+    #[tracing::instrument(skip_all, fields(http.version))]
+    pub(crate) async fn perform_request_in<
+        B: FnOnce(&Client) -> RequestBuilder,
+        D: AsyncFn(Response) -> Result<T, Error>,
+        T,
+    >(
+        &self,
+        ctx: &CallContext,
+        builder: B,
+        deserializer: D,
+    ) -> Result<T, Error> {
+        let request = trace_context::inject(builder(&self.http_client), ctx.traceparent());
+        let mut request = Some(request);
         tracing::debug!("request built successfully");
-        let response = request.send().await.context(PerformRequestNewSnafu)?;
-        tracing::debug!("response received successfully");
-        let response = Self::check_response(response).await?;
+        let mut attempt = 0;
+        let mut backoff = Duration::ZERO;
+        loop {
+            let current = request
+                .take()
+                .expect("a request is available for every attempt");
+            // Keep a copy for a retry; requests with streamed bodies cannot be replayed
+            request = current.try_clone();
+            let layer = if attempt == 0 {
+                ctx.layer()
+            } else {
+                RetryLayer::Http
+            };
+            ctx.charge_attempt(layer)?;
+            if let Some(limiter) = &self.rate_limiter {
+                match ctx.waits_for_rate_limit() {
+                    true => limiter.acquire(self.clock.as_ref()).await,
+                    false => limiter
+                        .try_acquire(self.clock.as_ref())
+                        .map_err(|wait| Error::RateLimited { wait })?,
+                }
+            }
+
+            let started = self.clock.now();
+            let span = tracing::debug_span!("retry_attempt", attempt = attempt + 1, ?layer);
+            let result = self
+                .send_once(current, &deserializer)
+                .instrument(span)
+                .await;
+            let duration = self.clock.now().saturating_duration_since(started);
+            let error = match result {
+                Ok((response, meta, body)) => {
+                    ctx.record_attempt(layer, None, backoff, duration);
+                    if let Some(version) = meta.http_version {
+                        Span::current().record("http.version", tracing::field::debug(version));
+                    }
+                    ctx.record_response(meta, body);
+                    return Ok(response);
+                }
+                Err(error) => error,
+            };
+            ctx.record_attempt(layer, Some(error.to_string()), backoff, duration);
+            let retryable = RetryPolicy::is_retryable(&error);
+            let retry = match &self.retry_policy {
+                Some(policy)
+                    if retryable
+                        && ctx.retries()
+                        && request.is_some()
+                        && attempt + 1 < policy.max_attempts =>
+                {
+                    Some(policy.delay(attempt, &error))
+                }
+                _ => None,
+            };
+            // `Some(None)`: the API asks to wait longer than the policy allows
+            let Some(Some(delay)) = retry else {
+                if (attempt > 0 && retryable) || retry.is_some() {
+                    let attempts = ctx.attempts();
+                    tracing::debug!(%attempts, "giving up retrying request");
+                    return Err(Error::RetriesExhausted {
+                        source: Box::new(error),
+                        attempts,
+                    });
+                }
+                return Err(error);
+            };
+            ctx.charge_delay(RetryLayer::Http, delay)?;
+            tracing::debug!(
+                attempt,
+                ?delay,
+                requested = error.retry_delay().is_some(),
+                error = %error,
+                "retrying request"
+            );
+            self.clock.sleep(delay).await;
+            backoff = delay;
+            attempt += 1;
+        }
+    }
+
+    /// Sends a request once, checks its status and deserializes the response.
+    ///
+    /// Also returns the meter of the response body, which keeps counting while a streamed
+    /// body is read.
+    async fn send_once<D: AsyncFn(Response) -> Result<T, Error>, T>(
+        &self,
+        request: RequestBuilder,
+        deserializer: &D,
+    ) -> Result<(T, ResponseMeta, BodyMeter), Error> {
+        let request = self
+            .authorize(request)
+            .await?
+            .build()
+            .map_err(|source| self.request_error(source))?;
+        let request_bytes = request
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .map(|body| body.len() as u64);
+        if let (Some(metrics), Some(bytes)) = (&self.metrics, request_bytes) {
+            metrics.record_histogram(metrics::REQUEST_BODY_BYTES, bytes as f64);
+        }
+        let (mut response, mut meta) = self
+            .http3
+            .execute(&self.http_client, request)
+            .await
+            .map_err(|source| self.request_error(source))?;
+        tracing::debug!(http.version = ?meta.http_version, "response received successfully");
+        let body = BodyMeter::new(self.metrics.clone());
+        response.extensions_mut().insert(body.clone());
+        let response = self.check_response(response).await?;
         tracing::debug!("response ok");
-        deserializer(response).await
+        let value = deserializer(response).await?;
+        meta.request_bytes = request_bytes;
+        meta.response_bytes = body.bytes();
+        Ok((value, meta, body))
+    }
+
+    /// Get the metadata of `model`
+    pub(crate) async fn get_model(&self, model: &Model) -> Result<ModelInfo, Error> {
+        let url = self.build_url_with_suffix(&self.backend.model_resource(model))?;
+        self.get_json(url).await
+    }
+
+    /// List the models available to the client's credentials
+    #[instrument(skip_all, fields(
+        page.size = page_size,
+        page.token.present = page_token.is_some(),
+    ))]
+    pub(crate) async fn list_models(
+        &self,
+        page_size: Option<u32>,
+        page_token: Option<String>,
+    ) -> Result<ListModelsResponse, Error> {
+        let mut url = self.build_url_with_suffix("models")?;
+
+        if let Some(size) = page_size {
+            url.query_pairs_mut()
+                .append_pair("pageSize", &size.to_string());
+        }
+        if let Some(token) = page_token {
+            url.query_pairs_mut().append_pair("pageToken", &token);
+        }
+
+        self.get_json(url).await
+    }
+
+    /// Fetches the token limits of the client's model, replacing the cached ones
+    pub(crate) async fn refresh_model_limits(&self) -> Result<Option<ModelLimits>, Error> {
+        let limits = self.get_model(&self.model).await?.limits();
+        match limits {
+            Some(limits) => self
+                .model_limits
+                .insert(&self.model, limits, self.clock.now()),
+            None => self.model_limits.remove(&self.model),
+        }
+        Ok(limits)
+    }
+
+    /// Fetches the metadata of the model once, without retrying, within `timeout`
+    ///
+    /// The body of a successful response is not read.
+    pub(crate) async fn check_model(&self, timeout: Duration) -> Result<(), Error> {
+        let url = self.build_url_with_suffix(&self.backend.model_resource(&self.model))?;
+        let request = self.http_client.get(url).timeout(timeout);
+        self.send_once(request, &async |_| Ok(())).await?;
+        Ok(())
+    }
+
+    /// Wraps a failure to send a request, naming the addresses tried for connection failures
+    fn request_error(&self, source: reqwest::Error) -> Error {
+        let target = source
+            .url()
+            .filter(|_| source.is_connect())
+            .and_then(|url| Some((url.host_str()?.to_string(), url.port_or_known_default()?)));
+        let Some((host, port)) = target else {
+            return Error::PerformRequestNew { source };
+        };
+        // As when connecting, a port of 0 stands for the port of the URL
+        let mut addresses = self.resolved.get(&host);
+        for addr in addresses.iter_mut().filter(|addr| addr.port() == 0) {
+            addr.set_port(port);
+        }
+        Error::Connect {
+            source,
+            host,
+            addresses,
+        }
     }
 
     /// Perform a GET request and deserialize the JSON response.
@@ -296,11 +1086,9 @@ impl GeminiClient {
     /// This is a convenience wrapper around [`perform_request`](Self::perform_request).
     #[tracing::instrument(skip(self), fields(request.type = "get", request.url = %url))]
     async fn get_json<T: serde::de::DeserializeOwned>(&self, url: Url) -> Result<T, Error> {
-        self.perform_request(
-            |c| c.get(url),
-            async |r| r.json().await.context(DecodeResponseSnafu),
-        )
-        .await
+        let limit = self.max_response_bytes;
+        self.perform_request(|c| c.get(url), move |r| read_json(r, limit))
+            .await
     }
 
     /// Perform a POST request with JSON body and deserialize the JSON response.
@@ -312,37 +1100,119 @@ impl GeminiClient {
         url: Url,
         body: &Req,
     ) -> Result<Res, Error> {
-        self.perform_request(
+        self.post_json_in(&CallContext::default(), url, body).await
+    }
+
+    /// Perform a POST request with JSON body within a call context.
+    async fn post_json_in<Req: serde::Serialize, Res: serde::de::DeserializeOwned>(
+        &self,
+        ctx: &CallContext,
+        url: Url,
+        body: &Req,
+    ) -> Result<Res, Error> {
+        let limit = self.max_response_bytes;
+        self.perform_request_in(
+            ctx,
             |c| c.post(url).json(body),
-            async |r| r.json().await.context(DecodeResponseSnafu),
+            move |r| read_json(r, limit),
         )
         .await
     }
 
-    /// Generate content
-    #[instrument(skip_all, fields(
-        model,
-        messages.parts.count = request.contents.len(),
-        tools.present = request.tools.is_some(),
-        system.instruction.present = request.system_instruction.is_some(),
-        cached.content.present = request.cached_content.is_some(),
-        usage.prompt_tokens,
-        usage.candidates_tokens,
-        usage.thoughts_tokens,
-        usage.cached_content_tokens,
-        usage.total_tokens,
-    ), ret(level = Level::TRACE), err)]
+    /// Generate content, falling back to the fallback models of the client's [`Policy`]
     pub(crate) async fn generate_content_raw(
         &self,
+        ctx: &CallContext,
         request: GenerateContentRequest,
     ) -> Result<GenerationResponse, Error> {
-        let url = self.build_url("generateContent")?;
-        let response: GenerationResponse = self.post_json(url, &request).await?;
+        let mut result = self.generate_content_once(ctx, request.clone()).await;
+        for client in self.fallbacks() {
+            if !Self::falls_back(&result, &client.model) {
+                break;
+            }
+            let ctx = ctx.with_layer(RetryLayer::Fallback);
+            result = client.generate_content_once(&ctx, request.clone()).await;
+        }
+        result
+    }
 
-        // Record usage metadata
-        if let Some(usage) = &response.usage_metadata {
-            #[rustfmt::skip]
-            Span::current()
+    /// Generate content with streaming, falling back to the fallback models of the client's
+    /// [`Policy`] until a stream starts
+    #[cfg(feature = "streaming")]
+    pub(crate) async fn generate_content_stream(
+        &self,
+        ctx: &CallContext,
+        request: GenerateContentRequest,
+    ) -> Result<impl TryStreamExt<Ok = GenerationResponse, Error = Error> + Send + use<>, Error>
+    {
+        let mut result = self
+            .generate_content_stream_once(ctx, request.clone())
+            .await;
+        for client in self.fallbacks() {
+            if !Self::falls_back(&result, &client.model) {
+                break;
+            }
+            let ctx = ctx.with_layer(RetryLayer::Fallback);
+            result = client
+                .generate_content_stream_once(&ctx, request.clone())
+                .await;
+        }
+        result
+    }
+
+    /// Clients of the fallback models of the client's [`Policy`], in the order they are tried
+    fn fallbacks(&self) -> impl Iterator<Item = GeminiClient> + '_ {
+        self.fallback_models
+            .iter()
+            .filter(|model| **model != self.model)
+            .map(|model| self.for_model(model.clone()))
+    }
+
+    /// Whether to send the request to the fallback model `to` after `result`: only transient
+    /// failures fall back, once the retry policy gave up on them
+    fn falls_back<T>(result: &Result<T, Error>, to: &Model) -> bool {
+        let Err(error) = result else {
+            return false;
+        };
+        let transient = matches!(error, Error::RetriesExhausted { .. }) || error.is_retryable();
+        if transient {
+            tracing::warn!(to = %to, error = %error, "falling back to another model");
+        }
+        transient
+    }
+
+    /// Generate content with one model
+    #[instrument(skip_all, fields(
+        model,
+        messages.parts.count = request.contents.len(),
+        tools.present = request.tools.is_some(),
+        system.instruction.present = request.system_instruction.is_some(),
+        cached.content.present = request.cached_content.is_some(),
+        usage.prompt_tokens,
+        usage.candidates_tokens,
+        usage.thoughts_tokens,
+        usage.cached_content_tokens,
+        usage.total_tokens,
+        gemini.response_id,
+    ), ret(level = Level::TRACE), err)]
+    async fn generate_content_once(
+        &self,
+        ctx: &CallContext,
+        request: GenerateContentRequest,
+    ) -> Result<GenerationResponse, Error> {
+        let url = self.build_url("generateContent")?;
+        let response: GenerationResponse = self.post_json_in(ctx, url, &request).await?;
+        check_prompt_feedback(&response)?;
+        check_inline_data(&response, self.max_inline_data_bytes)?;
+        if let Some(response_id) = &response.response_id {
+            Span::current().record("gemini.response_id", response_id.as_str());
+        }
+
+        // Record usage metadata
+        if let Some(usage) = &response.usage_metadata {
+            self.record_tokens(usage.total_token_count);
+            #[rustfmt::skip]
+            Span::current()
                 .record("usage.prompt_tokens", usage.prompt_token_count)
                 .record("usage.candidates_tokens", usage.candidates_token_count)
                 .record("usage.thoughts_tokens", usage.thoughts_token_count)
@@ -355,7 +1225,17 @@ impl GeminiClient {
         Ok(response)
     }
 
-    /// Generate content with streaming
+    /// Charges the tokens a response used to the client's rate limit, if any
+    fn record_tokens(&self, total_tokens: Option<i32>) {
+        if let (Some(limiter), Some(Ok(tokens))) =
+            (&self.rate_limiter, total_tokens.map(u32::try_from))
+        {
+            limiter.record_tokens(tokens, self.clock.as_ref());
+        }
+    }
+
+    /// Generate content with streaming with one model
+    #[cfg(feature = "streaming")]
     #[instrument(skip_all, fields(
         model,
         messages.parts.count = request.contents.len(),
@@ -363,31 +1243,132 @@ impl GeminiClient {
         system.instruction.present = request.system_instruction.is_some(),
         cached.content.present = request.cached_content.is_some(),
     ), err)]
-    pub(crate) async fn generate_content_stream(
+    async fn generate_content_stream_once(
         &self,
+        ctx: &CallContext,
         request: GenerateContentRequest,
     ) -> Result<impl TryStreamExt<Ok = GenerationResponse, Error = Error> + Send + use<>, Error>
     {
+        let limit = self.max_inline_data_bytes;
+        let stream = self.stream_json(ctx, &request).await?;
+        let meta = ctx.response_meta();
+        let body = ctx.body_meter();
+        let limiter = self.rate_limiter.clone();
+        let clock = self.clock.clone();
+        // Chunks report the usage of the stream so far
+        let mut recorded = 0;
+        Ok(stream.and_then(move |mut chunk: GenerationResponse| {
+            chunk.meta = meta.clone();
+            chunk.meta.response_bytes = body.bytes();
+            let total = chunk
+                .usage_metadata
+                .as_ref()
+                .and_then(|usage| usage.total_token_count)
+                .unwrap_or(0);
+            if let (Some(limiter), Ok(used)) = (&limiter, u32::try_from(total - recorded)) {
+                limiter.record_tokens(used, clock.as_ref());
+                recorded = total;
+            }
+            let checked = check_prompt_feedback(&chunk)
+                .and_then(|()| check_inline_data(&chunk, limit))
+                .map(|()| chunk);
+            async move { checked }
+        }))
+    }
+
+    /// Sends a `streamGenerateContent` request and deserializes every server-sent event
+    #[cfg(feature = "streaming")]
+    async fn stream_json<Req: serde::Serialize, Res: serde::de::DeserializeOwned>(
+        &self,
+        ctx: &CallContext,
+        body: &Req,
+    ) -> Result<impl TryStreamExt<Ok = Res, Error = Error> + Send + use<Req, Res>, Error> {
         let mut url = self.build_url("streamGenerateContent")?;
         url.query_pairs_mut().append_pair("alt", "sse");
 
+        let limit = self.max_response_bytes;
         let stream = self
-            .perform_request(
-                |c| c.post(url).json(&request),
-                async |r| Ok(r.bytes_stream()),
+            .perform_request_in(
+                ctx,
+                |c| c.post(url).json(body),
+                move |r| event_stream_body(r, limit),
             )
             .await?;
 
         Ok(stream
             .eventsource()
-            .map(|event| event.context(BadPartSnafu))
-            .map_ok(|event| {
-                serde_json::from_str::<GenerationResponse>(&event.data).context(DeserializeSnafu)
+            .map(|event| match event {
+                Ok(event) => Ok(event),
+                Err(EventStreamError::Transport(error)) => Err(error),
+                Err(EventStreamError::Utf8(error)) => Err(Error::BadPart {
+                    source: EventStreamError::Utf8(error),
+                }),
+                Err(EventStreamError::Parser(error)) => Err(Error::BadPart {
+                    source: EventStreamError::Parser(error),
+                }),
             })
+            .map_ok(|event| decode_event::<Res>(&event.data))
             .map(|r| r.flatten()))
     }
 
+    /// Sends an unvalidated `generateContent` request body
+    #[instrument(skip_all, fields(model = %self.model), err)]
+    pub(crate) async fn generate_content_value(
+        &self,
+        ctx: &CallContext,
+        body: &Value,
+    ) -> Result<Value, Error> {
+        let url = self.build_url("generateContent")?;
+        self.post_json_in(ctx, url, body).await
+    }
+
+    /// Count the tokens of a generate content request
+    #[instrument(skip_all, fields(model = %self.model), err)]
+    pub(crate) async fn count_tokens(
+        &self,
+        request: &GenerateContentRequest,
+    ) -> Result<CountTokensResponse, Error> {
+        /// `countTokens` takes the model inside the request
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct CountTokensRequest<'a> {
+            generate_content_request: ModelRequest<'a>,
+        }
+        #[derive(Serialize)]
+        struct ModelRequest<'a> {
+            model: &'a Model,
+            #[serde(flatten)]
+            request: &'a GenerateContentRequest,
+        }
+
+        let url = self.build_url("countTokens")?;
+        if self.backend.is_vertex() {
+            return self
+                .post_json(url, &VertexCountTokensRequest::from(request))
+                .await;
+        }
+        let body = CountTokensRequest {
+            generate_content_request: ModelRequest {
+                model: &self.model,
+                request,
+            },
+        };
+        self.post_json(url, &body).await
+    }
+
+    /// Sends an unvalidated `streamGenerateContent` request body
+    #[cfg(feature = "streaming")]
+    #[instrument(skip_all, fields(model = %self.model), err)]
+    pub(crate) async fn generate_content_value_stream(
+        &self,
+        ctx: &CallContext,
+        body: &Value,
+    ) -> Result<impl TryStreamExt<Ok = Value, Error = Error> + Send + use<>, Error> {
+        self.stream_json(ctx, body).await
+    }
+
     /// Embed content
+    #[cfg(feature = "embeddings")]
     #[instrument(skip_all, fields(
         model,
         task.type = request.task_type.as_ref().map(|t| format!("{:?}", t)),
@@ -398,21 +1379,38 @@ impl GeminiClient {
         &self,
         request: EmbedContentRequest,
     ) -> Result<ContentEmbeddingResponse, Error> {
+        if self.backend.is_vertex() {
+            let url = self.build_url("predict")?;
+            let response: PredictResponse = self
+                .post_json(url, &PredictRequest::embeddings([&request]))
+                .await?;
+            return response.into_single().context(MissingEmbeddingSnafu);
+        }
         let url = self.build_url("embedContent")?;
         self.post_json(url, &request).await
     }
 
     /// Batch Embed content
+    #[cfg(feature = "embeddings")]
     #[instrument(skip_all, fields(batch.size = request.requests.len()))]
     pub(crate) async fn embed_content_batch(
         &self,
         request: BatchEmbedContentsRequest,
     ) -> Result<BatchContentEmbeddingResponse, Error> {
+        if self.backend.is_vertex() {
+            let url = self.build_url("predict")?;
+            let response: PredictResponse = self
+                .post_json(url, &PredictRequest::embeddings(&request.requests))
+                .await?;
+            return Ok(response.into());
+        }
         let url = self.build_url("batchEmbedContents")?;
         self.post_json(url, &request).await
     }
 
-    /// Batch generate content (synchronous API that returns results immediately)
+    /// Batch generate content (synchronous API that returns results immediately), retrying
+    /// only if `retry` is set, see [`CallContext::non_idempotent()`]
+    #[cfg(feature = "batch")]
     #[instrument(skip_all, fields(
         batch.display_name = request.batch.display_name,
         batch.size = request.batch.input_config.batch_size(),
@@ -420,12 +1418,15 @@ impl GeminiClient {
     pub(crate) async fn batch_generate_content(
         &self,
         request: BatchGenerateContentRequest,
+        retry: bool,
     ) -> Result<BatchGenerateContentResponse, Error> {
         let url = self.build_url("batchGenerateContent")?;
-        self.post_json(url, &request).await
+        let ctx = CallContext::non_idempotent(retry);
+        self.post_json_in(&ctx, url, &request).await
     }
 
     /// Get a batch operation
+    #[cfg(feature = "batch")]
     #[instrument(skip_all, fields(
         operation.name = name,
     ))]
@@ -438,6 +1439,7 @@ impl GeminiClient {
     }
 
     /// List batch operations
+    #[cfg(feature = "batch")]
     #[instrument(skip_all, fields(
         page.size = page_size,
         page.token.present = page_token.is_some(),
@@ -461,6 +1463,7 @@ impl GeminiClient {
     }
 
     /// List files
+    #[cfg(feature = "files")]
     #[instrument(skip_all, fields(
         page.size = page_size,
         page.token.present = page_token.is_some(),
@@ -484,16 +1487,22 @@ impl GeminiClient {
     }
 
     /// Cancel a batch operation
+    #[cfg(feature = "batch")]
     #[instrument(skip_all, fields(
         operation.name = name,
     ))]
     pub(crate) async fn cancel_batch_operation(&self, name: &str) -> Result<(), Error> {
         let url = self.build_batch_url(name, Some("cancel"))?;
-        self.perform_request(|c| c.post(url).json(&json!({})), async |_r| Ok(()))
-            .await
+        self.perform_request_in(
+            &CallContext::non_idempotent(false),
+            |c| c.post(url).json(&json!({})),
+            async |_r| Ok(()),
+        )
+        .await
     }
 
     /// Delete a batch operation
+    #[cfg(feature = "batch")]
     #[instrument(skip_all, fields(
         operation.name = name,
     ))]
@@ -503,11 +1512,13 @@ impl GeminiClient {
             .await
     }
 
+    #[cfg(feature = "files")]
     async fn create_upload(
         &self,
-        bytes: usize,
+        bytes: u64,
         display_name: Option<String>,
         mime_type: Mime,
+        retry: bool,
     ) -> Result<Url, Error> {
         let url = self
             .base_url
@@ -516,7 +1527,8 @@ impl GeminiClient {
                 suffix: "/upload/v1beta/files".to_string(),
             })?;
 
-        self.perform_request(
+        self.perform_request_in(
+            &CallContext::non_idempotent(retry),
             |c| {
                 c.post(url)
                     .header("X-Goog-Upload-Protocol", "resumable")
@@ -538,6 +1550,7 @@ impl GeminiClient {
                             .map_err(|_| Error::BadResponse {
                                 code: 500,
                                 description: Some("Missing upload URL in response".to_string()),
+                                retry_after: None,
                             })
                     })
                     .and_then(|url| Url::parse(&url).context(UrlParseSnafu))
@@ -547,37 +1560,47 @@ impl GeminiClient {
     }
 
     /// Upload a file using the resumable upload protocol.
+    ///
+    /// Starting the upload session is retried only if `retry` is set, see
+    /// [`CallContext::non_idempotent()`].
+    #[cfg(feature = "files")]
     #[instrument(skip_all, fields(
-        file.size = file_bytes.len(),
+        file.size = size,
         mime.type = mime_type.to_string(),
         file.display_name = display_name.as_deref(),
     ))]
     pub(crate) async fn upload_file(
         &self,
         display_name: Option<String>,
-        file_bytes: Vec<u8>,
+        body: reqwest::Body,
+        size: u64,
         mime_type: Mime,
+        retry: bool,
     ) -> Result<File, Error> {
         // Step 1: Create resumable upload session
         let upload_url = self
-            .create_upload(file_bytes.len(), display_name, mime_type)
+            .create_upload(size, display_name, mime_type, retry)
             .await?;
 
-        // Step 2: Upload file content
-        let upload_response = self
+        // Step 2: Upload file content; the body is streamed, so its length is set here
+        let request = self
             .http_client
             .post(upload_url.clone())
             .header("X-Goog-Upload-Command", "upload, finalize")
             .header("X-Goog-Upload-Offset", "0")
-            .body(file_bytes)
-            .send()
-            .await
-            .map_err(|e| Error::PerformRequest {
-                source: e,
-                url: upload_url,
-            })?;
-
-        let final_response = Self::check_response(upload_response).await?;
+            .header(reqwest::header::CONTENT_LENGTH, size)
+            .body(body);
+        let upload_response =
+            self.authorize(request)
+                .await?
+                .send()
+                .await
+                .map_err(|e| Error::PerformRequest {
+                    source: e,
+                    url: upload_url,
+                })?;
+
+        let final_response = self.check_response(upload_response).await?;
 
         #[derive(serde::Deserialize)]
         struct UploadResponse {
@@ -585,11 +1608,12 @@ impl GeminiClient {
         }
 
         let upload_response: UploadResponse =
-            final_response.json().await.context(DecodeResponseSnafu)?;
+            read_json(final_response, self.max_response_bytes).await?;
         Ok(upload_response.file)
     }
 
     /// Get a file resource
+    #[cfg(feature = "files")]
     #[instrument(skip_all, fields(
         file.name = name,
     ))]
@@ -599,6 +1623,7 @@ impl GeminiClient {
     }
 
     /// Delete a file resource
+    #[cfg(feature = "files")]
     #[instrument(skip_all, fields(
         file.name = name,
     ))]
@@ -609,10 +1634,11 @@ impl GeminiClient {
     }
 
     /// Download a file resource
+    #[cfg(feature = "files")]
     #[instrument(skip_all, fields(
         file.name = name,
     ))]
-    pub(crate) async fn download_file(&self, name: &str) -> Result<Vec<u8>, Error> {
+    pub(crate) async fn download_file(&self, name: &str, limit: usize) -> Result<Vec<u8>, Error> {
         let mut url = self
             .base_url
             .join(&format!("/download/v1beta/{name}:download"))
@@ -621,34 +1647,32 @@ impl GeminiClient {
             })?;
         url.query_pairs_mut().append_pair("alt", "media");
 
-        self.perform_request(
-            |c| c.get(url),
-            async |r| {
-                r.bytes()
-                    .await
-                    .context(DecodeResponseSnafu)
-                    .map(|bytes| bytes.to_vec())
-            },
-        )
-        .await
+        self.perform_request(|c| c.get(url), move |r| read_body(r, limit))
+            .await
     }
 
-    /// Create cached content
+    /// Create cached content, retrying only if `retry` is set, see
+    /// [`CallContext::non_idempotent()`]
+    #[cfg(feature = "caching")]
     pub(crate) async fn create_cached_content(
         &self,
         cached_content: CreateCachedContentRequest,
+        retry: bool,
     ) -> Result<CachedContent, Error> {
         let url = self.build_cache_url(None)?;
-        self.post_json(url, &cached_content).await
+        let ctx = CallContext::non_idempotent(retry);
+        self.post_json_in(&ctx, url, &cached_content).await
     }
 
     /// Get cached content
+    #[cfg(feature = "caching")]
     pub(crate) async fn get_cached_content(&self, name: &str) -> Result<CachedContent, Error> {
         let url = self.build_cache_url(Some(name))?;
         self.get_json(url).await
     }
 
     /// Update cached content (typically to update TTL)
+    #[cfg(feature = "caching")]
     pub(crate) async fn update_cached_content(
         &self,
         name: &str,
@@ -664,14 +1688,16 @@ impl GeminiClient {
             }
         };
 
+        let limit = self.max_response_bytes;
         self.perform_request(
             |c| c.patch(url.clone()).json(&update_payload),
-            async |r| r.json().await.context(DecodeResponseSnafu),
+            move |r| read_json(r, limit),
         )
         .await
     }
 
     /// Delete cached content
+    #[cfg(feature = "caching")]
     pub(crate) async fn delete_cached_content(&self, name: &str) -> Result<(), Error> {
         let url = self.build_cache_url(Some(name))?;
         self.perform_request(|c| c.delete(url.clone()), async |_r| Ok(()))
@@ -679,6 +1705,7 @@ impl GeminiClient {
     }
 
     /// List cached contents
+    #[cfg(feature = "caching")]
     pub(crate) async fn list_cached_contents(
         &self,
         page_size: Option<i32>,
@@ -708,11 +1735,18 @@ impl GeminiClient {
     /// Build a URL for the API
     #[tracing::instrument(skip(self), ret(level = Level::DEBUG))]
     fn build_url(&self, endpoint: &str) -> Result<Url, Error> {
-        let suffix = format!("{}:{endpoint}", self.model);
+        let resource = self.backend.model_resource(&self.model);
+        let suffix = match &self.vertex_parent {
+            Some(parent) if resource.starts_with("publishers/") => {
+                format!("{parent}/{resource}:{endpoint}")
+            }
+            _ => format!("{resource}:{endpoint}"),
+        };
         self.build_url_with_suffix(&suffix)
     }
 
     /// Build a URL for a batch operation
+    #[cfg(feature = "batch")]
     fn build_batch_url(&self, name: &str, action: Option<&str>) -> Result<Url, Error> {
         let suffix = action
             .map(|a| format!("{name}:{a}"))
@@ -721,6 +1755,7 @@ impl GeminiClient {
     }
 
     /// Build a URL for file operations
+    #[cfg(feature = "files")]
     fn build_files_url(&self, name: Option<&str>) -> Result<Url, Error> {
         let suffix = name
             .map(|n| format!("files/{}", n.strip_prefix("files/").unwrap_or(n)))
@@ -729,6 +1764,7 @@ impl GeminiClient {
     }
 
     /// Build a URL for cache operations
+    #[cfg(feature = "caching")]
     fn build_cache_url(&self, name: Option<&str>) -> Result<Url, Error> {
         let suffix = name
             .map(|n| {
@@ -777,23 +1813,118 @@ impl GeminiClient {
 /// # }
 /// ```
 pub struct GeminiBuilder {
-    key: String,
+    auth: Auth,
     model: Model,
     client_builder: ClientBuilder,
     base_url: Url,
+    backend: Backend,
+    vertex_project: Option<(String, String)>,
+    retry_policy: Option<RetryPolicy>,
+    clock: Arc<dyn Clock>,
+    preflight: bool,
+    max_response_bytes: usize,
+    max_inline_data_bytes: usize,
+    allow_unauthenticated_default_endpoint: bool,
+    resolve_overrides: Vec<(String, Vec<SocketAddr>)>,
+    #[cfg(feature = "custom-dns")]
+    dns_resolver: Option<Arc<dyn dns::Resolve>>,
+    prices: PriceTable,
+    prefer_http3: bool,
+    metrics: Option<Arc<dyn MetricsSink>>,
+    context_guard: Option<ContextGuard>,
+    policy: Option<Policy>,
+    rate_limit: Option<RateLimit>,
+    output_sanitizer: Option<SanitizePolicy>,
 }
 
 impl GeminiBuilder {
     /// Creates a new `GeminiBuilder` with the given API key.
     pub fn new<K: Into<String>>(key: K) -> Self {
+        Self::with_auth_mode(Auth::ApiKey(key.into()))
+    }
+
+    /// Creates a new `GeminiBuilder` targeting Vertex AI in express mode with the given API
+    /// key, see [`backend`](crate::backend).
+    ///
+    /// A base URL set with [`with_base_url()`](Self::with_base_url) still replaces
+    /// [`VERTEX_EXPRESS_BASE_URL`], e.g. for a regional endpoint.
+    pub fn vertex_express<K: Into<String>>(key: K) -> Self {
+        let mut builder = Self::new(key);
+        builder.base_url = VERTEX_EXPRESS_BASE_URL.clone();
+        builder.backend = Backend::VertexExpress;
+        builder
+    }
+
+    /// Creates a new `GeminiBuilder` targeting the Vertex AI models of `project_id` in
+    /// `location`, e.g. `us-central1` or `global`, see [`backend`](crate::backend).
+    ///
+    /// Requests carry an OAuth access token from `token_provider` in the `Authorization`
+    /// header. A base URL set with [`with_base_url()`](Self::with_base_url) replaces the
+    /// regional endpoint, e.g. with a proxy, and is followed by the project path. Building
+    /// fails if the project or location is not a valid resource ID.
+    pub fn vertex(
+        project_id: impl Into<String>,
+        location: impl Into<String>,
+        token_provider: impl TokenProvider + 'static,
+    ) -> Self {
+        let location = location.into();
+        let mut builder = Self::with_auth_mode(Auth::bearer(token_provider));
+        // An invalid location fails the build
+        builder.base_url =
+            vertex_base_url(&location).unwrap_or_else(|_| VERTEX_EXPRESS_BASE_URL.clone());
+        builder.backend = Backend::Vertex;
+        builder.vertex_project = Some((project_id.into(), location));
+        builder
+    }
+
+    fn with_auth_mode(auth: Auth) -> Self {
         Self {
-            key: key.into(),
+            auth,
             model: Model::default(),
             client_builder: ClientBuilder::default(),
             base_url: DEFAULT_BASE_URL.clone(),
+            backend: Backend::GeminiApi,
+            vertex_project: None,
+            retry_policy: None,
+            clock: Arc::new(TokioClock),
+            preflight: false,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            max_inline_data_bytes: DEFAULT_MAX_INLINE_DATA_BYTES,
+            allow_unauthenticated_default_endpoint: false,
+            resolve_overrides: Vec::new(),
+            #[cfg(feature = "custom-dns")]
+            dns_resolver: None,
+            prices: PriceTable::default(),
+            prefer_http3: false,
+            metrics: None,
+            context_guard: None,
+            policy: None,
+            rate_limit: None,
+            output_sanitizer: None,
         }
     }
 
+    /// Sets how requests are authenticated, replacing the API key.
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Sends requests without credentials, e.g. to a gateway that authenticates them itself.
+    ///
+    /// Building fails if the base URL is still the default Google endpoint, unless
+    /// [`allow_unauthenticated_default_endpoint()`](Self::allow_unauthenticated_default_endpoint)
+    /// is set.
+    pub fn no_auth(self) -> Self {
+        self.with_auth(Auth::None)
+    }
+
+    /// Allows [`no_auth()`](Self::no_auth) against the default Google endpoint, e.g. in tests.
+    pub fn allow_unauthenticated_default_endpoint(mut self) -> Self {
+        self.allow_unauthenticated_default_endpoint = true;
+        self
+    }
+
     /// Sets the model for the client.
     pub fn with_model<M: Into<Model>>(mut self, model: M) -> Self {
         self.model = model.into();
@@ -806,25 +1937,474 @@ impl GeminiBuilder {
         self
     }
 
+    /// Connects to `addrs` instead of resolving `host`, see [`dns`].
+    ///
+    /// A port of `0` stands for the port of the base URL. Requests still name `host`, so TLS
+    /// certificates are verified against it.
+    pub fn resolve(
+        mut self,
+        host: impl Into<String>,
+        addrs: impl IntoIterator<Item = SocketAddr>,
+    ) -> Self {
+        self.resolve_overrides
+            .push((host.into(), addrs.into_iter().collect()));
+        self
+    }
+
+    /// Resolves host names with `resolver` instead of the system resolver, see [`dns`].
+    ///
+    /// Hosts pinned with [`resolve()`](Self::resolve) are not passed to the resolver.
+    #[cfg(feature = "custom-dns")]
+    pub fn dns_resolver(mut self, resolver: impl dns::Resolve + 'static) -> Self {
+        self.dns_resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Sends requests over HTTP/3 first, falling back to TCP when QUIC fails, see
+    /// [`transport`](crate::transport).
+    ///
+    /// Requires the `http3` feature; without it, every request is sent over TCP. HTTP/3
+    /// requests are sent by a separate client, which pins the same addresses and DNS resolver
+    /// but none of the settings of a client set with
+    /// [`with_http_client()`](Self::with_http_client).
+    pub fn prefer_http3(mut self, prefer: bool) -> Self {
+        self.prefer_http3 = prefer;
+        self
+    }
+
     /// Sets a custom base URL for the API.
     pub fn with_base_url(mut self, base_url: Url) -> Self {
         self.base_url = base_url;
         self
     }
 
+    /// Retries transient HTTP failures according to `policy`.
+    ///
+    /// Requests are not retried by default. Once the policy gives up, the last error is
+    /// wrapped in [`Error::RetriesExhausted`] with the [`AttemptsReport`] of the call.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Keeps requests under `requests_per_minute`, and the tokens they use approximately under
+    /// `tokens_per_minute`, if set, by delaying them, see [`pipeline`](crate::retry::pipeline).
+    ///
+    /// The limit is shared by every clone of the client. Like a rate limit set with
+    /// [`with_policy()`](Self::with_policy), of which it is a shorthand, building fails with
+    /// [`Error::InvalidPolicy`] if the limit allows nothing or if a policy sets one as well.
+    pub fn with_rate_limit(
+        mut self,
+        requests_per_minute: u32,
+        tokens_per_minute: impl Into<Option<u32>>,
+    ) -> Self {
+        let limit = RateLimit::per_minute(requests_per_minute);
+        self.rate_limit = Some(match tokens_per_minute.into() {
+            Some(tokens) => limit.with_tokens(tokens),
+            None => limit,
+        });
+        self
+    }
+
+    /// Sends every request through the layers of `policy`: a retry budget, model fallbacks,
+    /// retries, and a rate limit, see [`pipeline`](crate::retry::pipeline).
+    ///
+    /// Building fails with [`Error::InvalidPolicy`] if the layers cannot work together, or if
+    /// a retry policy is also set with [`with_retry_policy()`](Self::with_retry_policy).
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Sets the clock used for retry backoff, polling, and measuring durations.
+    ///
+    /// Defaults to [`TokioClock`]; tests can use
+    /// [`ManualClock`](crate::clock::ManualClock) (`testing` feature) to run time-dependent
+    /// behavior instantly.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Validates requests client-side before sending them.
+    ///
+    /// Currently the requested response modalities are checked against the
+    /// [capability matrix](crate::supported_response_modalities); unsupported ones fail with
+    /// [`Error::UnsupportedModalities`] instead of a `400` from the API. Models missing from
+    /// the matrix are not checked. Features the client's [backend](crate::backend) does not
+    /// offer fail with [`Error::UnsupportedOnBackend`].
+    pub fn with_preflight(mut self, preflight: bool) -> Self {
+        self.preflight = preflight;
+        self
+    }
+
+    /// Limits the size of every response body, including file downloads, to `max_bytes`.
+    ///
+    /// The limit is enforced while the body is read, so an oversized response fails with
+    /// [`Error::ResponseTooLarge`] without being buffered in full. For streamed responses it
+    /// applies to the total size of the stream. Defaults to [`DEFAULT_MAX_RESPONSE_BYTES`].
+    pub fn with_max_response_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_response_bytes = max_bytes;
+        self
+    }
+
+    /// Limits the decoded size of every inline data part of a generated response to
+    /// `max_bytes`.
+    ///
+    /// Larger parts fail with [`Error::InlineDataTooLarge`] before anything decodes them.
+    /// Defaults to [`DEFAULT_MAX_INLINE_DATA_BYTES`].
+    pub fn with_max_inline_data_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_inline_data_bytes = max_bytes;
+        self
+    }
+
+    /// Prices usage with `prices` instead of the list prices, e.g. negotiated ones, see
+    /// [`pricing`](crate::pricing).
+    pub fn with_price_table(mut self, prices: PriceTable) -> Self {
+        self.prices = prices;
+        self
+    }
+
+    /// Checks the prompt of every generation request against the context window of its
+    /// model before sending it, see [`limits`](crate::limits).
+    ///
+    /// Prompts exceeding the window fail with [`Error::ContextWindowExceeded`].
+    pub fn with_context_guard(mut self, guard: ContextGuard) -> Self {
+        self.context_guard = Some(guard);
+        self
+    }
+
+    /// Reports the sizes of request and response bodies to `sink`, see
+    /// [`metrics`](crate::metrics).
+    pub fn with_metrics_sink(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.metrics = Some(Arc::new(sink));
+        self
+    }
+
+    /// Removes control characters from the text of every response according to `policy`,
+    /// see [`safety::sanitize`](crate::safety::sanitize).
+    ///
+    /// Off by default, so that the text is returned exactly as the model produced it.
+    pub fn sanitize_output(mut self, policy: SanitizePolicy) -> Self {
+        self.output_sanitizer = Some(policy);
+        self
+    }
+
     /// Builds the `Gemini` client.
     pub fn build(self) -> Result<Gemini, Error> {
+        let unauthenticated = matches!(self.auth, Auth::None);
+        snafu::ensure!(
+            !unauthenticated
+                || self.allow_unauthenticated_default_endpoint
+                || self.base_url.host_str() != DEFAULT_BASE_URL.host_str(),
+            UnauthenticatedDefaultEndpointSnafu {
+                base_url: self.base_url,
+            }
+        );
+        let vertex_parent = match &self.vertex_project {
+            Some((project, location)) => Some(
+                vertex_parent(project, location)
+                    .context(InvalidVertexProjectSnafu { project, location })?,
+            ),
+            None => None,
+        };
+        let policy = self.policy.unwrap_or_default();
+        policy
+            .validate(
+                &self.model,
+                self.retry_policy.as_ref(),
+                self.rate_limit.as_ref(),
+            )
+            .context(InvalidPolicySnafu)?;
+        let resolved = Arc::new(ResolvedAddrs::default());
+        for (host, addrs) in &self.resolve_overrides {
+            resolved.record(host, addrs.clone());
+        }
+        #[cfg(feature = "custom-dns")]
+        let dns_resolver = self.dns_resolver.map(|inner| {
+            Arc::new(dns::RecordingResolver {
+                inner,
+                resolved: resolved.clone(),
+            })
+        });
+        // Both the TCP and the HTTP/3 client connect to the same addresses
+        let route = |mut builder: ClientBuilder| {
+            for (host, addrs) in &self.resolve_overrides {
+                builder = builder.resolve_to_addrs(host, addrs);
+            }
+            #[cfg(feature = "custom-dns")]
+            if let Some(resolver) = &dns_resolver {
+                builder = builder.dns_resolver(resolver.clone());
+            }
+            builder
+        };
+        let http3 = match self.prefer_http3 {
+            true => Http3State::preferred(route(
+                ClientBuilder::new()
+                    .default_headers(GeminiClient::default_headers(&self.auth, self.backend)?),
+            )),
+            false => Http3State::off(),
+        };
+        let client_builder = route(self.client_builder);
+        let mut client = GeminiClient::with_base_url(
+            client_builder,
+            self.auth,
+            self.model,
+            self.base_url,
+            self.backend,
+        )?;
+        client.resolved = resolved;
+        client.vertex_parent = vertex_parent;
+        client.retry_policy = self.retry_policy.or(policy.retry);
+        client.rate_limiter = policy
+            .rate_limit
+            .or(self.rate_limit)
+            .map(|limit| Arc::new(RateLimiter::new(limit)));
+        client.fallback_models = policy.fallback.into();
+        client.retry_budget = policy.budget;
+        client.clock = self.clock;
+        client.preflight = self.preflight;
+        client.max_response_bytes = self.max_response_bytes;
+        client.max_inline_data_bytes = self.max_inline_data_bytes;
+        client.prices = Arc::new(self.prices);
+        client.ledger = UsageLedger::with_shared_prices(client.prices.clone());
+        client.metrics = self.metrics;
+        client.context_guard = self.context_guard.map(Arc::new);
+        client.output_sanitizer = self.output_sanitizer;
+        client.http3 = Arc::new(http3);
         Ok(Gemini {
-            client: Arc::new(GeminiClient::with_base_url(
-                self.client_builder,
-                self.key,
-                self.model,
-                self.base_url,
-            )?),
+            client: Arc::new(client),
         })
     }
 }
 
+/// Deserializes a server-sent event.
+///
+/// The API reports errors occurring after the stream started, e.g. an overloaded model, as an
+/// event holding an error status; it becomes [`Error::BadResponse`] instead of an empty chunk.
+#[cfg(feature = "streaming")]
+fn decode_event<Res: serde::de::DeserializeOwned>(data: &str) -> Result<Res, Error> {
+    if let Some(status) = Status::from_body(data) {
+        return BadResponseSnafu {
+            code: match status.code {
+                0 => 500,
+                code => code,
+            },
+            description: data.to_string(),
+            retry_after: None,
+        }
+        .fail();
+    }
+    json::from_str(data).context(DeserializeSnafu)
+}
+
+/// Reads a response body, failing as soon as it exceeds `limit` bytes
+async fn read_body(mut response: Response, limit: usize) -> Result<Vec<u8>, Error> {
+    if let Some(length) = response.content_length() {
+        snafu::ensure!(length <= limit as u64, ResponseTooLargeSnafu { limit });
+    }
+    let meter = BodyMeter::of(&response);
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.context(DecodeResponseSnafu)? {
+        meter.add(chunk.len());
+        snafu::ensure!(
+            body.len() + chunk.len() <= limit,
+            ResponseTooLargeSnafu { limit }
+        );
+        body.extend_from_slice(&chunk);
+    }
+    meter.finish();
+    Ok(body)
+}
+
+/// Reads and deserializes a JSON response body of at most `limit` bytes
+async fn read_json<T: serde::de::DeserializeOwned>(
+    response: Response,
+    limit: usize,
+) -> Result<T, Error> {
+    let code = response.status().as_u16();
+    let content_type = content_type(&response);
+    let body = read_body(response, limit).await?;
+    let unexpected = |reason: String| Error::UnexpectedResponse {
+        code,
+        content_type: content_type.clone(),
+        reason,
+        body: body_excerpt(&body),
+    };
+    if let Some(content_type) = content_type
+        .as_deref()
+        .filter(|ct| !has_subtype(ct, "json"))
+    {
+        return Err(unexpected(format!("expected JSON, got {content_type}")));
+    }
+    json::from_slice(&body).map_err(|error| match body.is_empty() {
+        true => unexpected("empty body".to_string()),
+        false => unexpected(format!("invalid JSON: {error}")),
+    })
+}
+
+/// Streams a body of server-sent events, failing up front if the response has another
+/// content type
+#[cfg(feature = "streaming")]
+async fn event_stream_body(
+    response: Response,
+    limit: usize,
+) -> Result<impl Stream<Item = Result<impl AsRef<[u8]> + Send, Error>> + Send + use<>, Error> {
+    if let Some(content_type) =
+        content_type(&response).filter(|ct| !has_subtype(ct, "event-stream"))
+    {
+        let code = response.status().as_u16();
+        let body = read_prefix(response, UNEXPECTED_BODY_CHARS * 32).await;
+        return UnexpectedResponseSnafu {
+            code,
+            reason: format!("expected server-sent events, got {content_type}"),
+            content_type,
+            body: body_excerpt(&body),
+        }
+        .fail();
+    }
+    limited_body(response, limit)
+}
+
+/// Reads at least `len` bytes of a body if it has as many, ignoring errors
+#[cfg(feature = "streaming")]
+async fn read_prefix(mut response: Response, len: usize) -> Vec<u8> {
+    let mut body = Vec::new();
+    while body.len() < len {
+        match response.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            Ok(None) | Err(_) => break,
+        }
+    }
+    body
+}
+
+/// The `content-type` header of a response
+fn content_type(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Whether a content type has the given subtype or, like `application/problem+json`, suffix
+fn has_subtype(content_type: &str, subtype: &str) -> bool {
+    content_type.parse::<Mime>().is_ok_and(|mime| {
+        mime.subtype() == subtype || mime.suffix().is_some_and(|suffix| suffix == subtype)
+    })
+}
+
+/// The start of a body for diagnostics, at most [`UNEXPECTED_BODY_CHARS`] characters with
+/// whitespace and control characters collapsed
+fn body_excerpt(body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(body);
+    let mut excerpt = String::new();
+    let mut chars = 0;
+    for word in text
+        .split(|c: char| c.is_whitespace() || c.is_control())
+        .filter(|word| !word.is_empty())
+    {
+        for c in (!excerpt.is_empty())
+            .then_some(' ')
+            .into_iter()
+            .chain(word.chars())
+        {
+            if chars == UNEXPECTED_BODY_CHARS {
+                excerpt.push('…');
+                return excerpt;
+            }
+            excerpt.push(c);
+            chars += 1;
+        }
+    }
+    excerpt
+}
+
+/// Streams a response body, ending with an error (and dropping the connection) once more
+/// than `limit` bytes were received in total
+#[cfg(feature = "streaming")]
+fn limited_body(
+    response: Response,
+    limit: usize,
+) -> Result<impl Stream<Item = Result<impl AsRef<[u8]> + Send, Error>> + Send + use<>, Error> {
+    if let Some(length) = response.content_length() {
+        snafu::ensure!(length <= limit as u64, ResponseTooLargeSnafu { limit });
+    }
+    let meter = BodyMeter::of(&response);
+    Ok(Box::pin(async_stream::try_stream! {
+        let mut received = 0;
+        let mut chunks = response.bytes_stream();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.map_err(|source| Error::BadPart {
+                source: EventStreamError::Transport(source),
+            })?;
+            received += chunk.len();
+            meter.add(chunk.len());
+            if received > limit {
+                Err(ResponseTooLargeSnafu { limit }.build())?;
+            }
+            yield chunk;
+        }
+        meter.finish();
+    }))
+}
+
+fn join_modalities(modalities: &[Modality]) -> String {
+    modalities
+        .iter()
+        .map(Modality::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Fails if the prompt of a response was blocked
+fn check_prompt_feedback(response: &GenerationResponse) -> Result<(), Error> {
+    match &response.prompt_feedback {
+        Some(PromptFeedback {
+            block_reason: Some(reason),
+            safety_ratings,
+        }) if *reason != BlockReason::BlockReasonUnspecified => PromptBlockedSnafu {
+            reason: reason.clone(),
+            safety_ratings: safety_ratings.clone(),
+        }
+        .fail(),
+        _ => Ok(()),
+    }
+}
+
+/// Fails if an inline data part of a response decodes to more than `limit` bytes
+fn check_inline_data(response: &GenerationResponse, limit: usize) -> Result<(), Error> {
+    let blobs = response
+        .candidates
+        .iter()
+        .flat_map(|candidate| candidate.content.parts.iter().flatten())
+        .filter_map(|part| match part {
+            Part::InlineData { inline_data } => Some(inline_data),
+            _ => None,
+        });
+    for blob in blobs {
+        let size = blob.decoded_len();
+        snafu::ensure!(
+            size <= limit,
+            InlineDataTooLargeSnafu {
+                mime_type: blob.mime_type.clone(),
+                size,
+                limit,
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Resolves a model name given with or without the `models/` prefix
+fn raw_model(model: &str) -> Model {
+    match model.contains('/') {
+        true => Model::Custom(model.to_string()),
+        false => Model::Custom(format!("models/{model}")),
+    }
+}
+
 /// Client for the Gemini API
 #[derive(Clone)]
 pub struct Gemini {
@@ -858,29 +2438,308 @@ impl Gemini {
         model: M,
         base_url: Url,
     ) -> Result<Self, Error> {
-        let client =
-            GeminiClient::with_base_url(Default::default(), api_key, model.into(), base_url)?;
+        let client = GeminiClient::with_base_url(
+            Default::default(),
+            Auth::ApiKey(api_key.as_ref().to_string()),
+            model.into(),
+            base_url,
+            Backend::GeminiApi,
+        )?;
         Ok(Self {
             client: Arc::new(client),
         })
     }
 
+    /// Create a new client for Vertex AI in express mode with the specified API key, see
+    /// [`backend`](crate::backend)
+    pub fn vertex_express<K: Into<String>>(api_key: K) -> Result<Self, Error> {
+        GeminiBuilder::vertex_express(api_key).build()
+    }
+
+    /// Create a new client for the Vertex AI models of `project_id` in `location`,
+    /// authenticated with OAuth access tokens from `token_provider`, see
+    /// [`GeminiBuilder::vertex()`]
+    pub fn with_vertex(
+        project_id: impl Into<String>,
+        location: impl Into<String>,
+        token_provider: impl TokenProvider + 'static,
+    ) -> Result<Self, Error> {
+        GeminiBuilder::vertex(project_id, location, token_provider).build()
+    }
+
+    /// Start building a client without credentials; set them with
+    /// [`GeminiBuilder::with_auth()`], or use [`GeminiBuilder::no_auth()`] with a custom base URL.
+    pub fn builder() -> GeminiBuilder {
+        GeminiBuilder::with_auth_mode(Auth::None)
+    }
+
     /// Start building a content generation request
     pub fn generate_content(&self) -> ContentBuilder {
         ContentBuilder::new(self.client.clone())
     }
 
+    /// Start building a request to count the tokens of, without generating content.
+    ///
+    /// The builder takes the same content-construction methods as
+    /// [`generate_content()`](Self::generate_content), so it counts exactly the request those
+    /// calls would send.
+    pub fn count_tokens(&self) -> CountTokensBuilder {
+        CountTokensBuilder::from(self.generate_content())
+    }
+
+    /// Gets the metadata of `model`, e.g. [`Model::Gemini25Pro`] or the
+    /// [`name`](ModelInfo::name) of a listed model, including its token limits.
+    ///
+    /// The limits are cached for the client's [context guard](crate::limits).
+    pub async fn get_model(&self, model: impl Into<Model>) -> Result<ModelInfo, Error> {
+        let model = model.into();
+        let info = self.client.get_model(&model).await?;
+        if let Some(limits) = info.limits() {
+            let now = self.client.clock.now();
+            self.client.model_limits.insert(&model, limits, now);
+        }
+        Ok(info)
+    }
+
+    /// Lists the models available to the client's credentials, with their token limits and
+    /// supported generation methods.
+    ///
+    /// This method returns a stream that handles pagination automatically; collect it with
+    /// [`TryStreamExt::try_collect()`] to get every page.
+    pub fn list_models(
+        &self,
+        page_size: impl Into<Option<u32>>,
+    ) -> impl Stream<Item = Result<ModelInfo, Error>> + Send {
+        let client = self.client.clone();
+        let page_size = page_size.into();
+        async_stream::try_stream! {
+            let mut page_token: Option<String> = None;
+            loop {
+                let response = client
+                    .list_models(page_size, page_token.clone())
+                    .await?;
+
+                for model in response.models {
+                    yield model;
+                }
+
+                if let Some(next_page_token) = response.next_page_token {
+                    page_token = Some(next_page_token);
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Fetches the token limits of the client's model again, replacing the ones cached for
+    /// the client's [context guard](crate::limits).
+    pub async fn refresh_model_limits(&self) -> Result<Option<ModelLimits>, Error> {
+        self.client.refresh_model_limits().await
+    }
+
+    /// Checks that the API is reachable and accepts the credentials, without generating
+    /// content.
+    ///
+    /// Fetches the metadata of the client's default model once, within
+    /// [`DEFAULT_HEALTH_CHECK_TIMEOUT`](crate::health::DEFAULT_HEALTH_CHECK_TIMEOUT). Meant for
+    /// readiness probes, see the [`health`](crate::health) module.
+    pub async fn health_check(&self) -> HealthReport {
+        self.health_check_with_timeout(health::DEFAULT_HEALTH_CHECK_TIMEOUT)
+            .await
+    }
+
+    /// Like [`health_check()`](Self::health_check), bounded by `timeout` instead of the default.
+    #[instrument(skip(self), fields(model = %self.client.model))]
+    pub async fn health_check_with_timeout(&self, timeout: Duration) -> HealthReport {
+        let start = self.client.clock.now();
+        let status = HealthStatus::classify(self.client.check_model(timeout).await);
+        let latency = self.client.clock.now().saturating_duration_since(start);
+        tracing::debug!(?status, ?latency, "health check finished");
+        HealthReport { status, latency }
+    }
+
+    /// Runs `requests` concurrently and returns the first response accepted by `accept`.
+    ///
+    /// Useful to hide latency, e.g. by racing a cheap and an expensive prompt. Once a
+    /// response is accepted, the other requests are cancelled and their connections closed.
+    /// Fails with [`Error::NoAcceptableResponse`] if every request fails or is rejected.
+    ///
+    /// The usage of every response received, the accepted one and the rejected ones, is
+    /// recorded in the [client's ledger](Self::usage_ledger) under
+    /// [`race::LEDGER_TAG`](crate::generation::race::LEDGER_TAG).
+    pub async fn race<F>(
+        &self,
+        requests: impl IntoIterator<Item = ContentBuilder>,
+        accept: F,
+    ) -> Result<RaceResult, Error>
+    where
+        F: Fn(&GenerationResponse) -> bool,
+    {
+        race::race(
+            self.client.clock.as_ref(),
+            &self.client.ledger,
+            requests,
+            accept,
+        )
+        .await
+    }
+
+    /// Feeds the rows of a dataset through `template`, packing as many rows into each request
+    /// as fit `budget`, see [`dataset`](crate::generation::dataset).
+    ///
+    /// Each row is formatted with `format_row`; `{rows}` in `template` is replaced by the
+    /// formatted rows of a request, one per line.
+    pub fn process_dataset<T, I, F>(
+        &self,
+        rows: I,
+        format_row: F,
+        budget: TokenBudget,
+        template: impl Into<String>,
+    ) -> DatasetBuilder<I::IntoIter, F>
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: Send + 'static,
+        F: Fn(&T) -> String + Send + 'static,
+    {
+        DatasetBuilder::new(
+            self.clone(),
+            rows.into_iter(),
+            format_row,
+            budget,
+            template.into(),
+        )
+    }
+
+    /// Estimates the cost of `response` with the client's price table.
+    ///
+    /// The response is priced by the model version it names, or the client's model if it
+    /// names none. `None` if the table has no price for the model or the response carries no
+    /// usage metadata.
+    pub fn estimate_cost(&self, response: &GenerationResponse) -> Option<CostEstimate> {
+        let model = response
+            .model_version
+            .as_deref()
+            .unwrap_or(self.client.model.as_str());
+        self.client
+            .prices
+            .estimate(model, response.usage_metadata.as_ref()?)
+    }
+
+    /// The client's ledger, pricing usage with the client's price table.
+    ///
+    /// The ledger is shared by clones of the client and of the ledger. The client records
+    /// the usage of [races](Self::race) in it; record the usage of other requests with
+    /// [`UsageLedger::record_response()`].
+    pub fn usage_ledger(&self) -> UsageLedger {
+        self.client.ledger.clone()
+    }
+
+    /// Whether requests are sent over HTTP/3, see [`transport`](crate::transport)
+    pub fn http3_status(&self) -> Http3Status {
+        self.client.http3.status()
+    }
+
+    /// The clock of the client
+    pub(crate) fn clock(&self) -> Arc<dyn Clock> {
+        self.client.clock.clone()
+    }
+
+    /// The inner client, or one targeting `model` if it differs from the client's model
+    pub(crate) fn client_for(&self, model: &Model) -> Arc<GeminiClient> {
+        match self.client.model.as_str() == model.as_str() {
+            true => self.client.clone(),
+            false => Arc::new(self.client.for_model(model.clone())),
+        }
+    }
+
+    /// Start a multi-turn chat session
+    pub fn start_chat(&self) -> ChatSession {
+        ChatSession::new(self.client.clone())
+    }
+
+    /// Resume a chat session from an [export](ChatSession::export), possibly made with
+    /// another model.
+    ///
+    /// Exports keep the thought signatures of the history. If the export was made with a
+    /// different model, they are stripped, since signatures are bound to their model.
+    pub fn resume_chat(&self, export: SessionExport) -> ChatSession {
+        ChatSession::resume(self.client.clone(), export)
+    }
+
+    /// Send a raw `generateContent` request body to the given model.
+    ///
+    /// This is the escape hatch for API features the crate does not model yet: `body` is
+    /// sent as is, without any validation of its shape, and the response is returned as
+    /// untyped JSON. Authentication, base URL handling, retries, tracing, and error parsing
+    /// (see [`Error::api_status()`]) still apply.
+    ///
+    /// `model` may be given with or without the `models/` prefix.
+    pub async fn generate_content_raw(&self, model: &str, body: Value) -> Result<Value, Error> {
+        self.client
+            .for_model(raw_model(model))
+            .generate_content_value(&CallContext::default(), &body)
+            .await
+    }
+
+    /// Streaming twin of [`generate_content_raw()`](Self::generate_content_raw).
+    ///
+    /// Every server-sent event is returned as untyped JSON.
+    #[cfg(feature = "streaming")]
+    pub async fn generate_content_raw_stream(
+        &self,
+        model: &str,
+        body: Value,
+    ) -> Result<impl TryStream<Ok = Value, Error = Error> + Send + Unpin + use<>, Error> {
+        let stream = self
+            .client
+            .for_model(raw_model(model))
+            .generate_content_value_stream(&CallContext::default(), &body)
+            .await?;
+        Ok(Box::pin(stream.into_stream()))
+    }
+
     /// Start building a content embedding request
+    #[cfg(feature = "embeddings")]
     pub fn embed_content(&self) -> EmbedBuilder {
         EmbedBuilder::new(self.client.clone())
     }
 
+    /// Start building a request embedding many texts, in batches of up to 100 sent
+    /// concurrently, see [`EmbedBatchBuilder`].
+    #[cfg(feature = "embeddings")]
+    pub fn embed_batch<S: Into<String>>(
+        &self,
+        texts: impl IntoIterator<Item = S>,
+    ) -> EmbedBatchBuilder {
+        EmbedBatchBuilder::new(
+            self.client.clone(),
+            texts.into_iter().map(Into::into).collect(),
+        )
+    }
+
+    /// Start building a request embedding a long document in overlapping chunks.
+    ///
+    /// Each [`EmbeddedChunk`](crate::EmbeddedChunk) carries the byte range it spans in `text`.
+    /// Fails with [`Error::InvalidChunking`] if `config` cannot split text, see
+    /// [`TextChunker::new()`](crate::TextChunker::new).
+    #[cfg(feature = "embeddings")]
+    pub fn embed_document(
+        &self,
+        text: impl Into<String>,
+        config: ChunkingConfig,
+    ) -> Result<EmbedDocumentBuilder, Error> {
+        EmbedDocumentBuilder::new(self.client.clone(), text, config)
+    }
+
     /// Start building a batch content generation request
+    #[cfg(feature = "batch")]
     pub fn batch_generate_content(&self) -> BatchBuilder {
         BatchBuilder::new(self.client.clone())
     }
 
     /// Get a handle to a batch operation by its name.
+    #[cfg(feature = "batch")]
     pub fn get_batch(&self, name: &str) -> BatchHandle {
         BatchHandle::new(name.to_string(), self.client.clone())
     }
@@ -888,6 +2747,7 @@ impl Gemini {
     /// Lists batch operations.
     ///
     /// This method returns a stream that handles pagination automatically.
+    #[cfg(feature = "batch")]
     pub fn list_batches(
         &self,
         page_size: impl Into<Option<u32>>,
@@ -915,11 +2775,100 @@ impl Gemini {
     }
 
     /// Create cached content with a fluent API.
+    #[cfg(feature = "caching")]
     pub fn create_cache(&self) -> CacheBuilder {
         CacheBuilder::new(self.client.clone())
     }
 
+    /// Cache a large file with a fluent API.
+    ///
+    /// Uploads `source`, a path or bytes, waits until it is processed, and creates a cached
+    /// content referencing it, so that later requests can use it via
+    /// [`with_cached_content()`](crate::ContentBuilder::with_cached_content).
+    #[cfg(all(feature = "files", feature = "caching"))]
+    pub fn create_cache_from_file<S: Into<FileSource>>(
+        &self,
+        source: S,
+        mime_type: Mime,
+    ) -> CacheFromFileBuilder {
+        CacheFromFileBuilder::new(self.client.clone(), source.into(), mime_type)
+    }
+
+    /// The names of the files and cached contents whose [guards](crate::cleanup::ResourceGuard)
+    /// have not deleted them yet, sorted
+    #[cfg(any(feature = "files", feature = "caching"))]
+    pub fn guarded_resources(&self) -> Vec<String> {
+        self.client.resources.names()
+    }
+
+    /// Deletes the files and cached contents left over by earlier runs, see
+    /// [`cleanup`](crate::cleanup).
+    ///
+    /// Every file and cached content whose display name starts with `prefix` and that was
+    /// created more than `older_than` ago is deleted, except those still guarded by this
+    /// client. Files without a creation time are kept. Failing to delete a resource does not
+    /// stop the sweep; failing to list them does.
+    #[cfg(any(feature = "files", feature = "caching"))]
+    #[instrument(skip_all, fields(prefix, older_than = ?older_than))]
+    pub async fn cleanup_orphans(
+        &self,
+        prefix: &str,
+        older_than: Duration,
+    ) -> Result<OrphanSweep, Error> {
+        let cutoff = self.client.clock.now_utc() - older_than;
+        let guarded = self.guarded_resources();
+        let matches = |display_name: Option<&String>| {
+            display_name.is_some_and(|display_name| display_name.starts_with(prefix))
+        };
+
+        let mut orphans = Vec::new();
+        #[cfg(feature = "files")]
+        {
+            let files = self.list_files(None);
+            futures::pin_mut!(files);
+            while let Some(file) = files.try_next().await? {
+                let file = file.get_file_meta();
+                if matches(file.display_name.as_ref())
+                    && file.create_time.is_some_and(|created| created <= cutoff)
+                {
+                    orphans.push((ResourceKind::File, file.name.clone()));
+                }
+            }
+        }
+        #[cfg(feature = "caching")]
+        {
+            let caches = self.list_cached_contents(None);
+            futures::pin_mut!(caches);
+            while let Some(cache) = caches.try_next().await? {
+                if matches(cache.display_name.as_ref()) && cache.create_time <= cutoff {
+                    orphans.push((ResourceKind::CachedContent, cache.name));
+                }
+            }
+        }
+
+        let mut sweep = OrphanSweep::default();
+        for (kind, name) in orphans {
+            if guarded.contains(&name) {
+                continue;
+            }
+            match cleanup::delete(&self.client, kind, &name).await {
+                Ok(()) => sweep.deleted.push(name),
+                Err(error) => {
+                    tracing::debug!(resource.name = %name, %error, "deleting orphan failed");
+                    sweep.failed.push((name, error));
+                }
+            }
+        }
+        tracing::debug!(
+            deleted = sweep.deleted.len(),
+            failed = sweep.failed.len(),
+            "orphans swept"
+        );
+        Ok(sweep)
+    }
+
     /// Get a handle to cached content by its name.
+    #[cfg(feature = "caching")]
     pub fn get_cached_content(&self, name: &str) -> CachedContentHandle {
         CachedContentHandle::new(name.to_string(), self.client.clone())
     }
@@ -927,38 +2876,39 @@ impl Gemini {
     /// Lists cached contents.
     ///
     /// This method returns a stream that handles pagination automatically.
+    #[cfg(feature = "caching")]
     pub fn list_cached_contents(
         &self,
         page_size: impl Into<Option<i32>>,
     ) -> impl Stream<Item = Result<CachedContentSummary, Error>> + Send {
-        let client = self.client.clone();
-        let page_size = page_size.into();
-        async_stream::try_stream! {
-            let mut page_token: Option<String> = None;
-            loop {
-                let response = client
-                    .list_cached_contents(page_size, page_token.clone())
-                    .await?;
+        self.caches().list(page_size)
+    }
 
-                for cached_content in response.cached_contents {
-                    yield cached_content;
-                }
+    /// The cached contents API, for reusing a large prompt prefix across requests, see
+    /// [`cache::api`](crate::cache::api)
+    #[cfg(feature = "caching")]
+    pub fn caches(&self) -> Caches {
+        Caches::new(self.client.clone())
+    }
 
-                if let Some(next_page_token) = response.next_page_token {
-                    page_token = Some(next_page_token);
-                } else {
-                    break;
-                }
-            }
-        }
+    /// The Files API, for uploading media too large to send inline, see
+    /// [`files::api`](crate::files::api)
+    #[cfg(feature = "files")]
+    pub fn files(&self) -> Files {
+        Files::new(self.client.clone())
     }
 
     /// Start building a file resource
+    #[cfg(feature = "files")]
     pub fn create_file<B: Into<Vec<u8>>>(&self, bytes: B) -> crate::files::builder::FileBuilder {
-        crate::files::builder::FileBuilder::new(self.client.clone(), bytes)
+        crate::files::builder::FileBuilder::new(
+            self.client.clone(),
+            FileSource::Bytes(bytes.into()),
+        )
     }
 
     /// Get a handle to a file by its name.
+    #[cfg(feature = "files")]
     pub async fn get_file(&self, name: &str) -> Result<FileHandle, Error> {
         let file = self.client.get_file(name).await?;
         Ok(FileHandle::new(self.client.clone(), file))
@@ -967,6 +2917,7 @@ impl Gemini {
     /// Lists files.
     ///
     /// This method returns a stream that handles pagination automatically.
+    #[cfg(feature = "files")]
     pub fn list_files(
         &self,
         page_size: impl Into<Option<u32>>,
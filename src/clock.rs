@@ -0,0 +1,136 @@
+//! Time source of the client.
+//!
+//! Everything that waits or measures time (retry backoff, polling for file activation,
+//! timing raced requests) goes through the client's [`Clock`], set with
+//! [`GeminiBuilder::with_clock()`](crate::GeminiBuilder::with_clock). The default
+//! [`TokioClock`] uses tokio's timer. With the `testing` feature, [`ManualClock`] makes
+//! time-dependent behavior run instantly and deterministically.
+
+use async_trait::async_trait;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+
+/// A source of the current time and of delays
+#[async_trait]
+pub trait Clock: Send + Sync {
+    /// The current monotonic time, for measuring elapsed time
+    fn now(&self) -> Instant;
+
+    /// The current wall-clock time, for comparing with server timestamps
+    fn now_utc(&self) -> OffsetDateTime;
+
+    /// Waits for `duration`
+    async fn sleep(&self, duration: Duration);
+}
+
+/// The real time, with delays from tokio's timer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+#[async_trait]
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        // Follows tokio's paused time in tests
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn now_utc(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+pub use manual::ManualClock;
+
+#[cfg(any(test, feature = "testing"))]
+mod manual {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A clock that only moves when told to.
+    ///
+    /// Sleeping advances the clock by the requested duration and returns right away, so code
+    /// waiting for backoff or polling intervals runs instantly while still observing the time
+    /// passing. [`advance()`](Self::advance) moves the clock without sleeping, e.g. to let a
+    /// TTL run out. Clones share the same time.
+    #[derive(Debug, Clone)]
+    pub struct ManualClock {
+        start: Instant,
+        start_utc: OffsetDateTime,
+        state: Arc<Mutex<State>>,
+    }
+
+    #[derive(Debug, Default)]
+    struct State {
+        elapsed: Duration,
+        sleeps: Vec<Duration>,
+    }
+
+    impl ManualClock {
+        /// Creates a clock starting at the current time
+        pub fn new() -> Self {
+            Self::starting_at(OffsetDateTime::now_utc())
+        }
+
+        /// Creates a clock whose wall-clock time starts at `start_utc`
+        pub fn starting_at(start_utc: OffsetDateTime) -> Self {
+            Self {
+                start: Instant::now(),
+                start_utc,
+                state: Default::default(),
+            }
+        }
+
+        /// Moves the clock forward by `duration`
+        pub fn advance(&self, duration: Duration) {
+            self.state
+                .lock()
+                .expect("manual clock lock poisoned")
+                .elapsed += duration;
+        }
+
+        /// Time the clock has moved since it was created
+        pub fn elapsed(&self) -> Duration {
+            self.state
+                .lock()
+                .expect("manual clock lock poisoned")
+                .elapsed
+        }
+
+        /// The durations of all sleeps so far, in order
+        pub fn sleeps(&self) -> Vec<Duration> {
+            self.state
+                .lock()
+                .expect("manual clock lock poisoned")
+                .sleeps
+                .clone()
+        }
+    }
+
+    impl Default for ManualClock {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    #[async_trait]
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.start + self.elapsed()
+        }
+
+        fn now_utc(&self) -> OffsetDateTime {
+            self.start_utc + self.elapsed()
+        }
+
+        async fn sleep(&self, duration: Duration) {
+            let mut state = self.state.lock().expect("manual clock lock poisoned");
+            state.elapsed += duration;
+            state.sleeps.push(duration);
+        }
+    }
+}
@@ -1 +1,5 @@
+pub(crate) mod path;
+pub mod rpc;
 pub(crate) mod serde;
+pub mod strict;
+pub mod types;
@@ -0,0 +1,222 @@
+//! Deserializing JSON values while tracking where a failure happened.
+//!
+//! serde reports why a value could not be deserialized, but not where: a field holding a
+//! malformed date fails with the date parser's message alone. [`from_value()`] walks the value
+//! itself and, on failure, names the innermost field being deserialized as a JSONPath such as
+//! `$.events[2].start`, along with its raw string value.
+
+use serde::de::{
+    self, value::BorrowedStrDeserializer, DeserializeOwned, DeserializeSeed, Deserializer,
+    MapAccess, SeqAccess, Visitor,
+};
+use serde_json::Value;
+use std::{cell::RefCell, fmt};
+
+/// Where deserialization failed
+#[derive(Debug)]
+pub(crate) struct PathError {
+    /// The error of serde
+    pub source: serde_json::Error,
+    /// The field whose value failed, as a JSONPath
+    pub path: String,
+    /// The value of the field, if it is a string
+    pub raw: Option<String>,
+}
+
+/// Deserializes `value` into `T`, naming the field that failed
+pub(crate) fn from_value<T: DeserializeOwned>(value: &Value) -> Result<T, PathError> {
+    let failure = RefCell::new(None);
+    let tracked = Tracked {
+        value,
+        path: &Path::Root,
+        failure: &failure,
+    };
+    T::deserialize(tracked).map_err(|source| {
+        let (path, raw) = failure
+            .into_inner()
+            .unwrap_or_else(|| ("$".to_string(), None));
+        PathError { source, path, raw }
+    })
+}
+
+/// A position in a JSON value
+#[derive(Clone, Copy)]
+enum Path<'a> {
+    Root,
+    Key(&'a Path<'a>, &'a str),
+    Index(&'a Path<'a>, usize),
+}
+
+impl fmt::Display for Path<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Path::Root => f.write_str("$"),
+            Path::Key(parent, key) => write!(f, "{parent}.{key}"),
+            Path::Index(parent, index) => write!(f, "{parent}[{index}]"),
+        }
+    }
+}
+
+/// The path and raw string value of the innermost failed field
+type Failure = RefCell<Option<(String, Option<String>)>>;
+
+/// A value at a path, deserialized by delegating to [`Value`] for everything but containers
+struct Tracked<'a, 'de> {
+    value: &'de Value,
+    path: &'a Path<'a>,
+    failure: &'a Failure,
+}
+
+impl Tracked<'_, '_> {
+    /// Records the path of a failure, unless a field inside this one failed first
+    fn note<T>(&self, result: Result<T, serde_json::Error>) -> Result<T, serde_json::Error> {
+        note(self.failure, self.path, self.value, result)
+    }
+}
+
+/// Records `path` as the failed field if `result` is an error and no field inside it failed
+///
+/// Noted both where a value is deserialized and where its result is handed to the parent, as
+/// `Deserialize` impls may fail after deserializing successfully, e.g. when parsing a string.
+fn note<T>(
+    failure: &Failure,
+    path: &Path<'_>,
+    value: &Value,
+    result: Result<T, serde_json::Error>,
+) -> Result<T, serde_json::Error> {
+    if result.is_err() {
+        let mut failure = failure.borrow_mut();
+        if failure.is_none() {
+            let raw = value.as_str().map(str::to_string);
+            *failure = Some((path.to_string(), raw));
+        }
+    }
+    result
+}
+
+impl<'de> Deserializer<'de> for Tracked<'_, 'de> {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let result = match self.value {
+            Value::Object(map) => visitor.visit_map(TrackedMap {
+                entries: map.iter(),
+                value: None,
+                parent: self.path,
+                failure: self.failure,
+            }),
+            Value::Array(items) => visitor.visit_seq(TrackedSeq {
+                items: items.iter().enumerate(),
+                parent: self.path,
+                failure: self.failure,
+            }),
+            value => value.deserialize_any(visitor),
+        };
+        self.note(result)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let result = self.value.deserialize_enum(name, variants, visitor);
+        self.note(result)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct TrackedMap<'a, 'de> {
+    entries: serde_json::map::Iter<'de>,
+    /// The entry whose key was deserialized last
+    value: Option<(&'de str, &'de Value)>,
+    parent: &'a Path<'a>,
+    failure: &'a Failure,
+}
+
+impl<'de> MapAccess<'de> for TrackedMap<'_, 'de> {
+    type Error = serde_json::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        self.value = Some((key, value));
+        seed.deserialize(BorrowedStrDeserializer::new(key))
+            .map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (key, value) = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value requested before its key"))?;
+        let path = Path::Key(self.parent, key);
+        let result = seed.deserialize(Tracked {
+            value,
+            path: &path,
+            failure: self.failure,
+        });
+        note(self.failure, &path, value, result)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct TrackedSeq<'a, 'de> {
+    items: std::iter::Enumerate<std::slice::Iter<'de, Value>>,
+    parent: &'a Path<'a>,
+    failure: &'a Failure,
+}
+
+impl<'de> SeqAccess<'de> for TrackedSeq<'_, 'de> {
+    type Error = serde_json::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        let Some((index, value)) = self.items.next() else {
+            return Ok(None);
+        };
+        let path = Path::Index(self.parent, index);
+        let result = seed.deserialize(Tracked {
+            value,
+            path: &path,
+            failure: self.failure,
+        });
+        note(self.failure, &path, value, result).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
@@ -0,0 +1,78 @@
+//! Google RPC error payloads returned by the API in non-success response bodies.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::generation::video::parse_offset;
+
+/// Envelope wrapping a [`Status`] in an error response body
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ErrorEnvelope {
+    /// The error status
+    pub error: Status,
+}
+
+/// The `google.rpc.Status` error model
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    /// The HTTP status code
+    #[serde(default)]
+    pub code: u16,
+    /// Developer-facing error message
+    #[serde(default)]
+    pub message: String,
+    /// The canonical status name, e.g. `INVALID_ARGUMENT`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// Additional typed error details
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<serde_json::Value>,
+}
+
+/// A single invalid field reported in a `google.rpc.BadRequest` detail
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldViolation {
+    /// Path to the offending field, e.g. `contents[3].parts[2].inline_data`
+    pub field: String,
+    /// Description of why the field is invalid
+    #[serde(default)]
+    pub description: String,
+}
+
+impl Status {
+    /// Parse a status from a raw error response body
+    pub fn from_body(body: &str) -> Option<Self> {
+        serde_json::from_str::<ErrorEnvelope>(body)
+            .ok()
+            .map(|envelope| envelope.error)
+    }
+
+    /// Collect the field violations from all `BadRequest` details
+    pub fn field_violations(&self) -> Vec<FieldViolation> {
+        self.details
+            .iter()
+            .filter_map(|detail| detail.get("fieldViolations"))
+            .filter_map(|violations| {
+                serde_json::from_value::<Vec<FieldViolation>>(violations.clone()).ok()
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// The delay to wait before retrying, from a `RetryInfo` detail, e.g. for an exhausted
+    /// quota
+    pub fn retry_delay(&self) -> Option<Duration> {
+        self.details
+            .iter()
+            .filter(|detail| {
+                detail
+                    .get("@type")
+                    .and_then(serde_json::Value::as_str)
+                    .is_some_and(|kind| kind.ends_with("google.rpc.RetryInfo"))
+            })
+            .filter_map(|detail| detail.get("retryDelay")?.as_str())
+            .find_map(|delay| parse_offset(delay).ok())
+    }
+}
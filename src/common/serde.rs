@@ -1,8 +1,11 @@
 /// Custom serialization/deserialization for i64 as a string.
+#[cfg(feature = "files")]
 pub(crate) mod i64_as_string {
+    #[cfg(feature = "batch")]
     use serde::{self, de, Deserialize, Deserializer, Serializer};
 
     /// Serializes an `i64` as a string.
+    #[cfg(feature = "batch")]
     pub fn serialize<S>(value: &i64, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
@@ -11,6 +14,7 @@ pub(crate) mod i64_as_string {
     }
 
     /// Deserializes a string into an `i64`.
+    #[cfg(feature = "batch")]
     pub fn deserialize<'de, D>(deserializer: D) -> Result<i64, D::Error>
     where
         D: Deserializer<'de>,
@@ -48,6 +52,7 @@ pub(crate) mod i64_as_string {
 }
 
 /// Custom serialization/deserialization for the request key.
+#[cfg(feature = "batch")]
 pub(crate) mod key_as_string {
     use serde::{self, Deserialize, Deserializer, Serializer};
 
@@ -74,3 +79,60 @@ pub(crate) mod key_as_string {
         })
     }
 }
+
+/// Serialization of JSON values with the keys of every object sorted.
+///
+/// Requests are cached implicitly by their exact prefix, so the same logical request must
+/// serialize to the same bytes in every process. `serde_json::Map` keeps its keys sorted, unless
+/// another crate of the build enables serde_json's `preserve_order` feature: the keys then keep
+/// their insertion order, which differs when a value was built from a `HashMap`.
+pub(crate) mod sorted_json {
+    use serde::{ser::SerializeMap, Serialize, Serializer};
+    use serde_json::Value;
+
+    /// A JSON value serialized with sorted object keys
+    pub(crate) struct Sorted<'a>(pub(crate) &'a Value);
+
+    impl Serialize for Sorted<'_> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match self.0 {
+                Value::Object(object) => {
+                    let mut entries: Vec<_> = object.iter().collect();
+                    entries.sort_unstable_by_key(|(key, _)| *key);
+                    let mut map = serializer.serialize_map(Some(entries.len()))?;
+                    for (key, value) in entries {
+                        map.serialize_entry(key, &Sorted(value))?;
+                    }
+                    map.end()
+                }
+                Value::Array(items) => serializer.collect_seq(items.iter().map(Sorted)),
+                scalar => scalar.serialize(serializer),
+            }
+        }
+    }
+
+    /// Serializes a JSON value with sorted object keys.
+    pub fn serialize<S>(value: &Value, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Sorted(value).serialize(serializer)
+    }
+
+    /// Optional JSON value with sorted object keys.
+    pub(crate) mod optional {
+        use serde::{Serialize, Serializer};
+        use serde_json::Value;
+
+        /// Serializes an `Option<Value>` with sorted object keys or `None`.
+        pub fn serialize<S>(value: &Option<Value>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            value.as_ref().map(super::Sorted).serialize(serializer)
+        }
+    }
+}
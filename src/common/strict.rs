@@ -0,0 +1,108 @@
+//! Strict deserialization for catching wire-format drift.
+//!
+//! Typed parsing is tolerant: fields the crate does not model are silently ignored. Strict
+//! parsing performs the usual typed parse, re-serializes the result and diffs it against the
+//! raw JSON, reporting every key that did not survive the round trip, or came back as a
+//! different JSON type, together with its JSON path. Keys whose value is `null` are not
+//! reported when dropped: `skip_serializing_if` leaves out absent optional fields, and `null`
+//! carries nothing the model could have lost. This is intended for CI runs against recorded
+//! fixtures rather than production use.
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+/// A key present in the raw JSON that the typed model does not capture
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownField {
+    /// JSON path of the key, e.g. `candidates[0].content.parts[1].foo`
+    pub path: String,
+    /// The raw value that was dropped or changed
+    pub value: Value,
+    /// The value after the round trip, if the key survived it as a different JSON type;
+    /// `None` if the key was dropped
+    pub typed: Option<Value>,
+}
+
+impl fmt::Display for UnknownField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.typed {
+            Some(typed) => write!(
+                f,
+                "{} ({} read as {})",
+                self.path,
+                json_type(&self.value),
+                json_type(typed)
+            ),
+            None => write!(f, "{}", self.path),
+        }
+    }
+}
+
+/// Parses `raw` into `T` and reports every key the typed model ignores.
+pub fn parse_strict<T>(raw: &str) -> Result<(T, Vec<UnknownField>), serde_json::Error>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let raw: Value = serde_json::from_str(raw)?;
+    parse_strict_value(raw)
+}
+
+/// Like [`parse_strict`], but starting from an already parsed JSON value.
+pub fn parse_strict_value<T>(raw: Value) -> Result<(T, Vec<UnknownField>), serde_json::Error>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let typed: T = serde_json::from_value(raw.clone())?;
+    let roundtrip = serde_json::to_value(&typed)?;
+    let mut unknown = Vec::new();
+    diff(&raw, &roundtrip, String::new(), &mut unknown);
+    Ok((typed, unknown))
+}
+
+fn diff(raw: &Value, typed: &Value, path: String, unknown: &mut Vec<UnknownField>) {
+    match (raw, typed) {
+        (Value::Object(raw), Value::Object(typed)) => {
+            for (key, value) in raw {
+                let child = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                match typed.get(key) {
+                    Some(typed) => diff(value, typed, child, unknown),
+                    None if value.is_null() => {}
+                    None => unknown.push(UnknownField {
+                        path: child,
+                        value: value.clone(),
+                        typed: None,
+                    }),
+                }
+            }
+        }
+        (Value::Array(raw), Value::Array(typed)) => {
+            for (i, (raw, typed)) in raw.iter().zip(typed).enumerate() {
+                diff(raw, typed, format!("{path}[{i}]"), unknown);
+            }
+        }
+        (raw, typed) if !raw.is_null() && json_type(raw) != json_type(typed) => {
+            unknown.push(UnknownField {
+                path,
+                value: raw.clone(),
+                typed: Some(typed.clone()),
+            });
+        }
+        _ => {}
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
@@ -0,0 +1,259 @@
+//! Enumerations shared by requests and responses of several API areas.
+//!
+//! [`Role`] marks the author of [`Content`](crate::Content), in requests, responses, and chat
+//! histories. [`Modality`] names a kind of media, both in
+//! [`GenerationConfig::response_modalities()`](crate::GenerationConfig::response_modalities)
+//! and in per-modality token counts. [`Language`] is the programming language of
+//! [`ExecutableCode`](crate::ExecutableCode), and [`Outcome`] how running it ended.
+//!
+//! The API writes [`Modality`], [`Language`], and [`Outcome`] in `SCREAMING_SNAKE_CASE`;
+//! lowercase names are accepted too. Values added to the API after this crate was released
+//! deserialize as their `Unknown` variant and serialize back unchanged.
+//!
+//! Both convert from and to `String` and compare with `&str`. The `response_modalities` field
+//! of [`GenerationConfig`](crate::GenerationConfig) keeps holding names, as it did in 1.5; its
+//! `response_modalities()` method and `with_response_modalities()` setter speak [`Modality`].
+//! [`Modality`] is `#[non_exhaustive]`: matches on it need a wildcard arm.
+
+#![allow(clippy::enum_variant_names)]
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Role of a message in a conversation
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Message from the user
+    User,
+    /// Message from the model
+    Model,
+}
+
+/// Content modality type - the format of model input or output
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[non_exhaustive]
+pub enum Modality {
+    /// Default value.
+    ModalityUnspecified,
+    /// Plain text.
+    #[serde(alias = "text")]
+    Text,
+    /// Images.
+    #[serde(alias = "image")]
+    Image,
+    /// Audio.
+    #[serde(alias = "audio")]
+    Audio,
+    /// Video.
+    #[serde(alias = "video")]
+    Video,
+    /// Documents, e.g. PDF files.
+    #[serde(alias = "document")]
+    Document,
+    /// A modality this version of the crate does not know, as sent by the API
+    #[serde(untagged)]
+    Unknown(String),
+}
+
+impl Modality {
+    const KNOWN: [Modality; 6] = [
+        Modality::ModalityUnspecified,
+        Modality::Text,
+        Modality::Image,
+        Modality::Audio,
+        Modality::Video,
+        Modality::Document,
+    ];
+
+    /// The name of the modality as sent to the API
+    pub fn as_str(&self) -> &str {
+        match self {
+            Modality::ModalityUnspecified => "MODALITY_UNSPECIFIED",
+            Modality::Text => "TEXT",
+            Modality::Image => "IMAGE",
+            Modality::Audio => "AUDIO",
+            Modality::Video => "VIDEO",
+            Modality::Document => "DOCUMENT",
+            Modality::Unknown(modality) => modality,
+        }
+    }
+}
+
+impl fmt::Display for Modality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Parses a modality name in any casing, e.g. `"IMAGE"` or `"image"`
+impl From<&str> for Modality {
+    fn from(name: &str) -> Self {
+        Modality::KNOWN
+            .into_iter()
+            .find(|known| known.as_str().eq_ignore_ascii_case(name))
+            .unwrap_or_else(|| Modality::Unknown(name.to_string()))
+    }
+}
+
+impl From<String> for Modality {
+    fn from(name: String) -> Self {
+        Modality::from(name.as_str())
+    }
+}
+
+/// The name of the modality, for code written when modalities were strings
+impl From<&Modality> for String {
+    fn from(modality: &Modality) -> Self {
+        modality.as_str().to_string()
+    }
+}
+
+impl From<Modality> for String {
+    fn from(modality: Modality) -> Self {
+        String::from(&modality)
+    }
+}
+
+/// Compares with the name of the modality as sent to the API, e.g. `"AUDIO"`
+impl PartialEq<str> for Modality {
+    fn eq(&self, name: &str) -> bool {
+        self.as_str() == name
+    }
+}
+
+impl PartialEq<&str> for Modality {
+    fn eq(&self, name: &&str) -> bool {
+        self.as_str() == *name
+    }
+}
+
+/// Programming language of code generated for execution
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[non_exhaustive]
+pub enum Language {
+    /// Default value.
+    LanguageUnspecified,
+    /// Python >= 3.10, with numpy and simpy available.
+    #[serde(alias = "python")]
+    Python,
+    /// A language this version of the crate does not know, as sent by the API
+    #[serde(untagged)]
+    Unknown(String),
+}
+
+impl Language {
+    /// The name of the language as sent by the API
+    pub fn as_str(&self) -> &str {
+        match self {
+            Language::LanguageUnspecified => "LANGUAGE_UNSPECIFIED",
+            Language::Python => "PYTHON",
+            Language::Unknown(language) => language,
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Parses a language name in any casing, e.g. `"PYTHON"` or `"python"`
+impl From<&str> for Language {
+    fn from(name: &str) -> Self {
+        [Language::LanguageUnspecified, Language::Python]
+            .into_iter()
+            .find(|known| known.as_str().eq_ignore_ascii_case(name))
+            .unwrap_or_else(|| Language::Unknown(name.to_string()))
+    }
+}
+
+impl From<String> for Language {
+    fn from(name: String) -> Self {
+        Language::from(name.as_str())
+    }
+}
+
+/// The name of the language, for code written when languages were strings
+impl From<Language> for String {
+    fn from(language: Language) -> Self {
+        language.as_str().to_string()
+    }
+}
+
+/// Compares with the name of the language as sent by the API, e.g. `"PYTHON"`
+impl PartialEq<str> for Language {
+    fn eq(&self, name: &str) -> bool {
+        self.as_str() == name
+    }
+}
+
+impl PartialEq<&str> for Language {
+    fn eq(&self, name: &&str) -> bool {
+        self.as_str() == *name
+    }
+}
+
+/// How running [`ExecutableCode`](crate::ExecutableCode) ended
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[non_exhaustive]
+pub enum Outcome {
+    /// Default value.
+    OutcomeUnspecified,
+    /// The code ran to completion; its output is the standard output.
+    #[serde(alias = "outcome_ok")]
+    OutcomeOk,
+    /// The code ran but failed; its output holds the standard output and the error, e.g. a
+    /// Python traceback.
+    #[serde(alias = "outcome_failed")]
+    OutcomeFailed,
+    /// The code ran too long and was cancelled; its output may be partial.
+    #[serde(alias = "outcome_deadline_exceeded")]
+    OutcomeDeadlineExceeded,
+    /// An outcome this version of the crate does not know, as sent by the API
+    #[serde(untagged)]
+    Unknown(String),
+}
+
+impl Outcome {
+    /// The name of the outcome as sent by the API
+    pub fn as_str(&self) -> &str {
+        match self {
+            Outcome::OutcomeUnspecified => "OUTCOME_UNSPECIFIED",
+            Outcome::OutcomeOk => "OUTCOME_OK",
+            Outcome::OutcomeFailed => "OUTCOME_FAILED",
+            Outcome::OutcomeDeadlineExceeded => "OUTCOME_DEADLINE_EXCEEDED",
+            Outcome::Unknown(outcome) => outcome,
+        }
+    }
+
+    /// Whether the code ran to completion
+    pub fn is_success(&self) -> bool {
+        *self == Outcome::OutcomeOk
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Parses an outcome name in any casing, e.g. `"OUTCOME_OK"` or `"outcome_ok"`
+impl From<&str> for Outcome {
+    fn from(name: &str) -> Self {
+        [
+            Outcome::OutcomeUnspecified,
+            Outcome::OutcomeOk,
+            Outcome::OutcomeFailed,
+            Outcome::OutcomeDeadlineExceeded,
+        ]
+        .into_iter()
+        .find(|known| known.as_str().eq_ignore_ascii_case(name))
+        .unwrap_or_else(|| Outcome::Unknown(name.to_string()))
+    }
+}
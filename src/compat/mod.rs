@@ -0,0 +1,6 @@
+//! Conversions between the crate's types and other providers' wire formats
+
+#[cfg(feature = "openai-compat")]
+pub mod openai;
+#[cfg(feature = "proto")]
+pub mod proto;
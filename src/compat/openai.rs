@@ -0,0 +1,1129 @@
+//! Conversion between Gemini requests and responses and the OpenAI chat-completions JSON
+//! shape, for tooling built around the latter.
+//!
+//! Messages and roles (`system` messages become the system instruction, `assistant` becomes
+//! `model`), text, image and audio parts, function tools, tool calls and their results, the
+//! common sampling parameters, finish reasons and token usage are mapped in both directions.
+//! Whatever has no equivalent is reported as a [`Warning`] in [`Converted::warnings`] instead
+//! of being dropped silently:
+//!
+//! - Gemini-only data, e.g. safety ratings, grounding and citation metadata, thoughts, code
+//!   execution parts, `topK`, safety settings and cached content.
+//! - OpenAI-only data, e.g. `model` (chosen by the client instead), message `name`s,
+//!   penalties, `logit_bias`, `logprobs` and `created` timestamps.
+//! - Approximations: `developer` messages, system messages after the start of the
+//!   conversation, forcing a specific function, and finish reasons without an exact
+//!   counterpart.
+//!
+//! Explicit defaults such as `null`, `false`, `0` or `[]` carry no data and never produce a
+//! warning. Tool call ids are kept in [`FunctionCall::id`] and [`FunctionResponse::id`]; calls
+//! without one get a `call_<n>` id when converted to the OpenAI shape.
+
+use serde_json::{json, Map, Value};
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    Blob, Candidate, Content, FileData, FinishReason, FunctionCall, FunctionCallingConfig,
+    FunctionCallingMode, FunctionDeclaration, FunctionResponse, GenerateContentRequest,
+    GenerationConfig, GenerationResponse, Part, Role, Tool, ToolConfig, UsageMetadata,
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("`{path}` must be {expected}"))]
+    Invalid {
+        path: String,
+        expected: &'static str,
+    },
+
+    #[snafu(display("`{path}` is not valid JSON"))]
+    Arguments {
+        path: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("`{path}` does not reference an earlier tool call: {id}"))]
+    UnknownToolCall { path: String, id: String },
+}
+
+/// Data that could not be converted exactly
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The value at `path` has no equivalent and was dropped
+    Dropped { path: String },
+    /// The value at `path` was mapped to the closest equivalent
+    Approximated { path: String, detail: String },
+}
+
+impl Warning {
+    /// Path of the affected value in the input, e.g. `messages[2].name`
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Dropped { path } | Self::Approximated { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dropped { path } => write!(f, "dropped `{path}`"),
+            Self::Approximated { path, detail } => write!(f, "approximated `{path}`: {detail}"),
+        }
+    }
+}
+
+/// The result of a conversion, along with the data it lost
+#[derive(Debug, Clone)]
+pub struct Converted<T> {
+    /// The converted value
+    pub value: T,
+    /// Everything that was dropped or approximated, in input order
+    pub warnings: Vec<Warning>,
+}
+
+/// Converts an OpenAI chat-completions request body into a [`GenerateContentRequest`]
+pub fn from_chat_completion_request(
+    request: Value,
+) -> Result<Converted<GenerateContentRequest>, Error> {
+    let request = object(&request, "request")?;
+    let mut warnings = Warnings::default();
+    let mut converted = GenerateContentRequest {
+        contents: Vec::new(),
+        generation_config: None,
+        safety_settings: None,
+        tools: None,
+        tool_config: None,
+        system_instruction: None,
+        cached_content: None,
+        labels: Default::default(),
+    };
+    messages(
+        array(get(request, "messages"), "messages")?,
+        &mut converted,
+        &mut warnings,
+    )?;
+
+    let mut config = GenerationConfig::default();
+    for (key, value) in request {
+        match key.as_str() {
+            _ if value.is_null() => {}
+            "messages" => {}
+            "temperature" => config.temperature = Some(number(value, key)?),
+            "top_p" => config.top_p = Some(number(value, key)?),
+            "max_tokens" | "max_completion_tokens" => {
+                config.max_output_tokens = Some(integer(value, key)?)
+            }
+            "n" => config.candidate_count = Some(integer(value, key)?),
+            "seed" => config.seed = Some(integer(value, key)?),
+            "stop" => {
+                config.stop_sequences = Some(match value {
+                    Value::String(stop) => vec![stop.clone()],
+                    value => array(value, key)?
+                        .iter()
+                        .enumerate()
+                        .map(|(i, stop)| Ok(string(stop, &format!("stop[{i}]"))?.to_string()))
+                        .collect::<Result<_, Error>>()?,
+                })
+            }
+            "response_format" => response_format(value, &mut config, &mut warnings)?,
+            "tools" => converted.tools = tools(value, &mut warnings)?,
+            "tool_choice" => converted.tool_config = Some(tool_choice(value, &mut warnings)?),
+            // Whether to stream is chosen by the method sending the request
+            "stream" | "stream_options" => {}
+            _ => warnings.drop_field(key, value),
+        }
+    }
+    let is_empty = serde_json::to_value(&config).is_ok_and(|config| config == json!({}));
+    converted.generation_config = (!is_empty).then_some(config);
+
+    Ok(warnings.finish(converted))
+}
+
+/// Converts a [`GenerateContentRequest`] into an OpenAI chat-completions request body.
+///
+/// The body has no `model`, which is up to the caller.
+pub fn to_chat_completion_request(request: &GenerateContentRequest) -> Converted<Value> {
+    let mut warnings = Warnings::default();
+    let mut messages = Vec::new();
+    if let Some(system) = &request.system_instruction {
+        for (j, part) in system.parts.iter().flatten().enumerate() {
+            match part {
+                Part::Text {
+                    text,
+                    thought: None | Some(false),
+                    ..
+                } => messages.push(json!({ "role": "system", "content": text })),
+                _ => warnings.dropped(format!("systemInstruction.parts[{j}]")),
+            }
+        }
+    }
+    let mut ids = ToolCallIds::default();
+    for (i, content) in request.contents.iter().enumerate() {
+        let path = format!("contents[{i}]");
+        match content.role {
+            Some(Role::Model) => {
+                messages.push(assistant_message(content, &path, &mut ids, &mut warnings))
+            }
+            _ => messages.extend(user_messages(content, &path, &mut ids, &mut warnings)),
+        }
+    }
+
+    let mut converted = Map::new();
+    converted.insert("messages".into(), Value::Array(messages));
+    if let Some(config) = &request.generation_config {
+        generation_config(config, &mut converted, &mut warnings);
+    }
+    if let Some(tools) = &request.tools {
+        let tools = openai_tools(tools, &mut warnings);
+        if !tools.is_empty() {
+            converted.insert("tools".into(), Value::Array(tools));
+        }
+    }
+    if let Some(tool_config) = &request.tool_config {
+        if let Some(config) = &tool_config.function_calling_config {
+            let choice = match config.mode {
+                FunctionCallingMode::Auto => "auto",
+                FunctionCallingMode::Any => "required",
+                FunctionCallingMode::None => "none",
+            };
+            converted.insert("tool_choice".into(), choice.into());
+        }
+        if tool_config.retrieval_config.is_some() {
+            warnings.dropped("toolConfig.retrieval_config");
+        }
+    }
+    if request.safety_settings.is_some() {
+        warnings.dropped("safetySettings");
+    }
+    if request.cached_content.is_some() {
+        warnings.dropped("cachedContent");
+    }
+
+    warnings.finish(Value::Object(converted))
+}
+
+/// Converts a [`GenerationResponse`] into an OpenAI chat-completions response body
+pub fn to_chat_completion_response(response: &GenerationResponse) -> Converted<Value> {
+    let mut warnings = Warnings::default();
+    let mut ids = ToolCallIds::default();
+    let choices: Vec<Value> = response
+        .candidates
+        .iter()
+        .enumerate()
+        .map(|(i, candidate)| choice(candidate, i, &mut ids, &mut warnings))
+        .collect();
+
+    let mut converted = json!({ "object": "chat.completion", "choices": choices });
+    if let Some(id) = &response.response_id {
+        converted["id"] = id.as_str().into();
+    }
+    if let Some(model) = &response.model_version {
+        converted["model"] = model.as_str().into();
+    }
+    if let Some(usage) = &response.usage_metadata {
+        converted["usage"] = openai_usage(usage, &mut warnings);
+    }
+    if response.prompt_feedback.is_some() {
+        warnings.dropped("promptFeedback");
+    }
+
+    warnings.finish(converted)
+}
+
+/// Converts an OpenAI chat-completions response body into a [`GenerationResponse`]
+pub fn from_chat_completion_response(
+    response: Value,
+) -> Result<Converted<GenerationResponse>, Error> {
+    let response = object(&response, "response")?;
+    let mut warnings = Warnings::default();
+    let mut converted = GenerationResponse {
+        candidates: Vec::new(),
+        prompt_feedback: None,
+        usage_metadata: None,
+        model_version: None,
+        response_id: None,
+        continuations: 0,
+        modality_downgraded: false,
+        attempts: Default::default(),
+        meta: Default::default(),
+        tool_transcript: Vec::new(),
+    };
+    for (key, value) in response {
+        match key.as_str() {
+            _ if value.is_null() => {}
+            "id" => converted.response_id = Some(string(value, key)?.to_string()),
+            "model" => converted.model_version = Some(string(value, key)?.to_string()),
+            "choices" => {
+                for (i, choice) in array(value, key)?.iter().enumerate() {
+                    let candidate = candidate(choice, &format!("choices[{i}]"), &mut warnings)?;
+                    converted.candidates.push(candidate);
+                }
+            }
+            "usage" => converted.usage_metadata = Some(usage(value, &mut warnings)?),
+            // The type tag of the body
+            "object" => {}
+            _ => warnings.drop_field(key, value),
+        }
+    }
+
+    Ok(warnings.finish(converted))
+}
+
+#[derive(Default)]
+struct Warnings(Vec<Warning>);
+
+impl Warnings {
+    fn dropped(&mut self, path: impl Into<String>) {
+        self.0.push(Warning::Dropped { path: path.into() });
+    }
+
+    fn approximated(&mut self, path: impl Into<String>, detail: impl Into<String>) {
+        self.0.push(Warning::Approximated {
+            path: path.into(),
+            detail: detail.into(),
+        });
+    }
+
+    /// Reports a field without an equivalent, unless it carries no data
+    fn drop_field(&mut self, path: impl Into<String>, value: &Value) {
+        if carries_data(value) {
+            self.dropped(path);
+        }
+    }
+
+    fn finish<T>(self, value: T) -> Converted<T> {
+        Converted {
+            value,
+            warnings: self.0,
+        }
+    }
+}
+
+/// Whether a value is more than an explicit default such as `null`, `false`, `0` or `[]`
+fn carries_data(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(fields) => fields.values().any(carries_data),
+    }
+}
+
+/// Ids for tool calls and responses that have none, pairing responses with their calls
+#[derive(Default)]
+struct ToolCallIds {
+    generated: usize,
+    /// Calls without a response yet, as id and function name
+    open: Vec<(String, String)>,
+}
+
+impl ToolCallIds {
+    fn generate(&mut self) -> String {
+        self.generated += 1;
+        format!("call_{}", self.generated)
+    }
+
+    fn call(&mut self, call: &FunctionCall) -> String {
+        let id = call.id.clone().unwrap_or_else(|| self.generate());
+        self.open.push((id.clone(), call.name.clone()));
+        id
+    }
+
+    fn response(&mut self, response: &FunctionResponse) -> String {
+        let position = match &response.id {
+            Some(id) => self.open.iter().position(|(open, _)| open == id),
+            None => self
+                .open
+                .iter()
+                .position(|(_, name)| *name == response.name),
+        };
+        match position {
+            Some(position) => self.open.remove(position).0,
+            None => response.id.clone().unwrap_or_else(|| self.generate()),
+        }
+    }
+}
+
+fn get<'a>(object: &'a Map<String, Value>, key: &str) -> &'a Value {
+    object.get(key).unwrap_or(&Value::Null)
+}
+
+fn object<'a>(value: &'a Value, path: &str) -> Result<&'a Map<String, Value>, Error> {
+    value.as_object().context(InvalidSnafu {
+        path,
+        expected: "an object",
+    })
+}
+
+fn array<'a>(value: &'a Value, path: &str) -> Result<&'a [Value], Error> {
+    value.as_array().map(Vec::as_slice).context(InvalidSnafu {
+        path,
+        expected: "an array",
+    })
+}
+
+fn string<'a>(value: &'a Value, path: &str) -> Result<&'a str, Error> {
+    value.as_str().context(InvalidSnafu {
+        path,
+        expected: "a string",
+    })
+}
+
+fn number(value: &Value, path: &str) -> Result<f32, Error> {
+    value
+        .as_f64()
+        .map(|number| number as f32)
+        .context(InvalidSnafu {
+            path,
+            expected: "a number",
+        })
+}
+
+fn integer(value: &Value, path: &str) -> Result<i32, Error> {
+    value
+        .as_i64()
+        .and_then(|number| number.try_into().ok())
+        .context(InvalidSnafu {
+            path,
+            expected: "a 32-bit integer",
+        })
+}
+
+/// An integer field that may be missing
+fn optional_integer(
+    object: &Map<String, Value>,
+    key: &str,
+    path: &str,
+) -> Result<Option<i32>, Error> {
+    match get(object, key) {
+        Value::Null => Ok(None),
+        value => integer(value, &format!("{path}.{key}")).map(Some),
+    }
+}
+
+/// A float as the number it was written as, rather than its nearest `f64`
+fn f32_value(value: f32) -> Value {
+    value
+        .to_string()
+        .parse::<f64>()
+        .map_or(Value::Null, Value::from)
+}
+
+fn text_part(text: impl Into<String>) -> Part {
+    Part::Text {
+        text: text.into(),
+        thought: None,
+        thought_signature: None,
+    }
+}
+
+fn messages(
+    messages: &[Value],
+    request: &mut GenerateContentRequest,
+    warnings: &mut Warnings,
+) -> Result<(), Error> {
+    let mut system = Vec::new();
+    // Function names of the tool calls by id, for the tool messages answering them
+    let mut calls = HashMap::new();
+    for (i, message) in messages.iter().enumerate() {
+        let path = format!("messages[{i}]");
+        let message = object(message, &path)?;
+        let role_path = format!("{path}.role");
+        let content_path = format!("{path}.content");
+        let content = get(message, "content");
+        let known: &[&str] = match string(get(message, "role"), &role_path)? {
+            role @ ("system" | "developer") => {
+                if role == "developer" {
+                    warnings.approximated(&role_path, "converted to a system instruction");
+                }
+                if !request.contents.is_empty() {
+                    warnings.approximated(&path, "moved into the system instruction");
+                }
+                system.extend(parts(content, &content_path, false, warnings)?);
+                &["role", "content"]
+            }
+            "user" => {
+                let parts = parts(content, &content_path, true, warnings)?;
+                push(request, Role::User, parts);
+                &["role", "content"]
+            }
+            "assistant" => {
+                let mut parts = parts(content, &content_path, false, warnings)?;
+                let tool_calls = get(message, "tool_calls");
+                if !tool_calls.is_null() {
+                    let tool_calls_path = format!("{path}.tool_calls");
+                    for (j, call) in array(tool_calls, &tool_calls_path)?.iter().enumerate() {
+                        let function_call = tool_call(call, &format!("{tool_calls_path}[{j}]"))?;
+                        let id = function_call.id.clone().unwrap_or_default();
+                        calls.insert(id, function_call.name.clone());
+                        parts.push(Part::FunctionCall {
+                            function_call,
+                            thought_signature: None,
+                        });
+                    }
+                }
+                push(request, Role::Model, parts);
+                &["role", "content", "tool_calls"]
+            }
+            "tool" => {
+                let id_path = format!("{path}.tool_call_id");
+                let id = string(get(message, "tool_call_id"), &id_path)?;
+                let name = calls
+                    .get(id)
+                    .context(UnknownToolCallSnafu { path: id_path, id })?;
+                let text: String = parts(content, &content_path, false, warnings)?
+                    .into_iter()
+                    .filter_map(|part| match part {
+                        Part::Text { text, .. } => Some(text),
+                        _ => None,
+                    })
+                    .collect();
+                // The API requires the response to be an object
+                let response = match serde_json::from_str(&text) {
+                    Ok(Value::Object(object)) => Value::Object(object),
+                    _ => json!({ "content": text }),
+                };
+                let part = Part::FunctionResponse {
+                    function_response: FunctionResponse {
+                        id: Some(id.to_string()),
+                        ..FunctionResponse::new(name.clone(), response)
+                    },
+                };
+                // Results of parallel calls are answered in a single content
+                match request.contents.last_mut() {
+                    Some(last) if is_function_responses(last) => {
+                        last.parts.get_or_insert_with(Vec::new).push(part)
+                    }
+                    _ => push(request, Role::User, vec![part]),
+                }
+                &["role", "content", "tool_call_id"]
+            }
+            _ => {
+                return InvalidSnafu {
+                    path: role_path,
+                    expected: "one of `system`, `developer`, `user`, `assistant` or `tool`",
+                }
+                .fail()
+            }
+        };
+        for (key, value) in message {
+            if !known.contains(&key.as_str()) {
+                warnings.drop_field(format!("{path}.{key}"), value);
+            }
+        }
+    }
+    if !system.is_empty() {
+        request.system_instruction = Some(Content {
+            parts: Some(system),
+            role: None,
+        });
+    }
+    Ok(())
+}
+
+fn push(request: &mut GenerateContentRequest, role: Role, parts: Vec<Part>) {
+    request.contents.push(Content {
+        parts: Some(parts),
+        role: Some(role),
+    });
+}
+
+fn is_function_responses(content: &Content) -> bool {
+    content.role == Some(Role::User)
+        && content.parts.as_ref().is_some_and(|parts| {
+            !parts.is_empty()
+                && parts
+                    .iter()
+                    .all(|part| matches!(part, Part::FunctionResponse { .. }))
+        })
+}
+
+/// Converts message content; images and audio are only allowed if `media` is set
+fn parts(
+    content: &Value,
+    path: &str,
+    media: bool,
+    warnings: &mut Warnings,
+) -> Result<Vec<Part>, Error> {
+    let items = match content {
+        Value::Null => return Ok(Vec::new()),
+        Value::String(text) => return Ok(vec![text_part(text)]),
+        Value::Array(items) => items,
+        _ => {
+            return InvalidSnafu {
+                path,
+                expected: "a string or an array of content parts",
+            }
+            .fail()
+        }
+    };
+    let mut parts = Vec::new();
+    for (j, item) in items.iter().enumerate() {
+        let path = format!("{path}[{j}]");
+        let item = object(item, &path)?;
+        match string(get(item, "type"), &format!("{path}.type"))? {
+            "text" => parts.push(text_part(string(
+                get(item, "text"),
+                &format!("{path}.text"),
+            )?)),
+            "image_url" if media => parts.push(image(
+                get(item, "image_url"),
+                &format!("{path}.image_url"),
+                warnings,
+            )?),
+            "input_audio" if media => {
+                let audio_path = format!("{path}.input_audio");
+                let audio = object(get(item, "input_audio"), &audio_path)?;
+                let format = string(get(audio, "format"), &format!("{audio_path}.format"))?;
+                let data = string(get(audio, "data"), &format!("{audio_path}.data"))?;
+                parts.push(Part::InlineData {
+                    inline_data: Blob::new(format!("audio/{format}"), data),
+                });
+            }
+            _ => warnings.dropped(path),
+        }
+    }
+    Ok(parts)
+}
+
+fn image(value: &Value, path: &str, warnings: &mut Warnings) -> Result<Part, Error> {
+    let image = object(value, path)?;
+    let url = string(get(image, "url"), &format!("{path}.url"))?;
+    for (key, value) in image {
+        if key != "url" {
+            warnings.drop_field(format!("{path}.{key}"), value);
+        }
+    }
+    let data_url = url
+        .strip_prefix("data:")
+        .and_then(|url| url.split_once(";base64,"));
+    Ok(match data_url {
+        Some((mime_type, data)) => Part::InlineData {
+            inline_data: Blob::new(mime_type, data),
+        },
+        None => Part::FileData {
+            file_data: FileData {
+                mime_type: None,
+                file_uri: url.to_string(),
+            },
+            video_metadata: None,
+        },
+    })
+}
+
+fn tool_call(value: &Value, path: &str) -> Result<FunctionCall, Error> {
+    let call = object(value, path)?;
+    let id = string(get(call, "id"), &format!("{path}.id"))?;
+    let function_path = format!("{path}.function");
+    let function = object(get(call, "function"), &function_path)?;
+    let name = string(get(function, "name"), &format!("{function_path}.name"))?;
+    let arguments_path = format!("{function_path}.arguments");
+    let arguments = string(get(function, "arguments"), &arguments_path)?;
+    let args = serde_json::from_str(arguments).context(ArgumentsSnafu {
+        path: arguments_path,
+    })?;
+    Ok(FunctionCall {
+        id: Some(id.to_string()),
+        ..FunctionCall::new(name, args)
+    })
+}
+
+fn tools(value: &Value, warnings: &mut Warnings) -> Result<Option<Vec<Tool>>, Error> {
+    let mut declarations = Vec::new();
+    for (i, tool) in array(value, "tools")?.iter().enumerate() {
+        let path = format!("tools[{i}]");
+        let tool = object(tool, &path)?;
+        if get(tool, "type") != "function" {
+            warnings.dropped(path);
+            continue;
+        }
+        let function_path = format!("{path}.function");
+        let function = object(get(tool, "function"), &function_path)?;
+        let name = string(get(function, "name"), &format!("{function_path}.name"))?;
+        let mut declaration = FunctionDeclaration::new(name, "", None);
+        for (key, value) in function {
+            let field_path = format!("{function_path}.{key}");
+            match key.as_str() {
+                _ if value.is_null() => {}
+                "name" => {}
+                "description" => declaration.description = string(value, &field_path)?.into(),
+                "parameters" => declaration.parameters = Some(value.clone()),
+                _ => warnings.drop_field(field_path, value),
+            }
+        }
+        declarations.push(declaration);
+    }
+    Ok((!declarations.is_empty()).then(|| vec![Tool::with_functions(declarations)]))
+}
+
+fn tool_choice(value: &Value, warnings: &mut Warnings) -> Result<ToolConfig, Error> {
+    let mode = match value.as_str() {
+        Some("auto") => FunctionCallingMode::Auto,
+        Some("none") => FunctionCallingMode::None,
+        Some("required") => FunctionCallingMode::Any,
+        _ if value.get("type").is_some_and(|kind| kind == "function") => {
+            warnings.approximated(
+                "tool_choice",
+                "a specific function cannot be forced; any function may be called",
+            );
+            FunctionCallingMode::Any
+        }
+        _ => {
+            return InvalidSnafu {
+                path: "tool_choice",
+                expected: "`auto`, `none`, `required` or a function",
+            }
+            .fail()
+        }
+    };
+    Ok(ToolConfig {
+        function_calling_config: Some(FunctionCallingConfig { mode }),
+        ..Default::default()
+    })
+}
+
+fn response_format(
+    value: &Value,
+    config: &mut GenerationConfig,
+    warnings: &mut Warnings,
+) -> Result<(), Error> {
+    let format = object(value, "response_format")?;
+    match string(get(format, "type"), "response_format.type")? {
+        "text" => {}
+        "json_object" => config.response_mime_type = Some("application/json".into()),
+        "json_schema" => {
+            let schema = object(get(format, "json_schema"), "response_format.json_schema")?;
+            config.response_mime_type = Some("application/json".into());
+            for (key, value) in schema {
+                match key.as_str() {
+                    "schema" => config.response_schema = Some(value.clone()),
+                    _ => warnings.drop_field(format!("response_format.json_schema.{key}"), value),
+                }
+            }
+        }
+        _ => {
+            return InvalidSnafu {
+                path: "response_format.type",
+                expected: "`text`, `json_object` or `json_schema`",
+            }
+            .fail()
+        }
+    }
+    Ok(())
+}
+
+fn assistant_message(
+    content: &Content,
+    path: &str,
+    ids: &mut ToolCallIds,
+    warnings: &mut Warnings,
+) -> Value {
+    let mut text = String::new();
+    let mut tool_calls = Vec::new();
+    for (j, part) in content.parts.iter().flatten().enumerate() {
+        let part_path = format!("{path}.parts[{j}]");
+        match part {
+            Part::Text {
+                text: part_text,
+                thought: None | Some(false),
+                thought_signature,
+            } => {
+                text.push_str(part_text);
+                if thought_signature.is_some() {
+                    warnings.dropped(format!("{part_path}.thoughtSignature"));
+                }
+            }
+            Part::FunctionCall {
+                function_call,
+                thought_signature,
+            } => {
+                if thought_signature.is_some() || function_call.thought_signature.is_some() {
+                    warnings.dropped(format!("{part_path}.thoughtSignature"));
+                }
+                tool_calls.push(json!({
+                    "id": ids.call(function_call),
+                    "type": "function",
+                    "function": {
+                        "name": function_call.name,
+                        "arguments": function_call.args.to_string(),
+                    },
+                }));
+            }
+            _ => warnings.dropped(part_path),
+        }
+    }
+    let text = (!text.is_empty()).then_some(text);
+    let mut message = json!({ "role": "assistant", "content": text });
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = Value::Array(tool_calls);
+    }
+    message
+}
+
+/// A user content becomes a tool message per function response, then a user message
+fn user_messages(
+    content: &Content,
+    path: &str,
+    ids: &mut ToolCallIds,
+    warnings: &mut Warnings,
+) -> Vec<Value> {
+    let mut messages = Vec::new();
+    let mut parts = Vec::new();
+    for (j, part) in content.parts.iter().flatten().enumerate() {
+        let part_path = format!("{path}.parts[{j}]");
+        match part {
+            Part::Text {
+                text,
+                thought: None | Some(false),
+                ..
+            } => parts.push(json!({ "type": "text", "text": text })),
+            Part::InlineData { inline_data } => match inline_data.mime_type.split_once('/') {
+                Some(("image", _)) => parts.push(json!({
+                    "type": "image_url",
+                    "image_url": {
+                        "url": format!("data:{};base64,{}", inline_data.mime_type, inline_data.data),
+                    },
+                })),
+                Some(("audio", format)) => parts.push(json!({
+                    "type": "input_audio",
+                    "input_audio": { "data": inline_data.data, "format": format },
+                })),
+                _ => warnings.dropped(part_path),
+            },
+            Part::FileData { file_data, .. } => {
+                let mime_type = file_data.mime_type.as_deref();
+                if mime_type.is_some_and(|mime_type| !mime_type.starts_with("image/")) {
+                    warnings.approximated(part_path, "referenced as an image URL");
+                }
+                parts.push(json!({
+                    "type": "image_url",
+                    "image_url": { "url": file_data.file_uri },
+                }));
+            }
+            Part::FunctionResponse { function_response } => messages.push(json!({
+                "role": "tool",
+                "tool_call_id": ids.response(function_response),
+                "content": tool_result(function_response),
+            })),
+            _ => warnings.dropped(part_path),
+        }
+    }
+    if !parts.is_empty() {
+        let content = if parts.len() == 1 && parts[0]["type"] == "text" {
+            parts[0]["text"].clone()
+        } else {
+            Value::Array(parts)
+        };
+        messages.push(json!({ "role": "user", "content": content }));
+    }
+    messages
+}
+
+/// The content of a tool message: the text of a `{"content": "..."}` response, otherwise the
+/// response as JSON
+fn tool_result(response: &FunctionResponse) -> String {
+    let Some(value) = &response.response else {
+        return String::new();
+    };
+    let text = value
+        .as_object()
+        .filter(|object| object.len() == 1)
+        .and_then(|object| object.get("content")?.as_str());
+    match text {
+        Some(text) => text.to_string(),
+        None => value.to_string(),
+    }
+}
+
+fn generation_config(
+    config: &GenerationConfig,
+    request: &mut Map<String, Value>,
+    warnings: &mut Warnings,
+) {
+    let Ok(Value::Object(fields)) = serde_json::to_value(config) else {
+        return;
+    };
+    for (key, value) in fields {
+        let (field, value) = match key.as_str() {
+            "temperature" => ("temperature", config.temperature.map_or(value, f32_value)),
+            "topP" => ("top_p", config.top_p.map_or(value, f32_value)),
+            "maxOutputTokens" => ("max_tokens", value),
+            "candidateCount" => ("n", value),
+            "stopSequences" => ("stop", value),
+            "seed" => ("seed", value),
+            "responseMimeType" | "responseSchema" => continue,
+            _ => {
+                warnings.dropped(format!("generationConfig.{key}"));
+                continue;
+            }
+        };
+        request.insert(field.into(), value);
+    }
+
+    match config.response_mime_type.as_deref() {
+        Some("application/json") => {
+            let format = match &config.response_schema {
+                Some(schema) => json!({
+                    "type": "json_schema",
+                    "json_schema": { "name": "response", "schema": schema },
+                }),
+                None => json!({ "type": "json_object" }),
+            };
+            request.insert("response_format".into(), format);
+        }
+        None | Some("text/plain") => {
+            if config.response_schema.is_some() {
+                warnings.dropped("generationConfig.responseSchema");
+            }
+        }
+        Some(_) => warnings.dropped("generationConfig.responseMimeType"),
+    }
+}
+
+fn openai_tools(tools: &[Tool], warnings: &mut Warnings) -> Vec<Value> {
+    let mut converted = Vec::new();
+    for (i, tool) in tools.iter().enumerate() {
+        let Tool::Function {
+            function_declarations,
+        } = tool
+        else {
+            warnings.dropped(format!("tools[{i}]"));
+            continue;
+        };
+        for (j, declaration) in function_declarations.iter().enumerate() {
+            let path = format!("tools[{i}].function_declarations[{j}]");
+            let mut function = json!({ "name": declaration.name });
+            if !declaration.description.is_empty() {
+                function["description"] = declaration.description.as_str().into();
+            }
+            if let Some(parameters) = &declaration.parameters {
+                function["parameters"] = parameters.clone();
+            }
+            if declaration.behavior.is_some() {
+                warnings.dropped(format!("{path}.behavior"));
+            }
+            if declaration.response.is_some() {
+                warnings.dropped(format!("{path}.response"));
+            }
+            converted.push(json!({ "type": "function", "function": function }));
+        }
+    }
+    converted
+}
+
+fn choice(
+    candidate: &Candidate,
+    position: usize,
+    ids: &mut ToolCallIds,
+    warnings: &mut Warnings,
+) -> Value {
+    let path = format!("candidates[{position}]");
+    let message = assistant_message(
+        &candidate.content,
+        &format!("{path}.content"),
+        ids,
+        warnings,
+    );
+    let finish_reason = candidate.finish_reason.as_ref().map(|reason| {
+        let has_tool_calls = message.get("tool_calls").is_some();
+        openai_finish_reason(
+            reason,
+            has_tool_calls,
+            &format!("{path}.finishReason"),
+            warnings,
+        )
+    });
+    if let Ok(Value::Object(fields)) = serde_json::to_value(candidate) {
+        for (key, value) in fields {
+            if !matches!(key.as_str(), "content" | "finishReason" | "index") {
+                warnings.drop_field(format!("{path}.{key}"), &value);
+            }
+        }
+    }
+    json!({
+        "index": candidate.index.unwrap_or(position as i32),
+        "message": message,
+        "finish_reason": finish_reason,
+    })
+}
+
+fn openai_finish_reason(
+    reason: &FinishReason,
+    has_tool_calls: bool,
+    path: &str,
+    warnings: &mut Warnings,
+) -> &'static str {
+    let (converted, exact) = match reason {
+        FinishReason::Stop if has_tool_calls => ("tool_calls", true),
+        FinishReason::Stop => ("stop", true),
+        FinishReason::MaxTokens => ("length", true),
+        FinishReason::Safety => ("content_filter", true),
+        FinishReason::Recitation
+        | FinishReason::Blocklist
+        | FinishReason::ProhibitedContent
+        | FinishReason::Spii
+        | FinishReason::ImageSafety => ("content_filter", false),
+        _ => ("stop", false),
+    };
+    if !exact {
+        warnings.approximated(path, format!("{reason:?} reported as `{converted}`"));
+    }
+    converted
+}
+
+fn openai_usage(usage: &UsageMetadata, warnings: &mut Warnings) -> Value {
+    let prompt_tokens = usage.prompt_token_count.unwrap_or_default();
+    // OpenAI counts reasoning as part of the completion
+    let completion_tokens = usage.candidates_token_count.unwrap_or_default()
+        + usage.thoughts_token_count.unwrap_or_default();
+    let mut converted = json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": usage.total_token_count.unwrap_or(prompt_tokens + completion_tokens),
+    });
+    if let Some(reasoning_tokens) = usage.thoughts_token_count {
+        converted["completion_tokens_details"] = json!({ "reasoning_tokens": reasoning_tokens });
+    }
+    if let Some(cached_tokens) = usage.cached_content_token_count {
+        converted["prompt_tokens_details"] = json!({ "cached_tokens": cached_tokens });
+    }
+    if let Ok(Value::Object(fields)) = serde_json::to_value(usage) {
+        for (key, value) in fields {
+            let converted = matches!(
+                key.as_str(),
+                "promptTokenCount"
+                    | "candidatesTokenCount"
+                    | "totalTokenCount"
+                    | "thoughtsTokenCount"
+                    | "cachedContentTokenCount"
+            );
+            if !converted {
+                warnings.drop_field(format!("usageMetadata.{key}"), &value);
+            }
+        }
+    }
+    converted
+}
+
+fn candidate(value: &Value, path: &str, warnings: &mut Warnings) -> Result<Candidate, Error> {
+    let choice = object(value, path)?;
+    let mut candidate = Candidate {
+        content: Content {
+            parts: None,
+            role: Some(Role::Model),
+        },
+        safety_ratings: None,
+        citation_metadata: None,
+        grounding_metadata: None,
+        finish_reason: None,
+        index: None,
+        avg_logprobs: None,
+        url_context_metadata: None,
+        finish_message: None,
+    };
+    for (key, value) in choice {
+        let field_path = format!("{path}.{key}");
+        match key.as_str() {
+            _ if value.is_null() => {}
+            "index" => candidate.index = Some(integer(value, &field_path)?),
+            "message" => candidate.content.parts = Some(message(value, &field_path, warnings)?),
+            "finish_reason" => {
+                let reason = string(value, &field_path)?;
+                candidate.finish_reason = Some(finish_reason(reason, &field_path, warnings));
+            }
+            _ => warnings.drop_field(field_path, value),
+        }
+    }
+    Ok(candidate)
+}
+
+/// The parts of a response message
+fn message(value: &Value, path: &str, warnings: &mut Warnings) -> Result<Vec<Part>, Error> {
+    let message = object(value, path)?;
+    let mut parts = parts(
+        get(message, "content"),
+        &format!("{path}.content"),
+        false,
+        warnings,
+    )?;
+    let tool_calls = get(message, "tool_calls");
+    if !tool_calls.is_null() {
+        let tool_calls_path = format!("{path}.tool_calls");
+        for (j, call) in array(tool_calls, &tool_calls_path)?.iter().enumerate() {
+            parts.push(Part::FunctionCall {
+                function_call: tool_call(call, &format!("{tool_calls_path}[{j}]"))?,
+                thought_signature: None,
+            });
+        }
+    }
+    for (key, value) in message {
+        if !matches!(key.as_str(), "role" | "content" | "tool_calls") {
+            warnings.drop_field(format!("{path}.{key}"), value);
+        }
+    }
+    Ok(parts)
+}
+
+fn finish_reason(reason: &str, path: &str, warnings: &mut Warnings) -> FinishReason {
+    match reason {
+        "stop" | "tool_calls" | "function_call" => FinishReason::Stop,
+        "length" => FinishReason::MaxTokens,
+        "content_filter" => FinishReason::Safety,
+        _ => {
+            warnings.approximated(path, format!("`{reason}` reported as OTHER"));
+            FinishReason::Other
+        }
+    }
+}
+
+fn usage(value: &Value, warnings: &mut Warnings) -> Result<UsageMetadata, Error> {
+    let usage = object(value, "usage")?;
+    let mut details = |key: &str, converted: &str| -> Result<Option<i32>, Error> {
+        let path = format!("usage.{key}");
+        let details = match get(usage, key) {
+            Value::Null => return Ok(None),
+            value => object(value, &path)?,
+        };
+        for (key, value) in details {
+            if key != converted {
+                warnings.drop_field(format!("{path}.{key}"), value);
+            }
+        }
+        optional_integer(details, converted, &path)
+    };
+    let reasoning_tokens = details("completion_tokens_details", "reasoning_tokens")?;
+    let cached_tokens = details("prompt_tokens_details", "cached_tokens")?;
+    let completion_tokens = optional_integer(usage, "completion_tokens", "usage")?;
+
+    for (key, value) in usage {
+        let converted = matches!(
+            key.as_str(),
+            "prompt_tokens"
+                | "completion_tokens"
+                | "total_tokens"
+                | "completion_tokens_details"
+                | "prompt_tokens_details"
+        );
+        if !converted {
+            warnings.drop_field(format!("usage.{key}"), value);
+        }
+    }
+
+    Ok(UsageMetadata {
+        prompt_token_count: optional_integer(usage, "prompt_tokens", "usage")?,
+        candidates_token_count: completion_tokens
+            .map(|tokens| tokens - reasoning_tokens.unwrap_or_default()),
+        total_token_count: optional_integer(usage, "total_tokens", "usage")?,
+        thoughts_token_count: reasoning_tokens,
+        prompt_tokens_details: None,
+        cached_content_token_count: cached_tokens,
+        cache_tokens_details: None,
+        candidates_tokens_details: None,
+        tool_use_prompt_token_count: None,
+        tool_use_prompt_tokens_details: None,
+    })
+}
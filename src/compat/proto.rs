@@ -0,0 +1,677 @@
+//! Conversion between [`Content`](crate::Content) and the protobuf messages of the Generative
+//! Language API, for moving contents to and from the protobuf-based Google Cloud SDKs.
+//!
+//! The types below mirror `google.ai.generativelanguage.v1beta.Content` and the messages it
+//! nests, field numbers included, so they encode and decode with [`prost::Message`] to the
+//! same bytes as the SDKs' generated types. They are mirrors rather than the SDK's own
+//! types so that the `proto` feature only pulls in `prost`: move a content across by
+//! encoding it on one side and decoding it on the other.
+//!
+//! ```
+//! use gemini_rust::{compat::proto, Content};
+//! use prost::Message;
+//!
+//! let bytes = proto::Content::try_from(Content::text("Hello"))?.encode_to_vec();
+//! let content = Content::from(proto::Content::decode(bytes.as_slice())?);
+//! assert_eq!(content, Content::text("Hello"));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Text, inline data, file data, function calls and responses, executable code and code
+//! execution results are converted in both directions. Where the two sides differ:
+//!
+//! - Inline data and thought signatures are base64 text in [`Blob`](crate::Blob) and bytes in
+//!   protobuf, so converting a content whose text is not valid base64 fails with
+//!   [`Error::InvalidBase64`].
+//! - Function arguments and responses are `google.protobuf.Struct`s, so only JSON objects
+//!   convert, and numbers are doubles: integral numbers come back as integers.
+//! - Enum values this crate does not know, such as a new code execution outcome, are kept as
+//!   their number in the `Unknown` variants of [`Language`](crate::Language) and
+//!   [`Outcome`](crate::Outcome), and sent back as that number.
+//! - Video metadata is kept on file data parts only, and thoughts on text parts only.
+//!
+//! Fields that [`Content`] and [`Part`] do not declare, e.g. ones added to the API after
+//! this crate, are kept as read in their [`UnknownFields`] and encoded again. Since
+//! [`crate::Content`] has nowhere to hold them, [`Content::split()`] hands them out as an
+//! [`Attachment`] that [`Content::join()`] puts back, along with parts of kinds these types
+//! do not mirror. The plain conversions drop them. Unknown fields of the nested messages,
+//! and unknown groups, are dropped by prost.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use prost::{
+    bytes::{Buf, BufMut},
+    encoding::{self, wire_type::WireType, DecodeContext},
+    DecodeError,
+};
+use prost_types::{value::Kind, ListValue, Struct};
+use serde_json::{Map, Number, Value};
+use snafu::{ResultExt, Snafu};
+
+use crate::generation::video::{format_offset, parse_offset};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("`{path}` is not valid base64"))]
+    InvalidBase64 {
+        path: String,
+        source: base64::DecodeError,
+    },
+}
+
+/// Encoded fields a message does not declare, kept to be encoded again as they were read
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UnknownFields(Vec<u8>);
+
+impl UnknownFields {
+    /// The fields, encoded
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Whether there are none
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Reads a field with the given tag, keeping it unless it is a group
+    fn merge(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut impl Buf,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        match wire_type {
+            WireType::Varint => {
+                let mut value = 0;
+                encoding::uint64::merge(wire_type, &mut value, buf, ctx)?;
+                encoding::uint64::encode(tag, &value, &mut self.0);
+            }
+            WireType::SixtyFourBit => {
+                let mut value = 0;
+                encoding::fixed64::merge(wire_type, &mut value, buf, ctx)?;
+                encoding::fixed64::encode(tag, &value, &mut self.0);
+            }
+            WireType::ThirtyTwoBit => {
+                let mut value = 0;
+                encoding::fixed32::merge(wire_type, &mut value, buf, ctx)?;
+                encoding::fixed32::encode(tag, &value, &mut self.0);
+            }
+            WireType::LengthDelimited => {
+                let mut value = Vec::new();
+                encoding::bytes::merge(wire_type, &mut value, buf, ctx)?;
+                encoding::bytes::encode(tag, &value, &mut self.0);
+            }
+            WireType::StartGroup | WireType::EndGroup => {
+                encoding::skip_field(wire_type, tag, buf, ctx)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The unknown fields of a [`Content`] and its parts, which [`crate::Content`] cannot hold
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Attachment {
+    /// Unknown fields of the content, including the parts not converted
+    pub content: UnknownFields,
+    /// Unknown fields of each converted part, in order
+    pub parts: Vec<UnknownFields>,
+}
+
+/// A multi-part message, `google.ai.generativelanguage.v1beta.Content`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Content {
+    pub parts: Vec<Part>,
+    /// `user` or `model`, or empty
+    pub role: String,
+    pub unknown_fields: UnknownFields,
+}
+
+impl prost::Message for Content {
+    fn encode_raw(&self, buf: &mut impl BufMut) {
+        encoding::message::encode_repeated(1, &self.parts, buf);
+        if !self.role.is_empty() {
+            encoding::string::encode(2, &self.role, buf);
+        }
+        buf.put_slice(self.unknown_fields.as_bytes());
+    }
+
+    fn merge_field(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut impl Buf,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        match tag {
+            1 => encoding::message::merge_repeated(wire_type, &mut self.parts, buf, ctx),
+            2 => encoding::string::merge(wire_type, &mut self.role, buf, ctx),
+            _ => self.unknown_fields.merge(tag, wire_type, buf, ctx),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        encoding::message::encoded_len_repeated(1, &self.parts)
+            + if self.role.is_empty() {
+                0
+            } else {
+                encoding::string::encoded_len(2, &self.role)
+            }
+            + self.unknown_fields.as_bytes().len()
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// One part of a [`Content`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Part {
+    pub thought: bool,
+    pub thought_signature: Vec<u8>,
+    pub data: Option<part::Data>,
+    pub metadata: Option<part::Metadata>,
+    pub unknown_fields: UnknownFields,
+}
+
+impl prost::Message for Part {
+    fn encode_raw(&self, buf: &mut impl BufMut) {
+        if let Some(data) = &self.data {
+            data.encode(buf);
+        }
+        if self.thought {
+            encoding::bool::encode(11, &self.thought, buf);
+        }
+        if !self.thought_signature.is_empty() {
+            encoding::bytes::encode(13, &self.thought_signature, buf);
+        }
+        if let Some(metadata) = &self.metadata {
+            metadata.encode(buf);
+        }
+        buf.put_slice(self.unknown_fields.as_bytes());
+    }
+
+    fn merge_field(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut impl Buf,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        match tag {
+            2..=6 | 9 | 10 => part::Data::merge(&mut self.data, tag, wire_type, buf, ctx),
+            11 => encoding::bool::merge(wire_type, &mut self.thought, buf, ctx),
+            13 => encoding::bytes::merge(wire_type, &mut self.thought_signature, buf, ctx),
+            14 => part::Metadata::merge(&mut self.metadata, tag, wire_type, buf, ctx),
+            _ => self.unknown_fields.merge(tag, wire_type, buf, ctx),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.data.as_ref().map_or(0, part::Data::encoded_len)
+            + if self.thought {
+                encoding::bool::encoded_len(11, &self.thought)
+            } else {
+                0
+            }
+            + if self.thought_signature.is_empty() {
+                0
+            } else {
+                encoding::bytes::encoded_len(13, &self.thought_signature)
+            }
+            + self
+                .metadata
+                .as_ref()
+                .map_or(0, part::Metadata::encoded_len)
+            + self.unknown_fields.as_bytes().len()
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// The oneofs of [`Part`]
+pub mod part {
+    /// The data of a part
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Data {
+        #[prost(string, tag = "2")]
+        Text(String),
+        #[prost(message, tag = "3")]
+        InlineData(super::Blob),
+        #[prost(message, tag = "4")]
+        FunctionCall(super::FunctionCall),
+        #[prost(message, tag = "5")]
+        FunctionResponse(super::FunctionResponse),
+        #[prost(message, tag = "6")]
+        FileData(super::FileData),
+        #[prost(message, tag = "9")]
+        ExecutableCode(super::ExecutableCode),
+        #[prost(message, tag = "10")]
+        CodeExecutionResult(super::CodeExecutionResult),
+    }
+
+    /// Metadata describing the data of a part
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Metadata {
+        #[prost(message, tag = "14")]
+        VideoMetadata(super::VideoMetadata),
+    }
+}
+
+/// Raw bytes of inline data
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Blob {
+    #[prost(string, tag = "1")]
+    pub mime_type: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub data: Vec<u8>,
+}
+
+/// A reference to data stored elsewhere
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FileData {
+    #[prost(string, tag = "1")]
+    pub mime_type: String,
+    #[prost(string, tag = "2")]
+    pub file_uri: String,
+}
+
+/// A function call predicted by the model
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FunctionCall {
+    #[prost(string, tag = "3")]
+    pub id: String,
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, optional, tag = "2")]
+    pub args: Option<Struct>,
+}
+
+/// The result of a [`FunctionCall`]
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FunctionResponse {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(message, optional, tag = "3")]
+    pub response: Option<Struct>,
+}
+
+/// Code generated by the model for the code execution tool
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExecutableCode {
+    #[prost(enumeration = "executable_code::Language", tag = "1")]
+    pub language: i32,
+    #[prost(string, tag = "2")]
+    pub code: String,
+}
+
+/// The enums of [`ExecutableCode`]
+pub mod executable_code {
+    /// Programming language of the code
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Language {
+        Unspecified = 0,
+        Python = 1,
+    }
+}
+
+/// The result of running [`ExecutableCode`]
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CodeExecutionResult {
+    #[prost(enumeration = "code_execution_result::Outcome", tag = "1")]
+    pub outcome: i32,
+    #[prost(string, tag = "2")]
+    pub output: String,
+}
+
+/// The enums of [`CodeExecutionResult`]
+pub mod code_execution_result {
+    /// How the execution ended
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Outcome {
+        Unspecified = 0,
+        Ok = 1,
+        Failed = 2,
+        DeadlineExceeded = 3,
+    }
+}
+
+/// The clip and frame rate of a video
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct VideoMetadata {
+    #[prost(message, optional, tag = "1")]
+    pub start_offset: Option<prost_types::Duration>,
+    #[prost(message, optional, tag = "2")]
+    pub end_offset: Option<prost_types::Duration>,
+    #[prost(double, tag = "3")]
+    pub fps: f64,
+}
+
+impl Content {
+    /// Converts to a [`crate::Content`], handing out what it cannot hold as an [`Attachment`]
+    ///
+    /// Parts without data are skipped; those with fields left, e.g. of a kind these types do
+    /// not mirror, are kept in [`Attachment::content`].
+    pub fn split(self) -> (crate::Content, Attachment) {
+        let mut attachment = Attachment {
+            content: self.unknown_fields,
+            parts: Vec::new(),
+        };
+        let mut parts = Vec::new();
+        for mut part in self.parts {
+            let unknown_fields = std::mem::take(&mut part.unknown_fields);
+            if part.data.is_none() {
+                part.unknown_fields = unknown_fields;
+                if part != Part::default() {
+                    encoding::message::encode(1, &part, &mut attachment.content.0);
+                }
+                continue;
+            }
+            parts.extend(from_part(part));
+            attachment.parts.push(unknown_fields);
+        }
+        let content = crate::Content {
+            parts: (!parts.is_empty()).then_some(parts),
+            role: match self.role.as_str() {
+                "user" => Some(crate::Role::User),
+                "model" => Some(crate::Role::Model),
+                _ => None,
+            },
+        };
+        (content, attachment)
+    }
+
+    /// Converts a [`crate::Content`], putting back an [`Attachment`] split off before
+    ///
+    /// Parts kept in [`Attachment::content`] come back after the converted ones.
+    pub fn join(content: crate::Content, attachment: Attachment) -> Result<Self, Error> {
+        let mut unknown_fields = attachment.parts.into_iter();
+        let parts = content
+            .parts
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(index, part)| {
+                let mut part = to_part(index, part)?;
+                part.unknown_fields = unknown_fields.next().unwrap_or_default();
+                Ok(part)
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self {
+            parts,
+            role: match content.role {
+                Some(crate::Role::User) => "user".to_string(),
+                Some(crate::Role::Model) => "model".to_string(),
+                None => String::new(),
+            },
+            unknown_fields: attachment.content,
+        })
+    }
+}
+
+impl From<Content> for crate::Content {
+    fn from(content: Content) -> Self {
+        content.split().0
+    }
+}
+
+impl TryFrom<crate::Content> for Content {
+    type Error = Error;
+
+    fn try_from(content: crate::Content) -> Result<Self, Error> {
+        Self::join(content, Attachment::default())
+    }
+}
+
+/// Converts the part with the given index
+fn to_part(index: usize, part: crate::Part) -> Result<Part, Error> {
+    let mut converted = Part::default();
+    let data = match part {
+        crate::Part::Text {
+            text,
+            thought,
+            thought_signature,
+        } => {
+            converted.thought = thought.unwrap_or(false);
+            converted.thought_signature = decode(index, "thoughtSignature", thought_signature)?;
+            part::Data::Text(text)
+        }
+        crate::Part::InlineData { inline_data } => part::Data::InlineData(Blob {
+            data: decode(index, "inlineData.data", Some(inline_data.data))?,
+            mime_type: inline_data.mime_type,
+        }),
+        crate::Part::FileData {
+            file_data,
+            video_metadata,
+        } => {
+            converted.metadata = video_metadata.map(|metadata| {
+                part::Metadata::VideoMetadata(VideoMetadata {
+                    start_offset: metadata.start_offset.as_deref().and_then(duration),
+                    end_offset: metadata.end_offset.as_deref().and_then(duration),
+                    fps: metadata.fps.unwrap_or_default(),
+                })
+            });
+            part::Data::FileData(FileData {
+                mime_type: file_data.mime_type.unwrap_or_default(),
+                file_uri: file_data.file_uri,
+            })
+        }
+        crate::Part::FunctionCall {
+            function_call,
+            thought_signature,
+        } => {
+            converted.thought_signature = decode(
+                index,
+                "thoughtSignature",
+                thought_signature.or(function_call.thought_signature),
+            )?;
+            part::Data::FunctionCall(FunctionCall {
+                id: function_call.id.unwrap_or_default(),
+                name: function_call.name,
+                args: to_struct(function_call.args),
+            })
+        }
+        crate::Part::FunctionResponse { function_response } => {
+            part::Data::FunctionResponse(FunctionResponse {
+                id: function_response.id.unwrap_or_default(),
+                name: function_response.name,
+                response: function_response.response.and_then(to_struct),
+            })
+        }
+        crate::Part::ExecutableCode { executable_code } => {
+            part::Data::ExecutableCode(ExecutableCode {
+                language: match executable_code.language {
+                    crate::Language::Python => executable_code::Language::Python as i32,
+                    crate::Language::Unknown(number) => number.parse().unwrap_or_default(),
+                    _ => executable_code::Language::Unspecified as i32,
+                },
+                code: executable_code.code,
+            })
+        }
+        crate::Part::CodeExecutionResult {
+            code_execution_result,
+        } => {
+            use code_execution_result::Outcome;
+            part::Data::CodeExecutionResult(CodeExecutionResult {
+                outcome: match code_execution_result.outcome {
+                    crate::Outcome::OutcomeOk => Outcome::Ok as i32,
+                    crate::Outcome::OutcomeFailed => Outcome::Failed as i32,
+                    crate::Outcome::OutcomeDeadlineExceeded => Outcome::DeadlineExceeded as i32,
+                    crate::Outcome::Unknown(number) => number.parse().unwrap_or_default(),
+                    _ => Outcome::Unspecified as i32,
+                },
+                output: code_execution_result.output.unwrap_or_default(),
+            })
+        }
+    };
+    converted.data = Some(data);
+    Ok(converted)
+}
+
+/// Converts a part, or `None` if it has no data
+fn from_part(part: Part) -> Option<crate::Part> {
+    let thought_signature =
+        (!part.thought_signature.is_empty()).then(|| encode(&part.thought_signature));
+    Some(match part.data? {
+        part::Data::Text(text) => crate::Part::Text {
+            text,
+            thought: part.thought.then_some(true),
+            thought_signature,
+        },
+        part::Data::InlineData(blob) => crate::Part::InlineData {
+            inline_data: crate::Blob::new(blob.mime_type, encode(&blob.data)),
+        },
+        part::Data::FileData(file_data) => crate::Part::FileData {
+            file_data: crate::FileData {
+                mime_type: (!file_data.mime_type.is_empty()).then_some(file_data.mime_type),
+                file_uri: file_data.file_uri,
+            },
+            video_metadata: part
+                .metadata
+                .map(
+                    |part::Metadata::VideoMetadata(metadata)| crate::VideoMetadata {
+                        start_offset: metadata.start_offset.and_then(offset),
+                        end_offset: metadata.end_offset.and_then(offset),
+                        fps: (metadata.fps != 0.0).then_some(metadata.fps),
+                    },
+                ),
+        },
+        part::Data::FunctionCall(call) => crate::Part::FunctionCall {
+            function_call: crate::FunctionCall {
+                name: call.name,
+                args: call.args.map_or(Value::Null, from_struct),
+                thought_signature: None,
+                id: (!call.id.is_empty()).then_some(call.id),
+            },
+            thought_signature,
+        },
+        part::Data::FunctionResponse(response) => crate::Part::FunctionResponse {
+            function_response: crate::FunctionResponse {
+                name: response.name,
+                response: response.response.map(from_struct),
+                id: (!response.id.is_empty()).then_some(response.id),
+            },
+        },
+        part::Data::ExecutableCode(code) => crate::Part::ExecutableCode {
+            executable_code: crate::ExecutableCode {
+                language: match executable_code::Language::try_from(code.language) {
+                    Ok(executable_code::Language::Unspecified) => {
+                        crate::Language::LanguageUnspecified
+                    }
+                    Ok(executable_code::Language::Python) => crate::Language::Python,
+                    Err(_) => crate::Language::Unknown(code.language.to_string()),
+                },
+                code: code.code,
+            },
+        },
+        part::Data::CodeExecutionResult(result) => {
+            use code_execution_result::Outcome;
+            crate::Part::CodeExecutionResult {
+                code_execution_result: crate::CodeExecutionResult {
+                    outcome: match Outcome::try_from(result.outcome) {
+                        Ok(Outcome::Unspecified) => crate::Outcome::OutcomeUnspecified,
+                        Ok(Outcome::Ok) => crate::Outcome::OutcomeOk,
+                        Ok(Outcome::Failed) => crate::Outcome::OutcomeFailed,
+                        Ok(Outcome::DeadlineExceeded) => crate::Outcome::OutcomeDeadlineExceeded,
+                        Err(_) => crate::Outcome::Unknown(result.outcome.to_string()),
+                    },
+                    output: (!result.output.is_empty()).then_some(result.output),
+                },
+            }
+        }
+    })
+}
+
+fn encode(bytes: &[u8]) -> String {
+    STANDARD.encode(bytes)
+}
+
+/// Decodes the base64 text at `field` of the part with the given index, if any
+fn decode(index: usize, field: &str, text: Option<String>) -> Result<Vec<u8>, Error> {
+    let Some(text) = text else {
+        return Ok(Vec::new());
+    };
+    STANDARD.decode(text).context(InvalidBase64Snafu {
+        path: format!("parts[{index}].{field}"),
+    })
+}
+
+fn duration(offset: &str) -> Option<prost_types::Duration> {
+    let offset = parse_offset(offset).ok()?;
+    Some(prost_types::Duration {
+        seconds: i64::try_from(offset.as_secs()).ok()?,
+        nanos: offset.subsec_nanos() as i32,
+    })
+}
+
+fn offset(duration: prost_types::Duration) -> Option<String> {
+    let duration = std::time::Duration::try_from(duration).ok()?;
+    Some(format_offset(duration))
+}
+
+/// Converts a JSON object, or `None` for any other value
+fn to_struct(value: Value) -> Option<Struct> {
+    match value {
+        Value::Object(object) => Some(Struct {
+            fields: object
+                .into_iter()
+                .map(|(key, value)| (key, to_value(value)))
+                .collect(),
+        }),
+        _ => None,
+    }
+}
+
+fn to_value(value: Value) -> prost_types::Value {
+    let kind = match value {
+        Value::Null => Kind::NullValue(0),
+        Value::Bool(value) => Kind::BoolValue(value),
+        Value::Number(number) => Kind::NumberValue(number.as_f64().unwrap_or_default()),
+        Value::String(value) => Kind::StringValue(value),
+        Value::Array(values) => Kind::ListValue(ListValue {
+            values: values.into_iter().map(to_value).collect(),
+        }),
+        object @ Value::Object(_) => Kind::StructValue(to_struct(object).unwrap_or_default()),
+    };
+    prost_types::Value { kind: Some(kind) }
+}
+
+fn from_struct(value: Struct) -> Value {
+    let object: Map<String, Value> = value
+        .fields
+        .into_iter()
+        .map(|(key, value)| (key, from_value(value)))
+        .collect();
+    Value::Object(object)
+}
+
+/// The largest integer every smaller integer of which a double represents exactly
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+fn from_value(value: prost_types::Value) -> Value {
+    match value.kind {
+        None | Some(Kind::NullValue(_)) => Value::Null,
+        Some(Kind::BoolValue(value)) => Value::Bool(value),
+        Some(Kind::NumberValue(number))
+            if number.fract() == 0.0 && number.abs() <= MAX_SAFE_INTEGER =>
+        {
+            Value::Number((number as i64).into())
+        }
+        Some(Kind::NumberValue(number)) => {
+            Number::from_f64(number).map_or(Value::Null, Value::Number)
+        }
+        Some(Kind::StringValue(value)) => Value::String(value),
+        Some(Kind::ListValue(list)) => {
+            Value::Array(list.values.into_iter().map(from_value).collect())
+        }
+        Some(Kind::StructValue(value)) => from_struct(value),
+    }
+}
@@ -0,0 +1,81 @@
+//! Name resolution for restricted egress environments.
+//!
+//! Where only allow-listed addresses are reachable, the API host can be pinned to static
+//! addresses with [`GeminiBuilder::resolve()`](crate::GeminiBuilder::resolve), or resolved by
+//! a custom resolver with `GeminiBuilder::dns_resolver()` (`custom-dns` feature). Static
+//! addresses take precedence over the resolver.
+//!
+//! Only the addresses connected to change: requests still name the logical host, so the
+//! `Host` header, TLS SNI, and certificate verification all use the host name of the base
+//! URL. Connection failures report the addresses tried, see
+//! [`Error::Connect`](crate::ClientError::Connect).
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Mutex, PoisonError},
+};
+
+#[cfg(feature = "custom-dns")]
+pub use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+#[cfg(feature = "custom-dns")]
+use std::sync::Arc;
+
+/// The addresses each host was last resolved to, for reporting connection failures.
+///
+/// Every access is a single insert or lookup, so a poisoned lock still guards a consistent map
+/// and is recovered rather than failing requests over a diagnostic.
+#[derive(Debug, Default)]
+pub(crate) struct ResolvedAddrs(Mutex<HashMap<String, Vec<SocketAddr>>>);
+
+impl ResolvedAddrs {
+    pub(crate) fn record(&self, host: &str, addrs: Vec<SocketAddr>) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(host.to_ascii_lowercase(), addrs);
+    }
+
+    /// The addresses `host` was resolved to, empty if it was resolved by the system resolver
+    pub(crate) fn get(&self, host: &str) -> Vec<SocketAddr> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&host.to_ascii_lowercase())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// A custom resolver whose answers are recorded in [`ResolvedAddrs`]
+#[cfg(feature = "custom-dns")]
+pub(crate) struct RecordingResolver {
+    pub(crate) inner: Arc<dyn Resolve>,
+    pub(crate) resolved: Arc<ResolvedAddrs>,
+}
+
+#[cfg(feature = "custom-dns")]
+impl Resolve for RecordingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let resolving = self.inner.resolve(name);
+        let resolved = self.resolved.clone();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = resolving.await?.collect();
+            resolved.record(&host, addrs.clone());
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Formats the addresses of a connection failure
+pub(crate) fn describe(addrs: &[SocketAddr]) -> String {
+    match addrs {
+        [] => "addresses from the system resolver".to_string(),
+        addrs => addrs
+            .iter()
+            .map(SocketAddr::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
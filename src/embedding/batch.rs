@@ -0,0 +1,202 @@
+//! Embedding many texts at once.
+//!
+//! [`Gemini::embed_batch()`](crate::Gemini::embed_batch) splits the texts into batches of at
+//! most 100, the most one `batchEmbedContents` call accepts, and sends the batches
+//! concurrently. A fixed number of batches is in flight at once, unless an
+//! [`AdaptiveLimiter`](crate::AdaptiveLimiter) or another [`ConcurrencyLimiter`] is set with
+//! [`EmbedBatchBuilder::with_limiter()`].
+//!
+//! Embeddings are returned in the order of the texts. A batch that still fails after the
+//! client's retries leaves its texts without embeddings and is reported with the range of
+//! text indices it covered, so that only those texts need to be sent again.
+
+use futures::{stream, StreamExt};
+use std::{ops::Range, sync::Arc};
+use tracing::instrument;
+
+use super::{
+    document::MAX_BATCH_SIZE,
+    model::{BatchEmbedContentsRequest, EmbedContentRequest, TaskType},
+};
+use crate::{
+    client::{EmbeddingCountMismatchSnafu, Error as ClientError, GeminiClient},
+    generation::concurrency::{
+        self, ConcurrencyLimiter, FixedLimiter, RequestOutcome, RequestSample,
+    },
+    Message, Model,
+};
+
+/// Number of batches in flight at once, unless set with
+/// [`EmbedBatchBuilder::with_concurrency()`]
+pub const DEFAULT_EMBED_CONCURRENCY: usize = 4;
+
+/// A batch of texts that could not be embedded
+#[derive(Debug)]
+pub struct FailedBatch {
+    /// Indices of the texts of the batch
+    pub range: Range<usize>,
+    /// The error of the last attempt
+    pub error: ClientError,
+}
+
+/// The embeddings of a list of texts
+#[derive(Debug)]
+pub struct BatchEmbedding {
+    /// The embedding of every text, in the order of the texts; `None` for the texts of a
+    /// failed batch
+    pub embeddings: Vec<Option<Vec<f32>>>,
+    /// Batches that failed, in the order of the texts
+    pub failed: Vec<FailedBatch>,
+}
+
+impl BatchEmbedding {
+    /// Whether every text was embedded
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Builder for embedding many texts in concurrent batches, see the
+/// [module documentation](self)
+pub struct EmbedBatchBuilder {
+    client: Arc<GeminiClient>,
+    texts: Vec<String>,
+    task_type: Option<TaskType>,
+    title: Option<String>,
+    output_dimensionality: Option<i32>,
+    batch_size: usize,
+    limiter: Arc<dyn ConcurrencyLimiter>,
+}
+
+impl EmbedBatchBuilder {
+    pub(crate) fn new(client: Arc<GeminiClient>, texts: Vec<String>) -> Self {
+        Self {
+            client,
+            texts,
+            task_type: None,
+            title: None,
+            output_dimensionality: None,
+            batch_size: MAX_BATCH_SIZE,
+            limiter: Arc::new(FixedLimiter::new(DEFAULT_EMBED_CONCURRENCY)),
+        }
+    }
+
+    /// Embed with `model` instead of the client's model, see
+    /// [`EmbedBuilder::with_model()`](crate::EmbedBuilder::with_model)
+    pub fn with_model(mut self, model: impl Into<Model>) -> Self {
+        self.client = Arc::new(self.client.for_model(model));
+        self
+    }
+
+    /// Specify embedding task type
+    pub fn with_task_type(mut self, task_type: TaskType) -> Self {
+        self.task_type = Some(task_type);
+        self
+    }
+
+    /// Specify document title, sent with every text
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Specify output_dimensionality. If set, excessive values in the output embedding are truncated from the end
+    pub fn with_output_dimensionality(mut self, output_dimensionality: i32) -> Self {
+        self.output_dimensionality = Some(output_dimensionality);
+        self
+    }
+
+    /// Set the number of texts embedded per request, at most 100 (the default)
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.clamp(1, MAX_BATCH_SIZE);
+        self
+    }
+
+    /// Sends at most `concurrency` batches at once, instead of
+    /// [`DEFAULT_EMBED_CONCURRENCY`].
+    pub fn with_concurrency(self, concurrency: usize) -> Self {
+        self.with_limiter(FixedLimiter::new(concurrency))
+    }
+
+    /// Lets `limiter` decide how many batches are in flight at once, e.g. an
+    /// [`AdaptiveLimiter`](crate::AdaptiveLimiter).
+    ///
+    /// The limiter is told the outcome of every request as measured by the client's clock.
+    pub fn with_limiter(mut self, limiter: impl ConcurrencyLimiter + 'static) -> Self {
+        self.limiter = Arc::new(limiter);
+        self
+    }
+
+    /// Embeds the texts.
+    ///
+    /// Every batch is retried according to the client's [`RetryPolicy`](crate::RetryPolicy).
+    /// A batch that still fails, or whose response does not hold one embedding per text, is
+    /// reported in [`BatchEmbedding::failed`].
+    #[instrument(skip_all, fields(texts.len = self.texts.len(), batch.size = self.batch_size))]
+    pub async fn execute(self) -> BatchEmbedding {
+        let ranges: Vec<Range<usize>> = (0..self.texts.len())
+            .step_by(self.batch_size)
+            .map(|start| start..(start + self.batch_size).min(self.texts.len()))
+            .collect();
+        let jobs = stream::iter(ranges).map(|range| {
+            let request = BatchEmbedContentsRequest {
+                requests: self.texts[range.clone()]
+                    .iter()
+                    .map(|text| self.request(text))
+                    .collect(),
+            };
+            let client = self.client.clone();
+            let limiter = self.limiter.clone();
+            async move {
+                let started = client.clock.now();
+                let result = client.embed_content_batch(request).await;
+                limiter.record(RequestSample {
+                    started,
+                    latency: client.clock.now().saturating_duration_since(started),
+                    outcome: RequestOutcome::of(&result),
+                });
+                let result = result.and_then(|response| {
+                    let actual = response.embeddings.len();
+                    snafu::ensure!(
+                        actual == range.len(),
+                        EmbeddingCountMismatchSnafu {
+                            expected: range.len(),
+                            actual,
+                        }
+                    );
+                    Ok(response.embeddings)
+                });
+                (range, result)
+            }
+        });
+
+        let mut embeddings = Vec::with_capacity(self.texts.len());
+        let mut failed = Vec::new();
+        let mut results = concurrency::buffered(jobs, self.limiter.clone());
+        while let Some((range, result)) = results.next().await {
+            match result {
+                Ok(batch) => embeddings.extend(batch.into_iter().map(|e| Some(e.values))),
+                Err(error) => {
+                    tracing::warn!(
+                        range = ?range,
+                        error = %error,
+                        "batch embedding failed"
+                    );
+                    embeddings.extend(range.clone().map(|_| None));
+                    failed.push(FailedBatch { range, error });
+                }
+            }
+        }
+        BatchEmbedding { embeddings, failed }
+    }
+
+    fn request(&self, text: &str) -> EmbedContentRequest {
+        EmbedContentRequest {
+            model: self.client.model.clone(),
+            content: Message::embed(text).content,
+            task_type: self.task_type.clone(),
+            title: self.title.clone(),
+            output_dimensionality: self.output_dimensionality,
+        }
+    }
+}
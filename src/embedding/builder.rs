@@ -7,7 +7,7 @@ use super::model::{
 };
 use crate::{
     client::{Error as ClientError, GeminiClient},
-    Content, Message,
+    Content, Message, Model,
 };
 
 /// Builder for embed generation requests
@@ -32,6 +32,14 @@ impl EmbedBuilder {
         }
     }
 
+    /// Embed with `model` instead of the client's model, e.g.
+    /// [`Model::TextEmbedding004`] when the client generates
+    /// content with a Gemini model
+    pub fn with_model(mut self, model: impl Into<Model>) -> Self {
+        self.client = Arc::new(self.client.for_model(model));
+        self
+    }
+
     /// Add a vec of text to embed to the request
     pub fn with_text(mut self, text: impl Into<String>) -> Self {
         let message = Message::embed(text);
@@ -57,8 +65,8 @@ impl EmbedBuilder {
 
     /// Specify document title
     /// Supported by newer models since 2024 only !!
-    pub fn with_title(mut self, title: String) -> Self {
-        self.title = Some(title);
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
         self
     }
 
@@ -0,0 +1,157 @@
+//! Splitting of long text into overlapping chunks for embedding.
+//!
+//! Token counts are estimated locally rather than asking the API: a run of ASCII letters and
+//! digits counts one token per four characters, every other character except whitespace
+//! counts one token. This overestimates ordinary English text slightly and is close for
+//! scripts without spaces, so chunks stay within the model's input limit.
+
+use snafu::Snafu;
+use std::ops::Range;
+
+/// How a document is split into chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkingConfig {
+    /// Maximum estimated tokens per chunk
+    pub max_tokens: usize,
+    /// Estimated tokens repeated at the start of a chunk from the end of the previous one
+    pub overlap_tokens: usize,
+}
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: 512,
+            overlap_tokens: 64,
+        }
+    }
+}
+
+/// Error of a [`ChunkingConfig`] that cannot split text
+#[derive(Debug, Snafu, PartialEq, Eq)]
+pub enum ChunkingError {
+    #[snafu(display("chunks of at most 0 tokens cannot hold any text"))]
+    ZeroMaxTokens,
+
+    #[snafu(display(
+        "an overlap of {overlap_tokens} tokens leaves no room in chunks of {max_tokens} tokens"
+    ))]
+    OverlapTooLarge {
+        overlap_tokens: usize,
+        max_tokens: usize,
+    },
+}
+
+/// Splits text into chunks of at most [`ChunkingConfig::max_tokens`] estimated tokens.
+///
+/// Chunks end at the last paragraph, sentence, or word boundary in the second half of the
+/// window, in that order of preference, and never start or end with whitespace. Ranges are
+/// byte offsets into the original text and always fall on character boundaries.
+#[derive(Debug, Clone)]
+pub struct TextChunker {
+    config: ChunkingConfig,
+}
+
+impl TextChunker {
+    /// Creates a chunker.
+    ///
+    /// Fails if `max_tokens` is zero or `overlap_tokens` is not smaller than `max_tokens`.
+    pub fn new(config: ChunkingConfig) -> Result<Self, ChunkingError> {
+        let ChunkingConfig {
+            max_tokens,
+            overlap_tokens,
+        } = config;
+        snafu::ensure!(max_tokens > 0, ZeroMaxTokensSnafu);
+        snafu::ensure!(
+            overlap_tokens < max_tokens,
+            OverlapTooLargeSnafu {
+                overlap_tokens,
+                max_tokens,
+            }
+        );
+        Ok(Self { config })
+    }
+
+    /// Returns the byte ranges of the chunks of `text`, in order
+    pub fn chunks(&self, text: &str) -> Vec<Range<usize>> {
+        let starts = token_starts(text);
+        let ChunkingConfig {
+            max_tokens,
+            overlap_tokens,
+        } = self.config;
+
+        let mut chunks = Vec::new();
+        let mut first = 0;
+        while first < starts.len() {
+            let last = (first + max_tokens).min(starts.len());
+            let end = if last == starts.len() {
+                last
+            } else {
+                best_break(text, &starts, first + max_tokens / 2 + 1, last)
+            };
+            let byte_end = starts.get(end).copied().unwrap_or(text.len());
+            let range =
+                starts[first]..starts[first] + text[starts[first]..byte_end].trim_end().len();
+            chunks.push(range);
+            if end == starts.len() {
+                break;
+            }
+            // Start the overlap at a word if possible, and always make progress
+            let overlap = end.saturating_sub(overlap_tokens).max(first + 1);
+            first = (overlap..end)
+                .find(|&token| starts_word(text, starts[token]))
+                .unwrap_or(overlap);
+        }
+        chunks
+    }
+}
+
+/// Estimates the number of tokens of `text`, see the [module documentation](self)
+pub fn estimate_tokens(text: &str) -> usize {
+    token_starts(text).len()
+}
+
+/// Byte offsets at which the estimated tokens start; whitespace belongs to the token before it
+fn token_starts(text: &str) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut word_len = 0;
+    for (offset, c) in text.char_indices() {
+        if c.is_whitespace() {
+            word_len = 0;
+        } else if c.is_ascii_alphanumeric() {
+            if word_len % 4 == 0 {
+                starts.push(offset);
+            }
+            word_len += 1;
+        } else {
+            starts.push(offset);
+            word_len = 0;
+        }
+    }
+    starts
+}
+
+fn starts_word(text: &str, offset: usize) -> bool {
+    text[..offset]
+        .chars()
+        .next_back()
+        .is_none_or(char::is_whitespace)
+}
+
+/// Picks the token to end a chunk before, among `from..=to`
+fn best_break(text: &str, starts: &[usize], from: usize, to: usize) -> usize {
+    let candidates = || (from..=to).rev().filter(|&token| token < starts.len());
+    let before = |token: usize| text[..starts[token]].trim_end_matches(' ');
+    candidates()
+        .find(|&token| before(token).ends_with("\n\n"))
+        .or_else(|| {
+            candidates().find(|&token| {
+                let preceding = &text[..starts[token]];
+                let trimmed = preceding.trim_end();
+                // Full-width punctuation is not followed by a space
+                (trimmed.len() < preceding.len() && trimmed.ends_with(['.', '!', '?']))
+                    || trimmed.ends_with(['。', '！', '？'])
+            })
+        })
+        .or_else(|| candidates().find(|&token| starts_word(text, starts[token])))
+        .unwrap_or(to)
+}
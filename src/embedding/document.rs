@@ -0,0 +1,174 @@
+use snafu::ResultExt;
+use std::{ops::Range, sync::Arc};
+use tracing::instrument;
+
+use super::{
+    chunker::{ChunkingConfig, TextChunker},
+    model::{BatchEmbedContentsRequest, EmbedContentRequest, TaskType},
+};
+use crate::{
+    client::{Error as ClientError, GeminiClient, InvalidChunkingSnafu},
+    Message, Model,
+};
+
+/// Maximum number of requests the API accepts in one `batchEmbedContents` call
+pub(crate) const MAX_BATCH_SIZE: usize = 100;
+
+/// A chunk of a document and its embedding
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddedChunk {
+    /// Byte range of the chunk in the document
+    pub range: Range<usize>,
+    /// Text of the chunk, equal to `&document[range]`
+    pub text: String,
+    /// The embedding of the text
+    pub vector: Vec<f32>,
+}
+
+/// A chunk of a document that could not be embedded
+#[derive(Debug)]
+pub struct FailedChunk {
+    /// Byte range of the chunk in the document
+    pub range: Range<usize>,
+    /// The error of the last attempt
+    pub error: ClientError,
+}
+
+/// The embedded chunks of a document
+#[derive(Debug)]
+pub struct DocumentEmbedding {
+    /// Chunks that were embedded, in document order
+    pub chunks: Vec<EmbeddedChunk>,
+    /// Chunks that failed, in document order
+    pub failed: Vec<FailedChunk>,
+}
+
+impl DocumentEmbedding {
+    /// Whether every chunk was embedded
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Builder for chunking and embedding a long document
+pub struct EmbedDocumentBuilder {
+    client: Arc<GeminiClient>,
+    text: String,
+    chunker: TextChunker,
+    task_type: Option<TaskType>,
+    title: Option<String>,
+    output_dimensionality: Option<i32>,
+    batch_size: usize,
+}
+
+impl EmbedDocumentBuilder {
+    pub(crate) fn new(
+        client: Arc<GeminiClient>,
+        text: impl Into<String>,
+        config: ChunkingConfig,
+    ) -> Result<Self, ClientError> {
+        Ok(Self {
+            client,
+            text: text.into(),
+            chunker: TextChunker::new(config).context(InvalidChunkingSnafu)?,
+            task_type: Some(TaskType::RetrievalDocument),
+            title: None,
+            output_dimensionality: None,
+            batch_size: MAX_BATCH_SIZE,
+        })
+    }
+
+    /// Embed with `model` instead of the client's model, see
+    /// [`EmbedBuilder::with_model()`](crate::EmbedBuilder::with_model)
+    pub fn with_model(mut self, model: impl Into<Model>) -> Self {
+        self.client = Arc::new(self.client.for_model(model));
+        self
+    }
+
+    /// Specify embedding task type; defaults to [`TaskType::RetrievalDocument`]
+    pub fn with_task_type(mut self, task_type: TaskType) -> Self {
+        self.task_type = Some(task_type);
+        self
+    }
+
+    /// Specify document title, sent with every chunk
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Specify output_dimensionality. If set, excessive values in the output embedding are truncated from the end
+    pub fn with_output_dimensionality(mut self, output_dimensionality: i32) -> Self {
+        self.output_dimensionality = Some(output_dimensionality);
+        self
+    }
+
+    /// Set the number of chunks embedded per request, at most 100 (the default)
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.clamp(1, MAX_BATCH_SIZE);
+        self
+    }
+
+    /// Splits the document and embeds its chunks.
+    ///
+    /// Chunks are embedded in batches, each retried according to the client's
+    /// [`RetryPolicy`](crate::RetryPolicy). If a batch still fails, its chunks are embedded one
+    /// by one so that a single bad chunk does not fail the others; chunks failing on their own
+    /// are reported in [`DocumentEmbedding::failed`].
+    #[instrument(skip_all, fields(document.len = self.text.len(), batch.size = self.batch_size))]
+    pub async fn execute(self) -> DocumentEmbedding {
+        let ranges = self.chunker.chunks(&self.text);
+        let mut chunks = Vec::with_capacity(ranges.len());
+        let mut failed = Vec::new();
+        for batch in ranges.chunks(self.batch_size) {
+            let request = BatchEmbedContentsRequest {
+                requests: batch.iter().map(|range| self.request(range)).collect(),
+            };
+            match self.client.embed_content_batch(request).await {
+                Ok(response) if response.embeddings.len() == batch.len() => {
+                    for (range, embedding) in batch.iter().zip(response.embeddings) {
+                        chunks.push(self.chunk(range, embedding.values));
+                    }
+                    continue;
+                }
+                Ok(response) => tracing::warn!(
+                    expected = batch.len(),
+                    actual = response.embeddings.len(),
+                    "batch returned the wrong number of embeddings; embedding chunks one by one"
+                ),
+                Err(error) => tracing::warn!(
+                    error = %error,
+                    "batch embedding failed; embedding chunks one by one"
+                ),
+            }
+            for range in batch {
+                match self.client.embed_content(self.request(range)).await {
+                    Ok(response) => chunks.push(self.chunk(range, response.embedding.values)),
+                    Err(error) => failed.push(FailedChunk {
+                        range: range.clone(),
+                        error,
+                    }),
+                }
+            }
+        }
+        DocumentEmbedding { chunks, failed }
+    }
+
+    fn request(&self, range: &Range<usize>) -> EmbedContentRequest {
+        EmbedContentRequest {
+            model: self.client.model.clone(),
+            content: Message::embed(&self.text[range.clone()]).content,
+            task_type: self.task_type.clone(),
+            title: self.title.clone(),
+            output_dimensionality: self.output_dimensionality,
+        }
+    }
+
+    fn chunk(&self, range: &Range<usize>, vector: Vec<f32>) -> EmbeddedChunk {
+        EmbeddedChunk {
+            range: range.clone(),
+            text: self.text[range.clone()].to_string(),
+            vector,
+        }
+    }
+}
@@ -2,12 +2,29 @@
 //!
 //! This module provides functionality for generating text embeddings using the Gemini API.
 //! It includes support for both single and batch embedding operations with various task types
-//! for optimization.
+//! for optimization, concurrent batches of many texts, and chunked embedding of long documents.
+//!
+//! Only the [`chunker`], which also estimates the tokens of prompts, is built without the
+//! `embeddings` feature.
 
+#[cfg(feature = "embeddings")]
+pub mod batch;
+#[cfg(feature = "embeddings")]
 pub mod builder;
+pub mod chunker;
+#[cfg(feature = "embeddings")]
+pub mod document;
+#[cfg(feature = "embeddings")]
 pub mod model;
 
+#[cfg(feature = "embeddings")]
+pub use batch::{BatchEmbedding, EmbedBatchBuilder, FailedBatch};
+#[cfg(feature = "embeddings")]
 pub use builder::EmbedBuilder;
+pub use chunker::{ChunkingConfig, ChunkingError, TextChunker};
+#[cfg(feature = "embeddings")]
+pub use document::{DocumentEmbedding, EmbedDocumentBuilder, EmbeddedChunk, FailedChunk};
+#[cfg(feature = "embeddings")]
 pub use model::{
     BatchContentEmbeddingResponse, BatchEmbedContentsRequest, ContentEmbedding,
     ContentEmbeddingResponse, EmbedContentRequest, TaskType,
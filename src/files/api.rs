@@ -0,0 +1,99 @@
+//! The Files API, for media too large to send inline.
+//!
+//! Inline data is limited to a few megabytes per request. [`Gemini::files()`] returns a
+//! [`Files`] handle that uploads larger media, e.g. videos or long recordings, with the
+//! resumable upload protocol and waits until the API has processed them:
+//!
+//! ```no_run
+//! # async fn run(client: gemini_rust::Gemini) -> Result<(), Box<dyn std::error::Error>> {
+//! use std::path::Path;
+//!
+//! let video = client
+//!     .files()
+//!     .upload(Path::new("talk.mp4"), "video/mp4".parse()?)
+//!     .await?;
+//! let response = client
+//!     .generate_content()
+//!     .with_file(&video)
+//!     .with_user_message("Summarize this talk")
+//!     .execute()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Gemini::files()`]: crate::Gemini::files
+
+use mime::Mime;
+use std::{sync::Arc, time::Duration};
+
+use super::{
+    builder::{FileBuilder, FileSource, DEFAULT_POLL_INTERVAL},
+    handle::FileHandle,
+    Error,
+};
+use crate::client::GeminiClient;
+
+/// How long [`Files::upload()`] waits for a file to become active, unless set with
+/// [`Files::with_activation_timeout()`]
+pub const DEFAULT_ACTIVATION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Handle to the Files API of a client, see the [module documentation](self)
+#[derive(Clone)]
+pub struct Files {
+    client: Arc<GeminiClient>,
+    poll_interval: Duration,
+    activation_timeout: Duration,
+}
+
+impl Files {
+    pub(crate) fn new(client: Arc<GeminiClient>) -> Self {
+        Self {
+            client,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            activation_timeout: DEFAULT_ACTIVATION_TIMEOUT,
+        }
+    }
+
+    /// Set how often the file state is polled while waiting for processing (defaults to
+    /// [`DEFAULT_POLL_INTERVAL`]).
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Set how long to wait for an uploaded file to become active (defaults to
+    /// [`DEFAULT_ACTIVATION_TIMEOUT`]).
+    pub fn with_activation_timeout(mut self, timeout: Duration) -> Self {
+        self.activation_timeout = timeout;
+        self
+    }
+
+    /// Uploads `source`, a path or bytes, as `mime_type`, and waits until the file is active.
+    ///
+    /// Use [`builder()`](Self::builder) to set a display name or keep a guard on the file.
+    pub async fn upload(
+        &self,
+        source: impl Into<FileSource>,
+        mime_type: Mime,
+    ) -> Result<FileHandle, Error> {
+        self.builder(source)
+            .with_mime_type(mime_type)
+            .upload()
+            .await
+    }
+
+    /// Starts building the upload of `source`, waiting until the file is active as configured
+    /// on this handle
+    pub fn builder(&self, source: impl Into<FileSource>) -> FileBuilder {
+        FileBuilder::new(self.client.clone(), source.into())
+            .with_poll_interval(self.poll_interval)
+            .with_activation_timeout(self.activation_timeout)
+    }
+
+    /// Get a handle to a file by its name.
+    pub async fn get(&self, name: &str) -> Result<FileHandle, crate::ClientError> {
+        let file = self.client.get_file(name).await?;
+        Ok(FileHandle::new(self.client.clone(), file))
+    }
+}
@@ -1,27 +1,87 @@
+use futures::Stream;
 use mime::Mime;
 use snafu::ResultExt;
-use std::sync::Arc;
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tracing::instrument;
 
 use super::*;
-use crate::client::GeminiClient;
+use crate::{
+    cleanup::{ResourceGuard, ResourceKind},
+    client::GeminiClient,
+};
+
+/// How often the state of an uploaded file is polled while waiting for it to become active,
+/// unless set with [`FileBuilder::with_poll_interval()`]
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Size of the chunks a file is streamed in while uploading
+const UPLOAD_CHUNK_BYTES: usize = 256 * 1024;
+
+type ProgressFn = dyn Fn(u64, u64) + Send + Sync;
+
+/// The content of a file to upload
+#[derive(Debug, Clone)]
+pub enum FileSource {
+    /// The bytes of the file
+    Bytes(Vec<u8>),
+    /// A local file, streamed from disk while uploading
+    Path(PathBuf),
+}
+
+impl From<Vec<u8>> for FileSource {
+    fn from(bytes: Vec<u8>) -> Self {
+        FileSource::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for FileSource {
+    fn from(bytes: &[u8]) -> Self {
+        FileSource::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<PathBuf> for FileSource {
+    fn from(path: PathBuf) -> Self {
+        FileSource::Path(path)
+    }
+}
+
+impl From<&Path> for FileSource {
+    fn from(path: &Path) -> Self {
+        FileSource::Path(path.to_path_buf())
+    }
+}
 
 /// A builder for creating a file resource.
 #[derive(Clone)]
 pub struct FileBuilder {
     client: Arc<GeminiClient>,
-    file_bytes: Vec<u8>,
+    source: FileSource,
     display_name: Option<String>,
     mime_type: Option<Mime>,
+    poll_interval: Duration,
+    activation_timeout: Option<Duration>,
+    retry: bool,
+    progress: Option<Arc<ProgressFn>>,
 }
 
 impl FileBuilder {
-    pub(crate) fn new<B: Into<Vec<u8>>>(client: Arc<GeminiClient>, file_bytes: B) -> Self {
+    pub(crate) fn new(client: Arc<GeminiClient>, source: FileSource) -> Self {
         Self {
             client,
-            file_bytes: file_bytes.into(),
+            source,
             display_name: None,
             mime_type: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            activation_timeout: None,
+            retry: false,
+            progress: None,
         }
     }
 
@@ -37,21 +97,144 @@ impl FileBuilder {
         self
     }
 
+    /// Wait after uploading until the file is active, for at most `timeout`.
+    ///
+    /// Videos and other media are processed before they can be used in requests; without a
+    /// timeout, the upload returns as soon as the file is stored.
+    pub fn with_activation_timeout(mut self, timeout: Duration) -> Self {
+        self.activation_timeout = Some(timeout);
+        self
+    }
+
+    /// Set how often the file state is polled while waiting for processing (defaults to
+    /// [`DEFAULT_POLL_INTERVAL`]).
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Retry starting the upload after transient failures, as the client's
+    /// [`RetryPolicy`](crate::RetryPolicy) says.
+    ///
+    /// Off by default: a request failing after the API accepted it would open a second
+    /// upload session.
+    pub fn with_retries(mut self) -> Self {
+        self.retry = true;
+        self
+    }
+
+    /// Call `progress` with the bytes sent so far and the size of the file, after each chunk
+    /// of the upload.
+    pub fn with_progress(mut self, progress: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
     /// Upload the file.
+    ///
+    /// A file uploaded from a path is named after it unless a display name is set. With an
+    /// [activation timeout](Self::with_activation_timeout), the file is left uploaded if it
+    /// does not become active in time.
     #[instrument(skip_all, fields(
-        file.size = self.file_bytes.len(),
         mime.type = self.mime_type.as_ref().map(|m| m.to_string()),
         file.display_name = self.display_name,
     ))]
     pub async fn upload(self) -> Result<super::handle::FileHandle, super::Error> {
+        let (poll_interval, activation_timeout) = (self.poll_interval, self.activation_timeout);
+        let mut handle = self.upload_file().await?;
+        if let Some(timeout) = activation_timeout {
+            handle.wait_until_active(poll_interval, timeout).await?;
+        }
+        Ok(handle)
+    }
+
+    /// Upload the file, along with a guard deleting it once closed or dropped, see
+    /// [`cleanup`](crate::cleanup).
+    ///
+    /// If the file does not become active within the
+    /// [activation timeout](Self::with_activation_timeout), the guard is dropped and deletes it.
+    pub async fn upload_guarded(
+        self,
+    ) -> Result<(super::handle::FileHandle, ResourceGuard), super::Error> {
+        let client = self.client.clone();
+        let (poll_interval, activation_timeout) = (self.poll_interval, self.activation_timeout);
+        let mut handle = self.upload_file().await?;
+        let guard = ResourceGuard::new(client, ResourceKind::File, handle.name().to_string());
+        if let Some(timeout) = activation_timeout {
+            handle.wait_until_active(poll_interval, timeout).await?;
+        }
+        Ok((handle, guard))
+    }
+
+    async fn upload_file(self) -> Result<super::handle::FileHandle, super::Error> {
         let mime_type = self.mime_type.unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        let progress = self.progress;
+        let (body, size, display_name) = match self.source {
+            FileSource::Bytes(bytes) => {
+                let size = bytes.len() as u64;
+                (
+                    upload_body(Cursor::new(bytes), size, progress),
+                    size,
+                    self.display_name,
+                )
+            }
+            FileSource::Path(path) => {
+                let file = tokio::fs::File::open(&path)
+                    .await
+                    .context(ReadFileSnafu { path: path.clone() })?;
+                let size = file
+                    .metadata()
+                    .await
+                    .context(ReadFileSnafu { path: path.clone() })?
+                    .len();
+                let display_name = self.display_name.or_else(|| {
+                    path.file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                });
+                (upload_body(file, size, progress), size, display_name)
+            }
+        };
+        tracing::debug!(file.size = size, "uploading file");
 
         let file = self
             .client
-            .upload_file(self.display_name, self.file_bytes, mime_type)
+            .upload_file(display_name, body, size, mime_type, self.retry)
             .await
             .context(ClientSnafu)?;
 
         Ok(super::handle::FileHandle::new(self.client, file))
     }
 }
+
+/// Streams the first `size` bytes of `reader` as a request body, reporting each chunk sent to
+/// `progress`
+fn upload_body(
+    reader: impl AsyncRead + Send + Unpin + 'static,
+    size: u64,
+    progress: Option<Arc<ProgressFn>>,
+) -> reqwest::Body {
+    reqwest::Body::wrap_stream(chunks(reader.take(size), size, progress))
+}
+
+fn chunks(
+    mut reader: impl AsyncRead + Send + Unpin + 'static,
+    size: u64,
+    progress: Option<Arc<ProgressFn>>,
+) -> impl Stream<Item = std::io::Result<Vec<u8>>> + Send + 'static {
+    async_stream::try_stream! {
+        let mut sent = 0;
+        loop {
+            let mut chunk = vec![0; UPLOAD_CHUNK_BYTES];
+            let read = reader.read(&mut chunk).await?;
+            if read == 0 {
+                break;
+            }
+            chunk.truncate(read);
+            sent += read as u64;
+            if let Some(progress) = &progress {
+                progress(sent, size);
+            }
+            yield chunk;
+        }
+    }
+}
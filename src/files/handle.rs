@@ -1,6 +1,9 @@
+use reqwest::Url;
 use snafu::ResultExt;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+use time::OffsetDateTime;
 
+use super::model::FileState;
 use super::*;
 use crate::client::GeminiClient;
 
@@ -19,11 +22,70 @@ impl FileHandle {
         &self.inner.name
     }
 
+    /// The URI referencing the file in requests, see
+    /// [`ContentBuilder::with_file()`](crate::ContentBuilder::with_file)
+    pub fn uri(&self) -> Option<&Url> {
+        self.inner.uri.as_ref()
+    }
+
+    /// The MIME type of the file
+    pub fn mime_type(&self) -> Option<&str> {
+        self.inner.mime_type.as_deref()
+    }
+
+    /// The processing state of the file, as of the last refresh
+    pub fn state(&self) -> Option<&FileState> {
+        self.inner.state.as_ref()
+    }
+
+    /// When the file will be deleted by the API
+    pub fn expiration_time(&self) -> Option<OffsetDateTime> {
+        self.inner.expiration_time
+    }
+
     /// Get the file metadata.
     pub fn get_file_meta(&self) -> &super::model::File {
         &self.inner
     }
 
+    /// Polls the file until processing finished and it is ready for use.
+    ///
+    /// Fails if processing failed or the file is not active after `timeout`. The file
+    /// metadata is refreshed with every poll.
+    pub async fn wait_until_active(
+        &mut self,
+        poll_interval: Duration,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        let started = self.client.clock.now();
+        loop {
+            match self.inner.state {
+                // Files without a state are usable right away
+                Some(FileState::Active) | None => return Ok(()),
+                Some(FileState::Failed) => {
+                    return ProcessingFailedSnafu {
+                        name: self.inner.name.clone(),
+                    }
+                    .fail()
+                }
+                _ => {}
+            }
+            snafu::ensure!(
+                self.client.clock.now() - started < timeout,
+                ActivationTimeoutSnafu {
+                    name: self.inner.name.clone(),
+                    timeout,
+                }
+            );
+            self.client.clock.sleep(poll_interval).await;
+            self.inner = self
+                .client
+                .get_file(&self.inner.name)
+                .await
+                .context(ClientSnafu)?;
+        }
+    }
+
     /// Delete the file.
     pub async fn delete(self) -> Result<(), (Self, Error)> {
         match self
@@ -39,10 +101,18 @@ impl FileHandle {
 
     /// Download the file.
     ///
-    /// Work only for files that was generated by Gemini.
+    /// Work only for files that was generated by Gemini. Downloads larger than the client's
+    /// [response size limit](crate::GeminiBuilder::with_max_response_bytes) fail; see
+    /// [`download_with_limit()`](Self::download_with_limit) to raise it for one download.
     pub async fn download(&self) -> Result<Vec<u8>, Error> {
+        self.download_with_limit(self.client.max_response_bytes())
+            .await
+    }
+
+    /// Download the file, allowing it to be up to `max_bytes` large.
+    pub async fn download_with_limit(&self, max_bytes: usize) -> Result<Vec<u8>, Error> {
         self.client
-            .download_file(self.name())
+            .download_file(self.name(), max_bytes)
             .await
             .context(ClientSnafu)
     }
@@ -1,10 +1,30 @@
 use snafu::Snafu;
 
+pub mod api;
 pub mod builder;
 pub mod handle;
 pub mod model;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
-    Client { source: crate::client::Error },
+    Client {
+        source: crate::client::Error,
+    },
+
+    #[snafu(display("reading file '{}' failed", path.display()))]
+    ReadFile {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("processing of file '{name}' failed"))]
+    ProcessingFailed {
+        name: String,
+    },
+
+    #[snafu(display("file '{name}' did not become active within {timeout:?}"))]
+    ActivationTimeout {
+        name: String,
+        timeout: std::time::Duration,
+    },
 }
@@ -44,6 +44,9 @@ pub struct File {
     /// The current state of the file.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub state: Option<FileState>,
+    /// Where the file came from, e.g. `UPLOADED` or `GENERATED`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
 }
 
 /// The state of a file.
@@ -0,0 +1,305 @@
+//! Aggregating the candidates of a stream with a bounded buffer per candidate.
+//!
+//! [`ContentBuilder::execute_stream_aggregated()`](crate::ContentBuilder::execute_stream_aggregated)
+//! concatenates the answer text of every candidate of a stream. With
+//! [`AggregatePolicy::with_max_candidate_bytes()`], text beyond the cap is dropped as it
+//! arrives instead of being buffered, which keeps memory bounded when many long alternatives
+//! are only needed for reranking. Such candidates are marked [`AggregatedCandidate::truncated`],
+//! which is independent of their finish reason: a candidate can be truncated by the client and
+//! still finish with [`FinishReason::Stop`], or finish with [`FinishReason::MaxTokens`] without
+//! being truncated by the client.
+//!
+//! Only the answer text is kept by default. [`AggregatePolicy::with_parts()`] also keeps every
+//! part, with answer text cut at the same cap; streamed text arrives in many small parts,
+//! which can be merged as they arrive.
+//!
+//! A stream failing after some chunks were received fails with
+//! [`Error::PartialStream`](crate::ClientError::PartialStream), which carries what was
+//! aggregated so far in an [`AggregatedPartial`]. To salvage the answer rather than pay for the
+//! whole prompt again, send [`AggregatedPartial::text()`] back as a model turn followed by
+//! [`CONTINUE_PROMPT`](crate::generation::continuation::CONTINUE_PROMPT).
+
+use futures::{TryStream, TryStreamExt};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::Error as ClientError, clock::Clock, generation::items::StreamSummary,
+    models::push_merged, FinishReason, FunctionCall, GenerationResponse, Part,
+};
+
+type TruncateFn = dyn Fn(i32, usize) + Send + Sync;
+
+/// How much text of each candidate is kept
+#[derive(Clone, Default)]
+pub struct AggregatePolicy {
+    max_candidate_bytes: Option<usize>,
+    on_truncate: Option<Arc<TruncateFn>>,
+    parts: Option<PartsMode>,
+}
+
+/// How parts are kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PartsMode {
+    AsReceived,
+    Normalized,
+}
+
+impl fmt::Debug for AggregatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AggregatePolicy")
+            .field("max_candidate_bytes", &self.max_candidate_bytes)
+            .field("on_truncate", &self.on_truncate.is_some())
+            .field("parts", &self.parts)
+            .finish()
+    }
+}
+
+impl AggregatePolicy {
+    /// Keeps all text of every candidate.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps at most `max_bytes` of text per candidate, dropping the rest as it arrives.
+    ///
+    /// The kept text ends at a character boundary, so it may be a few bytes shorter.
+    pub fn with_max_candidate_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_candidate_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Also keeps the parts of every candidate in [`AggregatedCandidate::parts`].
+    ///
+    /// Answer text beyond the cap is dropped from the parts as from the text; thoughts and
+    /// other parts are kept whole. If `normalize` is set, adjacent text parts are merged as
+    /// they arrive, see [`Content::merge_text_parts()`](crate::Content::merge_text_parts).
+    pub fn with_parts(mut self, normalize: bool) -> Self {
+        self.parts = Some(match normalize {
+            true => PartsMode::Normalized,
+            false => PartsMode::AsReceived,
+        });
+        self
+    }
+
+    /// Calls `callback` with the candidate index and the number of dropped bytes for every
+    /// truncated candidate, once the stream has ended.
+    pub fn on_truncate(mut self, callback: impl Fn(i32, usize) + Send + Sync + 'static) -> Self {
+        self.on_truncate = Some(Arc::new(callback));
+        self
+    }
+}
+
+/// The collected answer of one candidate
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregatedCandidate {
+    /// The index of the candidate
+    pub index: i32,
+    /// The answer text, without thoughts, up to the policy's cap
+    pub text: String,
+    /// Why the model stopped generating the candidate
+    pub finish_reason: Option<FinishReason>,
+    /// Whether text was dropped because it exceeded the policy's cap
+    pub truncated: bool,
+    /// The number of bytes of text dropped
+    pub dropped_bytes: usize,
+    /// The parts of the candidate, if kept with [`AggregatePolicy::with_parts()`]
+    pub parts: Vec<Part>,
+}
+
+/// The candidates of a stream and its metadata
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregatedResponse {
+    /// The candidates, ordered by index
+    pub candidates: Vec<AggregatedCandidate>,
+    /// Metadata collected across the chunks
+    pub summary: StreamSummary,
+}
+
+/// What a stream delivered before failing, carried by
+/// [`Error::PartialStream`](crate::ClientError::PartialStream)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregatedPartial {
+    /// The candidates as far as received, ordered by index
+    pub candidates: Vec<AggregatedCandidate>,
+    /// The function calls of every candidate, in the order received; a call arrives whole in
+    /// a single chunk
+    pub function_calls: Vec<FunctionCall>,
+    /// The number of chunks received
+    pub chunks: usize,
+    /// Time from sending the request to the failure, as measured by the client's clock
+    pub elapsed: Duration,
+    /// Metadata collected across the chunks received
+    pub summary: StreamSummary,
+}
+
+impl AggregatedPartial {
+    /// The answer text of the first candidate received so far
+    pub fn text(&self) -> &str {
+        self.candidates
+            .first()
+            .map_or("", |candidate| candidate.text.as_str())
+    }
+}
+
+/// The candidate chosen by [`AggregatedResponse::best_by()`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BestCandidate<'a> {
+    /// The chosen candidate
+    pub candidate: &'a AggregatedCandidate,
+    /// Whether any ranked candidate was truncated, so the ranking saw only part of its text
+    pub ranked_truncated: bool,
+}
+
+impl AggregatedResponse {
+    /// Picks the candidate for which `key` is largest, the first one on ties.
+    ///
+    /// Ranking sees the text as kept by the policy; [`BestCandidate::ranked_truncated`] tells
+    /// whether that was less than the model generated.
+    pub fn best_by<K: Ord>(
+        &self,
+        mut key: impl FnMut(&AggregatedCandidate) -> K,
+    ) -> Option<BestCandidate<'_>> {
+        let candidate = self
+            .candidates
+            .iter()
+            .rev()
+            .max_by_key(|candidate| key(candidate))?;
+        let ranked_truncated = self.candidates.iter().any(|candidate| candidate.truncated);
+        if ranked_truncated {
+            tracing::debug!(
+                index = candidate.index,
+                "ranked candidates whose text was truncated by the client"
+            );
+        }
+        Some(BestCandidate {
+            candidate,
+            ranked_truncated,
+        })
+    }
+}
+
+/// Collects the candidates of `stream`, whose request was sent at `started`, according to
+/// `policy`
+pub(crate) async fn aggregate(
+    stream: impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send,
+    policy: AggregatePolicy,
+    clock: &dyn Clock,
+    started: Instant,
+) -> Result<AggregatedResponse, ClientError> {
+    let mut candidates: BTreeMap<i32, AggregatedCandidate> = BTreeMap::new();
+    let mut function_calls = Vec::new();
+    let mut chunks = 0;
+    let mut summary = StreamSummary::default();
+    let stream = stream.into_stream();
+    futures::pin_mut!(stream);
+    while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(source) if chunks == 0 => return Err(source),
+            Err(source) => {
+                let elapsed = clock.now().saturating_duration_since(started);
+                tracing::warn!(chunks, ?elapsed, error = %source, "stream failed midway");
+                return Err(ClientError::PartialStream {
+                    received: Box::new(AggregatedPartial {
+                        candidates: candidates.into_values().collect(),
+                        function_calls,
+                        chunks,
+                        elapsed,
+                        summary,
+                    }),
+                    source: Box::new(source),
+                });
+            }
+        };
+        chunks += 1;
+        summary.record(&chunk);
+        for (position, candidate) in chunk.candidates.iter().enumerate() {
+            let index = candidate.index.unwrap_or(position as i32);
+            let aggregated = candidates
+                .entry(index)
+                .or_insert_with(|| AggregatedCandidate {
+                    index,
+                    text: String::new(),
+                    finish_reason: None,
+                    truncated: false,
+                    dropped_bytes: 0,
+                    parts: Vec::new(),
+                });
+            for part in candidate.content.parts.iter().flatten() {
+                if let Part::FunctionCall { function_call, .. } = part {
+                    function_calls.push(function_call.clone());
+                }
+                let part = match part {
+                    Part::Text {
+                        text,
+                        thought: thought @ (None | Some(false)),
+                        thought_signature,
+                    } => {
+                        let kept = push_capped(aggregated, text, policy.max_candidate_bytes);
+                        if policy.parts.is_none()
+                            || (kept.is_empty() && thought_signature.is_none())
+                        {
+                            continue;
+                        }
+                        Part::Text {
+                            text: kept.to_string(),
+                            thought: *thought,
+                            thought_signature: thought_signature.clone(),
+                        }
+                    }
+                    _ if policy.parts.is_none() => continue,
+                    part => part.clone(),
+                };
+                match policy.parts {
+                    Some(PartsMode::Normalized) => {
+                        push_merged(&mut aggregated.parts, part);
+                    }
+                    _ => aggregated.parts.push(part),
+                }
+            }
+            if candidate.finish_reason.is_some() {
+                aggregated.finish_reason = candidate.finish_reason.clone();
+            }
+        }
+    }
+
+    let candidates: Vec<AggregatedCandidate> = candidates.into_values().collect();
+    if let Some(callback) = &policy.on_truncate {
+        for candidate in candidates.iter().filter(|candidate| candidate.truncated) {
+            callback(candidate.index, candidate.dropped_bytes);
+        }
+    }
+    Ok(AggregatedResponse {
+        candidates,
+        summary,
+    })
+}
+
+/// Appends as much of `text` as fits under `cap`, counting the rest as dropped; returns the
+/// part of `text` kept
+fn push_capped<'a>(
+    candidate: &mut AggregatedCandidate,
+    text: &'a str,
+    cap: Option<usize>,
+) -> &'a str {
+    let room = cap.map_or(usize::MAX, |cap| cap.saturating_sub(candidate.text.len()));
+    // Once text was dropped, nothing more is kept, even if a later delta would fit
+    let mut kept = match candidate.truncated {
+        true => 0,
+        false => text.len().min(room),
+    };
+    while !text.is_char_boundary(kept) {
+        kept -= 1;
+    }
+    candidate.text.push_str(&text[..kept]);
+    if kept < text.len() {
+        candidate.truncated = true;
+        candidate.dropped_bytes += text.len() - kept;
+    }
+    &text[..kept]
+}
@@ -0,0 +1,258 @@
+//! Attributed answers over inline source documents.
+//!
+//! [`ContentBuilder::attributed_answer()`](crate::ContentBuilder::attributed_answer) asks the
+//! model for a structured answer of the form
+//! `{answer, citations: [{doc_id, quote, start_hint}]}` and verifies every citation against
+//! the supplied documents. Quotes are matched after normalizing whitespace, case, and
+//! typographic quotes, so minor formatting differences do not count as fabrication.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use snafu::{ResultExt, Snafu};
+use tracing::instrument;
+
+use crate::GenerationResponse;
+use crate::{client::Error as ClientError, generation::documents::Document, ContentBuilder};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to generate attributed answer"))]
+    Client { source: ClientError },
+
+    #[snafu(display("failed to decode attributed answer from model output"))]
+    Decode { source: serde_json::Error },
+}
+
+/// Instruction sent along with the documents
+const ATTRIBUTION_INSTRUCTION: &str = "Answer the question using only the documents provided. \
+     Support every claim with citations: for each citation give the id of the document, a quote \
+     copied verbatim from that document, and the character offset where the quote starts.";
+
+/// A citation as produced by the model
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Citation {
+    /// Id of the cited document
+    pub doc_id: String,
+    /// Text quoted from the document
+    pub quote: String,
+    /// Approximate character offset of the quote, as claimed by the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_hint: Option<usize>,
+}
+
+/// The raw structured answer produced by the model
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttributedAnswer {
+    /// The answer text
+    pub answer: String,
+    /// Citations supporting the answer
+    #[serde(default)]
+    pub citations: Vec<Citation>,
+}
+
+/// Outcome of verifying a citation against the supplied documents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CitationStatus {
+    /// The quote appears in the document, starting at this byte offset
+    Verified { offset: usize },
+    /// The document exists but does not contain the quote
+    QuoteNotFound,
+    /// No document has the cited id
+    UnknownDocument,
+}
+
+/// A citation together with its verification outcome
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedCitation {
+    pub citation: Citation,
+    pub status: CitationStatus,
+}
+
+impl VerifiedCitation {
+    /// Whether the citation could not be found in the cited document
+    pub fn is_fabricated(&self) -> bool {
+        !matches!(self.status, CitationStatus::Verified { .. })
+    }
+}
+
+/// A verified attributed answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributedResult {
+    /// The answer text
+    pub answer: String,
+    /// Every citation with its verification outcome
+    pub citations: Vec<VerifiedCitation>,
+}
+
+impl AttributedResult {
+    /// Verifies every citation of `answer` against `documents`
+    pub fn verify(answer: AttributedAnswer, documents: &[Document]) -> Self {
+        let citations = answer
+            .citations
+            .into_iter()
+            .map(|citation| {
+                let status = match documents.iter().find(|d| d.id == citation.doc_id) {
+                    None => CitationStatus::UnknownDocument,
+                    Some(document) => {
+                        match find_quote(&document.text, &citation.quote, citation.start_hint) {
+                            Some(offset) => CitationStatus::Verified { offset },
+                            None => CitationStatus::QuoteNotFound,
+                        }
+                    }
+                };
+                VerifiedCitation { citation, status }
+            })
+            .collect();
+        Self {
+            answer: answer.answer,
+            citations,
+        }
+    }
+
+    /// Parses the structured answer from a response and verifies it against `documents`
+    pub fn from_response(
+        response: &GenerationResponse,
+        documents: &[Document],
+    ) -> Result<Self, Error> {
+        let answer: AttributedAnswer =
+            serde_json::from_str(response.text().trim()).context(DecodeSnafu)?;
+        Ok(Self::verify(answer, documents))
+    }
+
+    /// Citations that could not be verified
+    pub fn fabricated(&self) -> impl Iterator<Item = &VerifiedCitation> {
+        self.citations.iter().filter(|c| c.is_fabricated())
+    }
+
+    /// Whether every citation was verified
+    pub fn is_fully_attributed(&self) -> bool {
+        self.fabricated().next().is_none()
+    }
+}
+
+/// The response schema of an attributed answer
+pub fn attribution_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "answer": {
+                "type": "string",
+                "description": "The answer to the question"
+            },
+            "citations": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "doc_id": {
+                            "type": "string",
+                            "description": "Id of the cited document"
+                        },
+                        "quote": {
+                            "type": "string",
+                            "description": "Text copied verbatim from the document"
+                        },
+                        "start_hint": {
+                            "type": "integer",
+                            "description": "Character offset where the quote starts"
+                        }
+                    },
+                    "required": ["doc_id", "quote"]
+                }
+            }
+        },
+        "required": ["answer", "citations"]
+    })
+}
+
+/// Builder for an attributed answer request
+pub struct AttributedAnswerBuilder {
+    builder: ContentBuilder,
+    documents: Vec<Document>,
+}
+
+impl AttributedAnswerBuilder {
+    pub(crate) fn new(builder: ContentBuilder, documents: Vec<Document>, question: String) -> Self {
+        let builder = builder
+            .append_system_instruction(ATTRIBUTION_INSTRUCTION)
+            .with_documents(documents.clone())
+            .with_user_message(question)
+            .with_response_mime_type("application/json")
+            .with_response_schema(attribution_schema());
+        Self { builder, documents }
+    }
+
+    /// Returns the underlying content builder, e.g. to inspect the request
+    pub fn content_builder(&self) -> &ContentBuilder {
+        &self.builder
+    }
+
+    /// Executes the request and verifies the returned citations
+    #[instrument(skip_all, fields(documents = self.documents.len()))]
+    pub async fn execute(self) -> Result<AttributedResult, Error> {
+        let response = self.builder.execute().await.context(ClientSnafu)?;
+        AttributedResult::from_response(&response, &self.documents)
+    }
+}
+
+/// Normalizes text for fuzzy matching, returning the normalized characters and the byte
+/// offset in the original text where each one starts
+fn normalize(text: &str) -> (Vec<char>, Vec<usize>) {
+    let mut chars = Vec::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len());
+    let mut pending_space = None;
+    for (offset, c) in text.char_indices() {
+        if c.is_whitespace() {
+            pending_space.get_or_insert(offset);
+            continue;
+        }
+        if let Some(space) = pending_space.take() {
+            if !chars.is_empty() {
+                chars.push(' ');
+                offsets.push(space);
+            }
+        }
+        let c = match c {
+            '\u{2018}' | '\u{2019}' => '\'',
+            '\u{201C}' | '\u{201D}' => '"',
+            '\u{2013}' | '\u{2014}' => '-',
+            c => c,
+        };
+        for lower in c.to_lowercase() {
+            chars.push(lower);
+            offsets.push(offset);
+        }
+    }
+    (chars, offsets)
+}
+
+/// Finds `quote` in `document` after normalizing whitespace, case, and typographic quotes.
+///
+/// Leading and trailing ellipses of the quote are ignored. When the quote occurs several
+/// times, the occurrence closest to `start_hint`, a character offset as the model gives it,
+/// wins. Returns the byte offset of the match in `document`.
+pub fn find_quote(document: &str, quote: &str, start_hint: Option<usize>) -> Option<usize> {
+    let quote = quote
+        .trim()
+        .trim_matches(|c: char| c == '…' || c == '.' || c.is_whitespace());
+    let (needle, _) = normalize(quote);
+    if needle.is_empty() {
+        return None;
+    }
+    let (haystack, offsets) = normalize(document);
+    let matches = haystack
+        .windows(needle.len())
+        .enumerate()
+        .filter(|(_, window)| *window == needle.as_slice())
+        .map(|(i, _)| offsets[i]);
+    match start_hint {
+        Some(hint) => {
+            let hint = document
+                .char_indices()
+                .nth(hint)
+                .map_or(document.len(), |(offset, _)| offset);
+            matches.min_by_key(|offset| offset.abs_diff(hint))
+        }
+        None => matches.min(),
+    }
+}
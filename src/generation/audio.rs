@@ -0,0 +1,115 @@
+//! Audio sent inline with a prompt.
+//!
+//! [`ContentBuilder::with_audio_from_path()`](crate::ContentBuilder::with_audio_from_path) and
+//! [`ContentBuilder::with_audio_bytes()`](crate::ContentBuilder::with_audio_bytes) base64-encode
+//! a clip into an `inlineData` part, joining the preceding user turn like
+//! [images](crate::generation::image) do:
+//!
+//! ```no_run
+//! # fn run(client: gemini_rust::Gemini) -> Result<(), gemini_rust::AudioError> {
+//! let request = client
+//!     .generate_content()
+//!     .with_user_message("Transcribe this clip")
+//!     .with_audio_from_path("interview.mp3")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Only the types in [`AUDIO_MIME_TYPES`] are sent. Clips larger than
+//! [`MAX_INLINE_AUDIO_BYTES`] once base64-encoded fail before being read; upload them with
+//! [`Gemini::files()`](crate::Gemini::files) and add them with
+//! [`ContentBuilder::with_file()`](crate::ContentBuilder::with_file) instead.
+
+use snafu::Snafu;
+use std::path::{Path, PathBuf};
+
+use super::inline::{self, InlineKind};
+use crate::Part;
+
+/// Largest clip sent inline, once base64-encoded; the API rejects requests larger than 20 MB
+pub const MAX_INLINE_AUDIO_BYTES: usize = 20 * 1024 * 1024;
+
+/// Audio types the API accepts
+pub const AUDIO_MIME_TYPES: &[&str] = &[
+    "audio/wav",
+    "audio/mp3",
+    "audio/aiff",
+    "audio/aac",
+    "audio/ogg",
+    "audio/flac",
+];
+
+/// Error adding inline audio
+#[derive(Debug, Snafu)]
+pub enum AudioError {
+    #[snafu(display("reading audio '{}' failed", path.display()))]
+    ReadAudio {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "audio type '{audio_type}' is not supported, expected WAV, MP3, AIFF, AAC, OGG or FLAC"
+    ))]
+    UnsupportedAudioType {
+        /// The MIME type given, or the extension of the path
+        audio_type: String,
+    },
+
+    #[snafu(display(
+        "audio of {size} bytes once base64-encoded exceeds the inline limit of {limit} bytes, upload it with the Files API"
+    ))]
+    AudioTooLarge { size: usize, limit: usize },
+}
+
+/// Audio, for the inline checks; the types are those of [`AUDIO_MIME_TYPES`]
+struct Audio;
+
+impl InlineKind for Audio {
+    const TYPES: &'static [(&'static str, &'static str)] = &[
+        ("wav", "audio/wav"),
+        ("mp3", "audio/mp3"),
+        ("aif", "audio/aiff"),
+        ("aiff", "audio/aiff"),
+        ("aac", "audio/aac"),
+        ("ogg", "audio/ogg"),
+        ("flac", "audio/flac"),
+    ];
+    const LIMIT: usize = MAX_INLINE_AUDIO_BYTES;
+
+    type Error = AudioError;
+
+    fn unsupported(audio_type: String) -> AudioError {
+        AudioError::UnsupportedAudioType { audio_type }
+    }
+
+    fn too_large(size: usize, limit: usize) -> AudioError {
+        AudioError::AudioTooLarge { size, limit }
+    }
+
+    fn unreadable(path: &Path, source: std::io::Error) -> AudioError {
+        AudioError::ReadAudio {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+/// Returns the MIME type of an audio file from its extension, or `None` for types the API
+/// does not accept
+pub fn audio_mime_type(path: impl AsRef<Path>) -> Option<&'static str> {
+    inline::mime_type::<Audio>(path.as_ref())
+}
+
+/// Encodes `bytes` as an inline audio part
+pub(crate) fn audio_part(bytes: &[u8], mime_type: &str) -> Result<Part, AudioError> {
+    let mime_type = inline::accepted::<Audio>(mime_type)?;
+    inline::check_size::<Audio>(bytes.len())?;
+    Ok(inline::part(mime_type, bytes))
+}
+
+/// Reads the clip at `path` as an inline audio part
+pub(crate) fn audio_part_from_path(path: &Path) -> Result<Part, AudioError> {
+    let (bytes, mime_type) = inline::read::<Audio>(path)?;
+    audio_part(&bytes, mime_type)
+}
@@ -1,39 +1,265 @@
-use futures::TryStream;
-use std::sync::Arc;
+#[cfg(feature = "streaming")]
+use futures::{TryStream, TryStreamExt};
+use serde::de::DeserializeOwned;
+use snafu::ResultExt;
+use std::{collections::BTreeMap, fmt::Write, path::Path, sync::Arc};
 use tracing::instrument;
 
+#[cfg(feature = "caching")]
+use crate::cache::CachedContentHandle;
+#[cfg(feature = "toon_wip")]
+use crate::client::ToonOutputSnafu;
+#[cfg(feature = "files")]
+use crate::files::handle::FileHandle;
+#[cfg(feature = "image")]
+use crate::generation::image::{self, ImageError};
+#[cfg(feature = "schema")]
+use crate::schema::{Schema, SchemaError};
+#[cfg(feature = "tools")]
 use crate::{
-    cache::CachedContentHandle,
-    client::{Error as ClientError, GeminiClient},
-    generation::{GenerateContentRequest, SpeakerVoiceConfig, SpeechConfig, ThinkingConfig},
+    chat::SeedStrategy,
+    client::{MalformedFunctionCallSnafu, ToolLoopExhaustedSnafu},
+    tools::{
+        registry::{failed_code_correction, malformed_call_correction},
+        run_log::RunRecorder,
+        RunLog, RunLogOptions, ToolExchange, ToolLoopState, ToolRegistry,
+    },
+};
+#[cfg(feature = "toon")]
+use crate::{client::ToonInputSnafu, toon};
+use crate::{
+    client::{
+        CachedContentConflictSnafu, DecodeAudioSnafu, Error as ClientError, GeminiClient,
+        LintDeniedSnafu, Model, StructuredOutputSnafu, UnsupportedBundleVersionSnafu,
+    },
+    common::path,
+    generation::{
+        attribution::AttributedAnswerBuilder,
+        audio::{self, AudioError},
+        bundle::{self, BundleFlags, PromptBundle, BUNDLE_VERSION},
+        continuation,
+        documents::{self, documents_content, Document, DocumentError},
+        fragments::{FragmentError, SystemPromptComposer},
+        lint::{self, LintKind, LintRule, LintWarning},
+        modalities,
+        provenance::ProvenanceMap,
+        spoken::SpokenResponse,
+        video::{Video, VideoError},
+        CountTokensResponse, GenerateContentRequest, SpeakerVoiceConfig, SpeechConfig,
+        ThinkingConfig,
+    },
+    retry::{budget::CallContext, RetryBudget, RetryLayer},
+    safety::{
+        screen::{self, OutputScreen},
+        threshold::SafetyThresholds,
+    },
     tools::{FunctionCallingConfig, ToolConfig},
-    Content, FunctionCallingMode, FunctionDeclaration, GenerationConfig, GenerationResponse,
-    Message, Role, Tool,
+    trace_context, Content, FinishReason, FunctionCall, FunctionCallingMode, FunctionDeclaration,
+    FunctionResponse, Gemini, GenerationConfig, GenerationResponse, HarmBlockThreshold,
+    HarmCategory, HarmProbability, Message, Modality, Part, Role, SafetySetting, Tool, UserData,
+};
+#[cfg(feature = "streaming")]
+use crate::{
+    generation::{
+        aggregate::{self, AggregatePolicy, AggregatedResponse},
+        items::{self, ItemEvent},
+        spill::{self, CollectedText, SpillPolicy},
+        stop::{self, StopPattern},
+        stream::{self, StreamEvent},
+    },
+    safety::{sanitize, screen::StreamScreen, threshold},
 };
 
+/// Model used to transcribe generated speech
+pub const TRANSCRIPTION_MODEL: Model = Model::Gemini25Flash;
+
+/// Prompt sent along with generated speech to obtain its transcript
+const TRANSCRIPTION_PROMPT: &str =
+    "Transcribe the speech in this audio verbatim. Respond with the transcript only.";
+
 /// Builder for content generation requests
 #[derive(Clone)]
 pub struct ContentBuilder {
     client: Arc<GeminiClient>,
     pub contents: Vec<Content>,
     generation_config: Option<GenerationConfig>,
+    safety_settings: Vec<SafetySetting>,
     tools: Option<Vec<Tool>>,
     tool_config: Option<ToolConfig>,
     system_instruction: Option<Content>,
+    /// TOON spec appended to the system instruction when the request is built
+    #[cfg(feature = "toon")]
+    toon_spec: Option<String>,
+    /// Layout of the documents added by `with_toon_message`
+    #[cfg(feature = "toon")]
+    toon_options: toon::ToonOptions,
     cached_content: Option<String>,
+    labels: BTreeMap<String, String>,
+    pub(crate) provenance: ProvenanceMap,
+    output_screen: Option<Arc<dyn OutputScreen>>,
+    safety_thresholds: SafetyThresholds,
+    transcript: bool,
+    retry_budget: Option<RetryBudget>,
+    regeneration: Option<Regeneration>,
+    abort_on: Vec<FinishReason>,
+    #[cfg(feature = "streaming")]
+    client_stops: Vec<StopPattern>,
+    max_continuations: u32,
+    fallback_to_text: bool,
+    user_data: UserData,
+    rate_limit_wait: bool,
+    /// `traceparent` header sent instead of that of the current span
+    traceparent: Option<String>,
+    lint_rules: Vec<Arc<dyn LintRule>>,
+    denied_lints: Vec<LintKind>,
+    /// Seeds of the tool loop's requests, one per step
+    #[cfg(feature = "tools")]
+    seed_strategy: SeedStrategy,
+}
+
+/// Content-level regeneration of rejected responses
+#[derive(Clone)]
+struct Regeneration {
+    max_regenerations: u32,
+    accept: Arc<dyn Fn(&GenerationResponse) -> bool + Send + Sync>,
+}
+
+/// Appends `text` to `instruction` as a new part, creating the instruction if there is none
+fn push_system_text(instruction: &mut Option<Content>, text: String) {
+    let part = Part::Text {
+        text,
+        thought: None,
+        thought_signature: None,
+    };
+    match instruction {
+        Some(instruction) => instruction.parts.get_or_insert_with(Vec::new).push(part),
+        None => {
+            *instruction = Some(Content {
+                parts: Some(vec![part]),
+                role: None,
+            })
+        }
+    }
 }
 
 impl ContentBuilder {
+    /// The model the request is sent to
+    pub(crate) fn model(&self) -> &Model {
+        &self.client.model
+    }
+
     /// Creates a new `ContentBuilder`.
     pub(crate) fn new(client: Arc<GeminiClient>) -> Self {
         Self {
             client,
             contents: Vec::new(),
             generation_config: None,
+            safety_settings: Vec::new(),
             tools: None,
             tool_config: None,
             system_instruction: None,
+            #[cfg(feature = "toon")]
+            toon_spec: None,
+            #[cfg(feature = "toon")]
+            toon_options: toon::ToonOptions::default(),
             cached_content: None,
+            labels: BTreeMap::new(),
+            provenance: ProvenanceMap::default(),
+            output_screen: None,
+            safety_thresholds: SafetyThresholds::default(),
+            transcript: false,
+            retry_budget: None,
+            regeneration: None,
+            abort_on: Vec::new(),
+            #[cfg(feature = "streaming")]
+            client_stops: Vec::new(),
+            max_continuations: 0,
+            fallback_to_text: false,
+            user_data: UserData::default(),
+            rate_limit_wait: true,
+            traceparent: None,
+            lint_rules: Vec::new(),
+            denied_lints: Vec::new(),
+            #[cfg(feature = "tools")]
+            seed_strategy: SeedStrategy::None,
+        }
+    }
+
+    /// Creates the context of a call, with the call's retry budget or else the client's
+    fn call_context(&self) -> CallContext {
+        let ctx = CallContext::new(self.retry_budget.or(self.client.retry_budget))
+            .with_traceparent(self.traceparent.as_deref());
+        match self.rate_limit_wait {
+            true => ctx,
+            false => ctx.without_rate_limit_wait(),
+        }
+    }
+
+    /// Appends a content, recording which builder method produced its parts.
+    fn push_content(&mut self, content: Content, method: &'static str) {
+        let parts = content.parts.as_ref().map_or(0, Vec::len);
+        self.provenance.record(self.contents.len(), parts, method);
+        self.contents.push(content);
+    }
+
+    /// Adds a part to the preceding turn if it is a user turn of text and media only,
+    /// otherwise as a new user turn.
+    fn push_user_part(&mut self, part: Part, method: &'static str) {
+        let turn = self.contents.last_mut().filter(|content| {
+            content.role == Some(Role::User)
+                && content.parts.as_ref().is_some_and(|parts| {
+                    parts.iter().all(|part| {
+                        matches!(
+                            part,
+                            Part::Text { .. } | Part::InlineData { .. } | Part::FileData { .. }
+                        )
+                    })
+                })
+        });
+        match turn {
+            Some(content) => {
+                content.parts.get_or_insert_with(Vec::new).push(part);
+                self.provenance.append(self.contents.len() - 1, method);
+            }
+            None => {
+                let content = Content {
+                    parts: Some(vec![part]),
+                    role: Some(Role::User),
+                };
+                self.push_content(content, method);
+            }
+        }
+    }
+
+    /// Adds a function response, to the preceding turn if it holds only function responses,
+    /// so the responses to parallel calls are sent in one turn, in the order they were added.
+    fn push_function_response(
+        &mut self,
+        function_response: FunctionResponse,
+        method: &'static str,
+    ) {
+        let part = Part::FunctionResponse { function_response };
+        let responses = self.contents.last_mut().filter(|content| {
+            content.role == Some(Role::User)
+                && content.parts.as_ref().is_some_and(|parts| {
+                    !parts.is_empty()
+                        && parts
+                            .iter()
+                            .all(|part| matches!(part, Part::FunctionResponse { .. }))
+                })
+        });
+        match responses {
+            Some(content) => {
+                content.parts.get_or_insert_with(Vec::new).push(part);
+                self.provenance.append(self.contents.len() - 1, method);
+            }
+            None => {
+                let content = Content {
+                    parts: Some(vec![part]),
+                    role: Some(Role::User),
+                };
+                self.push_content(content, method);
+            }
         }
     }
 
@@ -47,24 +273,93 @@ impl ContentBuilder {
     /// Sets the system instruction for the request.
     ///
     /// System instructions are used to provide high-level guidance to the model, such as
-    /// setting a persona, providing context, or defining the desired output format.
+    /// setting a persona, providing context, or defining the desired output format. They are
+    /// sent in the request's `systemInstruction` field, apart from the conversation.
+    ///
+    /// Calling this again replaces the previous instruction. Guidance the client adds itself,
+    /// e.g. for an [attributed answer](Self::attributed_answer), is appended to the
+    /// instruction as a separate part instead.
     pub fn with_system_instruction(mut self, text: impl Into<String>) -> Self {
         let content = Content::text(text);
         self.system_instruction = Some(content);
+        self.provenance.set_system_fragments(Vec::new());
+        self
+    }
+
+    /// Sets a system instruction made of several parts, e.g. a persona and a style guide kept
+    /// apart.
+    ///
+    /// Like [`with_system_instruction()`](Self::with_system_instruction), this replaces any
+    /// previous instruction.
+    pub fn with_system_instruction_parts(mut self, parts: impl IntoIterator<Item = Part>) -> Self {
+        self.system_instruction = Some(Content {
+            parts: Some(parts.into_iter().collect()),
+            role: None,
+        });
+        self.provenance.set_system_fragments(Vec::new());
+        self
+    }
+
+    /// Sets the system instruction composed from the fragments `ids` of `composer`.
+    ///
+    /// The ids of the fragments included are recorded in the request provenance, see
+    /// [`system_fragments()`](Self::system_fragments). Like
+    /// [`with_system_instruction()`](Self::with_system_instruction), this replaces any
+    /// previous instruction. Fails if one of the ids is not registered.
+    pub fn with_system_fragments<S: AsRef<str>>(
+        mut self,
+        composer: &SystemPromptComposer,
+        ids: &[S],
+    ) -> std::result::Result<Self, FragmentError> {
+        let composed = composer.compose(ids)?;
+        self.system_instruction = Some(Content::text(composed.text));
+        self.provenance.set_system_fragments(composed.fragments);
+        Ok(self)
+    }
+
+    /// The ids of the fragments the system instruction was composed from, in the order of
+    /// the instruction; empty unless it was set with
+    /// [`with_system_fragments()`](Self::with_system_fragments)
+    pub fn system_fragments(&self) -> &[String] {
+        self.provenance.system_fragments()
+    }
+
+    /// Sets the system instruction from an existing content.
+    pub(crate) fn with_system_instruction_content(mut self, content: Content) -> Self {
+        self.system_instruction = Some(content);
+        self.provenance.set_system_fragments(Vec::new());
         self
     }
 
+    /// Appends `text` to the system instruction as a new part, keeping the caller's
+    /// instruction, if any, first
+    pub(crate) fn append_system_instruction(mut self, text: impl Into<String>) -> Self {
+        push_system_text(&mut self.system_instruction, text.into());
+        self
+    }
+
+    /// The system instruction as sent, followed by the TOON spec if one was requested
+    fn sent_system_instruction(&self) -> Option<Content> {
+        #[allow(unused_mut)]
+        let mut instruction = self.system_instruction.clone();
+        #[cfg(feature = "toon")]
+        if let Some(spec) = &self.toon_spec {
+            push_system_text(&mut instruction, spec.clone());
+        }
+        instruction
+    }
+
     /// Adds a user message to the conversation history.
     pub fn with_user_message(mut self, text: impl Into<String>) -> Self {
         let message = Message::user(text);
-        self.contents.push(message.content);
+        self.push_content(message.content, "with_user_message");
         self
     }
 
     /// Adds a model message to the conversation history.
     pub fn with_model_message(mut self, text: impl Into<String>) -> Self {
         let message = Message::model(text);
-        self.contents.push(message.content);
+        self.push_content(message.content, "with_model_message");
         self
     }
 
@@ -77,10 +372,112 @@ impl ContentBuilder {
         mime_type: impl Into<String>,
     ) -> Self {
         let content = Content::inline_data(mime_type, data).with_role(Role::User);
-        self.contents.push(content);
+        self.push_content(content, "with_inline_data");
+        self
+    }
+
+    /// Adds a file uploaded with the Files API to the request, see
+    /// [`files::api`](crate::files::api).
+    ///
+    /// The file is referenced by its URI, or by its name if its metadata has none; its MIME
+    /// type defaults to `application/octet-stream`.
+    #[cfg(feature = "files")]
+    pub fn with_file(mut self, file: &FileHandle) -> Self {
+        let uri = file
+            .uri()
+            .map_or_else(|| file.name().to_string(), ToString::to_string);
+        let mime_type = file
+            .mime_type()
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM.as_ref());
+        let content = Content::file_data(mime_type, uri).with_role(Role::User);
+        self.push_content(content, "with_file");
+        self
+    }
+
+    /// Adds a file referenced by `uri` to the request, e.g. one uploaded with the Files API.
+    pub fn with_file_uri(mut self, uri: impl Into<String>, mime_type: impl Into<String>) -> Self {
+        let content = Content::file_data(mime_type, uri).with_role(Role::User);
+        self.push_content(content, "with_file_uri");
         self
     }
 
+    /// Adds an image read from `path`, see [`image`].
+    ///
+    /// The MIME type is inferred from the extension. The file is read synchronously.
+    #[cfg(feature = "image")]
+    pub fn with_image_from_path(mut self, path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let part = image::image_part_from_path(path.as_ref())?;
+        self.push_user_part(part, "with_image_from_path");
+        Ok(self)
+    }
+
+    /// Adds an image of type `mime_type`, e.g. `image/png`, see [`image`].
+    #[cfg(feature = "image")]
+    pub fn with_image_bytes(
+        mut self,
+        bytes: impl AsRef<[u8]>,
+        mime_type: impl AsRef<str>,
+    ) -> Result<Self, ImageError> {
+        let part = image::image_part(bytes.as_ref(), mime_type.as_ref())?;
+        self.push_user_part(part, "with_image_bytes");
+        Ok(self)
+    }
+
+    /// Adds a video referenced by URI, optionally clipped, see [`video`](crate::generation::video).
+    ///
+    /// Fails if the clip's offsets are not well-formed durations, or if it ends before it
+    /// starts.
+    pub fn with_video(mut self, video: impl Into<Video>) -> Result<Self, VideoError> {
+        let part = video.into().into_part()?;
+        self.push_user_part(part, "with_video");
+        Ok(self)
+    }
+
+    /// Adds an audio clip read from `path`, see [`audio`].
+    ///
+    /// The MIME type is inferred from the extension. The file is read synchronously.
+    pub fn with_audio_from_path(mut self, path: impl AsRef<Path>) -> Result<Self, AudioError> {
+        let part = audio::audio_part_from_path(path.as_ref())?;
+        self.push_user_part(part, "with_audio_from_path");
+        Ok(self)
+    }
+
+    /// Adds an audio clip of type `mime_type`, one of
+    /// [`AUDIO_MIME_TYPES`](audio::AUDIO_MIME_TYPES), see [`audio`].
+    pub fn with_audio_bytes(
+        mut self,
+        bytes: impl AsRef<[u8]>,
+        mime_type: impl AsRef<str>,
+    ) -> Result<Self, AudioError> {
+        let part = audio::audio_part(bytes.as_ref(), mime_type.as_ref())?;
+        self.push_user_part(part, "with_audio_bytes");
+        Ok(self)
+    }
+
+    /// Adds a document read from `path`, see [`documents`].
+    ///
+    /// The MIME type is inferred from the extension. The file is read synchronously.
+    pub fn with_document_from_path(
+        mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, DocumentError> {
+        let part = documents::document_part_from_path(path.as_ref())?;
+        self.push_user_part(part, "with_document_from_path");
+        Ok(self)
+    }
+
+    /// Adds a document of type `mime_type`, one of
+    /// [`DOCUMENT_MIME_TYPES`](documents::DOCUMENT_MIME_TYPES), see [`documents`].
+    pub fn with_document_bytes(
+        mut self,
+        bytes: impl AsRef<[u8]>,
+        mime_type: impl AsRef<str>,
+    ) -> Result<Self, DocumentError> {
+        let part = documents::document_part(bytes.as_ref(), mime_type.as_ref())?;
+        self.push_user_part(part, "with_document_bytes");
+        Ok(self)
+    }
+
     /// Adds a function response to the request using a `Serialize` response.
     ///
     /// This is used to provide the model with the result of a function call it has requested.
@@ -92,9 +489,25 @@ impl ContentBuilder {
     where
         Response: serde::Serialize,
     {
-        let content = Content::function_response_json(name, serde_json::to_value(response)?)
-            .with_role(Role::User);
-        self.contents.push(content);
+        let response = FunctionResponse::new(name, serde_json::to_value(response)?);
+        self.push_function_response(response, "with_function_response");
+        Ok(self)
+    }
+
+    /// Adds the response to a function call the model made, echoing the call's ID.
+    ///
+    /// When the model makes several calls in one turn, add their responses in the order of
+    /// the calls: consecutive responses are sent in one turn.
+    pub fn with_function_response_for<Response>(
+        mut self,
+        call: &FunctionCall,
+        response: Response,
+    ) -> std::result::Result<Self, serde_json::Error>
+    where
+        Response: serde::Serialize,
+    {
+        let response = FunctionResponse::for_call(call, serde_json::to_value(response)?);
+        self.push_function_response(response, "with_function_response_for");
         Ok(self)
     }
 
@@ -108,28 +521,214 @@ impl ContentBuilder {
     ) -> std::result::Result<Self, serde_json::Error> {
         let response_str = response.into();
         let json = serde_json::from_str(&response_str)?;
-        let content = Content::function_response_json(name, json).with_role(Role::User);
-        self.contents.push(content);
+        let response = FunctionResponse::new(name, json);
+        self.push_function_response(response, "with_function_response_str");
         Ok(self)
     }
 
+    /// Adds a user message holding `rows` as a [TOON table](crate::toon).
+    ///
+    /// Tables of uniform records take far fewer tokens than the same records as JSON.
+    #[cfg(feature = "toon")]
+    pub fn with_toon_table_message<T: serde::Serialize>(
+        mut self,
+        rows: &[T],
+    ) -> Result<Self, ClientError> {
+        let table = toon::to_table_string(rows).context(ToonInputSnafu)?;
+        self.push_content(Message::user(table).content, "with_toon_table_message");
+        Ok(self)
+    }
+
+    /// Adds a user message holding `value` as a [TOON document](crate::toon), laid out with
+    /// the options set by [`with_toon_options()`](Self::with_toon_options).
+    #[cfg(feature = "toon")]
+    pub fn with_toon_message<T: serde::Serialize>(
+        mut self,
+        value: &T,
+    ) -> Result<Self, ClientError> {
+        let document = toon::to_string_with(value, &self.toon_options).context(ToonInputSnafu)?;
+        self.push_content(Message::user(document).content, "with_toon_message");
+        Ok(self)
+    }
+
+    /// Lays out the documents of later [`with_toon_message()`](Self::with_toon_message) calls
+    /// with `options`, e.g. a tab delimiter; messages added before are left as they are.
+    #[cfg(feature = "toon")]
+    pub fn with_toon_options(mut self, options: toon::ToonOptions) -> Self {
+        self.toon_options = options;
+        self
+    }
+
+    /// Asks the model to answer in TOON, adding the [format specification](toon::SPEC) to the
+    /// system instruction.
+    ///
+    /// The spec is appended as a separate part when the request is built, so it follows the
+    /// system instruction whether that is set before or after this call. Parse the answer with
+    /// [`toon::from_str()`] after [`toon::extract()`], or with the unstable `execute_toon()`.
+    #[cfg(feature = "toon")]
+    pub fn using_toon(self) -> Self {
+        self.using_toon_with_spec(toon::SPEC)
+    }
+
+    /// Like [`using_toon()`](Self::using_toon), with `spec` added to the system instruction
+    /// instead of the crate's, e.g. a prompt tuned for a model. Calling either again replaces
+    /// the spec.
+    #[cfg(feature = "toon")]
+    pub fn using_toon_with_spec(mut self, spec: impl Into<String>) -> Self {
+        self.toon_spec = Some(spec.into());
+        self
+    }
+
     /// Adds a `Message` to the conversation history.
     pub fn with_message(mut self, message: Message) -> Self {
         let content = message.content.clone();
         let role = content.role.clone().unwrap_or(message.role);
-        self.contents.push(content.with_role(role));
+        self.push_content(content.with_role(role), "with_message");
+        self
+    }
+
+    /// Adds source documents to the conversation as a single user turn.
+    ///
+    /// Each document becomes a text part wrapped in a `<document id="...">` tag.
+    pub fn with_documents(mut self, documents: impl IntoIterator<Item = Document>) -> Self {
+        let documents: Vec<Document> = documents.into_iter().collect();
+        self.push_content(documents_content(&documents), "with_documents");
         self
     }
 
+    /// Turns the request into an attributed answer over `documents`.
+    ///
+    /// The documents and the question are appended to the conversation, and the model is
+    /// asked for a structured answer whose citations are verified against the documents on
+    /// [`execute()`](AttributedAnswerBuilder::execute). The instructions for citing are
+    /// appended to the system instruction set so far.
+    pub fn attributed_answer(
+        self,
+        documents: impl IntoIterator<Item = Document>,
+        question: impl Into<String>,
+    ) -> AttributedAnswerBuilder {
+        AttributedAnswerBuilder::new(self, documents.into_iter().collect(), question.into())
+    }
+
+    /// Attaches a label to the parts added by the previous builder call.
+    ///
+    /// Labels are never sent to the API. They show up in [`debug_outline()`](Self::debug_outline)
+    /// and in [`ClientError::InvalidFields`] when the API rejects one of the labeled parts.
+    pub fn with_part_label(mut self, label: impl Into<String>) -> Self {
+        if let Some(index) = self.contents.len().checked_sub(1) {
+            self.provenance.label(index, &label.into());
+        }
+        self
+    }
+
+    /// Renders the turn/part tree of the request along with the provenance of each part.
+    ///
+    /// Parts are shown by their [summary](Part::summary), so inline data never ends up in the
+    /// outline.
+    pub fn debug_outline(&self) -> String {
+        let mut outline = String::new();
+        if let Some(system_instruction) = &self.sent_system_instruction() {
+            let parts = system_instruction.parts.as_ref().map_or(0, Vec::len);
+            let _ = match self.provenance.system_instruction() {
+                Some(origin) => {
+                    writeln!(outline, "system_instruction: {parts} part(s) <- {origin}")
+                }
+                None => writeln!(outline, "system_instruction: {parts} part(s)"),
+            };
+        }
+        for (i, content) in self.contents.iter().enumerate() {
+            let role = content
+                .role
+                .as_ref()
+                .map(|r| format!("{r:?}").to_lowercase())
+                .unwrap_or_else(|| "unspecified".to_string());
+            let _ = writeln!(outline, "contents[{i}] ({role})");
+            for (j, part) in content.parts.iter().flatten().enumerate() {
+                let origin = self
+                    .provenance
+                    .get(i, j)
+                    .map(ToString::to_string)
+                    .unwrap_or_else(|| "contents (direct)".to_string());
+                let _ = writeln!(outline, "  parts[{j}] {part} <- {origin}");
+            }
+        }
+        outline
+    }
+
+    /// Resolves API field violations against the provenance of the request parts.
+    pub(crate) fn enrich_error(provenance: &ProvenanceMap, error: ClientError) -> ClientError {
+        let violations = error
+            .api_status()
+            .map(|status| status.field_violations())
+            .unwrap_or_default();
+        match error {
+            ClientError::BadResponse {
+                code, description, ..
+            } if !violations.is_empty() => ClientError::InvalidFields {
+                code,
+                description,
+                fields: violations.iter().map(|v| provenance.resolve(v)).collect(),
+            },
+            error => error,
+        }
+    }
+
     /// Uses cached content for this request.
     ///
     /// This allows reusing previously cached system instructions and conversation history,
-    /// which can reduce latency and cost.
+    /// which can reduce latency and cost. The system instruction, tools and tool config then
+    /// come from the cache: setting them on this builder as well fails with
+    /// [`ClientError::CachedContentConflict`], even if the cache holds none of them. Contents
+    /// added to this builder are sent after the cached ones.
+    #[cfg(feature = "caching")]
     pub fn with_cached_content(mut self, cached_content: &CachedContentHandle) -> Self {
         self.cached_content = Some(cached_content.name().to_string());
         self
     }
 
+    /// Attaches a label to the request, for breaking down billing by label.
+    ///
+    /// Labels are supported on [Vertex AI](crate::backend) only; with
+    /// [preflight checks](crate::GeminiBuilder::with_preflight), other backends fail with
+    /// [`ClientError::UnsupportedOnBackend`] instead of a `400` from the API.
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.insert(key.into(), value.into());
+        self
+    }
+
+    /// Fails with [`ClientError::RateLimited`] instead of waiting when the client's rate limit,
+    /// see [`GeminiBuilder::with_rate_limit()`](crate::GeminiBuilder::with_rate_limit), has no
+    /// slot free for a request of the call.
+    ///
+    /// Requests waiting for a slot already are served first, so the call fails while any are.
+    pub fn without_rate_limit_wait(mut self) -> Self {
+        self.rate_limit_wait = false;
+        self
+    }
+
+    /// Sends `traceparent` as the W3C `traceparent` header of the call's requests, instead of
+    /// that of the current span, see [`trace_context`](crate::trace_context).
+    ///
+    /// The value must be a version `00` traceparent such as
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`; any other value is logged
+    /// and ignored.
+    pub fn with_traceparent(mut self, traceparent: &str) -> Self {
+        match trace_context::is_valid(traceparent) {
+            true => self.traceparent = Some(traceparent.to_string()),
+            false => tracing::warn!(traceparent, "ignoring malformed traceparent"),
+        }
+        self
+    }
+
+    /// Attaches `value` to the request, to be returned with its response or error, see
+    /// [`user_data`](crate::user_data).
+    ///
+    /// The value is not sent to the API. A value of a type attached before replaces it.
+    pub fn with_user_data<T: Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.user_data.insert(value);
+        self
+    }
+
     /// Adds multiple messages to the conversation history.
     pub fn with_messages(mut self, messages: impl IntoIterator<Item = Message>) -> Self {
         for message in messages {
@@ -138,7 +737,11 @@ impl ContentBuilder {
         self
     }
 
-    /// Sets the generation configuration for the request.
+    /// Sets the generation configuration for the request, replacing any sampling parameters
+    /// set before.
+    ///
+    /// The config is [validated](GenerationConfig::validate) when the request is sent, so one
+    /// config can be reused across requests.
     pub fn with_generation_config(mut self, config: GenerationConfig) -> Self {
         self.generation_config = Some(config);
         self
@@ -148,6 +751,8 @@ impl ContentBuilder {
     ///
     /// Temperature controls the randomness of the output. Higher values (e.g., 1.0) produce
     /// more creative results, while lower values (e.g., 0.2) produce more deterministic results.
+    /// Values outside 0.0 to 2.0 fail the request before it is sent, see
+    /// [`GenerationConfig::validate()`].
     pub fn with_temperature(mut self, temperature: f32) -> Self {
         self.generation_config
             .get_or_insert_with(Default::default)
@@ -202,6 +807,29 @@ impl ContentBuilder {
         self
     }
 
+    /// Sets the decoding seed for the request.
+    ///
+    /// Identical requests with the same seed produce output that is as reproducible as the
+    /// backend allows.
+    pub fn with_seed(mut self, seed: i32) -> Self {
+        self.generation_config
+            .get_or_insert_with(Default::default)
+            .seed = Some(seed);
+        self
+    }
+
+    /// Sets how the requests of [`execute_with_tools()`](Self::execute_with_tools) are seeded.
+    ///
+    /// Every step of the tool loop, re-sent steps included, is a round trip: the request of
+    /// step `n` carries the seed [`SeedStrategy::seed_for_turn(n)`](SeedStrategy::seed_for_turn)
+    /// picks, overriding the seed set with [`with_seed()`](Self::with_seed), so that the whole
+    /// run can be replayed.
+    #[cfg(feature = "tools")]
+    pub fn with_seed_strategy(mut self, strategy: SeedStrategy) -> Self {
+        self.seed_strategy = strategy;
+        self
+    }
+
     /// Sets the response MIME type for the request.
     ///
     /// This can be used to request structured output, such as JSON.
@@ -215,14 +843,32 @@ impl ContentBuilder {
     /// Sets the response schema for structured output.
     ///
     /// When used with a JSON MIME type, this schema will be used to validate the model's
-    /// output.
-    pub fn with_response_schema(mut self, schema: serde_json::Value) -> Self {
+    /// output. Accepts raw JSON as well as a [`Schema`](crate::Schema), e.g. from
+    /// [`ToSchema::schema()`](crate::ToSchema::schema).
+    pub fn with_response_schema(mut self, schema: impl Into<serde_json::Value>) -> Self {
         self.generation_config
             .get_or_insert_with(Default::default)
-            .response_schema = Some(schema);
+            .response_schema = Some(schema.into());
         self
     }
 
+    /// Requests JSON output described by `T`'s [`JsonSchema`](schemars::JsonSchema).
+    ///
+    /// Sets the response MIME type to `application/json` and the response schema to the one
+    /// schemars generates for `T`, translated with [`Schema::of()`].
+    /// Constructs the API rejects, such as maps or recursive types, fail here with a
+    /// [`SchemaError`] rather than with a `400` from the API. Read the
+    /// answer with [`execute_as()`](Self::execute_as).
+    #[cfg(feature = "schema")]
+    pub fn with_response_schema_of<T: schemars::JsonSchema>(
+        self,
+    ) -> std::result::Result<Self, SchemaError> {
+        let schema = Schema::of::<T>()?;
+        Ok(self
+            .with_response_mime_type("application/json")
+            .with_response_schema(schema))
+    }
+
     /// Adds a tool to the request.
     ///
     /// Tools allow the model to interact with external systems, such as APIs or databases.
@@ -231,6 +877,12 @@ impl ContentBuilder {
         self
     }
 
+    /// Adds several tools to the request, in order.
+    pub fn with_tools(mut self, tools: impl IntoIterator<Item = Tool>) -> Self {
+        self.tools.get_or_insert_with(Vec::new).extend(tools);
+        self
+    }
+
     /// Adds a function declaration as a tool.
     ///
     /// This is a convenience method for creating a `Tool` from a `FunctionDeclaration`.
@@ -299,7 +951,38 @@ impl ContentBuilder {
     pub fn with_audio_output(mut self) -> Self {
         self.generation_config
             .get_or_insert_with(Default::default)
-            .response_modalities = Some(vec!["AUDIO".to_string()]);
+            .response_modalities = Some(vec![Modality::Audio.to_string()]);
+        self
+    }
+
+    /// Sets the modalities the model responds with, e.g. `[Modality::Text, Modality::Image]`
+    /// or, by name, `["TEXT", "IMAGE"]`.
+    pub fn with_response_modalities(
+        mut self,
+        modalities: impl IntoIterator<Item = impl Into<Modality>>,
+    ) -> Self {
+        self.generation_config
+            .get_or_insert_with(Default::default)
+            .response_modalities = Some(
+            modalities
+                .into_iter()
+                .map(|modality| modality.into().to_string())
+                .collect(),
+        );
+        self
+    }
+
+    /// Strips response modalities the model does not support instead of failing.
+    ///
+    /// With [preflight checks](crate::GeminiBuilder::with_preflight), modalities missing from
+    /// the model's [capabilities](crate::supported_response_modalities) are stripped before
+    /// the request is sent. Otherwise, a `400 INVALID_ARGUMENT` whose field violations point
+    /// at `response_modalities` makes the request be sent once more without the rejected
+    /// modalities (keeping at least `TEXT`). Either way the response is marked
+    /// [`modality_downgraded`](GenerationResponse::modality_downgraded). Streamed execution
+    /// only applies preflight stripping.
+    pub fn fallback_to_text(mut self, fallback: bool) -> Self {
+        self.fallback_to_text = fallback;
         self
     }
 
@@ -323,45 +1006,893 @@ impl ContentBuilder {
         self.with_speech_config(speech_config).with_audio_output()
     }
 
+    /// Screens the model output before it is returned.
+    ///
+    /// The screen can allow, redact, or block the text of every candidate. Blocked responses
+    /// fail with [`ClientError::OutputBlocked`]. When streaming, text is released with a
+    /// holdback window so that patterns split across chunks are still caught; see
+    /// [`safety::screen`](crate::safety::screen) for details.
+    pub fn with_output_screen(mut self, screen: impl OutputScreen + 'static) -> Self {
+        self.output_screen = Some(Arc::new(screen));
+        self
+    }
+
+    /// Checks the request for common prompt mistakes without sending it, see
+    /// [`lint`].
+    ///
+    /// Runs the [default rules](lint::default_rules), then those added with
+    /// [`with_lint_rule()`](Self::with_lint_rule).
+    pub fn lint(&self) -> Vec<LintWarning> {
+        let request = self.clone().build();
+        let added = self.lint_rules.iter().map(|rule| rule.as_ref());
+        lint::default_rules()
+            .iter()
+            .map(|rule| rule.as_ref())
+            .chain(added)
+            .flat_map(|rule| rule.check(&request))
+            .collect()
+    }
+
+    /// Adds a rule to those [`lint()`](Self::lint) runs.
+    pub fn with_lint_rule(mut self, rule: impl LintRule + 'static) -> Self {
+        self.lint_rules.push(Arc::new(rule));
+        self
+    }
+
+    /// Fails the request with [`ClientError::LintDenied`] before it is sent if
+    /// [`lint()`](Self::lint) finds warnings of any of `kinds`.
+    pub fn deny_lints(mut self, kinds: &[LintKind]) -> Self {
+        self.denied_lints.extend_from_slice(kinds);
+        self
+    }
+
+    /// Sets the threshold at which the API blocks content of `category`.
+    ///
+    /// Setting a category again replaces its threshold. Categories left unset use the
+    /// model's default threshold.
+    pub fn with_safety_setting(
+        mut self,
+        category: HarmCategory,
+        threshold: HarmBlockThreshold,
+    ) -> Self {
+        self.safety_settings
+            .retain(|setting| setting.category != category);
+        self.safety_settings.push(SafetySetting {
+            category,
+            threshold,
+        });
+        self
+    }
+
+    /// Adds several safety settings, see [`with_safety_setting()`](Self::with_safety_setting).
+    ///
+    /// When `settings` names a category more than once, the last threshold wins.
+    pub fn with_safety_settings(
+        mut self,
+        settings: impl IntoIterator<Item = SafetySetting>,
+    ) -> Self {
+        for setting in settings {
+            self = self.with_safety_setting(setting.category, setting.threshold);
+        }
+        self
+    }
+
+    /// Lists the safety ratings of the output at or above `threshold` in
+    /// [`ResponseMeta::flags`](crate::ResponseMeta::flags) of the response.
+    ///
+    /// Ratings are checked client-side after generation, whatever the request's safety
+    /// settings; when streaming, every chunk carries the flags of the aggregate ratings so far.
+    /// See [`safety::threshold`](crate::safety::threshold) for details.
+    pub fn flag_safety_above(mut self, threshold: HarmProbability) -> Self {
+        self.safety_thresholds.flag = Some(threshold);
+        self
+    }
+
+    /// Fails with [`ClientError::SafetyThresholdExceeded`] if any safety rating of the output
+    /// is at or above `threshold`.
+    ///
+    /// When streaming, the stream fails at the first chunk whose aggregate ratings reach the
+    /// threshold, without releasing that chunk. See
+    /// [`safety::threshold`](crate::safety::threshold) for details.
+    pub fn reject_safety_above(mut self, threshold: HarmProbability) -> Self {
+        self.safety_thresholds.reject = Some(threshold);
+        self
+    }
+
+    /// Requests a transcript of the generated audio from
+    /// [`execute_spoken()`](Self::execute_spoken).
+    ///
+    /// The REST text-to-speech models do not return transcripts, so the generated audio is
+    /// sent to [`TRANSCRIPTION_MODEL`] in a follow-up request. The follow-up shares the
+    /// call's [retry budget](Self::with_retry_budget) and does not fall back to other models.
+    pub fn with_transcript(mut self, transcript: bool) -> Self {
+        self.transcript = transcript;
+        self
+    }
+
+    /// Caps the network attempts and backoff delay of the whole call.
+    ///
+    /// The budget is shared by every retry mechanism involved in the call: HTTP retries,
+    /// tool loop steps, and regeneration. See [`retry`](crate::retry) for details. Replaces
+    /// the budget of the client's [`Policy`](crate::Policy), if any.
+    pub fn with_retry_budget(mut self, budget: RetryBudget) -> Self {
+        self.retry_budget = Some(budget);
+        self
+    }
+
+    /// Regenerates the response up to `max_regenerations` times while `accept` rejects it.
+    ///
+    /// The last response is returned if none is accepted.
+    pub fn with_regeneration<F>(mut self, max_regenerations: u32, accept: F) -> Self
+    where
+        F: Fn(&GenerationResponse) -> bool + Send + Sync + 'static,
+    {
+        self.regeneration = Some(Regeneration {
+            max_regenerations,
+            accept: Arc::new(accept),
+        });
+        self
+    }
+
+    /// Continues an answer cut off at the output token limit up to `max_continuations` times.
+    ///
+    /// While the first candidate finishes with [`FinishReason::MaxTokens`], the truncated
+    /// answer is sent back as a model turn followed by an instruction to continue it, and the
+    /// continuation is stitched onto the answer, dropping any text repeated at the seam. The
+    /// returned response carries the stitched answer in a single text part, the finish reason
+    /// of the last round trip, the usage of all round trips combined, and the number of
+    /// [`continuations`](GenerationResponse::continuations) made. Only
+    /// [`execute()`](Self::execute) continues answers.
+    pub fn auto_continue(mut self, max_continuations: u32) -> Self {
+        self.max_continuations = max_continuations;
+        self
+    }
+
+    /// Ends a streamed response as soon as a candidate finishes with `reason`.
+    ///
+    /// The chunk carrying the finish reason is still delivered (followed by
+    /// [`StreamEvent::Finished`] when streaming events), then the connection is dropped so
+    /// that trailing chunks are not downloaded.
+    #[cfg(feature = "streaming")]
+    pub fn abort_stream_on(mut self, reason: FinishReason) -> Self {
+        self.abort_on.push(reason);
+        self
+    }
+
+    /// Stops a streamed response client-side at the first match of `pattern`.
+    ///
+    /// Unlike [`with_stop_sequences()`](Self::with_stop_sequences), patterns can be
+    /// case-insensitive or regular expressions. The text is truncated where the match starts,
+    /// the chunk gets the finish reason [`FinishReason::ClientStop`], and the connection is
+    /// dropped. Only the first candidate's answer text is scanned; only streamed execution
+    /// applies client stops.
+    #[cfg(feature = "streaming")]
+    pub fn with_client_stop(mut self, pattern: StopPattern) -> Self {
+        self.client_stops.push(pattern);
+        self
+    }
+
+    /// Captures the request and its client-side options in a [`PromptBundle`].
+    ///
+    /// Output screens and regeneration checks are callbacks and are left out. A warning is
+    /// logged if the bundle embeds more inline media than one request may carry.
+    pub fn to_bundle(&self) -> PromptBundle {
+        let bundle = PromptBundle {
+            bundle_version: BUNDLE_VERSION,
+            model: self.client.model.clone(),
+            contents: self.contents.clone(),
+            system_instruction: self.sent_system_instruction(),
+            generation_config: self.generation_config.clone(),
+            safety_settings: self.safety_settings.clone(),
+            tools: self.tools.clone(),
+            tool_config: self.tool_config.clone(),
+            cached_content: self.cached_content.clone(),
+            labels: self.labels.clone(),
+            flags: BundleFlags {
+                max_continuations: self.max_continuations,
+                fallback_to_text: self.fallback_to_text,
+                transcript: self.transcript,
+                abort_on: self.abort_on.clone(),
+                extra: Default::default(),
+            },
+            extra: Default::default(),
+        };
+        bundle::warn_on_inline_size(&bundle);
+        bundle
+    }
+
+    /// Rebuilds a builder from a [`PromptBundle`], sending to the bundle's model with the
+    /// credentials and settings of `client`.
+    ///
+    /// Fails if the bundle has a newer [format version](crate::generation::bundle) than this
+    /// crate knows.
+    pub fn from_bundle(client: &Gemini, bundle: PromptBundle) -> Result<Self, ClientError> {
+        snafu::ensure!(
+            bundle.bundle_version <= BUNDLE_VERSION,
+            UnsupportedBundleVersionSnafu {
+                version: bundle.bundle_version,
+                supported: BUNDLE_VERSION,
+            }
+        );
+        let mut builder = Self::new(client.client_for(&bundle.model));
+        for content in bundle.contents {
+            builder.push_content(content, "from_bundle");
+        }
+        builder.system_instruction = bundle.system_instruction;
+        builder.generation_config = bundle.generation_config;
+        builder.safety_settings = bundle.safety_settings;
+        builder.tools = bundle.tools;
+        builder.tool_config = bundle.tool_config;
+        builder.cached_content = bundle.cached_content;
+        builder.labels = bundle.labels;
+        builder.max_continuations = bundle.flags.max_continuations;
+        builder.fallback_to_text = bundle.flags.fallback_to_text;
+        builder.transcript = bundle.flags.transcript;
+        builder.abort_on = bundle.flags.abort_on;
+        Ok(builder)
+    }
+
     /// Builds the `GenerateContentRequest`.
     pub fn build(self) -> GenerateContentRequest {
         GenerateContentRequest {
+            system_instruction: self.sent_system_instruction(),
             contents: self.contents,
             generation_config: self.generation_config,
-            safety_settings: None,
+            safety_settings: (!self.safety_settings.is_empty()).then_some(self.safety_settings),
             tools: self.tools,
             tool_config: self.tool_config,
-            system_instruction: self.system_instruction,
             cached_content: self.cached_content,
+            labels: self.labels,
+        }
+    }
+
+    /// Returns the size in bytes the request body will have on the wire, without sending it.
+    ///
+    /// Inline media counts with its base64 encoding, so this is the number to compare with
+    /// the API's request size limit or to decide between inline data and the Files API. The
+    /// size matches [`ResponseMeta::request_bytes`](crate::ResponseMeta::request_bytes) of the
+    /// response, unless [preflight](crate::GeminiBuilder::with_preflight) drops unsupported
+    /// response modalities before sending.
+    pub fn estimated_size(&self) -> usize {
+        /// Counts the bytes written instead of storing them
+        struct Counter(usize);
+
+        impl std::io::Write for Counter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0 += buf.len();
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut counter = Counter(0);
+        serde_json::to_writer(&mut counter, &self.clone().build())
+            .expect("serializing a request to a counter cannot fail");
+        counter.0
+    }
+
+    /// Fails if the generation config is out of range, see [`GenerationConfig::validate()`].
+    fn check_generation_config(&self) -> Result<(), ClientError> {
+        self.generation_config
+            .as_ref()
+            .map_or(Ok(()), GenerationConfig::validate)
+    }
+
+    /// Fails with the warnings of the kinds denied with [`deny_lints()`](Self::deny_lints), if
+    /// any.
+    fn check_lints(&self) -> Result<(), ClientError> {
+        if self.denied_lints.is_empty() {
+            return Ok(());
+        }
+        let warnings: Vec<LintWarning> = self
+            .lint()
+            .into_iter()
+            .filter(|warning| self.denied_lints.contains(&warning.kind))
+            .collect();
+        match warnings.is_empty() {
+            true => Ok(()),
+            false => LintDeniedSnafu { warnings }.fail(),
+        }
+    }
+
+    /// Fails if the request sets fields that must come from its cached content.
+    fn check_cached_content(&self) -> Result<(), ClientError> {
+        let Some(cached_content) = &self.cached_content else {
+            return Ok(());
+        };
+        let fields: Vec<&'static str> = [
+            (
+                "systemInstruction",
+                self.sent_system_instruction().is_some(),
+            ),
+            ("tools", self.tools.is_some()),
+            ("toolConfig", self.tool_config.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(field, set)| set.then_some(field))
+        .collect();
+        snafu::ensure!(
+            fields.is_empty(),
+            CachedContentConflictSnafu {
+                cached_content: cached_content.clone(),
+                fields,
+            }
+        );
+        Ok(())
+    }
+
+    /// Counts the tokens of the request.
+    ///
+    /// With [cached content](Self::with_cached_content), the cached tokens are reported
+    /// separately; [`CountTokensResponse::uncached_tokens()`] counts what this request adds.
+    #[instrument(skip_all, fields(
+        messages.parts.count = self.contents.len(),
+        cached.content.present = self.cached_content.is_some(),
+    ))]
+    pub async fn count_tokens(self) -> Result<CountTokensResponse, ClientError> {
+        let user_data = self.user_data.clone();
+        let result = async move {
+            self.check_generation_config()?;
+            self.check_cached_content()?;
+            let client = self.client.clone();
+            let provenance = self.provenance.clone();
+            client
+                .count_tokens(&self.build())
+                .await
+                .map_err(|e| Self::enrich_error(&provenance, e))
         }
+        .await;
+        result.map_err(|e| e.with_user_data(&user_data))
     }
 
     /// Executes the content generation request.
     #[instrument(skip_all, fields(
         messages.parts.count = self.contents.len(),
         tools.present = self.tools.is_some(),
-        system.instruction.present = self.system_instruction.is_some(),
+        system.instruction.present = self.sent_system_instruction().is_some(),
         cached.content.present = self.cached_content.is_some(),
     ))]
     pub async fn execute(self) -> Result<GenerationResponse, ClientError> {
-        let client = self.client.clone();
-        let request = self.build();
-        client.generate_content_raw(request).await
+        let ctx = self.call_context();
+        let result = self.execute_in(&ctx).await;
+        result.map_err(|e| e.with_user_data(&self.user_data))
+    }
+
+    /// Executes the request within `ctx`, see [`execute()`](Self::execute)
+    async fn execute_in(&self, ctx: &CallContext) -> Result<GenerationResponse, ClientError> {
+        let mut response = self.generate(ctx).await?;
+        if self.max_continuations > 0 {
+            response = self.continue_truncated(ctx, response).await?;
+        }
+        self.screen(response)
+    }
+
+    /// Follows up on an answer cut off at the output token limit, see
+    /// [`auto_continue()`](Self::auto_continue).
+    async fn continue_truncated(
+        &self,
+        ctx: &CallContext,
+        mut response: GenerationResponse,
+    ) -> Result<GenerationResponse, ClientError> {
+        let truncated = |response: &GenerationResponse| {
+            response
+                .candidates
+                .first()
+                .is_some_and(|candidate| candidate.finish_reason == Some(FinishReason::MaxTokens))
+        };
+        let ctx = ctx.with_layer(RetryLayer::Continuation);
+        let mut answer = continuation::answer_text(&response);
+        let mut usage = response.usage_metadata.clone();
+        let mut continuations = 0;
+        let mut downgraded = response.modality_downgraded;
+        while continuations < self.max_continuations && truncated(&response) {
+            tracing::debug!(continuations, "continuing truncated answer");
+            let mut follow_up = self.clone();
+            follow_up.contents = continuation::follow_up(&self.contents, &answer);
+            let next = follow_up.generate(&ctx).await?;
+            continuations += 1;
+            downgraded |= next.modality_downgraded;
+            match (&mut usage, &next.usage_metadata) {
+                (Some(usage), Some(next)) => usage.accumulate(next),
+                (None, Some(next)) => usage = Some(next.clone()),
+                _ => {}
+            }
+            if next.candidates.is_empty() {
+                break;
+            }
+            answer = continuation::stitch(&answer, &continuation::answer_text(&next));
+            response = next;
+        }
+        if continuations == 0 {
+            return Ok(response);
+        }
+        let mut response = continuation::with_answer(response, answer);
+        response.usage_metadata = usage;
+        response.continuations = continuations;
+        response.modality_downgraded = downgraded;
+        Ok(response)
+    }
+
+    /// Sends the request once, regenerating rejected responses.
+    async fn generate(&self, ctx: &CallContext) -> Result<GenerationResponse, ClientError> {
+        self.check_generation_config()?;
+        self.check_cached_content()?;
+        self.check_lints()?;
+        let mut request = self.clone().build();
+        let mut downgraded = self.preflight(&mut request)?;
+        if let Some(guard) = &self.client.context_guard {
+            guard.check(&self.client, &request).await?;
+        }
+        let mut response = match self.client.generate_content_raw(ctx, request.clone()).await {
+            Err(error)
+                if self.fallback_to_text
+                    && !downgraded
+                    && modalities::strip_rejected(&mut request, &error) =>
+            {
+                tracing::debug!("re-sending request without rejected response modalities");
+                downgraded = true;
+                self.client.generate_content_raw(ctx, request.clone()).await
+            }
+            result => result,
+        }
+        .map_err(|e| Self::enrich_error(&self.provenance, e))?;
+        if let Some(regeneration) = &self.regeneration {
+            let ctx = ctx.with_layer(RetryLayer::Regeneration);
+            for attempt in 0..regeneration.max_regenerations {
+                if (regeneration.accept)(&response) {
+                    break;
+                }
+                tracing::debug!(attempt, "regenerating rejected response");
+                response = self
+                    .client
+                    .generate_content_raw(&ctx, request.clone())
+                    .await
+                    .map_err(|e| Self::enrich_error(&self.provenance, e))?;
+            }
+        }
+        response.modality_downgraded = downgraded;
+        response.attempts = ctx.attempts();
+        response.meta = ctx.response_meta();
+        response.meta.user_data = self.user_data.clone();
+        tracing::debug!(attempts = %response.attempts, "response generated");
+        Ok(response)
+    }
+
+    /// Applies the client's preflight checks to a request, returning whether response
+    /// modalities were stripped.
+    fn preflight(&self, request: &mut GenerateContentRequest) -> Result<bool, ClientError> {
+        if !self.client.preflight {
+            return Ok(false);
+        }
+        self.client.backend.preflight(request)?;
+        modalities::preflight(&self.client.model, request, self.fallback_to_text)
+    }
+
+    /// Applies the client's output sanitizer, the safety thresholds, and the output screen, if
+    /// any, to a complete response.
+    fn screen(&self, response: GenerationResponse) -> Result<GenerationResponse, ClientError> {
+        let response = match &self.client.output_sanitizer {
+            Some(policy) => policy.apply(response),
+            None => response,
+        };
+        let response = self.safety_thresholds.apply(response)?;
+        match &self.output_screen {
+            Some(output_screen) => screen::screen_response(output_screen.as_ref(), response),
+            None => Ok(response),
+        }
+    }
+
+    /// Executes the request, running the registered handler of every function call the model
+    /// makes until it answers without calling a function.
+    ///
+    /// The registry's declarations are added to the request; with a
+    /// [tool selector](ToolRegistry::with_tool_selector), only the functions it selects for
+    /// the round trip at hand are declared. Each round trip appends the
+    /// model's function calls and the handlers' responses to the conversation. Steps the model
+    /// answers with a malformed function call are re-sent, or corrected with
+    /// [`ToolRegistry::with_malformed_call_recovery()`], and a failed code execution is retried
+    /// with [`ToolRegistry::with_code_execution_retry()`]. Fails with
+    /// [`ClientError::ToolLoopExhausted`] once [`ToolRegistry::max_steps()`] round trips have
+    /// been made.
+    ///
+    /// Every call and the response fed back for it are listed in the final response's
+    /// [`tool_transcript`](GenerationResponse::tool_transcript).
+    #[cfg(feature = "tools")]
+    #[instrument(skip_all, fields(tools = registry.declarations().len()))]
+    pub async fn execute_with_tools(
+        mut self,
+        registry: &ToolRegistry,
+    ) -> Result<GenerationResponse, ClientError> {
+        let result = self.run_tools(registry, None).await;
+        result.map_err(|e| e.with_user_data(&self.user_data))
+    }
+
+    /// Executes the request like [`execute_with_tools()`](Self::execute_with_tools), returning
+    /// a [`RunLog`] of the run alongside the result.
+    ///
+    /// The log is returned whether or not the run succeeded; arguments of function calls are
+    /// scrubbed as `options` set.
+    #[cfg(feature = "tools")]
+    #[instrument(skip_all, fields(tools = registry.declarations().len()))]
+    pub async fn execute_with_tools_logged(
+        mut self,
+        registry: &ToolRegistry,
+        options: RunLogOptions,
+    ) -> (Result<GenerationResponse, ClientError>, RunLog) {
+        let mut recorder = RunRecorder::new(options);
+        let result = self.run_tools(registry, Some(&mut recorder)).await;
+        let log = recorder.finish(&result);
+        (result.map_err(|e| e.with_user_data(&self.user_data)), log)
+    }
+
+    /// Runs the tool loop of [`execute_with_tools()`](Self::execute_with_tools), leaving the
+    /// function calls and responses of every step in `self.contents` and recording the run in
+    /// `log`, if any.
+    #[cfg(feature = "tools")]
+    pub(crate) async fn run_tools(
+        &mut self,
+        registry: &ToolRegistry,
+        mut log: Option<&mut RunRecorder>,
+    ) -> Result<GenerationResponse, ClientError> {
+        let base_tools = self.tools.clone();
+        let ctx = self.call_context();
+        let clock = self.client.clock.clone();
+        let mut transcript = Vec::new();
+        let mut malformed = 0;
+        let mut code_retried = false;
+        for step in 0..registry.max_steps() {
+            let step_ctx = match step {
+                0 => ctx.clone(),
+                _ => ctx.with_layer(RetryLayer::ToolLoop),
+            };
+            let active = registry.select(&ToolLoopState {
+                step,
+                contents: &self.contents,
+            });
+            self.tools = base_tools.clone();
+            if let Some(tool) = active.tool() {
+                self.tools.get_or_insert_with(Vec::new).push(tool);
+            }
+            if let Some(seed) = self.seed_strategy.seed_for_turn(step) {
+                self.generation_config
+                    .get_or_insert_with(Default::default)
+                    .seed = Some(seed);
+            }
+            if let Some(log) = log.as_deref_mut() {
+                log.request(
+                    step,
+                    &self.clone().build(),
+                    self.provenance.system_fragments(),
+                );
+            }
+            let started = clock.now();
+            let result = self.generate(&step_ctx).await;
+            if let Some(log) = log.as_deref_mut() {
+                log.attempts(step, &step_ctx.attempts());
+            }
+            let response = result?;
+            if let Some(log) = log.as_deref_mut() {
+                let took = clock.now().saturating_duration_since(started);
+                log.response(step, &response, took);
+            }
+            let Some(candidate) = response.candidates.first() else {
+                return self.screen(response).map(|response| GenerationResponse {
+                    tool_transcript: transcript,
+                    ..response
+                });
+            };
+            if let Some(raw) = candidate.malformed_function_call() {
+                let Some(retries) = registry.malformed_call_retries() else {
+                    tracing::debug!(step, "re-sending step after malformed function call");
+                    continue;
+                };
+                if malformed == retries {
+                    return MalformedFunctionCallSnafu { raw }.fail();
+                }
+                malformed += 1;
+                tracing::debug!(step, malformed, "correcting malformed function call");
+                let correction =
+                    malformed_call_correction(&raw, self.tools.as_deref().unwrap_or_default());
+                self.push_content(correction, "execute_with_tools");
+                continue;
+            }
+            malformed = 0;
+            let calls: Vec<FunctionCall> = candidate
+                .content
+                .parts
+                .iter()
+                .flatten()
+                .filter_map(|part| match part {
+                    Part::FunctionCall { function_call, .. } => {
+                        tracing::debug!(step, call = %part, "running function call");
+                        Some(function_call.clone())
+                    }
+                    _ => None,
+                })
+                .collect();
+            if calls.is_empty() {
+                let retry = registry.code_execution_retry() && !code_retried;
+                if let Some(result) = candidate.failed_code_execution().filter(|_| retry) {
+                    code_retried = true;
+                    tracing::debug!(step, outcome = %result.outcome, "retrying failed code");
+                    let correction = failed_code_correction(result);
+                    let model_content = candidate.content.clone().with_role(Role::Model);
+                    self.push_content(model_content, "execute_with_tools");
+                    self.push_content(correction, "execute_with_tools");
+                    continue;
+                }
+                return self.screen(response).map(|response| GenerationResponse {
+                    tool_transcript: transcript,
+                    ..response
+                });
+            }
+
+            let model_content = candidate.content.clone().with_role(Role::Model);
+            self.push_content(model_content, "execute_with_tools");
+            let runs = calls.into_iter().map(|call| {
+                let (active, clock) = (&active, &clock);
+                async move {
+                    let started = clock.now();
+                    let (response, outcome) = active.call_with_outcome(call.clone()).await;
+                    let took = clock.now().saturating_duration_since(started);
+                    (call, response, outcome, took)
+                }
+            });
+            let mut responses = Vec::new();
+            for (call, response, outcome, took) in futures::future::join_all(runs).await {
+                if let Some(log) = log.as_deref_mut() {
+                    log.tool_call(step, call.clone(), took, outcome);
+                }
+                transcript.push(ToolExchange {
+                    step,
+                    call,
+                    response: response.clone(),
+                });
+                responses.push(Part::FunctionResponse {
+                    function_response: response,
+                });
+            }
+            let content = Content {
+                parts: Some(responses),
+                role: Some(Role::User),
+            };
+            self.push_content(content, "execute_with_tools");
+        }
+        ToolLoopExhaustedSnafu {
+            steps: registry.max_steps(),
+        }
+        .fail()
+    }
+
+    /// Executes a speech generation request and returns the audio, along with its transcript
+    /// if [`with_transcript(true)`](Self::with_transcript) was set.
+    #[instrument(skip_all, fields(transcript = self.transcript))]
+    pub async fn execute_spoken(self) -> Result<SpokenResponse, ClientError> {
+        let user_data = self.user_data.clone();
+        self.speak().await.map_err(|e| e.with_user_data(&user_data))
+    }
+
+    /// Executes a speech generation request, see [`execute_spoken()`](Self::execute_spoken)
+    async fn speak(self) -> Result<SpokenResponse, ClientError> {
+        let ctx = self.call_context();
+        let response = self.execute_in(&ctx).await?;
+        let mut spoken = SpokenResponse::from_response(&response);
+        if !self.transcript {
+            return Ok(spoken);
+        }
+        let Some(audio) = spoken.audio_as_input().context(DecodeAudioSnafu)? else {
+            return Ok(spoken);
+        };
+
+        // The transcript is of the generated audio, so the call's budget covers it and no
+        // other model stands in for the transcription model
+        let transcriber = Arc::new(
+            self.client
+                .for_model(TRANSCRIPTION_MODEL)
+                .without_fallbacks(),
+        );
+        let mut request = ContentBuilder::new(transcriber);
+        let content = Content {
+            parts: Some(vec![
+                Part::InlineData { inline_data: audio },
+                Part::Text {
+                    text: TRANSCRIPTION_PROMPT.to_string(),
+                    thought: None,
+                    thought_signature: None,
+                },
+            ]),
+            role: Some(Role::User),
+        };
+        request.push_content(content, "execute_spoken");
+        let transcription = request.execute_in(&ctx).await?;
+        spoken.transcript = Some(transcription.text().trim().to_string());
+        Ok(spoken)
     }
 
     /// Executes the content generation request as a stream.
+    #[cfg(feature = "streaming")]
     #[instrument(skip_all, fields(
         messages.parts.count = self.contents.len(),
         tools.present = self.tools.is_some(),
-        system.instruction.present = self.system_instruction.is_some(),
+        system.instruction.present = self.sent_system_instruction().is_some(),
         cached.content.present = self.cached_content.is_some(),
     ))]
     pub async fn execute_stream(
         self,
     ) -> Result<impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send, ClientError>
     {
+        let user_data = self.user_data.clone();
+        let stream = self
+            .open_stream()
+            .await
+            .map_err(|e| e.with_user_data(&user_data))?;
+        let chunk_data = user_data.clone();
+        Ok(stream
+            .map_ok(move |mut chunk| {
+                chunk.meta.user_data = chunk_data.clone();
+                chunk
+            })
+            .map_err(move |e| e.with_user_data(&user_data)))
+    }
+
+    /// Sends the request of [`execute_stream()`](Self::execute_stream) and screens its stream
+    #[cfg(feature = "streaming")]
+    async fn open_stream(
+        self,
+    ) -> Result<impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send, ClientError>
+    {
+        self.check_generation_config()?;
+        self.check_cached_content()?;
+        self.check_lints()?;
         let client = self.client.clone();
-        let request = self.build();
-        client.generate_content_stream(request).await
+        let provenance = self.provenance.clone();
+        let output_screen = self.output_screen.clone();
+        let safety_thresholds = self.safety_thresholds;
+        let abort_on = self.abort_on.clone();
+        let client_stops = self.client_stops.clone();
+        let fallback_to_text = self.fallback_to_text;
+        let ctx = self.call_context();
+        let mut request = self.build();
+        if client.preflight {
+            client.backend.preflight(&request)?;
+            modalities::preflight(&client.model, &mut request, fallback_to_text)?;
+        }
+        if let Some(guard) = &client.context_guard {
+            guard.check(&client, &request).await?;
+        }
+        let stream = client
+            .generate_content_stream(&ctx, request)
+            .await
+            .map_err(|e| Self::enrich_error(&provenance, e))?;
+        let stream = sanitize::sanitize_stream(stream, client.output_sanitizer);
+        let stream = threshold::screen_stream(stream, safety_thresholds);
+        let stream = Self::screen_stream(stream, output_screen);
+        let stream = stop::client_stop(stream, client_stops);
+        Ok(stream::abort_on(stream, abort_on))
+    }
+
+    /// Executes the content generation request as a stream of [`StreamEvent`]s.
+    ///
+    /// Besides every chunk, the stream reports the finish reason of each candidate the moment
+    /// it first appears.
+    #[cfg(feature = "streaming")]
+    pub async fn execute_stream_events(
+        self,
+    ) -> Result<impl TryStream<Ok = StreamEvent, Error = ClientError> + Send + Unpin, ClientError>
+    {
+        Ok(stream::events(self.execute_stream().await?))
+    }
+
+    /// Executes the request and deserializes the answer text, a JSON value, into `T`.
+    ///
+    /// Pair this with [`with_response_schema_of()`](Self::with_response_schema_of), or with
+    /// [`with_response_schema()`](Self::with_response_schema) describing `T`. If a field fails to deserialize, e.g. a date the model wrote in an unexpected format,
+    /// [`ClientError::StructuredOutput`] names the field's path and its raw string value.
+    pub async fn execute_as<T: DeserializeOwned>(self) -> Result<T, ClientError> {
+        let user_data = self.user_data.clone();
+        let response = self.execute().await?;
+        let value: serde_json::Value = serde_json::from_str(&response.text())
+            .context(StructuredOutputSnafu {
+                path: "$",
+                raw: None::<String>,
+            })
+            .map_err(|e| e.with_user_data(&user_data))?;
+        path::from_value(&value).map_err(|error| {
+            ClientError::StructuredOutput {
+                source: error.source,
+                path: error.path,
+                raw: error.raw,
+            }
+            .with_user_data(&user_data)
+        })
+    }
+
+    /// Executes the request and parses the answer text, a TOON document, into `T`.
+    ///
+    /// The document is [extracted](toon::extract) from code fences or prose around it first.
+    /// If it fails to parse, [`ClientError::ToonOutput`] carries the whole answer text. Ask
+    /// for TOON with [`using_toon()`](Self::using_toon) so the model knows the syntax.
+    ///
+    /// Unstable, behind the `toon_wip` feature: models often answer with something close to
+    /// TOON that does not parse, and this method may change in any release.
+    #[cfg(feature = "toon_wip")]
+    pub async fn execute_toon<T: DeserializeOwned>(self) -> Result<T, ClientError> {
+        let user_data = self.user_data.clone();
+        let response = self.execute().await?;
+        let raw = response.text();
+        toon::from_str(toon::extract(&raw))
+            .context(ToonOutputSnafu { raw: raw.as_str() })
+            .map_err(|e| e.with_user_data(&user_data))
+    }
+
+    /// Executes a request whose answer is a JSON array, yielding each element as it completes.
+    ///
+    /// The first candidate's answer text is parsed incrementally, so an element is available
+    /// as soon as its last character arrives. An element that cannot be decoded into `T` is
+    /// reported as an item-level error without ending the stream. The last event is
+    /// [`ItemEvent::Finished`] with the response's metadata.
+    ///
+    /// Pair this with [`with_response_schema()`](Self::with_response_schema) describing an
+    /// array of `T`.
+    #[cfg(feature = "streaming")]
+    pub async fn execute_stream_as_items<T: DeserializeOwned + Send + 'static>(
+        self,
+    ) -> Result<impl TryStream<Ok = ItemEvent<T>, Error = ClientError> + Send + Unpin, ClientError>
+    {
+        Ok(items::items(self.execute_stream().await?))
+    }
+
+    /// Executes the request as a stream and collects the first candidate's answer text.
+    ///
+    /// Text beyond the memory cap of `policy` is spilled to a temporary file, see
+    /// [`spill`](crate::generation::spill); the file is deleted when the returned
+    /// [`TextSource`](crate::TextSource) is dropped, or right away if the stream fails.
+    #[cfg(feature = "streaming")]
+    pub async fn execute_stream_collected(
+        self,
+        policy: SpillPolicy,
+    ) -> Result<CollectedText, ClientError> {
+        spill::collect(self.execute_stream().await?, policy).await
+    }
+
+    /// Executes the request as a stream and collects the answer text of every candidate.
+    ///
+    /// Text of a candidate beyond the cap of `policy` is dropped as it arrives, see
+    /// [`aggregate`](crate::generation::aggregate). A stream failing after its first chunk fails
+    /// with [`Error::PartialStream`](ClientError::PartialStream), carrying what was received.
+    #[cfg(feature = "streaming")]
+    pub async fn execute_stream_aggregated(
+        self,
+        policy: AggregatePolicy,
+    ) -> Result<AggregatedResponse, ClientError> {
+        let clock = self.client.clock.clone();
+        let started = clock.now();
+        aggregate::aggregate(self.execute_stream().await?, policy, &*clock, started).await
+    }
+
+    /// Applies the output screen (if any) to every chunk of a stream.
+    #[cfg(feature = "streaming")]
+    pub(crate) fn screen_stream(
+        stream: impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send,
+        output_screen: Option<Arc<dyn OutputScreen>>,
+    ) -> impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send + Unpin {
+        Box::pin(async_stream::try_stream! {
+            let mut screen = output_screen.map(StreamScreen::new);
+            let stream = stream.into_stream();
+            futures::pin_mut!(stream);
+            while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
+                let chunk = chunk?;
+                match screen.as_mut() {
+                    Some(screen) => yield screen.process(chunk)?,
+                    None => yield chunk,
+                }
+            }
+            if let Some(last) = screen.as_mut().map(StreamScreen::finish).transpose()?.flatten() {
+                yield last;
+            }
+        })
     }
 }
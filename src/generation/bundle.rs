@@ -0,0 +1,141 @@
+//! Prompt bundles: requests serialized for handoff to other languages and tools.
+//!
+//! [`ContentBuilder::to_bundle()`](crate::ContentBuilder::to_bundle) captures everything a
+//! builder sends, plus the client-side options that shape the output, in a [`PromptBundle`].
+//! [`ContentBuilder::from_bundle()`](crate::ContentBuilder::from_bundle) rebuilds an
+//! equivalent builder, so a bundle can be re-run from Rust, or from any language that sends
+//! its fields as a `generateContent` request.
+//!
+//! # Format
+//!
+//! A bundle is a JSON object with camelCase fields:
+//!
+//! ```json
+//! {
+//!   "bundleVersion": 1,
+//!   "model": "models/gemini-2.5-flash",
+//!   "contents": [{"role": "user", "parts": [{"text": "Describe this image"},
+//!     {"inlineData": {"mimeType": "image/png", "data": "iVBORw0KGgo..."}}]}],
+//!   "systemInstruction": {"parts": [{"text": "Be brief"}]},
+//!   "generationConfig": {"temperature": 0.2},
+//!   "flags": {"maxContinuations": 2}
+//! }
+//! ```
+//!
+//! `contents`, `systemInstruction`, `generationConfig`, `safetySettings`, `tools`,
+//! `toolConfig`, and `cachedContent` have exactly the shape of the `generateContent` request fields of the same
+//! name. Inline media is embedded as base64; file references keep their URIs, which stay
+//! valid only as long as the files do. `flags` holds the client-side options, which have no
+//! API counterpart; absent flags take their defaults. Callbacks, such as output screens and
+//! regeneration checks, cannot be bundled.
+//!
+//! # Compatibility
+//!
+//! Fields may be added to a bundle without changing [`BUNDLE_VERSION`]; readers must ignore
+//! fields they do not know. A [`PromptBundle`] keeps unknown top-level and flag fields in its
+//! `extra` maps and writes them back out, so bundles pass through older versions of this
+//! crate unchanged. `bundleVersion` is only raised for changes older readers would misread,
+//! and [`ContentBuilder::from_bundle()`](crate::ContentBuilder::from_bundle) rejects bundles
+//! of a version it does not know.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use crate::{
+    tools::{Tool, ToolConfig},
+    Content, FinishReason, GenerationConfig, Model, Part, SafetySetting,
+};
+
+/// The bundle format version written by this crate
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Decoded size of inline media in a bundle above which a warning is logged
+///
+/// This matches the API's limit on the total size of inline data in one request.
+pub const INLINE_DATA_WARNING_BYTES: usize = 20 * 1024 * 1024;
+
+/// A serialized request, see the [module documentation](self)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptBundle {
+    /// The version of the bundle format
+    pub bundle_version: u32,
+    /// The model the request is sent to
+    pub model: Model,
+    /// The conversation
+    pub contents: Vec<Content>,
+    /// The system instruction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    /// The generation config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation_config: Option<GenerationConfig>,
+    /// The safety settings
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub safety_settings: Vec<SafetySetting>,
+    /// The tools the model can use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    /// The tool config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
+    /// The name of the cached content the request refers to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_content: Option<String>,
+    /// Labels attached to the request
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Client-side options shaping the output
+    #[serde(default)]
+    pub flags: BundleFlags,
+    /// Fields written by newer versions, kept so they are written back out
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Client-side options of a [`PromptBundle`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct BundleFlags {
+    /// See [`ContentBuilder::auto_continue()`](crate::ContentBuilder::auto_continue)
+    #[serde(skip_serializing_if = "is_zero")]
+    pub max_continuations: u32,
+    /// See [`ContentBuilder::fallback_to_text()`](crate::ContentBuilder::fallback_to_text)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub fallback_to_text: bool,
+    /// See [`ContentBuilder::with_transcript()`](crate::ContentBuilder::with_transcript)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub transcript: bool,
+    /// See [`ContentBuilder::abort_stream_on()`](crate::ContentBuilder::abort_stream_on)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub abort_on: Vec<FinishReason>,
+    /// Flags written by newer versions, kept so they are written back out
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+/// Logs a warning if the inline media of `bundle` exceeds [`INLINE_DATA_WARNING_BYTES`]
+pub(crate) fn warn_on_inline_size(bundle: &PromptBundle) {
+    let size: usize = bundle
+        .contents
+        .iter()
+        .chain(&bundle.system_instruction)
+        .flat_map(|content| content.parts.iter().flatten())
+        .filter_map(|part| match part {
+            Part::InlineData { inline_data } => Some(inline_data.decoded_len()),
+            _ => None,
+        })
+        .sum();
+    if size > INLINE_DATA_WARNING_BYTES {
+        tracing::warn!(
+            size,
+            limit = INLINE_DATA_WARNING_BYTES,
+            "prompt bundle embeds more inline data than one request may carry; consider file references"
+        );
+    }
+}
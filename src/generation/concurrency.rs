@@ -0,0 +1,332 @@
+//! Limits on the number of requests in flight at once.
+//!
+//! Pipelines sending many requests, such as
+//! [`Gemini::process_dataset()`](crate::Gemini::process_dataset), ask a [`ConcurrencyLimiter`]
+//! how many requests may be in flight and report the outcome of each request back to it.
+//! [`FixedLimiter`] always allows the same number. [`AdaptiveLimiter`] adapts the limit
+//! additively-increase/multiplicatively-decrease (AIMD): it raises the limit by one after a run
+//! of fast successes and halves it when the API reports overload (`429` or `503`) or a latency
+//! spikes past a percentile of the recent latencies.
+//!
+//! Only requests started after the last decrease can decrease the limit again, so a burst of
+//! failures from requests sent at the old limit halves it once.
+
+use futures::{stream, stream::FuturesOrdered, Future, Stream, StreamExt};
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+use crate::client::Error as ClientError;
+
+/// Decides how many requests may be in flight at once
+pub trait ConcurrencyLimiter: Send + Sync {
+    /// The number of requests that may be in flight now, at least one
+    fn limit(&self) -> usize;
+
+    /// Records how a request went
+    fn record(&self, sample: RequestSample);
+}
+
+/// How a request went, reported to a [`ConcurrencyLimiter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestSample {
+    /// When the request was sent, as measured by the client's clock
+    pub started: Instant,
+    /// Time from sending the request to receiving its outcome
+    pub latency: Duration,
+    /// What the request resulted in
+    pub outcome: RequestOutcome,
+}
+
+/// What a request resulted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestOutcome {
+    /// The request succeeded
+    Success,
+    /// The API asked to slow down with `429 Too Many Requests` or `503 Service Unavailable`
+    Overloaded,
+    /// The request failed for another reason
+    Failed,
+}
+
+impl RequestOutcome {
+    /// Classifies the result of a request, looking through user data and exhausted retries
+    /// at the error of the last attempt
+    pub(crate) fn of<T>(result: &Result<T, ClientError>) -> Self {
+        let Err(mut error) = result.as_ref() else {
+            return RequestOutcome::Success;
+        };
+        loop {
+            match error {
+                ClientError::WithUserData { source, .. }
+                | ClientError::RetriesExhausted { source, .. } => error = source,
+                ClientError::BadResponse {
+                    code: 429 | 503, ..
+                } => return RequestOutcome::Overloaded,
+                _ => return RequestOutcome::Failed,
+            }
+        }
+    }
+}
+
+/// Allows the same number of requests in flight at all times
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedLimiter(usize);
+
+impl FixedLimiter {
+    /// Allows `limit` requests in flight, at least one
+    pub fn new(limit: usize) -> Self {
+        Self(limit.max(1))
+    }
+}
+
+impl ConcurrencyLimiter for FixedLimiter {
+    fn limit(&self) -> usize {
+        self.0
+    }
+
+    fn record(&self, _sample: RequestSample) {}
+}
+
+/// Number of latencies [`AdaptiveLimiter`] computes its latency percentile from, unless set
+/// with [`AdaptiveLimiter::with_window()`]
+pub const DEFAULT_LATENCY_WINDOW: usize = 50;
+
+/// Number of recent latencies needed before [`AdaptiveLimiter`] detects latency spikes
+pub const MIN_LATENCY_SAMPLES: usize = 10;
+
+/// Adapts the number of requests in flight to the latency and errors observed, see the
+/// [module documentation](self).
+///
+/// A success is fast if its latency is at most the configured percentile of the recent
+/// latencies; [`with_increase_after()`](Self::with_increase_after) consecutive fast successes
+/// raise the limit by one. A latency greater than the percentile times the spike factor
+/// counts as a spike and halves the limit, as does an overloaded request. Other failures
+/// only end the run of fast successes.
+///
+/// Clones share the same state, so a clone kept aside reports the current limit and latency,
+/// e.g. for a dashboard.
+#[derive(Clone)]
+pub struct AdaptiveLimiter {
+    config: AdaptiveConfig,
+    state: Arc<Mutex<AdaptiveState>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AdaptiveConfig {
+    min_limit: usize,
+    max_limit: usize,
+    increase_after: u32,
+    percentile: f64,
+    spike_factor: f64,
+    window: usize,
+    smoothing: f64,
+}
+
+#[derive(Debug)]
+struct AdaptiveState {
+    limit: usize,
+    fast_successes: u32,
+    /// Latencies of the most recent requests, oldest first
+    latencies: VecDeque<Duration>,
+    smoothed_latency: Option<Duration>,
+    last_decrease: Option<Instant>,
+}
+
+impl fmt::Debug for AdaptiveLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdaptiveLimiter")
+            .field("config", &self.config)
+            .field("limit", &self.limit())
+            .field("smoothed_latency", &self.smoothed_latency())
+            .finish()
+    }
+}
+
+impl AdaptiveLimiter {
+    /// A limiter starting at `initial_limit`, adapting between one and 64 requests in flight.
+    ///
+    /// By default, 5 consecutive fast successes raise the limit, a latency above twice the
+    /// 90th percentile is a spike, and the smoothed latency weighs each new latency by 0.2.
+    pub fn new(initial_limit: usize) -> Self {
+        let config = AdaptiveConfig {
+            min_limit: 1,
+            max_limit: 64,
+            increase_after: 5,
+            percentile: 0.9,
+            spike_factor: 2.0,
+            window: DEFAULT_LATENCY_WINDOW,
+            smoothing: 0.2,
+        };
+        Self {
+            state: Arc::new(Mutex::new(AdaptiveState {
+                limit: initial_limit.clamp(config.min_limit, config.max_limit),
+                fast_successes: 0,
+                latencies: VecDeque::new(),
+                smoothed_latency: None,
+                last_decrease: None,
+            })),
+            config,
+        }
+    }
+
+    /// Keeps the limit between `min_limit` and `max_limit`, both at least one
+    pub fn with_bounds(mut self, min_limit: usize, max_limit: usize) -> Self {
+        self.config.min_limit = min_limit.max(1);
+        self.config.max_limit = max_limit.max(self.config.min_limit);
+        let mut state = self.state.lock().expect("adaptive limiter lock poisoned");
+        state.limit = state
+            .limit
+            .clamp(self.config.min_limit, self.config.max_limit);
+        drop(state);
+        self
+    }
+
+    /// Raises the limit after `successes` consecutive fast successes, at least one
+    pub fn with_increase_after(mut self, successes: u32) -> Self {
+        self.config.increase_after = successes.max(1);
+        self
+    }
+
+    /// Treats a latency above `factor` (at least 1, and 1 if NaN) times the `percentile`
+    /// (between 0 and 1) of the recent latencies as a spike
+    pub fn with_latency_spike(mut self, percentile: f64, factor: f64) -> Self {
+        self.config.percentile = percentile.clamp(0.0, 1.0);
+        self.config.spike_factor = if factor.is_nan() {
+            1.0
+        } else {
+            factor.clamp(1.0, f64::MAX)
+        };
+        self
+    }
+
+    /// Computes the latency percentile from the `window` most recent latencies; defaults to
+    /// [`DEFAULT_LATENCY_WINDOW`]
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.config.window = window.max(1);
+        self
+    }
+
+    /// Weighs each new latency by `weight` (between 0 and 1) in the smoothed latency
+    pub fn with_smoothing(mut self, weight: f64) -> Self {
+        self.config.smoothing = weight.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The exponentially smoothed latency of the recent requests, if any finished
+    pub fn smoothed_latency(&self) -> Option<Duration> {
+        self.state
+            .lock()
+            .expect("adaptive limiter lock poisoned")
+            .smoothed_latency
+    }
+
+    /// The configured percentile of the recent latencies, if any requests finished
+    pub fn percentile_latency(&self) -> Option<Duration> {
+        percentile(
+            &self
+                .state
+                .lock()
+                .expect("adaptive limiter lock poisoned")
+                .latencies,
+            self.config.percentile,
+        )
+    }
+}
+
+impl ConcurrencyLimiter for AdaptiveLimiter {
+    fn limit(&self) -> usize {
+        self.state
+            .lock()
+            .expect("adaptive limiter lock poisoned")
+            .limit
+    }
+
+    fn record(&self, sample: RequestSample) {
+        let config = &self.config;
+        let mut state = self.state.lock().expect("adaptive limiter lock poisoned");
+        let threshold = (state.latencies.len() >= MIN_LATENCY_SAMPLES)
+            .then(|| percentile(&state.latencies, config.percentile))
+            .flatten();
+        let spike = threshold
+            .is_some_and(|t| sample.latency.as_secs_f64() > t.as_secs_f64() * config.spike_factor);
+        let fast = threshold.is_none_or(|t| sample.latency <= t);
+
+        state.smoothed_latency = Some(match state.smoothed_latency {
+            Some(smoothed) => {
+                smoothed.mul_f64(1.0 - config.smoothing) + sample.latency.mul_f64(config.smoothing)
+            }
+            None => sample.latency,
+        });
+        if sample.outcome != RequestOutcome::Failed {
+            state.latencies.push_back(sample.latency);
+            if state.latencies.len() > config.window {
+                state.latencies.pop_front();
+            }
+        }
+
+        let stale = state
+            .last_decrease
+            .is_some_and(|decrease| sample.started < decrease);
+        let slow_down = match sample.outcome {
+            RequestOutcome::Overloaded => true,
+            RequestOutcome::Success => spike,
+            RequestOutcome::Failed => false,
+        };
+        if slow_down || sample.outcome != RequestOutcome::Success || !fast {
+            state.fast_successes = 0;
+        }
+        if slow_down && !stale {
+            state.limit = (state.limit / 2).max(config.min_limit);
+            state.last_decrease = Some(sample.started + sample.latency);
+        } else if sample.outcome == RequestOutcome::Success && fast {
+            state.fast_successes += 1;
+            if state.fast_successes >= config.increase_after {
+                state.fast_successes = 0;
+                state.limit = (state.limit + 1).min(config.max_limit);
+            }
+        }
+    }
+}
+
+/// The `percentile` (between 0 and 1) of `latencies`, if there are any
+fn percentile(latencies: &VecDeque<Duration>, percentile: f64) -> Option<Duration> {
+    let mut sorted: Vec<_> = latencies.iter().copied().collect();
+    sorted.sort_unstable();
+    let rank = (percentile * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+/// Like [`StreamExt::buffered()`], running the futures of `futures` concurrently and yielding
+/// their outputs in order, with as many in flight as `limiter` allows at the time.
+///
+/// As with `buffered()`, an output waiting for earlier ones counts as in flight.
+pub(crate) fn buffered<S>(
+    futures: S,
+    limiter: Arc<dyn ConcurrencyLimiter>,
+) -> impl Stream<Item = <S::Item as Future>::Output>
+where
+    S: Stream,
+    S::Item: Future,
+{
+    let mut futures = Box::pin(futures);
+    let mut in_flight = FuturesOrdered::new();
+    let mut exhausted = false;
+    stream::poll_fn(move |cx| {
+        while !exhausted && in_flight.len() < limiter.limit() {
+            match futures.as_mut().poll_next(cx) {
+                Poll::Ready(Some(future)) => in_flight.push_back(future),
+                Poll::Ready(None) => exhausted = true,
+                Poll::Pending => break,
+            }
+        }
+        match in_flight.poll_next_unpin(cx) {
+            Poll::Ready(None) if !exhausted => Poll::Pending,
+            poll => poll,
+        }
+    })
+}
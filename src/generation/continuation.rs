@@ -0,0 +1,113 @@
+//! Automatic continuation of answers cut off at the output token limit.
+//!
+//! When a candidate finishes with [`FinishReason::MaxTokens`](crate::FinishReason::MaxTokens),
+//! [`ContentBuilder::auto_continue()`](crate::ContentBuilder::auto_continue) sends the
+//! truncated answer back as a model turn followed by [`CONTINUE_PROMPT`], and stitches the
+//! continuation onto the answer. Models often repeat the last few words (or restart the
+//! sentence) when continuing, so [`stitch()`] drops the longest suffix of the answer the
+//! continuation starts with.
+
+use crate::{Content, GenerationResponse, Part, Role};
+
+/// Instruction sent after a truncated answer to obtain the rest of it
+pub const CONTINUE_PROMPT: &str = "Your previous answer was cut off. Continue exactly where you \
+     left off, without repeating anything and without any preamble.";
+
+/// Minimum length, in characters, of an overlap that starts in the middle of a word
+const MIN_MID_WORD_OVERLAP: usize = 12;
+
+/// Maximum length, in bytes, of the end of the answer searched for an overlap
+const MAX_SEAM: usize = 1024;
+
+/// Appends `continuation` to `previous`, dropping the overlap at the seam.
+///
+/// The overlap is the longest suffix of `previous` that `continuation` starts with. Short
+/// overlaps are only trusted when they start at a word boundary of `previous`: continuing
+/// `"The quick bro"` with `"brown fox"` yields `"The quick brown fox"`, while `"own fox"` is
+/// taken as the rest of the word.
+pub(crate) fn stitch(previous: &str, continuation: &str) -> String {
+    let seam_start = previous.len().saturating_sub(MAX_SEAM);
+    let overlap = previous
+        .char_indices()
+        .map(|(start, _)| start)
+        .filter(|&start| start >= seam_start)
+        .find(|&start| {
+            let suffix = &previous[start..];
+            continuation.starts_with(suffix)
+                && (at_word_boundary(previous, start)
+                    || suffix.chars().count() >= MIN_MID_WORD_OVERLAP)
+        })
+        .map_or(0, |start| previous.len() - start);
+    let mut stitched = String::with_capacity(previous.len() + continuation.len() - overlap);
+    stitched.push_str(previous);
+    stitched.push_str(&continuation[overlap..]);
+    stitched
+}
+
+/// Whether the text from `start` on does not continue a word that begins before it
+fn at_word_boundary(text: &str, start: usize) -> bool {
+    let before = text[..start].chars().next_back();
+    let after = text[start..].chars().next();
+    match (before, after) {
+        (Some(before), Some(after)) => !(before.is_alphanumeric() && after.is_alphanumeric()),
+        _ => true,
+    }
+}
+
+/// The answer text of the first candidate, without thoughts
+pub(crate) fn answer_text(response: &GenerationResponse) -> String {
+    response
+        .candidates
+        .first()
+        .into_iter()
+        .flat_map(|candidate| candidate.content.parts.iter().flatten())
+        .filter_map(|part| match part {
+            Part::Text {
+                text,
+                thought: None | Some(false),
+                ..
+            } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The contents of a follow-up request: the original conversation, the answer so far, and
+/// [`CONTINUE_PROMPT`]
+pub(crate) fn follow_up(contents: &[Content], answer: &str) -> Vec<Content> {
+    let mut contents = contents.to_vec();
+    contents.push(Content::text(answer).with_role(Role::Model));
+    contents.push(Content::text(CONTINUE_PROMPT).with_role(Role::User));
+    contents
+}
+
+/// Replaces the answer of the first candidate of `response` with `answer`
+pub(crate) fn with_answer(mut response: GenerationResponse, answer: String) -> GenerationResponse {
+    if let Some(candidate) = response.candidates.first_mut() {
+        let mut answer = Some(answer);
+        let parts = candidate.content.parts.get_or_insert_with(Vec::new);
+        // The stitched text replaces the first text part; the other text parts are folded into it
+        parts.retain_mut(|part| match part {
+            Part::Text {
+                text,
+                thought: None | Some(false),
+                ..
+            } => match answer.take() {
+                Some(answer) => {
+                    *text = answer;
+                    true
+                }
+                None => false,
+            },
+            _ => true,
+        });
+        if let Some(text) = answer {
+            parts.push(Part::Text {
+                text,
+                thought: None,
+                thought_signature: None,
+            });
+        }
+    }
+    response
+}
@@ -0,0 +1,223 @@
+//! Counting the tokens of a prompt before sending it.
+//!
+//! [`Gemini::count_tokens()`](crate::Gemini::count_tokens) starts a [`CountTokensBuilder`],
+//! which takes the same content-construction methods as [`ContentBuilder`] and sends the
+//! resulting request to the `countTokens` endpoint instead of generating content. The request
+//! counted is exactly the one [`generate_content()`](crate::Gemini::generate_content) would
+//! send for the same calls.
+//!
+//! To count a request built for generation, convert it with `CountTokensBuilder::from()`, or
+//! call [`ContentBuilder::count_tokens()`] directly.
+
+use std::path::Path;
+
+#[cfg(feature = "caching")]
+use crate::cache::CachedContentHandle;
+#[cfg(feature = "files")]
+use crate::files::handle::FileHandle;
+#[cfg(feature = "image")]
+use crate::generation::image::ImageError;
+use crate::{
+    client::Error as ClientError,
+    generation::{
+        audio::AudioError,
+        documents::DocumentError,
+        fragments::{FragmentError, SystemPromptComposer},
+        video::{Video, VideoError},
+        ContentBuilder, CountTokensResponse, GenerationConfig,
+    },
+    Document, FunctionDeclaration, Message, Part, Tool,
+};
+
+/// Builder for `countTokens` requests, see the [module documentation](self)
+#[derive(Clone)]
+pub struct CountTokensBuilder {
+    inner: ContentBuilder,
+}
+
+impl From<ContentBuilder> for CountTokensBuilder {
+    fn from(inner: ContentBuilder) -> Self {
+        Self { inner }
+    }
+}
+
+impl CountTokensBuilder {
+    /// Sets the system prompt, see [`ContentBuilder::with_system_prompt()`]
+    pub fn with_system_prompt(self, text: impl Into<String>) -> Self {
+        self.map(|inner| inner.with_system_prompt(text))
+    }
+
+    /// Sets the system instruction, see [`ContentBuilder::with_system_instruction()`]
+    pub fn with_system_instruction(self, text: impl Into<String>) -> Self {
+        self.map(|inner| inner.with_system_instruction(text))
+    }
+
+    /// Sets a system instruction made of several parts, see
+    /// [`ContentBuilder::with_system_instruction_parts()`]
+    pub fn with_system_instruction_parts(self, parts: impl IntoIterator<Item = Part>) -> Self {
+        self.map(|inner| inner.with_system_instruction_parts(parts))
+    }
+
+    /// Sets the system instruction composed from fragments, see
+    /// [`ContentBuilder::with_system_fragments()`]
+    pub fn with_system_fragments<S: AsRef<str>>(
+        self,
+        composer: &SystemPromptComposer,
+        ids: &[S],
+    ) -> Result<Self, FragmentError> {
+        self.try_map(|inner| inner.with_system_fragments(composer, ids))
+    }
+
+    /// Adds a user message, see [`ContentBuilder::with_user_message()`]
+    pub fn with_user_message(self, text: impl Into<String>) -> Self {
+        self.map(|inner| inner.with_user_message(text))
+    }
+
+    /// Adds a model message, see [`ContentBuilder::with_model_message()`]
+    pub fn with_model_message(self, text: impl Into<String>) -> Self {
+        self.map(|inner| inner.with_model_message(text))
+    }
+
+    /// Adds base64-encoded inline data, see [`ContentBuilder::with_inline_data()`]
+    pub fn with_inline_data(self, data: impl Into<String>, mime_type: impl Into<String>) -> Self {
+        self.map(|inner| inner.with_inline_data(data, mime_type))
+    }
+
+    /// Adds an uploaded file, see [`ContentBuilder::with_file()`]
+    #[cfg(feature = "files")]
+    pub fn with_file(self, file: &FileHandle) -> Self {
+        self.map(|inner| inner.with_file(file))
+    }
+
+    /// Adds a file referenced by its URI, see [`ContentBuilder::with_file_uri()`]
+    pub fn with_file_uri(self, uri: impl Into<String>, mime_type: impl Into<String>) -> Self {
+        self.map(|inner| inner.with_file_uri(uri, mime_type))
+    }
+
+    /// Adds an image read from a file, see [`ContentBuilder::with_image_from_path()`]
+    #[cfg(feature = "image")]
+    pub fn with_image_from_path(self, path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        self.try_map(|inner| inner.with_image_from_path(path))
+    }
+
+    /// Adds an image, see [`ContentBuilder::with_image_bytes()`]
+    #[cfg(feature = "image")]
+    pub fn with_image_bytes(
+        self,
+        bytes: impl AsRef<[u8]>,
+        mime_type: impl AsRef<str>,
+    ) -> Result<Self, ImageError> {
+        self.try_map(|inner| inner.with_image_bytes(bytes, mime_type))
+    }
+
+    /// Adds a video, see [`ContentBuilder::with_video()`]
+    pub fn with_video(self, video: impl Into<Video>) -> Result<Self, VideoError> {
+        self.try_map(|inner| inner.with_video(video))
+    }
+
+    /// Adds an audio clip read from a file, see [`ContentBuilder::with_audio_from_path()`]
+    pub fn with_audio_from_path(self, path: impl AsRef<Path>) -> Result<Self, AudioError> {
+        self.try_map(|inner| inner.with_audio_from_path(path))
+    }
+
+    /// Adds an audio clip, see [`ContentBuilder::with_audio_bytes()`]
+    pub fn with_audio_bytes(
+        self,
+        bytes: impl AsRef<[u8]>,
+        mime_type: impl AsRef<str>,
+    ) -> Result<Self, AudioError> {
+        self.try_map(|inner| inner.with_audio_bytes(bytes, mime_type))
+    }
+
+    /// Adds a document read from a file, see [`ContentBuilder::with_document_from_path()`]
+    pub fn with_document_from_path(self, path: impl AsRef<Path>) -> Result<Self, DocumentError> {
+        self.try_map(|inner| inner.with_document_from_path(path))
+    }
+
+    /// Adds a document, see [`ContentBuilder::with_document_bytes()`]
+    pub fn with_document_bytes(
+        self,
+        bytes: impl AsRef<[u8]>,
+        mime_type: impl AsRef<str>,
+    ) -> Result<Self, DocumentError> {
+        self.try_map(|inner| inner.with_document_bytes(bytes, mime_type))
+    }
+
+    /// Adds a function response, see [`ContentBuilder::with_function_response()`]
+    pub fn with_function_response<Response: serde::Serialize>(
+        self,
+        name: impl Into<String>,
+        response: Response,
+    ) -> Result<Self, serde_json::Error> {
+        self.try_map(|inner| inner.with_function_response(name, response))
+    }
+
+    /// Adds a user message holding a TOON table, see
+    /// [`ContentBuilder::with_toon_table_message()`]
+    #[cfg(feature = "toon")]
+    pub fn with_toon_table_message<T: serde::Serialize>(
+        self,
+        rows: &[T],
+    ) -> Result<Self, ClientError> {
+        self.try_map(|inner| inner.with_toon_table_message(rows))
+    }
+
+    /// Adds a message, see [`ContentBuilder::with_message()`]
+    pub fn with_message(self, message: Message) -> Self {
+        self.map(|inner| inner.with_message(message))
+    }
+
+    /// Adds several messages, see [`ContentBuilder::with_messages()`]
+    pub fn with_messages(self, messages: impl IntoIterator<Item = Message>) -> Self {
+        self.map(|inner| inner.with_messages(messages))
+    }
+
+    /// Adds source documents, see [`ContentBuilder::with_documents()`]
+    pub fn with_documents(self, documents: impl IntoIterator<Item = Document>) -> Self {
+        self.map(|inner| inner.with_documents(documents))
+    }
+
+    /// Uses cached content, see [`ContentBuilder::with_cached_content()`]
+    #[cfg(feature = "caching")]
+    pub fn with_cached_content(self, cached_content: &CachedContentHandle) -> Self {
+        self.map(|inner| inner.with_cached_content(cached_content))
+    }
+
+    /// Adds a tool, see [`ContentBuilder::with_tool()`]
+    pub fn with_tool(self, tool: Tool) -> Self {
+        self.map(|inner| inner.with_tool(tool))
+    }
+
+    /// Adds several tools, see [`ContentBuilder::with_tools()`]
+    pub fn with_tools(self, tools: impl IntoIterator<Item = Tool>) -> Self {
+        self.map(|inner| inner.with_tools(tools))
+    }
+
+    /// Adds a function declaration as a tool, see [`ContentBuilder::with_function()`]
+    pub fn with_function(self, function: FunctionDeclaration) -> Self {
+        self.map(|inner| inner.with_function(function))
+    }
+
+    /// Sets the generation configuration, see [`ContentBuilder::with_generation_config()`]
+    pub fn with_generation_config(self, config: GenerationConfig) -> Self {
+        self.map(|inner| inner.with_generation_config(config))
+    }
+
+    /// Counts the tokens of the request, see [`ContentBuilder::count_tokens()`]
+    pub async fn execute(self) -> Result<CountTokensResponse, ClientError> {
+        self.inner.count_tokens().await
+    }
+
+    fn map(self, f: impl FnOnce(ContentBuilder) -> ContentBuilder) -> Self {
+        Self {
+            inner: f(self.inner),
+        }
+    }
+
+    fn try_map<E>(
+        self,
+        f: impl FnOnce(ContentBuilder) -> Result<ContentBuilder, E>,
+    ) -> Result<Self, E> {
+        f(self.inner).map(|inner| Self { inner })
+    }
+}
@@ -0,0 +1,283 @@
+//! Feeding the rows of a large dataset through a prompt, many rows per request.
+//!
+//! [`Gemini::process_dataset()`](crate::Gemini::process_dataset) formats every row and packs
+//! consecutive rows greedily into requests, as many as fit the [`TokenBudget`] of one request.
+//! Rows are read from the iterator only as requests are sent, so the dataset need not fit in
+//! memory. The prompt of a request is the template with `{rows}` replaced by its rows, one per
+//! line; a template without the placeholder is followed by the rows.
+//!
+//! Token counts are estimated locally with a [`TokenEstimator`], by adding the estimates of the
+//! template and of each row. A row that does not fit into a request on its own is not sent; it
+//! is reported in order with [`Error::RowExceedsBudget`](crate::ClientError::RowExceedsBudget).
+//!
+//! A fixed number of requests is in flight at once, unless an
+//! [`AdaptiveLimiter`](crate::AdaptiveLimiter) or another [`ConcurrencyLimiter`] is set with
+//! [`DatasetBuilder::with_limiter()`].
+
+use futures::{stream, Stream, StreamExt};
+use std::{fmt, iter::Enumerate, ops::Range, sync::Arc};
+
+use crate::{
+    client::Error as ClientError,
+    generation::concurrency::{
+        self, ConcurrencyLimiter, FixedLimiter, RequestOutcome, RequestSample,
+    },
+    Gemini, GenerationResponse,
+};
+
+/// Placeholder of a dataset template replaced by the rows of a request
+pub const ROWS_PLACEHOLDER: &str = "{rows}";
+
+/// Number of requests of a dataset in flight at once, unless set with
+/// [`DatasetBuilder::with_concurrency()`]
+pub const DEFAULT_DATASET_CONCURRENCY: usize = 4;
+
+/// Estimates the number of tokens of a text
+pub trait TokenEstimator: Send + Sync {
+    /// The estimated number of tokens of `text`
+    fn estimate(&self, text: &str) -> usize;
+}
+
+impl<F: Fn(&str) -> usize + Send + Sync> TokenEstimator for F {
+    fn estimate(&self, text: &str) -> usize {
+        self(text)
+    }
+}
+
+/// Estimates tokens with the heuristic of [`estimate_tokens()`](crate::embedding::chunker::estimate_tokens)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeuristicEstimator;
+
+impl TokenEstimator for HeuristicEstimator {
+    fn estimate(&self, text: &str) -> usize {
+        crate::embedding::chunker::estimate_tokens(text)
+    }
+}
+
+/// The number of prompt tokens one request may use
+#[derive(Clone)]
+pub struct TokenBudget {
+    max_tokens: usize,
+    estimator: Arc<dyn TokenEstimator>,
+}
+
+impl fmt::Debug for TokenBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenBudget")
+            .field("max_tokens", &self.max_tokens)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TokenBudget {
+    /// A budget of `max_tokens` prompt tokens per request, estimated with
+    /// [`HeuristicEstimator`]
+    pub fn new(max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            estimator: Arc::new(HeuristicEstimator),
+        }
+    }
+
+    /// Estimates tokens with `estimator`, e.g. one calibrated against
+    /// [`count_tokens()`](crate::ContentBuilder::count_tokens)
+    pub fn with_estimator(mut self, estimator: impl TokenEstimator + 'static) -> Self {
+        self.estimator = Arc::new(estimator);
+        self
+    }
+
+    /// The number of prompt tokens one request may use
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+}
+
+/// Builder of a dataset run, see [`Gemini::process_dataset()`]
+pub struct DatasetBuilder<I, F> {
+    client: Gemini,
+    rows: I,
+    format_row: F,
+    budget: TokenBudget,
+    template: String,
+    limiter: Arc<dyn ConcurrencyLimiter>,
+}
+
+impl<T, I, F> DatasetBuilder<I, F>
+where
+    I: Iterator<Item = T> + Send + 'static,
+    F: Fn(&T) -> String + Send + 'static,
+{
+    pub(crate) fn new(
+        client: Gemini,
+        rows: I,
+        format_row: F,
+        budget: TokenBudget,
+        template: String,
+    ) -> Self {
+        Self {
+            client,
+            rows,
+            format_row,
+            budget,
+            template,
+            limiter: Arc::new(FixedLimiter::new(DEFAULT_DATASET_CONCURRENCY)),
+        }
+    }
+
+    /// Sends at most `concurrency` requests at once; defaults to
+    /// [`DEFAULT_DATASET_CONCURRENCY`].
+    pub fn with_concurrency(self, concurrency: usize) -> Self {
+        self.with_limiter(FixedLimiter::new(concurrency))
+    }
+
+    /// Lets `limiter` decide how many requests are in flight at once, e.g. an
+    /// [`AdaptiveLimiter`](crate::AdaptiveLimiter) following the API's latency and overload
+    /// errors.
+    ///
+    /// The limiter is told the outcome of every request as measured by the client's clock.
+    pub fn with_limiter(mut self, limiter: impl ConcurrencyLimiter + 'static) -> Self {
+        self.limiter = Arc::new(limiter);
+        self
+    }
+
+    /// Runs the dataset, yielding the range of row indices of every request with its outcome,
+    /// in dataset order.
+    ///
+    /// Responses arriving early are held back until the ones before them have been yielded.
+    pub fn execute(
+        self,
+    ) -> impl Stream<Item = (Range<usize>, Result<GenerationResponse, ClientError>)> + Send {
+        let client = self.client;
+        let template = self.template;
+        let limiter = self.limiter.clone();
+        let packer = Packer::new(self.rows, self.format_row, self.budget, &template);
+        let jobs = stream::iter(packer).map(move |batch| {
+            let job = match batch {
+                Batch::Rows { range, rows } => Ok((
+                    range,
+                    client
+                        .generate_content()
+                        .with_user_message(render(&template, &rows)),
+                )),
+                Batch::Oversized {
+                    row,
+                    estimated_tokens,
+                    max_tokens,
+                } => Err((
+                    row..row + 1,
+                    ClientError::RowExceedsBudget {
+                        row,
+                        estimated_tokens,
+                        max_tokens,
+                    },
+                )),
+            };
+            let clock = client.clock();
+            let limiter = limiter.clone();
+            async move {
+                match job {
+                    Ok((range, request)) => {
+                        let started = clock.now();
+                        let result = request.execute().await;
+                        limiter.record(RequestSample {
+                            started,
+                            latency: clock.now().saturating_duration_since(started),
+                            outcome: RequestOutcome::of(&result),
+                        });
+                        (range, result)
+                    }
+                    Err((range, error)) => (range, Err(error)),
+                }
+            }
+        });
+        concurrency::buffered(jobs, self.limiter)
+    }
+}
+
+/// Replaces the placeholder of `template` with `rows`, one per line
+fn render(template: &str, rows: &[String]) -> String {
+    let rows = rows.join("\n");
+    match template.contains(ROWS_PLACEHOLDER) {
+        true => template.replace(ROWS_PLACEHOLDER, &rows),
+        false => format!("{template}\n\n{rows}"),
+    }
+}
+
+/// Rows packed into one request, or a row too large for any request
+#[derive(Debug, PartialEq)]
+pub(crate) enum Batch {
+    Rows {
+        range: Range<usize>,
+        rows: Vec<String>,
+    },
+    Oversized {
+        row: usize,
+        estimated_tokens: usize,
+        max_tokens: usize,
+    },
+}
+
+/// Packs formatted rows greedily into batches
+pub(crate) struct Packer<I, F> {
+    rows: Enumerate<I>,
+    format_row: F,
+    budget: TokenBudget,
+    template_tokens: usize,
+    /// A row read but left for the next batch: its index, text, and estimated tokens
+    pending: Option<(usize, String, usize)>,
+}
+
+impl<T, I: Iterator<Item = T>, F: Fn(&T) -> String> Packer<I, F> {
+    pub(crate) fn new(rows: I, format_row: F, budget: TokenBudget, template: &str) -> Self {
+        let template_tokens = budget
+            .estimator
+            .estimate(&template.replace(ROWS_PLACEHOLDER, ""));
+        Self {
+            rows: rows.enumerate(),
+            format_row,
+            budget,
+            template_tokens,
+            pending: None,
+        }
+    }
+
+    fn next_row(&mut self) -> Option<(usize, String, usize)> {
+        let (index, row) = self.rows.next()?;
+        let text = (self.format_row)(&row);
+        let tokens = self.budget.estimator.estimate(&text);
+        Some((index, text, tokens))
+    }
+}
+
+impl<T, I: Iterator<Item = T>, F: Fn(&T) -> String> Iterator for Packer<I, F> {
+    type Item = Batch;
+
+    fn next(&mut self) -> Option<Batch> {
+        let max_tokens = self.budget.max_tokens;
+        let mut start = None;
+        let mut rows = Vec::new();
+        let mut used = self.template_tokens;
+        while let Some((index, text, tokens)) = self.pending.take().or_else(|| self.next_row()) {
+            let alone = self.template_tokens + tokens;
+            if alone > max_tokens && rows.is_empty() {
+                return Some(Batch::Oversized {
+                    row: index,
+                    estimated_tokens: alone,
+                    max_tokens,
+                });
+            }
+            if used + tokens > max_tokens {
+                self.pending = Some((index, text, tokens));
+                break;
+            }
+            start.get_or_insert(index);
+            rows.push(text);
+            used += tokens;
+        }
+        let start = start?;
+        Some(Batch::Rows {
+            range: start..start + rows.len(),
+            rows,
+        })
+    }
+}
@@ -0,0 +1,214 @@
+//! Source documents supplied inline with a request.
+//!
+//! Documents are rendered as text parts wrapped in `<document id="...">` tags so that the
+//! model (and any answer schema) can refer to them by identifier.
+//!
+//! Document files, PDFs and plain text, Markdown, or CSV files, are sent as they are with
+//! [`ContentBuilder::with_document_from_path()`](crate::ContentBuilder::with_document_from_path)
+//! and [`ContentBuilder::with_document_bytes()`](crate::ContentBuilder::with_document_bytes),
+//! base64-encoded into an `inlineData` part that joins the preceding user turn:
+//!
+//! ```no_run
+//! # fn run(client: gemini_rust::Gemini) -> Result<(), gemini_rust::DocumentError> {
+//! let request = client
+//!     .generate_content()
+//!     .with_user_message("Here is the contract:")
+//!     .with_document_from_path("contract.pdf")?
+//!     .with_user_message("When can either party terminate it?");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The API's constraints are checked before anything is sent: only the types in
+//! [`DOCUMENT_MIME_TYPES`] are accepted, a PDF must start with the `%PDF-` header and have at
+//! most [`MAX_DOCUMENT_PAGES`] pages, and documents larger than [`MAX_INLINE_DOCUMENT_BYTES`]
+//! once base64-encoded fail before being read; upload them with [`Gemini::files()`](crate::Gemini::files) and add
+//! them with [`ContentBuilder::with_file()`](crate::ContentBuilder::with_file) instead.
+
+use snafu::Snafu;
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use super::inline::{self, InlineKind};
+use crate::{Content, Part, Role};
+
+/// Largest document sent inline, once base64-encoded; the API rejects requests larger than 20 MB
+pub const MAX_INLINE_DOCUMENT_BYTES: usize = 20 * 1024 * 1024;
+
+/// Most pages of a PDF the API accepts
+pub const MAX_DOCUMENT_PAGES: usize = 1000;
+
+/// Document types the API accepts
+pub const DOCUMENT_MIME_TYPES: &[&str] =
+    &["application/pdf", "text/plain", "text/markdown", "text/csv"];
+
+/// Error adding an inline document
+#[derive(Debug, Snafu)]
+pub enum DocumentError {
+    #[snafu(display("reading document '{}' failed", path.display()))]
+    ReadDocument {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "document type '{document_type}' is not supported, expected PDF, plain text, Markdown or CSV"
+    ))]
+    UnsupportedDocumentType {
+        /// The MIME type given, or the extension of the path
+        document_type: String,
+    },
+
+    #[snafu(display(
+        "document of {size} bytes once base64-encoded exceeds the inline limit of {limit} bytes, upload it with the Files API"
+    ))]
+    DocumentTooLarge { size: usize, limit: usize },
+
+    #[snafu(display("document is not a PDF, it does not start with '%PDF-'"))]
+    NotAPdf,
+
+    #[snafu(display("PDF of {pages} pages exceeds the limit of {limit} pages"))]
+    TooManyPages { pages: usize, limit: usize },
+}
+
+/// Documents, for the inline checks; the types are those of [`DOCUMENT_MIME_TYPES`]
+struct Documents;
+
+impl InlineKind for Documents {
+    const TYPES: &'static [(&'static str, &'static str)] = &[
+        ("pdf", "application/pdf"),
+        ("txt", "text/plain"),
+        ("md", "text/markdown"),
+        ("markdown", "text/markdown"),
+        ("csv", "text/csv"),
+    ];
+    const LIMIT: usize = MAX_INLINE_DOCUMENT_BYTES;
+
+    type Error = DocumentError;
+
+    fn unsupported(document_type: String) -> DocumentError {
+        DocumentError::UnsupportedDocumentType { document_type }
+    }
+
+    fn too_large(size: usize, limit: usize) -> DocumentError {
+        DocumentError::DocumentTooLarge { size, limit }
+    }
+
+    fn unreadable(path: &Path, source: std::io::Error) -> DocumentError {
+        DocumentError::ReadDocument {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+/// A source document identified by a caller-chosen id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    /// Identifier the model uses to refer to the document
+    pub id: String,
+    /// Optional human-readable title
+    pub title: Option<String>,
+    /// The document text
+    pub text: String,
+}
+
+impl Document {
+    /// Creates a new document
+    pub fn new(id: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            title: None,
+            text: text.into(),
+        }
+    }
+
+    /// Sets the document title
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Renders the document as tagged text
+    pub fn render(&self) -> String {
+        let mut rendered = format!("<document id=\"{}\"", self.id);
+        if let Some(title) = &self.title {
+            let _ = write!(rendered, " title=\"{title}\"");
+        }
+        let _ = write!(rendered, ">\n{}\n</document>", self.text);
+        rendered
+    }
+}
+
+/// Renders documents as a single user content with one text part per document
+pub(crate) fn documents_content(documents: &[Document]) -> Content {
+    Content {
+        parts: Some(
+            documents
+                .iter()
+                .map(|document| Part::Text {
+                    text: document.render(),
+                    thought: None,
+                    thought_signature: None,
+                })
+                .collect(),
+        ),
+        role: Some(Role::User),
+    }
+}
+
+/// Returns the MIME type of a document file from its extension, or `None` for types the API
+/// does not accept
+pub fn document_mime_type(path: impl AsRef<Path>) -> Option<&'static str> {
+    inline::mime_type::<Documents>(path.as_ref())
+}
+
+/// Counts the page objects of a PDF, `None` if it has none in plain sight.
+///
+/// Pages stored in compressed object streams are not seen, so the count is a lower bound.
+pub fn pdf_page_count(bytes: &[u8]) -> Option<usize> {
+    const TYPE: &[u8] = b"/Type";
+    const PAGE: &[u8] = b"/Page";
+    let mut pages = 0;
+    let mut rest = bytes;
+    while let Some(at) = rest.windows(TYPE.len()).position(|window| window == TYPE) {
+        rest = &rest[at + TYPE.len()..];
+        let value = rest.trim_ascii_start();
+        // `/Pages` is the page tree, not a page
+        if value.starts_with(PAGE)
+            && !value
+                .get(PAGE.len())
+                .is_some_and(|next| next.is_ascii_alphanumeric())
+        {
+            pages += 1;
+        }
+    }
+    (pages > 0).then_some(pages)
+}
+
+/// Encodes `bytes` as an inline document part
+pub(crate) fn document_part(bytes: &[u8], mime_type: &str) -> Result<Part, DocumentError> {
+    let mime_type = inline::accepted::<Documents>(mime_type)?;
+    inline::check_size::<Documents>(bytes.len())?;
+    if mime_type == "application/pdf" {
+        snafu::ensure!(bytes.starts_with(b"%PDF-"), NotAPdfSnafu);
+        if let Some(pages) = pdf_page_count(bytes) {
+            snafu::ensure!(
+                pages <= MAX_DOCUMENT_PAGES,
+                TooManyPagesSnafu {
+                    pages,
+                    limit: MAX_DOCUMENT_PAGES,
+                }
+            );
+        }
+    }
+    Ok(inline::part(mime_type, bytes))
+}
+
+/// Reads the document at `path` as an inline document part
+pub(crate) fn document_part_from_path(path: &Path) -> Result<Part, DocumentError> {
+    let (bytes, mime_type) = inline::read::<Documents>(path)?;
+    document_part(&bytes, mime_type)
+}
@@ -0,0 +1,205 @@
+//! System instructions composed from named, reusable fragments.
+//!
+//! Prompts often combine policy text owned by different teams: a tone guide, compliance
+//! rules, product facts. A [`SystemPromptComposer`] holds these fragments under ids such as
+//! `tone.v2`, each with a priority, and [`compose()`](SystemPromptComposer::compose) joins the
+//! requested ones into a single instruction:
+//!
+//! - fragments are ordered by descending priority, then by id, whatever order they were
+//!   registered or requested in, so the same selection always yields the same text;
+//! - the text of every fragment is trimmed, empty fragments are left out, and the rest are
+//!   joined with the [separator](SystemPromptComposer::with_separator), a blank line by default;
+//! - an id requested twice is included once.
+//!
+//! [`ContentBuilder::with_system_fragments()`](crate::ContentBuilder::with_system_fragments)
+//! sets the composed text as the system instruction and records the included ids in the
+//! request provenance, where they explain API errors about the instruction and show up in
+//! [`debug_outline()`](crate::ContentBuilder::debug_outline), and in the `request` events of a
+//! [`RunLog`](crate::RunLog).
+//!
+//! The composed text is measured with a [`TokenEstimator`]. When it exceeds the limit set with
+//! [`with_token_warning()`](SystemPromptComposer::with_token_warning), a warning is logged and
+//! [`ComposedPrompt::over_limit`] is set; the instruction is still composed.
+
+use snafu::Snafu;
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use crate::generation::dataset::{HeuristicEstimator, TokenEstimator};
+
+/// Separator between the fragments of a composed instruction, unless set with
+/// [`SystemPromptComposer::with_separator()`]
+pub const DEFAULT_FRAGMENT_SEPARATOR: &str = "\n\n";
+
+/// Error registering or composing fragments
+#[derive(Debug, Snafu, PartialEq, Eq)]
+pub enum FragmentError {
+    #[snafu(display("system prompt fragment '{id}' is already registered"))]
+    DuplicateFragment { id: String },
+
+    #[snafu(display("system prompt fragment '{id}' is not registered"))]
+    UnknownFragment { id: String },
+}
+
+/// A registered fragment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptFragment {
+    /// Priority of the fragment; fragments of higher priority come first
+    pub priority: i32,
+    /// Text of the fragment
+    pub text: String,
+}
+
+/// A system instruction composed by [`SystemPromptComposer::compose()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComposedPrompt {
+    /// The instruction text
+    pub text: String,
+    /// Ids of the fragments included, in the order of the text
+    pub fragments: Vec<String>,
+    /// Estimated number of tokens of the text
+    pub estimated_tokens: usize,
+    /// Whether the estimate exceeds the composer's token warning limit
+    pub over_limit: bool,
+}
+
+/// Registry of named system prompt fragments, see the [module documentation](self)
+#[derive(Clone)]
+pub struct SystemPromptComposer {
+    fragments: BTreeMap<String, PromptFragment>,
+    separator: String,
+    estimator: Arc<dyn TokenEstimator>,
+    token_warning: Option<usize>,
+}
+
+impl fmt::Debug for SystemPromptComposer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SystemPromptComposer")
+            .field("fragments", &self.fragments)
+            .field("separator", &self.separator)
+            .field("token_warning", &self.token_warning)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for SystemPromptComposer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SystemPromptComposer {
+    /// An empty composer, joining fragments with [`DEFAULT_FRAGMENT_SEPARATOR`] and estimating
+    /// tokens with [`HeuristicEstimator`]
+    pub fn new() -> Self {
+        Self {
+            fragments: BTreeMap::new(),
+            separator: DEFAULT_FRAGMENT_SEPARATOR.to_string(),
+            estimator: Arc::new(HeuristicEstimator),
+            token_warning: None,
+        }
+    }
+
+    /// Registers the fragment `id`, failing if the id is already registered
+    pub fn register(
+        &mut self,
+        id: impl Into<String>,
+        priority: i32,
+        text: impl Into<String>,
+    ) -> Result<&mut Self, FragmentError> {
+        let id = id.into();
+        snafu::ensure!(
+            !self.fragments.contains_key(&id),
+            DuplicateFragmentSnafu { id }
+        );
+        let text = text.into();
+        self.fragments.insert(id, PromptFragment { priority, text });
+        Ok(self)
+    }
+
+    /// Registers the fragment `id`, see [`register()`](Self::register)
+    pub fn with_fragment(
+        mut self,
+        id: impl Into<String>,
+        priority: i32,
+        text: impl Into<String>,
+    ) -> Result<Self, FragmentError> {
+        self.register(id, priority, text)?;
+        Ok(self)
+    }
+
+    /// Sets the separator placed between fragments
+    pub fn with_separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// Sets the estimator measuring composed instructions
+    pub fn with_estimator(mut self, estimator: impl TokenEstimator + 'static) -> Self {
+        self.estimator = Arc::new(estimator);
+        self
+    }
+
+    /// Warns when a composed instruction is estimated at more than `max_tokens` tokens
+    pub fn with_token_warning(mut self, max_tokens: usize) -> Self {
+        self.token_warning = Some(max_tokens);
+        self
+    }
+
+    /// The fragment registered as `id`
+    pub fn fragment(&self, id: &str) -> Option<&PromptFragment> {
+        self.fragments.get(id)
+    }
+
+    /// The registered ids, sorted
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.fragments.keys().map(String::as_str)
+    }
+
+    /// Composes the fragments `ids` into a system instruction, failing on the first id that
+    /// is not registered
+    pub fn compose<S: AsRef<str>>(&self, ids: &[S]) -> Result<ComposedPrompt, FragmentError> {
+        let mut selected = Vec::with_capacity(ids.len());
+        for id in ids {
+            let id = id.as_ref();
+            let Some((id, fragment)) = self.fragments.get_key_value(id) else {
+                return UnknownFragmentSnafu { id }.fail();
+            };
+            selected.push((id, fragment));
+        }
+        selected.sort_by(|(a_id, a), (b_id, b)| b.priority.cmp(&a.priority).then(a_id.cmp(b_id)));
+        selected.dedup_by_key(|(id, _)| *id);
+
+        let mut text = String::new();
+        let mut fragments = Vec::with_capacity(selected.len());
+        for (id, fragment) in selected {
+            let fragment_text = fragment.text.trim();
+            if fragment_text.is_empty() {
+                continue;
+            }
+            if !text.is_empty() {
+                text.push_str(&self.separator);
+            }
+            text.push_str(fragment_text);
+            fragments.push(id.clone());
+        }
+
+        let estimated_tokens = self.estimator.estimate(&text);
+        let over_limit = self
+            .token_warning
+            .is_some_and(|max_tokens| estimated_tokens > max_tokens);
+        if over_limit {
+            tracing::warn!(
+                fragments = ?fragments,
+                estimated_tokens,
+                max_tokens = self.token_warning,
+                "composed system instruction exceeds the token warning limit"
+            );
+        }
+        Ok(ComposedPrompt {
+            text,
+            fragments,
+            estimated_tokens,
+            over_limit,
+        })
+    }
+}
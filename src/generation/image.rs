@@ -0,0 +1,104 @@
+//! Images sent inline with a prompt.
+//!
+//! [`ContentBuilder::with_image_from_path()`](crate::ContentBuilder::with_image_from_path) and
+//! [`ContentBuilder::with_image_bytes()`](crate::ContentBuilder::with_image_bytes) base64-encode
+//! an image into an `inlineData` part. The image joins the preceding user turn when that turn
+//! holds only text and inline images, so a question followed by its image is sent as one
+//! `Content`:
+//!
+//! ```no_run
+//! # fn run(client: gemini_rust::Gemini) -> Result<(), gemini_rust::ImageError> {
+//! let request = client
+//!     .generate_content()
+//!     .with_user_message("Describe this image")
+//!     .with_image_from_path("photo.jpg")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Only the image types the API accepts are sent, see [`image_mime_type()`]. Images larger than
+//! [`MAX_INLINE_IMAGE_BYTES`] once base64-encoded fail before being read; upload them with the
+//! [Files API](crate::files::api) instead.
+
+use snafu::Snafu;
+use std::path::{Path, PathBuf};
+
+use super::inline::{self, InlineKind};
+use crate::Part;
+
+/// Largest image sent inline, once base64-encoded; the API rejects requests larger than 20 MB
+pub const MAX_INLINE_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// Error adding an inline image
+#[derive(Debug, Snafu)]
+pub enum ImageError {
+    #[snafu(display("reading image '{}' failed", path.display()))]
+    ReadImage {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "image type '{image_type}' is not supported, expected PNG, JPEG, WebP, HEIC or HEIF"
+    ))]
+    UnsupportedImageType {
+        /// The MIME type given, or the extension of the path
+        image_type: String,
+    },
+
+    #[snafu(display(
+        "image of {size} bytes once base64-encoded exceeds the inline limit of {limit} bytes"
+    ))]
+    ImageTooLarge { size: usize, limit: usize },
+}
+
+/// Images, for the inline checks
+struct Images;
+
+impl InlineKind for Images {
+    const TYPES: &'static [(&'static str, &'static str)] = &[
+        ("png", "image/png"),
+        ("jpg", "image/jpeg"),
+        ("jpeg", "image/jpeg"),
+        ("webp", "image/webp"),
+        ("heic", "image/heic"),
+        ("heif", "image/heif"),
+    ];
+    const LIMIT: usize = MAX_INLINE_IMAGE_BYTES;
+
+    type Error = ImageError;
+
+    fn unsupported(image_type: String) -> ImageError {
+        ImageError::UnsupportedImageType { image_type }
+    }
+
+    fn too_large(size: usize, limit: usize) -> ImageError {
+        ImageError::ImageTooLarge { size, limit }
+    }
+
+    fn unreadable(path: &Path, source: std::io::Error) -> ImageError {
+        ImageError::ReadImage {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+/// Returns the MIME type of an image file from its extension, or `None` for types the API
+/// does not accept
+pub fn image_mime_type(path: impl AsRef<Path>) -> Option<&'static str> {
+    inline::mime_type::<Images>(path.as_ref())
+}
+
+/// Encodes `bytes` as an inline image part
+pub(crate) fn image_part(bytes: &[u8], mime_type: &str) -> Result<Part, ImageError> {
+    let mime_type = inline::accepted::<Images>(mime_type)?;
+    inline::check_size::<Images>(bytes.len())?;
+    Ok(inline::part(mime_type, bytes))
+}
+
+/// Reads the image at `path` as an inline image part
+pub(crate) fn image_part_from_path(path: &Path) -> Result<Part, ImageError> {
+    let (bytes, mime_type) = inline::read::<Images>(path)?;
+    image_part(&bytes, mime_type)
+}
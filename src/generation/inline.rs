@@ -0,0 +1,84 @@
+//! Media sent inline with a prompt, shared by images, audio, and documents.
+//!
+//! Each kind of media lists the file extensions and MIME types the API accepts for it and
+//! says how its errors are built; the checks, the reading, and the encoding are the same for
+//! all of them. The API limits the size of the whole request, so the size checked against the
+//! limit is that of the media once base64-encoded, see [`encoded_len()`].
+
+use base64::{engine::general_purpose, Engine as _};
+use std::path::Path;
+
+use crate::{Blob, Part};
+
+/// A kind of media sent inline
+pub(crate) trait InlineKind {
+    /// File extensions the API accepts, with the MIME type of each
+    const TYPES: &'static [(&'static str, &'static str)];
+    /// Largest encoded size sent inline
+    const LIMIT: usize;
+
+    type Error;
+
+    /// The error for a type not in [`TYPES`](Self::TYPES), given as a MIME type or extension
+    fn unsupported(given: String) -> Self::Error;
+    /// The error for media of `size` encoded bytes, more than `limit`
+    fn too_large(size: usize, limit: usize) -> Self::Error;
+    /// The error for a file that could not be read
+    fn unreadable(path: &Path, source: std::io::Error) -> Self::Error;
+}
+
+/// Size of `size` bytes once base64-encoded, with padding
+pub(crate) fn encoded_len(size: usize) -> usize {
+    size.div_ceil(3).saturating_mul(4)
+}
+
+/// Returns the MIME type of a file of kind `K` from its extension, or `None` for types the
+/// API does not accept
+pub(crate) fn mime_type<K: InlineKind>(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    K::TYPES
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, mime_type)| *mime_type)
+}
+
+/// Checks that `mime_type`, in any casing, is accepted for kind `K`, returning it lowercased
+pub(crate) fn accepted<K: InlineKind>(mime_type: &str) -> Result<String, K::Error> {
+    let mime_type = mime_type.to_ascii_lowercase();
+    match K::TYPES.iter().any(|(_, known)| *known == mime_type) {
+        true => Ok(mime_type),
+        false => Err(K::unsupported(mime_type)),
+    }
+}
+
+/// Checks that `size` bytes of kind `K` fit the inline limit once encoded
+pub(crate) fn check_size<K: InlineKind>(size: usize) -> Result<(), K::Error> {
+    let size = encoded_len(size);
+    match size <= K::LIMIT {
+        true => Ok(()),
+        false => Err(K::too_large(size, K::LIMIT)),
+    }
+}
+
+/// Reads the file at `path` as media of kind `K`, returning its bytes and MIME type.
+///
+/// The type and size are checked before the file is read.
+pub(crate) fn read<K: InlineKind>(path: &Path) -> Result<(Vec<u8>, &'static str), K::Error> {
+    let Some(mime_type) = mime_type::<K>(path) else {
+        let given = path.extension().map_or_else(String::new, |extension| {
+            extension.to_string_lossy().into_owned()
+        });
+        return Err(K::unsupported(given));
+    };
+    let metadata = std::fs::metadata(path).map_err(|source| K::unreadable(path, source))?;
+    check_size::<K>(usize::try_from(metadata.len()).unwrap_or(usize::MAX))?;
+    let bytes = std::fs::read(path).map_err(|source| K::unreadable(path, source))?;
+    Ok((bytes, mime_type))
+}
+
+/// Encodes `bytes` as an inline data part
+pub(crate) fn part(mime_type: String, bytes: &[u8]) -> Part {
+    Part::InlineData {
+        inline_data: Blob::new(mime_type, general_purpose::STANDARD.encode(bytes)),
+    }
+}
@@ -0,0 +1,292 @@
+//! Typed items of a streamed JSON array.
+//!
+//! A response asked to be a JSON array arrives as text deltas that split elements at
+//! arbitrary points. [`ArrayParser`] consumes the deltas and returns every element as soon as
+//! it is complete: objects and arrays the moment their closing bracket arrives, other values
+//! at the following `,` or `]`. It tracks strings and escapes so brackets inside strings do
+//! not count, skips anything before the opening `[` (such as a Markdown code fence), and
+//! ignores anything after the closing `]`.
+//!
+//! A malformed element is reported as an [`Error`] for that element only; the parser then
+//! resynchronizes at the next `,` at the top level of the array.
+
+use futures::{TryStream, TryStreamExt};
+use serde::de::DeserializeOwned;
+use snafu::{ResultExt, Snafu};
+
+use crate::{
+    client::Error as ClientError, json, FinishReason, GenerationResponse, Part, UsageMetadata,
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("element {index} could not be decoded"))]
+    Decode {
+        index: usize,
+        element: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("element {index} has mismatched brackets"))]
+    Malformed { index: usize, element: String },
+
+    #[snafu(display("the response ended inside element {index}"))]
+    Truncated { index: usize, element: String },
+
+    #[snafu(display("the response is not a JSON array"))]
+    NotAnArray,
+}
+
+/// An event of a stream of array items
+#[derive(Debug)]
+pub enum ItemEvent<T> {
+    /// An element of the array, or why it could not be decoded
+    Item(Result<T, Error>),
+    /// The stream ended
+    Finished(StreamSummary),
+}
+
+/// Metadata of a streamed response, collected across its chunks
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamSummary {
+    /// The finish reason of the first candidate
+    pub finish_reason: Option<FinishReason>,
+    /// Token usage, from the last chunk reporting it
+    pub usage_metadata: Option<UsageMetadata>,
+    /// The model version that generated the response
+    pub model_version: Option<String>,
+    /// The response ID
+    pub response_id: Option<String>,
+    /// The number of elements, including those that failed to decode; always zero for
+    /// [collected text](crate::ContentBuilder::execute_stream_collected)
+    pub items: usize,
+}
+
+impl StreamSummary {
+    pub(crate) fn record(&mut self, chunk: &GenerationResponse) {
+        if let Some(reason) = chunk
+            .candidates
+            .first()
+            .and_then(|c| c.finish_reason.clone())
+        {
+            self.finish_reason = Some(reason);
+        }
+        if let Some(usage) = &chunk.usage_metadata {
+            self.usage_metadata = Some(usage.clone());
+        }
+        if let Some(model_version) = &chunk.model_version {
+            self.model_version = Some(model_version.clone());
+        }
+        if let Some(response_id) = &chunk.response_id {
+            self.response_id = Some(response_id.clone());
+        }
+    }
+}
+
+/// Where the parser is in the array
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Before the opening bracket
+    Before,
+    /// Inside the array
+    Inside,
+    /// Skipping a malformed element up to the next top-level comma
+    Skipping,
+    /// After the closing bracket
+    After,
+}
+
+/// Incremental parser splitting a JSON array into its elements
+#[derive(Debug)]
+pub(crate) struct ArrayParser {
+    state: State,
+    /// Open brackets of the current element
+    stack: Vec<char>,
+    in_string: bool,
+    escaped: bool,
+    /// Text of the current element
+    element: String,
+    /// Whether the current element is complete and only a separator may follow
+    complete: bool,
+    /// Index of the current element
+    index: usize,
+}
+
+impl ArrayParser {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: State::Before,
+            stack: Vec::new(),
+            in_string: false,
+            escaped: false,
+            element: String::new(),
+            complete: false,
+            index: 0,
+        }
+    }
+
+    /// Consumes a text delta, returning the elements it completes
+    pub(crate) fn feed(&mut self, text: &str) -> Vec<Result<String, Error>> {
+        let mut elements = Vec::new();
+        for c in text.chars() {
+            match self.state {
+                State::Before => {
+                    if c == '[' {
+                        self.state = State::Inside;
+                    }
+                }
+                State::After => {}
+                State::Inside => self.inside(c, &mut elements),
+                State::Skipping => self.skipping(c),
+            }
+        }
+        elements
+    }
+
+    /// Ends the input, reporting an unfinished element or a missing array
+    pub(crate) fn finish(&mut self) -> Option<Error> {
+        match self.state {
+            State::Before => Some(Error::NotAnArray),
+            State::Inside if !self.element.trim().is_empty() => Some(Error::Truncated {
+                index: self.index,
+                element: std::mem::take(&mut self.element),
+            }),
+            _ => None,
+        }
+    }
+
+    fn inside(&mut self, c: char, elements: &mut Vec<Result<String, Error>>) {
+        if self.in_string {
+            self.element.push(c);
+            self.string(c);
+            return;
+        }
+        match c {
+            ',' | ']' if self.stack.is_empty() => {
+                if !self.complete {
+                    let element = std::mem::take(&mut self.element);
+                    // `[]` and trailing commas have no element
+                    if !element.trim().is_empty() {
+                        elements.push(Ok(element.trim().to_string()));
+                        self.index += 1;
+                    }
+                }
+                self.complete = false;
+                self.element.clear();
+                if c == ']' {
+                    self.state = State::After;
+                }
+            }
+            c if c.is_whitespace() && (self.complete || self.element.is_empty()) => {}
+            _ if self.complete => self.malformed(elements),
+            '"' => {
+                self.element.push(c);
+                self.in_string = true;
+            }
+            '{' | '[' => {
+                self.element.push(c);
+                self.stack.push(c);
+            }
+            '}' | ']' => {
+                self.element.push(c);
+                let expected = if c == '}' { '{' } else { '[' };
+                if self.stack.pop() != Some(expected) {
+                    return self.malformed(elements);
+                }
+                if self.stack.is_empty() {
+                    elements.push(Ok(std::mem::take(&mut self.element)));
+                    self.index += 1;
+                    self.complete = true;
+                }
+            }
+            c => self.element.push(c),
+        }
+    }
+
+    fn skipping(&mut self, c: char) {
+        if self.in_string {
+            self.string(c);
+            return;
+        }
+        match c {
+            '"' => self.in_string = true,
+            '{' | '[' => self.stack.push(c),
+            ',' if self.stack.is_empty() => {
+                self.state = State::Inside;
+                self.complete = false;
+            }
+            ']' if self.stack.is_empty() => self.state = State::After,
+            '}' | ']' => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn string(&mut self, c: char) {
+        if self.escaped {
+            self.escaped = false;
+        } else if c == '\\' {
+            self.escaped = true;
+        } else if c == '"' {
+            self.in_string = false;
+        }
+    }
+
+    /// Reports the current element and skips to the next top-level comma
+    fn malformed(&mut self, elements: &mut Vec<Result<String, Error>>) {
+        // Text trailing a complete element counts as an element of its own
+        let element = std::mem::take(&mut self.element);
+        elements.push(Err(Error::Malformed {
+            index: self.index,
+            element,
+        }));
+        self.index += 1;
+        self.stack.clear();
+        self.state = State::Skipping;
+    }
+}
+
+/// Decodes the elements of the JSON array streamed in the first candidate's answer text
+pub(crate) fn items<T: DeserializeOwned + Send + 'static>(
+    stream: impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send,
+) -> impl TryStream<Ok = ItemEvent<T>, Error = ClientError> + Send + Unpin {
+    Box::pin(async_stream::try_stream! {
+        let mut parser = ArrayParser::new();
+        let mut summary = StreamSummary::default();
+        let stream = stream.into_stream();
+        futures::pin_mut!(stream);
+        let mut index = 0;
+        while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
+            let chunk = chunk?;
+            summary.record(&chunk);
+            let text: String = chunk
+                .candidates
+                .first()
+                .and_then(|candidate| candidate.content.parts.as_ref())
+                .into_iter()
+                .flatten()
+                .filter_map(|part| match part {
+                    Part::Text { text, thought: None | Some(false), .. } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect();
+            for element in parser.feed(&text) {
+                summary.items += 1;
+                yield ItemEvent::Item(element.and_then(|element| decode(index, element)));
+                index += 1;
+            }
+        }
+        if let Some(error) = parser.finish() {
+            if matches!(error, Error::Truncated { .. }) {
+                summary.items += 1;
+            }
+            yield ItemEvent::Item(Err(error));
+        }
+        yield ItemEvent::Finished(summary);
+    })
+}
+
+fn decode<T: DeserializeOwned>(index: usize, element: String) -> Result<T, Error> {
+    json::from_str(&element).context(DecodeSnafu { index, element })
+}
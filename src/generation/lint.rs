@@ -0,0 +1,385 @@
+//! Offline checks of a request for common prompt mistakes.
+//!
+//! Some requests are sure to waste quota: an empty message, a `{{placeholder}}` a template
+//! left unrendered, the same user turn sent twice. [`ContentBuilder::lint()`] runs a set of
+//! [`LintRule`]s over the request it would send and returns what they found, without sending
+//! anything:
+//!
+//! | Rule | Finds | Severity |
+//! |------|-------|----------|
+//! | [`EmptyMessages`] | a message without any part, or with blank text only | error |
+//! | [`UnresolvedPlaceholders`] | `{{name}}` left in the text | error |
+//! | [`DuplicateTurns`] | a user turn identical to an earlier one | warning |
+//! | [`SystemInstructionAsUserMessage`] | a first user message reading like a system instruction, e.g. "You are a helpful assistant" | warning |
+//! | [`JsonModeWithProse`] | a JSON response requested from a prompt asking for prose | warning |
+//!
+//! [`ContentBuilder::deny_lints()`] turns the warnings of chosen kinds into an error: sending
+//! fails with [`ClientError::LintDenied`](crate::ClientError::LintDenied) before the request
+//! leaves the client. Applications add house rules with
+//! [`ContentBuilder::with_lint_rule()`], reporting warnings of kind [`LintKind::Custom`]:
+//!
+//! ```
+//! use gemini_rust::{GenerateContentRequest, LintKind, LintRule, LintSeverity, LintWarning};
+//!
+//! /// Every prompt must name the product
+//! struct NamesTheProduct;
+//!
+//! impl LintRule for NamesTheProduct {
+//!     fn check(&self, request: &GenerateContentRequest) -> Vec<LintWarning> {
+//!         let text = serde_json::to_string(&request.contents).unwrap_or_default();
+//!         match text.contains("Acme") {
+//!             true => Vec::new(),
+//!             false => vec![LintWarning::new(
+//!                 LintKind::Custom("product-name".into()),
+//!                 LintSeverity::Error,
+//!                 "the prompt does not name the product",
+//!             )],
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! [`ContentBuilder::lint()`]: crate::ContentBuilder::lint
+//! [`ContentBuilder::deny_lints()`]: crate::ContentBuilder::deny_lints
+//! [`ContentBuilder::with_lint_rule()`]: crate::ContentBuilder::with_lint_rule
+
+use regex::Regex;
+use std::{fmt, sync::LazyLock};
+
+use crate::{Content, GenerateContentRequest, Part, Role};
+
+/// Matches a `{{placeholder}}` of a template, e.g. `{{ user.name }}`
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\{\{\s*[A-Za-z_][\w.-]*\s*\}\}").expect("placeholder pattern is valid")
+});
+
+/// Openings of a system instruction, lowercase
+const INSTRUCTION_OPENINGS: &[&str] = &[
+    "you are a ",
+    "you are an ",
+    "act as ",
+    "your role is ",
+    "as an ai assistant",
+];
+
+/// Requests for prose, lowercase
+const PROSE_REQUESTS: &[&str] = &[
+    "in prose",
+    "in plain text",
+    "as plain text",
+    "in full sentences",
+    "do not use json",
+    "don't use json",
+    "without json",
+];
+
+/// What a [`LintWarning`] is about
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LintKind {
+    /// A message without any part, or whose text is blank
+    EmptyMessage,
+    /// A `{{placeholder}}` left in the text
+    UnresolvedPlaceholder,
+    /// A user turn identical to an earlier one
+    DuplicateTurn,
+    /// A user message reading like a system instruction
+    SystemInstructionAsUserMessage,
+    /// A JSON response requested from a prompt asking for prose
+    JsonModeWithProse,
+    /// A warning of a rule added by the application, named by it
+    Custom(String),
+}
+
+impl fmt::Display for LintKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintKind::EmptyMessage => f.write_str("empty-message"),
+            LintKind::UnresolvedPlaceholder => f.write_str("unresolved-placeholder"),
+            LintKind::DuplicateTurn => f.write_str("duplicate-turn"),
+            LintKind::SystemInstructionAsUserMessage => {
+                f.write_str("system-instruction-as-user-message")
+            }
+            LintKind::JsonModeWithProse => f.write_str("json-mode-with-prose"),
+            LintKind::Custom(name) => f.write_str(name),
+        }
+    }
+}
+
+/// How likely a [`LintWarning`] is to make the request fail or waste its answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LintSeverity {
+    /// Worth a look
+    Info,
+    /// Probably a mistake
+    Warning,
+    /// Certainly a mistake
+    Error,
+}
+
+impl fmt::Display for LintSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LintSeverity::Info => "info",
+            LintSeverity::Warning => "warning",
+            LintSeverity::Error => "error",
+        })
+    }
+}
+
+/// A possible mistake found in a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintWarning {
+    /// What the warning is about
+    pub kind: LintKind,
+    /// How serious it is
+    pub severity: LintSeverity,
+    /// What was found
+    pub message: String,
+    /// Where it was found, e.g. `contents[2].parts[0]` or `system_instruction`
+    pub location: Option<String>,
+}
+
+impl LintWarning {
+    /// A warning not tied to a location
+    pub fn new(kind: LintKind, severity: LintSeverity, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            severity,
+            message: message.into(),
+            location: None,
+        }
+    }
+
+    /// Sets where the warning was found
+    pub fn at(mut self, location: impl Into<String>) -> Self {
+        self.location = Some(location.into());
+        self
+    }
+}
+
+/// Formatted as `error[empty-message] at contents[0]: message has no text`
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]", self.severity, self.kind)?;
+        if let Some(location) = &self.location {
+            write!(f, " at {location}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// A check of a request, see the [module documentation](self)
+pub trait LintRule: Send + Sync {
+    /// Returns the warnings about `request`, the request as it would be sent
+    fn check(&self, request: &GenerateContentRequest) -> Vec<LintWarning>;
+}
+
+/// The rules [`ContentBuilder::lint()`](crate::ContentBuilder::lint) always runs
+pub fn default_rules() -> Vec<Box<dyn LintRule>> {
+    vec![
+        Box::new(EmptyMessages),
+        Box::new(UnresolvedPlaceholders),
+        Box::new(DuplicateTurns),
+        Box::new(SystemInstructionAsUserMessage),
+        Box::new(JsonModeWithProse),
+    ]
+}
+
+/// Finds messages without any part, or whose parts are all blank text
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmptyMessages;
+
+impl LintRule for EmptyMessages {
+    fn check(&self, request: &GenerateContentRequest) -> Vec<LintWarning> {
+        let blank = |content: &Content| {
+            content.parts.iter().flatten().all(|part| match part {
+                Part::Text { text, thought, .. } => {
+                    *thought == Some(true) || text.trim().is_empty()
+                }
+                _ => false,
+            })
+        };
+        let mut warnings = Vec::new();
+        if request.system_instruction.as_ref().is_some_and(blank) {
+            warnings.push(
+                LintWarning::new(
+                    LintKind::EmptyMessage,
+                    LintSeverity::Error,
+                    "system instruction has no text",
+                )
+                .at("system_instruction"),
+            );
+        }
+        for (index, content) in request.contents.iter().enumerate() {
+            if blank(content) {
+                warnings.push(
+                    LintWarning::new(
+                        LintKind::EmptyMessage,
+                        LintSeverity::Error,
+                        format!("{} message has no text", role_name(content)),
+                    )
+                    .at(format!("contents[{index}]")),
+                );
+            }
+        }
+        warnings
+    }
+}
+
+/// Finds `{{placeholders}}` left in the text by a template that was not fully rendered
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnresolvedPlaceholders;
+
+impl LintRule for UnresolvedPlaceholders {
+    fn check(&self, request: &GenerateContentRequest) -> Vec<LintWarning> {
+        texts(request)
+            .filter_map(|(location, text)| {
+                let found: Vec<&str> = PLACEHOLDER.find_iter(text).map(|m| m.as_str()).collect();
+                (!found.is_empty()).then(|| {
+                    LintWarning::new(
+                        LintKind::UnresolvedPlaceholder,
+                        LintSeverity::Error,
+                        format!("unresolved placeholder(s) {}", found.join(", ")),
+                    )
+                    .at(location)
+                })
+            })
+            .collect()
+    }
+}
+
+/// Finds user turns identical to an earlier user turn, e.g. a message added twice
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DuplicateTurns;
+
+impl LintRule for DuplicateTurns {
+    fn check(&self, request: &GenerateContentRequest) -> Vec<LintWarning> {
+        let user_turns: Vec<(usize, &Content)> = request
+            .contents
+            .iter()
+            .enumerate()
+            .filter(|(_, content)| matches!(content.role, Some(Role::User) | None))
+            .filter(|(_, content)| content.parts.as_ref().is_some_and(|p| !p.is_empty()))
+            .collect();
+        user_turns
+            .iter()
+            .enumerate()
+            .filter_map(|(position, (index, content))| {
+                let (earlier, _) = user_turns[..position]
+                    .iter()
+                    .find(|(_, other)| other.parts == content.parts)?;
+                Some(
+                    LintWarning::new(
+                        LintKind::DuplicateTurn,
+                        LintSeverity::Warning,
+                        format!("user turn repeats contents[{earlier}]"),
+                    )
+                    .at(format!("contents[{index}]")),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Finds a first user message that reads like a system instruction, e.g. "You are a helpful
+/// assistant", which belongs in
+/// [`with_system_instruction()`](crate::ContentBuilder::with_system_instruction)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemInstructionAsUserMessage;
+
+impl LintRule for SystemInstructionAsUserMessage {
+    fn check(&self, request: &GenerateContentRequest) -> Vec<LintWarning> {
+        let Some(first) = request.contents.first() else {
+            return Vec::new();
+        };
+        if matches!(first.role, Some(Role::Model)) {
+            return Vec::new();
+        }
+        let opening = first.parts.iter().flatten().find_map(|part| match part {
+            Part::Text { text, .. } => {
+                let text = text.trim_start().to_lowercase();
+                INSTRUCTION_OPENINGS
+                    .iter()
+                    .any(|opening| text.starts_with(opening))
+                    .then_some(())
+            }
+            _ => None,
+        });
+        match opening {
+            Some(()) => vec![LintWarning::new(
+                LintKind::SystemInstructionAsUserMessage,
+                LintSeverity::Warning,
+                "first user message reads like a system instruction",
+            )
+            .at("contents[0]")],
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Finds a JSON response requested with a prompt that asks for prose
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonModeWithProse;
+
+impl LintRule for JsonModeWithProse {
+    fn check(&self, request: &GenerateContentRequest) -> Vec<LintWarning> {
+        let json_mode = request
+            .generation_config
+            .as_ref()
+            .and_then(|config| config.response_mime_type.as_deref())
+            == Some("application/json");
+        if !json_mode {
+            return Vec::new();
+        }
+        texts(request)
+            .filter_map(|(location, text)| {
+                let text = text.to_lowercase();
+                let request = PROSE_REQUESTS
+                    .iter()
+                    .find(|phrase| text.contains(**phrase))?;
+                Some(
+                    LintWarning::new(
+                        LintKind::JsonModeWithProse,
+                        LintSeverity::Warning,
+                        format!("JSON response requested, but the prompt says {request:?}"),
+                    )
+                    .at(location),
+                )
+            })
+            .collect()
+    }
+}
+
+/// The text parts of the system instruction and the contents, with their locations
+fn texts(request: &GenerateContentRequest) -> impl Iterator<Item = (String, &str)> {
+    let system = request
+        .system_instruction
+        .iter()
+        .flat_map(|content| content.parts.iter().flatten().enumerate())
+        .map(|(part, p)| (format!("system_instruction.parts[{part}]"), p));
+    let contents = request
+        .contents
+        .iter()
+        .enumerate()
+        .flat_map(|(index, content)| {
+            content
+                .parts
+                .iter()
+                .flatten()
+                .enumerate()
+                .map(move |(part, p)| (format!("contents[{index}].parts[{part}]"), p))
+        });
+    system
+        .chain(contents)
+        .filter_map(|(location, part)| match part {
+            Part::Text { text, .. } => Some((location, text.as_str())),
+            _ => None,
+        })
+}
+
+fn role_name(content: &Content) -> &'static str {
+    match content.role {
+        Some(Role::Model) => "model",
+        _ => "user",
+    }
+}
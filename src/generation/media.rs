@@ -0,0 +1,210 @@
+//! Saving images and audio returned inline in a response.
+//!
+//! Image generation and speech models answer with `inlineData` parts. [`Part::save_to_dir()`]
+//! decodes one of them into a file, and [`GenerationResponse::save_all_media()`] saves every
+//! inline part of a response:
+//!
+//! ```no_run
+//! # async fn run(response: gemini_rust::GenerationResponse) -> Result<(), gemini_rust::MediaError> {
+//! for media in response.save_all_media("out").await? {
+//!     println!("{} ({}, {} bytes)", media.path.display(), media.mime_type, media.size);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Files are named by the SHA-256 hash of their content, with an extension picked from the
+//! MIME type, e.g. `3f2a…e1.png`; types without a known extension are saved as `.bin`. Saving
+//! the same content twice therefore yields the same file, which is not written again. The
+//! directory is created if missing, and a file already at the path with different content is
+//! never overwritten: saving fails with [`MediaError::ContentMismatch`] instead.
+
+use base64::{engine::general_purpose, Engine as _};
+use sha2::{Digest, Sha256};
+use snafu::{ResultExt, Snafu};
+use std::{
+    fmt::Write as _,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::{Blob, GenerationResponse, Part};
+
+/// Extensions of the MIME types the API returns or accepts, preferred over those guessed
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/webp", "webp"),
+    ("image/gif", "gif"),
+    ("image/heic", "heic"),
+    ("image/heif", "heif"),
+    ("audio/wav", "wav"),
+    ("audio/x-wav", "wav"),
+    ("audio/mp3", "mp3"),
+    ("audio/mpeg", "mp3"),
+    ("audio/aiff", "aiff"),
+    ("audio/aac", "aac"),
+    ("audio/ogg", "ogg"),
+    ("audio/flac", "flac"),
+    ("audio/l16", "pcm"),
+    ("video/mp4", "mp4"),
+    ("application/pdf", "pdf"),
+    ("text/plain", "txt"),
+    ("application/octet-stream", "bin"),
+];
+
+/// Error saving inline media
+#[derive(Debug, Snafu)]
+pub enum MediaError {
+    #[snafu(display("part holds no inline data"))]
+    NotInlineData,
+
+    #[snafu(display("failed to decode inline data of type '{mime_type}'"))]
+    DecodeMedia {
+        mime_type: String,
+        source: base64::DecodeError,
+    },
+
+    #[snafu(display("failed to create directory '{}'", path.display()))]
+    CreateDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("failed to write '{}'", path.display()))]
+    WriteMedia {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("'{}' already exists with different content", path.display()))]
+    ContentMismatch { path: PathBuf },
+}
+
+/// A file written by [`Part::save_to_dir()`] or [`GenerationResponse::save_all_media()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedMedia {
+    /// The path of the file
+    pub path: PathBuf,
+    /// The MIME type of the inline data
+    pub mime_type: String,
+    /// The size of the file, in bytes
+    pub size: usize,
+}
+
+/// The file extension for `mime_type`, without the dot; `bin` for unknown types.
+///
+/// Parameters such as `;rate=24000` are ignored.
+pub fn extension_for(mime_type: &str) -> &'static str {
+    let essence = mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == essence)
+        .map(|(_, extension)| *extension)
+        .or_else(|| {
+            mime_guess::get_mime_extensions_str(&essence)
+                .and_then(|extensions| extensions.first().copied())
+        })
+        .unwrap_or("bin")
+}
+
+/// The file name of `bytes` of type `mime_type`: their SHA-256 hash and the type's extension
+fn file_name(bytes: &[u8], mime_type: &str) -> String {
+    let mut name = String::with_capacity(68);
+    for byte in Sha256::digest(bytes) {
+        let _ = write!(name, "{byte:02x}");
+    }
+    name.push('.');
+    name.push_str(extension_for(mime_type));
+    name
+}
+
+/// Decodes `blob` into a content-addressed file in `dir`
+async fn save_blob(blob: &Blob, dir: &Path) -> Result<SavedMedia, MediaError> {
+    let bytes = general_purpose::STANDARD
+        .decode(&blob.data)
+        .context(DecodeMediaSnafu {
+            mime_type: &blob.mime_type,
+        })?;
+    tokio::fs::create_dir_all(dir)
+        .await
+        .context(CreateDirSnafu { path: dir })?;
+    let path = dir.join(file_name(&bytes, &blob.mime_type));
+    let created = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .await;
+    match created {
+        Ok(mut file) => {
+            let written = async {
+                file.write_all(&bytes).await?;
+                file.flush().await
+            }
+            .await;
+            if let Err(source) = written {
+                // Leave no partial file behind to be mistaken for the content later
+                let _ = tokio::fs::remove_file(&path).await;
+                return Err(source).context(WriteMediaSnafu { path });
+            }
+        }
+        Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+            let existing = tokio::fs::read(&path)
+                .await
+                .context(WriteMediaSnafu { path: &path })?;
+            snafu::ensure!(existing == bytes, ContentMismatchSnafu { path });
+        }
+        Err(source) => return Err(source).context(WriteMediaSnafu { path }),
+    }
+    Ok(SavedMedia {
+        path,
+        mime_type: blob.mime_type.clone(),
+        size: bytes.len(),
+    })
+}
+
+impl Part {
+    /// Saves the inline data of this part to a file in `dir` named by its content, see
+    /// [`media`](crate::generation::media), and returns the file's path.
+    ///
+    /// Fails with [`MediaError::NotInlineData`] for parts of other kinds.
+    pub async fn save_to_dir(&self, dir: impl AsRef<Path>) -> Result<PathBuf, MediaError> {
+        match self {
+            Part::InlineData { inline_data } => {
+                Ok(save_blob(inline_data, dir.as_ref()).await?.path)
+            }
+            _ => NotInlineDataSnafu.fail(),
+        }
+    }
+}
+
+impl GenerationResponse {
+    /// Saves the inline data parts of every candidate to files in `dir`, in order, see
+    /// [`media`](crate::generation::media).
+    ///
+    /// Parts with the same content are saved to the same file, which is listed once per part.
+    pub async fn save_all_media(
+        &self,
+        dir: impl AsRef<Path>,
+    ) -> Result<Vec<SavedMedia>, MediaError> {
+        let blobs = self
+            .candidates
+            .iter()
+            .flat_map(|candidate| candidate.content.parts.iter().flatten())
+            .filter_map(|part| match part {
+                Part::InlineData { inline_data } => Some(inline_data),
+                _ => None,
+            });
+        let mut saved = Vec::new();
+        for blob in blobs {
+            saved.push(save_blob(blob, dir.as_ref()).await?);
+        }
+        Ok(saved)
+    }
+}
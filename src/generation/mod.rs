@@ -1,5 +1,35 @@
+#[cfg(feature = "streaming")]
+pub mod aggregate;
+pub mod attribution;
+pub mod audio;
 pub mod builder;
+pub mod bundle;
+pub mod concurrency;
+pub mod continuation;
+pub mod count;
+pub mod dataset;
+pub mod documents;
+pub mod fragments;
+#[cfg(feature = "image")]
+pub mod image;
+mod inline;
+#[cfg(feature = "streaming")]
+pub mod items;
+pub mod lint;
+#[cfg(feature = "image")]
+pub mod media;
+pub mod modalities;
 pub mod model;
+pub mod provenance;
+pub mod race;
+#[cfg(feature = "streaming")]
+pub mod spill;
+pub mod spoken;
+#[cfg(feature = "streaming")]
+pub mod stop;
+#[cfg(feature = "streaming")]
+pub mod stream;
+pub mod video;
 
 pub use builder::ContentBuilder;
 pub use model::*;
@@ -0,0 +1,149 @@
+//! Response modalities each model can produce, and the fallback to text.
+//!
+//! Requesting `responseModalities: ["TEXT", "IMAGE"]` from a model without image output fails
+//! with a `400 INVALID_ARGUMENT`. With [preflight checks](crate::GeminiBuilder::with_preflight)
+//! enabled, requests are validated against [`supported_response_modalities()`] before they are
+//! sent. With [`ContentBuilder::fallback_to_text()`](crate::ContentBuilder::fallback_to_text),
+//! the unsupported modalities are stripped instead, either up front or after the API rejected
+//! them; rejections are recognized by their status and field path, never by their message.
+
+use crate::{
+    client::{Error as ClientError, UnsupportedModalitiesSnafu},
+    GenerateContentRequest, Modality, Model,
+};
+
+/// Response modalities of known models, by model name prefix; the longest prefix wins
+const CAPABILITIES: &[(&str, &[Modality])] = &[
+    ("gemini-2.0-flash", &[Modality::Text]),
+    ("gemini-2.0-flash-lite", &[Modality::Text]),
+    (
+        "gemini-2.0-flash-preview-image-generation",
+        &[Modality::Text, Modality::Image],
+    ),
+    ("gemini-2.5-flash", &[Modality::Text]),
+    ("gemini-2.5-flash-image", &[Modality::Text, Modality::Image]),
+    ("gemini-2.5-flash-lite", &[Modality::Text]),
+    ("gemini-2.5-flash-preview-tts", &[Modality::Audio]),
+    ("gemini-2.5-pro", &[Modality::Text]),
+    ("gemini-2.5-pro-preview-tts", &[Modality::Audio]),
+];
+
+/// Returns the response modalities `model` supports, or `None` for models not in the
+/// capability matrix
+pub fn supported_response_modalities(model: &Model) -> Option<&'static [Modality]> {
+    let name = model.as_str();
+    let name = name.strip_prefix("models/").unwrap_or(name);
+    CAPABILITIES
+        .iter()
+        .filter(|(prefix, _)| name.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, modalities)| *modalities)
+}
+
+/// Validates the requested response modalities against the capability matrix.
+///
+/// Unsupported modalities are stripped if `fallback` is set, returning whether any were;
+/// otherwise they fail with [`ClientError::UnsupportedModalities`].
+pub(crate) fn preflight(
+    model: &Model,
+    request: &mut GenerateContentRequest,
+    fallback: bool,
+) -> Result<bool, ClientError> {
+    let requested = request
+        .generation_config
+        .as_ref()
+        .and_then(|config| config.response_modalities.as_ref());
+    let (Some(supported), Some(requested)) = (supported_response_modalities(model), requested)
+    else {
+        return Ok(false);
+    };
+    let unsupported: Vec<Modality> = requested
+        .iter()
+        .map(|name| Modality::from(name.as_str()))
+        .filter(|modality| !supported.contains(modality))
+        .collect();
+    if unsupported.is_empty() {
+        return Ok(false);
+    }
+    snafu::ensure!(
+        fallback,
+        UnsupportedModalitiesSnafu {
+            model: model.to_string(),
+            unsupported,
+            supported: supported.to_vec(),
+        }
+    );
+    Ok(strip(request, |_, modality| {
+        unsupported.iter().any(|u| u == modality)
+    }))
+}
+
+/// Strips the modalities an API error rejected, returning whether any were.
+///
+/// Only a `400 INVALID_ARGUMENT` whose field violations point at
+/// `generation_config.response_modalities` counts. Violations naming an index strip that
+/// modality; otherwise every modality but `TEXT` is stripped.
+pub(crate) fn strip_rejected(request: &mut GenerateContentRequest, error: &ClientError) -> bool {
+    let Some(status) = error.api_status() else {
+        return false;
+    };
+    if status.code != 400
+        || status
+            .status
+            .as_deref()
+            .is_some_and(|s| s != "INVALID_ARGUMENT")
+    {
+        return false;
+    }
+    let rejected: Vec<Option<usize>> = status
+        .field_violations()
+        .iter()
+        .filter_map(|violation| modality_index(&violation.field))
+        .collect();
+    if rejected.is_empty() {
+        return false;
+    }
+    strip(request, |index, modality| {
+        rejected.contains(&Some(index)) || (rejected.contains(&None) && *modality != Modality::Text)
+    })
+}
+
+/// Removes the modalities matching `reject`, keeping at least `TEXT`
+fn strip(request: &mut GenerateContentRequest, reject: impl Fn(usize, &Modality) -> bool) -> bool {
+    let Some(modalities) = request
+        .generation_config
+        .as_mut()
+        .and_then(|config| config.response_modalities.as_mut())
+    else {
+        return false;
+    };
+    let kept: Vec<String> = modalities
+        .iter()
+        .enumerate()
+        .filter(|(index, name)| !reject(*index, &Modality::from(name.as_str())))
+        .map(|(_, name)| name.clone())
+        .collect();
+    if kept.len() == modalities.len() {
+        return false;
+    }
+    *modalities = match kept.is_empty() {
+        true => vec![Modality::Text.to_string()],
+        false => kept,
+    };
+    true
+}
+
+/// Recognizes a field path such as `generation_config.response_modalities[1]` (or its
+/// camelCase form), returning the index it names, if any
+fn modality_index(field: &str) -> Option<Option<usize>> {
+    let normalized: String = field
+        .chars()
+        .filter(|c| *c != '_')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let rest = &normalized[normalized.find("responsemodalities")? + "responsemodalities".len()..];
+    Some(
+        rest.strip_prefix('[')
+            .and_then(|rest| rest[..rest.find(']')?].parse().ok()),
+    )
+}
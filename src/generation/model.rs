@@ -1,10 +1,19 @@
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use time::OffsetDateTime;
 
 use crate::{
+    common::{
+        serde::sorted_json,
+        strict::{self, UnknownField},
+    },
+    retry::AttemptsReport,
     safety::{SafetyRating, SafetySetting},
-    Content, Modality, Part,
+    text_utils,
+    tools::ToolExchange,
+    transport::ResponseMeta,
+    CodeExecutionResult, Content, Modality, Outcome, Part,
 };
 
 /// Reason why generation finished
@@ -39,6 +48,9 @@ pub enum FinishReason {
     UnexpectedToolCall,
     /// Model called too many tools consecutively, thus the system exited execution.
     TooManyToolCalls,
+    /// Generation was stopped by a client-side [`StopPattern`](crate::StopPattern); never sent
+    /// by the API.
+    ClientStop,
 }
 
 /// Citation metadata for content
@@ -90,11 +102,107 @@ pub struct Candidate {
     /// The index of the candidate
     #[serde(skip_serializing_if = "Option::is_none")]
     pub index: Option<i32>,
+    /// Average log probability of the candidate's tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_logprobs: Option<f64>,
+    /// Metadata about the URLs retrieved by the URL context tool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url_context_metadata: Option<UrlContextMetadata>,
+    /// Details of the finish reason, e.g. the malformed call of
+    /// [`FinishReason::MalformedFunctionCall`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_message: Option<String>,
 }
 
-/// Metadata about token usage
+impl Candidate {
+    /// The function call the model failed to make, if it finished with
+    /// [`FinishReason::MalformedFunctionCall`].
+    ///
+    /// The API reports the model's output in the [finish message](Self::finish_message), e.g.
+    /// `Malformed function call: print(default_api.lookup(city=Paris))`, of which the call is
+    /// returned; without a finish message, the text of the content is returned, which may be
+    /// empty.
+    pub fn malformed_function_call(&self) -> Option<String> {
+        if self.finish_reason != Some(FinishReason::MalformedFunctionCall) {
+            return None;
+        }
+        let message = self.finish_message.as_deref().map(|message| {
+            message
+                .strip_prefix("Malformed function call:")
+                .unwrap_or(message)
+                .trim()
+        });
+        Some(match message {
+            Some(call) if !call.is_empty() => call.to_string(),
+            _ => self
+                .content
+                .parts
+                .iter()
+                .flatten()
+                .filter_map(|part| match part {
+                    Part::Text { text, .. } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect(),
+        })
+    }
+
+    /// The result of the last code the model ran, if that failed or exceeded its deadline
+    pub fn failed_code_execution(&self) -> Option<&CodeExecutionResult> {
+        let last = self
+            .content
+            .parts
+            .iter()
+            .flatten()
+            .rev()
+            .find_map(|part| match part {
+                Part::CodeExecutionResult {
+                    code_execution_result,
+                } => Some(code_execution_result),
+                _ => None,
+            })?;
+        matches!(
+            last.outcome,
+            Outcome::OutcomeFailed | Outcome::OutcomeDeadlineExceeded
+        )
+        .then_some(last)
+    }
+
+    /// Returns the highest probability score of the candidate's safety ratings.
+    ///
+    /// Scores are only returned by Vertex AI; `None` if no rating carries one.
+    pub fn max_safety_score(&self) -> Option<f32> {
+        self.safety_ratings
+            .iter()
+            .flatten()
+            .filter_map(|rating| rating.probability_score)
+            .reduce(f32::max)
+    }
+}
+
+/// Metadata about the URLs retrieved by the URL context tool
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlContextMetadata {
+    /// One entry per retrieved URL
+    #[serde(default)]
+    pub url_metadata: Vec<UrlMetadata>,
+}
+
+/// Retrieval status of a single URL
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
+pub struct UrlMetadata {
+    /// The URL that was retrieved
+    pub retrieved_url: String,
+    /// The retrieval status, e.g. `URL_RETRIEVAL_STATUS_SUCCESS`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url_retrieval_status: Option<String>,
+}
+
+/// Metadata about token usage
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct UsageMetadata {
     /// The number of prompt tokens (null if request processing failed)
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -111,12 +219,98 @@ pub struct UsageMetadata {
     /// Detailed prompt token information
     #[serde(skip_serializing_if = "Option::is_none")]
     pub prompt_tokens_details: Option<Vec<PromptTokenDetails>>,
-    /// The number of cached content tokens (batch API)
+    /// The number of prompt tokens served from the cached content the request references,
+    /// see [`cache::api`](crate::cache::api); part of the prompt token count
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cached_content_token_count: Option<i32>,
-    /// Detailed cache token information (batch API)
+    /// Detailed cache token information by modality
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cache_tokens_details: Option<Vec<PromptTokenDetails>>,
+    /// Detailed response token information by modality
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidates_tokens_details: Option<Vec<PromptTokenDetails>>,
+    /// The number of tokens in the results of tool calls
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_use_prompt_token_count: Option<i32>,
+    /// Detailed tool-use prompt token information by modality
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_use_prompt_tokens_details: Option<Vec<PromptTokenDetails>>,
+}
+
+impl UsageMetadata {
+    /// Adds the token counts of another round trip, merging the per-modality details
+    pub(crate) fn accumulate(&mut self, other: &UsageMetadata) {
+        fn add(count: &mut Option<i32>, other: Option<i32>) {
+            if let Some(other) = other {
+                *count = Some(count.unwrap_or(0) + other);
+            }
+        }
+        fn merge(
+            details: &mut Option<Vec<PromptTokenDetails>>,
+            other: &Option<Vec<PromptTokenDetails>>,
+        ) {
+            for detail in other.iter().flatten() {
+                let details = details.get_or_insert_with(Vec::new);
+                match details.iter_mut().find(|d| d.modality == detail.modality) {
+                    Some(existing) => existing.token_count += detail.token_count,
+                    None => details.push(detail.clone()),
+                }
+            }
+        }
+        add(&mut self.prompt_token_count, other.prompt_token_count);
+        add(
+            &mut self.candidates_token_count,
+            other.candidates_token_count,
+        );
+        add(&mut self.total_token_count, other.total_token_count);
+        add(&mut self.thoughts_token_count, other.thoughts_token_count);
+        add(
+            &mut self.cached_content_token_count,
+            other.cached_content_token_count,
+        );
+        add(
+            &mut self.tool_use_prompt_token_count,
+            other.tool_use_prompt_token_count,
+        );
+        merge(
+            &mut self.prompt_tokens_details,
+            &other.prompt_tokens_details,
+        );
+        merge(&mut self.cache_tokens_details, &other.cache_tokens_details);
+        merge(
+            &mut self.candidates_tokens_details,
+            &other.candidates_tokens_details,
+        );
+        merge(
+            &mut self.tool_use_prompt_tokens_details,
+            &other.tool_use_prompt_tokens_details,
+        );
+    }
+}
+
+/// Response of a `countTokens` request
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CountTokensResponse {
+    /// The number of tokens of the whole prompt, including cached content
+    #[serde(default)]
+    pub total_tokens: i32,
+    /// The number of tokens of the cached content the request references
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached_content_token_count: Option<i32>,
+    /// Prompt tokens by modality
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<Vec<PromptTokenDetails>>,
+    /// Cached content tokens by modality
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_tokens_details: Option<Vec<PromptTokenDetails>>,
+}
+
+impl CountTokensResponse {
+    /// The number of tokens sent with the request itself, i.e. without the cached content
+    pub fn uncached_tokens(&self) -> i32 {
+        self.total_tokens - self.cached_content_token_count.unwrap_or_default()
+    }
 }
 
 /// Details about prompt tokens by modality
@@ -145,6 +339,21 @@ pub struct GroundingMetadata {
     /// Google Maps widget context token
     #[serde(skip_serializing_if = "Option::is_none")]
     pub google_maps_widget_context_token: Option<String>,
+    /// Google Search entry point to display alongside grounded responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_entry_point: Option<SearchEntryPoint>,
+}
+
+/// Google Search entry point
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchEntryPoint {
+    /// HTML and CSS snippet rendering the search suggestions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rendered_content: Option<String>,
+    /// Base64-encoded JSON of search terms and URLs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sdk_blob: Option<String>,
 }
 
 /// A chunk of grounding information from a source
@@ -223,11 +432,45 @@ pub struct GenerationResponse {
     /// Response ID
     #[serde(skip_serializing_if = "Option::is_none")]
     pub response_id: Option<String>,
+    /// Number of continuations stitched onto a truncated answer, see
+    /// [`ContentBuilder::auto_continue()`](crate::ContentBuilder::auto_continue).
+    ///
+    /// Set by the client, never part of the wire format.
+    #[serde(skip)]
+    pub continuations: u32,
+    /// Whether unsupported response modalities were stripped from the request, see
+    /// [`ContentBuilder::fallback_to_text()`](crate::ContentBuilder::fallback_to_text).
+    ///
+    /// Set by the client, never part of the wire format.
+    #[serde(skip)]
+    pub modality_downgraded: bool,
+    /// Every network attempt made for this response, failed retries included.
+    ///
+    /// Set by the client, never part of the wire format.
+    #[serde(skip)]
+    pub attempts: AttemptsReport,
+    /// Details of the HTTP response, such as its HTTP version, see
+    /// [`transport`](crate::transport), and the safety flags of
+    /// [`ContentBuilder::flag_safety_above()`](crate::ContentBuilder::flag_safety_above).
+    ///
+    /// Set by the client, never part of the wire format.
+    #[serde(skip)]
+    pub meta: ResponseMeta,
+    /// Every function call run by the tool loop that produced this response, in order, see
+    /// [`ContentBuilder::execute_with_tools()`](crate::ContentBuilder::execute_with_tools).
+    ///
+    /// Set by the client, never part of the wire format.
+    #[serde(skip)]
+    pub tool_transcript: Vec<ToolExchange>,
 }
 
 /// Reason why content was blocked
+///
+/// Reasons added to the API after this crate was released deserialize as
+/// [`BlockReason::Unknown`].
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[non_exhaustive]
 pub enum BlockReason {
     /// Default value. This value is unused.
     BlockReasonUnspecified,
@@ -241,6 +484,55 @@ pub enum BlockReason {
     ProhibitedContent,
     /// Candidates blocked due to unsafe image generation content.
     ImageSafety,
+    /// A reason this version of the crate does not know, as sent by the API
+    #[serde(untagged)]
+    Unknown(String),
+}
+
+impl BlockReason {
+    /// The name of the reason as sent by the API
+    pub fn as_str(&self) -> &str {
+        match self {
+            BlockReason::BlockReasonUnspecified => "BLOCK_REASON_UNSPECIFIED",
+            BlockReason::Safety => "SAFETY",
+            BlockReason::Other => "OTHER",
+            BlockReason::Blocklist => "BLOCKLIST",
+            BlockReason::ProhibitedContent => "PROHIBITED_CONTENT",
+            BlockReason::ImageSafety => "IMAGE_SAFETY",
+            BlockReason::Unknown(reason) => reason,
+        }
+    }
+
+    /// What the reason means, and whether changing the request can help
+    pub fn guidance(&self) -> &'static str {
+        match self {
+            BlockReason::Safety => {
+                "a safety filter matched; the safety ratings name the category, whose threshold \
+                 can be relaxed with the request's safety settings"
+            }
+            BlockReason::Blocklist => {
+                "a term in the prompt matched Google's terminology blocklist; this is not \
+                 governed by your safety settings, so the prompt itself must change"
+            }
+            BlockReason::ProhibitedContent => {
+                "the prompt contains content that is prohibited regardless of safety settings"
+            }
+            BlockReason::ImageSafety => {
+                "image generation was blocked as unsafe; this is not governed by your safety \
+                 settings"
+            }
+            BlockReason::Other => "the API did not say why; retrying the same prompt will not help",
+            BlockReason::BlockReasonUnspecified | BlockReason::Unknown(_) => {
+                "the reason is not known to this version of the crate"
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for BlockReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 /// Feedback about the prompt
@@ -256,23 +548,65 @@ pub struct PromptFeedback {
 }
 
 impl GenerationResponse {
-    /// Get the text of the first candidate
+    /// Parses a raw response body, reporting every field the typed model does not capture.
+    ///
+    /// Regular deserialization silently ignores unknown fields. This is meant for CI runs
+    /// against recorded fixtures, to catch new or renamed API fields as soon as they appear.
+    pub fn parse_strict(raw: &str) -> Result<(Self, Vec<UnknownField>), serde_json::Error> {
+        strict::parse_strict(raw)
+    }
+
+    /// Merges adjacent text parts of every candidate, returning how many parts were merged
+    /// away; see [`Content::merge_text_parts()`].
+    ///
+    /// Only the number of parts changes: the text, thoughts, and usage metadata stay the same.
+    pub fn normalize(&mut self) -> usize {
+        self.candidates
+            .iter_mut()
+            .map(|candidate| candidate.content.merge_text_parts())
+            .sum()
+    }
+
+    /// Get the text of the first candidate, concatenating its text parts other than thoughts
     pub fn text(&self) -> String {
         self.candidates
             .first()
-            .and_then(|c| {
-                c.content.parts.as_ref().and_then(|parts| {
-                    parts.first().and_then(|p| match p {
-                        Part::Text {
-                            text,
-                            thought: _,
-                            thought_signature: _,
-                        } => Some(text.clone()),
-                        _ => None,
-                    })
-                })
+            .and_then(|c| c.content.parts.as_ref())
+            .into_iter()
+            .flatten()
+            .filter_map(|p| match p {
+                Part::Text {
+                    text,
+                    thought: None | Some(false),
+                    thought_signature: _,
+                } => Some(text.as_str()),
+                _ => None,
             })
-            .unwrap_or_default()
+            .collect()
+    }
+
+    /// Get the text of the first candidate as plain text.
+    ///
+    /// Markdown is stripped, whitespace normalized and typographic quotes replaced, see
+    /// [`text_utils`](crate::text_utils). Indentation inside code blocks is not preserved.
+    pub fn plain_text(&self) -> String {
+        let text = text_utils::strip_markdown(&self.text());
+        text_utils::ascii_quotes(&text_utils::normalize_whitespace(&text))
+    }
+
+    /// Returns the candidates with a safety rating whose probability score exceeds
+    /// `threshold`, see [`Candidate::max_safety_score()`].
+    pub fn candidates_above_safety_score(&self, threshold: f32) -> Vec<&Candidate> {
+        self.candidates
+            .iter()
+            .filter(|c| c.max_safety_score().is_some_and(|score| score > threshold))
+            .collect()
+    }
+
+    /// The malformed function call of the first candidate, see
+    /// [`Candidate::malformed_function_call()`]
+    pub fn malformed_function_call(&self) -> Option<String> {
+        self.candidates.first()?.malformed_function_call()
     }
 
     /// Get function calls from the response
@@ -434,6 +768,38 @@ pub struct GenerateContentRequest {
     /// The cached content to use
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cached_content: Option<String>,
+    /// Labels attached to the request for billing breakdowns, supported on Vertex AI only
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl GenerateContentRequest {
+    /// Returns a hash of the request suitable as a cache or deduplication key.
+    ///
+    /// Thought signatures are left out, so a request replaying a history with signatures
+    /// hashes the same as one without them. The hash is computed with a fixed function over
+    /// the serialized request, so it is stable across processes and platforms. JSON values,
+    /// such as function arguments and schemas, serialize with their object keys sorted, and
+    /// labels are kept sorted, so requests built in a different order hash the same.
+    pub fn canonical_hash(&self) -> u64 {
+        let mut request = self.clone();
+        request
+            .contents
+            .iter_mut()
+            .chain(request.system_instruction.as_mut())
+            .for_each(|content| {
+                content.strip_thought_signatures();
+            });
+        let bytes = serde_json::to_vec(&request).unwrap_or_default();
+        fnv1a64(&bytes)
+    }
+}
+
+/// The 64-bit FNV-1a hash
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
 }
 
 /// Configuration for thinking (Gemini 2.5 series only)
@@ -511,7 +877,7 @@ impl Default for ThinkingConfig {
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GenerationConfig {
-    /// The temperature for the model (0.0 to 1.0)
+    /// The temperature for the model (0.0 to 2.0)
     ///
     /// Controls the randomness of the output. Higher values (e.g., 0.9) make output
     /// more random, lower values (e.g., 0.1) make output more deterministic.
@@ -526,26 +892,26 @@ pub struct GenerationConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f32>,
 
-    /// The top-k value for the model
+    /// The top-k value for the model (at least 1)
     ///
     /// For each token generation step, the model considers the top_k most likely tokens.
     /// Lower values are more selective, higher values allow more variety.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_k: Option<i32>,
 
-    /// The maximum number of tokens to generate
+    /// The maximum number of tokens to generate (at least 1)
     ///
     /// Limits the length of the generated content. One token is roughly 4 characters.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_output_tokens: Option<i32>,
 
-    /// The candidate count
+    /// The candidate count (1 to 8)
     ///
     /// Number of alternative responses to generate.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub candidate_count: Option<i32>,
 
-    /// Whether to stop on specific sequences
+    /// Whether to stop on specific sequences (up to 5, none empty)
     ///
     /// The model will stop generating content when it encounters any of these sequences.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -559,11 +925,17 @@ pub struct GenerationConfig {
     /// The response schema
     ///
     /// Specifies the JSON schema for structured responses.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "sorted_json::optional::serialize"
+    )]
     pub response_schema: Option<serde_json::Value>,
 
     /// Response modalities (for TTS and other multimodal outputs)
     #[serde(skip_serializing_if = "Option::is_none")]
+    ///
+    /// Names such as `"AUDIO"`; [`response_modalities()`](Self::response_modalities) parses
+    /// them as [`Modality`] values.
     pub response_modalities: Option<Vec<String>>,
 
     /// Speech configuration for text-to-speech generation
@@ -575,6 +947,137 @@ pub struct GenerationConfig {
     /// Configuration for the model's thinking process (Gemini 2.5 series only).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thinking_config: Option<ThinkingConfig>,
+
+    /// The seed used in decoding
+    ///
+    /// Using the same seed with the same request makes the output as reproducible as the
+    /// backend allows. A random seed is used when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i32>,
+}
+
+impl GenerationConfig {
+    /// Checks the sampling parameters against the ranges the API accepts.
+    ///
+    /// Requests check their config before anything is sent, failing with
+    /// [`InvalidGenerationConfig`](crate::ClientError::InvalidGenerationConfig) for the first
+    /// field out of range; validating a config up front catches the error where the config
+    /// is made.
+    pub fn validate(&self) -> Result<(), crate::ClientError> {
+        fn check<T: std::fmt::Display + Copy>(
+            field: &'static str,
+            value: Option<T>,
+            valid: impl Fn(T) -> bool,
+            expected: &'static str,
+        ) -> Result<(), crate::ClientError> {
+            match value {
+                Some(value) if !valid(value) => Err(crate::ClientError::InvalidGenerationConfig {
+                    field,
+                    value: value.to_string(),
+                    expected,
+                }),
+                _ => Ok(()),
+            }
+        }
+
+        check(
+            "temperature",
+            self.temperature,
+            |t| (0.0..=2.0).contains(&t),
+            "a number from 0.0 to 2.0",
+        )?;
+        check(
+            "topP",
+            self.top_p,
+            |p| (0.0..=1.0).contains(&p),
+            "a number from 0.0 to 1.0",
+        )?;
+        check("topK", self.top_k, |k| k >= 1, "at least 1")?;
+        check(
+            "maxOutputTokens",
+            self.max_output_tokens,
+            |tokens| tokens >= 1,
+            "at least 1",
+        )?;
+        check(
+            "candidateCount",
+            self.candidate_count,
+            |count| (1..=8).contains(&count),
+            "from 1 to 8",
+        )?;
+        if let Some(stop_sequences) = &self.stop_sequences {
+            check(
+                "stopSequences",
+                Some(stop_sequences.len()),
+                |len| len <= 5,
+                "at most 5 sequences",
+            )?;
+            if stop_sequences.iter().any(String::is_empty) {
+                return Err(crate::ClientError::InvalidGenerationConfig {
+                    field: "stopSequences",
+                    value: format!("{stop_sequences:?}"),
+                    expected: "non-empty sequences",
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns this config with every field set in `overrides` replaced by the override.
+    ///
+    /// Fields are merged one by one, including those of the thinking config; fields the
+    /// override leaves unset keep their value from `self`.
+    pub fn merged_with(&self, overrides: &GenerationConfig) -> GenerationConfig {
+        fn pick<T: Clone>(base: &Option<T>, over: &Option<T>) -> Option<T> {
+            over.as_ref().or(base.as_ref()).cloned()
+        }
+
+        let thinking_config = match (&self.thinking_config, &overrides.thinking_config) {
+            (Some(base), Some(over)) => Some(ThinkingConfig {
+                thinking_budget: pick(&base.thinking_budget, &over.thinking_budget),
+                include_thoughts: pick(&base.include_thoughts, &over.include_thoughts),
+            }),
+            (base, over) => pick(base, over),
+        };
+        GenerationConfig {
+            temperature: pick(&self.temperature, &overrides.temperature),
+            top_p: pick(&self.top_p, &overrides.top_p),
+            top_k: pick(&self.top_k, &overrides.top_k),
+            max_output_tokens: pick(&self.max_output_tokens, &overrides.max_output_tokens),
+            candidate_count: pick(&self.candidate_count, &overrides.candidate_count),
+            stop_sequences: pick(&self.stop_sequences, &overrides.stop_sequences),
+            response_mime_type: pick(&self.response_mime_type, &overrides.response_mime_type),
+            response_schema: pick(&self.response_schema, &overrides.response_schema),
+            response_modalities: pick(&self.response_modalities, &overrides.response_modalities),
+            speech_config: pick(&self.speech_config, &overrides.speech_config),
+            thinking_config,
+            seed: pick(&self.seed, &overrides.seed),
+        }
+    }
+
+    /// The response modalities, parsed from their names in any casing
+    pub fn response_modalities(&self) -> Option<Vec<Modality>> {
+        let names = self.response_modalities.as_ref()?;
+        Some(
+            names
+                .iter()
+                .map(|name| Modality::from(name.as_str()))
+                .collect(),
+        )
+    }
+
+    /// Sets the response modalities, given as [`Modality`] values or by name such as
+    /// `"AUDIO"`
+    pub fn with_response_modalities(
+        mut self,
+        modalities: impl IntoIterator<Item = impl Into<Modality>>,
+    ) -> Self {
+        let names = modalities
+            .into_iter()
+            .map(|modality| modality.into().to_string());
+        self.response_modalities = Some(names.collect());
+        self
+    }
 }
 
 /// Configuration for speech generation (text-to-speech)
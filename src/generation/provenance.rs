@@ -0,0 +1,160 @@
+//! Provenance tracking for the parts assembled by [`ContentBuilder`](super::ContentBuilder).
+//!
+//! Every part added through the builder records which builder method produced it, plus an
+//! optional user-supplied label. Provenance never reaches the wire; it is used to explain
+//! API errors that reference `contents[i].parts[j]` and to render
+//! [`debug_outline()`](super::ContentBuilder::debug_outline). A system instruction composed
+//! with [`with_system_fragments()`](super::ContentBuilder::with_system_fragments) records the
+//! ids of its fragments, which explain errors referencing `systemInstruction`.
+
+use std::fmt;
+
+use crate::common::rpc::FieldViolation;
+
+/// Where a single request part came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartProvenance {
+    /// The builder method that added the part, e.g. `with_user_message`
+    pub method: &'static str,
+    /// Optional label attached with [`with_part_label()`](super::ContentBuilder::with_part_label)
+    pub label: Option<String>,
+}
+
+impl PartProvenance {
+    pub(crate) fn new(method: &'static str) -> Self {
+        Self {
+            method,
+            label: None,
+        }
+    }
+}
+
+impl fmt::Display for PartProvenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.label {
+            Some(label) => write!(f, "{} [{label}]", self.method),
+            None => write!(f, "{}", self.method),
+        }
+    }
+}
+
+/// A field violation reported by the API, resolved to the builder call that produced it
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldOrigin {
+    /// Path to the offending field as reported by the API
+    pub field: String,
+    /// The API's description of the violation
+    pub description: String,
+    /// Index of the offending content, if the path references one
+    pub content_index: Option<usize>,
+    /// Index of the offending part within the content, if the path references one
+    pub part_index: Option<usize>,
+    /// Provenance of the referenced part (or of the first part of the referenced content)
+    pub provenance: Option<PartProvenance>,
+}
+
+impl fmt::Display for FieldOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.field)?;
+        if !self.description.is_empty() {
+            write!(f, ": {}", self.description)?;
+        }
+        if let Some(provenance) = &self.provenance {
+            write!(f, " (added by {provenance})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Provenance of every part in a request, indexed by content then by part
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ProvenanceMap {
+    contents: Vec<Vec<PartProvenance>>,
+    system_fragments: Vec<String>,
+}
+
+impl ProvenanceMap {
+    /// Records a content of `parts` parts added by `method`, at position `index`
+    pub(crate) fn record(&mut self, index: usize, parts: usize, method: &'static str) {
+        if self.contents.len() < index {
+            self.contents.resize_with(index, Vec::new);
+        }
+        let entry = vec![PartProvenance::new(method); parts];
+        if self.contents.len() == index {
+            self.contents.push(entry);
+        } else {
+            self.contents[index] = entry;
+        }
+    }
+
+    /// Records a part added by `method` to the end of the content at `index`
+    pub(crate) fn append(&mut self, index: usize, method: &'static str) {
+        if self.contents.len() <= index {
+            self.contents.resize_with(index + 1, Vec::new);
+        }
+        self.contents[index].push(PartProvenance::new(method));
+    }
+
+    /// Labels every part of the content at `index`
+    pub(crate) fn label(&mut self, index: usize, label: &str) {
+        if let Some(parts) = self.contents.get_mut(index) {
+            for part in parts {
+                part.label = Some(label.to_string());
+            }
+        }
+    }
+
+    /// Records the ids of the fragments the system instruction was composed from, none if it
+    /// was set otherwise
+    pub(crate) fn set_system_fragments(&mut self, ids: Vec<String>) {
+        self.system_fragments = ids;
+    }
+
+    /// The ids of the fragments the system instruction was composed from
+    pub(crate) fn system_fragments(&self) -> &[String] {
+        &self.system_fragments
+    }
+
+    /// Returns the provenance of the system instruction, if it was composed from fragments
+    pub(crate) fn system_instruction(&self) -> Option<PartProvenance> {
+        (!self.system_fragments.is_empty()).then(|| PartProvenance {
+            method: "with_system_fragments",
+            label: Some(self.system_fragments.join(", ")),
+        })
+    }
+
+    /// Returns the provenance of a single part
+    pub(crate) fn get(&self, content: usize, part: usize) -> Option<&PartProvenance> {
+        self.contents.get(content).and_then(|parts| parts.get(part))
+    }
+
+    /// Resolves an API field violation against the recorded provenance
+    pub(crate) fn resolve(&self, violation: &FieldViolation) -> FieldOrigin {
+        let content_index = parse_index(&violation.field, "contents[");
+        let part_index = parse_index(&violation.field, "parts[");
+        let provenance = match content_index {
+            Some(c) => self.get(c, part_index.unwrap_or(0)).cloned(),
+            None if is_system_instruction(&violation.field) => self.system_instruction(),
+            None => None,
+        };
+        FieldOrigin {
+            field: violation.field.clone(),
+            description: violation.description.clone(),
+            content_index,
+            part_index,
+            provenance,
+        }
+    }
+}
+
+/// Extracts the index following `prefix` in a field path such as `contents[3].parts[2]`
+fn parse_index(field: &str, prefix: &str) -> Option<usize> {
+    let start = field.find(prefix)? + prefix.len();
+    let end = field[start..].find(']')? + start;
+    field[start..end].parse().ok()
+}
+
+/// Whether a field path references the system instruction, in either casing
+fn is_system_instruction(field: &str) -> bool {
+    field.starts_with("systemInstruction") || field.starts_with("system_instruction")
+}
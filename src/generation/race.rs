@@ -0,0 +1,131 @@
+//! Speculative generation: race several requests and keep the first acceptable answer.
+//!
+//! All requests run concurrently within the calling task. As soon as one response passes the
+//! acceptance predicate the remaining requests are dropped, which closes their connections
+//! immediately. Responses that arrived but were rejected keep their usage metadata so their
+//! cost can still be accounted for, and the usage of every response received is recorded in
+//! the client's [`UsageLedger`] under [`LEDGER_TAG`].
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::time::Duration;
+
+use crate::{
+    client::Error as ClientError, clock::Clock, ContentBuilder, GenerationResponse, UsageLedger,
+    UsageMetadata,
+};
+
+/// The tag under which races record the usage of their responses in the client's ledger
+pub const LEDGER_TAG: &str = "race";
+
+/// How a raced request ended
+#[derive(Debug)]
+pub enum RaceOutcome {
+    /// The request produced the accepted response
+    Won,
+    /// The response arrived but was not acceptable
+    Rejected {
+        /// Usage of the rejected response
+        usage_metadata: Option<UsageMetadata>,
+    },
+    /// The request failed
+    Failed {
+        /// The error of the request
+        error: ClientError,
+    },
+    /// The request was cancelled because another one won
+    Cancelled,
+}
+
+/// A raced request
+#[derive(Debug)]
+pub struct RaceAttempt {
+    /// Time from the start of the race until the request ended or was cancelled
+    pub elapsed: Duration,
+    /// How the request ended
+    pub outcome: RaceOutcome,
+}
+
+/// Result of [`Gemini::race()`](crate::Gemini::race)
+#[derive(Debug)]
+pub struct RaceResult {
+    /// Index of the request that won
+    pub winner: usize,
+    /// The accepted response
+    pub response: GenerationResponse,
+    /// One attempt per request, in request order
+    pub attempts: Vec<RaceAttempt>,
+}
+
+impl RaceResult {
+    /// Usage of every response received, the winner's and the rejected ones'
+    pub fn usage(&self) -> impl Iterator<Item = &UsageMetadata> {
+        let rejected = self.attempts.iter().filter_map(|a| match &a.outcome {
+            RaceOutcome::Rejected { usage_metadata } => usage_metadata.as_ref(),
+            _ => None,
+        });
+        self.response.usage_metadata.iter().chain(rejected)
+    }
+}
+
+pub(crate) async fn race<F>(
+    clock: &dyn Clock,
+    ledger: &UsageLedger,
+    requests: impl IntoIterator<Item = ContentBuilder>,
+    accept: F,
+) -> Result<RaceResult, ClientError>
+where
+    F: Fn(&GenerationResponse) -> bool,
+{
+    let started = clock.now();
+    let mut pending: FuturesUnordered<_> = requests
+        .into_iter()
+        .enumerate()
+        .map(|(index, request)| async move {
+            let model = request.model().clone();
+            (index, model, request.execute().await)
+        })
+        .collect();
+    let mut attempts: Vec<Option<RaceAttempt>> = (0..pending.len()).map(|_| None).collect();
+
+    while let Some((index, model, result)) = pending.next().await {
+        let elapsed = clock.now() - started;
+        if let Ok(response) = &result {
+            ledger.record_response(LEDGER_TAG, model.as_str(), response);
+        }
+        let outcome = match result {
+            Ok(response) if accept(&response) => {
+                // Dropping the losers aborts their connections right away
+                drop(pending);
+                tracing::debug!(winner = index, ?elapsed, "race won");
+                attempts[index] = Some(RaceAttempt {
+                    elapsed,
+                    outcome: RaceOutcome::Won,
+                });
+                let attempts = attempts
+                    .into_iter()
+                    .map(|attempt| {
+                        attempt.unwrap_or(RaceAttempt {
+                            elapsed,
+                            outcome: RaceOutcome::Cancelled,
+                        })
+                    })
+                    .collect();
+                return Ok(RaceResult {
+                    winner: index,
+                    response,
+                    attempts,
+                });
+            }
+            Ok(response) => RaceOutcome::Rejected {
+                usage_metadata: response.usage_metadata,
+            },
+            Err(error) => RaceOutcome::Failed { error },
+        };
+        tracing::debug!(index, ?elapsed, "raced request lost");
+        attempts[index] = Some(RaceAttempt { elapsed, outcome });
+    }
+
+    Err(ClientError::NoAcceptableResponse {
+        attempts: attempts.into_iter().flatten().collect(),
+    })
+}
@@ -0,0 +1,195 @@
+//! Collecting long streamed answers with bounded memory.
+//!
+//! [`ContentBuilder::execute_stream_collected()`](crate::ContentBuilder::execute_stream_collected)
+//! concatenates the answer text of a stream. Up to the [`SpillPolicy`]'s memory cap the text
+//! is kept in memory; once the next delta would exceed it, the text so far is moved to a
+//! temporary file and every further delta is appended there. The answer is then a
+//! [`TextSource::File`] rather than a [`TextSource::Memory`].
+//!
+//! The temporary file is deleted when its [`SpilledFile`] is dropped, and when the stream
+//! fails part-way, so no file outlives the answer it holds.
+
+use futures::{TryStream, TryStreamExt};
+use snafu::ResultExt;
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    client::{Error as ClientError, SpillSnafu},
+    generation::{continuation, items::StreamSummary},
+    GenerationResponse,
+};
+
+/// Default memory cap of a [`SpillPolicy`], in bytes
+pub const DEFAULT_MEMORY_CAP: usize = 4 * 1024 * 1024;
+
+/// How much answer text is kept in memory before spilling to disk
+#[derive(Debug, Clone)]
+pub struct SpillPolicy {
+    memory_cap: usize,
+    dir: PathBuf,
+}
+
+impl Default for SpillPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEMORY_CAP)
+    }
+}
+
+impl SpillPolicy {
+    /// Keeps up to `memory_cap` bytes of text in memory, spilling to the system's temporary
+    /// directory beyond that.
+    pub fn new(memory_cap: usize) -> Self {
+        Self {
+            memory_cap,
+            dir: std::env::temp_dir(),
+        }
+    }
+
+    /// Creates spill files in `dir` instead of the system's temporary directory.
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+}
+
+/// A temporary file holding spilled text, deleted when dropped
+#[derive(Debug)]
+pub struct SpilledFile {
+    path: PathBuf,
+}
+
+impl SpilledFile {
+    /// The path of the file, valid until the `SpilledFile` is dropped
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SpilledFile {
+    fn drop(&mut self) {
+        if let Err(error) = std::fs::remove_file(&self.path) {
+            tracing::warn!(path = %self.path.display(), %error, "failed to remove spill file");
+        }
+    }
+}
+
+/// The collected answer text
+#[derive(Debug)]
+pub enum TextSource {
+    /// The text, if it stayed within the memory cap
+    Memory(String),
+    /// The file the text was spilled to
+    File(SpilledFile),
+}
+
+impl TextSource {
+    /// Reads the whole text, from memory or from the spill file.
+    pub async fn read_to_string(&self) -> std::io::Result<String> {
+        match self {
+            TextSource::Memory(text) => Ok(text.clone()),
+            TextSource::File(file) => tokio::fs::read_to_string(file.path()).await,
+        }
+    }
+}
+
+/// The answer text of a stream and its metadata
+#[derive(Debug)]
+pub struct CollectedText {
+    /// The first candidate's answer text, without thoughts
+    pub text: TextSource,
+    /// Metadata collected across the chunks
+    pub summary: StreamSummary,
+}
+
+/// Distinguishes the spill files of one process
+static SPILL_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Answer text collected so far
+struct Collector {
+    policy: SpillPolicy,
+    buffer: String,
+    file: Option<(SpilledFile, tokio::fs::File)>,
+}
+
+impl Collector {
+    async fn push(&mut self, text: &str) -> Result<(), ClientError> {
+        if self.file.is_none() && self.buffer.len() + text.len() > self.policy.memory_cap {
+            self.spill().await?;
+        }
+        match &mut self.file {
+            Some((spilled, file)) => file.write_all(text.as_bytes()).await.context(SpillSnafu {
+                path: spilled.path.clone(),
+            }),
+            None => {
+                self.buffer.push_str(text);
+                Ok(())
+            }
+        }
+    }
+
+    /// Moves the buffered text to a new spill file
+    async fn spill(&mut self) -> Result<(), ClientError> {
+        let name = format!(
+            "gemini-stream-{}-{}.txt",
+            std::process::id(),
+            SPILL_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = self.policy.dir.join(name);
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+            .context(SpillSnafu { path: path.clone() })?;
+        // From here on, the file is deleted however collection ends
+        let spilled = SpilledFile { path };
+        tracing::debug!(path = %spilled.path.display(), "spilling streamed text to disk");
+        file.write_all(std::mem::take(&mut self.buffer).as_bytes())
+            .await
+            .context(SpillSnafu {
+                path: spilled.path.clone(),
+            })?;
+        self.file = Some((spilled, file));
+        Ok(())
+    }
+
+    async fn finish(self) -> Result<TextSource, ClientError> {
+        match self.file {
+            Some((spilled, mut file)) => {
+                file.flush().await.context(SpillSnafu {
+                    path: spilled.path.clone(),
+                })?;
+                Ok(TextSource::File(spilled))
+            }
+            None => Ok(TextSource::Memory(self.buffer)),
+        }
+    }
+}
+
+/// Collects the answer text of `stream`, spilling to disk according to `policy`
+pub(crate) async fn collect(
+    stream: impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send,
+    policy: SpillPolicy,
+) -> Result<CollectedText, ClientError> {
+    let mut collector = Collector {
+        policy,
+        buffer: String::new(),
+        file: None,
+    };
+    let mut summary = StreamSummary::default();
+    let stream = stream.into_stream();
+    futures::pin_mut!(stream);
+    while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
+        let chunk = chunk?;
+        summary.record(&chunk);
+        collector.push(&continuation::answer_text(&chunk)).await?;
+    }
+    Ok(CollectedText {
+        text: collector.finish().await?,
+        summary,
+    })
+}
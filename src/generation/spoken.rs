@@ -0,0 +1,111 @@
+//! Spoken responses: generated audio paired with a text transcript.
+//!
+//! [`SpokenResponse`] is shared by the REST text-to-speech path
+//! ([`ContentBuilder::execute_spoken()`](crate::ContentBuilder::execute_spoken)) and the Live
+//! API path ([`live::spoken_turns()`](crate::live::spoken_turns)).
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{Blob, GenerationResponse, Part};
+
+/// Generated audio together with what was said
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpokenResponse {
+    /// Audio chunks in the order they were produced
+    pub audio: Vec<Blob>,
+    /// Transcript of the generated audio, if requested
+    pub transcript: Option<String>,
+    /// Transcript of the audio sent by the user (Live API only)
+    pub input_transcript: Option<String>,
+}
+
+impl SpokenResponse {
+    /// Collects the inline audio parts of a response
+    pub fn from_response(response: &GenerationResponse) -> Self {
+        let audio = response
+            .candidates
+            .iter()
+            .flat_map(|candidate| candidate.content.parts.iter().flatten())
+            .filter_map(|part| match part {
+                Part::InlineData { inline_data } if inline_data.mime_type.starts_with("audio/") => {
+                    Some(inline_data.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        Self {
+            audio,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the response carries any audio
+    pub fn has_audio(&self) -> bool {
+        !self.audio.is_empty()
+    }
+
+    /// Decodes and concatenates all audio chunks
+    pub fn audio_bytes(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        let mut bytes = Vec::new();
+        for blob in &self.audio {
+            bytes.extend(STANDARD.decode(&blob.data)?);
+        }
+        Ok(bytes)
+    }
+
+    /// Appends a transcript fragment to `transcript`
+    #[cfg(feature = "live")]
+    pub(crate) fn push_transcript(transcript: &mut Option<String>, fragment: &str) {
+        transcript
+            .get_or_insert_with(String::new)
+            .push_str(fragment);
+    }
+
+    /// Returns the audio as a single blob that the API accepts as input.
+    ///
+    /// Raw PCM (`audio/L16`) output is wrapped in a WAV container; other formats are
+    /// concatenated as is.
+    pub(crate) fn audio_as_input(&self) -> Result<Option<Blob>, base64::DecodeError> {
+        let Some(first) = self.audio.first() else {
+            return Ok(None);
+        };
+        let bytes = self.audio_bytes()?;
+        let blob = match pcm_sample_rate(&first.mime_type) {
+            Some(rate) => Blob::new("audio/wav", STANDARD.encode(wav(&bytes, rate))),
+            None => Blob::new(first.mime_type.clone(), STANDARD.encode(bytes)),
+        };
+        Ok(Some(blob))
+    }
+}
+
+/// Extracts the sample rate from a MIME type such as `audio/L16;codec=pcm;rate=24000`
+fn pcm_sample_rate(mime_type: &str) -> Option<u32> {
+    let mut params = mime_type.split(';').map(str::trim);
+    if !params.next()?.eq_ignore_ascii_case("audio/L16") {
+        return None;
+    }
+    params
+        .find_map(|param| param.strip_prefix("rate="))
+        .and_then(|rate| rate.parse().ok())
+        .or(Some(24_000))
+}
+
+/// Wraps 16-bit mono little-endian PCM samples in a WAV header
+fn wav(pcm: &[u8], sample_rate: u32) -> Vec<u8> {
+    let data_len = pcm.len() as u32;
+    let mut wav = Vec::with_capacity(44 + pcm.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    wav.extend_from_slice(pcm);
+    wav
+}
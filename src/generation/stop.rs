@@ -0,0 +1,295 @@
+//! Client-side stop patterns for streamed responses.
+//!
+//! The API's `stopSequences` only match literal text. A [`StopPattern`] is matched by the
+//! crate instead, so it can be a case-insensitive literal or a regular expression. While
+//! streaming, text that could still turn out to be the start of a match is held back until
+//! the next chunk decides it, so matches split across chunk boundaries are found and the
+//! output is truncated exactly where the match starts. For literals only a partial match at
+//! the end of the text is held back; for regexes, their maximum match length.
+
+use futures::{TryStream, TryStreamExt};
+use regex::Regex;
+
+use crate::{
+    client::Error as ClientError, Candidate, Content, FinishReason, GenerationResponse, Part, Role,
+};
+
+/// Characters of already emitted text kept so that look-behind assertions such as `\b`
+/// work at the start of a chunk
+const CONTEXT_CHARS: usize = 16;
+
+/// A pattern that ends a streamed response when it appears in the output
+#[derive(Debug, Clone)]
+pub struct StopPattern {
+    regex: Regex,
+    kind: PatternKind,
+}
+
+#[derive(Debug, Clone)]
+enum PatternKind {
+    Literal { text: String, ignore_case: bool },
+    Regex { max_len: usize },
+}
+
+impl StopPattern {
+    /// Stops at the first occurrence of `text`
+    pub fn literal(text: impl Into<String>) -> Self {
+        Self::new_literal(text.into(), false)
+    }
+
+    /// Stops at the first occurrence of `text`, ignoring case
+    pub fn literal_ignore_case(text: impl Into<String>) -> Self {
+        Self::new_literal(text.into(), true)
+    }
+
+    fn new_literal(text: String, ignore_case: bool) -> Self {
+        let flags = if ignore_case { "(?i)" } else { "" };
+        Self {
+            regex: Regex::new(&format!("{flags}{}", regex::escape(&text)))
+                .expect("escaped literal is a valid regex"),
+            kind: PatternKind::Literal { text, ignore_case },
+        }
+    }
+
+    /// Stops at the first match of the regular expression `pattern`.
+    ///
+    /// `max_len` bounds the length of a match in characters; while streaming, up to
+    /// `max_len - 1` characters are held back, so longer matches may be missed across chunks.
+    pub fn regex(pattern: &str, max_len: usize) -> Result<Self, regex::Error> {
+        Ok(Self {
+            regex: Regex::new(pattern)?,
+            kind: PatternKind::Regex { max_len },
+        })
+    }
+
+    /// Whether a match ending at the very end of the text so far is final. Regex matches may
+    /// depend on text that has not arrived yet, e.g. through a trailing `\b`.
+    fn is_exact(&self) -> bool {
+        matches!(self.kind, PatternKind::Literal { .. })
+    }
+
+    /// Number of characters at the end of `text` that may start a match
+    fn holdback(&self, text: &str) -> usize {
+        match &self.kind {
+            PatternKind::Regex { max_len } => max_len.saturating_sub(1),
+            PatternKind::Literal {
+                text: literal,
+                ignore_case,
+            } => {
+                let same = |a: char, b: char| match ignore_case {
+                    true => a.to_lowercase().eq(b.to_lowercase()),
+                    false => a == b,
+                };
+                let tail: Vec<char> = text.chars().rev().take(literal.chars().count()).collect();
+                (1..literal.chars().count())
+                    .rev()
+                    .find(|&k| {
+                        k <= tail.len()
+                            && tail[..k]
+                                .iter()
+                                .rev()
+                                .zip(literal.chars())
+                                .all(|(a, b)| same(*a, b))
+                    })
+                    .unwrap_or(0)
+            }
+        }
+    }
+}
+
+/// Scans the answer text of the first candidate across chunks
+struct StopScanner {
+    patterns: Vec<StopPattern>,
+    /// Tail of the emitted text
+    context: String,
+    /// Text received but not emitted yet
+    pending: String,
+}
+
+impl StopScanner {
+    fn new(patterns: Vec<StopPattern>) -> Self {
+        Self {
+            patterns,
+            context: String::new(),
+            pending: String::new(),
+        }
+    }
+
+    /// Returns the text that can be emitted and whether a pattern matched
+    fn scan(&mut self, ended: bool) -> (String, bool) {
+        let haystack = format!("{}{}", self.context, self.pending);
+        let start = self.context.len();
+
+        let mut first: Option<(usize, bool)> = None;
+        for pattern in &self.patterns {
+            if let Some(m) = pattern.regex.find_at(&haystack, start) {
+                let accepted = ended || pattern.is_exact() || m.end() < haystack.len();
+                if first.is_none_or(|(s, _)| m.start() < s) {
+                    first = Some((m.start(), accepted));
+                }
+            }
+        }
+        if let Some((s, true)) = first {
+            self.pending.clear();
+            return (haystack[start..s].to_string(), true);
+        }
+
+        let mut cut = first.map_or(haystack.len(), |(s, _)| s);
+        if !ended {
+            let text = &haystack[start..];
+            let holdback = self.patterns.iter().map(|p| p.holdback(text)).max();
+            let chars = text.chars().count();
+            let keep_from = text
+                .char_indices()
+                .nth(chars.saturating_sub(holdback.unwrap_or(0)))
+                .map_or(haystack.len(), |(i, _)| start + i);
+            cut = cut.min(keep_from);
+        }
+        let emit = haystack[start..cut].to_string();
+        self.pending = haystack[cut..].to_string();
+
+        let emitted = &haystack[..cut];
+        let context_from = emitted
+            .char_indices()
+            .rev()
+            .nth(CONTEXT_CHARS - 1)
+            .map_or(0, |(i, _)| i);
+        self.context = emitted[context_from..].to_string();
+        (emit, false)
+    }
+
+    /// Runs the chunk's answer text through the scanner, returning whether a pattern matched.
+    ///
+    /// Each text part is scanned in turn and keeps its place and its thought signature; text
+    /// held back at the end of one part is emitted at the start of the next.
+    fn apply(&mut self, chunk: &mut GenerationResponse) -> bool {
+        let Some(candidate) = chunk.candidates.first_mut() else {
+            return false;
+        };
+        let ended = candidate.finish_reason.is_some();
+        let parts = candidate.content.parts.take().unwrap_or_default();
+        let last_text = parts.iter().rposition(is_answer_text);
+
+        let mut kept = Vec::with_capacity(parts.len());
+        let mut stopped = false;
+        for (index, mut part) in parts.into_iter().enumerate() {
+            let Part::Text {
+                text,
+                thought: None | Some(false),
+                thought_signature,
+            } = &mut part
+            else {
+                kept.push(part);
+                continue;
+            };
+            self.pending.push_str(text);
+            let emit;
+            (emit, stopped) = self.scan(ended && Some(index) == last_text);
+            *text = emit;
+            if !text.is_empty() || thought_signature.is_some() {
+                kept.push(part);
+            }
+            if stopped {
+                candidate.finish_reason = Some(FinishReason::ClientStop);
+                break;
+            }
+        }
+        if last_text.is_none() && ended && !self.pending.is_empty() {
+            let emit;
+            (emit, stopped) = self.scan(true);
+            if stopped {
+                candidate.finish_reason = Some(FinishReason::ClientStop);
+            }
+            if !emit.is_empty() {
+                kept.push(Part::Text {
+                    text: emit,
+                    thought: None,
+                    thought_signature: None,
+                });
+            }
+        }
+        candidate.content.parts = Some(kept);
+        stopped
+    }
+
+    /// Emits the held back text once the stream ended without a finish reason
+    fn flush(&mut self) -> Option<GenerationResponse> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let (emit, stopped) = self.scan(true);
+        Some(GenerationResponse {
+            candidates: vec![Candidate {
+                content: Content {
+                    parts: Some(vec![Part::Text {
+                        text: emit,
+                        thought: None,
+                        thought_signature: None,
+                    }]),
+                    role: Some(Role::Model),
+                },
+                safety_ratings: None,
+                citation_metadata: None,
+                grounding_metadata: None,
+                finish_reason: stopped.then_some(FinishReason::ClientStop),
+                index: Some(0),
+                avg_logprobs: None,
+                url_context_metadata: None,
+                finish_message: None,
+            }],
+            prompt_feedback: None,
+            usage_metadata: None,
+            model_version: None,
+            response_id: None,
+            continuations: 0,
+            modality_downgraded: false,
+            attempts: Default::default(),
+            meta: Default::default(),
+            tool_transcript: Vec::new(),
+        })
+    }
+}
+
+/// Whether `part` is answer text, as opposed to a thought or another kind of part
+fn is_answer_text(part: &Part) -> bool {
+    matches!(
+        part,
+        Part::Text {
+            thought: None | Some(false),
+            ..
+        }
+    )
+}
+
+/// Truncates the first candidate's text at the first match of any of `patterns`.
+///
+/// The chunk containing the match gets the finish reason [`FinishReason::ClientStop`] and
+/// ends the stream; dropping the underlying stream closes the connection. Without patterns,
+/// chunks pass through untouched.
+pub(crate) fn client_stop(
+    stream: impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send + Unpin,
+    patterns: Vec<StopPattern>,
+) -> impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send + Unpin {
+    Box::pin(async_stream::try_stream! {
+        let mut scanner = (!patterns.is_empty()).then(|| StopScanner::new(patterns));
+        let stream = stream.into_stream();
+        futures::pin_mut!(stream);
+        let mut stopped = false;
+        while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
+            let mut chunk = chunk?;
+            if let Some(scanner) = scanner.as_mut() {
+                stopped = scanner.apply(&mut chunk);
+            }
+            yield chunk;
+            if stopped {
+                tracing::debug!("aborting stream after client stop pattern");
+                break;
+            }
+        }
+        if !stopped {
+            if let Some(chunk) = scanner.as_mut().and_then(StopScanner::flush) {
+                yield chunk;
+            }
+        }
+    })
+}
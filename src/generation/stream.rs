@@ -0,0 +1,76 @@
+//! Event view of streamed responses.
+//!
+//! Streamed chunks carry a candidate's `finishReason` on the chunk where generation stopped,
+//! which may be followed by trailing chunks (e.g. with usage metadata, or repeating the
+//! reason). [`StreamEvent`] surfaces the finish reason as soon as it first appears, exactly
+//! once per candidate.
+
+use futures::{TryStream, TryStreamExt};
+use std::collections::BTreeSet;
+
+use crate::{client::Error as ClientError, FinishReason, GenerationResponse};
+
+/// An event of a streamed response
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    /// A chunk of the response
+    Chunk(Box<GenerationResponse>),
+    /// A candidate finished; emitted right after the chunk carrying the finish reason
+    Finished(FinishReason),
+}
+
+/// Turns a stream of chunks into a stream of events
+pub(crate) fn events(
+    stream: impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send,
+) -> impl TryStream<Ok = StreamEvent, Error = ClientError> + Send + Unpin {
+    Box::pin(async_stream::try_stream! {
+        let mut finished = BTreeSet::new();
+        let stream = stream.into_stream();
+        futures::pin_mut!(stream);
+        while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
+            let chunk = chunk?;
+            let reasons: Vec<FinishReason> = chunk
+                .candidates
+                .iter()
+                .enumerate()
+                .filter_map(|(position, candidate)| {
+                    let index = candidate.index.unwrap_or(position as i32);
+                    let reason = candidate.finish_reason.clone()?;
+                    finished.insert(index).then_some(reason)
+                })
+                .collect();
+            yield StreamEvent::Chunk(Box::new(chunk));
+            for reason in reasons {
+                yield StreamEvent::Finished(reason);
+            }
+        }
+    })
+}
+
+/// Ends the stream after the first chunk in which a candidate finishes with one of `reasons`.
+///
+/// Dropping the underlying stream closes the connection, so trailing chunks are never
+/// downloaded.
+pub(crate) fn abort_on(
+    stream: impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send + Unpin,
+    reasons: Vec<FinishReason>,
+) -> impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send + Unpin {
+    Box::pin(async_stream::try_stream! {
+        let stream = stream.into_stream();
+        futures::pin_mut!(stream);
+        while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
+            let chunk = chunk?;
+            let abort = chunk.candidates.iter().any(|candidate| {
+                candidate
+                    .finish_reason
+                    .as_ref()
+                    .is_some_and(|reason| reasons.contains(reason))
+            });
+            yield chunk;
+            if abort {
+                tracing::debug!("aborting stream after finish reason");
+                break;
+            }
+        }
+    })
+}
@@ -0,0 +1,175 @@
+//! Videos referenced by URI, optionally clipped.
+//!
+//! [`ContentBuilder::with_video()`](crate::ContentBuilder::with_video) adds a `fileData` part
+//! for a video uploaded with the [Files API](crate::files::api) or hosted on YouTube. A
+//! [`Video`] can target a clip and set the frame rate the model samples, through the part's
+//! [`VideoMetadata`], without re-encoding the video:
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! use gemini_rust::generation::video::Video;
+//!
+//! # fn run(client: gemini_rust::Gemini) -> Result<(), gemini_rust::VideoError> {
+//! let video = Video::youtube("https://www.youtube.com/watch?v=9hE5-98ZeCg")
+//!     .with_start_offset("40s")
+//!     .with_end_offset("80s")
+//!     .with_fps(5.0);
+//! let request = client
+//!     .generate_content()
+//!     .with_user_message("What happens in this clip?")
+//!     .with_video(video)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Offsets are checked when the video is added: they must be durations in the API's format,
+//! such as `"90s"` or `"1.5s"`, and the clip must end after it starts.
+
+use snafu::Snafu;
+use std::time::Duration;
+
+#[cfg(feature = "files")]
+use crate::files::handle::FileHandle;
+use crate::{FileData, Part, VideoMetadata};
+
+/// Error adding a video
+#[derive(Debug, Snafu, PartialEq)]
+pub enum VideoError {
+    #[snafu(display("video offset '{offset}' is not a duration such as '90s' or '1.5s'"))]
+    InvalidOffset { offset: String },
+
+    #[snafu(display("video clip ends at {end} before it starts at {start}"))]
+    EmptyClip { start: String, end: String },
+
+    #[snafu(display("video frame rate {fps} is not a positive number"))]
+    InvalidFps { fps: f64 },
+}
+
+/// A video referenced by URI, with an optional clip and frame rate
+#[derive(Debug, Clone, PartialEq)]
+pub struct Video {
+    file_data: FileData,
+    metadata: VideoMetadata,
+}
+
+impl Video {
+    /// A video at `uri` of type `mime_type`, e.g. `video/mp4`
+    pub fn new(uri: impl Into<String>, mime_type: impl Into<String>) -> Self {
+        Self {
+            file_data: FileData::new(mime_type, uri),
+            metadata: VideoMetadata::default(),
+        }
+    }
+
+    /// A public YouTube video at `url`
+    pub fn youtube(url: impl Into<String>) -> Self {
+        Self {
+            file_data: FileData {
+                mime_type: None,
+                file_uri: url.into(),
+            },
+            metadata: VideoMetadata::default(),
+        }
+    }
+
+    /// Starts the clip at `offset`, e.g. `"90s"`
+    pub fn with_start_offset(mut self, offset: impl Into<String>) -> Self {
+        self.metadata.start_offset = Some(offset.into());
+        self
+    }
+
+    /// Ends the clip at `offset`, e.g. `"120.5s"`
+    pub fn with_end_offset(mut self, offset: impl Into<String>) -> Self {
+        self.metadata.end_offset = Some(offset.into());
+        self
+    }
+
+    /// Clips the video to the range from `start` to `end`
+    pub fn with_clip(self, start: Duration, end: Duration) -> Self {
+        self.with_start_offset(format_offset(start))
+            .with_end_offset(format_offset(end))
+    }
+
+    /// Samples `fps` frames per second instead of one
+    pub fn with_fps(mut self, fps: f64) -> Self {
+        self.metadata.fps = Some(fps);
+        self
+    }
+
+    /// Checks the clip and frame rate, and returns the video's part
+    pub(crate) fn into_part(self) -> Result<Part, VideoError> {
+        let metadata = self.metadata;
+        let start = metadata
+            .start_offset
+            .as_deref()
+            .map(parse_offset)
+            .transpose()?;
+        let end = metadata
+            .end_offset
+            .as_deref()
+            .map(parse_offset)
+            .transpose()?;
+        if let (Some(start), Some(end)) = (start, end) {
+            snafu::ensure!(
+                start < end,
+                EmptyClipSnafu {
+                    start: metadata.start_offset.clone().unwrap_or_default(),
+                    end: metadata.end_offset.clone().unwrap_or_default(),
+                }
+            );
+        }
+        if let Some(fps) = metadata.fps {
+            snafu::ensure!(fps.is_finite() && fps > 0.0, InvalidFpsSnafu { fps });
+        }
+        let video_metadata = (metadata != VideoMetadata::default()).then_some(metadata);
+        Ok(Part::FileData {
+            file_data: self.file_data,
+            video_metadata,
+        })
+    }
+}
+
+/// The file is referenced as by [`ContentBuilder::with_file()`](crate::ContentBuilder::with_file)
+#[cfg(feature = "files")]
+impl From<&FileHandle> for Video {
+    fn from(file: &FileHandle) -> Self {
+        let uri = file
+            .uri()
+            .map_or_else(|| file.name().to_string(), ToString::to_string);
+        let mime_type = file
+            .mime_type()
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM.as_ref());
+        Self::new(uri, mime_type)
+    }
+}
+
+/// Parses a duration in the API's format: seconds with up to nine decimals, followed by `s`
+pub fn parse_offset(offset: &str) -> Result<Duration, VideoError> {
+    let invalid = || VideoError::InvalidOffset {
+        offset: offset.to_string(),
+    };
+    let seconds = offset.strip_suffix('s').ok_or_else(invalid)?;
+    let (whole, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let fraction_ok = !seconds.contains('.') || (digits(fraction) && fraction.len() <= 9);
+    if !digits(whole) || !fraction_ok {
+        return Err(invalid());
+    }
+    let whole: u64 = whole.parse().map_err(|_| invalid())?;
+    let nanos: u32 = match fraction {
+        "" => 0,
+        fraction => format!("{fraction:0<9}").parse().map_err(|_| invalid())?,
+    };
+    Ok(Duration::new(whole, nanos))
+}
+
+/// Formats a duration in the API's format, e.g. `"1.5s"`
+pub fn format_offset(offset: Duration) -> String {
+    match offset.subsec_nanos() {
+        0 => format!("{}s", offset.as_secs()),
+        nanos => {
+            let fraction = format!("{nanos:09}");
+            format!("{}.{}s", offset.as_secs(), fraction.trim_end_matches('0'))
+        }
+    }
+}
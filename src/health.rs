@@ -0,0 +1,81 @@
+//! Cheap readiness checks of credentials and connectivity.
+//!
+//! [`Gemini::health_check()`](crate::Gemini::health_check) fetches the metadata of the
+//! client's default model, which costs no tokens, and classifies the outcome as a
+//! [`HealthStatus`]. The check is sent once, never retried, and bounded by its own timeout
+//! ([`DEFAULT_HEALTH_CHECK_TIMEOUT`] unless given), independent of the client's timeouts, so a
+//! probe answers within a known time.
+
+use std::time::Duration;
+
+use crate::client::Error as ClientError;
+
+/// Timeout of [`Gemini::health_check()`](crate::Gemini::health_check)
+pub const DEFAULT_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The outcome of a health check
+#[derive(Debug)]
+pub struct HealthReport {
+    /// How the API answered
+    pub status: HealthStatus,
+    /// Time from sending the request to classifying its outcome
+    pub latency: Duration,
+}
+
+impl HealthReport {
+    /// Whether the API answered successfully
+    pub fn is_healthy(&self) -> bool {
+        matches!(self.status, HealthStatus::Healthy)
+    }
+}
+
+/// How the API answered a health check
+#[derive(Debug)]
+pub enum HealthStatus {
+    /// The API is reachable and accepted the credentials
+    Healthy,
+    /// The API rejected the credentials, or no credentials could be obtained
+    AuthError {
+        /// The rejected request
+        source: ClientError,
+    },
+    /// The credentials are valid, but their quota is exhausted
+    RateLimited,
+    /// The API could not be reached in time, or failed for another reason
+    Unreachable {
+        /// Why the check failed
+        source: ClientError,
+    },
+}
+
+impl HealthStatus {
+    /// Classifies the outcome of a health check request
+    pub(crate) fn classify(result: Result<(), ClientError>) -> Self {
+        let source = match result {
+            Ok(()) => return HealthStatus::Healthy,
+            Err(source) => source,
+        };
+        match &source {
+            ClientError::BadResponse { code: 429, .. } => HealthStatus::RateLimited,
+            ClientError::BadResponse {
+                code: 401 | 403, ..
+            }
+            | ClientError::BearerToken { .. } => HealthStatus::AuthError { source },
+            // An invalid API key is reported as a bad request
+            ClientError::BadResponse { code: 400, .. } if is_invalid_api_key(&source) => {
+                HealthStatus::AuthError { source }
+            }
+            _ => HealthStatus::Unreachable { source },
+        }
+    }
+}
+
+/// Whether the error body of a bad request reports an invalid API key
+fn is_invalid_api_key(error: &ClientError) -> bool {
+    error.api_status().is_some_and(|status| {
+        status
+            .details
+            .iter()
+            .any(|detail| detail.get("reason").and_then(|r| r.as_str()) == Some("API_KEY_INVALID"))
+    })
+}
@@ -0,0 +1,94 @@
+//! The JSON parser used for response bodies.
+//!
+//! Response bodies, server-sent events, and the items of
+//! [`execute_stream_as_items()`](crate::ContentBuilder::execute_stream_as_items) are parsed with
+//! serde_json by default. With the `simd-json` feature they are parsed with simd-json.
+//!
+//! Whether simd-json is faster depends on the bodies and the build. simd-json picks its SIMD
+//! instructions at compile time, so build with e.g. `RUSTFLAGS="-C target-cpu=native"` to use
+//! AVX2. `benches/json_backend.rs` compares the backends on a large structured answer and on
+//! a batch of embeddings:
+//!
+//! ```sh
+//! RUSTFLAGS="-C target-cpu=native" cargo bench --bench json_backend --features simd-json
+//! ```
+//!
+//! On an x86-64 machine with AVX2, simd-json parsed the structured answer, made mostly of
+//! strings, about 25% faster than serde_json, but took about twice as long on the
+//! embeddings, made mostly of floats. Measure with your own responses before enabling it.
+//!
+//! The backend only changes how bytes become values: the public types, and the
+//! `serde_json::Error`s reported for invalid JSON, stay the same. simd-json parses in place,
+//! so the input is copied into an owned buffer first, keeping the body intact for error
+//! diagnostics. Input simd-json rejects is parsed again with serde_json, which either accepts
+//! it or produces the error.
+
+use serde::de::DeserializeOwned;
+
+/// A JSON parser, see the [module documentation](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonBackend {
+    /// serde_json, the default
+    SerdeJson,
+    /// simd-json, enabled by the `simd-json` feature
+    #[cfg(feature = "simd-json")]
+    SimdJson,
+}
+
+impl JsonBackend {
+    /// The backend the client parses responses with
+    #[cfg(not(feature = "simd-json"))]
+    pub const ACTIVE: JsonBackend = JsonBackend::SerdeJson;
+
+    /// The backend the client parses responses with
+    #[cfg(feature = "simd-json")]
+    pub const ACTIVE: JsonBackend = JsonBackend::SimdJson;
+
+    /// Every backend this build supports
+    pub fn available() -> &'static [JsonBackend] {
+        &[
+            JsonBackend::SerdeJson,
+            #[cfg(feature = "simd-json")]
+            JsonBackend::SimdJson,
+        ]
+    }
+
+    /// Deserializes `T` from JSON bytes with this backend alone.
+    ///
+    /// Errors of simd-json are converted to `serde_json::Error`s, without a line and column.
+    pub fn from_slice<T: DeserializeOwned>(self, json: &[u8]) -> Result<T, serde_json::Error> {
+        match self {
+            JsonBackend::SerdeJson => serde_json::from_slice(json),
+            #[cfg(feature = "simd-json")]
+            JsonBackend::SimdJson => {
+                let mut owned = json.to_vec();
+                simd_json::serde::from_slice(&mut owned).map_err(serde::de::Error::custom)
+            }
+        }
+    }
+
+    /// Deserializes `T` from JSON text with this backend alone
+    pub fn from_str<T: DeserializeOwned>(self, json: &str) -> Result<T, serde_json::Error> {
+        self.from_slice(json.as_bytes())
+    }
+}
+
+/// Deserializes `T` from JSON bytes with the [active](JsonBackend::ACTIVE) backend, falling
+/// back to serde_json
+pub(crate) fn from_slice<T: DeserializeOwned>(json: &[u8]) -> Result<T, serde_json::Error> {
+    match JsonBackend::ACTIVE {
+        JsonBackend::SerdeJson => serde_json::from_slice(json),
+        #[cfg(feature = "simd-json")]
+        backend => backend.from_slice(json).or_else(|error| {
+            tracing::trace!(%error, "simd-json rejected input, parsing with serde_json");
+            serde_json::from_slice(json)
+        }),
+    }
+}
+
+/// Deserializes `T` from JSON text with the [active](JsonBackend::ACTIVE) backend, falling
+/// back to serde_json
+#[cfg(feature = "streaming")]
+pub(crate) fn from_str<T: DeserializeOwned>(json: &str) -> Result<T, serde_json::Error> {
+    from_slice(json.as_bytes())
+}
@@ -7,12 +7,35 @@
 //! This crate is organized into domain-specific modules that align with the Gemini API's
 //! capabilities:
 //!
+//! - **`auth`** - Authentication modes (API key, bearer tokens, none)
+//! - **`backend`** - The Gemini API or Vertex AI, in a project or in express mode
 //! - **`generation`** - Content generation, including text, images, and audio
-//! - **`embedding`** - Text embedding generation for semantic analysis
-//! - **`batch`** - Batch processing for multiple requests
-//! - **`files`** - File upload and management
-//! - **`cache`** - Content caching for reusable contexts
+//! - **`embedding`** - Text embedding generation for semantic analysis (`embeddings` feature)
+//! - **`batch`** - Batch processing for multiple requests (`batch` feature)
+//! - **`files`** - File upload and management (`files` feature)
+//! - **`cache`** - Content caching for reusable contexts (`caching` feature)
+//! - **`chat`** - Stateful multi-turn chat sessions
+//! - **`cleanup`** - Deletion of temporary files and cached contents, on drop or by sweeping
+//! - **`clock`** - Time source for backoff and polling, replaceable in tests
+//! - **`dns`** - Static addresses and custom resolvers for restricted egress environments
+//! - **`health`** - Readiness checks of credentials and connectivity
+//! - **`json`** - The JSON parser of response bodies, serde_json or simd-json (`simd-json` feature)
+//! - **`limits`** - Token limits of models and a context-window guard
+//! - **`pricing`** - Dollar estimates of request costs from token usage
+//! - **`relay`** - Generation streams relayed to HTTP clients as server-sent events (`relay` feature)
+//! - **`compat`** - Conversion to and from other providers' formats (`openai-compat` feature)
+//!   and the API's protobuf messages (`proto` feature)
+//! - **`live`** - Message types for the bidirectional Live API (`live` feature)
+//! - **`metrics`** - Request and response sizes reported to a metrics sink
+//! - **`retry`** - Retry policies and retry budgets
 //! - **`safety`** - Content moderation and safety settings
+//! - **`schema`** - Response and parameter schemas, derivable with `#[derive(GeminiSchema)]`
+//! - **`text_utils`** - Plain-text rendering of Markdown model output
+//! - **`toon`** - TOON encoding of datasets sent to the model, and TOON/JSON conversion (`toon`
+//!   feature)
+//! - **`trace_context`** - W3C `traceparent` headers joining requests to the caller's trace,
+//!   from OpenTelemetry with the `otel` feature
+//! - **`transport`** - HTTP versions of the connections to the API, including HTTP/3
 //! - **`tools`** - Function calling and tool integration
 //! - **`models`** - Core primitive types shared across modules
 //! - **`prelude`** - Convenient re-exports of commonly used types
@@ -27,6 +50,44 @@
 //!
 //! For more specialized types, import them directly from the crate root or their
 //! respective modules.
+//!
+//! ## Feature flags
+//!
+//! The core of the crate is the REST text path: generation, chat sessions, retries, safety
+//! settings, and function declarations. Everything else is behind a feature:
+//!
+//! | Feature | Adds |
+//! |---|---|
+//! | `tools` | The automatic tool loop: `ToolRegistry`, `execute_with_tools()`, run logs |
+//! | `schema` | `#[derive(GeminiSchema)]` and schemas of schemars' `JsonSchema` types |
+//! | `files` | The Files API |
+//! | `caching` | Cached contents |
+//! | `embeddings` | Text embeddings |
+//! | `batch` | Batch jobs; enables `files` |
+//! | `streaming` | Streamed generation and everything built on it |
+//! | `toon` | TOON encoding and parsing |
+//! | `live` | Message types of the Live API |
+//! | `image` | Images read from files, and inline media saved to files |
+//! | `full` | All of the above |
+//!
+//! The default features are `streaming`, `embeddings`, `files`, `batch`, `caching`, and
+//! `schema`, which together cover the surface of 1.5, released before the flags were
+//! introduced. With `default-features = false`, the build is the core alone, and the
+//! features above add to it. The core alone becomes the default in 2.0; making it the default
+//! now would break every 1.x build.
+//!
+//! `relay` enables `streaming`; `openai-compat`, `testing`, `custom-dns`, `chrono`, `uuid`,
+//! `simd-json`, `http3`, `otel`, and `proto` are independent of them. Every feature compiles
+//! on its own, which `tests/features.rs` checks.
+//!
+//! `toon_wip` enables `toon` and adds `ContentBuilder::execute_toon()`, which parses answers
+//! as TOON. It is unstable, may change in any release, and is left out of `full`.
+
+/// Authentication modes of the client
+pub mod auth;
+
+/// The API a client talks to
+pub mod backend;
 
 pub mod client;
 mod models;
@@ -35,35 +96,109 @@ mod models;
 pub mod prelude;
 
 /// Batch processing for multiple generation requests
+#[cfg(feature = "batch")]
 pub mod batch;
 
 /// Content caching for reusable contexts and system instructions
+#[cfg(feature = "caching")]
 pub mod cache;
 
+/// Stateful multi-turn chat sessions
+pub mod chat;
+
+/// Conversion to and from other providers' request and response formats
+#[cfg(any(feature = "openai-compat", feature = "proto"))]
+pub mod compat;
+
+/// Deletion of temporary files and cached contents
+#[cfg(any(feature = "files", feature = "caching"))]
+pub mod cleanup;
+
+/// Time source for backoff, polling, and measuring durations
+pub mod clock;
+
 /// Common utilities and serialization helpers
 pub mod common;
 
+/// Static addresses and custom resolvers for restricted egress environments
+pub mod dns;
+
 /// Text embedding generation for semantic analysis
 pub mod embedding;
 
 /// File upload and management
+#[cfg(feature = "files")]
 pub mod files;
 
+/// Cheap readiness checks of credentials and connectivity
+pub mod health;
+
 /// Content generation including text, images, and audio
 pub mod generation;
 
+/// The JSON parser used for response bodies
+pub mod json;
+
+/// Message types for the bidirectional Live API
+#[cfg(feature = "live")]
+pub mod live;
+
+/// Token limits of models and a context-window guard
+pub mod limits;
+
+/// Metrics the client reports about its requests
+pub mod metrics;
+
+/// Dollar estimates of request costs from token usage
+pub mod pricing;
+
+/// Generation streams relayed to HTTP clients as server-sent events
+#[cfg(feature = "relay")]
+pub mod relay;
+
+/// Retry policies and retry budgets shared across nested retry mechanisms
+pub mod retry;
+
 /// Content moderation and safety settings
 pub mod safety;
 
+/// Response and parameter schemas in the subset of OpenAPI the API accepts
+pub mod schema;
+
+/// Plain-text rendering of Markdown model output
+pub mod text_utils;
+
+/// TOON encoding of datasets sent to the model, and TOON/JSON conversion
+#[cfg(feature = "toon")]
+pub mod toon;
+
 /// Function calling and tool integration
 pub mod tools;
 
+/// W3C trace context sent with requests
+pub mod trace_context;
+
+/// HTTP versions of the connections to the API, including opt-in HTTP/3
+pub mod transport;
+
+/// Typed user data carried from a request to its response or error
+pub mod user_data;
+
 #[cfg(test)]
 mod tests;
 
+// Lets `#[derive(GeminiSchema)]` refer to `::gemini_rust` inside this crate too
+extern crate self as gemini_rust;
+
 // ========== Core Types ==========
 // These are the fundamental types used throughout the API
 
+/// How the client authenticates requests
+pub use auth::{Auth, TokenProvider};
+
+/// The API a client talks to
+pub use backend::Backend;
+
 /// The main client error type
 pub use client::Error as ClientError;
 /// The main Gemini API client
@@ -74,27 +209,73 @@ pub use client::GeminiBuilder;
 pub use client::Model;
 
 /// Core primitive types for building requests and parsing responses
-pub use models::{Blob, Content, Message, Modality, Part, Role};
+pub use models::{
+    Blob, CodeExecutionResult, Content, ExecutableCode, FileData, Message, Part, VideoMetadata,
+    SUMMARY_CHARS,
+};
+
+/// Enumerations shared across request and response types
+pub use common::types::{Language, Modality, Outcome, Role};
 
 // ========== Content Generation ==========
 // Types for generating text, images, and audio content
 
 pub use generation::{
-    builder::ContentBuilder, model::BlockReason, model::Candidate, model::CitationMetadata,
-    model::CitationSource, model::FinishReason, model::GenerateContentRequest,
-    model::GenerationConfig, model::GenerationResponse, model::GroundingChunk,
-    model::GroundingMetadata, model::GroundingSegment, model::GroundingSupport,
-    model::MapsGroundingChunk, model::MultiSpeakerVoiceConfig, model::PrebuiltVoiceConfig,
-    model::PromptFeedback, model::PromptTokenDetails, model::SpeakerVoiceConfig,
-    model::SpeechConfig, model::ThinkingConfig, model::UsageMetadata, model::VoiceConfig,
-    model::WebGroundingChunk,
+    attribution::AttributedAnswer, attribution::AttributedAnswerBuilder,
+    attribution::AttributedResult, attribution::Citation, attribution::CitationStatus,
+    attribution::Error as AttributionError, attribution::VerifiedCitation, audio::AudioError,
+    builder::ContentBuilder, bundle::BundleFlags, bundle::PromptBundle,
+    concurrency::AdaptiveLimiter, concurrency::ConcurrencyLimiter, concurrency::FixedLimiter,
+    concurrency::RequestOutcome, concurrency::RequestSample, count::CountTokensBuilder,
+    dataset::DatasetBuilder, dataset::TokenBudget, dataset::TokenEstimator, documents::Document,
+    documents::DocumentError, fragments::ComposedPrompt, fragments::FragmentError,
+    fragments::SystemPromptComposer, lint::LintKind, lint::LintRule, lint::LintSeverity,
+    lint::LintWarning, modalities::supported_response_modalities, model::BlockReason,
+    model::Candidate, model::CitationMetadata, model::CitationSource, model::CountTokensResponse,
+    model::FinishReason, model::GenerateContentRequest, model::GenerationConfig,
+    model::GenerationResponse, model::GroundingChunk, model::GroundingMetadata,
+    model::GroundingSegment, model::GroundingSupport, model::MapsGroundingChunk,
+    model::MultiSpeakerVoiceConfig, model::PrebuiltVoiceConfig, model::PromptFeedback,
+    model::PromptTokenDetails, model::SearchEntryPoint, model::SpeakerVoiceConfig,
+    model::SpeechConfig, model::ThinkingConfig, model::UrlContextMetadata, model::UrlMetadata,
+    model::UsageMetadata, model::VoiceConfig, model::WebGroundingChunk, race::RaceAttempt,
+    race::RaceOutcome, race::RaceResult, spoken::SpokenResponse, video::Video, video::VideoError,
+};
+
+/// Streamed generation
+#[cfg(feature = "streaming")]
+pub use generation::{
+    aggregate::AggregatePolicy, aggregate::AggregatedCandidate, aggregate::AggregatedPartial,
+    aggregate::AggregatedResponse, aggregate::BestCandidate, items::Error as ItemError,
+    items::ItemEvent, items::StreamSummary, spill::CollectedText, spill::SpillPolicy,
+    spill::SpilledFile, spill::TextSource, stop::StopPattern, stream::StreamEvent,
+};
+
+/// Errors reading and writing TOON
+#[cfg(feature = "toon")]
+pub use toon::Error as ToonError;
+
+/// Images and inline media
+#[cfg(feature = "image")]
+pub use generation::{image::ImageError, media::MediaError, media::SavedMedia};
+
+// ========== Chat Sessions ==========
+// Types for multi-turn conversations
+
+pub use chat::{
+    media::MediaAction, media::MediaPolicy, model::SeedStrategy, model::SessionExport,
+    model::TurnRecord, session::ChatSession, session::SessionView, tree::SessionTree,
 };
 
 // ========== Text Embeddings ==========
 // Types for generating and working with text embeddings
 
+pub use embedding::chunker::{ChunkingConfig, ChunkingError, TextChunker};
+#[cfg(feature = "embeddings")]
 pub use embedding::{
-    builder::EmbedBuilder, model::BatchContentEmbeddingResponse, model::BatchEmbedContentsRequest,
+    batch::BatchEmbedding, batch::EmbedBatchBuilder, batch::FailedBatch, builder::EmbedBuilder,
+    document::DocumentEmbedding, document::EmbedDocumentBuilder, document::EmbeddedChunk,
+    document::FailedChunk, model::BatchContentEmbeddingResponse, model::BatchEmbedContentsRequest,
     model::ContentEmbedding, model::ContentEmbeddingResponse, model::EmbedContentRequest,
     model::TaskType,
 };
@@ -103,20 +284,70 @@ pub use embedding::{
 // Types for content moderation and safety settings
 
 pub use safety::model::{
-    HarmBlockThreshold, HarmCategory, HarmProbability, SafetyRating, SafetySetting,
+    HarmBlockThreshold, HarmCategory, HarmProbability, HarmSeverity, SafetyRating, SafetySetting,
 };
+pub use safety::sanitize::{ControlAction, SanitizePolicy};
+pub use safety::screen::{OutputScreen, Redaction, Redactor, ScreenDecision};
+pub use safety::threshold::SafetyFlag;
+
+// ========== Schemas ==========
+// Types describing structured output and function parameters
+
+pub use schema::{Schema, SchemaError, SchemaType, ToSchema};
+
+/// Derives [`ToSchema`] from a struct or enum definition, see [`schema`]
+#[cfg(feature = "schema")]
+pub use gemini_rust_macros::GeminiSchema;
 
 // ========== Function Calling & Tools ==========
 // Types for integrating external tools and function calling
 
 pub use tools::model::{
     FunctionCall, FunctionCallingConfig, FunctionCallingMode, FunctionDeclaration,
-    FunctionResponse, GoogleMapsConfig, LatLng, RetrievalConfig, Tool, ToolConfig,
+    FunctionResponse, GoogleMapsConfig, LatLng, RetrievalConfig, Tool, ToolConfig, ToolExchange,
+};
+#[cfg(feature = "tools")]
+pub use tools::registry::{ToolLoopState, ToolRegistry, ToolResult};
+#[cfg(feature = "tools")]
+pub use tools::run_log::{
+    RunEvent, RunLog, RunLogError, RunLogOptions, RunOutcome, ToolOutcome, RUN_LOG_VERSION,
 };
 
+// ========== Retries ==========
+// Types for retrying failed requests
+
+pub use retry::{
+    AttemptRecord, AttemptsReport, BudgetUsage, Policy, PolicyError, RateLimit, RetryBudget,
+    RetryLayer, RetryPolicy,
+};
+
+// ========== Health Checks ==========
+// Types for probing the API without generating content
+
+pub use health::{HealthReport, HealthStatus};
+
+// ========== Model Limits ==========
+// Types for checking requests against the context window of their model
+
+pub use limits::{ContextGuard, ListModelsResponse, ModelInfo, ModelLimits};
+
+// ========== Pricing ==========
+// Types for estimating the cost of requests
+
+pub use pricing::{CostEstimate, LedgerEntry, ModelPrice, PriceTable, UsageLedger};
+
+// ========== Transport ==========
+// Types describing the HTTP connections to the API
+
+pub use json::JsonBackend;
+pub use metrics::MetricsSink;
+pub use transport::{Http3Status, HttpVersion, ResponseMeta};
+pub use user_data::UserData;
+
 // ========== Batch Processing ==========
 // Types for processing multiple requests in batch operations
 
+#[cfg(feature = "batch")]
 pub use batch::{
     builder::BatchBuilder, handle::BatchGenerationResponseItem, handle::BatchHandle,
     handle::BatchHandle as Batch, handle::BatchStatus, handle::Error as BatchHandleError,
@@ -127,14 +358,22 @@ pub use batch::{
 // ========== File Management ==========
 // Types for uploading and managing files
 
+#[cfg(any(feature = "files", feature = "caching"))]
+pub use cleanup::{OrphanSweep, ResourceGuard, ResourceKind};
+#[cfg(feature = "files")]
 pub use files::{
-    builder::FileBuilder, handle::FileHandle, model::File, model::FileState, Error as FilesError,
+    api::Files, builder::FileBuilder, builder::FileSource, handle::FileHandle, model::File,
+    model::FileState, Error as FilesError,
 };
 
 // ========== Content Caching ==========
 // Types for caching contexts and system instructions
 
+#[cfg(all(feature = "caching", feature = "files"))]
+pub use cache::from_file::CacheFromFileBuilder;
+#[cfg(feature = "caching")]
 pub use cache::{
-    builder::CacheBuilder, handle::CachedContentHandle, model::CacheExpirationRequest,
+    api::Caches, builder::CacheBuilder, handle::CachedContentHandle, model::CacheExpirationRequest,
     model::CacheExpirationResponse, model::CachedContent, model::CreateCachedContentRequest,
+    Error as CacheError,
 };
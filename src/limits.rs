@@ -0,0 +1,361 @@
+//! Token limits of models, and a guard failing requests that exceed the context window.
+//!
+//! A prompt larger than the context window of the model is rejected with a `400`, but only
+//! after the whole request was uploaded. With a [`ContextGuard`] set with
+//! [`GeminiBuilder::with_context_guard()`](crate::GeminiBuilder::with_context_guard), the
+//! prompt tokens of every request are counted before it is sent, and a prompt exceeding the
+//! input token limit of the model fails fast with
+//! [`Error::ContextWindowExceeded`](crate::ClientError::ContextWindowExceeded).
+//!
+//! The guard takes the limits of a model from one of two sources:
+//!
+//! - the table of [`DEFAULT_LIMITS`](crate::limits::DEFAULT_LIMITS), as published on
+//!   [`LIMITS_TABLE_VERSION`](crate::limits::LIMITS_TABLE_VERSION), by default;
+//! - with [`ContextGuard::with_live_limits()`], the `models.get` endpoint, see
+//!   [`Gemini::get_model()`](crate::Gemini::get_model). Fetched limits are cached on the client
+//!   for the [TTL](ContextGuard::with_cache_ttl) of the guard;
+//!   [`Gemini::refresh_model_limits()`](crate::Gemini::refresh_model_limits) fetches them again
+//!   right away. If fetching fails, the table is used.
+//!
+//! Requests to models with unknown limits are sent unchecked.
+//!
+//! Prompt tokens are estimated locally with a [`TokenEstimator`] by default, from the text of
+//! the prompt, function calls and responses, and tool declarations; media parts and cached
+//! content are not counted. [`ContextGuard::with_count_tokens()`] asks the `countTokens`
+//! endpoint instead, which is exact but costs a round trip per request.
+//!
+//! The response shares the context window with the prompt: when the prompt plus
+//! `max_output_tokens` exceeds the input token limit, a warning is logged and the request is
+//! sent. Without `max_output_tokens`, the model's output token limit is assumed, which is the
+//! API's default.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{ContextWindowExceededSnafu, Error as ClientError, GeminiClient},
+    generation::dataset::{HeuristicEstimator, TokenEstimator},
+    GenerateContentRequest, Model, Part,
+};
+
+/// The date the limits of [`DEFAULT_LIMITS`] were taken from the published model pages
+pub const LIMITS_TABLE_VERSION: &str = "2025-10-01";
+
+/// Token limits of known models, by model name prefix; the longest prefix wins
+pub const DEFAULT_LIMITS: &[(&str, ModelLimits)] = &[
+    ("gemini-2.0-flash", ModelLimits::new(1_048_576, 8_192)),
+    ("gemini-2.0-flash-lite", ModelLimits::new(1_048_576, 8_192)),
+    (
+        "gemini-2.0-flash-preview-image-generation",
+        ModelLimits::new(32_000, 8_192),
+    ),
+    ("gemini-2.5-flash", ModelLimits::new(1_048_576, 65_536)),
+    ("gemini-2.5-flash-image", ModelLimits::new(32_768, 32_768)),
+    ("gemini-2.5-flash-lite", ModelLimits::new(1_048_576, 65_536)),
+    (
+        "gemini-2.5-flash-preview-tts",
+        ModelLimits::new(8_192, 16_384),
+    ),
+    ("gemini-2.5-pro", ModelLimits::new(1_048_576, 65_536)),
+    (
+        "gemini-2.5-pro-preview-tts",
+        ModelLimits::new(8_192, 16_384),
+    ),
+    ("gemini-embedding-001", ModelLimits::new(2_048, 1)),
+    ("text-embedding-004", ModelLimits::new(2_048, 1)),
+];
+
+/// How long limits fetched from the API are used before being fetched again, unless set with
+/// [`ContextGuard::with_cache_ttl()`]
+pub const DEFAULT_LIMITS_TTL: Duration = Duration::from_secs(3600);
+
+/// Token limits of a model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelLimits {
+    /// Maximum number of prompt tokens, the context window
+    pub input_token_limit: u64,
+    /// Maximum number of response tokens
+    pub output_token_limit: u64,
+}
+
+impl ModelLimits {
+    /// Limits of `input_token_limit` prompt and `output_token_limit` response tokens
+    pub const fn new(input_token_limit: u64, output_token_limit: u64) -> Self {
+        Self {
+            input_token_limit,
+            output_token_limit,
+        }
+    }
+}
+
+/// Returns the limits of `model` from [`DEFAULT_LIMITS`], or `None` for models not in the
+/// table
+pub fn default_model_limits(model: &Model) -> Option<ModelLimits> {
+    let name = model.as_str();
+    let name = name.strip_prefix("models/").unwrap_or(name);
+    DEFAULT_LIMITS
+        .iter()
+        .filter(|(prefix, _)| name.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, limits)| *limits)
+}
+
+/// Metadata of a model, as returned by [`Gemini::get_model()`](crate::Gemini::get_model) and
+/// [`Gemini::list_models()`](crate::Gemini::list_models).
+///
+/// Fields the API adds later are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelInfo {
+    /// The resource name of the model, e.g. `models/gemini-2.5-flash`
+    pub name: String,
+    /// The name of the base model, e.g. `gemini-2.5-flash`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_model_id: Option<String>,
+    /// The version of the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The human-readable name of the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// A short description of the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Maximum number of prompt tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_token_limit: Option<u64>,
+    /// Maximum number of response tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_token_limit: Option<u64>,
+    /// The methods the model supports, e.g. `generateContent`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub supported_generation_methods: Vec<String>,
+    /// Whether the model supports thinking
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<bool>,
+    /// The default temperature of requests not setting one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// The highest temperature a request may set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_temperature: Option<f32>,
+    /// The default nucleus sampling probability
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// The default number of tokens top-k sampling considers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<i32>,
+}
+
+/// A page of models, as returned by the `models.list` endpoint
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListModelsResponse {
+    /// The models of the page
+    #[serde(default)]
+    pub models: Vec<ModelInfo>,
+    /// A token to retrieve the next page of results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_page_token: Option<String>,
+}
+
+impl ModelInfo {
+    /// The token limits of the model, if both are reported
+    pub fn limits(&self) -> Option<ModelLimits> {
+        Some(ModelLimits::new(
+            self.input_token_limit?,
+            self.output_token_limit?,
+        ))
+    }
+}
+
+/// How the guard counts the tokens of a prompt
+#[derive(Clone)]
+enum PromptCount {
+    Estimate(Arc<dyn TokenEstimator>),
+    CountTokens,
+}
+
+/// Checks requests against the context window of their model, see the
+/// [module documentation](self)
+#[derive(Clone)]
+pub struct ContextGuard {
+    live_limits: bool,
+    count: PromptCount,
+    cache_ttl: Duration,
+}
+
+impl fmt::Debug for ContextGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = match self.count {
+            PromptCount::Estimate(_) => "estimate",
+            PromptCount::CountTokens => "countTokens",
+        };
+        f.debug_struct("ContextGuard")
+            .field("live_limits", &self.live_limits)
+            .field("count", &count)
+            .field("cache_ttl", &self.cache_ttl)
+            .finish()
+    }
+}
+
+impl Default for ContextGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContextGuard {
+    /// A guard taking limits from [`DEFAULT_LIMITS`] and estimating prompt tokens with
+    /// [`HeuristicEstimator`]
+    pub fn new() -> Self {
+        Self {
+            live_limits: false,
+            count: PromptCount::Estimate(Arc::new(HeuristicEstimator)),
+            cache_ttl: DEFAULT_LIMITS_TTL,
+        }
+    }
+
+    /// Takes limits from the `models.get` endpoint, cached on the client
+    pub fn with_live_limits(mut self) -> Self {
+        self.live_limits = true;
+        self
+    }
+
+    /// Sets how long fetched limits are used before being fetched again (defaults to
+    /// [`DEFAULT_LIMITS_TTL`])
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Estimates prompt tokens with `estimator`
+    pub fn with_estimator(mut self, estimator: impl TokenEstimator + 'static) -> Self {
+        self.count = PromptCount::Estimate(Arc::new(estimator));
+        self
+    }
+
+    /// Counts prompt tokens with the `countTokens` endpoint
+    pub fn with_count_tokens(mut self) -> Self {
+        self.count = PromptCount::CountTokens;
+        self
+    }
+
+    /// Fails if the prompt of `request` exceeds the context window of the client's model
+    pub(crate) async fn check(
+        &self,
+        client: &GeminiClient,
+        request: &GenerateContentRequest,
+    ) -> Result<(), ClientError> {
+        let Some(limits) = self.limits(client).await else {
+            tracing::debug!(model = %client.model, "no token limits known, request unchecked");
+            return Ok(());
+        };
+        let estimated = match &self.count {
+            PromptCount::Estimate(estimator) => estimate(estimator.as_ref(), request),
+            PromptCount::CountTokens => client.count_tokens(request).await?.total_tokens as u64,
+        };
+        snafu::ensure!(
+            estimated <= limits.input_token_limit,
+            ContextWindowExceededSnafu {
+                estimated,
+                limit: limits.input_token_limit,
+                model: client.model.to_string(),
+            }
+        );
+        let max_output_tokens = request
+            .generation_config
+            .as_ref()
+            .and_then(|config| config.max_output_tokens)
+            .map_or(limits.output_token_limit, |tokens| tokens.max(0) as u64);
+        if estimated + max_output_tokens > limits.input_token_limit {
+            tracing::warn!(
+                model = %client.model,
+                estimated,
+                max_output_tokens,
+                limit = limits.input_token_limit,
+                "prompt and max_output_tokens exceed the context window"
+            );
+        }
+        Ok(())
+    }
+
+    /// The limits of the client's model, from the cache, the API, or the table
+    async fn limits(&self, client: &GeminiClient) -> Option<ModelLimits> {
+        if !self.live_limits {
+            return default_model_limits(&client.model);
+        }
+        let now = client.clock.now();
+        if let Some(limits) = client.model_limits.get(&client.model, now, self.cache_ttl) {
+            return Some(limits);
+        }
+        match client.refresh_model_limits().await {
+            Ok(Some(limits)) => Some(limits),
+            Ok(None) => default_model_limits(&client.model),
+            Err(error) => {
+                tracing::warn!(
+                    model = %client.model,
+                    %error,
+                    "fetching model limits failed, using the limits table"
+                );
+                default_model_limits(&client.model)
+            }
+        }
+    }
+}
+
+/// Estimates the prompt tokens of `request`, leaving out media and cached content
+fn estimate(estimator: &dyn TokenEstimator, request: &GenerateContentRequest) -> u64 {
+    let parts = request
+        .system_instruction
+        .iter()
+        .chain(&request.contents)
+        .flat_map(|content| content.parts.iter().flatten());
+    let mut tokens = 0;
+    for part in parts {
+        tokens += match part {
+            Part::Text { text, .. } => estimator.estimate(text),
+            Part::InlineData { .. } | Part::FileData { .. } => 0,
+            part => estimator.estimate(&serde_json::to_string(part).unwrap_or_default()),
+        };
+    }
+    for tool in request.tools.iter().flatten() {
+        tokens += estimator.estimate(&serde_json::to_string(tool).unwrap_or_default());
+    }
+    tokens as u64
+}
+
+/// Limits fetched from the API, by model name, with the time they were fetched
+#[derive(Debug, Default)]
+pub(crate) struct LimitCache {
+    entries: Mutex<HashMap<String, (ModelLimits, Instant)>>,
+}
+
+impl LimitCache {
+    /// The limits of `model`, if fetched less than `ttl` before `now`
+    fn get(&self, model: &Model, now: Instant, ttl: Duration) -> Option<ModelLimits> {
+        let entries = self.lock();
+        let (limits, fetched) = entries.get(model.as_str())?;
+        (now.saturating_duration_since(*fetched) < ttl).then_some(*limits)
+    }
+
+    pub(crate) fn insert(&self, model: &Model, limits: ModelLimits, now: Instant) {
+        self.lock()
+            .insert(model.as_str().to_string(), (limits, now));
+    }
+
+    pub(crate) fn remove(&self, model: &Model) {
+        self.lock().remove(model.as_str());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, (ModelLimits, Instant)>> {
+        self.entries
+            .lock()
+            .expect("model limit cache lock poisoned")
+    }
+}
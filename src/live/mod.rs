@@ -0,0 +1,65 @@
+//! # Live API Module
+//!
+//! Message types for the bidirectional Live API and helpers to consume its server messages.
+//!
+//! The transport (a WebSocket connection) is left to the caller: send a [`LiveSetup`] as the
+//! first message, then feed the decoded [`LiveServerMessage`]s into [`spoken_turns()`] to get
+//! the audio of each model turn paired with its transcripts.
+
+pub mod model;
+
+use futures::{Stream, StreamExt};
+
+use crate::generation::spoken::SpokenResponse;
+use crate::Part;
+
+pub use model::{
+    AudioTranscriptionConfig, LiveServerContent, LiveServerMessage, LiveSetup, Transcription,
+};
+
+/// Pairs the audio chunks of every model turn with the transcripts received for that turn.
+///
+/// One [`SpokenResponse`] is yielded per completed (or interrupted) turn. Audio and
+/// transcription events arrive independently and in any order; everything received between
+/// two turn boundaries belongs to the same turn. A turn still open when the stream ends is
+/// yielded as well. Errors are passed through unchanged.
+pub fn spoken_turns<S, E>(messages: S) -> impl Stream<Item = Result<SpokenResponse, E>> + Unpin
+where
+    S: Stream<Item = Result<LiveServerMessage, E>>,
+{
+    Box::pin(async_stream::stream! {
+        let mut turn = SpokenResponse::default();
+        futures::pin_mut!(messages);
+        while let Some(message) = messages.next().await {
+            let content = match message {
+                Ok(message) => message.server_content,
+                Err(error) => {
+                    yield Err(error);
+                    continue;
+                }
+            };
+            let Some(content) = content else {
+                continue;
+            };
+            if let Some(model_turn) = content.model_turn {
+                for part in model_turn.parts.into_iter().flatten() {
+                    if let Part::InlineData { inline_data } = part {
+                        turn.audio.push(inline_data);
+                    }
+                }
+            }
+            if let Some(transcription) = content.output_transcription {
+                SpokenResponse::push_transcript(&mut turn.transcript, &transcription.text);
+            }
+            if let Some(transcription) = content.input_transcription {
+                SpokenResponse::push_transcript(&mut turn.input_transcript, &transcription.text);
+            }
+            if content.turn_complete.unwrap_or(false) || content.interrupted.unwrap_or(false) {
+                yield Ok(std::mem::take(&mut turn));
+            }
+        }
+        if turn != SpokenResponse::default() {
+            yield Ok(turn);
+        }
+    })
+}
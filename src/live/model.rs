@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Content, GenerationConfig, Model};
+
+/// Enables transcription of an audio stream; carries no options
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudioTranscriptionConfig {}
+
+/// The setup message sent at the start of a Live session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveSetup {
+    /// The model to use
+    pub model: Model,
+    /// Generation configuration, e.g. response modalities and speech config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation_config: Option<GenerationConfig>,
+    /// System instruction for the session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    /// Requests a transcript of the audio generated by the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_audio_transcription: Option<AudioTranscriptionConfig>,
+    /// Requests a transcript of the audio sent by the user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_audio_transcription: Option<AudioTranscriptionConfig>,
+}
+
+impl LiveSetup {
+    /// Creates a setup message for the given model
+    pub fn new(model: impl Into<Model>) -> Self {
+        Self {
+            model: model.into(),
+            generation_config: None,
+            system_instruction: None,
+            output_audio_transcription: None,
+            input_audio_transcription: None,
+        }
+    }
+
+    /// Sets the generation config
+    pub fn with_generation_config(mut self, config: GenerationConfig) -> Self {
+        self.generation_config = Some(config);
+        self
+    }
+
+    /// Sets the system instruction
+    pub fn with_system_instruction(mut self, text: impl Into<String>) -> Self {
+        self.system_instruction = Some(Content::text(text));
+        self
+    }
+
+    /// Enables or disables transcription of the model's audio output
+    pub fn with_output_audio_transcription(mut self, enabled: bool) -> Self {
+        self.output_audio_transcription = enabled.then(AudioTranscriptionConfig::default);
+        self
+    }
+
+    /// Enables or disables transcription of the user's audio input
+    pub fn with_input_audio_transcription(mut self, enabled: bool) -> Self {
+        self.input_audio_transcription = enabled.then(AudioTranscriptionConfig::default);
+        self
+    }
+
+    /// Wraps the setup in the client message envelope, ready to be sent
+    pub fn to_message(&self) -> serde_json::Value {
+        serde_json::json!({ "setup": self })
+    }
+}
+
+/// A transcript fragment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transcription {
+    /// The transcribed text
+    #[serde(default)]
+    pub text: String,
+}
+
+/// Incremental content produced by the server
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveServerContent {
+    /// Content generated by the model, e.g. audio chunks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_turn: Option<Content>,
+    /// Whether the model has finished its turn
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub turn_complete: Option<bool>,
+    /// Whether the model turn was interrupted by the user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interrupted: Option<bool>,
+    /// Transcript fragment of the user's audio
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_transcription: Option<Transcription>,
+    /// Transcript fragment of the model's audio
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_transcription: Option<Transcription>,
+}
+
+/// A message received from the server during a Live session
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveServerMessage {
+    /// Sent once in response to the setup message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub setup_complete: Option<serde_json::Value>,
+    /// Content generated by the server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_content: Option<LiveServerContent>,
+}
@@ -0,0 +1,28 @@
+//! Metrics the client reports about the requests it sends.
+//!
+//! Set a [`MetricsSink`] with
+//! [`GeminiBuilder::with_metrics_sink()`](crate::GeminiBuilder::with_metrics_sink) to receive
+//! them, e.g. to forward them to Prometheus or OpenTelemetry. Every metric is a histogram
+//! sample named after one of the constants below.
+//!
+//! Body sizes help plan capacity: inline media grows by a third once base64-encoded, so
+//! [`REQUEST_BODY_BYTES`] shows when uploading a file through the Files API would be cheaper.
+//! The client does not ask for compressed responses, so [`RESPONSE_BODY_BYTES`] is also the
+//! size received over the network. The same sizes are recorded on every response, see
+//! [`ResponseMeta`](crate::ResponseMeta).
+
+/// Size in bytes of the serialized body of every request sent, including retries.
+///
+/// Requests without a body, e.g. `GET`s, and with a streamed body, e.g. file uploads, are not
+/// recorded.
+pub const REQUEST_BODY_BYTES: &str = "gemini.request.body_bytes";
+
+/// Size in bytes of every response body read in full, recorded once the body has been
+/// received; a streamed response is recorded when its stream ends.
+pub const RESPONSE_BODY_BYTES: &str = "gemini.response.body_bytes";
+
+/// Receives the metrics of a client, see the [module documentation](self)
+pub trait MetricsSink: Send + Sync {
+    /// Records a sample of the histogram `name`
+    fn record_histogram(&self, name: &'static str, value: f64);
+}
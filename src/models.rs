@@ -6,12 +6,13 @@
 //!
 //! ## Core Types
 //!
-//! - [`Role`] - Represents the speaker in a conversation (User or Model)
 //! - [`Part`] - Content fragments that make up messages (text, images, function calls)
 //! - [`Blob`] - Binary data with MIME type for inline content
 //! - [`Content`] - Container for parts with optional role assignment
 //! - [`Message`] - Complete message with content and explicit role
-//! - [`Modality`] - Output format types (text, image, audio)
+//!
+//! The enumerations these types use, such as `Role`, live in
+//! [`common::types`](crate::common::types).
 //!
 //! ## Usage
 //!
@@ -24,19 +25,10 @@
 //! - `cache` - For content caching
 //! - `files` - For file management
 
-#![allow(clippy::enum_variant_names)]
-
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-/// Role of a message in a conversation
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "lowercase")]
-pub enum Role {
-    /// Message from the user
-    User,
-    /// Message from the model
-    Model,
-}
+use crate::common::types::{Language, Outcome, Role};
 
 /// Content part that can be included in a message
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -58,6 +50,15 @@ pub enum Part {
         #[serde(rename = "inlineData")]
         inline_data: Blob,
     },
+    /// Data stored elsewhere, e.g. a file uploaded with the Files API
+    FileData {
+        /// The file reference
+        #[serde(rename = "fileData")]
+        file_data: FileData,
+        /// The clip and frame rate of a video
+        #[serde(rename = "videoMetadata", skip_serializing_if = "Option::is_none")]
+        video_metadata: Option<VideoMetadata>,
+    },
     /// Function call from the model
     FunctionCall {
         /// The function call details
@@ -73,6 +74,90 @@ pub enum Part {
         #[serde(rename = "functionResponse")]
         function_response: super::tools::FunctionResponse,
     },
+    /// Code generated by the model for the code execution tool
+    ExecutableCode {
+        /// The generated code
+        #[serde(rename = "executableCode")]
+        executable_code: ExecutableCode,
+    },
+    /// Result of running code with the code execution tool
+    CodeExecutionResult {
+        /// The execution result
+        #[serde(rename = "codeExecutionResult")]
+        code_execution_result: CodeExecutionResult,
+    },
+}
+
+/// Code generated by the model to be executed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ExecutableCode {
+    /// Programming language of the code
+    pub language: Language,
+    /// The code to execute
+    pub code: String,
+}
+
+impl ExecutableCode {
+    /// Code in `language`, given as a [`Language`] or by name such as `"PYTHON"`
+    pub fn new(language: impl Into<Language>, code: impl Into<String>) -> Self {
+        Self {
+            language: language.into(),
+            code: code.into(),
+        }
+    }
+}
+
+/// Result of executing [`ExecutableCode`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CodeExecutionResult {
+    /// Outcome of the execution
+    pub outcome: Outcome,
+    /// Output of the execution, if any: the standard output, followed by the error when the
+    /// code failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+/// The line starting the traceback Python prints for an uncaught exception
+const TRACEBACK_MARKER: &str = "Traceback (most recent call last):";
+
+impl CodeExecutionResult {
+    /// Whether the code ran to completion
+    pub fn is_success(&self) -> bool {
+        self.outcome.is_success()
+    }
+
+    /// The standard output of the code, without the [error output](Self::error_output)
+    pub fn stdout(&self) -> &str {
+        let output = self.output.as_deref().unwrap_or_default();
+        &output[..self.error_start().unwrap_or(output.len())]
+    }
+
+    /// The error of failed code, split from its standard output on a best-effort basis; `None`
+    /// unless the outcome is [`Outcome::OutcomeFailed`].
+    ///
+    /// The error is the output from the last line starting with Python's
+    /// `Traceback (most recent call last):`, so that the same text printed earlier by the code,
+    /// or within a line, stays in the [standard output](Self::stdout). Failed output without
+    /// such a line, as for a syntax error, is all error output.
+    pub fn error_output(&self) -> Option<&str> {
+        let output = self.output.as_deref().unwrap_or_default();
+        Some(&output[self.error_start()?..])
+    }
+
+    /// The byte offset of the error output within the output
+    fn error_start(&self) -> Option<usize> {
+        if self.outcome != Outcome::OutcomeFailed {
+            return None;
+        }
+        let output = self.output.as_deref().unwrap_or_default();
+        let traceback = output
+            .match_indices(TRACEBACK_MARKER)
+            .map(|(start, _)| start)
+            .filter(|&start| start == 0 || output[..start].ends_with('\n'))
+            .last();
+        Some(traceback.unwrap_or(0))
+    }
 }
 
 /// Blob for a message part
@@ -93,6 +178,51 @@ impl Blob {
             data: data.into(),
         }
     }
+
+    /// The number of bytes the data decodes to, computed without decoding it
+    pub fn decoded_len(&self) -> usize {
+        decoded_len(&self.data)
+    }
+}
+
+/// Reference to data stored outside the request
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FileData {
+    /// The MIME type of the data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// The URI of the data
+    pub file_uri: String,
+}
+
+impl FileData {
+    /// Create a new file reference with mime type and URI
+    pub fn new(mime_type: impl Into<String>, file_uri: impl Into<String>) -> Self {
+        Self {
+            mime_type: Some(mime_type.into()),
+            file_uri: file_uri.into(),
+        }
+    }
+}
+
+/// The part of a video the model sees, and how densely it is sampled
+///
+/// Offsets are durations in the API's format, a number of seconds with up to nine decimals
+/// followed by `s`, e.g. `"90s"` or `"1.5s"`. See [`Video`](crate::generation::video::Video)
+/// for setting them with validation.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoMetadata {
+    /// Start of the clip
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_offset: Option<String>,
+    /// End of the clip
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_offset: Option<String>,
+    /// Frames sampled per second, 1 by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fps: Option<f64>,
 }
 
 /// Content of a message
@@ -203,11 +333,246 @@ impl Content {
         }
     }
 
+    /// Create a new content referencing a file, e.g. one uploaded with the Files API
+    pub fn file_data(mime_type: impl Into<String>, file_uri: impl Into<String>) -> Self {
+        Self {
+            parts: Some(vec![Part::FileData {
+                file_data: FileData::new(mime_type, file_uri),
+                video_metadata: None,
+            }]),
+            role: None,
+        }
+    }
+
     /// Add a role to this content
     pub fn with_role(mut self, role: Role) -> Self {
         self.role = Some(role);
         self
     }
+
+    /// Removes the thought signatures of all parts, returning how many were removed.
+    ///
+    /// Thought signatures are only valid for the model that produced them; strip them before
+    /// sending a history to a different model.
+    pub fn strip_thought_signatures(&mut self) -> usize {
+        let mut stripped = 0;
+        for part in self.parts.iter_mut().flatten() {
+            let signatures = match part {
+                Part::Text {
+                    thought_signature, ..
+                } => vec![thought_signature],
+                Part::FunctionCall {
+                    function_call,
+                    thought_signature,
+                } => vec![thought_signature, &mut function_call.thought_signature],
+                _ => continue,
+            };
+            stripped += signatures.into_iter().filter_map(Option::take).count();
+        }
+        stripped
+    }
+
+    /// Merges adjacent text parts with the same thought status, returning how many parts were
+    /// merged away.
+    ///
+    /// Nothing is merged across a non-text part, or between a thought and an answer. A part
+    /// is only merged into a preceding part without a thought signature; the merged part
+    /// takes the signature of the last part merged into it, as the API attaches signatures
+    /// to the end of a run of text.
+    pub fn merge_text_parts(&mut self) -> usize {
+        let Some(parts) = &mut self.parts else {
+            return 0;
+        };
+        let mut merged = Vec::with_capacity(parts.len());
+        let mut count = 0;
+        for part in std::mem::take(parts) {
+            count += usize::from(push_merged(&mut merged, part));
+        }
+        *parts = merged;
+        count
+    }
+}
+
+/// Appends `part`, merging it into the last part where
+/// [`Content::merge_text_parts()`] would; returns whether it was merged
+pub(crate) fn push_merged(parts: &mut Vec<Part>, part: Part) -> bool {
+    if let (
+        Some(Part::Text {
+            text,
+            thought,
+            thought_signature: signature @ None,
+        }),
+        Part::Text {
+            text: next,
+            thought: next_thought,
+            thought_signature: next_signature,
+        },
+    ) = (parts.last_mut(), &part)
+    {
+        if thought.unwrap_or(false) == next_thought.unwrap_or(false) {
+            text.push_str(next);
+            *signature = next_signature.clone();
+            return true;
+        }
+    }
+    parts.push(part);
+    false
+}
+
+/// Number of characters of text, code, and arguments shown by the [`Display`](fmt::Display)
+/// implementations of [`Part`] and [`Content`]
+pub const SUMMARY_CHARS: usize = 40;
+
+impl Part {
+    /// Summarizes the part on one line, e.g. `InlineData(image/png, 245KB)`, showing at most
+    /// `max_chars` characters of text, code, and function arguments.
+    ///
+    /// Unlike the [`Debug`](fmt::Debug) output, the summary never contains inline data, so it
+    /// is suited for logs.
+    pub fn summary(&self, max_chars: usize) -> String {
+        match self {
+            Part::Text {
+                text,
+                thought: Some(true),
+                ..
+            } => format!(
+                "Thought({:?}, {})",
+                preview(text, max_chars),
+                byte_size(text.len())
+            ),
+            Part::Text { text, .. } => {
+                format!(
+                    "Text({:?}, {})",
+                    preview(text, max_chars),
+                    byte_size(text.len())
+                )
+            }
+            Part::InlineData { inline_data } => format!(
+                "InlineData({}, {})",
+                inline_data.mime_type,
+                byte_size(decoded_len(&inline_data.data))
+            ),
+            Part::FileData { file_data, .. } => match &file_data.mime_type {
+                Some(mime_type) => format!("FileData({mime_type}, {})", file_data.file_uri),
+                None => format!("FileData({})", file_data.file_uri),
+            },
+            Part::FunctionCall { function_call, .. } => format!(
+                "FunctionCall({}, {})",
+                function_call.name,
+                preview(&function_call.args.to_string(), max_chars)
+            ),
+            Part::FunctionResponse { function_response } => match &function_response.response {
+                Some(response) => format!(
+                    "FunctionResponse({}, {})",
+                    function_response.name,
+                    preview(&response.to_string(), max_chars)
+                ),
+                None => format!("FunctionResponse({})", function_response.name),
+            },
+            Part::ExecutableCode { executable_code } => format!(
+                "ExecutableCode({}, {:?})",
+                executable_code.language,
+                preview(&executable_code.code, max_chars)
+            ),
+            Part::CodeExecutionResult {
+                code_execution_result,
+            } => match &code_execution_result.output {
+                Some(output) => format!(
+                    "CodeExecutionResult({}, {:?})",
+                    code_execution_result.outcome,
+                    preview(output, max_chars)
+                ),
+                None => format!("CodeExecutionResult({})", code_execution_result.outcome),
+            },
+        }
+    }
+
+    /// A one-word description of the part, e.g. `text` or `image`
+    fn kind(&self) -> &'static str {
+        let media = |mime_type: Option<&str>| match mime_type.and_then(|m| m.split('/').next()) {
+            Some("image") => "image",
+            Some("audio") => "audio",
+            Some("video") => "video",
+            _ => "file",
+        };
+        match self {
+            Part::Text {
+                thought: Some(true),
+                ..
+            } => "thought",
+            Part::Text { .. } => "text",
+            Part::InlineData { inline_data } => media(Some(&inline_data.mime_type)),
+            Part::FileData { file_data, .. } => media(file_data.mime_type.as_deref()),
+            Part::FunctionCall { .. } => "function_call",
+            Part::FunctionResponse { .. } => "function_response",
+            Part::ExecutableCode { .. } => "code",
+            Part::CodeExecutionResult { .. } => "code_result",
+        }
+    }
+}
+
+/// Shows the part's [summary](Part::summary) with at most [`SUMMARY_CHARS`] characters of
+/// text
+impl fmt::Display for Part {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.summary(SUMMARY_CHARS))
+    }
+}
+
+impl Content {
+    /// Summarizes the content on one line, e.g. `Content[user]: 3 parts (text, image, text)`
+    pub fn summary(&self) -> String {
+        let role = match &self.role {
+            Some(Role::User) => "[user]",
+            Some(Role::Model) => "[model]",
+            None => "",
+        };
+        let parts = self.parts.as_deref().unwrap_or_default();
+        let kinds: Vec<&str> = parts.iter().map(Part::kind).collect();
+        match kinds.len() {
+            0 => format!("Content{role}: 0 parts"),
+            1 => format!("Content{role}: 1 part ({})", kinds[0]),
+            n => format!("Content{role}: {n} parts ({})", kinds.join(", ")),
+        }
+    }
+}
+
+/// Shows the content's [summary](Content::summary)
+impl fmt::Display for Content {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.summary())
+    }
+}
+
+/// Collapses whitespace and shortens text to `max_chars` characters
+fn preview(text: &str, max_chars: usize) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text,
+    }
+}
+
+/// Formats a byte count, e.g. `512B`, `1.2KB`, or `245KB`
+fn byte_size(bytes: usize) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KB", "MB", "GB"] {
+        if size < 1024.0 || unit == "GB" {
+            return match unit {
+                "B" => format!("{bytes}B"),
+                _ if size < 10.0 => format!("{size:.1}{unit}"),
+                _ => format!("{size:.0}{unit}"),
+            };
+        }
+        size /= 1024.0;
+    }
+    unreachable!("the last unit always matches")
+}
+
+/// The number of bytes encoded by base64 `data`, without decoding it
+fn decoded_len(data: &str) -> usize {
+    let padding = data.bytes().rev().take_while(|&b| b == b'=').count();
+    (data.len() * 3 / 4).saturating_sub(padding)
 }
 
 /// Message in a conversation
@@ -265,19 +630,3 @@ impl Message {
         })
     }
 }
-
-/// Content modality type - specifies the format of model output
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
-pub enum Modality {
-    /// Default value.
-    ModalityUnspecified,
-    /// Indicates the model should return text.
-    Text,
-    /// Indicates the model should return images.
-    Image,
-    /// Indicates the model should return audio.
-    Audio,
-    /// Indicates the model should return video.
-    Video,
-}
@@ -15,16 +15,22 @@
 pub use crate::{ClientError, Gemini, Model};
 
 // Builders for creating requests
-pub use crate::{ContentBuilder, EmbedBuilder};
+pub use crate::ContentBuilder;
+#[cfg(feature = "embeddings")]
+pub use crate::EmbedBuilder;
 
 // Core data types for messages and content
-pub use crate::{Content, Message, Role};
+pub use crate::{Content, Language, Message, Modality, Role};
 
 // Main response types
-pub use crate::{ContentEmbeddingResponse, GenerationResponse};
+#[cfg(feature = "embeddings")]
+pub use crate::ContentEmbeddingResponse;
+pub use crate::GenerationResponse;
 
 // Configuration types
-pub use crate::{GenerationConfig, TaskType};
+pub use crate::GenerationConfig;
+#[cfg(feature = "embeddings")]
+pub use crate::TaskType;
 
 // Safety settings
 pub use crate::{HarmBlockThreshold, HarmCategory, SafetySetting};
@@ -33,4 +39,7 @@ pub use crate::{HarmBlockThreshold, HarmCategory, SafetySetting};
 pub use crate::{FunctionDeclaration, FunctionResponse, Tool};
 
 // Batch and file handles (commonly used for async operations)
-pub use crate::{Batch, FileHandle};
+#[cfg(feature = "batch")]
+pub use crate::Batch;
+#[cfg(feature = "files")]
+pub use crate::FileHandle;
@@ -0,0 +1,362 @@
+//! Dollar estimates of the cost of requests, computed from their token usage.
+//!
+//! A [`PriceTable`] holds the price per million tokens of each model, for prompt, cached
+//! prompt, output, and thinking tokens. The default table holds the list prices in
+//! [`DEFAULT_PRICES`] as of [`PRICE_TABLE_VERSION`]; a table of negotiated prices can be set
+//! with [`GeminiBuilder::with_price_table()`](crate::GeminiBuilder::with_price_table).
+//!
+//! [`CostEstimate::from_usage()`] prices the [`UsageMetadata`] of one response, and a
+//! [`UsageLedger`] sums usage and cost per tag, e.g. per feature or customer:
+//!
+//! ```rust
+//! use gemini_rust::{CostEstimate, UsageMetadata};
+//!
+//! let usage = UsageMetadata {
+//!     prompt_token_count: Some(1_000_000),
+//!     candidates_token_count: Some(100_000),
+//!     ..Default::default()
+//! };
+//! let cost = CostEstimate::from_usage("gemini-2.5-flash", &usage).unwrap();
+//! assert!((cost.total() - 0.55).abs() < 1e-9);
+//! ```
+//!
+//! Estimates ignore long-context surcharges, free tiers, and batch discounts, and prices
+//! change: treat them as estimates, not invoices.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    ops::AddAssign,
+    sync::{Arc, Mutex},
+};
+
+use crate::{GenerationResponse, UsageMetadata};
+
+/// The date the prices of [`DEFAULT_PRICES`] were taken from the published price list
+pub const PRICE_TABLE_VERSION: &str = "2025-10-01";
+
+/// List prices in US dollars per million tokens, for prompts of up to 200k tokens
+pub const DEFAULT_PRICES: &[(&str, ModelPrice)] = &[
+    (
+        "gemini-2.5-pro",
+        ModelPrice {
+            input: 1.25,
+            cached_input: 0.125,
+            output: 10.0,
+            thinking: 10.0,
+        },
+    ),
+    (
+        "gemini-2.5-flash",
+        ModelPrice {
+            input: 0.30,
+            cached_input: 0.03,
+            output: 2.50,
+            thinking: 2.50,
+        },
+    ),
+    (
+        "gemini-2.5-flash-lite",
+        ModelPrice {
+            input: 0.10,
+            cached_input: 0.01,
+            output: 0.40,
+            thinking: 0.40,
+        },
+    ),
+    (
+        "gemini-2.0-flash",
+        ModelPrice {
+            input: 0.10,
+            cached_input: 0.025,
+            output: 0.40,
+            thinking: 0.40,
+        },
+    ),
+    (
+        "gemini-2.0-flash-lite",
+        ModelPrice {
+            input: 0.075,
+            cached_input: 0.075,
+            output: 0.30,
+            thinking: 0.30,
+        },
+    ),
+];
+
+/// Prices of one model in US dollars per million tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    /// Price of prompt tokens, including the results of tool calls
+    pub input: f64,
+    /// Price of prompt tokens served from a context cache
+    pub cached_input: f64,
+    /// Price of response tokens
+    pub output: f64,
+    /// Price of thinking tokens
+    pub thinking: f64,
+}
+
+/// Prices per model, see the [module documentation](self)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceTable {
+    version: String,
+    prices: BTreeMap<String, ModelPrice>,
+}
+
+impl Default for PriceTable {
+    /// The list prices of [`DEFAULT_PRICES`]
+    fn default() -> Self {
+        let mut table = Self::new(PRICE_TABLE_VERSION);
+        for (model, price) in DEFAULT_PRICES {
+            table = table.with_price(*model, *price);
+        }
+        table
+    }
+}
+
+impl PriceTable {
+    /// An empty table, labelled with `version`
+    pub fn new(version: impl Into<String>) -> Self {
+        Self {
+            version: version.into(),
+            prices: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the prices of `model`, replacing earlier prices of the same model
+    pub fn with_price(mut self, model: impl Into<String>, price: ModelPrice) -> Self {
+        let model = model.into();
+        let model = model.strip_prefix("models/").unwrap_or(&model).to_string();
+        self.prices.insert(model, price);
+        self
+    }
+
+    /// The label of the table, e.g. the date its prices were taken
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// The prices of `model`.
+    ///
+    /// `models/` prefixes are ignored, and a versioned name such as
+    /// `gemini-2.5-flash-preview-09-2025` falls back to the longest model name it starts with.
+    pub fn price(&self, model: &str) -> Option<&ModelPrice> {
+        let model = model.strip_prefix("models/").unwrap_or(model);
+        self.prices.get(model).or_else(|| {
+            self.prices
+                .iter()
+                .filter(|(name, _)| model.starts_with(name.as_str()))
+                .max_by_key(|(name, _)| name.len())
+                .map(|(_, price)| price)
+        })
+    }
+
+    /// Prices `usage` of `model`, or `None` if the table has no price for the model
+    pub fn estimate(&self, model: &str, usage: &UsageMetadata) -> Option<CostEstimate> {
+        let price = self.price(model)?;
+        let count = |count: Option<i32>| count.unwrap_or(0).max(0) as u64;
+        let prompt = count(usage.prompt_token_count) + count(usage.tool_use_prompt_token_count);
+        let cached = count(usage.cached_content_token_count).min(prompt);
+        let per_token = |price: f64| price / 1_000_000.0;
+        let mut estimate = CostEstimate {
+            input_tokens: prompt - cached,
+            cached_tokens: cached,
+            output_tokens: count(usage.candidates_token_count),
+            thinking_tokens: count(usage.thoughts_token_count),
+            ..Default::default()
+        };
+        estimate.input_cost = estimate.input_tokens as f64 * per_token(price.input);
+        estimate.cached_cost = estimate.cached_tokens as f64 * per_token(price.cached_input);
+        estimate.output_cost = estimate.output_tokens as f64 * per_token(price.output);
+        estimate.thinking_cost = estimate.thinking_tokens as f64 * per_token(price.thinking);
+        Some(estimate)
+    }
+}
+
+/// The estimated cost of token usage in US dollars, by kind of token.
+///
+/// Token counts missing from the usage count as zero. Cached tokens are part of the prompt
+/// count the API reports, so they are priced at the cached price and left out of
+/// `input_tokens`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CostEstimate {
+    /// Prompt tokens not served from a cache, including the results of tool calls
+    pub input_tokens: u64,
+    /// Prompt tokens served from a context cache
+    pub cached_tokens: u64,
+    /// Response tokens
+    pub output_tokens: u64,
+    /// Thinking tokens
+    pub thinking_tokens: u64,
+    /// Cost of `input_tokens`
+    pub input_cost: f64,
+    /// Cost of `cached_tokens`
+    pub cached_cost: f64,
+    /// Cost of `output_tokens`
+    pub output_cost: f64,
+    /// Cost of `thinking_tokens`
+    pub thinking_cost: f64,
+}
+
+impl CostEstimate {
+    /// Prices `usage` of `model` with the default [`PriceTable`], or `None` if it has no
+    /// price for the model
+    pub fn from_usage(model: &str, usage: &UsageMetadata) -> Option<Self> {
+        PriceTable::default().estimate(model, usage)
+    }
+
+    /// The total cost
+    pub fn total(&self) -> f64 {
+        self.input_cost + self.cached_cost + self.output_cost + self.thinking_cost
+    }
+}
+
+impl AddAssign for CostEstimate {
+    fn add_assign(&mut self, other: Self) {
+        self.input_tokens += other.input_tokens;
+        self.cached_tokens += other.cached_tokens;
+        self.output_tokens += other.output_tokens;
+        self.thinking_tokens += other.thinking_tokens;
+        self.input_cost += other.input_cost;
+        self.cached_cost += other.cached_cost;
+        self.output_cost += other.output_cost;
+        self.thinking_cost += other.thinking_cost;
+    }
+}
+
+impl fmt::Display for CostEstimate {
+    /// E.g. `$0.000550 (input: 1000 tokens $0.000300, cached: 0 tokens $0.000000, ...)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "${:.6} (input: {} tokens ${:.6}, cached: {} tokens ${:.6}, \
+             output: {} tokens ${:.6}, thinking: {} tokens ${:.6})",
+            self.total(),
+            self.input_tokens,
+            self.input_cost,
+            self.cached_tokens,
+            self.cached_cost,
+            self.output_tokens,
+            self.output_cost,
+            self.thinking_tokens,
+            self.thinking_cost,
+        )
+    }
+}
+
+/// Usage and cost of the requests recorded under one tag of a [`UsageLedger`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LedgerEntry {
+    /// Number of requests recorded
+    pub requests: u64,
+    /// Number of those requests whose model has no price; their tokens are in `usage` only
+    pub unpriced_requests: u64,
+    /// The summed token counts
+    pub usage: UsageMetadata,
+    /// The summed cost of the priced requests
+    pub cost: CostEstimate,
+}
+
+/// Sums token usage and cost per tag.
+///
+/// Clones share the same totals, so one ledger can be handed to every task of a pipeline.
+#[derive(Debug, Clone)]
+pub struct UsageLedger {
+    prices: Arc<PriceTable>,
+    entries: Arc<Mutex<BTreeMap<String, LedgerEntry>>>,
+}
+
+impl Default for UsageLedger {
+    fn default() -> Self {
+        Self::new(PriceTable::default())
+    }
+}
+
+impl UsageLedger {
+    /// An empty ledger pricing usage with `prices`
+    pub fn new(prices: PriceTable) -> Self {
+        Self::with_shared_prices(Arc::new(prices))
+    }
+
+    pub(crate) fn with_shared_prices(prices: Arc<PriceTable>) -> Self {
+        Self {
+            prices,
+            entries: Default::default(),
+        }
+    }
+
+    /// Records `usage` of `model` under `tag`, returning its cost if the model has a price
+    pub fn record(
+        &self,
+        tag: impl Into<String>,
+        model: &str,
+        usage: &UsageMetadata,
+    ) -> Option<CostEstimate> {
+        let cost = self.prices.estimate(model, usage);
+        let mut entries = self.entries.lock().expect("usage ledger lock poisoned");
+        let entry = entries.entry(tag.into()).or_default();
+        entry.requests += 1;
+        entry.usage.accumulate(usage);
+        match cost {
+            Some(cost) => entry.cost += cost,
+            None => entry.unpriced_requests += 1,
+        }
+        cost
+    }
+
+    /// Records the usage of `response` under `tag`, priced by the model version the response
+    /// names, or `model` if it names none.
+    ///
+    /// Responses without usage metadata are counted as requests with no tokens.
+    pub fn record_response(
+        &self,
+        tag: impl Into<String>,
+        model: &str,
+        response: &GenerationResponse,
+    ) -> Option<CostEstimate> {
+        let model = response.model_version.as_deref().unwrap_or(model);
+        let usage = response.usage_metadata.clone().unwrap_or_default();
+        self.record(tag, model, &usage)
+    }
+
+    /// The entry of `tag`, if anything was recorded under it
+    pub fn entry(&self, tag: &str) -> Option<LedgerEntry> {
+        let entries = self.entries.lock().expect("usage ledger lock poisoned");
+        entries.get(tag).cloned()
+    }
+
+    /// The entries of all tags
+    pub fn entries(&self) -> BTreeMap<String, LedgerEntry> {
+        self.entries
+            .lock()
+            .expect("usage ledger lock poisoned")
+            .clone()
+    }
+
+    /// The total cost of all tags
+    pub fn total_cost(&self) -> f64 {
+        let entries = self.entries.lock().expect("usage ledger lock poisoned");
+        entries.values().map(|entry| entry.cost.total()).sum()
+    }
+}
+
+impl fmt::Display for UsageLedger {
+    /// One line per tag, followed by the total
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = self.entries();
+        for (tag, entry) in &entries {
+            write!(f, "{tag}: {} requests, {}", entry.requests, entry.cost)?;
+            if entry.unpriced_requests > 0 {
+                write!(f, ", {} unpriced", entry.unpriced_requests)?;
+            }
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "total: ${:.6} (prices of {})",
+            self.total_cost(),
+            self.prices.version()
+        )
+    }
+}
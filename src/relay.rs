@@ -0,0 +1,123 @@
+//! Relaying a generation stream to an HTTP client as server-sent events.
+//!
+//! [`relay()`](crate::relay::relay) turns the stream of
+//! [`ContentBuilder::execute_stream()`](crate::ContentBuilder::execute_stream) into the body of
+//! a `text/event-stream` response. The body is a `Stream` of [`Bytes`](bytes::Bytes), one
+//! item per event, so that it plugs into any framework without depending on one: axum's
+//! `Body::from_stream()`, or hyper through `http_body_util::StreamBody`. Every event is a
+//! separate item, so servers flush it as soon as the API sends the chunk.
+//!
+//! Every event is framed the same way, a name and one line of compact JSON:
+//!
+//! ```text
+//! event: chunk
+//! data: {"text":"Hello","response":{"candidates":[...]}}
+//!
+//! event: done
+//! data: {"chunks":3,"usageMetadata":{"promptTokenCount":4,...}}
+//! ```
+//!
+//! - `chunk` carries the text of the first candidate of a chunk and the chunk itself, as the
+//!   API sent it.
+//! - `done` ends a stream that completed; it carries the number of chunks and the usage
+//!   metadata of the last chunk reporting it.
+//! - `error` ends a stream that failed, with `{"error":{"code":...,"message":...,"status":...}}`
+//!   in the shape of the API's own errors. The code and status are those of the API's error
+//!   when it sent one, and are absent otherwise.
+//!
+//! A stream ends with exactly one `done` or `error` event. Dropping the body, as servers do
+//! when the browser disconnects, drops the upstream stream and so cancels the request to the
+//! API.
+
+use bytes::Bytes;
+use futures::{Stream, TryStream, TryStreamExt};
+use serde::Serialize;
+use std::convert::Infallible;
+
+use crate::{client::Error as ClientError, GenerationResponse, UsageMetadata};
+
+/// Content type of a relayed body
+pub const RELAY_CONTENT_TYPE: &str = "text/event-stream";
+
+/// Converts a generation stream into the body of a server-sent events response, see the
+/// [module documentation](self)
+pub fn relay(
+    stream: impl TryStream<Ok = GenerationResponse, Error = ClientError> + Send + 'static,
+) -> impl Stream<Item = Result<Bytes, Infallible>> + Send + 'static {
+    Box::pin(async_stream::stream! {
+        let stream = stream.into_stream();
+        futures::pin_mut!(stream);
+        let mut chunks = 0usize;
+        let mut usage_metadata = None;
+        while let Some(result) = futures::StreamExt::next(&mut stream).await {
+            match result {
+                Ok(response) => {
+                    chunks += 1;
+                    if response.usage_metadata.is_some() {
+                        usage_metadata = response.usage_metadata.clone();
+                    }
+                    let data = ChunkEvent {
+                        text: response.text(),
+                        response: &response,
+                    };
+                    yield Ok(event("chunk", &data));
+                }
+                Err(error) => {
+                    tracing::warn!(error = %error, chunks, "relayed stream failed");
+                    yield Ok(event("error", &ErrorEvent::from(&error)));
+                    return;
+                }
+            }
+        }
+        yield Ok(event("done", &DoneEvent { chunks, usage_metadata }));
+    })
+}
+
+#[derive(Serialize)]
+struct ChunkEvent<'a> {
+    text: String,
+    response: &'a GenerationResponse,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DoneEvent {
+    chunks: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage_metadata: Option<UsageMetadata>,
+}
+
+#[derive(Serialize)]
+struct ErrorEvent {
+    error: ErrorBody,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<u16>,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+}
+
+impl From<&ClientError> for ErrorEvent {
+    fn from(error: &ClientError) -> Self {
+        let status = error.api_status();
+        ErrorEvent {
+            error: ErrorBody {
+                code: status.as_ref().map(|status| status.code),
+                message: status
+                    .as_ref()
+                    .map_or_else(|| error.to_string(), |status| status.message.clone()),
+                status: status.and_then(|status| status.status),
+            },
+        }
+    }
+}
+
+/// Frames one event; compact JSON holds no newline, so the data fits on one `data:` line
+fn event(name: &str, data: &impl Serialize) -> Bytes {
+    let data = serde_json::to_string(data).expect("relay events serialize to JSON");
+    Bytes::from(format!("event: {name}\ndata: {data}\n\n"))
+}
@@ -0,0 +1,93 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::RetryLayer;
+
+/// One network attempt of a call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttemptRecord {
+    /// The number of the attempt within the call, starting at 1
+    pub attempt_number: u32,
+    /// The layer on whose behalf the attempt was made
+    pub layer: RetryLayer,
+    /// Why the attempt failed, or `None` if it succeeded
+    pub error_summary: Option<String>,
+    /// The backoff delay waited before the attempt
+    pub backoff_applied: Duration,
+    /// Time from sending the request to its outcome, as measured by the client's clock
+    pub duration: Duration,
+}
+
+/// Every network attempt of a call, in the order they were made
+///
+/// Attached to [`GenerationResponse::attempts`](crate::GenerationResponse::attempts) and to
+/// [`ClientError::RetriesExhausted`](crate::ClientError::RetriesExhausted), so failed attempts
+/// stay visible after a retry succeeded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttemptsReport {
+    /// The attempts
+    pub attempts: Vec<AttemptRecord>,
+}
+
+impl AttemptsReport {
+    /// The number of failed attempts
+    pub fn failures(&self) -> usize {
+        self.attempts
+            .iter()
+            .filter(|attempt| attempt.error_summary.is_some())
+            .count()
+    }
+
+    /// Total backoff delay waited between attempts
+    pub fn total_backoff(&self) -> Duration {
+        self.attempts
+            .iter()
+            .map(|attempt| attempt.backoff_applied)
+            .sum()
+    }
+}
+
+impl fmt::Display for AttemptsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} attempt(s), {} failed, {:?} backoff",
+            self.attempts.len(),
+            self.failures(),
+            self.total_backoff()
+        )
+    }
+}
+
+/// Shared record of the attempts of a call
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AttemptsRecorder(Arc<Mutex<AttemptsReport>>);
+
+impl AttemptsRecorder {
+    /// Records an attempt, numbering it after the ones recorded before
+    pub(crate) fn record(
+        &self,
+        layer: RetryLayer,
+        error_summary: Option<String>,
+        backoff_applied: Duration,
+        duration: Duration,
+    ) {
+        let mut report = self.0.lock().expect("attempts lock poisoned");
+        let attempt_number = report.attempts.len() as u32 + 1;
+        report.attempts.push(AttemptRecord {
+            attempt_number,
+            layer,
+            error_summary,
+            backoff_applied,
+            duration,
+        });
+    }
+
+    /// The attempts recorded so far
+    pub(crate) fn report(&self) -> AttemptsReport {
+        self.0.lock().expect("attempts lock poisoned").clone()
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::attempts::{AttemptsRecorder, AttemptsReport};
+use crate::{
+    client::Error,
+    transport::{BodyMeter, ResponseMeta},
+};
+
+/// The layer on whose behalf a network attempt or delay was spent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RetryLayer {
+    /// The first attempt of a top-level call
+    #[default]
+    Initial,
+    /// Retries of transient HTTP failures
+    Http,
+    /// Follow-up requests and re-sent steps of the tool loop
+    ToolLoop,
+    /// Content-level regeneration of rejected responses
+    Regeneration,
+    /// Follow-up requests continuing answers cut off at the output token limit
+    Continuation,
+    /// Requests sent to a fallback model, see [`Policy`](crate::Policy)
+    Fallback,
+}
+
+impl fmt::Display for RetryLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetryLayer::Initial => write!(f, "initial request"),
+            RetryLayer::Http => write!(f, "http retries"),
+            RetryLayer::ToolLoop => write!(f, "tool loop"),
+            RetryLayer::Regeneration => write!(f, "regeneration"),
+            RetryLayer::Continuation => write!(f, "continuation"),
+            RetryLayer::Fallback => write!(f, "model fallback"),
+        }
+    }
+}
+
+/// Limits shared by every retry mechanism of a single top-level call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryBudget {
+    /// Maximum number of network attempts, the first one included
+    pub max_attempts: u32,
+    /// Maximum total backoff delay added by retries
+    pub max_added_delay: Duration,
+}
+
+impl RetryBudget {
+    /// Creates a budget of `max_attempts` network attempts without a delay limit
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            max_added_delay: Duration::MAX,
+        }
+    }
+
+    /// Limits the total backoff delay added by retries
+    pub fn with_max_added_delay(mut self, delay: Duration) -> Self {
+        self.max_added_delay = delay;
+        self
+    }
+}
+
+/// What each layer consumed of a retry budget
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BudgetUsage {
+    /// Network attempts per layer
+    pub attempts: BTreeMap<RetryLayer, u32>,
+    /// Backoff delay per layer
+    pub added_delay: BTreeMap<RetryLayer, Duration>,
+}
+
+impl BudgetUsage {
+    /// Total number of network attempts
+    pub fn total_attempts(&self) -> u32 {
+        self.attempts.values().sum()
+    }
+
+    /// Total backoff delay
+    pub fn total_added_delay(&self) -> Duration {
+        self.added_delay.values().sum()
+    }
+}
+
+impl fmt::Display for BudgetUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (layer, attempts) in &self.attempts {
+            if !first {
+                write!(f, ", ")?;
+            }
+            first = false;
+            write!(f, "{layer}: {attempts} attempt(s)")?;
+            if let Some(delay) = self.added_delay.get(layer) {
+                write!(f, " ({delay:?} delay)")?;
+            }
+        }
+        if first {
+            write!(f, "nothing")?;
+        }
+        Ok(())
+    }
+}
+
+/// Shared accounting of a retry budget
+#[derive(Debug, Clone)]
+pub(crate) struct BudgetTracker {
+    budget: RetryBudget,
+    usage: Arc<Mutex<BudgetUsage>>,
+}
+
+impl BudgetTracker {
+    fn new(budget: RetryBudget) -> Self {
+        Self {
+            budget,
+            usage: Default::default(),
+        }
+    }
+
+    fn exhausted(&self, usage: &BudgetUsage) -> Error {
+        Error::RetryBudgetExhausted {
+            budget: self.budget,
+            usage: usage.clone(),
+        }
+    }
+
+    fn charge_attempt(&self, layer: RetryLayer) -> Result<(), Error> {
+        let mut usage = self.usage.lock().expect("retry budget lock poisoned");
+        if usage.total_attempts() >= self.budget.max_attempts {
+            return Err(self.exhausted(&usage));
+        }
+        *usage.attempts.entry(layer).or_default() += 1;
+        Ok(())
+    }
+
+    fn charge_delay(&self, layer: RetryLayer, delay: Duration) -> Result<(), Error> {
+        let mut usage = self.usage.lock().expect("retry budget lock poisoned");
+        if usage.total_added_delay().saturating_add(delay) > self.budget.max_added_delay {
+            return Err(self.exhausted(&usage));
+        }
+        *usage.added_delay.entry(layer).or_default() += delay;
+        Ok(())
+    }
+}
+
+/// Per-call context threaded through every layer that may send requests
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CallContext {
+    budget: Option<BudgetTracker>,
+    /// Layer charged for the first attempt of the next request
+    layer: RetryLayer,
+    attempts: AttemptsRecorder,
+    /// Transport details of the last response received
+    response: Arc<Mutex<ResponseMeta>>,
+    /// Meter of the body of the last response received, still counting while it streams
+    body: Arc<Mutex<BodyMeter>>,
+    /// Whether requests fail instead of waiting for the client's rate limit
+    no_rate_limit_wait: bool,
+    /// The `traceparent` header sent with requests instead of that of the current span
+    traceparent: Option<Arc<str>>,
+    /// Whether requests are sent once, because repeating them is not safe
+    no_retries: bool,
+}
+
+impl CallContext {
+    /// Creates the context of a new top-level call
+    pub(crate) fn new(budget: Option<RetryBudget>) -> Self {
+        Self {
+            budget: budget.map(BudgetTracker::new),
+            layer: RetryLayer::Initial,
+            attempts: AttemptsRecorder::default(),
+            response: Arc::default(),
+            body: Arc::default(),
+            no_rate_limit_wait: false,
+            traceparent: None,
+            no_retries: false,
+        }
+    }
+
+    /// Creates the context of a call that is not idempotent, e.g. creating a resource, whose
+    /// requests are retried only if `retry` is set: a request failing after the server
+    /// accepted it would otherwise be repeated
+    #[cfg(any(feature = "files", feature = "caching", feature = "batch"))]
+    pub(crate) fn non_idempotent(retry: bool) -> Self {
+        Self {
+            no_retries: !retry,
+            ..Self::default()
+        }
+    }
+
+    /// Whether failed requests may be retried
+    pub(crate) fn retries(&self) -> bool {
+        !self.no_retries
+    }
+
+    /// Returns a context whose requests fail with
+    /// [`Error::RateLimited`](crate::ClientError::RateLimited) instead of waiting for the
+    /// client's rate limit
+    pub(crate) fn without_rate_limit_wait(mut self) -> Self {
+        self.no_rate_limit_wait = true;
+        self
+    }
+
+    /// Whether requests wait for the client's rate limit
+    pub(crate) fn waits_for_rate_limit(&self) -> bool {
+        !self.no_rate_limit_wait
+    }
+
+    /// Returns a context whose requests send `traceparent`, see
+    /// [`trace_context`](crate::trace_context)
+    pub(crate) fn with_traceparent(mut self, traceparent: Option<&str>) -> Self {
+        self.traceparent = traceparent.map(Arc::from);
+        self
+    }
+
+    /// The `traceparent` header set for the requests of the call, if any
+    pub(crate) fn traceparent(&self) -> Option<&str> {
+        self.traceparent.as_deref()
+    }
+
+    /// Returns a context charging the first attempt of its requests to `layer`
+    pub(crate) fn with_layer(&self, layer: RetryLayer) -> Self {
+        Self {
+            budget: self.budget.clone(),
+            layer,
+            attempts: self.attempts.clone(),
+            response: self.response.clone(),
+            body: self.body.clone(),
+            no_rate_limit_wait: self.no_rate_limit_wait,
+            traceparent: self.traceparent.clone(),
+            no_retries: self.no_retries,
+        }
+    }
+
+    /// The layer charged for the first attempt of a request
+    pub(crate) fn layer(&self) -> RetryLayer {
+        self.layer
+    }
+
+    /// Charges one network attempt to `layer`
+    pub(crate) fn charge_attempt(&self, layer: RetryLayer) -> Result<(), Error> {
+        match &self.budget {
+            Some(budget) => budget.charge_attempt(layer),
+            None => Ok(()),
+        }
+    }
+
+    /// Records a network attempt made on behalf of `layer`
+    pub(crate) fn record_attempt(
+        &self,
+        layer: RetryLayer,
+        error_summary: Option<String>,
+        backoff_applied: Duration,
+        duration: Duration,
+    ) {
+        self.attempts
+            .record(layer, error_summary, backoff_applied, duration);
+    }
+
+    /// Every network attempt of the call so far
+    pub(crate) fn attempts(&self) -> AttemptsReport {
+        self.attempts.report()
+    }
+
+    /// Records the transport details of a response received
+    pub(crate) fn record_response(&self, meta: ResponseMeta, body: BodyMeter) {
+        *self.response.lock().expect("response meta lock poisoned") = meta;
+        *self.body.lock().expect("body meter lock poisoned") = body;
+    }
+
+    /// The transport details of the last response received in the call
+    pub(crate) fn response_meta(&self) -> ResponseMeta {
+        self.response
+            .lock()
+            .expect("response meta lock poisoned")
+            .clone()
+    }
+
+    /// The meter of the body of the last response received in the call
+    #[cfg(feature = "streaming")]
+    pub(crate) fn body_meter(&self) -> BodyMeter {
+        self.body.lock().expect("body meter lock poisoned").clone()
+    }
+
+    /// Charges a backoff delay to `layer`
+    pub(crate) fn charge_delay(&self, layer: RetryLayer, delay: Duration) -> Result<(), Error> {
+        match &self.budget {
+            Some(budget) => budget.charge_delay(layer, delay),
+            None => Ok(()),
+        }
+    }
+}
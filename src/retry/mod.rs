@@ -0,0 +1,33 @@
+//! # Retry Module
+//!
+//! Retries happen at several layers: the HTTP layer retries transient failures according to a
+//! [`RetryPolicy`], the tool loop re-sends steps the model answered with a malformed function
+//! call, content-level regeneration re-sends requests whose response was rejected, and
+//! [automatic continuation](crate::ContentBuilder::auto_continue) follows up on truncated
+//! answers. Since these layers nest, their worst-case amplification is multiplicative.
+//!
+//! A [`RetryBudget`] caps the total number of network attempts (and the total backoff delay)
+//! of a top-level call across all layers. It is attached with
+//! [`ContentBuilder::with_retry_budget()`](crate::ContentBuilder::with_retry_budget); when it
+//! runs out, the call fails with [`ClientError::RetryBudgetExhausted`](crate::ClientError),
+//! which reports how much of the budget each layer consumed.
+//!
+//! Every network attempt of a call is recorded in an [`AttemptsReport`], with its layer, its
+//! error if it failed, and the backoff waited before it. Generated responses carry the report
+//! in [`GenerationResponse::attempts`](crate::GenerationResponse::attempts); when the
+//! [`RetryPolicy`] gives up, the last error is wrapped in
+//! [`ClientError::RetriesExhausted`](crate::ClientError::RetriesExhausted) with the report.
+//!
+//! A [`Policy`] composes a budget, model fallbacks, HTTP retries, and a rate limit into the
+//! pipeline of a client, in the order documented in
+//! [`pipeline`](crate::retry::pipeline).
+
+pub mod attempts;
+pub mod budget;
+pub mod pipeline;
+pub mod policy;
+
+pub use attempts::{AttemptRecord, AttemptsReport};
+pub use budget::{BudgetUsage, RetryBudget, RetryLayer};
+pub use pipeline::{Policy, PolicyError, RateLimit};
+pub use policy::RetryPolicy;
@@ -0,0 +1,324 @@
+//! Composition of the layers every request passes through.
+//!
+//! A [`Policy`] assembles a retry budget, model fallbacks, HTTP retries, and a rate limit into
+//! one pipeline, set on a client with
+//! [`GeminiBuilder::with_policy()`](crate::GeminiBuilder::with_policy). The layers nest in a
+//! fixed order, from the call down to the wire:
+//!
+//! 1. **Budget**: the [`RetryBudget`] of the call, charged by every attempt of every layer
+//!    below. Once it runs out, nothing below retries or falls back.
+//! 2. **Fallback**: once the client's model fails with a transient error, even after retries,
+//!    the request is sent to the next fallback model, retried again according to the retry
+//!    policy. Errors other than transient ones are returned as they are.
+//! 3. **Retry**: the [`RetryPolicy`] retries transient failures with backoff.
+//! 4. **Rate limit**: every attempt, retries included, waits for the [`RateLimit`] right
+//!    before it is sent. The wait is not charged to the budget's delay. Attempts wait their
+//!    turn first come, first served, and the limiter is shared by every clone of the client.
+//!    A call can fail with [`ClientError::RateLimited`](crate::ClientError::RateLimited)
+//!    instead of waiting, see
+//!    [`ContentBuilder::without_rate_limit_wait()`](crate::ContentBuilder::without_rate_limit_wait).
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! use gemini_rust::{GeminiBuilder, Model, Policy, RateLimit, RetryBudget, RetryPolicy};
+//!
+//! # fn run() -> Result<(), gemini_rust::ClientError> {
+//! let policy = Policy::new()
+//!     .with_rate_limit(RateLimit::new(60, Duration::from_secs(60)))
+//!     .with_retry(RetryPolicy::new(3))
+//!     .with_fallback(Model::Gemini25FlashLite)
+//!     .with_budget(RetryBudget::new(8));
+//! let client = GeminiBuilder::new("YOUR_API_KEY")
+//!     .with_policy(policy)
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A rate limit can also cap tokens, e.g. for a free-tier quota of 15 requests and 250,000
+//! tokens per minute, set with
+//! [`GeminiBuilder::with_rate_limit()`](crate::GeminiBuilder::with_rate_limit). The tokens a
+//! request uses are only known from the usage metadata of its response, so the limit is kept
+//! approximately: once the responses of the last period used up the tokens, requests wait
+//! until the usage is paid back.
+//!
+//! Combinations that cannot work are rejected when the client is built, with a
+//! [`PolicyError`].
+
+use snafu::Snafu;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{RetryBudget, RetryPolicy};
+use crate::{clock::Clock, Model};
+
+/// A limit of `requests` requests per `per`, allowing bursts of up to `requests` requests,
+/// and optionally of `tokens` tokens per `per`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Requests allowed per period, and the largest burst
+    pub requests: u32,
+    /// The period
+    pub per: Duration,
+    /// Tokens allowed per period, as counted by the usage metadata of the responses
+    pub tokens: Option<u32>,
+}
+
+impl RateLimit {
+    /// Allows `requests` requests per `per`
+    pub fn new(requests: u32, per: Duration) -> Self {
+        Self {
+            requests,
+            per,
+            tokens: None,
+        }
+    }
+
+    /// Allows `requests` requests per minute
+    pub fn per_minute(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(60))
+    }
+
+    /// Also allows only `tokens` tokens per period
+    pub fn with_tokens(mut self, tokens: u32) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+}
+
+/// Error of a [`Policy`] whose layers cannot work together
+#[derive(Debug, Snafu, PartialEq, Eq)]
+pub enum PolicyError {
+    #[snafu(display("rate limit allows no requests"))]
+    EmptyRateLimit,
+
+    #[snafu(display(
+        "a rate limit is set both on the client builder and in the policy, set it in one place"
+    ))]
+    ConflictingRateLimit,
+
+    #[snafu(display("retry policy allows no attempts"))]
+    NoRetryAttempts,
+
+    #[snafu(display("retry budget allows no attempts"))]
+    NoBudgetAttempts,
+
+    #[snafu(display(
+        "a retry policy is set both on the client builder and in the policy, set it in one place"
+    ))]
+    ConflictingRetryPolicy,
+
+    #[snafu(display("fallback model '{model}' is already tried before it"))]
+    RepeatedFallback { model: Model },
+
+    #[snafu(display(
+        "retry budget of {budget} attempt(s) runs out before the last of {fallbacks} fallback model(s) is tried"
+    ))]
+    UnreachableFallback { budget: u32, fallbacks: usize },
+}
+
+/// The layers every request of a client passes through, see the
+/// [module documentation](self)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Policy {
+    pub(crate) rate_limit: Option<RateLimit>,
+    pub(crate) retry: Option<RetryPolicy>,
+    pub(crate) fallback: Vec<Model>,
+    pub(crate) budget: Option<RetryBudget>,
+}
+
+impl Policy {
+    /// Creates a policy without any layer: requests are sent once, to the client's model
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits for `limit` before every attempt
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Retries transient failures according to `policy`, see
+    /// [`GeminiBuilder::with_retry_policy()`](crate::GeminiBuilder::with_retry_policy)
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Falls back to `model` once the models before it failed with a transient error.
+    ///
+    /// Fallback models are tried in the order they are added.
+    pub fn with_fallback(mut self, model: impl Into<Model>) -> Self {
+        self.fallback.push(model.into());
+        self
+    }
+
+    /// Caps the attempts of every call, unless a call sets its own budget with
+    /// [`ContentBuilder::with_retry_budget()`](crate::ContentBuilder::with_retry_budget)
+    pub fn with_budget(mut self, budget: RetryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Checks that the layers can work together for a client of `model`, with the retry
+    /// policy and rate limit set on the client builder, if any
+    pub(crate) fn validate(
+        &self,
+        model: &Model,
+        builder_retry: Option<&RetryPolicy>,
+        builder_rate_limit: Option<&RateLimit>,
+    ) -> Result<(), PolicyError> {
+        if let Some(limit) = self.rate_limit.as_ref().or(builder_rate_limit) {
+            snafu::ensure!(
+                limit.requests > 0 && !limit.per.is_zero() && limit.tokens != Some(0),
+                EmptyRateLimitSnafu
+            );
+        }
+        snafu::ensure!(
+            self.rate_limit.is_none() || builder_rate_limit.is_none(),
+            ConflictingRateLimitSnafu
+        );
+        if let Some(retry) = &self.retry {
+            snafu::ensure!(retry.max_attempts > 0, NoRetryAttemptsSnafu);
+            snafu::ensure!(builder_retry.is_none(), ConflictingRetryPolicySnafu);
+        }
+        for (index, fallback) in self.fallback.iter().enumerate() {
+            snafu::ensure!(
+                fallback != model && !self.fallback[..index].contains(fallback),
+                RepeatedFallbackSnafu {
+                    model: fallback.clone()
+                }
+            );
+        }
+        if let Some(budget) = self.budget {
+            snafu::ensure!(budget.max_attempts > 0, NoBudgetAttemptsSnafu);
+            // Every model takes at least one attempt
+            snafu::ensure!(
+                budget.max_attempts as usize > self.fallback.len(),
+                UnreachableFallbackSnafu {
+                    budget: budget.max_attempts,
+                    fallbacks: self.fallback.len(),
+                }
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Token buckets enforcing a [`RateLimit`], shared by every request of a client
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    /// Held by the request whose turn it is; waiting requests are served in order
+    queue: tokio::sync::Mutex<()>,
+    buckets: Mutex<Option<Buckets>>,
+}
+
+/// The requests and tokens left, and when they were counted
+#[derive(Debug, Clone, Copy)]
+struct Buckets {
+    requests: f64,
+    /// Negative while the tokens used exceed the limit
+    tokens: f64,
+    counted: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            queue: tokio::sync::Mutex::new(()),
+            buckets: Mutex::new(None),
+        }
+    }
+
+    /// Waits until a request may be sent, and takes its slot
+    pub(crate) async fn acquire(&self, clock: &dyn Clock) {
+        let _turn = self.queue.lock().await;
+        while let Err(wait) = self.take(clock.now()) {
+            tracing::debug!(?wait, "waiting for the rate limit");
+            clock.sleep(wait).await;
+        }
+    }
+
+    /// Takes the slot of a request if one is free right away, without waiting; otherwise
+    /// returns how long it will take at least until one is
+    pub(crate) fn try_acquire(&self, clock: &dyn Clock) -> Result<(), Duration> {
+        let Ok(_turn) = self.queue.try_lock() else {
+            // Requests are waiting already, so the next slot is theirs
+            let per_request = self.limit.per.div_f64(f64::from(self.limit.requests));
+            return Err(self.wait(clock.now()).max(per_request));
+        };
+        self.take(clock.now())
+    }
+
+    /// Records the tokens used by a response
+    pub(crate) fn record_tokens(&self, tokens: u32, clock: &dyn Clock) {
+        if self.limit.tokens.is_none() {
+            return;
+        }
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        let mut refilled = self.refill(*buckets, clock.now());
+        refilled.tokens -= f64::from(tokens);
+        *buckets = Some(refilled);
+    }
+
+    /// Takes the slot of a request, or returns how long to wait for one
+    fn take(&self, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        let mut refilled = self.refill(*buckets, now);
+        let wait = self.wait_for(&refilled);
+        if wait.is_zero() {
+            refilled.requests = (refilled.requests - 1.0).max(0.0);
+        }
+        *buckets = Some(refilled);
+        match wait.is_zero() {
+            true => Ok(()),
+            false => Err(wait),
+        }
+    }
+
+    /// How long to wait for a slot, zero if one is free
+    fn wait(&self, now: Instant) -> Duration {
+        let buckets = *self.buckets.lock().expect("rate limiter lock poisoned");
+        self.wait_for(&self.refill(buckets, now))
+    }
+
+    fn wait_for(&self, buckets: &Buckets) -> Duration {
+        let per = self.limit.per.as_secs_f64();
+        let requests = f64::from(self.limit.requests);
+        // Tolerates the rounding of a wait that refilled exactly one request
+        let request_wait = match buckets.requests >= 1.0 - 1e-9 {
+            true => 0.0,
+            false => (1.0 - buckets.requests) * per / requests,
+        };
+        let token_wait = match self.limit.tokens {
+            Some(tokens) if buckets.tokens < -1e-9 => -buckets.tokens * per / f64::from(tokens),
+            _ => 0.0,
+        };
+        Duration::from_secs_f64(request_wait.max(token_wait))
+    }
+
+    /// The buckets refilled for the time elapsed since they were counted
+    fn refill(&self, buckets: Option<Buckets>, now: Instant) -> Buckets {
+        let requests = f64::from(self.limit.requests);
+        let tokens = f64::from(self.limit.tokens.unwrap_or(0));
+        let Some(buckets) = buckets else {
+            return Buckets {
+                requests,
+                tokens,
+                counted: now,
+            };
+        };
+        let periods = now.saturating_duration_since(buckets.counted).as_secs_f64()
+            / self.limit.per.as_secs_f64();
+        Buckets {
+            requests: (buckets.requests + periods * requests).min(requests),
+            tokens: (buckets.tokens + periods * tokens).min(tokens),
+            counted: now,
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    time::Duration,
+};
+use time::{format_description::well_known::Rfc2822, OffsetDateTime};
+
+use crate::client::Error;
+
+/// Retry policy for transient HTTP failures
+///
+/// Requests failing with `429 Too Many Requests`, `500`, `502`, `503`, or `504`, or with a
+/// connection error or timeout, are retried with exponential backoff; other errors, such as
+/// `400`, `401`, `403`, or a blocked prompt, fail right away. Requests whose body cannot be
+/// replayed (streamed uploads) are never retried.
+///
+/// Only idempotent requests are retried: generating content, counting tokens, embedding, and
+/// reading, updating, or deleting resources. Requests creating a resource, i.e. batches,
+/// cached contents, and file uploads, and cancelling batches, are sent once, since a failure
+/// after the server accepted one would create a duplicate; their builders opt in with
+/// `with_retries()`, e.g. [`CacheBuilder::with_retries()`](crate::CacheBuilder::with_retries).
+///
+/// When the API says how long to wait, with a `Retry-After` header or the `retryDelay` of a
+/// `RetryInfo` error detail, the retry waits that long instead of backing off, see
+/// [`ClientError::retry_delay()`](crate::ClientError::retry_delay). If that is longer than
+/// [`max_backoff`](Self::max_backoff), the request fails with
+/// [`ClientError::RetriesExhausted`](crate::ClientError::RetriesExhausted) instead.
+///
+/// Every attempt runs in a `retry_attempt` tracing span recording its number, and every retry
+/// logs its delay and the error that caused it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between two attempts
+    pub max_backoff: Duration,
+    /// Factor applied to the delay after every retry
+    pub multiplier: f64,
+    /// Share of each backoff delay taken off at random, from 0 (none) to 1 (anywhere between
+    /// zero and the full delay), so that clients failing together do not retry together
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a policy allowing `max_attempts` attempts with the default backoff
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the delay before the first retry
+    pub fn with_initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the upper bound for the delay between two attempts
+    pub fn with_max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Sets the factor applied to the delay after every retry; a negative or NaN factor is
+    /// taken as 1, keeping the delay constant
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = if multiplier >= 0.0 { multiplier } else { 1.0 };
+        self
+    }
+
+    /// Sets the share of each backoff delay taken off at random, clamped to 0 to 1; a NaN or
+    /// infinite share disables jitter
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = sanitize_jitter(jitter);
+        self
+    }
+
+    /// Delay before the retry following the failed attempt with the given (zero-based) index,
+    /// before jitter
+    ///
+    /// The delay is capped before it is turned into a [`Duration`], so a large multiplier or
+    /// attempt index yields [`max_backoff`](Self::max_backoff) instead of overflowing.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.min(i32::MAX as u32) as i32);
+        let seconds = self.initial_backoff.as_secs_f64() * factor;
+        if seconds.is_nan() || seconds <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::try_from_secs_f64(seconds)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+
+    /// Delay before retrying `error`, the error of the attempt with the given (zero-based)
+    /// index: the delay the API asked for, if any, or else the jittered backoff. `None` if the
+    /// API asked for more than [`max_backoff`](Self::max_backoff).
+    pub(crate) fn delay(&self, attempt: u32, error: &Error) -> Option<Duration> {
+        if let Some(delay) = error.retry_delay() {
+            return (delay <= self.max_backoff).then_some(delay);
+        }
+        let backoff = self.backoff(attempt);
+        let jitter = sanitize_jitter(self.jitter);
+        if jitter == 0.0 {
+            return Some(backoff);
+        }
+        let random = RandomState::new().hash_one(attempt) as f64 / u64::MAX as f64;
+        Some(backoff.mul_f64(1.0 - jitter * random))
+    }
+
+    /// Whether an error is worth retrying
+    pub(crate) fn is_retryable(error: &Error) -> bool {
+        match error {
+            Error::BadResponse { code, .. } => matches!(code, 429 | 500 | 502 | 503 | 504),
+            Error::Connect { .. } => true,
+            Error::PromptBlocked { .. } => false,
+            Error::WithUserData { source, .. } => Self::is_retryable(source),
+            Error::PerformRequestNew { source } | Error::PerformRequest { source, .. } => {
+                source.is_connect() || source.is_timeout()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Clamps a jitter share to 0 to 1, mapping non-finite values to 0 first since clamping NaN
+/// keeps it NaN
+fn sanitize_jitter(jitter: f64) -> f64 {
+    if jitter.is_finite() {
+        jitter.clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Parses a `Retry-After` header, either a number of seconds or an HTTP date, as a delay from
+/// `now`
+pub(crate) fn parse_retry_after(value: &str, now: OffsetDateTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = OffsetDateTime::parse(value, &Rfc2822).ok()?;
+    Some((date - now).try_into().unwrap_or(Duration::ZERO))
+}
@@ -1,2 +1,5 @@
 pub mod model;
+pub mod sanitize;
+pub mod screen;
+pub mod threshold;
 pub use model::*;
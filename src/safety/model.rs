@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 /// Setting for safety
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SafetySetting {
     /// The category of content to filter
     pub category: HarmCategory,
@@ -10,7 +10,7 @@ pub struct SafetySetting {
 }
 
 /// Category of harmful content
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum HarmCategory {
     /// Category is unspecified.
     #[serde(rename = "HARM_CATEGORY_UNSPECIFIED")]
@@ -49,7 +49,7 @@ pub enum HarmCategory {
 
 /// Threshold for blocking harmful content
 #[allow(clippy::enum_variant_names)]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum HarmBlockThreshold {
     /// Threshold is unspecified.
@@ -67,7 +67,10 @@ pub enum HarmBlockThreshold {
 }
 
 /// Probability that content is harmful
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+///
+/// Probabilities are ordered `HarmProbabilityUnspecified < Negligible < Low < Medium < High`,
+/// see [`rank()`](Self::rank).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum HarmProbability {
     /// Probability is unspecified.
@@ -82,11 +85,70 @@ pub enum HarmProbability {
     High,
 }
 
+impl HarmProbability {
+    /// The position of the probability in the order of probabilities, from 0 for
+    /// [`HarmProbabilityUnspecified`](Self::HarmProbabilityUnspecified) to 4 for
+    /// [`High`](Self::High).
+    ///
+    /// The order is defined here rather than by the order of the variants, so that reordering
+    /// them cannot change how probabilities compare.
+    pub fn rank(self) -> u8 {
+        match self {
+            HarmProbability::HarmProbabilityUnspecified => 0,
+            HarmProbability::Negligible => 1,
+            HarmProbability::Low => 2,
+            HarmProbability::Medium => 3,
+            HarmProbability::High => 4,
+        }
+    }
+}
+
+impl PartialOrd for HarmProbability {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HarmProbability {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+/// Severity of harmful content (Vertex AI only)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum HarmSeverity {
+    /// Severity is unspecified.
+    HarmSeverityUnspecified,
+    /// Negligible level of harm severity.
+    HarmSeverityNegligible,
+    /// Low level of harm severity.
+    HarmSeverityLow,
+    /// Medium level of harm severity.
+    HarmSeverityMedium,
+    /// High level of harm severity.
+    HarmSeverityHigh,
+}
+
 /// Safety rating for content
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct SafetyRating {
     /// The category of the safety rating
     pub category: HarmCategory,
     /// The probability that the content is harmful
     pub probability: HarmProbability,
+    /// The probability as a score between 0 and 1 (Vertex AI only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probability_score: Option<f32>,
+    /// The severity of the harm (Vertex AI only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<HarmSeverity>,
+    /// The severity as a score between 0 and 1 (Vertex AI only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity_score: Option<f32>,
+    /// Whether the content was blocked because of this rating
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocked: Option<bool>,
 }
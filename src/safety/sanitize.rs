@@ -0,0 +1,285 @@
+//! Sanitization of control characters in model output.
+//!
+//! Model output occasionally contains stray control characters, such as `U+0000`, that break
+//! downstream systems, or bidirectional formatting characters, such as `U+202E`, that reverse
+//! the order text is displayed in and so let it pose as something else. A [`SanitizePolicy`]
+//! set with [`GeminiBuilder::sanitize_output()`](crate::GeminiBuilder::sanitize_output)
+//! removes them from the text parts of every response and stream chunk of the client:
+//!
+//! - C0 and C1 controls, and `DEL`, are stripped or replaced, except newlines and tabs. A
+//!   carriage return directly before a newline is kept, so `\r\n` line endings survive.
+//! - Bidirectional formatting characters are stripped if
+//!   [`strip_bidi()`](SanitizePolicy::strip_bidi) is set.
+//!
+//! Text decoded from the API is always valid UTF-8, so the policy works on characters. What
+//! was removed is recorded in [`ResponseMeta::sanitized`](crate::ResponseMeta::sanitized).
+//!
+//! In a stream, a carriage return ending a chunk is held back until the next chunk of its
+//! candidate shows whether a newline follows. A carriage return ending the stream is handled
+//! in one last chunk.
+//!
+//! Output is left exactly as the model produced it unless a policy is set.
+
+#[cfg(feature = "streaming")]
+use futures::TryStream;
+use std::collections::BTreeMap;
+#[cfg(feature = "streaming")]
+use std::collections::BTreeSet;
+
+#[cfg(feature = "streaming")]
+use crate::{client::Error, Candidate, Content, Role};
+use crate::{GenerationResponse, Part};
+
+/// Bidirectional formatting characters: marks, embeddings, overrides, and isolates
+const BIDI_CONTROLS: &[char] = &[
+    '\u{061C}', '\u{200E}', '\u{200F}', '\u{202A}', '\u{202B}', '\u{202C}', '\u{202D}', '\u{202E}',
+    '\u{2066}', '\u{2067}', '\u{2068}', '\u{2069}',
+];
+
+/// What happens to the control characters in model output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlAction {
+    /// Remove them
+    Strip,
+    /// Replace each of them with a character, e.g. `U+FFFD`
+    Replace(char),
+}
+
+/// Which characters are removed from model output, see the [module documentation](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SanitizePolicy {
+    controls: ControlAction,
+    strip_bidi: bool,
+}
+
+impl Default for SanitizePolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SanitizePolicy {
+    /// Strips control characters and keeps bidirectional formatting characters
+    pub fn new() -> Self {
+        Self {
+            controls: ControlAction::Strip,
+            strip_bidi: false,
+        }
+    }
+
+    /// Replaces every control character with `replacement` instead of stripping it
+    pub fn replace_controls(mut self, replacement: char) -> Self {
+        self.controls = ControlAction::Replace(replacement);
+        self
+    }
+
+    /// Whether bidirectional formatting characters are stripped as well
+    pub fn strip_bidi(mut self, strip: bool) -> Self {
+        self.strip_bidi = strip;
+        self
+    }
+
+    /// Sanitizes a complete text
+    pub fn sanitize(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        self.sanitize_into(text, &mut out, &mut BTreeMap::new());
+        out
+    }
+
+    /// Appends the sanitized `text` to `out`, counting what was removed in `removed`
+    fn sanitize_into(&self, text: &str, out: &mut String, removed: &mut BTreeMap<char, usize>) {
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            let keep = match c {
+                '\n' | '\t' => true,
+                '\r' => chars.peek() == Some(&'\n'),
+                c if c.is_control() => false,
+                c => !(self.strip_bidi && BIDI_CONTROLS.contains(&c)),
+            };
+            if keep {
+                out.push(c);
+                continue;
+            }
+            *removed.entry(c).or_default() += 1;
+            if let (ControlAction::Replace(replacement), false) =
+                (self.controls, BIDI_CONTROLS.contains(&c))
+            {
+                out.push(replacement);
+            }
+        }
+    }
+
+    /// Sanitizes the text parts of a complete response
+    pub(crate) fn apply(&self, mut response: GenerationResponse) -> GenerationResponse {
+        let mut removed = BTreeMap::new();
+        for part in response
+            .candidates
+            .iter_mut()
+            .flat_map(|candidate| candidate.content.parts.iter_mut().flatten())
+        {
+            if let Part::Text { text, .. } = part {
+                let mut out = String::with_capacity(text.len());
+                self.sanitize_into(text, &mut out, &mut removed);
+                *text = out;
+            }
+        }
+        response.meta.sanitized = removed;
+        response
+    }
+}
+
+/// Sanitizes a stream chunk by chunk, holding back carriage returns ending a chunk
+#[cfg(feature = "streaming")]
+#[derive(Debug)]
+pub(crate) struct StreamSanitizer {
+    policy: SanitizePolicy,
+    /// Candidates whose text ended with a held back carriage return, keyed by index and
+    /// thought flag
+    pending: BTreeSet<(i32, bool)>,
+}
+
+#[cfg(feature = "streaming")]
+impl StreamSanitizer {
+    pub(crate) fn new(policy: SanitizePolicy) -> Self {
+        Self {
+            policy,
+            pending: BTreeSet::new(),
+        }
+    }
+
+    /// Sanitizes the text parts of one chunk
+    pub(crate) fn process(&mut self, mut chunk: GenerationResponse) -> GenerationResponse {
+        let mut removed = BTreeMap::new();
+        for (position, candidate) in chunk.candidates.iter_mut().enumerate() {
+            let index = candidate.index.unwrap_or(position as i32);
+            let mut parts = Vec::new();
+            for part in candidate.content.parts.take().into_iter().flatten() {
+                match part {
+                    Part::Text {
+                        text,
+                        thought,
+                        thought_signature,
+                    } => {
+                        let key = (index, thought.unwrap_or(false));
+                        let mut out = String::with_capacity(text.len() + 1);
+                        let mut text = text.as_str();
+                        if !text.is_empty() && self.pending.remove(&key) {
+                            if text.starts_with('\n') {
+                                out.push('\r');
+                            } else {
+                                self.policy.sanitize_into("\r", &mut out, &mut removed);
+                            }
+                        }
+                        if let Some(held) = text.strip_suffix('\r') {
+                            text = held;
+                            self.pending.insert(key);
+                        }
+                        self.policy.sanitize_into(text, &mut out, &mut removed);
+                        if !out.is_empty() || thought_signature.is_some() {
+                            parts.push(Part::Text {
+                                text: out,
+                                thought,
+                                thought_signature,
+                            });
+                        }
+                    }
+                    other => {
+                        // A carriage return before a non-text part is followed by no newline
+                        parts.extend(self.flush_candidate(index, &mut removed));
+                        parts.push(other);
+                    }
+                }
+            }
+            if candidate.finish_reason.is_some() {
+                parts.extend(self.flush_candidate(index, &mut removed));
+            }
+            candidate.content.parts = Some(parts);
+        }
+        chunk.meta.sanitized = removed;
+        chunk
+    }
+
+    /// Sanitizes the held back carriage returns of a candidate, as text parts
+    fn flush_candidate(&mut self, index: i32, removed: &mut BTreeMap<char, usize>) -> Vec<Part> {
+        let mut parts = Vec::new();
+        for thought in [true, false] {
+            if !self.pending.remove(&(index, thought)) {
+                continue;
+            }
+            let mut text = String::new();
+            self.policy.sanitize_into("\r", &mut text, removed);
+            if !text.is_empty() {
+                parts.push(Part::Text {
+                    text,
+                    thought: thought.then_some(true),
+                    thought_signature: None,
+                });
+            }
+        }
+        parts
+    }
+
+    /// Sanitizes the carriage returns still held back once the stream has ended, in a last
+    /// chunk recording them
+    pub(crate) fn finish(&mut self) -> Option<GenerationResponse> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let indices: BTreeSet<i32> = self.pending.iter().map(|(index, _)| *index).collect();
+        let mut removed = BTreeMap::new();
+        let candidates = indices
+            .into_iter()
+            .map(|index| Candidate {
+                content: Content {
+                    parts: Some(self.flush_candidate(index, &mut removed)),
+                    role: Some(Role::Model),
+                },
+                safety_ratings: None,
+                citation_metadata: None,
+                grounding_metadata: None,
+                finish_reason: None,
+                index: Some(index),
+                avg_logprobs: None,
+                url_context_metadata: None,
+                finish_message: None,
+            })
+            .collect();
+        let mut last = GenerationResponse {
+            candidates,
+            prompt_feedback: None,
+            usage_metadata: None,
+            model_version: None,
+            response_id: None,
+            continuations: 0,
+            modality_downgraded: false,
+            attempts: Default::default(),
+            meta: Default::default(),
+            tool_transcript: Vec::new(),
+        };
+        last.meta.sanitized = removed;
+        Some(last)
+    }
+}
+
+/// Applies `policy`, if any, to every chunk of a stream
+#[cfg(feature = "streaming")]
+pub(crate) fn sanitize_stream(
+    stream: impl TryStream<Ok = GenerationResponse, Error = Error> + Send,
+    policy: Option<SanitizePolicy>,
+) -> impl TryStream<Ok = GenerationResponse, Error = Error> + Send + Unpin {
+    Box::pin(async_stream::try_stream! {
+        let mut sanitizer = policy.map(StreamSanitizer::new);
+        let stream = futures::TryStreamExt::into_stream(stream);
+        futures::pin_mut!(stream);
+        while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
+            let chunk = chunk?;
+            match sanitizer.as_mut() {
+                Some(sanitizer) => yield sanitizer.process(chunk),
+                None => yield chunk,
+            }
+        }
+        if let Some(last) = sanitizer.as_mut().and_then(StreamSanitizer::finish) {
+            yield last;
+        }
+    })
+}
@@ -0,0 +1,403 @@
+//! Client-side screening of model output.
+//!
+//! An [`OutputScreen`] inspects the text produced by the model before it reaches the caller
+//! and decides to allow it, redact parts of it, or block the response entirely. Screens are
+//! attached to a request with
+//! [`ContentBuilder::with_output_screen()`](crate::ContentBuilder::with_output_screen).
+//!
+//! For streaming responses, text is screened per delta with a small holdback window: the last
+//! [`OutputScreen::window()`] bytes of every candidate are kept back until more text arrives,
+//! so that a pattern split across several chunks is still caught before any of it is released.
+//!
+//! [`Redactor`] is a regex-based reference implementation; [`Redactor::pii()`] redacts email
+//! addresses, phone numbers, and credit card numbers.
+
+use regex::{Captures, Regex};
+#[cfg(feature = "streaming")]
+use std::collections::BTreeMap;
+use std::{
+    fmt,
+    ops::Range,
+    sync::{Arc, LazyLock},
+};
+
+use crate::{client::Error, GenerationResponse, Part};
+
+/// A single replacement applied to screened text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redaction {
+    /// Byte range of the redacted text
+    pub range: Range<usize>,
+    /// Text substituted for the range
+    pub replacement: String,
+}
+
+/// Outcome of screening a piece of model output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScreenDecision {
+    /// The text may be released as is
+    Allow,
+    /// The text may be released after applying the redactions
+    Redact(Vec<Redaction>),
+    /// The response must not be released
+    Block {
+        /// Why the response was blocked
+        reason: String,
+    },
+}
+
+/// Inspects model output before it is released to the caller
+pub trait OutputScreen: Send + Sync {
+    /// Screens a piece of text.
+    ///
+    /// Redaction ranges must lie on character boundaries and must not overlap.
+    fn screen(&self, text: &str) -> ScreenDecision;
+
+    /// Number of trailing bytes held back while streaming.
+    ///
+    /// This should be at least the length of the longest pattern the screen detects, so that
+    /// a match split across chunks is seen in full before any part of it is released.
+    fn window(&self) -> usize {
+        64
+    }
+}
+
+type ReplaceFn = dyn Fn(&Captures) -> String + Send + Sync;
+
+enum Replacement {
+    Fixed(String),
+    Callback(Arc<ReplaceFn>),
+}
+
+struct Rule {
+    pattern: Regex,
+    replacement: Replacement,
+}
+
+/// Regex-based [`OutputScreen`] that redacts every match of its rules
+#[derive(Clone, Default)]
+pub struct Redactor {
+    rules: Vec<Arc<Rule>>,
+    window: Option<usize>,
+}
+
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}")
+        .expect("unreachable error: invalid email pattern")
+});
+
+static CREDIT_CARD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").expect("unreachable error: invalid credit card pattern")
+});
+
+static PHONE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{3}\)|\b\d{3})[ .-]?\d{3}[ .-]?\d{4}\b")
+        .expect("unreachable error: invalid phone pattern")
+});
+
+/// Validates a candidate card number with the Luhn checksum
+fn luhn(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match i % 2 {
+            0 => d,
+            _ if d * 2 > 9 => d * 2 - 9,
+            _ => d * 2,
+        })
+        .sum();
+    digits.len() >= 13 && sum.is_multiple_of(10)
+}
+
+impl Redactor {
+    /// Creates a redactor without rules
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Redacts email addresses, phone numbers, and (Luhn-valid) credit card numbers
+    pub fn pii() -> Self {
+        Self::new()
+            .with_callback(CREDIT_CARD.clone(), |caps| {
+                if luhn(&caps[0]) {
+                    "[REDACTED CARD]".to_string()
+                } else {
+                    caps[0].to_string()
+                }
+            })
+            .with_pattern(EMAIL.clone(), "[REDACTED EMAIL]")
+            .with_pattern(PHONE.clone(), "[REDACTED PHONE]")
+    }
+
+    /// Replaces every match of `pattern` with `replacement`
+    pub fn with_pattern(mut self, pattern: Regex, replacement: impl Into<String>) -> Self {
+        self.rules.push(Arc::new(Rule {
+            pattern,
+            replacement: Replacement::Fixed(replacement.into()),
+        }));
+        self
+    }
+
+    /// Replaces every match of `pattern` with the output of `callback`
+    ///
+    /// Returning the matched text unchanged leaves the match alone.
+    pub fn with_callback<F>(mut self, pattern: Regex, callback: F) -> Self
+    where
+        F: Fn(&Captures) -> String + Send + Sync + 'static,
+    {
+        self.rules.push(Arc::new(Rule {
+            pattern,
+            replacement: Replacement::Callback(Arc::new(callback)),
+        }));
+        self
+    }
+
+    /// Overrides the streaming holdback window (defaults to 64 bytes)
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = Some(window);
+        self
+    }
+}
+
+impl fmt::Debug for Redactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Redactor")
+            .field(
+                "rules",
+                &self
+                    .rules
+                    .iter()
+                    .map(|r| r.pattern.as_str())
+                    .collect::<Vec<_>>(),
+            )
+            .field("window", &self.window())
+            .finish()
+    }
+}
+
+impl OutputScreen for Redactor {
+    fn screen(&self, text: &str) -> ScreenDecision {
+        let mut redactions: Vec<Redaction> = Vec::new();
+        for rule in &self.rules {
+            for caps in rule.pattern.captures_iter(text) {
+                let whole = caps.get(0).expect("capture group 0 always exists");
+                let range = whole.range();
+                // Earlier rules take precedence over overlapping later matches
+                if redactions
+                    .iter()
+                    .any(|r| r.range.start < range.end && range.start < r.range.end)
+                {
+                    continue;
+                }
+                let replacement = match &rule.replacement {
+                    Replacement::Fixed(text) => text.clone(),
+                    Replacement::Callback(callback) => callback(&caps),
+                };
+                if replacement != whole.as_str() {
+                    redactions.push(Redaction { range, replacement });
+                }
+            }
+        }
+        if redactions.is_empty() {
+            ScreenDecision::Allow
+        } else {
+            redactions.sort_by_key(|r| r.range.start);
+            ScreenDecision::Redact(redactions)
+        }
+    }
+
+    fn window(&self) -> usize {
+        self.window.unwrap_or(64)
+    }
+}
+
+/// Applies non-overlapping redactions sorted by start offset
+pub fn apply_redactions(text: &str, redactions: &[Redaction]) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut cursor = 0;
+    for redaction in redactions {
+        output.push_str(&text[cursor..redaction.range.start]);
+        output.push_str(&redaction.replacement);
+        cursor = redaction.range.end;
+    }
+    output.push_str(&text[cursor..]);
+    output
+}
+
+/// Screens `text` completely, returning the text to release
+fn screen_text(screen: &dyn OutputScreen, text: &str) -> Result<String, Error> {
+    match screen.screen(text) {
+        ScreenDecision::Allow => Ok(text.to_string()),
+        ScreenDecision::Redact(redactions) => Ok(apply_redactions(text, &redactions)),
+        ScreenDecision::Block { reason } => Err(Error::OutputBlocked { reason }),
+    }
+}
+
+/// Screens every text part of a complete response
+pub(crate) fn screen_response(
+    screen: &dyn OutputScreen,
+    mut response: GenerationResponse,
+) -> Result<GenerationResponse, Error> {
+    for candidate in &mut response.candidates {
+        for part in candidate.content.parts.iter_mut().flatten() {
+            if let Part::Text { text, .. } = part {
+                *text = screen_text(screen, text)?;
+            }
+        }
+    }
+    Ok(response)
+}
+
+/// Screens a streamed response chunk by chunk, holding back a window of unreleased text
+#[cfg(feature = "streaming")]
+pub(crate) struct StreamScreen {
+    screen: Arc<dyn OutputScreen>,
+    /// Unreleased text keyed by candidate index and thought flag
+    pending: BTreeMap<(i32, bool), String>,
+}
+
+#[cfg(feature = "streaming")]
+impl StreamScreen {
+    pub(crate) fn new(screen: Arc<dyn OutputScreen>) -> Self {
+        Self {
+            screen,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Screens the text accumulated for a candidate and returns the releasable prefix.
+    fn release(&mut self, key: (i32, bool), flush: bool) -> Result<String, Error> {
+        let Some(pending) = self.pending.get_mut(&key) else {
+            return Ok(String::new());
+        };
+        let redactions = match self.screen.screen(pending) {
+            ScreenDecision::Allow => Vec::new(),
+            ScreenDecision::Redact(redactions) => redactions,
+            ScreenDecision::Block { reason } => return Err(Error::OutputBlocked { reason }),
+        };
+
+        let mut cut = if flush {
+            pending.len()
+        } else {
+            pending.len().saturating_sub(self.screen.window())
+        };
+        while !pending.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        // Never split a redaction across the released prefix and the held back tail
+        if let Some(straddling) = redactions
+            .iter()
+            .find(|r| r.range.start < cut && cut < r.range.end)
+        {
+            cut = straddling.range.start;
+        }
+
+        let released: Vec<Redaction> = redactions
+            .into_iter()
+            .filter(|r| r.range.end <= cut)
+            .collect();
+        let text = apply_redactions(&pending[..cut], &released);
+        pending.drain(..cut);
+        Ok(text)
+    }
+
+    /// Screens one chunk, replacing its text parts with the releasable text.
+    pub(crate) fn process(
+        &mut self,
+        mut chunk: GenerationResponse,
+    ) -> Result<GenerationResponse, Error> {
+        for (position, candidate) in chunk.candidates.iter_mut().enumerate() {
+            let index = candidate.index.unwrap_or(position as i32);
+            let mut parts = Vec::new();
+            for part in candidate.content.parts.take().into_iter().flatten() {
+                match part {
+                    Part::Text {
+                        text,
+                        thought,
+                        thought_signature,
+                    } => {
+                        let key = (index, thought.unwrap_or(false));
+                        self.pending.entry(key).or_default().push_str(&text);
+                        let text = self.release(key, false)?;
+                        if !text.is_empty() || thought_signature.is_some() {
+                            parts.push(Part::Text {
+                                text,
+                                thought,
+                                thought_signature,
+                            });
+                        }
+                    }
+                    other => {
+                        // Text preceding a non-text part must be released before it
+                        parts.extend(self.flush_candidate(index)?);
+                        parts.push(other);
+                    }
+                }
+            }
+            if candidate.finish_reason.is_some() {
+                parts.extend(self.flush_candidate(index)?);
+            }
+            candidate.content.parts = Some(parts);
+        }
+        Ok(chunk)
+    }
+
+    /// Releases all held back text of a candidate as text parts
+    fn flush_candidate(&mut self, index: i32) -> Result<Vec<Part>, Error> {
+        let mut parts = Vec::new();
+        for thought in [true, false] {
+            let text = self.release((index, thought), true)?;
+            self.pending.remove(&(index, thought));
+            if !text.is_empty() {
+                parts.push(Part::Text {
+                    text,
+                    thought: thought.then_some(true),
+                    thought_signature: None,
+                });
+            }
+        }
+        Ok(parts)
+    }
+
+    /// Releases any text still held back once the stream has ended
+    pub(crate) fn finish(&mut self) -> Result<Option<GenerationResponse>, Error> {
+        let indices: Vec<i32> = self.pending.keys().map(|(index, _)| *index).collect();
+        let mut candidates = Vec::new();
+        for index in indices {
+            let parts = self.flush_candidate(index)?;
+            if !parts.is_empty() {
+                candidates.push(crate::Candidate {
+                    content: crate::Content {
+                        parts: Some(parts),
+                        role: Some(crate::Role::Model),
+                    },
+                    safety_ratings: None,
+                    citation_metadata: None,
+                    grounding_metadata: None,
+                    finish_reason: None,
+                    index: Some(index),
+                    avg_logprobs: None,
+                    url_context_metadata: None,
+                    finish_message: None,
+                });
+            }
+        }
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(GenerationResponse {
+            candidates,
+            prompt_feedback: None,
+            usage_metadata: None,
+            model_version: None,
+            response_id: None,
+            continuations: 0,
+            modality_downgraded: false,
+            attempts: Default::default(),
+            meta: Default::default(),
+            tool_transcript: Vec::new(),
+        }))
+    }
+}
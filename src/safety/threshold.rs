@@ -0,0 +1,144 @@
+//! Client-side thresholds on the safety ratings of model output.
+//!
+//! The API blocks output according to the request's [safety settings](crate::SafetySetting);
+//! with lenient settings, output rated as possibly harmful is still returned. Policies that
+//! need to act on those ratings anyway can set thresholds on the request:
+//!
+//! - [`ContentBuilder::flag_safety_above()`](crate::ContentBuilder::flag_safety_above) lists
+//!   every rating at or above the threshold in
+//!   [`ResponseMeta::flags`](crate::ResponseMeta::flags) of the response;
+//! - [`ContentBuilder::reject_safety_above()`](crate::ContentBuilder::reject_safety_above)
+//!   fails with [`ClientError::SafetyThresholdExceeded`](crate::ClientError::SafetyThresholdExceeded)
+//!   instead, carrying the offending ratings.
+//!
+//! Ratings are compared by the order of [`HarmProbability`]. When streaming, each chunk is
+//! judged by the aggregate of the ratings so far, the highest probability seen per candidate
+//! and category, since the API rates each chunk on its own.
+
+#[cfg(feature = "streaming")]
+use futures::TryStream;
+
+use crate::{client::Error, GenerationResponse, HarmCategory, HarmProbability, SafetyRating};
+
+/// A safety rating of a candidate at or above a threshold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafetyFlag {
+    /// Index of the rated candidate
+    pub candidate_index: i32,
+    /// The category of the rating
+    pub category: HarmCategory,
+    /// The probability the candidate was rated with
+    pub probability: HarmProbability,
+}
+
+/// The thresholds set on a request
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SafetyThresholds {
+    pub(crate) flag: Option<HarmProbability>,
+    pub(crate) reject: Option<HarmProbability>,
+}
+
+impl SafetyThresholds {
+    fn is_set(&self) -> bool {
+        self.flag.is_some() || self.reject.is_some()
+    }
+
+    /// Flags or rejects a complete response
+    pub(crate) fn apply(
+        &self,
+        mut response: GenerationResponse,
+    ) -> Result<GenerationResponse, Error> {
+        if !self.is_set() {
+            return Ok(response);
+        }
+        let ratings = Aggregate::of(&response);
+        response.meta.flags = self.judge(&ratings)?;
+        Ok(response)
+    }
+
+    /// Returns the ratings at or above the flag threshold, failing if any is at or above the
+    /// reject threshold
+    fn judge(&self, ratings: &Aggregate) -> Result<Vec<SafetyFlag>, Error> {
+        if let Some(threshold) = self.reject {
+            let flags = ratings.at_or_above(threshold);
+            snafu::ensure!(
+                flags.is_empty(),
+                crate::client::SafetyThresholdExceededSnafu { threshold, flags }
+            );
+        }
+        Ok(self
+            .flag
+            .map(|threshold| ratings.at_or_above(threshold))
+            .unwrap_or_default())
+    }
+}
+
+/// The highest probability seen per candidate and category
+#[derive(Default)]
+struct Aggregate {
+    ratings: Vec<SafetyFlag>,
+}
+
+impl Aggregate {
+    fn of(response: &GenerationResponse) -> Self {
+        let mut aggregate = Self::default();
+        aggregate.add(response);
+        aggregate
+    }
+
+    fn add(&mut self, response: &GenerationResponse) {
+        for (position, candidate) in response.candidates.iter().enumerate() {
+            let candidate_index = candidate.index.unwrap_or(position as i32);
+            let ratings = candidate.safety_ratings.iter().flatten();
+            for SafetyRating {
+                category,
+                probability,
+                ..
+            } in ratings
+            {
+                let seen = self.ratings.iter_mut().find(|flag| {
+                    flag.candidate_index == candidate_index && flag.category == *category
+                });
+                match seen {
+                    Some(seen) => seen.probability = seen.probability.max(*probability),
+                    None => self.ratings.push(SafetyFlag {
+                        candidate_index,
+                        category: category.clone(),
+                        probability: *probability,
+                    }),
+                }
+            }
+        }
+    }
+
+    fn at_or_above(&self, threshold: HarmProbability) -> Vec<SafetyFlag> {
+        self.ratings
+            .iter()
+            .filter(|flag| flag.probability >= threshold)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Flags or rejects every chunk of a stream by the aggregate of the ratings so far.
+///
+/// A rejected chunk is not released; the stream fails and ends instead.
+#[cfg(feature = "streaming")]
+pub(crate) fn screen_stream(
+    stream: impl TryStream<Ok = GenerationResponse, Error = Error> + Send,
+    thresholds: SafetyThresholds,
+) -> impl TryStream<Ok = GenerationResponse, Error = Error> + Send + Unpin {
+    Box::pin(async_stream::try_stream! {
+        let mut aggregate = Aggregate::default();
+        let stream = futures::TryStreamExt::into_stream(stream);
+        futures::pin_mut!(stream);
+        while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
+            let mut chunk = chunk?;
+            if thresholds.is_set() {
+                aggregate.add(&chunk);
+                chunk.meta.flags = thresholds.judge(&aggregate)?;
+            }
+            yield chunk;
+        }
+    })
+}
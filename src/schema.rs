@@ -0,0 +1,585 @@
+//! Response and parameter schemas in the OpenAPI subset the Gemini API accepts.
+//!
+//! [`Schema`] models the API's schema object directly. Types implement [`ToSchema`] to
+//! describe themselves, usually with `#[derive(GeminiSchema)]` from the `schema` feature,
+//! which turns doc comments into descriptions:
+//!
+//! ```rust
+//! use gemini_rust::{GeminiSchema, ToSchema};
+//!
+//! /// A city worth visiting
+//! #[derive(GeminiSchema)]
+//! struct City {
+//!     /// Name in the local language
+//!     name: String,
+//!     #[gemini(rename = "populationMillions")]
+//!     population: f32,
+//!     /// Only set for capitals
+//!     country: Option<String>,
+//!     #[gemini(skip)]
+//!     cached_rank: u32,
+//! }
+//!
+//! let schema = City::schema();
+//! assert_eq!(schema.description.as_deref(), Some("A city worth visiting"));
+//! assert_eq!(schema.required, Some(vec!["name".into(), "populationMillions".into()]));
+//! ```
+//!
+//! The derive supports structs with named fields, newtype structs (described by their
+//! field), and enums. Enums of unit variants become string enums; variants with data become
+//! single-property objects, matching serde's default representation. Field attributes:
+//!
+//! - `#[gemini(rename = "name")]` sets the property or variant name
+//! - `#[gemini(skip)]` leaves the field or variant out
+//! - `#[gemini(format = "date-time")]` sets the format of a field, e.g. of a `String` the
+//!   application parses itself
+//!
+//! `Option<T>` fields are nullable and not required. With the `chrono` and `uuid` features,
+//! chrono's date and time types and `uuid::Uuid` describe themselves as strings of the
+//! matching format, such as `date-time` or `uuid`.
+//!
+//! With the same feature, types implementing schemars' `JsonSchema` instead can be described
+//! with [`Schema::of()`], which translates the JSON Schema schemars generates and rejects
+//! constructs the API does not accept with a [`SchemaError`], e.g. for
+//! [`ContentBuilder::with_response_schema_of()`](crate::ContentBuilder::with_response_schema_of).
+//!
+//! Unsupported shapes fail to compile:
+//!
+//! ```compile_fail
+//! #[derive(gemini_rust::GeminiSchema)]
+//! struct Pair(String, u32);
+//! ```
+//!
+//! ```compile_fail
+//! #[derive(gemini_rust::GeminiSchema)]
+//! struct Tagged {
+//!     #[gemini(flatten)]
+//!     inner: String,
+//! }
+//! ```
+//!
+//! ```compile_fail
+//! struct NotDescribed;
+//!
+//! #[derive(gemini_rust::GeminiSchema)]
+//! struct Outer {
+//!     inner: NotDescribed,
+//! }
+//! ```
+
+#[cfg(feature = "schema")]
+use schemars::{generate::SchemaSettings, JsonSchema, SchemaGenerator};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use snafu::Snafu;
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+
+/// A JSON Schema that cannot be expressed as a [`Schema`]
+#[derive(Debug, Snafu)]
+#[snafu(display("unsupported schema at {path}: {reason}"))]
+pub struct SchemaError {
+    /// Path of the unsupported value within the described JSON, e.g. `$.tags[]`
+    pub path: String,
+    /// What is unsupported
+    pub reason: String,
+}
+
+/// Data type of a [`Schema`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SchemaType {
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Array,
+    Object,
+}
+
+/// A schema describing a JSON value, see <https://ai.google.dev/api/caching#Schema>
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Schema {
+    /// The data type
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub schema_type: Option<SchemaType>,
+    /// Format of the data, e.g. `int64` or `date-time`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// What the value means
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Whether the value may be null
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nullable: Option<bool>,
+    /// Allowed values of a string
+    #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+    pub enum_values: Option<Vec<String>>,
+    /// Properties of an object
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<BTreeMap<String, Schema>>,
+    /// Order in which the model generates the properties of an object
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub property_ordering: Option<Vec<String>>,
+    /// Properties of an object that must be present
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required: Option<Vec<String>>,
+    /// Schema of the elements of an array
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<Box<Schema>>,
+    /// The value matches at least one of these schemas
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub any_of: Option<Vec<Schema>>,
+}
+
+impl Schema {
+    /// A schema of the given type
+    pub fn new(schema_type: SchemaType) -> Self {
+        Self {
+            schema_type: Some(schema_type),
+            ..Default::default()
+        }
+    }
+
+    /// A string restricted to `values`
+    pub fn enumeration(values: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            enum_values: Some(values.into_iter().map(Into::into).collect()),
+            ..Self::new(SchemaType::String)
+        }
+    }
+
+    /// An array of `items`
+    pub fn array(items: Schema) -> Self {
+        Self {
+            items: Some(Box::new(items)),
+            ..Self::new(SchemaType::Array)
+        }
+    }
+
+    /// An object with `properties`, all required except the optional ones, in the given order
+    pub fn object(properties: impl IntoIterator<Item = (String, Schema, bool)>) -> Self {
+        let mut schema = Self::new(SchemaType::Object);
+        let (mut map, mut ordering, mut required) = (BTreeMap::new(), Vec::new(), Vec::new());
+        for (name, property, optional) in properties {
+            if !optional {
+                required.push(name.clone());
+            }
+            ordering.push(name.clone());
+            map.insert(name, property);
+        }
+        schema.properties = Some(map);
+        schema.property_ordering = (!ordering.is_empty()).then_some(ordering);
+        schema.required = (!required.is_empty()).then_some(required);
+        schema
+    }
+
+    /// Sets the format
+    pub fn with_format(mut self, format: impl Into<String>) -> Self {
+        self.format = Some(format.into());
+        self
+    }
+
+    /// Sets the description
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// The schema of `T`'s JSON representation, translated from the JSON Schema schemars
+    /// generates for it, see [`from_json_schema()`](Self::from_json_schema).
+    #[cfg(feature = "schema")]
+    pub fn of<T: JsonSchema>() -> Result<Self, SchemaError> {
+        Self::from_json_schema(&json_schema_of::<T>())
+    }
+
+    /// Translates a JSON Schema, in the OpenAPI 3 dialect schemars generates, into the subset
+    /// the API accepts.
+    ///
+    /// Enums of strings, including the `oneOf` of documented unit variants, become string
+    /// enums; `null` alternatives make the schema nullable; other `oneOf` and `anyOf`
+    /// alternatives become `anyOf`. Titles, defaults, examples, and validation keywords such as
+    /// `minimum` or `pattern` are dropped. Integer formats become `int32` or `int64`.
+    ///
+    /// Constructs the API rejects fail with a [`SchemaError`] naming where they occur:
+    /// references (recursive types), maps with arbitrary keys, tuples, objects without
+    /// properties, arbitrary JSON values, enums of non-strings, and keywords such as `allOf`
+    /// or `not`.
+    pub fn from_json_schema(schema: &Value) -> Result<Self, SchemaError> {
+        translate(schema, "$")
+    }
+}
+
+/// The JSON Schema of `T`, with subschemas inlined and no meta-schema or root title
+#[cfg(feature = "schema")]
+pub(crate) fn json_schema_of<T: JsonSchema>() -> Value {
+    // Gemini-optimized settings, see: https://ai.google.dev/api/caching#Schema
+    let schema_generator = SchemaGenerator::new(SchemaSettings::openapi3().with(|s| {
+        s.inline_subschemas = true;
+        s.meta_schema = None;
+    }));
+
+    let mut schema = schema_generator.into_root_schema_for::<T>();
+
+    // Root schemas always include a title field, which we don't want or need
+    schema.remove("title");
+    schema.to_value()
+}
+
+/// Keywords without an equivalent in [`Schema`] that do not change which values are valid,
+/// or only narrow them
+const DROPPED_KEYWORDS: &[&str] = &[
+    "$schema",
+    "$comment",
+    "title",
+    "default",
+    "examples",
+    "example",
+    "deprecated",
+    "readOnly",
+    "writeOnly",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "multipleOf",
+    "minLength",
+    "maxLength",
+    "pattern",
+    "minItems",
+    "maxItems",
+    "uniqueItems",
+    "minProperties",
+    "maxProperties",
+    "contentEncoding",
+    "contentMediaType",
+];
+
+fn unsupported<T>(path: &str, reason: impl Into<String>) -> Result<T, SchemaError> {
+    SchemaSnafu { path, reason }.fail()
+}
+
+/// Translates the JSON Schema `schema` of the value at `path`
+fn translate(schema: &Value, path: &str) -> Result<Schema, SchemaError> {
+    let object = match schema {
+        Value::Object(object) if !object.is_empty() => object,
+        Value::Object(_) | Value::Bool(true) => {
+            return unsupported(path, "arbitrary JSON values cannot be described");
+        }
+        _ => return unsupported(path, format!("`{schema}` is not a schema")),
+    };
+    for keyword in object.keys() {
+        let known = matches!(
+            keyword.as_str(),
+            "type"
+                | "format"
+                | "description"
+                | "nullable"
+                | "enum"
+                | "const"
+                | "properties"
+                | "required"
+                | "additionalProperties"
+                | "items"
+                | "anyOf"
+                | "oneOf"
+        );
+        if !known && !DROPPED_KEYWORDS.contains(&keyword.as_str()) {
+            return unsupported(path, unsupported_keyword(keyword));
+        }
+    }
+
+    let alternatives = object.get("anyOf").or_else(|| object.get("oneOf"));
+    let mut result = match alternatives {
+        Some(alternatives) => translate_alternatives(alternatives, path)?,
+        None => translate_single(object, path)?,
+    };
+    if let Some(Value::String(description)) = object.get("description") {
+        result.description = Some(description.clone());
+    }
+    if object.get("nullable") == Some(&Value::Bool(true)) {
+        result.nullable = Some(true);
+    }
+    Ok(result)
+}
+
+fn unsupported_keyword(keyword: &str) -> String {
+    match keyword {
+        "$ref" => "references, e.g. of recursive types, are not supported".into(),
+        "allOf" => "`allOf` is not supported".into(),
+        _ => format!("keyword `{keyword}` is not supported"),
+    }
+}
+
+/// Translates a schema without `anyOf` or `oneOf`
+fn translate_single(object: &Map<String, Value>, path: &str) -> Result<Schema, SchemaError> {
+    let (schema_type, mut nullable) = schema_type(object, path)?;
+    let values = match (object.get("const"), object.get("enum")) {
+        (Some(value), _) => Some(std::slice::from_ref(value)),
+        (None, Some(Value::Array(values))) => Some(values.as_slice()),
+        (None, Some(_)) => return unsupported(path, "`enum` must be an array"),
+        (None, None) => None,
+    };
+    let mut enum_values = None;
+    if let Some(values) = values {
+        let mut strings = Vec::new();
+        for value in values {
+            match value {
+                Value::String(value) => strings.push(value.clone()),
+                Value::Null => nullable = true,
+                _ => return unsupported(path, format!("enum value `{value}` is not a string")),
+            }
+        }
+        if strings.is_empty() {
+            return unsupported(path, "a value that is always null cannot be described");
+        }
+        enum_values = Some(strings);
+    }
+    let schema_type = match (schema_type, &enum_values) {
+        (Some(schema_type), _) => schema_type,
+        (None, Some(_)) => SchemaType::String,
+        (None, None) => return unsupported(path, "a schema needs a type"),
+    };
+    if enum_values.is_some() && schema_type != SchemaType::String {
+        return unsupported(path, "enums must be enums of strings");
+    }
+
+    let mut schema = Schema::new(schema_type);
+    schema.enum_values = enum_values;
+    schema.nullable = nullable.then_some(true);
+    schema.format = match (schema_type, object.get("format").and_then(Value::as_str)) {
+        (SchemaType::Integer, Some("int8" | "int16" | "int32" | "uint8" | "uint16")) => {
+            Some("int32".into())
+        }
+        (SchemaType::Integer, Some(_)) => Some("int64".into()),
+        (SchemaType::Number, Some(format @ ("float" | "double"))) => Some(format.into()),
+        (SchemaType::String, Some(format)) => Some(format.into()),
+        _ => None,
+    };
+
+    match schema_type {
+        SchemaType::Array => {
+            let items = match object.get("items") {
+                Some(Value::Array(_)) => return unsupported(path, "tuples are not supported"),
+                Some(items) => items,
+                None => return unsupported(path, "arrays need a schema of their items"),
+            };
+            schema.items = Some(Box::new(translate(items, &format!("{path}[]"))?));
+        }
+        SchemaType::Object => {
+            if let Some(additional) = object.get("additionalProperties") {
+                if additional != &Value::Bool(false) {
+                    return unsupported(path, "maps with arbitrary keys are not supported");
+                }
+            }
+            let properties = match object.get("properties").and_then(Value::as_object) {
+                Some(properties) if !properties.is_empty() => properties,
+                _ => return unsupported(path, "objects need at least one property"),
+            };
+            let mut translated = BTreeMap::new();
+            for (name, property) in properties {
+                let property = translate(property, &format!("{path}.{name}"))?;
+                translated.insert(name.clone(), property);
+            }
+            schema.properties = Some(translated);
+            schema.required = object
+                .get("required")
+                .and_then(Value::as_array)
+                .map(|required| {
+                    required
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(String::from)
+                        .collect::<Vec<_>>()
+                })
+                .filter(|required| !required.is_empty());
+        }
+        _ => {}
+    }
+    Ok(schema)
+}
+
+/// The type of a schema without alternatives and whether a `null` type makes it nullable
+fn schema_type(
+    object: &Map<String, Value>,
+    path: &str,
+) -> Result<(Option<SchemaType>, bool), SchemaError> {
+    let names: Vec<&str> = match object.get("type") {
+        None => return Ok((None, false)),
+        Some(Value::String(name)) => vec![name],
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        Some(other) => return unsupported(path, format!("`{other}` is not a type")),
+    };
+    let nullable = names.contains(&"null");
+    let types: Vec<&str> = names.into_iter().filter(|name| *name != "null").collect();
+    let name = match types.as_slice() {
+        [name] => *name,
+        [] if nullable => return Ok((None, true)),
+        _ => return unsupported(path, format!("a value of several types {types:?}")),
+    };
+    let schema_type = match name {
+        "string" => SchemaType::String,
+        "number" => SchemaType::Number,
+        "integer" => SchemaType::Integer,
+        "boolean" => SchemaType::Boolean,
+        "array" => SchemaType::Array,
+        "object" => SchemaType::Object,
+        _ => return unsupported(path, format!("unknown type `{name}`")),
+    };
+    Ok((Some(schema_type), nullable))
+}
+
+/// Translates `anyOf` or `oneOf` alternatives.
+///
+/// `null` alternatives make the result nullable, alternatives that are all string enums
+/// merge into one enum, and a single remaining alternative stands for itself.
+fn translate_alternatives(alternatives: &Value, path: &str) -> Result<Schema, SchemaError> {
+    let Some(alternatives) = alternatives.as_array() else {
+        return unsupported(path, "alternatives must be an array of schemas");
+    };
+    let mut nullable = false;
+    let mut translated = Vec::new();
+    for alternative in alternatives {
+        let is_null = alternative.get("type") == Some(&Value::String("null".into()))
+            || alternative.get("enum") == Some(&Value::Array(vec![Value::Null]))
+            || alternative.get("const") == Some(&Value::Null);
+        if is_null {
+            nullable = true;
+        } else {
+            translated.push(translate(alternative, path)?);
+        }
+    }
+    let all_enums = translated.iter().all(|alternative| {
+        alternative.enum_values.is_some() && alternative.schema_type == Some(SchemaType::String)
+    });
+    let mut schema = match translated.len() {
+        0 => return unsupported(path, "a value that is always null cannot be described"),
+        1 => translated.pop().expect("one alternative"),
+        _ if all_enums => {
+            nullable |= translated.iter().any(|a| a.nullable == Some(true));
+            Schema::enumeration(translated.into_iter().flat_map(|a| a.enum_values.unwrap()))
+        }
+        _ => Schema {
+            any_of: Some(translated),
+            ..Default::default()
+        },
+    };
+    if nullable {
+        schema.nullable = Some(true);
+    }
+    Ok(schema)
+}
+
+impl From<Schema> for Value {
+    fn from(schema: Schema) -> Self {
+        serde_json::to_value(schema).expect("schemas serialize to JSON")
+    }
+}
+
+/// A type that describes its JSON representation with a [`Schema`].
+///
+/// Derive it with `#[derive(GeminiSchema)]`, see the [module documentation](self).
+pub trait ToSchema {
+    /// The schema of the type's JSON representation
+    fn schema() -> Schema;
+
+    /// Whether a field of this type may be left out; `true` only for `Option`
+    fn optional() -> bool {
+        false
+    }
+}
+
+macro_rules! primitive_schema {
+    ($schema:expr => $($ty:ty),+) => {
+        $(
+            impl ToSchema for $ty {
+                fn schema() -> Schema {
+                    $schema
+                }
+            }
+        )+
+    };
+}
+
+primitive_schema!(Schema::new(SchemaType::Boolean) => bool);
+primitive_schema!(Schema::new(SchemaType::String) => String, str, char);
+primitive_schema!(
+    Schema::new(SchemaType::Integer).with_format("int32") => i8, i16, i32, u8, u16
+);
+primitive_schema!(
+    Schema::new(SchemaType::Integer).with_format("int64") => i64, u32, u64, isize, usize
+);
+primitive_schema!(Schema::new(SchemaType::Number).with_format("float") => f32);
+primitive_schema!(Schema::new(SchemaType::Number).with_format("double") => f64);
+
+impl<T: ToSchema + ?Sized> ToSchema for &T {
+    fn schema() -> Schema {
+        T::schema()
+    }
+
+    fn optional() -> bool {
+        T::optional()
+    }
+}
+
+impl<T: ToSchema + ?Sized> ToSchema for Box<T> {
+    fn schema() -> Schema {
+        T::schema()
+    }
+
+    fn optional() -> bool {
+        T::optional()
+    }
+}
+
+impl<T: ToSchema> ToSchema for Option<T> {
+    fn schema() -> Schema {
+        Schema {
+            nullable: Some(true),
+            ..T::schema()
+        }
+    }
+
+    fn optional() -> bool {
+        true
+    }
+}
+
+macro_rules! array_schema {
+    ($($ty:ty),+) => {
+        $(
+            impl<T: ToSchema> ToSchema for $ty {
+                fn schema() -> Schema {
+                    Schema::array(T::schema())
+                }
+            }
+        )+
+    };
+}
+
+array_schema!([T], Vec<T>, VecDeque<T>, BTreeSet<T>, HashSet<T>);
+
+impl<T: ToSchema, const N: usize> ToSchema for [T; N] {
+    fn schema() -> Schema {
+        Schema::array(T::schema())
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> ToSchema for chrono::DateTime<Tz> {
+    fn schema() -> Schema {
+        Schema::new(SchemaType::String).with_format("date-time")
+    }
+}
+
+#[cfg(feature = "chrono")]
+primitive_schema!(Schema::new(SchemaType::String).with_format("date") => chrono::NaiveDate);
+#[cfg(feature = "chrono")]
+primitive_schema!(Schema::new(SchemaType::String).with_format("partial-time") => chrono::NaiveTime);
+#[cfg(feature = "chrono")]
+primitive_schema!(
+    Schema::new(SchemaType::String).with_format("partial-date-time") => chrono::NaiveDateTime
+);
+
+#[cfg(feature = "uuid")]
+primitive_schema!(Schema::new(SchemaType::String).with_format("uuid") => uuid::Uuid);
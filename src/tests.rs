@@ -1,6 +1,27 @@
-use crate::{FinishReason, FunctionCall, GenerationResponse, Model, Part};
+mod mock;
+
+use crate::{
+    auth::TokenProvider,
+    clock::{Clock, ManualClock},
+    embedding::chunker::estimate_tokens,
+    generation::attribution::{attribution_schema, find_quote},
+    generation::stop::client_stop,
+    live::{spoken_turns, LiveServerMessage, LiveSetup},
+    text_utils::{ascii_quotes, normalize_whitespace, strip_markdown},
+    toon, Auth, CacheError, ChunkingConfig, ChunkingError, CitationStatus, ClientError, Content,
+    Document, ExecutableCode, FileState, FinishReason, FunctionCall, FunctionDeclaration, Gemini,
+    GeminiBuilder, GenerateContentRequest, GenerationConfig, GenerationResponse, HarmSeverity,
+    Language, LintKind, LintRule, LintSeverity, LintWarning, Modality, Model, Outcome, Part,
+    RaceOutcome, RetryBudget, RetryLayer, RetryPolicy, Role, SeedStrategy, SessionTree,
+    StopPattern, StreamEvent, TextChunker, Tool, ToolRegistry,
+};
+use mock::{MockResponse, MockServer};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
 
 #[test]
 fn test_model_deserialization() {
@@ -339,3 +360,9378 @@ fn test_content_creation_with_thought_signature() {
     assert!(serialized_thought.contains("thought_signature_456"));
     assert!(serialized_thought.contains("\"thought\":true"));
 }
+
+#[test]
+fn test_field_violation_enriched_with_part_provenance() {
+    use crate::{client::Error, ContentBuilder, Gemini};
+
+    let builder = Gemini::new("test-key")
+        .unwrap()
+        .generate_content()
+        .with_system_instruction("You are a contract reviewer")
+        .with_user_message("Here is the contract:")
+        .with_inline_data("bm90IGEgcGRm", "application/pdf")
+        .with_part_label("contract.pdf")
+        .with_model_message("Understood.")
+        .with_function_response("lookup_clause", json!({"clause": 7}))
+        .unwrap();
+
+    let outline = builder.debug_outline();
+    assert_eq!(
+        outline,
+        "system_instruction: 1 part(s)\n\
+         contents[0] (user)\n  parts[0] Text(\"Here is the contract:\", 21B) <- with_user_message\n\
+         contents[1] (user)\n  parts[0] InlineData(application/pdf, 9B) <- with_inline_data [contract.pdf]\n\
+         contents[2] (model)\n  parts[0] Text(\"Understood.\", 11B) <- with_model_message\n\
+         contents[3] (user)\n  parts[0] FunctionResponse(lookup_clause, {\"clause\":7}) <- with_function_response\n"
+    );
+
+    let body = json!({
+        "error": {
+            "code": 400,
+            "message": "Invalid value at 'contents[1].parts[0].inline_data.data'",
+            "status": "INVALID_ARGUMENT",
+            "details": [{
+                "@type": "type.googleapis.com/google.rpc.BadRequest",
+                "fieldViolations": [
+                    {
+                        "field": "contents[1].parts[0].inline_data.data",
+                        "description": "Base64 decoding failed"
+                    },
+                    {
+                        "field": "contents[3].parts[0].function_response",
+                        "description": "Unknown function"
+                    }
+                ]
+            }]
+        }
+    });
+    let error = Error::BadResponse {
+        code: 400,
+        description: Some(body.to_string()),
+        retry_after: None,
+    };
+
+    let enriched = ContentBuilder::enrich_error(&builder.provenance, error);
+    assert!(matches!(enriched, Error::InvalidFields { ref fields, .. } if fields.len() == 2));
+    assert_eq!(
+        enriched.to_string(),
+        "bad request; code 400; \
+         contents[1].parts[0].inline_data.data: Base64 decoding failed \
+         (added by with_inline_data [contract.pdf]); \
+         contents[3].parts[0].function_response: Unknown function \
+         (added by with_function_response)"
+    );
+
+    // Errors without field violations pass through untouched
+    let plain = Error::BadResponse {
+        code: 500,
+        description: Some("internal".to_string()),
+        retry_after: None,
+    };
+    assert!(matches!(
+        ContentBuilder::enrich_error(&builder.provenance, plain),
+        Error::BadResponse { code: 500, .. }
+    ));
+}
+
+#[test]
+fn test_parse_strict_reports_unknown_fields() {
+    let raw = json!({
+        "candidates": [
+            {
+                "content": {
+                    "parts": [
+                        { "text": "Hello" },
+                        { "text": "World", "newPartFlag": true }
+                    ],
+                    "role": "model",
+                    "contentTag": "x"
+                },
+                "finishReason": "STOP",
+                "index": 0,
+                "modelStatus": "NEW"
+            }
+        ],
+        "usageMetadata": {
+            "promptTokenCount": 3,
+            "candidatesTokenCount": 2,
+            "totalTokenCount": 5,
+            "cacheHitRatio": 0.5
+        },
+        "modelVersion": "gemini-2.5-flash",
+        "createTime": "2025-10-01T00:00:00Z"
+    });
+
+    let (response, unknown) = GenerationResponse::parse_strict(&raw.to_string()).unwrap();
+    assert_eq!(response.text(), "HelloWorld");
+
+    let mut paths: Vec<_> = unknown.iter().map(|f| f.path.as_str()).collect();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![
+            "candidates[0].content.contentTag",
+            "candidates[0].content.parts[1].newPartFlag",
+            "candidates[0].modelStatus",
+            "createTime",
+            "usageMetadata.cacheHitRatio",
+        ]
+    );
+
+    // A response fully covered by the model reports nothing
+    let known = json!({
+        "candidates": [{ "content": { "parts": [{ "text": "Hi" }], "role": "model" } }],
+        "modelVersion": "gemini-2.5-flash"
+    });
+    let (_, unknown) = GenerationResponse::parse_strict(&known.to_string()).unwrap();
+    assert!(unknown.is_empty(), "unexpected unknown fields: {unknown:?}");
+}
+
+#[test]
+fn test_parse_strict_reports_type_drift_and_dropped_empty_values() {
+    use serde::{de::Error as _, Deserializer};
+
+    // Counts the API may send as strings, as it does for 64-bit integers
+    fn lenient<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::String(count) => count.parse().map_err(D::Error::custom),
+            count => serde_json::from_value(count).map_err(D::Error::custom),
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Usage {
+        #[serde(deserialize_with = "lenient")]
+        count: u64,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        note: Option<String>,
+    }
+
+    let raw = json!({"count": "12", "tags": [], "note": null, "extra": {}});
+    let (usage, unknown) = crate::common::strict::parse_strict::<Usage>(&raw.to_string()).unwrap();
+    assert_eq!(usage.count, 12);
+    let mut reported: Vec<_> = unknown.iter().map(ToString::to_string).collect();
+    reported.sort();
+    assert_eq!(
+        reported,
+        vec!["count (string read as number)", "extra", "tags"]
+    );
+    let count = unknown.iter().find(|field| field.path == "count").unwrap();
+    assert_eq!(count.typed, Some(json!(12)));
+
+    let (_, unknown) =
+        crate::common::strict::parse_strict::<Usage>(r#"{"count": 7, "tags": ["a"]}"#).unwrap();
+    assert!(unknown.is_empty(), "unexpected unknown fields: {unknown:?}");
+}
+
+fn text_chunk(text: &str, finish: Option<&str>) -> GenerationResponse {
+    let mut candidate = json!({
+        "content": { "parts": [{ "text": text }], "role": "model" },
+        "index": 0
+    });
+    if let Some(finish) = finish {
+        candidate["finishReason"] = json!(finish);
+    }
+    serde_json::from_value(json!({ "candidates": [candidate] })).unwrap()
+}
+
+#[test]
+fn test_pii_redactor_on_complete_text() {
+    use crate::safety::screen::{apply_redactions, OutputScreen, Redactor, ScreenDecision};
+
+    let text = "Mail jane.doe@example.com or call (555) 123-4567, card 4111 1111 1111 1111. \
+                Order 1234567890123 is not a card.";
+    let ScreenDecision::Redact(redactions) = Redactor::pii().screen(text) else {
+        panic!("expected redactions");
+    };
+    assert_eq!(
+        apply_redactions(text, &redactions),
+        "Mail [REDACTED EMAIL] or call [REDACTED PHONE], card [REDACTED CARD]. \
+         Order 1234567890123 is not a card."
+    );
+    assert_eq!(
+        Redactor::pii().screen("nothing here"),
+        ScreenDecision::Allow
+    );
+}
+
+#[tokio::test]
+async fn test_output_screen_catches_card_split_across_chunks() {
+    use crate::{safety::screen::Redactor, ContentBuilder};
+    use futures::TryStreamExt;
+
+    let chunks = vec![
+        Ok(text_chunk("Your card number is 4111 11", None)),
+        Ok(text_chunk("11 1111 1", None)),
+        Ok(text_chunk("111, keep it safe.", Some("STOP"))),
+    ];
+    let screened = ContentBuilder::screen_stream(
+        futures::stream::iter(chunks),
+        Some(std::sync::Arc::new(Redactor::pii())),
+    );
+    let released: Vec<GenerationResponse> = screened.try_collect().await.unwrap();
+    let texts: Vec<String> = released.iter().map(|chunk| chunk.text()).collect();
+
+    // Nothing containing digits of the card leaks before the match is complete
+    assert!(texts.iter().all(|t| !t.contains("4111")), "{texts:?}");
+    assert_eq!(
+        texts.concat(),
+        "Your card number is [REDACTED CARD], keep it safe."
+    );
+}
+
+#[tokio::test]
+async fn test_output_screen_block_fails_stream() {
+    use crate::{
+        client::Error,
+        safety::screen::{OutputScreen, ScreenDecision},
+        ContentBuilder,
+    };
+    use futures::TryStreamExt;
+
+    struct NoSecrets;
+    impl OutputScreen for NoSecrets {
+        fn screen(&self, text: &str) -> ScreenDecision {
+            if text.contains("SECRET") {
+                ScreenDecision::Block {
+                    reason: "secret disclosed".to_string(),
+                }
+            } else {
+                ScreenDecision::Allow
+            }
+        }
+        fn window(&self) -> usize {
+            8
+        }
+    }
+
+    let chunks = vec![
+        Ok(text_chunk("The code is SEC", None)),
+        Ok(text_chunk("RET-42", Some("STOP"))),
+    ];
+    let result: Result<Vec<GenerationResponse>, Error> = ContentBuilder::screen_stream(
+        futures::stream::iter(chunks),
+        Some(std::sync::Arc::new(NoSecrets)),
+    )
+    .try_collect()
+    .await;
+    assert!(matches!(result, Err(Error::OutputBlocked { reason }) if reason == "secret disclosed"));
+}
+
+#[test]
+fn test_harm_probability_order() {
+    use crate::HarmProbability::*;
+
+    let ascending = [HarmProbabilityUnspecified, Negligible, Low, Medium, High];
+    for (rank, probability) in ascending.iter().enumerate() {
+        assert_eq!(probability.rank() as usize, rank);
+    }
+    assert!(ascending.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(Low.max(Medium), Medium);
+    assert!(High >= Medium && Medium >= Medium && Low < Medium);
+}
+
+fn rated(text: &str, ratings: serde_json::Value) -> serde_json::Value {
+    json!({
+        "candidates": [{
+            "content": {"role": "model", "parts": [{"text": text}]},
+            "safetyRatings": ratings,
+            "index": 0
+        }]
+    })
+}
+
+#[tokio::test]
+async fn test_safety_thresholds_flag_and_reject() {
+    use crate::{HarmCategory, HarmProbability, SafetyFlag};
+
+    let ratings = json!([
+        {"category": "HARM_CATEGORY_HARASSMENT", "probability": "LOW"},
+        {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "MEDIUM"}
+    ]);
+    let server = MockServer::start().await;
+    server
+        .push(MockResponse::json(
+            200,
+            rated("Careful now", ratings.clone()),
+        ))
+        .push(MockResponse::json(200, rated("Careful now", ratings)));
+    let client = server.client();
+
+    let response = client
+        .generate_content()
+        .with_user_message("hi")
+        .flag_safety_above(HarmProbability::Medium)
+        .execute()
+        .await
+        .unwrap();
+    let dangerous = SafetyFlag {
+        candidate_index: 0,
+        category: HarmCategory::DangerousContent,
+        probability: HarmProbability::Medium,
+    };
+    assert_eq!(response.meta.flags, vec![dangerous.clone()]);
+
+    let error = client
+        .generate_content()
+        .with_user_message("hi")
+        .flag_safety_above(HarmProbability::Low)
+        .reject_safety_above(HarmProbability::Medium)
+        .execute()
+        .await
+        .unwrap_err();
+    let ClientError::SafetyThresholdExceeded { threshold, flags } = &error else {
+        panic!("unexpected error: {error}");
+    };
+    assert_eq!(*threshold, HarmProbability::Medium);
+    assert_eq!(flags, &vec![dangerous]);
+    assert!(!error.is_retryable());
+}
+
+#[tokio::test]
+async fn test_safety_thresholds_judge_stream_aggregate() {
+    use crate::{HarmCategory, HarmProbability};
+    use futures::TryStreamExt;
+
+    let harassment = |probability: &str| json!([{"category": "HARM_CATEGORY_HARASSMENT", "probability": probability}]);
+    let server = MockServer::start().await;
+    let chunks = || {
+        MockResponse::sse([
+            rated("One", harassment("MEDIUM")),
+            rated(" two", harassment("NEGLIGIBLE")),
+            rated(" three", harassment("HIGH")),
+        ])
+    };
+    server.push(chunks()).push(chunks());
+    let client = server.client();
+
+    // Later chunks rated lower keep the flags of earlier ones
+    let flagged: Vec<GenerationResponse> = client
+        .generate_content()
+        .with_user_message("hi")
+        .flag_safety_above(HarmProbability::Medium)
+        .execute_stream()
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    let probabilities: Vec<_> = flagged
+        .iter()
+        .map(|chunk| {
+            assert_eq!(chunk.meta.flags.len(), 1);
+            assert_eq!(chunk.meta.flags[0].category, HarmCategory::Harassment);
+            chunk.meta.flags[0].probability
+        })
+        .collect();
+    assert_eq!(
+        probabilities,
+        [
+            HarmProbability::Medium,
+            HarmProbability::Medium,
+            HarmProbability::High
+        ]
+    );
+
+    let mut stream = Box::pin(
+        client
+            .generate_content()
+            .with_user_message("hi")
+            .reject_safety_above(HarmProbability::High)
+            .execute_stream()
+            .await
+            .unwrap(),
+    );
+    let mut released = String::new();
+    let error = loop {
+        match stream.try_next().await {
+            Ok(Some(chunk)) => released.push_str(&chunk.text()),
+            Ok(None) => panic!("stream ended without rejecting"),
+            Err(error) => break error,
+        }
+    };
+    assert_eq!(released, "One two");
+    assert!(matches!(
+        error,
+        ClientError::SafetyThresholdExceeded { ref flags, .. } if flags[0].probability == HarmProbability::High
+    ));
+    assert!(stream.try_next().await.unwrap().is_none());
+}
+
+#[test]
+fn test_per_turn_derived_seeds_are_stable() {
+    let strategy = SeedStrategy::PerTurnDerived { base_seed: 42 };
+    let seeds: Vec<i32> = (0..3).map(|t| strategy.seed_for_turn(t).unwrap()).collect();
+    assert_eq!(seeds, vec![1966338424, 885758336, 629383753]);
+    assert!(seeds.iter().all(|s| *s >= 0));
+
+    assert_eq!(SeedStrategy::None.seed_for_turn(3), None);
+    assert_eq!(SeedStrategy::Fixed(7).seed_for_turn(3), Some(7));
+}
+
+#[tokio::test]
+async fn test_chat_session_records_seed_per_turn() {
+    let server = MockServer::start().await;
+    for reply in ["Hi!", "Fine."] {
+        server.push(MockResponse::json(
+            200,
+            json!({
+                "candidates": [{
+                    "content": {"role": "model", "parts": [{"text": reply}]},
+                    "finishReason": "STOP"
+                }]
+            }),
+        ));
+    }
+
+    let strategy = SeedStrategy::PerTurnDerived { base_seed: 42 };
+    let mut chat = server.client().start_chat().with_seed_strategy(strategy);
+    chat.send_message("Hello").await.unwrap();
+    chat.send_message("How are you?").await.unwrap();
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    for (turn, request) in requests.iter().enumerate() {
+        let body = request.json();
+        assert_eq!(
+            body["generationConfig"]["seed"],
+            json!(strategy.seed_for_turn(turn).unwrap())
+        );
+    }
+    // The second request carries the first exchange plus the new message
+    assert_eq!(requests[1].json()["contents"].as_array().unwrap().len(), 3);
+
+    let export = serde_json::to_value(chat.export()).unwrap();
+    assert_eq!(export["history"].as_array().unwrap().len(), 4);
+    assert_eq!(
+        export["turns"][1]["seed"],
+        json!(strategy.seed_for_turn(1).unwrap())
+    );
+    assert_eq!(
+        export["seedStrategy"],
+        json!({"perTurnDerived": {"baseSeed": 42}})
+    );
+}
+
+#[tokio::test]
+async fn test_tool_loop_seeds_every_step() {
+    let server = MockServer::start().await;
+    let lookup = json!([{"functionCall": {"name": "lookup", "args": {}}}]);
+    server
+        .push(model_parts(lookup.clone(), "STOP"))
+        .push(model_parts(lookup, "STOP"))
+        .push(answer("done", 5));
+    let registry = ToolRegistry::new().with_function(
+        FunctionDeclaration::new("lookup", "Looks something up", None),
+        |_| async { Ok(json!({"value": 42})) },
+    );
+
+    let strategy = SeedStrategy::PerTurnDerived { base_seed: 42 };
+    let run = || async {
+        server
+            .client()
+            .generate_content()
+            .with_user_message("hi")
+            .with_seed(7)
+            .with_seed_strategy(strategy)
+            .execute_with_tools(&registry)
+            .await
+            .unwrap()
+    };
+    assert_eq!(run().await.text(), "done");
+
+    let seeds: Vec<serde_json::Value> = server
+        .requests()
+        .iter()
+        .map(|request| request.json()["generationConfig"]["seed"].clone())
+        .collect();
+    assert_eq!(
+        seeds,
+        [1966338424, 885758336, 629383753].map(|seed| json!(seed))
+    );
+
+    // The same strategy replays the same seeds
+    let lookup = json!([{"functionCall": {"name": "lookup", "args": {}}}]);
+    server
+        .push(model_parts(lookup.clone(), "STOP"))
+        .push(model_parts(lookup, "STOP"))
+        .push(answer("done", 5));
+    run().await;
+    let replayed: Vec<serde_json::Value> = server.requests()[3..]
+        .iter()
+        .map(|request| request.json()["generationConfig"]["seed"].clone())
+        .collect();
+    assert_eq!(replayed, seeds);
+}
+
+#[test]
+fn test_find_quote_normalizes_whitespace_and_quotes() {
+    let document =
+        "The  bridge was\n opened in 1932.\nIt is called \u{201C}the Coathanger\u{201D}.";
+    assert_eq!(find_quote(document, "bridge was opened", None), Some(5));
+    assert_eq!(
+        find_quote(document, "...The Bridge WAS opened…", None),
+        Some(0)
+    );
+    assert_eq!(
+        find_quote(document, "called \"the coathanger\"", None),
+        document.find("called")
+    );
+    assert_eq!(find_quote(document, "opened in 1933", None), None);
+    assert_eq!(find_quote(document, "   ", None), None);
+
+    // The occurrence closest to the hint wins
+    let repeated = "alpha beta. alpha beta. alpha beta.";
+    assert_eq!(find_quote(repeated, "alpha beta", Some(13)), Some(12));
+
+    // The hint counts characters, the result bytes
+    let accented = "éééééééééé ab éééééééééé ab";
+    assert_eq!(find_quote(accented, "ab", Some(25)), Some(45));
+    assert_eq!(find_quote(accented, "ab", Some(11)), Some(21));
+    assert_eq!(find_quote(accented, "ab", Some(1000)), Some(45));
+}
+
+#[tokio::test]
+async fn test_attributed_answer_preset_flags_fabricated_citation() {
+    let server = MockServer::start().await;
+    let answer = json!({
+        "answer": "It opened in 1932.",
+        "citations": [
+            {"doc_id": "bridge", "quote": "opened in 1932", "start_hint": 16},
+            {"doc_id": "bridge", "quote": "opened by the Queen"},
+            {"doc_id": "tower", "quote": "opened in 1932"}
+        ]
+    });
+    server.push(MockResponse::json(
+        200,
+        json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [{"text": answer.to_string()}]},
+                "finishReason": "STOP"
+            }]
+        }),
+    ));
+
+    let documents = vec![Document::new("bridge", "The bridge was opened in 1932.")];
+    let result = server
+        .client()
+        .generate_content()
+        .attributed_answer(documents, "When did the bridge open?")
+        .execute()
+        .await
+        .unwrap();
+
+    let body = server.requests()[0].json();
+    assert_eq!(
+        body["generationConfig"]["responseSchema"],
+        attribution_schema()
+    );
+    assert_eq!(
+        body["generationConfig"]["responseMimeType"],
+        json!("application/json")
+    );
+    assert_eq!(
+        body["contents"][0]["parts"][0]["text"],
+        json!("<document id=\"bridge\">\nThe bridge was opened in 1932.\n</document>")
+    );
+    assert_eq!(
+        body["contents"][1]["parts"][0]["text"],
+        json!("When did the bridge open?")
+    );
+
+    let statuses: Vec<CitationStatus> = result.citations.iter().map(|c| c.status).collect();
+    assert_eq!(
+        statuses,
+        vec![
+            CitationStatus::Verified { offset: 15 },
+            CitationStatus::QuoteNotFound,
+            CitationStatus::UnknownDocument,
+        ]
+    );
+    assert_eq!(result.fabricated().count(), 2);
+    assert!(!result.is_fully_attributed());
+}
+
+#[tokio::test]
+async fn test_live_spoken_turns_pair_audio_with_transcripts() {
+    let messages: Vec<Result<LiveServerMessage, std::convert::Infallible>> = [
+        json!({"setupComplete": {}}),
+        json!({"serverContent": {"inputTranscription": {"text": "What time"}}}),
+        json!({"serverContent": {"inputTranscription": {"text": " is it?"}}}),
+        json!({"serverContent": {"modelTurn": {"parts": [{"inlineData": {"mimeType": "audio/pcm;rate=24000", "data": "AAA="}}]}}}),
+        json!({"serverContent": {"outputTranscription": {"text": "It is"}}}),
+        json!({"serverContent": {"modelTurn": {"parts": [{"inlineData": {"mimeType": "audio/pcm;rate=24000", "data": "AQE="}}]}}}),
+        json!({"serverContent": {"outputTranscription": {"text": " noon."}}}),
+        json!({"serverContent": {"turnComplete": true}}),
+        json!({"serverContent": {"modelTurn": {"parts": [{"inlineData": {"mimeType": "audio/pcm;rate=24000", "data": "AgI="}}]}}}),
+        json!({"serverContent": {"outputTranscription": {"text": "Any"}, "interrupted": true}}),
+    ]
+    .into_iter()
+    .map(|m| Ok(serde_json::from_value(m).unwrap()))
+    .collect();
+
+    let turns: Vec<_> =
+        futures::StreamExt::collect::<Vec<_>>(spoken_turns(futures::stream::iter(messages)))
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+
+    assert_eq!(turns.len(), 2);
+    assert_eq!(
+        turns[0].input_transcript.as_deref(),
+        Some("What time is it?")
+    );
+    assert_eq!(turns[0].transcript.as_deref(), Some("It is noon."));
+    assert_eq!(turns[0].audio_bytes().unwrap(), vec![0, 0, 1, 1]);
+    assert_eq!(turns[1].transcript.as_deref(), Some("Any"));
+    assert_eq!(turns[1].audio.len(), 1);
+
+    let setup = LiveSetup::new(Model::Gemini25Flash).with_output_audio_transcription(true);
+    assert_eq!(
+        setup.to_message(),
+        json!({"setup": {"model": "models/gemini-2.5-flash", "outputAudioTranscription": {}}})
+    );
+}
+
+#[tokio::test]
+async fn test_execute_spoken_transcribes_generated_audio() {
+    let server = MockServer::start().await;
+    server.push(MockResponse::json(
+        200,
+        json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [
+                    {"inlineData": {"mimeType": "audio/L16;codec=pcm;rate=24000", "data": "AAEC"}}
+                ]},
+                "finishReason": "STOP"
+            }]
+        }),
+    ));
+    server.push(MockResponse::json(
+        200,
+        json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [{"text": "Hello there.\n"}]},
+                "finishReason": "STOP"
+            }]
+        }),
+    ));
+
+    let client = server
+        .builder()
+        .with_model("models/gemini-2.5-flash-preview-tts".to_string())
+        .build()
+        .unwrap();
+    let spoken = client
+        .generate_content()
+        .with_user_message("Say hello")
+        .with_audio_output()
+        .with_transcript(true)
+        .execute_spoken()
+        .await
+        .unwrap();
+
+    assert_eq!(spoken.audio_bytes().unwrap(), vec![0, 1, 2]);
+    assert_eq!(spoken.transcript.as_deref(), Some("Hello there."));
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[0]
+        .path
+        .contains("gemini-2.5-flash-preview-tts:generateContent"));
+    assert!(requests[1]
+        .path
+        .contains("gemini-2.5-flash:generateContent"));
+    let audio = &requests[1].json()["contents"][0]["parts"][0]["inlineData"];
+    assert_eq!(audio["mimeType"], json!("audio/wav"));
+    // 44-byte WAV header followed by the three PCM bytes
+    let wav = base64::Engine::decode(
+        &base64::engine::general_purpose::STANDARD,
+        audio["data"].as_str().unwrap(),
+    )
+    .unwrap();
+    assert_eq!(&wav[..4], b"RIFF");
+    assert_eq!(&wav[44..], &[0, 1, 2]);
+}
+
+#[tokio::test]
+async fn test_execute_spoken_transcribes_within_the_call() {
+    use crate::Policy;
+
+    let audio = || {
+        MockResponse::json(
+            200,
+            json!({
+                "candidates": [{
+                    "content": {"role": "model", "parts": [
+                        {"inlineData": {"mimeType": "audio/L16;codec=pcm;rate=24000", "data": "AAEC"}}
+                    ]},
+                    "finishReason": "STOP"
+                }]
+            }),
+        )
+    };
+    let client = |server: &MockServer| {
+        server
+            .builder()
+            .with_model("models/gemini-2.5-flash-preview-tts".to_string())
+            .with_policy(
+                Policy::new()
+                    .with_retry(RetryPolicy::new(2).with_initial_backoff(Duration::from_secs(1)))
+                    .with_fallback(Model::Gemini25FlashLite),
+            )
+            .with_clock(ManualClock::new())
+            .build()
+            .unwrap()
+    };
+    let speak = |client: &Gemini| {
+        client
+            .generate_content()
+            .with_user_message("Say hello")
+            .with_audio_output()
+            .with_transcript(true)
+    };
+
+    // The transcription model is pinned: no fallback after its retries give up
+    let server = MockServer::start().await;
+    server
+        .push(audio())
+        .push(unavailable())
+        .push(unavailable())
+        .push(answer("Hello there.", 5));
+    let result = speak(&client(&server)).execute_spoken().await;
+    assert!(result.is_err());
+    let paths: Vec<_> = server.requests().iter().map(|r| r.path.clone()).collect();
+    assert_eq!(paths.len(), 3, "{paths:?}");
+    assert!(paths[1..]
+        .iter()
+        .all(|path| path.contains("/gemini-2.5-flash:generateContent")));
+
+    // The transcription spends the budget of the call
+    let server = MockServer::start().await;
+    server
+        .push(audio())
+        .push(unavailable())
+        .push(answer("Hello there.", 5));
+    let result = speak(&client(&server))
+        .with_retry_budget(RetryBudget::new(2))
+        .execute_spoken()
+        .await;
+    assert!(result.is_err());
+    assert_eq!(server.requests().len(), 2);
+}
+
+fn unavailable() -> MockResponse {
+    MockResponse::json(
+        503,
+        json!({"error": {"code": 503, "message": "overloaded"}}),
+    )
+}
+
+fn model_parts(parts: serde_json::Value, finish_reason: &str) -> MockResponse {
+    MockResponse::json(
+        200,
+        json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": parts},
+                "finishReason": finish_reason
+            }]
+        }),
+    )
+}
+
+#[tokio::test]
+async fn test_retry_policy_retries_transient_failures() {
+    let server = MockServer::start().await;
+    server.push(unavailable()).push(unavailable());
+    server.push(model_parts(json!([{"text": "ok"}]), "STOP"));
+
+    let clock = ManualClock::new();
+    let client = server
+        .builder()
+        .with_retry_policy(RetryPolicy::new(3))
+        .with_clock(clock.clone())
+        .build()
+        .unwrap();
+    let response = client
+        .generate_content()
+        .with_user_message("hi")
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(response.text(), "ok");
+    assert_eq!(server.requests().len(), 3);
+    // The default backoff runs on the manual clock without waiting
+    assert_eq!(
+        clock.sleeps(),
+        vec![Duration::from_millis(500), Duration::from_secs(1)]
+    );
+}
+
+#[tokio::test]
+async fn test_attempts_report_records_failed_retries() {
+    let server = MockServer::start().await;
+    server.push(unavailable()).push(unavailable());
+    server.push(model_parts(json!([{"text": "ok"}]), "STOP"));
+    server.push(unavailable()).push(unavailable());
+
+    let client = server
+        .builder()
+        .with_retry_policy(RetryPolicy::new(3))
+        .with_clock(ManualClock::new())
+        .build()
+        .unwrap();
+    let response = client
+        .generate_content()
+        .with_user_message("hi")
+        .execute()
+        .await
+        .unwrap();
+    let attempts = &response.attempts.attempts;
+    let summary: Vec<_> = attempts
+        .iter()
+        .map(|a| (a.attempt_number, a.layer, a.backoff_applied))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (1, RetryLayer::Initial, Duration::ZERO),
+            (2, RetryLayer::Http, Duration::from_millis(500)),
+            (3, RetryLayer::Http, Duration::from_secs(1)),
+        ]
+    );
+    assert!(attempts[0].error_summary.as_ref().unwrap().contains("503"));
+    assert!(attempts[1].error_summary.is_some());
+    assert_eq!(attempts[2].error_summary, None);
+    assert_eq!(
+        response.attempts.to_string(),
+        "3 attempt(s), 2 failed, 1.5s backoff"
+    );
+
+    // Once the policy gives up, the last error carries the report
+    let client = server
+        .builder()
+        .with_retry_policy(RetryPolicy::new(2))
+        .with_clock(ManualClock::new())
+        .build()
+        .unwrap();
+    let error = client
+        .generate_content()
+        .with_user_message("hi")
+        .execute()
+        .await
+        .unwrap_err();
+    let ClientError::RetriesExhausted { source, attempts } = &error else {
+        panic!("unexpected error: {error:?}");
+    };
+    assert!(matches!(
+        **source,
+        ClientError::BadResponse { code: 503, .. }
+    ));
+    assert_eq!(attempts.failures(), 2);
+    assert!(!error.is_retryable());
+}
+
+#[tokio::test]
+async fn test_retry_budget_caps_attempts_across_layers() {
+    let server = MockServer::start().await;
+    let lookup = json!([{"functionCall": {"name": "lookup", "args": {}}}]);
+    server
+        .push(unavailable()) // initial request
+        .push(model_parts(lookup, "STOP")) // http retry
+        .push(model_parts(json!([{"text": ""}]), "STOP")) // tool loop step, rejected
+        .push(model_parts(json!([]), "MALFORMED_FUNCTION_CALL")) // regeneration, rejected
+        .push(unavailable()) // regeneration
+        .push(unavailable()); // http retry of the regeneration
+
+    let client = server
+        .builder()
+        .with_retry_policy(RetryPolicy::new(3))
+        .with_clock(ManualClock::new())
+        .build()
+        .unwrap();
+    let registry = ToolRegistry::new().with_function(
+        FunctionDeclaration::new("lookup", "Looks something up", None),
+        |_| async { Ok(json!({"value": 42})) },
+    );
+    let error = client
+        .generate_content()
+        .with_user_message("hi")
+        .with_regeneration(3, |r| {
+            !r.text().is_empty() || !r.function_calls().is_empty()
+        })
+        .with_retry_budget(RetryBudget::new(6))
+        .execute_with_tools(&registry)
+        .await
+        .unwrap_err();
+
+    assert_eq!(server.requests().len(), 6);
+    let ClientError::RetryBudgetExhausted { usage, .. } = &error else {
+        panic!("unexpected error: {error}");
+    };
+    assert_eq!(usage.attempts[&RetryLayer::Initial], 1);
+    assert_eq!(usage.attempts[&RetryLayer::Http], 2);
+    assert_eq!(usage.attempts[&RetryLayer::ToolLoop], 1);
+    assert_eq!(usage.attempts[&RetryLayer::Regeneration], 2);
+    assert!(error
+        .to_string()
+        .contains("tool loop: 1 attempt(s), regeneration: 2 attempt(s)"));
+
+    // The function response was fed back in the tool loop step
+    let body = server.requests()[2].json();
+    assert_eq!(
+        body["contents"][2]["parts"][0]["functionResponse"]["response"],
+        json!({"value": 42})
+    );
+}
+
+#[tokio::test]
+async fn test_tool_selector_changes_declarations_per_round() {
+    let server = MockServer::start().await;
+    server
+        .push(model_parts(
+            json!([{"functionCall": {"name": "search_flights", "args": {"to": "LIS"}}}]),
+            "STOP",
+        ))
+        .push(model_parts(json!([{"text": "Booked"}]), "STOP"));
+
+    let declaration = |name: &str| FunctionDeclaration::new(name, "A travel function", None);
+    let registry = ToolRegistry::new()
+        .with_function(declaration("search_flights"), |_| async {
+            Ok(json!({"flights": ["TP123"]}))
+        })
+        .with_function(declaration("book_flight"), |_| async { Ok(json!({})) })
+        .with_function(declaration("cancel_booking"), |_| async { Ok(json!({})) })
+        .with_function(declaration("weather"), |_| async { Ok(json!({})) });
+
+    let subset = registry.subset(&["search_flights", "book_flight", "missing"]);
+    let names: Vec<_> = subset.declarations().into_iter().map(|d| d.name).collect();
+    assert_eq!(names, vec!["book_flight", "search_flights"]);
+
+    let registry = subset.with_tool_selector(|state| match state.contents.len() {
+        1 => vec!["search_flights"],
+        _ => vec!["book_flight"],
+    });
+    let response = server
+        .client()
+        .generate_content()
+        .with_user_message("Fly me to Lisbon")
+        .execute_with_tools(&registry)
+        .await
+        .unwrap();
+    assert_eq!(response.text(), "Booked");
+
+    let declared = |i: usize| -> Vec<String> {
+        server.requests()[i].json()["tools"][0]["function_declarations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(declared(0), vec!["search_flights"]);
+    assert_eq!(declared(1), vec!["book_flight"]);
+    assert_eq!(
+        server.requests()[1].json()["tools"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+    // search_flights was still exposed when the model called it
+    assert_eq!(
+        server.requests()[1].json()["contents"][2]["parts"][0]["functionResponse"]["response"],
+        json!({"flights": ["TP123"]})
+    );
+}
+
+#[tokio::test]
+async fn test_tool_handlers_by_name_and_transcript() {
+    let server = MockServer::start().await;
+    server
+        .push(model_parts(
+            json!([
+                {"functionCall": {"name": "get_weather", "args": {"city": "Oslo"}}},
+                {"functionCall": {"name": "get_time", "args": {}}}
+            ]),
+            "STOP",
+        ))
+        .push(model_parts(json!([{"text": "Cold, time unknown"}]), "STOP"));
+
+    let registry = ToolRegistry::new()
+        .with_handler("get_weather", |args| async move {
+            Ok(json!({"city": args["city"], "celsius": -3}))
+        })
+        .with_handler("get_time", |_| async { Err("clock unavailable".into()) });
+    assert!(registry.declarations().is_empty());
+    assert!(registry.tool().is_none());
+
+    let response = server
+        .client()
+        .generate_content()
+        .with_user_message("Weather and time in Oslo?")
+        .with_function(FunctionDeclaration::new(
+            "get_weather",
+            "Gets the weather",
+            None,
+        ))
+        .with_function(FunctionDeclaration::new("get_time", "Gets the time", None))
+        .execute_with_tools(&registry)
+        .await
+        .unwrap();
+    assert_eq!(response.text(), "Cold, time unknown");
+
+    // Only the request's own declarations are sent
+    let body = server.requests()[0].json();
+    assert_eq!(body["tools"].as_array().unwrap().len(), 2);
+
+    let transcript = &response.tool_transcript;
+    assert_eq!(transcript.len(), 2);
+    assert_eq!(transcript[0].step, 0);
+    assert_eq!(transcript[0].call.name, "get_weather");
+    assert_eq!(
+        transcript[0].response.response,
+        Some(json!({"city": "Oslo", "celsius": -3}))
+    );
+    assert_eq!(
+        transcript[1].response.response,
+        Some(json!({"error": "clock unavailable"}))
+    );
+    assert_eq!(
+        server.requests()[1].json()["contents"][2]["parts"][1]["functionResponse"]["response"],
+        json!({"error": "clock unavailable"})
+    );
+}
+
+/// Golden test of the run log format; set `UPDATE_RUN_LOGS=1` to rewrite
+/// `tests/run_logs/tool_loop.json` after an intended change.
+#[tokio::test]
+async fn test_run_log_of_scripted_tool_loop() {
+    use crate::{RunEvent, RunLog, RunLogError, RunLogOptions, RunOutcome, ToolOutcome};
+
+    let server = MockServer::start().await;
+    server
+        .push(model_parts(
+            json!([
+                {"functionCall": {"id": "c1", "name": "get_weather", "args": {
+                    "city": "Lisbon",
+                    "account": {"api_key": "sk-123", "user": "ana"}
+                }}},
+                {"functionCall": {"id": "c2", "name": "get_tides", "args": {}}}
+            ]),
+            "STOP",
+        ))
+        .push(unavailable())
+        .push(model_parts(
+            json!([{"functionCall": {"name": "book_table", "args": {"guests": 2}}}]),
+            "STOP",
+        ))
+        .push(MockResponse::json(
+            200,
+            json!({
+                "candidates": [{
+                    "content": {"role": "model", "parts": [{"text": "Sunny; no table free."}]},
+                    "finishReason": "STOP"
+                }],
+                "usageMetadata": {
+                    "promptTokenCount": 120,
+                    "candidatesTokenCount": 8,
+                    "totalTokenCount": 128
+                }
+            }),
+        ));
+
+    let clock = ManualClock::new();
+    let client = server
+        .builder()
+        .with_retry_policy(RetryPolicy::new(2))
+        .with_clock(clock.clone())
+        .build()
+        .unwrap();
+    let registry = ToolRegistry::new()
+        .with_function(
+            FunctionDeclaration::new("get_weather", "Gets the weather", None),
+            move |_| {
+                let clock = clock.clone();
+                async move {
+                    clock.advance(Duration::from_millis(40));
+                    Ok(json!({"sky": "clear"}))
+                }
+            },
+        )
+        .with_function(
+            FunctionDeclaration::new("book_table", "Books a table", None),
+            |_| async { Err("fully booked".into()) },
+        );
+    let options = RunLogOptions::new()
+        .scrub_field("api_key")
+        .with_scrubber(|function, args| {
+            if function == "get_weather" {
+                args["account"]["user"] = json!("[user]");
+            }
+        });
+
+    let (result, log) = client
+        .generate_content()
+        .with_user_message("Weather in Lisbon, and book dinner for two")
+        .execute_with_tools_logged(&registry, options)
+        .await;
+    assert_eq!(result.unwrap().text(), "Sunny; no table free.");
+    assert_eq!(log.steps(), 3);
+    assert!(!log.to_json().contains("sk-123"));
+
+    let path = format!(
+        "{}/tests/run_logs/tool_loop.json",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    let actual = log.to_json() + "\n";
+    if std::env::var_os("UPDATE_RUN_LOGS").is_some() {
+        std::fs::write(&path, &actual).unwrap();
+    }
+    let expected =
+        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("cannot read {path}: {e}"));
+    assert_eq!(actual, expected, "run log differs from {path}");
+    assert_eq!(RunLog::from_json(&expected).unwrap(), log);
+
+    let ToolOutcome::Error { message } = log
+        .events
+        .iter()
+        .find_map(|event| match event {
+            RunEvent::ToolCall { name, outcome, .. } if name == "book_table" => Some(outcome),
+            _ => None,
+        })
+        .unwrap()
+    else {
+        panic!("book_table should have failed");
+    };
+    assert_eq!(message, "fully booked");
+
+    let newer = expected.replacen("\"version\": 1", "\"version\": 2", 1);
+    assert!(matches!(
+        RunLog::from_json(&newer),
+        Err(RunLogError::UnsupportedVersion { version: 2 })
+    ));
+
+    // Failed runs are logged too, and chat sessions log their tool loop
+    server.push(MockResponse::json(
+        400,
+        json!({"error": {"code": 400, "message": "bad request"}}),
+    ));
+    let mut chat = client.start_chat();
+    let (result, log) = chat
+        .send_message_with_tools_logged("Weather?", &registry, RunLogOptions::new())
+        .await;
+    assert!(result.is_err());
+    assert!(chat.history().is_empty());
+    let Some(RunEvent::Finished {
+        steps: 1,
+        outcome: RunOutcome::Error { .. },
+    }) = log.events.last()
+    else {
+        panic!("unexpected events: {:?}", log.events);
+    };
+}
+
+#[tokio::test]
+async fn test_generate_content_raw_keeps_retries_and_error_parsing() {
+    let server = MockServer::start().await;
+    server.push(unavailable()).push(MockResponse::json(
+        200,
+        json!({"candidates": [], "brandNewField": {"enabled": true}}),
+    ));
+    server.push(MockResponse::json(
+        400,
+        json!({"error": {
+            "code": 400,
+            "message": "Invalid JSON payload",
+            "status": "INVALID_ARGUMENT",
+            "details": [{"fieldViolations": [{"field": "futureConfig", "description": "Unknown name"}]}]
+        }}),
+    ));
+    server.push(MockResponse::sse([
+        json!({"candidates": [{"content": {"parts": [{"text": "a"}]}}], "extra": 1}),
+        json!({"candidates": [{"content": {"parts": [{"text": "b"}]}}]}),
+    ]));
+
+    let client = server
+        .builder()
+        .with_retry_policy(RetryPolicy::new(2))
+        .with_clock(ManualClock::new())
+        .build()
+        .unwrap();
+    let body = json!({
+        "contents": [{"role": "user", "parts": [{"text": "hi"}]}],
+        "futureConfig": {"mode": "NEW"}
+    });
+
+    let response = client
+        .generate_content_raw("gemini-future", body.clone())
+        .await
+        .unwrap();
+    assert_eq!(response["brandNewField"], json!({"enabled": true}));
+
+    let error = client
+        .generate_content_raw("models/gemini-future", body.clone())
+        .await
+        .unwrap_err();
+    let status = error.api_status().unwrap();
+    assert_eq!(status.status.as_deref(), Some("INVALID_ARGUMENT"));
+    assert_eq!(status.field_violations()[0].field, "futureConfig");
+
+    let stream = client
+        .generate_content_raw_stream("gemini-future", body.clone())
+        .await
+        .unwrap();
+    let events: Vec<serde_json::Value> = futures::TryStreamExt::try_collect(stream).await.unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["extra"], json!(1));
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 4);
+    assert_eq!(
+        requests[0].path,
+        "/v1beta/models/gemini-future:generateContent"
+    );
+    assert_eq!(requests[1].json(), body);
+    assert_eq!(requests[2].header("x-goog-api-key"), Some("test-key"));
+    assert_eq!(
+        requests[3].path,
+        "/v1beta/models/gemini-future:streamGenerateContent?alt=sse"
+    );
+}
+
+/// A stream that turns to SAFETY mid-way and keeps sending trailing chunks
+fn safety_stream() -> MockResponse {
+    let chunk = |text: &str, finish: Option<&str>| {
+        let mut candidate =
+            json!({"content": {"role": "model", "parts": [{"text": text}]}, "index": 0});
+        if let Some(finish) = finish {
+            candidate["finishReason"] = json!(finish);
+        }
+        json!({"candidates": [candidate]})
+    };
+    MockResponse::sse([
+        chunk("Once upon", None),
+        chunk(" a time", Some("SAFETY")),
+        chunk("", Some("SAFETY")),
+        chunk("", Some("SAFETY")),
+    ])
+}
+
+#[tokio::test]
+async fn test_stream_events_report_finish_reason_once() {
+    let server = MockServer::start().await;
+    server.push(safety_stream());
+
+    let stream = server
+        .client()
+        .generate_content()
+        .with_user_message("Tell me a story")
+        .execute_stream_events()
+        .await
+        .unwrap();
+    let events: Vec<StreamEvent> = futures::TryStreamExt::try_collect(stream).await.unwrap();
+
+    let kinds: Vec<&str> = events
+        .iter()
+        .map(|e| match e {
+            StreamEvent::Chunk(_) => "chunk",
+            StreamEvent::Finished(_) => "finished",
+        })
+        .collect();
+    assert_eq!(kinds, ["chunk", "chunk", "finished", "chunk", "chunk"]);
+    assert_eq!(events[2], StreamEvent::Finished(FinishReason::Safety));
+}
+
+#[tokio::test]
+async fn test_abort_stream_on_safety_drops_trailing_chunks() {
+    let server = MockServer::start().await;
+    server.push(safety_stream().with_chunk_delay(Duration::from_millis(300)));
+
+    let started = std::time::Instant::now();
+    let stream = server
+        .client()
+        .generate_content()
+        .with_user_message("Tell me a story")
+        .abort_stream_on(FinishReason::Safety)
+        .execute_stream_events()
+        .await
+        .unwrap();
+    let events: Vec<StreamEvent> = futures::TryStreamExt::try_collect(stream).await.unwrap();
+
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[2], StreamEvent::Finished(FinishReason::Safety));
+    // Two chunks were awaited; the two trailing ones were not
+    assert!(started.elapsed() < Duration::from_millis(900));
+}
+
+/// Runs text chunks through the client stop scanner, returning the emitted text per chunk
+/// and the finish reason of the last chunk
+async fn client_stop_chunks(
+    chunks: &[&str],
+    patterns: Vec<StopPattern>,
+) -> (Vec<String>, Option<FinishReason>) {
+    let last = chunks.len() - 1;
+    let chunks: Vec<Result<GenerationResponse, ClientError>> = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, text)| Ok(text_chunk(text, (i == last).then_some("STOP"))))
+        .collect();
+    let stream = client_stop(futures::stream::iter(chunks), patterns);
+    let out: Vec<GenerationResponse> = futures::TryStreamExt::try_collect(stream).await.unwrap();
+    let finish = out.last().unwrap().candidates[0].finish_reason.clone();
+    (out.iter().map(|c| c.text()).collect(), finish)
+}
+
+#[tokio::test]
+async fn test_client_stop_matches_across_chunks() {
+    // Only a possible prefix of the literal is held back
+    let (texts, finish) = client_stop_chunks(
+        &["Hello wor", "ld. STO", "P here", "never sent"],
+        vec![StopPattern::literal("STOP")],
+    )
+    .await;
+    assert_eq!(texts, ["Hello wor", "ld. ", ""]);
+    assert_eq!(finish, Some(FinishReason::ClientStop));
+
+    let (texts, finish) = client_stop_chunks(
+        &["We append", " to the le", "gend. The E", "ND! more"],
+        vec![StopPattern::regex(r"(?i)\bend\b", 3).unwrap()],
+    )
+    .await;
+    assert_eq!(texts.concat(), "We append to the legend. The ");
+    assert_eq!(finish, Some(FinishReason::ClientStop));
+
+    // A regex match at the end of a chunk waits for the next one to confirm it
+    let (texts, finish) = client_stop_chunks(
+        &["Ending soon. The End", "ing is not it; the end."],
+        vec![
+            StopPattern::regex(r"(?i)\bend\b", 3).unwrap(),
+            StopPattern::literal_ignore_case("nothing"),
+        ],
+    )
+    .await;
+    assert_eq!(texts, ["Ending soon. The ", "Ending is not it; the "]);
+    assert_eq!(finish, Some(FinishReason::ClientStop));
+
+    // Held back text is released when the stream finishes without a match
+    let (texts, finish) =
+        client_stop_chunks(&["abc ST", "OR", "Y"], vec![StopPattern::literal("STOP")]).await;
+    assert_eq!(texts, ["abc ", "STOR", "Y"]);
+    assert_eq!(finish, Some(FinishReason::Stop));
+}
+
+#[tokio::test]
+async fn test_streams_keep_signed_text_parts() {
+    let signed = || {
+        json!({"candidates": [{"content": {"role": "model", "parts": [
+            {"text": "First part. ", "thoughtSignature": "c2lnMQ=="},
+            {"text": "Second STOP part.", "thoughtSignature": "c2lnMg=="}
+        ]}, "finishReason": "STOP", "index": 0}]})
+    };
+    let server = MockServer::start().await;
+    server.push(MockResponse::sse([signed()]));
+
+    // Without stop patterns, chunks come out as the API sent them
+    let stream = server
+        .client()
+        .generate_content()
+        .with_user_message("Hi")
+        .execute_stream()
+        .await
+        .unwrap();
+    let chunks: Vec<GenerationResponse> = futures::TryStreamExt::try_collect(stream).await.unwrap();
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(
+        serde_json::to_value(&chunks[0].candidates[0].content).unwrap(),
+        signed()["candidates"][0]["content"]
+    );
+
+    // With one, each part is cut on its own and keeps its signature
+    let chunk: GenerationResponse = serde_json::from_value(signed()).unwrap();
+    let stream = client_stop(
+        futures::stream::iter([Ok::<_, ClientError>(chunk)]),
+        vec![StopPattern::literal("STOP")],
+    );
+    let chunks: Vec<GenerationResponse> = futures::TryStreamExt::try_collect(stream).await.unwrap();
+    assert_eq!(
+        serde_json::to_value(&chunks[0].candidates[0].content).unwrap(),
+        json!({"role": "model", "parts": [
+            {"text": "First part. ", "thoughtSignature": "c2lnMQ=="},
+            {"text": "Second ", "thoughtSignature": "c2lnMg=="}
+        ]})
+    );
+    assert_eq!(
+        chunks[0].candidates[0].finish_reason,
+        Some(FinishReason::ClientStop)
+    );
+}
+
+#[tokio::test]
+async fn test_client_stop_aborts_connection() {
+    let server = MockServer::start().await;
+    let chunk = |text: &str| json!({"candidates": [{"content": {"role": "model", "parts": [{"text": text}]}, "index": 0}]});
+    let mut chunks = vec![chunk("Step 1. "), chunk("Step 2. do"), chunk("ne. Step 3.")];
+    chunks.extend((0..7).map(|_| chunk("filler ")));
+    server.push(MockResponse::sse(chunks).with_chunk_delay(Duration::from_millis(150)));
+
+    let started = std::time::Instant::now();
+    let stream = server
+        .client()
+        .generate_content()
+        .with_user_message("Count")
+        .with_client_stop(StopPattern::literal_ignore_case("DONE."))
+        .execute_stream_events()
+        .await
+        .unwrap();
+    let events: Vec<StreamEvent> = futures::TryStreamExt::try_collect(stream).await.unwrap();
+
+    let text: String = events
+        .iter()
+        .filter_map(|e| match e {
+            StreamEvent::Chunk(chunk) => Some(chunk.text()),
+            StreamEvent::Finished(_) => None,
+        })
+        .collect();
+    assert_eq!(text, "Step 1. Step 2. ");
+    assert_eq!(
+        events.last(),
+        Some(&StreamEvent::Finished(FinishReason::ClientStop))
+    );
+    // Three chunks were awaited; the seven trailing ones were not
+    assert!(started.elapsed() < Duration::from_millis(900));
+}
+
+#[test]
+fn test_strip_markdown_cases() {
+    let cases = [
+        ("plain text", "plain text"),
+        ("**bold** and *italic*", "bold and italic"),
+        ("__bold__ and _italic_", "bold and italic"),
+        ("***both*** and ~~gone~~", "both and gone"),
+        ("**bold with *nested italic* inside**", "bold with nested italic inside"),
+        ("*italic with **nested bold***", "italic with nested bold"),
+        ("***a** b*", "a b"),
+        ("2 * 3 * 4 = 24", "2 * 3 * 4 = 24"),
+        ("snake_case_name stays", "snake_case_name stays"),
+        ("an *unclosed marker", "an *unclosed marker"),
+        (r"escaped \*stars\*", "escaped *stars*"),
+        ("see [the docs](https://example.com/a_(b)) now", "see the docs now"),
+        ("[**bold** link](https://example.com)", "bold link"),
+        ("a [reference][docs] link", "a reference link"),
+        ("just [brackets] here", "just [brackets] here"),
+        ("before ![diagram](img.png) after", "before  after"),
+        ("mail <me@example.com> or <https://example.com>", "mail me@example.com or https://example.com"),
+        ("use `let *x* = 1;` here", "use let *x* = 1; here"),
+        ("`` a `tick` ``", "a `tick`"),
+        ("# Title", "Title"),
+        ("### Section ###", "Section"),
+        ("#hashtag", "#hashtag"),
+        ("> quoted *text*\n>> nested", "quoted text\nnested"),
+        ("* one\n+ two\n- three", "- one\n- two\n- three"),
+        ("- outer\n  * inner", "- outer\n  - inner"),
+        ("1. first\n2) second", "1. first\n2. second"),
+        ("- [ ] todo\n- [x] done", "- todo\n- done"),
+        ("above\n\n---\n\nbelow", "above\n\n\nbelow"),
+        ("Title\n=====\nbody", "Title\nbody"),
+        ("text\n\n[docs]: https://example.com", "text\n"),
+        (
+            "```rust\nlet **x** = [a](b);\n    indented\n```\nafter",
+            "let **x** = [a](b);\n    indented\nafter",
+        ),
+        ("~~~~\n```\nstill code\n~~~~", "```\nstill code"),
+        (
+            "| Name | Role |\n|:-----|-----:|\n| **Ada** | [Eng](x) |\n| Bob \\| Jr | PM |\n\nafter",
+            "Name, Role\nAda, Eng\nBob | Jr, PM\n\nafter",
+        ),
+        ("Name | Age\n--- | ---\nAda | 36", "Name, Age\nAda, 36"),
+        ("a | b without delimiter row", "a | b without delimiter row"),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(strip_markdown(input), expected, "input: {input:?}");
+    }
+}
+
+#[test]
+fn test_normalize_whitespace_and_ascii_quotes() {
+    assert_eq!(
+        normalize_whitespace("\n\n  a \t b\u{a0}c  \n\n\n\nd\n\n"),
+        "a b c\n\nd"
+    );
+    assert_eq!(
+        ascii_quotes("\u{201c}It\u{2019}s\u{201d} \u{2018}x\u{2019} 5\u{2032}"),
+        "\"It's\" 'x' 5'"
+    );
+
+    let response: GenerationResponse = serde_json::from_value(json!({
+        "candidates": [{"content": {"role": "model", "parts": [{
+            "text": "## Answer\n\nIt\u{2019}s   **done**:\n\n\n* see [docs](https://x.y)\n"
+        }]}}]
+    }))
+    .unwrap();
+    assert_eq!(response.plain_text(), "Answer\n\nIt's done:\n\n- see docs");
+}
+
+#[tokio::test]
+async fn test_create_cache_from_file_cleans_up_on_failure() {
+    let server = MockServer::start().await;
+    let upload_url = server.url().join("/upload-session").unwrap();
+    let file = |state: &str| {
+        json!({
+            "name": "files/report",
+            "uri": "https://generativelanguage.googleapis.com/v1beta/files/report",
+            "mimeType": "application/pdf",
+            "state": state
+        })
+    };
+    let upload = || {
+        server
+            .push(
+                MockResponse::json(200, json!({}))
+                    .with_header("x-goog-upload-url", upload_url.as_str()),
+            )
+            .push(MockResponse::json(200, json!({"file": file("PROCESSING")})))
+            .push(MockResponse::json(200, file("ACTIVE")));
+    };
+    upload();
+    server.push(MockResponse::json(
+        200,
+        json!({
+            "name": "cachedContents/report",
+            "model": "models/gemini-2.5-pro",
+            "createTime": "2025-06-02T08:00:00Z",
+            "updateTime": "2025-06-02T08:00:00Z",
+            "expireTime": "2025-06-02T08:10:00Z",
+            "usageMetadata": {"totalTokenCount": 40960}
+        }),
+    ));
+    upload();
+    server
+        .push(MockResponse::json(
+            400,
+            json!({"error": {"code": 400, "message": "Cached content is too small", "status": "INVALID_ARGUMENT"}}),
+        ))
+        .push(MockResponse::json(200, json!({})));
+
+    let clock = ManualClock::new();
+    let client = server.builder().with_clock(clock.clone()).build().unwrap();
+    let create = || {
+        client
+            .create_cache_from_file(b"%PDF-1.7".to_vec(), "application/pdf".parse().unwrap())
+            .with_system_instruction("Answer from the report")
+            .with_ttl(Duration::from_secs(600))
+            .with_model(Model::Gemini25Pro)
+    };
+
+    let (file, cache) = create().execute().await.unwrap();
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(2)]);
+    assert_eq!(file.get_file_meta().state, Some(FileState::Active));
+    assert_eq!(cache.name(), "cachedContents/report");
+
+    let body = server.requests()[3].json();
+    assert_eq!(body["model"], "models/gemini-2.5-pro");
+    assert_eq!(body["ttl"], "600s");
+    assert_eq!(
+        body["systemInstruction"]["parts"][0]["text"],
+        "Answer from the report"
+    );
+    assert_eq!(
+        body["contents"][0]["parts"][0]["fileData"],
+        json!({
+            "mimeType": "application/pdf",
+            "fileUri": "https://generativelanguage.googleapis.com/v1beta/files/report"
+        })
+    );
+
+    let Err(error) = create().execute().await else {
+        panic!("cache creation should fail");
+    };
+    assert!(matches!(error, CacheError::Client { .. }), "{error:?}");
+    let requests = server.requests();
+    let steps: Vec<_> = requests[4..]
+        .iter()
+        .map(|r| format!("{} {}", r.method, r.path))
+        .collect();
+    assert_eq!(
+        steps,
+        vec![
+            "POST /upload/v1beta/files",
+            "POST /upload-session",
+            "GET /v1beta/files/report",
+            "POST /v1beta/cachedContents",
+            "DELETE /v1beta/files/report",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_create_cache_from_file_streams_a_path_with_progress() {
+    let server = MockServer::start().await;
+    let upload_url = server.url().join("/upload-session").unwrap();
+    let file = json!({
+        "name": "files/report",
+        "uri": "https://generativelanguage.googleapis.com/v1beta/files/report",
+        "mimeType": "application/pdf",
+        "state": "ACTIVE"
+    });
+    server
+        .push(
+            MockResponse::json(200, json!({}))
+                .with_header("x-goog-upload-url", upload_url.as_str()),
+        )
+        .push(MockResponse::json(200, json!({"file": file})))
+        .push(MockResponse::json(
+            200,
+            json!({
+                "name": "cachedContents/report",
+                "model": "models/gemini-2.5-pro",
+                "createTime": "2025-06-02T08:00:00Z",
+                "updateTime": "2025-06-02T08:00:00Z",
+                "expireTime": "2025-06-02T08:10:00Z",
+                "usageMetadata": {"totalTokenCount": 40960}
+            }),
+        ));
+
+    let contents: Vec<u8> = (0..600 * 1024).map(|i| (i % 251) as u8).collect();
+    let path = spill_dir("cache_from_path").join("report.pdf");
+    std::fs::write(&path, &contents).unwrap();
+    let reported = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let (file, _cache) = server
+        .client()
+        .caches()
+        .create_from_file(path, "application/pdf".parse().unwrap())
+        .with_progress({
+            let reported = reported.clone();
+            move |sent, size| reported.lock().unwrap().push((sent, size))
+        })
+        .with_ttl(Duration::from_secs(600))
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(file.name(), "files/report");
+
+    let requests = server.requests();
+    assert_eq!(requests[0].json()["file"]["displayName"], "report.pdf");
+    assert_eq!(
+        requests[0].header("x-goog-upload-content-length"),
+        Some("614400")
+    );
+    assert_eq!(requests[1].body, contents);
+    let size = contents.len() as u64;
+    assert_eq!(
+        *reported.lock().unwrap(),
+        vec![(262144, size), (524288, size), (size, size)]
+    );
+}
+
+/// Scripts one generate, file upload, file get, and embedding call, returning the
+/// `x-goog-api-key` and `authorization` headers of each of the five requests
+async fn auth_headers(
+    builder: GeminiBuilder,
+    server: &MockServer,
+) -> Vec<(Option<String>, Option<String>)> {
+    let upload_url = server.url().join("/upload-session").unwrap();
+    let file = json!({"name": "files/a", "state": "ACTIVE"});
+    server
+        .push(model_parts(json!([{"text": "hi"}]), "STOP"))
+        .push(
+            MockResponse::json(200, json!({}))
+                .with_header("x-goog-upload-url", upload_url.as_str()),
+        )
+        .push(MockResponse::json(200, json!({"file": file.clone()})))
+        .push(MockResponse::json(200, file))
+        .push(MockResponse::json(
+            200,
+            json!({"embedding": {"values": [0.5]}}),
+        ));
+
+    let start = server.requests().len();
+    let client = builder.build().unwrap();
+    client
+        .generate_content()
+        .with_user_message("hi")
+        .execute()
+        .await
+        .unwrap();
+    client.create_file(b"data".to_vec()).upload().await.unwrap();
+    client.get_file("files/a").await.unwrap();
+    client
+        .embed_content()
+        .with_text("hi")
+        .execute()
+        .await
+        .unwrap();
+
+    server.requests()[start..]
+        .iter()
+        .map(|r| {
+            (
+                r.header("x-goog-api-key").map(str::to_string),
+                r.header("authorization").map(str::to_string),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_auth_modes_apply_to_every_endpoint() {
+    struct CountingProvider(AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl TokenProvider for CountingProvider {
+        async fn token(&self) -> Result<String, crate::auth::TokenError> {
+            Ok(format!("token-{}", self.0.fetch_add(1, Ordering::SeqCst)))
+        }
+    }
+
+    let server = MockServer::start().await;
+
+    let headers = auth_headers(server.builder(), &server).await;
+    assert_eq!(headers, vec![(Some("test-key".to_string()), None); 5]);
+
+    let bearer = server
+        .builder()
+        .with_auth(Auth::bearer(CountingProvider(AtomicUsize::new(0))));
+    let headers = auth_headers(bearer, &server).await;
+    let expected: Vec<_> = (0..5)
+        .map(|i| (None, Some(format!("Bearer token-{i}"))))
+        .collect();
+    assert_eq!(headers, expected, "a token is fetched for every request");
+
+    let none = Gemini::builder().no_auth().with_base_url(server.url());
+    let headers = auth_headers(none, &server).await;
+    assert_eq!(headers, vec![(None, None); 5]);
+}
+
+#[test]
+fn test_no_auth_refuses_default_endpoint() {
+    let error = Gemini::builder().no_auth().build().err().unwrap();
+    assert!(matches!(
+        error,
+        ClientError::UnauthenticatedDefaultEndpoint { .. }
+    ));
+    assert!(error
+        .to_string()
+        .contains("generativelanguage.googleapis.com"));
+
+    assert!(Gemini::builder()
+        .no_auth()
+        .allow_unauthenticated_default_endpoint()
+        .build()
+        .is_ok());
+    assert!(Gemini::builder()
+        .with_auth(Auth::ApiKey("key".to_string()))
+        .build()
+        .is_ok());
+}
+
+#[test]
+fn test_safety_scores_from_both_backends() {
+    let fixture = |name: &str| -> GenerationResponse {
+        let path = format!(
+            "{}/tests/fixtures/generate_content/{name}.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    };
+
+    let vertex = fixture("vertex_safety_scores");
+    let ratings = vertex.candidates[0].safety_ratings.as_ref().unwrap();
+    assert_eq!(ratings[1].severity, Some(HarmSeverity::HarmSeverityLow));
+    assert_eq!(ratings[1].severity_score, Some(0.218));
+    assert_eq!(vertex.candidates[0].max_safety_score(), Some(0.274));
+    assert_eq!(vertex.candidates_above_safety_score(0.2).len(), 1);
+    assert!(vertex.candidates_above_safety_score(0.3).is_empty());
+
+    // The consumer API only returns the categorical probability
+    let consumer = fixture("candidate_safety_block");
+    let ratings = consumer.candidates[0].safety_ratings.as_ref().unwrap();
+    assert!(ratings
+        .iter()
+        .all(|r| r.probability_score.is_none() && r.severity.is_none()));
+    assert_eq!(consumer.candidates[0].max_safety_score(), None);
+    assert!(consumer.candidates_above_safety_score(0.0).is_empty());
+}
+
+#[tokio::test]
+async fn test_blocked_prompts_fail_without_retrying() {
+    use crate::BlockReason;
+
+    let cases = [
+        ("prompt_blocked", BlockReason::Safety, "safety settings"),
+        (
+            "prompt_blocked_blocklist",
+            BlockReason::Blocklist,
+            "blocklist",
+        ),
+        (
+            "prompt_blocked_prohibited_content",
+            BlockReason::ProhibitedContent,
+            "prohibited",
+        ),
+        (
+            "prompt_blocked_image_safety",
+            BlockReason::ImageSafety,
+            "image",
+        ),
+        ("prompt_blocked_other", BlockReason::Other, "did not say"),
+        (
+            "prompt_blocked_unknown_reason",
+            BlockReason::Unknown("JAILBREAK_ATTEMPT".to_string()),
+            "not known",
+        ),
+    ];
+    for (name, expected, guidance) in cases {
+        let path = format!(
+            "{}/tests/fixtures/generate_content/{name}.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let body = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let server = MockServer::start().await;
+        server.push(MockResponse::json(200, body));
+        let client = server
+            .builder()
+            .with_retry_policy(RetryPolicy::new(3))
+            .build()
+            .unwrap();
+
+        let error = client
+            .generate_content()
+            .with_user_message("hi")
+            .execute()
+            .await
+            .unwrap_err();
+        let ClientError::PromptBlocked { reason, .. } = &error else {
+            panic!("{name}: expected a blocked prompt, got {error:?}");
+        };
+        assert_eq!(*reason, expected, "{name}");
+        assert!(error.to_string().contains(guidance), "{name}: {error}");
+        assert!(!error.is_retryable(), "{name}");
+        assert_eq!(server.requests().len(), 1, "{name}");
+    }
+
+    // Unknown reasons are named as sent, and written back out unchanged
+    let reason: BlockReason = serde_json::from_value(json!("NEW_REASON")).unwrap();
+    assert_eq!(reason.to_string(), "NEW_REASON");
+    assert_eq!(serde_json::to_value(&reason).unwrap(), json!("NEW_REASON"));
+}
+
+#[tokio::test]
+async fn test_health_check_classifies_outcomes() {
+    use crate::HealthStatus;
+
+    let invalid_key = json!({"error": {
+        "code": 400,
+        "message": "API key not valid. Please pass a valid API key.",
+        "status": "INVALID_ARGUMENT",
+        "details": [{
+            "@type": "type.googleapis.com/google.rpc.ErrorInfo",
+            "reason": "API_KEY_INVALID",
+        }],
+    }});
+    let cases = [
+        (
+            MockResponse::json(200, json!({"name": "models/x"})),
+            "healthy",
+        ),
+        (MockResponse::json(403, json!({})), "auth"),
+        (MockResponse::json(400, invalid_key), "auth"),
+        (MockResponse::json(429, json!({})), "rate limited"),
+        (MockResponse::json(503, json!({})), "unreachable"),
+    ];
+    for (response, expected) in cases {
+        let server = MockServer::start().await;
+        server.push(response);
+        let client = server
+            .builder()
+            .with_model("models/x".to_string())
+            .with_retry_policy(RetryPolicy::new(3))
+            .build()
+            .unwrap();
+
+        let report = client.health_check().await;
+        let classified = match report.status {
+            HealthStatus::Healthy => "healthy",
+            HealthStatus::AuthError { .. } => "auth",
+            HealthStatus::RateLimited => "rate limited",
+            HealthStatus::Unreachable { .. } => "unreachable",
+        };
+        assert_eq!(classified, expected);
+        // Never retried, and never generates content
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1, "{expected}");
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/v1beta/models/x");
+    }
+}
+
+#[tokio::test]
+async fn test_health_check_is_bounded_by_its_timeout() {
+    // Accepts connections but never answers
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            held.push(stream);
+        }
+    });
+    let client = GeminiBuilder::new("test-key")
+        .with_base_url(format!("http://{addr}/v1beta/").parse().unwrap())
+        .build()
+        .unwrap();
+
+    let report = client
+        .health_check_with_timeout(Duration::from_millis(200))
+        .await;
+    let crate::HealthStatus::Unreachable { source } = &report.status else {
+        panic!("expected an unreachable API, got {:?}", report.status);
+    };
+    assert!(!report.is_healthy());
+    assert!(matches!(source, ClientError::PerformRequestNew { source } if source.is_timeout()));
+    assert!(report.latency >= Duration::from_millis(200));
+    assert!(
+        report.latency < Duration::from_secs(2),
+        "{:?}",
+        report.latency
+    );
+}
+
+/// A mock server answering once with `response` after `delay`
+async fn delayed_server(response: MockResponse, delay: u64) -> MockServer {
+    let server = MockServer::start().await;
+    server.push(response.with_chunk_delay(Duration::from_millis(delay)));
+    server
+}
+
+fn answer(text: &str, total_tokens: i32) -> MockResponse {
+    MockResponse::json(
+        200,
+        json!({
+            "candidates": [{"content": {"role": "model", "parts": [{"text": text}]}, "finishReason": "STOP"}],
+            "usageMetadata": {"promptTokenCount": 4, "totalTokenCount": total_tokens}
+        }),
+    )
+}
+
+#[tokio::test]
+async fn test_race_returns_first_acceptable_and_cancels_the_rest() {
+    let cheap = delayed_server(answer("cheap answer", 10), 50).await;
+    let expensive = delayed_server(answer("expensive answer", 90), 3000).await;
+
+    let started = std::time::Instant::now();
+    let result = cheap
+        .client()
+        .race(
+            [
+                cheap.client().generate_content().with_user_message("q"),
+                expensive.client().generate_content().with_user_message("q"),
+            ],
+            |r| !r.text().is_empty(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(result.winner, 0);
+    assert_eq!(result.response.text(), "cheap answer");
+    assert!(matches!(result.attempts[0].outcome, RaceOutcome::Won));
+    assert!(matches!(result.attempts[1].outcome, RaceOutcome::Cancelled));
+    assert!(result.attempts[1].elapsed < Duration::from_millis(1000));
+    // The slow request was not awaited
+    assert!(started.elapsed() < Duration::from_millis(1000));
+    assert_eq!(expensive.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_race_skips_failed_and_rejected_responses() {
+    let failing = delayed_server(
+        MockResponse::json(
+            400,
+            json!({"error": {"code": 400, "message": "bad", "status": "INVALID_ARGUMENT"}}),
+        ),
+        10,
+    )
+    .await;
+    let rejected = delayed_server(answer("", 25), 50).await;
+    let good = delayed_server(answer("good answer", 60), 150).await;
+
+    let client = good.client();
+    let requests = || {
+        [&failing, &rejected, &good]
+            .map(|server| server.client().generate_content().with_user_message("q"))
+    };
+    let result = client
+        .race(requests(), |r| !r.text().is_empty())
+        .await
+        .unwrap();
+
+    assert_eq!(result.winner, 2);
+    assert!(matches!(
+        result.attempts[0].outcome,
+        RaceOutcome::Failed { .. }
+    ));
+    assert!(matches!(
+        &result.attempts[1].outcome,
+        RaceOutcome::Rejected { usage_metadata: Some(usage) } if usage.total_token_count == Some(25)
+    ));
+    assert!(result.attempts[0].elapsed <= result.attempts[1].elapsed);
+    assert!(result.attempts[1].elapsed <= result.attempts[2].elapsed);
+    // The rejected response's cost is still accounted for
+    let tokens: Vec<_> = result.usage().map(|u| u.total_token_count).collect();
+    assert_eq!(tokens, [Some(60), Some(25)]);
+
+    // Nothing acceptable
+    for server in [&failing, &rejected, &good] {
+        server.push(answer("", 5));
+    }
+    let error = client.race(requests(), |_| false).await.unwrap_err();
+    let ClientError::NoAcceptableResponse { attempts } = &error else {
+        panic!("unexpected error: {error}");
+    };
+    assert_eq!(attempts.len(), 3);
+    assert!(error.to_string().contains("none of the 3 raced request(s)"));
+}
+
+#[tokio::test]
+async fn test_race_records_every_received_response_in_the_ledger() {
+    let failing = delayed_server(
+        MockResponse::json(
+            400,
+            json!({"error": {"code": 400, "message": "bad", "status": "INVALID_ARGUMENT"}}),
+        ),
+        10,
+    )
+    .await;
+    let rejected = delayed_server(answer("", 25), 10).await;
+    let good = delayed_server(answer("good answer", 60), 100).await;
+    let slow = delayed_server(answer("slow answer", 90), 3000).await;
+
+    let client = good.client();
+    let requests = [&failing, &rejected, &good, &slow]
+        .map(|server| server.client().generate_content().with_user_message("q"));
+    let result = client
+        .race(requests, |r| !r.text().is_empty())
+        .await
+        .unwrap();
+    assert_eq!(result.winner, 2);
+
+    // The winner and the rejected response, but not the failed or cancelled requests
+    let entry = client
+        .usage_ledger()
+        .entry(crate::generation::race::LEDGER_TAG)
+        .unwrap();
+    assert_eq!(entry.requests, 2);
+    assert_eq!(entry.usage.total_token_count, Some(85));
+    assert_eq!(entry.usage.prompt_token_count, Some(8));
+    assert_eq!(client.usage_ledger().entries().len(), 1);
+}
+
+/// OpenAI-shaped requests that convert to Gemini and back without loss
+#[cfg(feature = "openai-compat")]
+fn openai_request_corpus() -> Vec<serde_json::Value> {
+    vec![
+        json!({
+            "messages": [{"role": "user", "content": "Hello"}],
+        }),
+        json!({
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "system", "content": "Answer in French."},
+                {"role": "user", "content": "What is the capital of Italy?"},
+                {"role": "assistant", "content": "Rome."},
+                {"role": "user", "content": "And of Spain?"},
+            ],
+            "temperature": 0.7,
+            "top_p": 0.95,
+            "max_tokens": 256,
+            "n": 2,
+            "seed": 42,
+            "stop": ["\n\n", "END"],
+        }),
+        json!({
+            "messages": [
+                {"role": "user", "content": "Weather in Paris and Rome?"},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_a", "type": "function",
+                     "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}},
+                    {"id": "call_b", "type": "function",
+                     "function": {"name": "get_weather", "arguments": "{\"city\":\"Rome\"}"}},
+                ]},
+                {"role": "tool", "tool_call_id": "call_a", "content": "{\"temperature\":21}"},
+                {"role": "tool", "tool_call_id": "call_b", "content": "sunny"},
+                {"role": "assistant", "content": "Paris is 21°C, Rome is sunny."},
+            ],
+            "tools": [{"type": "function", "function": {
+                "name": "get_weather",
+                "description": "Returns the weather of a city",
+                "parameters": {"type": "object", "properties": {"city": {"type": "string"}}, "required": ["city"]},
+            }}],
+            "tool_choice": "required",
+        }),
+        json!({
+            "messages": [{"role": "user", "content": [
+                {"type": "text", "text": "Describe these."},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}},
+                {"type": "image_url", "image_url": {"url": "https://example.com/cat.jpg"}},
+                {"type": "input_audio", "input_audio": {"data": "UklGRg==", "format": "wav"}},
+            ]}],
+            "response_format": {"type": "json_object"},
+            "tool_choice": "none",
+        }),
+    ]
+}
+
+#[cfg(feature = "openai-compat")]
+#[test]
+fn test_openai_request_roundtrip() {
+    use crate::compat::openai::{from_chat_completion_request, to_chat_completion_request};
+
+    for request in openai_request_corpus() {
+        let gemini = from_chat_completion_request(request.clone()).unwrap();
+        assert_eq!(gemini.warnings, [], "{request}");
+        let openai = to_chat_completion_request(&gemini.value);
+        assert_eq!(openai.warnings, [], "{request}");
+        assert_eq!(openai.value, request);
+    }
+
+    // Mapped onto the Gemini shape
+    let request = from_chat_completion_request(openai_request_corpus().swap_remove(2))
+        .unwrap()
+        .value;
+    let wire = serde_json::to_value(&request).unwrap();
+    assert_eq!(wire["contents"][1]["role"], "model");
+    assert_eq!(
+        wire["contents"][1]["parts"][1]["functionCall"]["id"],
+        "call_b"
+    );
+    assert_eq!(
+        wire["contents"][2]["parts"],
+        json!([
+            {"functionResponse": {"name": "get_weather", "response": {"temperature": 21}, "id": "call_a"}},
+            {"functionResponse": {"name": "get_weather", "response": {"content": "sunny"}, "id": "call_b"}},
+        ])
+    );
+    assert_eq!(wire["toolConfig"]["function_calling_config"]["mode"], "ANY");
+}
+
+#[cfg(feature = "openai-compat")]
+#[test]
+fn test_openai_request_reports_lossy_conversions() {
+    use crate::compat::openai::{
+        from_chat_completion_request, to_chat_completion_request, Error, Warning,
+    };
+
+    let request = json!({
+        "model": "gpt-4o",
+        "messages": [
+            {"role": "developer", "content": "Be brief."},
+            {"role": "user", "name": "alice", "content": [
+                {"type": "text", "text": "Hi"},
+                {"type": "image_url", "image_url": {"url": "https://example.com/a.png", "detail": "high"}},
+                {"type": "file", "file": {"file_id": "file-1"}},
+            ]},
+            {"role": "system", "content": "Late instruction."},
+        ],
+        "presence_penalty": 0.5,
+        "frequency_penalty": 0,
+        "logprobs": false,
+        "stream": true,
+        "tool_choice": {"type": "function", "function": {"name": "get_weather"}},
+        "response_format": {"type": "json_schema", "json_schema": {
+            "name": "answer", "strict": true, "schema": {"type": "object"},
+        }},
+    });
+    let converted = from_chat_completion_request(request).unwrap();
+    let paths: Vec<_> = converted.warnings.iter().map(Warning::path).collect();
+    assert_eq!(
+        paths,
+        [
+            "messages[0].role",
+            "messages[1].content[1].image_url.detail",
+            "messages[1].content[2]",
+            "messages[1].name",
+            "messages[2]",
+            "model",
+            "presence_penalty",
+            "response_format.json_schema.name",
+            "response_format.json_schema.strict",
+            "tool_choice",
+        ]
+    );
+    assert!(matches!(
+        &converted.warnings[0],
+        Warning::Approximated { detail, .. } if detail == "converted to a system instruction"
+    ));
+    assert_eq!(
+        converted.warnings[2].to_string(),
+        "dropped `messages[1].content[2]`"
+    );
+    let system = converted.value.system_instruction.as_ref().unwrap();
+    assert_eq!(system.parts.as_ref().unwrap().len(), 2);
+
+    // Gemini-only settings are reported the other way around
+    let mut gemini = converted.value;
+    gemini.generation_config.as_mut().unwrap().top_k = Some(40);
+    gemini.cached_content = Some("cachedContents/abc".into());
+    let converted = to_chat_completion_request(&gemini);
+    let paths: Vec<_> = converted.warnings.iter().map(Warning::path).collect();
+    assert_eq!(paths, ["generationConfig.topK", "cachedContent"]);
+    assert_eq!(
+        converted.value["response_format"],
+        json!({"type": "json_schema", "json_schema": {"name": "response", "schema": {"type": "object"}}})
+    );
+
+    // Malformed input is an error rather than a warning
+    let error = from_chat_completion_request(json!({"messages": [
+        {"role": "tool", "tool_call_id": "call_x", "content": "42"},
+    ]}))
+    .unwrap_err();
+    assert!(matches!(error, Error::UnknownToolCall { .. }));
+    let error = from_chat_completion_request(json!({"messages": [{"role": "bot"}]})).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "`messages[0].role` must be one of `system`, `developer`, `user`, `assistant` or `tool`"
+    );
+}
+
+#[cfg(feature = "openai-compat")]
+#[test]
+fn test_openai_response_roundtrip() {
+    use crate::compat::openai::{
+        from_chat_completion_response, to_chat_completion_response, Warning,
+    };
+
+    let responses = [
+        json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "model": "gemini-2.5-flash",
+            "choices": [
+                {"index": 0, "message": {"role": "assistant", "content": "Hello!"}, "finish_reason": "stop"},
+                {"index": 1, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "length"},
+            ],
+            "usage": {
+                "prompt_tokens": 10, "completion_tokens": 25, "total_tokens": 35,
+                "completion_tokens_details": {"reasoning_tokens": 20},
+                "prompt_tokens_details": {"cached_tokens": 4},
+            },
+        }),
+        json!({
+            "object": "chat.completion",
+            "choices": [{"index": 0, "finish_reason": "tool_calls", "message": {
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{"id": "call_1", "type": "function",
+                    "function": {"name": "lookup", "arguments": "{\"query\":\"rust\"}"}}],
+            }}],
+        }),
+        json!({
+            "object": "chat.completion",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": null}, "finish_reason": "content_filter"}],
+        }),
+    ];
+    for response in responses {
+        let gemini = from_chat_completion_response(response.clone()).unwrap();
+        assert_eq!(gemini.warnings, [], "{response}");
+        let openai = to_chat_completion_response(&gemini.value);
+        assert_eq!(openai.warnings, [], "{response}");
+        assert_eq!(openai.value, response);
+    }
+
+    // Token counts: OpenAI includes reasoning in the completion
+    let gemini = from_chat_completion_response(json!({
+        "created": 1_700_000_000,
+        "system_fingerprint": null,
+        "choices": [{"index": 0, "message": {"role": "assistant", "content": "ok", "refusal": null, "annotations": []},
+                     "logprobs": {"content": []}, "finish_reason": "stop"}],
+        "usage": {"prompt_tokens": 3, "completion_tokens": 7, "total_tokens": 10,
+                  "completion_tokens_details": {"reasoning_tokens": 5, "audio_tokens": 0}},
+    }))
+    .unwrap();
+    let usage = gemini.value.usage_metadata.unwrap();
+    assert_eq!(usage.candidates_token_count, Some(2));
+    assert_eq!(usage.thoughts_token_count, Some(5));
+    assert_eq!(
+        gemini.warnings,
+        [Warning::Dropped {
+            path: "created".into()
+        }]
+    );
+}
+
+#[cfg(feature = "openai-compat")]
+#[test]
+fn test_gemini_response_to_openai() {
+    use crate::compat::openai::{to_chat_completion_response, Warning};
+
+    let response: GenerationResponse = serde_json::from_value(json!({
+        "candidates": [{
+            "content": {"role": "model", "parts": [
+                {"text": "Considering the question...", "thought": true},
+                {"text": "The answer"},
+                {"text": " is 42."},
+                {"functionCall": {"name": "record", "args": {"value": 42}}},
+            ]},
+            "finishReason": "STOP",
+            "safetyRatings": [{"category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE"}],
+        }, {
+            "content": {"role": "model", "parts": [{"text": "I can't"}]},
+            "finishReason": "RECITATION",
+            "index": 1,
+        }],
+        "usageMetadata": {"promptTokenCount": 5, "candidatesTokenCount": 9, "totalTokenCount": 14,
+                          "toolUsePromptTokenCount": 2},
+        "responseId": "abc",
+    }))
+    .unwrap();
+    let converted = to_chat_completion_response(&response);
+    assert_eq!(
+        converted.value,
+        json!({
+            "id": "abc",
+            "object": "chat.completion",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "The answer is 42.", "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": {"name": "record", "arguments": "{\"value\":42}"},
+                }]},
+                "finish_reason": "tool_calls",
+            }, {
+                "index": 1,
+                "message": {"role": "assistant", "content": "I can't"},
+                "finish_reason": "content_filter",
+            }],
+            "usage": {"prompt_tokens": 5, "completion_tokens": 9, "total_tokens": 14},
+        })
+    );
+    assert_eq!(
+        converted.warnings,
+        [
+            Warning::Dropped {
+                path: "candidates[0].content.parts[0]".into()
+            },
+            Warning::Dropped {
+                path: "candidates[0].safetyRatings".into()
+            },
+            Warning::Approximated {
+                path: "candidates[1].finishReason".into(),
+                detail: "Recitation reported as `content_filter`".into()
+            },
+            Warning::Dropped {
+                path: "usageMetadata.toolUsePromptTokenCount".into()
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_text_chunker_ranges_are_byte_accurate() {
+    let texts = [
+        "The quick brown fox jumps over the lazy dog. ".repeat(40),
+        "東京は日本の首都です。大阪は商業の中心地です。京都には多くの寺があります。".repeat(12),
+        "Ünïcödé façade — naïve café «quotes» ½ ∑ ∞. ".repeat(30),
+        "👩‍👩‍👧‍👦 family 🇫🇷 flag 🎉🎉 emoji! ".repeat(25),
+        "مرحبا بالعالم. هذا نص عربي للاختبار.\n\nПривет, мир! Это тест.\n\n".repeat(15),
+        "a".repeat(1000),
+        "   \n\t  ".to_string(),
+        String::new(),
+    ];
+    for text in &texts {
+        for (max_tokens, overlap_tokens) in [(1, 0), (7, 3), (32, 8), (100, 0), (512, 64)] {
+            let config = ChunkingConfig {
+                max_tokens,
+                overlap_tokens,
+            };
+            let chunks = TextChunker::new(config).unwrap().chunks(text);
+            assert_eq!(chunks.is_empty(), text.trim().is_empty());
+            for range in &chunks {
+                // Slicing panics if the range is not on character boundaries
+                let chunk = &text[range.clone()];
+                assert!(!chunk.is_empty() && chunk.trim() == chunk, "{chunk:?}");
+                assert!(estimate_tokens(chunk) <= max_tokens, "{chunk:?}");
+            }
+            for pair in chunks.windows(2) {
+                assert!(pair[0].start < pair[1].start);
+                // Overlapping chunks, or nothing but whitespace between them
+                assert!(
+                    pair[1].start <= pair[0].end
+                        || text[pair[0].end..pair[1].start].trim().is_empty()
+                );
+            }
+            if let (Some(first), Some(last)) = (chunks.first(), chunks.last()) {
+                assert_eq!(&text[first.start..last.end], text.trim());
+            }
+        }
+    }
+
+    // Chunks prefer to end at sentence boundaries
+    let text = "One two three four five. Six seven eight nine ten. Eleven twelve.";
+    let chunks = TextChunker::new(ChunkingConfig {
+        max_tokens: 10,
+        overlap_tokens: 0,
+    })
+    .unwrap()
+    .chunks(text);
+    let chunks: Vec<_> = chunks.into_iter().map(|range| &text[range]).collect();
+    assert_eq!(
+        chunks,
+        [
+            "One two three four five.",
+            "Six seven eight nine ten.",
+            "Eleven twelve."
+        ]
+    );
+}
+
+#[test]
+fn test_invalid_chunking_configs_are_errors() {
+    let config = |max_tokens, overlap_tokens| ChunkingConfig {
+        max_tokens,
+        overlap_tokens,
+    };
+    assert_eq!(
+        TextChunker::new(config(0, 0)).unwrap_err(),
+        ChunkingError::ZeroMaxTokens
+    );
+    assert_eq!(
+        TextChunker::new(config(8, 8)).unwrap_err(),
+        ChunkingError::OverlapTooLarge {
+            overlap_tokens: 8,
+            max_tokens: 8
+        }
+    );
+
+    let client = Gemini::new("key").unwrap();
+    let Err(error) = client.embed_document("text", config(4, 10)) else {
+        panic!("expected an invalid chunking config");
+    };
+    assert!(
+        matches!(
+            &error,
+            ClientError::InvalidChunking {
+                source: ChunkingError::OverlapTooLarge { .. }
+            }
+        ),
+        "{error:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_embed_document_keeps_successful_chunks() {
+    let embeddings = |n: usize, first: f32| {
+        let embeddings: Vec<_> = (0..n)
+            .map(|i| json!({"values": [first + i as f32, 0.5]}))
+            .collect();
+        MockResponse::json(200, json!({ "embeddings": embeddings }))
+    };
+    let bad_request =
+        || MockResponse::json(400, json!({"error": {"code": 400, "message": "bad chunk"}}));
+    let server = MockServer::start().await;
+    server
+        .push(embeddings(2, 0.0))
+        // The second batch fails, then its chunks are embedded on their own
+        .push(bad_request())
+        .push(MockResponse::json(
+            200,
+            json!({"embedding": {"values": [2.0, 0.5]}}),
+        ))
+        .push(bad_request())
+        .push(embeddings(1, 4.0));
+
+    let text =
+        "Ça va? Très bien. Ünïcödé façade über naïve. 東京は日本の首都です。 Ελληνικά κείμενο εδώ.";
+    let config = ChunkingConfig {
+        max_tokens: 14,
+        overlap_tokens: 0,
+    };
+    let ranges = TextChunker::new(config).unwrap().chunks(text);
+    assert_eq!(ranges.len(), 5);
+
+    let document = server
+        .client()
+        .embed_document(text, config)
+        .unwrap()
+        .with_batch_size(2)
+        .execute()
+        .await;
+
+    assert!(!document.is_complete());
+    let embedded: Vec<_> = document.chunks.iter().map(|c| c.range.clone()).collect();
+    assert_eq!(embedded, [0, 1, 2, 4].map(|i| ranges[i].clone()));
+    for chunk in &document.chunks {
+        assert_eq!(&text[chunk.range.clone()], chunk.text);
+    }
+    let vectors: Vec<_> = document.chunks.iter().map(|c| c.vector[0]).collect();
+    assert_eq!(vectors, [0.0, 1.0, 2.0, 4.0]);
+    assert_eq!(document.failed.len(), 1);
+    assert_eq!(document.failed[0].range, ranges[3]);
+
+    let requests = server.requests();
+    assert!(requests[0].path.ends_with(":batchEmbedContents"));
+    assert!(requests[2].path.ends_with(":embedContent"));
+    let body = requests[0].json();
+    assert_eq!(body["requests"].as_array().unwrap().len(), 2);
+    assert_eq!(
+        body["requests"][1]["content"]["parts"][0]["text"],
+        &text[ranges[1].clone()]
+    );
+    assert_eq!(body["requests"][0]["taskType"], "RETRIEVAL_DOCUMENT");
+}
+
+#[tokio::test]
+async fn test_system_instruction_only_cache() {
+    let server = MockServer::start().await;
+    server
+        .push(MockResponse::json(
+            200,
+            json!({
+                "name": "cachedContents/manual",
+                "model": "models/gemini-2.5-flash",
+                "createTime": "2025-06-02T08:00:00Z",
+                "updateTime": "2025-06-02T08:00:00Z",
+                "expireTime": "2025-06-02T09:00:00Z",
+                "usageMetadata": {"totalTokenCount": 32768}
+            }),
+        ))
+        .push(answer("Restart the router.", 40))
+        .push(MockResponse::json(
+            200,
+            json!({"totalTokens": 32780, "cachedContentTokenCount": 32768}),
+        ));
+    let client = server.client();
+
+    let cache = client
+        .create_cache()
+        .with_system_instruction("You are the support assistant. <manual>...</manual>")
+        .with_tool(Tool::new(FunctionDeclaration::new(
+            "open_ticket",
+            "Opens a support ticket",
+            None,
+        )))
+        .with_ttl(Duration::from_secs(3600))
+        .execute()
+        .await
+        .unwrap();
+
+    let body = server.requests()[0].json();
+    assert!(body.get("contents").is_none());
+    assert!(body["systemInstruction"]["parts"][0]["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("You are the support assistant."));
+    assert_eq!(
+        body["tools"][0]["function_declarations"][0]["name"],
+        "open_ticket"
+    );
+
+    // Only the user's contents are sent along with the reference
+    let request = || {
+        client
+            .generate_content()
+            .with_cached_content(&cache)
+            .with_user_message("My internet is down")
+    };
+    request().execute().await.unwrap();
+    let body = server.requests()[1].json();
+    assert_eq!(body["cachedContent"], "cachedContents/manual");
+    assert_eq!(
+        body["contents"][0]["parts"][0]["text"],
+        "My internet is down"
+    );
+    for field in ["systemInstruction", "tools", "toolConfig"] {
+        assert!(body.get(field).is_none(), "{field}");
+    }
+
+    // Counting tokens sends the same shape and separates the cached part
+    let count = request().count_tokens().await.unwrap();
+    assert_eq!(count.uncached_tokens(), 12);
+    let counted = &server.requests()[2];
+    assert!(counted.path.ends_with(":countTokens"));
+    let body = counted.json();
+    assert_eq!(
+        body["generateContentRequest"]["cachedContent"],
+        "cachedContents/manual"
+    );
+    assert_eq!(
+        body["generateContentRequest"]["model"],
+        "models/gemini-2.5-flash"
+    );
+    assert!(body["generateContentRequest"]
+        .get("systemInstruction")
+        .is_none());
+
+    // Repeating what the cache provides is rejected before sending
+    let error = request()
+        .with_system_instruction("Be brief")
+        .with_tool(Tool::new(FunctionDeclaration::new("x", "y", None)))
+        .execute()
+        .await
+        .unwrap_err();
+    let ClientError::CachedContentConflict { fields, .. } = &error else {
+        panic!("unexpected error: {error}");
+    };
+    assert_eq!(fields, &["systemInstruction", "tools"]);
+    assert!(request()
+        .with_system_instruction("Be brief")
+        .count_tokens()
+        .await
+        .is_err());
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn test_caches_manage_the_lifecycle_of_a_cached_prefix() {
+    use futures::TryStreamExt;
+
+    let server = MockServer::start().await;
+    let cached = |expire_time: &str| {
+        MockResponse::json(
+            200,
+            json!({
+                "name": "cachedContents/report",
+                "model": "models/gemini-2.5-flash",
+                "createTime": "2025-06-02T08:00:00Z",
+                "updateTime": "2025-06-02T08:00:00Z",
+                "expireTime": expire_time,
+                "usageMetadata": {"totalTokenCount": 200000}
+            }),
+        )
+    };
+    server
+        .push(cached("2025-06-02T09:00:00Z"))
+        .push(MockResponse::json(
+            200,
+            json!({
+                "candidates": [{"content": {"role": "model", "parts": [{"text": "Growth."}]}, "finishReason": "STOP"}],
+                "usageMetadata": {
+                    "promptTokenCount": 200012,
+                    "cachedContentTokenCount": 200000,
+                    "totalTokenCount": 200015
+                }
+            }),
+        ))
+        .push(cached("2025-06-02T10:00:00Z"))
+        .push(MockResponse::json(
+            200,
+            json!({"cachedContents": [{
+                "name": "cachedContents/report",
+                "model": "models/gemini-2.5-flash",
+                "createTime": "2025-06-02T08:00:00Z",
+                "updateTime": "2025-06-02T08:00:00Z",
+                "expireTime": "2025-06-02T10:00:00Z",
+                "usageMetadata": {"totalTokenCount": 200000}
+            }]}),
+        ))
+        .push(MockResponse::json(200, json!({})));
+    let client = server.client();
+    let caches = client.caches();
+
+    let cache = caches
+        .create()
+        .with_system_instruction("You answer questions about the report.")
+        .with_user_message("<report>...</report>")
+        .with_ttl(Duration::from_secs(3600))
+        .execute()
+        .await
+        .unwrap();
+    let response = client
+        .generate_content()
+        .with_cached_content(&cache)
+        .with_user_message("What are the key findings?")
+        .execute()
+        .await
+        .unwrap();
+    let usage = response.usage_metadata.unwrap();
+    assert_eq!(usage.cached_content_token_count, Some(200000));
+
+    let updated = caches
+        .update_ttl(cache.name(), Duration::from_secs(7200))
+        .await
+        .unwrap();
+    assert_eq!(
+        updated.expiration.expire_time.unwrap().hour(),
+        10,
+        "{updated:?}"
+    );
+    let listed: Vec<_> = caches.list(None).try_collect().await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].name, "cachedContents/report");
+    caches.delete(cache.name()).await.unwrap();
+
+    let requests = server.requests();
+    let calls: Vec<_> = requests
+        .iter()
+        .map(|request| format!("{} {}", request.method, request.path))
+        .collect();
+    assert_eq!(calls[0], "POST /v1beta/cachedContents");
+    assert_eq!(requests[0].json()["ttl"], "3600s");
+    assert_eq!(requests[1].json()["cachedContent"], "cachedContents/report");
+    assert!(calls[2].starts_with("PATCH /v1beta/cachedContents/report"));
+    assert_eq!(requests[2].json(), json!({"ttl": "7200s"}));
+    assert!(calls[3].starts_with("GET /v1beta/cachedContents"));
+    assert_eq!(calls[4], "DELETE /v1beta/cachedContents/report");
+}
+
+/// Feeds `chunks` to a fresh array parser, rendering errors as `!<index>`
+fn parse_array(chunks: &[&str]) -> Vec<String> {
+    use crate::generation::items::{ArrayParser, Error};
+
+    let render = |element: Result<String, Error>| match element {
+        Ok(element) => element,
+        Err(Error::Malformed { index, .. } | Error::Truncated { index, .. }) => format!("!{index}"),
+        Err(error) => format!("!{error}"),
+    };
+    let mut parser = ArrayParser::new();
+    let mut elements: Vec<String> = chunks
+        .iter()
+        .flat_map(|chunk| parser.feed(chunk))
+        .map(render)
+        .collect();
+    elements.extend(parser.finish().map(|error| render(Err(error))));
+    elements
+}
+
+#[test]
+fn test_array_parser_strings_split_across_chunks() {
+    let text = r#"[{"a": "x}],{\"y"}, {"b": ["[", "\\"]}]"#;
+    let expected = vec![r#"{"a": "x}],{\"y"}"#, r#"{"b": ["[", "\\"]}"#];
+
+    // Every split point, including inside escapes and right after a backslash
+    for split in 0..=text.len() {
+        let (head, tail) = text.split_at(split);
+        assert_eq!(parse_array(&[head, tail]), expected, "split at {split}");
+    }
+    let chars: Vec<String> = text.chars().map(String::from).collect();
+    let chars: Vec<&str> = chars.iter().map(String::as_str).collect();
+    assert_eq!(parse_array(&chars), expected);
+
+    // Multi-byte characters and escaped quotes at the end of a string
+    assert_eq!(
+        parse_array(&["[\"日本\\\"", "\", \"é\\\\\"", "]"]),
+        vec!["\"日本\\\"\"", "\"é\\\\\""]
+    );
+}
+
+#[test]
+fn test_array_parser_values_fences_and_ends() {
+    assert_eq!(
+        parse_array(&["```json\n[1, tr", "ue, null ,\"s\", [2, [3]],{}", "]\n```"]),
+        vec!["1", "true", "null", "\"s\"", "[2, [3]]", "{}"]
+    );
+    assert!(parse_array(&["[ ]"]).is_empty());
+    assert_eq!(parse_array(&["[1, 2,]"]), vec!["1", "2"]);
+    // Text after the array is ignored
+    assert_eq!(parse_array(&["[{}] and {\"more\": 1}"]), vec!["{}"]);
+    assert_eq!(
+        parse_array(&["[{\"a\": 1}, {\"b\""]),
+        vec!["{\"a\": 1}", "!1"]
+    );
+    assert_eq!(parse_array(&["[\"open"]), vec!["!0"]);
+    assert_eq!(
+        parse_array(&["{\"not\": \"an array\"}"]),
+        vec!["!the response is not a JSON array"]
+    );
+}
+
+#[test]
+fn test_array_parser_resynchronizes_after_malformed_element() {
+    // Mismatched brackets, possibly split, skip to the next comma at the top level
+    assert_eq!(
+        parse_array(&["[{\"a\": 1]", ", {\"b\": 2}]"]),
+        vec!["!0", "{\"b\": 2}"]
+    );
+    assert_eq!(
+        parse_array(&["[{\"a\": [1}, {\"b\": 2}]"]),
+        vec!["!0", "{\"b\": 2}"]
+    );
+    // Commas inside strings and nested values are skipped over
+    assert_eq!(
+        parse_array(&["[{} x \"a,", "b\" {\"c\": [\",\"]}, 3]"]),
+        vec!["{}", "!1", "3"]
+    );
+    // Garbage after a complete element counts as an element of its own
+    assert_eq!(
+        parse_array(&["[{\"a\": 1} oops {\"b\": \"]\"}, 2]"]),
+        vec!["{\"a\": 1}", "!1", "2"]
+    );
+    // A stray closing brace at the top level
+    assert_eq!(parse_array(&["[1, }, 2]"]), vec!["1", "!1", "2"]);
+    // A malformed last element is reported even when the array ends right after it
+    assert_eq!(parse_array(&["[{]]"]), vec!["!0"]);
+}
+
+#[tokio::test]
+async fn test_execute_stream_as_items() {
+    use crate::{ItemError, ItemEvent};
+    use futures::TryStreamExt;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct City {
+        name: String,
+        population: u64,
+    }
+
+    let server = MockServer::start().await;
+    let chunk = |text: &str| json!({"candidates": [{"content": {"parts": [{"text": text}], "role": "model"}}]});
+    server.push(MockResponse::sse([
+        json!({"candidates": [{"content": {"parts": [
+            {"text": "Listing [cities]", "thought": true},
+            {"text": "[{\"name\": \"Par"}
+        ], "role": "model"}}]}),
+        chunk("is\", \"population\": 2100000}, {\"name\": \"Lyon\"}, {\"name\": \"{Nice}\", "),
+        chunk("\"population\": 340000}"),
+        json!({
+            "candidates": [{"content": {"parts": [{"text": "]"}], "role": "model"}, "finishReason": "STOP"}],
+            "usageMetadata": {"promptTokenCount": 5, "candidatesTokenCount": 30, "totalTokenCount": 35},
+            "modelVersion": "gemini-2.5-flash",
+            "responseId": "r-1"
+        }),
+    ]));
+
+    let events: Vec<ItemEvent<City>> = server
+        .client()
+        .generate_content()
+        .with_user_message("Cities as JSON")
+        .execute_stream_as_items()
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+
+    let [ItemEvent::Item(Ok(paris)), ItemEvent::Item(Err(lyon)), ItemEvent::Item(Ok(nice)), ItemEvent::Finished(summary)] =
+        &events[..]
+    else {
+        panic!("unexpected events: {events:?}");
+    };
+    assert_eq!(
+        paris,
+        &City {
+            name: "Paris".into(),
+            population: 2_100_000
+        }
+    );
+    assert!(
+        matches!(lyon, ItemError::Decode { index: 1, element, .. } if element == "{\"name\": \"Lyon\"}")
+    );
+    assert_eq!(nice.name, "{Nice}");
+    assert_eq!(summary.items, 3);
+    assert_eq!(summary.finish_reason, Some(FinishReason::Stop));
+    assert_eq!(
+        summary.usage_metadata.as_ref().unwrap().total_token_count,
+        Some(35)
+    );
+    assert_eq!(summary.response_id.as_deref(), Some("r-1"));
+}
+
+#[tokio::test]
+async fn test_file_activation_times_out_on_manual_clock() {
+    let server = MockServer::start().await;
+    let processing = json!({"name": "files/video", "state": "PROCESSING"});
+    for _ in 0..4 {
+        server.push(MockResponse::json(200, processing.clone()));
+    }
+
+    let clock = ManualClock::new();
+    let client = server.builder().with_clock(clock.clone()).build().unwrap();
+    let mut file = client.get_file("files/video").await.unwrap();
+    let error = file
+        .wait_until_active(Duration::from_secs(2), Duration::from_secs(5))
+        .await
+        .unwrap_err();
+
+    // Polled at 2s, 4s, and 6s of clock time, then gave up without waiting in real time
+    assert!(
+        matches!(error, crate::files::Error::ActivationTimeout { .. }),
+        "{error}"
+    );
+    assert_eq!(server.requests().len(), 4);
+    assert_eq!(clock.elapsed(), Duration::from_secs(6));
+
+    // Moving the clock without sleeping moves both monotonic and wall-clock time
+    let (now, now_utc) = (clock.now(), clock.now_utc());
+    clock.advance(Duration::from_secs(3600));
+    assert_eq!(clock.now() - now, Duration::from_secs(3600));
+    assert_eq!(clock.now_utc() - now_utc, time::Duration::hours(1));
+    assert_eq!(clock.sleeps().len(), 3);
+}
+
+#[tokio::test]
+async fn test_session_tree_mermaid() {
+    let server = MockServer::start().await;
+    let calls = json!([
+        {"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}},
+        {"functionCall": {"name": "get_weather", "args": {"city": "Lyon \"centre\""}}}
+    ]);
+    server
+        .push(model_parts(calls, "STOP"))
+        .push(answer(
+            "Paris is \"sunny\" and Lyon is rainy.\nBring an umbrella to Lyon.",
+            120,
+        ))
+        .push(answer("You're welcome <3 #1", 20))
+        .push(MockResponse::json(
+            200,
+            json!({
+                "candidates": [{
+                    "content": {"role": "model", "parts": [{"text": "Tomorrow brings clouds over Paris in the morning, then"}]},
+                    "finishReason": "MAX_TOKENS"
+                }],
+                "usageMetadata": {"totalTokenCount": 300}
+            }),
+        ));
+
+    let registry = ToolRegistry::new().with_function(
+        FunctionDeclaration::new("get_weather", "Current weather of a city", None),
+        |call| async move { Ok(json!({"city": call.args["city"], "sky": "clear"})) },
+    );
+    let tree = SessionTree::new();
+    let mut session = server.client().start_chat().with_tree(&tree);
+    session
+        .send_message_with_tools("What's the weather in Paris and Lyon?", &registry)
+        .await
+        .unwrap();
+    // Function calls and responses are part of the history
+    assert_eq!(session.history().len(), 4);
+
+    let mut fork = session.fork();
+    session.send_message("Thanks!").await.unwrap();
+    fork.send_message("And tomorrow?").await.unwrap();
+    assert_eq!(fork.history().len(), 6);
+    assert_eq!(session.history()[..4], fork.history()[..4]);
+
+    assert_eq!(
+        tree.to_mermaid(),
+        include_str!("../test_data/session_tree.mmd")
+    );
+    // A session on its own has no fork edge, but includes the turns it shares
+    let own = fork.to_mermaid();
+    assert!(!own.contains("fork"));
+    assert!(own.contains("s0t0 --> s0t1"), "{own}");
+}
+
+#[test]
+fn test_image_dimensions_from_headers() {
+    use crate::chat::media::image_dimensions;
+
+    let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+    png.extend(1024u32.to_be_bytes());
+    png.extend(768u32.to_be_bytes());
+    assert_eq!(image_dimensions(&png), Some((1024, 768)));
+
+    let gif = b"GIF89a\x40\x01\xf0\x00";
+    assert_eq!(image_dimensions(gif), Some((320, 240)));
+
+    // SOI, an APP0 segment of 16 bytes, then a baseline frame of 480x640 (height first)
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x10];
+    jpeg.extend([0; 14]);
+    jpeg.extend([0xff, 0xc0, 0x00, 0x11, 0x08, 0x02, 0x80, 0x01, 0xe0]);
+    assert_eq!(image_dimensions(&jpeg), Some((480, 640)));
+
+    let mut webp = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0\0\0\0\0".to_vec();
+    webp.extend([0xff, 0x07, 0x00, 0x37, 0x04, 0x00]);
+    assert_eq!(image_dimensions(&webp), Some((2048, 1080)));
+
+    assert_eq!(image_dimensions(b"not an image"), None);
+    assert_eq!(image_dimensions(&png[..20]), None);
+}
+
+#[tokio::test]
+async fn test_compress_media_only_in_requests() {
+    use crate::{Blob, FileData, MediaAction, MediaPolicy};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+    png.extend(1024u32.to_be_bytes());
+    png.extend(768u32.to_be_bytes());
+    png.resize(30_000, 0);
+    let image = Part::InlineData {
+        inline_data: Blob::new("image/png", STANDARD.encode(&png)),
+    };
+    let text = |text: &str| Part::Text {
+        text: text.to_string(),
+        thought: None,
+        thought_signature: None,
+    };
+
+    let server = MockServer::start().await;
+    for _ in 0..6 {
+        server.push(answer("noted", 10));
+    }
+    let mut session = server
+        .client()
+        .start_chat()
+        .compress_media(MediaPolicy::new(1));
+    session
+        .send_message_parts(vec![text("What is on this screenshot?"), image.clone()])
+        .await
+        .unwrap();
+    session.send_message("And in the corner?").await.unwrap();
+    session.send_message("Thanks").await.unwrap();
+
+    let requests = server.requests();
+    let sizes: Vec<usize> = requests
+        .iter()
+        .map(|r| r.json().to_string().len())
+        .collect();
+    // The previous turn's image is still sent, the one before is not
+    assert!(sizes[1] > 40_000, "{sizes:?}");
+    assert!(sizes[2] < 1_000, "{sizes:?}");
+    assert_eq!(
+        requests[2].json()["contents"][0]["parts"][1],
+        json!({"text": "[image: previously shared image/png, 1024x768]"})
+    );
+    // The history keeps the original parts
+    assert_eq!(session.history()[0].parts.as_ref().unwrap()[1], image);
+    assert_eq!(session.export().history[0], session.history()[0]);
+
+    // Dropping removes inline data, and file references are kept by default
+    let file = Part::FileData {
+        file_data: FileData::new("video/mp4", "https://example.com/files/clip"),
+        video_metadata: None,
+    };
+    let mut session = server
+        .client()
+        .start_chat()
+        .compress_media(MediaPolicy::new(0).with_inline_data(MediaAction::Drop));
+    session
+        .send_message_parts(vec![image.clone()])
+        .await
+        .unwrap();
+    session
+        .send_message_parts(vec![file.clone(), text("Same clip?")])
+        .await
+        .unwrap();
+    session.send_message("Anything else?").await.unwrap();
+    let body = server.requests()[5].json();
+    assert_eq!(
+        body["contents"][0]["parts"],
+        json!([{"text": "[image: previously shared image/png, 1024x768]"}])
+    );
+    assert_eq!(
+        body["contents"][2]["parts"][0]["fileData"]["mimeType"],
+        "video/mp4"
+    );
+}
+
+#[test]
+fn test_stitch_trims_overlap_at_seam() {
+    use crate::generation::continuation::stitch;
+
+    // No overlap
+    assert_eq!(stitch("The quick", " brown fox"), "The quick brown fox");
+    // The continuation repeats the unfinished word
+    assert_eq!(stitch("The quick bro", "brown fox"), "The quick brown fox");
+    // The continuation finishes the word; a one-letter match inside a word is no overlap
+    assert_eq!(stitch("The quick bro", "own fox"), "The quick broown fox");
+    assert_eq!(stitch("The quick bro", "wn fox"), "The quick brown fox");
+    // The continuation restarts the sentence
+    assert_eq!(
+        stitch(
+            "It rained. Then the cat sat on th",
+            "Then the cat sat on the mat."
+        ),
+        "It rained. Then the cat sat on the mat."
+    );
+    // Long overlaps are trusted even when they start inside a word
+    assert_eq!(
+        stitch("internationalization", "nationalization is long"),
+        "internationalization is long"
+    );
+    assert_eq!(stitch("banana", "nana split"), "banananana split");
+    // Repeated phrases: the longest overlap wins
+    assert_eq!(
+        stitch("ha ha ha ha", "ha ha and more"),
+        "ha ha ha ha and more"
+    );
+    assert_eq!(
+        stitch("to be or not to be", "to be, that is the question"),
+        "to be or not to be, that is the question"
+    );
+    // A single repeated word, and duplicated whitespace
+    assert_eq!(stitch("I have a", "a dog"), "I have a dog");
+    assert_eq!(stitch("End. ", " Next"), "End. Next");
+    // Multi-byte characters at the seam
+    assert_eq!(stitch("Grüße aus Mün", "München"), "Grüße aus München");
+    assert_eq!(stitch("", "text"), "text");
+    assert_eq!(stitch("text", ""), "text");
+}
+
+fn truncated(text: &str, total_tokens: i32) -> MockResponse {
+    MockResponse::json(
+        200,
+        json!({
+            "candidates": [{"content": {"role": "model", "parts": [{"text": text}]}, "finishReason": "MAX_TOKENS"}],
+            "usageMetadata": {
+                "promptTokenCount": 4,
+                "candidatesTokenCount": total_tokens - 4,
+                "totalTokenCount": total_tokens,
+                "promptTokensDetails": [{"modality": "TEXT", "tokenCount": 4}]
+            }
+        }),
+    )
+}
+
+#[tokio::test]
+async fn test_auto_continue_stitches_truncated_answers() {
+    use crate::generation::continuation::CONTINUE_PROMPT;
+
+    let server = MockServer::start().await;
+    server
+        .push(truncated("Roses are red, violets are bl", 10))
+        .push(truncated("violets are blue, sugar is", 12))
+        .push(answer(" sweet.", 14));
+    let response = server
+        .client()
+        .generate_content()
+        .with_user_message("A poem")
+        .auto_continue(3)
+        .execute()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response.text(),
+        "Roses are red, violets are blue, sugar is sweet."
+    );
+    assert_eq!(response.continuations, 2);
+    assert_eq!(
+        response.candidates[0].finish_reason,
+        Some(FinishReason::Stop)
+    );
+    let usage = response.usage_metadata.unwrap();
+    assert_eq!(usage.total_token_count, Some(36));
+    assert_eq!(usage.prompt_token_count, Some(12));
+    assert_eq!(usage.candidates_token_count, Some(14));
+    assert_eq!(usage.prompt_tokens_details.unwrap()[0].token_count, 8);
+
+    // Each follow-up carries the answer so far and the instruction to continue
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    let body = requests[2].json();
+    assert_eq!(body["contents"].as_array().unwrap().len(), 3);
+    assert_eq!(body["contents"][1]["role"], "model");
+    assert_eq!(
+        body["contents"][1]["parts"][0]["text"],
+        "Roses are red, violets are blue, sugar is"
+    );
+    assert_eq!(body["contents"][2]["parts"][0]["text"], CONTINUE_PROMPT);
+}
+
+#[tokio::test]
+async fn test_auto_continue_stops_at_cap() {
+    let server = MockServer::start().await;
+    server
+        .push(truncated("one", 5))
+        .push(truncated(" two", 5))
+        .push(answer(" three", 5));
+    let response = server
+        .client()
+        .generate_content()
+        .with_user_message("Count")
+        .auto_continue(1)
+        .execute()
+        .await
+        .unwrap();
+
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(response.text(), "one two");
+    assert_eq!(response.continuations, 1);
+    // The answer is still truncated
+    assert_eq!(
+        response.candidates[0].finish_reason,
+        Some(FinishReason::MaxTokens)
+    );
+
+    // Complete answers are returned unchanged
+    let server = MockServer::start().await;
+    server.push(answer("done", 5));
+    let response = server
+        .client()
+        .generate_content()
+        .with_user_message("Count")
+        .auto_continue(3)
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(server.requests().len(), 1);
+    assert_eq!(response.continuations, 0);
+    assert_eq!(response.text(), "done");
+}
+
+#[test]
+fn test_shared_enums_roundtrip_at_every_call_site() {
+    // Role in contents
+    let content: Content = serde_json::from_value(json!({"role": "model", "parts": []})).unwrap();
+    assert_eq!(content.role, Some(Role::Model));
+    assert_eq!(serde_json::to_value(&content).unwrap()["role"], "model");
+
+    // Modality in response modalities, in either casing, and in usage breakdowns
+    let config: crate::GenerationConfig =
+        serde_json::from_value(json!({"responseModalities": ["TEXT", "image", "HOLOGRAM"]}))
+            .unwrap();
+    assert_eq!(
+        config.response_modalities.as_deref(),
+        Some(
+            &[
+                "TEXT".to_string(),
+                "image".to_string(),
+                "HOLOGRAM".to_string()
+            ][..]
+        )
+    );
+    let modalities = config.response_modalities().unwrap();
+    assert_eq!(
+        modalities,
+        [
+            Modality::Text,
+            Modality::Image,
+            Modality::Unknown("HOLOGRAM".to_string())
+        ]
+    );
+    assert_eq!(
+        serde_json::to_value(&config).unwrap()["responseModalities"],
+        json!(["TEXT", "image", "HOLOGRAM"])
+    );
+    let details: crate::PromptTokenDetails =
+        serde_json::from_value(json!({"modality": "DOCUMENT", "tokenCount": 258})).unwrap();
+    assert_eq!(details.modality, Modality::Document);
+    assert_eq!(
+        serde_json::to_value(&details).unwrap(),
+        json!({"modality": "DOCUMENT", "tokenCount": 258})
+    );
+    let request = Gemini::new("key")
+        .unwrap()
+        .generate_content()
+        .with_response_modalities(["text", "AUDIO"])
+        .build();
+    let config = request.generation_config.unwrap();
+    assert_eq!(
+        config.response_modalities,
+        Some(vec!["TEXT".to_string(), "AUDIO".to_string()])
+    );
+    assert_eq!(
+        config.response_modalities(),
+        Some(vec![Modality::Text, Modality::Audio])
+    );
+
+    // Language in executable code
+    let part: Part = serde_json::from_value(
+        json!({"executableCode": {"language": "PYTHON", "code": "print(1)"}}),
+    )
+    .unwrap();
+    let Part::ExecutableCode { executable_code } = &part else {
+        panic!("expected executable code, got {part:?}");
+    };
+    assert_eq!(executable_code.language, Language::Python);
+    assert_eq!(
+        serde_json::to_value(&part).unwrap()["executableCode"]["language"],
+        "PYTHON"
+    );
+    assert_eq!(Language::from("python"), Language::Python);
+    assert_eq!(
+        Language::from("RUST").to_string(),
+        "RUST",
+        "unknown languages keep their name"
+    );
+}
+
+#[test]
+fn test_code_written_for_string_modalities_and_languages() {
+    // The 1.5 struct literal still compiles and reads back typed
+    let config = GenerationConfig {
+        response_modalities: Some(vec!["TEXT".to_string(), "audio".to_string()]),
+        ..Default::default()
+    };
+    assert_eq!(
+        config.response_modalities(),
+        Some(vec![Modality::Text, Modality::Audio])
+    );
+    let config =
+        GenerationConfig::default().with_response_modalities([Modality::Image, "text".into()]);
+    assert_eq!(
+        config.response_modalities,
+        Some(vec!["IMAGE".to_string(), "TEXT".to_string()])
+    );
+    let modalities = config.response_modalities().unwrap();
+    assert!(modalities[0] == "IMAGE");
+    assert!(modalities.iter().any(|modality| *modality == "TEXT"));
+    assert_eq!(String::from(modalities[1].clone()), "TEXT");
+
+    let code = ExecutableCode::new("PYTHON".to_string(), "print(1)");
+    assert_eq!(code.language, Language::Python);
+    assert!(code.language == "PYTHON");
+    assert_eq!(code.language.as_str(), "PYTHON");
+    let language: String = code.language.into();
+    assert_eq!(language, "PYTHON");
+}
+
+#[test]
+fn test_supported_response_modalities() {
+    use crate::supported_response_modalities;
+
+    assert_eq!(
+        supported_response_modalities(&Model::Gemini25Flash),
+        Some(&[Modality::Text][..])
+    );
+    // The longest matching prefix wins
+    let image = Model::Custom("models/gemini-2.5-flash-image-preview".to_string());
+    assert_eq!(
+        supported_response_modalities(&image),
+        Some(&[Modality::Text, Modality::Image][..])
+    );
+    let tts = Model::Custom("gemini-2.5-flash-preview-tts".to_string());
+    assert_eq!(
+        supported_response_modalities(&tts),
+        Some(&[Modality::Audio][..])
+    );
+    let unknown = Model::Custom("models/gemini-future".to_string());
+    assert_eq!(supported_response_modalities(&unknown), None);
+}
+
+#[tokio::test]
+async fn test_preflight_rejects_unsupported_modalities() {
+    let server = MockServer::start().await;
+    server.push(answer("a picture of a cat", 10));
+    let client = server.builder().with_preflight(true).build().unwrap();
+
+    let error = client
+        .generate_content()
+        .with_user_message("Draw a cat")
+        .with_response_modalities(["TEXT", "IMAGE"])
+        .execute()
+        .await
+        .unwrap_err();
+    let ClientError::UnsupportedModalities {
+        unsupported,
+        supported,
+        ..
+    } = &error
+    else {
+        panic!("unexpected error: {error}");
+    };
+    assert_eq!(unsupported, &[Modality::Image]);
+    assert_eq!(supported, &[Modality::Text]);
+    assert!(server.requests().is_empty());
+
+    // With the fallback, the unsupported modality is stripped before sending
+    let response = client
+        .generate_content()
+        .with_user_message("Draw a cat")
+        .with_response_modalities(["TEXT", "IMAGE"])
+        .fallback_to_text(true)
+        .execute()
+        .await
+        .unwrap();
+    assert!(response.modality_downgraded);
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0].json()["generationConfig"]["responseModalities"],
+        json!(["TEXT"])
+    );
+}
+
+fn modalities_rejected(field: &str) -> MockResponse {
+    MockResponse::json(
+        400,
+        json!({"error": {
+            "code": 400,
+            "message": "The requested combination of response modalities is not supported by the model.",
+            "status": "INVALID_ARGUMENT",
+            "details": [{
+                "@type": "type.googleapis.com/google.rpc.BadRequest",
+                "fieldViolations": [{"field": field, "description": "Unsupported modality"}]
+            }]
+        }}),
+    )
+}
+
+#[tokio::test]
+async fn test_fallback_to_text_after_modality_rejection() {
+    let server = MockServer::start().await;
+    server
+        .push(modalities_rejected(
+            "generation_config.response_modalities[1]",
+        ))
+        .push(answer("a cat, described", 10));
+    let response = server
+        .client()
+        .generate_content()
+        .with_user_message("Draw a cat")
+        .with_response_modalities(["TEXT", "IMAGE"])
+        .fallback_to_text(true)
+        .execute()
+        .await
+        .unwrap();
+
+    assert!(response.modality_downgraded);
+    assert_eq!(response.text(), "a cat, described");
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+        requests[0].json()["generationConfig"]["responseModalities"],
+        json!(["TEXT", "IMAGE"])
+    );
+    assert_eq!(
+        requests[1].json()["generationConfig"]["responseModalities"],
+        json!(["TEXT"])
+    );
+}
+
+#[tokio::test]
+async fn test_modality_rejection_is_a_hard_error_without_fallback() {
+    // Without the fallback, the rejection is returned as is
+    let server = MockServer::start().await;
+    server.push(modalities_rejected("generationConfig.responseModalities"));
+    let error = server
+        .client()
+        .generate_content()
+        .with_user_message("Draw a cat")
+        .with_response_modalities(["TEXT", "IMAGE"])
+        .execute()
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        ClientError::InvalidFields { code: 400, .. }
+    ));
+    assert_eq!(server.requests().len(), 1);
+
+    // Errors that mention modalities only in their message are not retried
+    let server = MockServer::start().await;
+    server.push(modalities_rejected("contents[0].parts[0]"));
+    let error = server
+        .client()
+        .generate_content()
+        .with_user_message("Draw a cat")
+        .with_response_modalities(["TEXT", "IMAGE"])
+        .fallback_to_text(true)
+        .execute()
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        ClientError::InvalidFields { code: 400, .. }
+    ));
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_concurrent_sends_are_serialized_and_views_are_snapshots() {
+    let server = MockServer::start().await;
+    server
+        .push(answer("first answer", 10))
+        .push(answer("second answer", 20));
+    let session = std::sync::Arc::new(tokio::sync::Mutex::new(server.client().start_chat()));
+
+    let before = session.lock().await.view();
+    let sends: Vec<_> = ["a", "b"]
+        .into_iter()
+        .map(|text| {
+            let session = session.clone();
+            tokio::spawn(async move { session.lock().await.send_message(text).await })
+        })
+        .collect();
+    for send in sends {
+        send.await.unwrap().unwrap();
+    }
+
+    // The sends ran one after the other: the second request carried the first exchange
+    let requests = server.requests();
+    assert_eq!(requests[0].json()["contents"].as_array().unwrap().len(), 1);
+    assert_eq!(requests[1].json()["contents"].as_array().unwrap().len(), 3);
+
+    let session = session.lock().await;
+    let roles: Vec<_> = session
+        .history()
+        .iter()
+        .map(|content| content.role.clone().unwrap())
+        .collect();
+    assert_eq!(roles, [Role::User, Role::Model, Role::User, Role::Model]);
+
+    // A view keeps the state it was taken in
+    assert!(before.history().is_empty());
+    assert!(before.turns().is_empty());
+    let after = session.view();
+    assert_eq!(after.history(), session.history());
+    assert_eq!(after.turns().len(), 2);
+}
+
+#[tokio::test]
+async fn test_view_renders_while_send_is_in_progress() {
+    let server = MockServer::start().await;
+    server.push(answer("first", 10)).push(answer("second", 10));
+    let mut session = server.client().start_chat();
+    session.send_message("one").await.unwrap();
+
+    let view = session.view();
+    let render = async move {
+        view.history()
+            .iter()
+            .flat_map(|content| content.parts.iter().flatten())
+            .filter_map(|part| match part {
+                Part::Text { text, .. } => Some(text.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let (rendered, sent) = tokio::join!(render, session.send_message("two"));
+    sent.unwrap();
+
+    assert_eq!(rendered, ["one", "first"]);
+    assert_eq!(session.history().len(), 4);
+}
+
+#[test]
+fn test_part_and_content_summaries() {
+    use crate::{
+        models::{CodeExecutionResult, ExecutableCode, FileData},
+        tools::FunctionResponse,
+        Blob, Content,
+    };
+
+    let text = |text: &str| Part::Text {
+        text: text.to_string(),
+        thought: None,
+        thought_signature: None,
+    };
+    let prompt = format!(
+        "Analyze this contract\nclause by clause. {}",
+        "x".repeat(1200)
+    );
+    assert_eq!(text("Hello").to_string(), r#"Text("Hello", 5B)"#);
+    assert_eq!(
+        text(&prompt).summary(12),
+        r#"Text("Analyze this...", 1.2KB)"#
+    );
+    // Whitespace is collapsed and quotes are escaped
+    assert_eq!(
+        text("say\n  \"hi\"").to_string(),
+        r#"Text("say \"hi\"", 10B)"#
+    );
+    let thought = Part::Text {
+        text: "Let me think".to_string(),
+        thought: Some(true),
+        thought_signature: None,
+    };
+    assert_eq!(thought.to_string(), r#"Thought("Let me think", 12B)"#);
+
+    // 250880 bytes of base64 payload
+    let image = Part::InlineData {
+        inline_data: Blob::new("image/png", "A".repeat(334508)),
+    };
+    assert_eq!(image.to_string(), "InlineData(image/png, 245KB)");
+    let padded = Part::InlineData {
+        inline_data: Blob::new("application/pdf", "bm90IGEgcGRmIQ=="),
+    };
+    assert_eq!(padded.to_string(), "InlineData(application/pdf, 10B)");
+
+    let file = Part::FileData {
+        file_data: FileData::new("video/mp4", "https://example.com/files/abc"),
+        video_metadata: None,
+    };
+    assert_eq!(
+        file.to_string(),
+        "FileData(video/mp4, https://example.com/files/abc)"
+    );
+
+    let call = Part::FunctionCall {
+        function_call: FunctionCall::new("get_weather", json!({"city": "Paris"})),
+        thought_signature: None,
+    };
+    assert_eq!(
+        call.to_string(),
+        r#"FunctionCall(get_weather, {"city":"Paris"})"#
+    );
+    assert_eq!(
+        call.summary(10),
+        r#"FunctionCall(get_weather, {"city":"P...)"#
+    );
+    let response = Part::FunctionResponse {
+        function_response: FunctionResponse::new("get_weather", json!({"temp": 21})),
+    };
+    assert_eq!(
+        response.to_string(),
+        r#"FunctionResponse(get_weather, {"temp":21})"#
+    );
+
+    let code = Part::ExecutableCode {
+        executable_code: ExecutableCode {
+            language: Language::Python,
+            code: "import math\nprint(math.pi)".to_string(),
+        },
+    };
+    assert_eq!(
+        code.summary(16),
+        r#"ExecutableCode(PYTHON, "import math prin...")"#
+    );
+    let result = Part::CodeExecutionResult {
+        code_execution_result: CodeExecutionResult {
+            outcome: Outcome::OutcomeOk,
+            output: Some("3.14159\n".to_string()),
+        },
+    };
+    assert_eq!(
+        result.to_string(),
+        r#"CodeExecutionResult(OUTCOME_OK, "3.14159")"#
+    );
+
+    let content = Content {
+        parts: Some(vec![text("Compare"), image, text("and this")]),
+        role: Some(Role::User),
+    };
+    assert_eq!(
+        content.to_string(),
+        "Content[user]: 3 parts (text, image, text)"
+    );
+    assert_eq!(
+        Content::text("hi").with_role(Role::Model).summary(),
+        "Content[model]: 1 part (text)"
+    );
+    assert_eq!(Content::default().summary(), "Content: 0 parts");
+}
+
+#[tokio::test]
+async fn test_oversized_response_fails_without_buffering_body() {
+    let server = MockServer::start().await;
+    let chunk = vec![b' '; 64 * 1024];
+    server.push(
+        MockResponse {
+            status: 200,
+            headers: vec![("content-type".into(), "application/json".into())],
+            chunks: vec![(Duration::from_millis(2), chunk); 256],
+            content_length: true,
+            reset_after: None,
+        }
+        .close_delimited(),
+    );
+
+    let client = server
+        .builder()
+        .with_max_response_bytes(1024 * 1024)
+        .build()
+        .unwrap();
+    let error = client
+        .generate_content()
+        .with_user_message("hi")
+        .execute()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, ClientError::ResponseTooLarge { limit: 1048576 }),
+        "{error:?}"
+    );
+
+    // The client hung up long before the 16 MiB body was sent
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(server.body_bytes_sent() < 8 * 1024 * 1024);
+}
+
+#[tokio::test]
+async fn test_announced_oversized_response_fails_before_reading() {
+    let server = MockServer::start().await;
+    server.push(answer(&"x".repeat(4096), 10));
+
+    let client = server
+        .builder()
+        .with_max_response_bytes(1024)
+        .build()
+        .unwrap();
+    let error = client
+        .generate_content()
+        .with_user_message("hi")
+        .execute()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, ClientError::ResponseTooLarge { limit: 1024 }),
+        "{error:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_stream_fails_once_total_exceeds_limit() {
+    let server = MockServer::start().await;
+    let chunk = json!({"candidates": [{"content": {"parts": [{"text": "x".repeat(512)}]}}]});
+    server.push(
+        MockResponse::sse(vec![chunk; 16])
+            .with_chunk_delay(Duration::from_millis(10))
+            .close_delimited(),
+    );
+
+    let client = server
+        .builder()
+        .with_max_response_bytes(4096)
+        .build()
+        .unwrap();
+    let stream = client
+        .generate_content()
+        .with_user_message("hi")
+        .execute_stream()
+        .await
+        .unwrap();
+    let results: Vec<_> =
+        futures::StreamExt::collect(futures::TryStreamExt::into_stream(stream)).await;
+
+    let (received, error) = results.split_at(results.len() - 1);
+    assert!(!received.is_empty() && received.len() < 8);
+    assert!(received.iter().all(Result::is_ok));
+    assert!(
+        matches!(error[0], Err(ClientError::ResponseTooLarge { limit: 4096 })),
+        "{error:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_oversized_inline_data_is_rejected() {
+    let server = MockServer::start().await;
+    let image = json!([{"inlineData": {"mimeType": "image/png", "data": "A".repeat(4000)}}]);
+    server
+        .push(model_parts(image.clone(), "STOP"))
+        .push(model_parts(image, "STOP"));
+
+    let client = server
+        .builder()
+        .with_max_inline_data_bytes(2048)
+        .build()
+        .unwrap();
+    let error = client
+        .generate_content()
+        .with_user_message("draw")
+        .execute()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            ClientError::InlineDataTooLarge { mime_type, size: 3000, limit: 2048 }
+                if mime_type == "image/png"
+        ),
+        "{error:?}"
+    );
+
+    let response = server
+        .client()
+        .generate_content()
+        .with_user_message("draw")
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(response.candidates.len(), 1);
+}
+
+#[tokio::test]
+async fn test_download_respects_response_limit() {
+    let server = MockServer::start().await;
+    let file = json!({"name": "files/a", "state": "ACTIVE"});
+    server
+        .push(MockResponse::json(200, file))
+        .push(MockResponse::text(200, "image/png", "x".repeat(2048)))
+        .push(MockResponse::text(200, "image/png", "x".repeat(2048)));
+
+    let client = server
+        .builder()
+        .with_max_response_bytes(1024)
+        .build()
+        .unwrap();
+    let file = client.get_file("files/a").await.unwrap();
+    let error = file.download().await.unwrap_err();
+    assert!(
+        matches!(
+            error,
+            crate::files::Error::Client {
+                source: ClientError::ResponseTooLarge { limit: 1024 }
+            }
+        ),
+        "{error:?}"
+    );
+    assert_eq!(file.download_with_limit(4096).await.unwrap().len(), 2048);
+}
+
+#[test]
+fn test_canonical_hash_ignores_thought_signatures() {
+    let request = |signed: bool| {
+        let mut answer = Content::text("Paris").with_role(Role::Model);
+        let mut call = Content::function_call(FunctionCall::new("lookup", json!({"q": "x"})))
+            .with_role(Role::Model);
+        if signed {
+            answer = Content::text_with_thought_signature("Paris", "sig-a").with_role(Role::Model);
+            call = Content::function_call_with_thought(
+                FunctionCall::new("lookup", json!({"q": "x"})),
+                "sig-b",
+            )
+            .with_role(Role::Model);
+        }
+        GenerateContentRequest {
+            contents: vec![Content::text("Capital of France?"), answer, call],
+            generation_config: None,
+            safety_settings: None,
+            tools: None,
+            tool_config: None,
+            system_instruction: None,
+            cached_content: None,
+            labels: Default::default(),
+        }
+    };
+
+    let signed = request(true);
+    assert_eq!(signed.canonical_hash(), request(false).canonical_hash());
+    assert_eq!(signed.canonical_hash(), signed.clone().canonical_hash());
+    // Hashing leaves the signatures of the request itself in place
+    assert!(serde_json::to_string(&signed).unwrap().contains("sig-a"));
+
+    let mut other = request(false);
+    other.contents[0] = Content::text("Capital of Spain?");
+    assert_ne!(other.canonical_hash(), signed.canonical_hash());
+
+    let mut stripped = signed.clone();
+    let count: usize = stripped
+        .contents
+        .iter_mut()
+        .map(Content::strip_thought_signatures)
+        .sum();
+    assert_eq!(count, 2);
+    let json = serde_json::to_string(&stripped).unwrap();
+    assert!(!json.contains("sig-") && !json.contains("thoughtSignature"));
+}
+
+#[tokio::test]
+async fn test_resume_chat_strips_signatures_on_model_mismatch() {
+    let server = MockServer::start().await;
+    server.push(model_parts(
+        json!([{"text": "Paris", "thoughtSignature": "sig-a"}]),
+        "STOP",
+    ));
+    let mut chat = server.client().start_chat();
+    chat.send_message("Capital of France?").await.unwrap();
+
+    let export = chat.export();
+    assert_eq!(export.model, Some(Model::default()));
+    assert!(serde_json::to_string(&export).unwrap().contains("sig-a"));
+
+    server.push(answer("Madrid", 10)).push(answer("Rome", 10));
+    let mut same = server.client().resume_chat(export.clone());
+    same.send_message("And Spain?").await.unwrap();
+    let mut other = server
+        .builder()
+        .with_model("models/gemini-2.5-pro".to_string())
+        .build()
+        .unwrap()
+        .resume_chat(export);
+    other.send_message("And Italy?").await.unwrap();
+
+    let requests = server.requests();
+    assert!(String::from_utf8_lossy(&requests[1].body).contains("sig-a"));
+    assert!(!String::from_utf8_lossy(&requests[2].body).contains("sig-a"));
+    assert_eq!(requests[2].json()["contents"].as_array().unwrap().len(), 3);
+    assert_eq!(other.turns().len(), 2);
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Employee {
+    id: u32,
+    name: String,
+    role: String,
+    manager: Option<u32>,
+}
+
+fn employees() -> Vec<Employee> {
+    let employee = |id, name: &str, role: &str, manager| Employee {
+        id,
+        name: name.to_string(),
+        role: role.to_string(),
+        manager,
+    };
+    vec![
+        employee(1, "Alice", "admin", None),
+        employee(2, "Smith, Jane", "user", Some(1)),
+        employee(3, "Bob", "true", Some(1)),
+    ]
+}
+
+#[test]
+fn test_toon_table_format() {
+    let table = toon::to_table_string(&employees()).unwrap();
+    assert_eq!(
+        table,
+        "[3]{id,name,role,manager}:\n  1,Alice,admin,null\n  2,\"Smith, Jane\",user,1\n  3,Bob,\"true\",1"
+    );
+    assert_eq!(
+        toon::from_table_str::<Employee>(&table).unwrap(),
+        employees()
+    );
+    assert_eq!(toon::to_table_string::<Employee>(&[]).unwrap(), "[0]:");
+
+    let rows = [json!({"id": 1, "name": "a"}), json!({"id": 2})];
+    let error = toon::to_table_string(&rows).unwrap_err();
+    assert!(
+        matches!(&error, toon::Error::Serialize { message } if message.contains("row 1")),
+        "{error:?}"
+    );
+    let rows = [json!({"id": 1, "tags": ["x"]})];
+    assert!(matches!(
+        toon::to_table_string(&rows),
+        Err(toon::Error::Serialize { message }) if message.contains("'tags' of row 0")
+    ));
+}
+
+#[test]
+fn test_toon_table_parses_hand_written_table() {
+    let table = r#"
+[3]{id,name,role,manager}:
+  1, Alice ,admin,null
+  2,"Smith, Jane","user",1
+  3,"Bob \"the builder\"",-,1
+"#;
+    let rows: Vec<Employee> = toon::from_table_str(table).unwrap();
+    assert_eq!(rows[0].name, "Alice");
+    assert_eq!(rows[1].name, "Smith, Jane");
+    assert_eq!(rows[2].name, r#"Bob "the builder""#);
+    assert_eq!(rows[2].role, "-");
+    assert_eq!(rows[2].manager, Some(1));
+
+    assert!(matches!(
+        toon::from_table_str::<Employee>("[4]{id,name,role,manager}:\n  1,a,b,null"),
+        Err(toon::Error::Deserialize {
+            line: 1,
+            column: 1,
+            message,
+        }) if message == "expected 4 rows, found 1"
+    ));
+    // A missing value is reported at the end of its row, an extra one where it starts
+    assert!(matches!(
+        toon::from_table_str::<Employee>("[1]{id,name,role,manager}:\n  1,a,b"),
+        Err(toon::Error::Deserialize {
+            line: 2,
+            column: 8,
+            ..
+        })
+    ));
+    assert!(matches!(
+        toon::from_table_str::<Employee>("[1]{id,name,role,manager}:\n  1,a,b,null, x"),
+        Err(toon::Error::Deserialize {
+            line: 2,
+            column: 15,
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn test_toon_table_message() {
+    let server = MockServer::start().await;
+    server.push(answer("ok", 10));
+
+    server
+        .client()
+        .generate_content()
+        .with_toon_table_message(&employees())
+        .unwrap()
+        .execute()
+        .await
+        .unwrap();
+    let body = server.requests()[0].json();
+    assert_eq!(body["contents"][0]["role"], "user");
+    assert!(body["contents"][0]["parts"][0]["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("[3]{id,name,role,manager}:\n  1,Alice"));
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Team {
+    name: String,
+    members: Vec<Employee>,
+}
+
+#[test]
+fn test_toon_extract() {
+    let document = "name: core\nmembers[1]{id,name,role,manager}:\n  1,Alice,admin,null";
+    assert_eq!(toon::extract(document), document);
+    assert_eq!(
+        toon::extract(&format!(
+            "Sure!\n\n```toon\n{document}\n```\nAnything else?"
+        )),
+        document
+    );
+    assert_eq!(
+        toon::extract(&format!(
+            "Here is the team:\n{document}\n\nLet me know if that helps."
+        )),
+        document
+    );
+    assert_eq!(toon::extract(&format!("```\n{document}")), document);
+    assert_eq!(toon::extract("  just prose  "), "just prose");
+}
+
+#[cfg(feature = "toon_wip")]
+#[tokio::test]
+async fn test_execute_toon() {
+    let server = MockServer::start().await;
+    let team = Team {
+        name: "core".to_string(),
+        members: employees(),
+    };
+    let document = toon::to_string(&team).unwrap();
+    server.push(answer(&format!("Sure:\n```toon\n{document}\n```"), 10));
+    server.push(answer("Sorry, I can't help with that.", 10));
+
+    let parsed: Team = server
+        .client()
+        .generate_content()
+        .with_toon_message(&team)
+        .unwrap()
+        .execute_toon()
+        .await
+        .unwrap();
+    assert_eq!(parsed, team);
+    assert_eq!(
+        server.requests()[0].json()["contents"][0]["parts"][0]["text"],
+        document
+    );
+
+    let error = server
+        .client()
+        .generate_content()
+        .with_user_message("Describe the team")
+        .execute_toon::<Team>()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, ClientError::ToonOutput { raw, .. } if raw == "Sorry, I can't help with that."),
+        "{error:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_toon_message_with_options() {
+    let server = MockServer::start().await;
+    server.push(answer("ok", 10));
+    let team = Team {
+        name: "core".to_string(),
+        members: employees(),
+    };
+    let options = toon::ToonOptions::new().with_delimiter(toon::Delimiter::Tab);
+
+    server
+        .client()
+        .generate_content()
+        .with_toon_message(&team)
+        .unwrap()
+        .with_toon_options(options.clone())
+        .with_toon_message(&team)
+        .unwrap()
+        .execute()
+        .await
+        .unwrap();
+    let body = server.requests()[0].json();
+    let text = |index: usize| {
+        body["contents"][index]["parts"][0]["text"]
+            .as_str()
+            .unwrap()
+    };
+    assert_eq!(text(0), toon::to_string(&team).unwrap());
+    assert_eq!(text(1), toon::to_string_with(&team, &options).unwrap());
+    assert!(text(1).contains("members[3\t]{id\tname\trole\tmanager}:"));
+    assert_eq!(toon::from_str::<Team>(text(1)).unwrap(), team);
+}
+
+#[test]
+fn test_using_toon_merges_with_system_instruction() {
+    let client = Gemini::new("test-key").unwrap();
+    let instruction = |builder: crate::ContentBuilder| {
+        serde_json::to_value(builder.with_user_message("Describe the team").build()).unwrap()
+            ["systemInstruction"]
+            .clone()
+    };
+
+    let before = instruction(
+        client
+            .generate_content()
+            .using_toon()
+            .with_system_instruction("You are an HR assistant"),
+    );
+    let after = instruction(
+        client
+            .generate_content()
+            .with_system_instruction("You are an HR assistant")
+            .using_toon(),
+    );
+    let expected = json!({"parts": [{"text": "You are an HR assistant"}, {"text": toon::SPEC}]});
+    assert_eq!(before, expected);
+    assert_eq!(after, expected);
+
+    let alone = instruction(client.generate_content().using_toon());
+    assert_eq!(alone, json!({"parts": [{"text": toon::SPEC}]}));
+
+    let custom = instruction(
+        client
+            .generate_content()
+            .using_toon()
+            .using_toon_with_spec("Reply in TOON, two-space indents."),
+    );
+    assert_eq!(
+        custom,
+        json!({"parts": [{"text": "Reply in TOON, two-space indents."}]})
+    );
+
+    // The spec is part of the bundle, so a rebuilt request sends it too
+    let builder = client
+        .generate_content()
+        .with_system_instruction("You are an HR assistant")
+        .using_toon();
+    let bundle = builder.to_bundle();
+    let rebuilt = crate::ContentBuilder::from_bundle(&client, bundle).unwrap();
+    assert_eq!(instruction(rebuilt), expected);
+}
+
+#[test]
+fn test_toon_spec_example_parses() {
+    let example: serde_json::Value = toon::from_str(toon::extract(toon::SPEC)).unwrap();
+    assert_eq!(example["customer"]["name"], "Smith, Jane");
+    assert_eq!(
+        example["lines"][1],
+        json!({"sku": "P-7", "qty": 1, "price": null})
+    );
+    assert_eq!(example["events"][1]["carriers"], json!(["DHL"]));
+}
+
+#[tokio::test]
+async fn test_using_toon_request() {
+    let server = MockServer::start().await;
+    let team = Team {
+        name: "core".to_string(),
+        members: employees(),
+    };
+    let document = toon::to_string(&team).unwrap();
+    server.push(answer(&format!("```toon\n{document}\n```"), 10));
+
+    let response = server
+        .client()
+        .generate_content()
+        .with_user_message("Describe the core team")
+        .using_toon()
+        .execute()
+        .await
+        .unwrap();
+    let parsed: Team = toon::from_str(toon::extract(&response.text())).unwrap();
+    assert_eq!(parsed, team);
+    let request = server.requests()[0].json();
+    assert_eq!(request["systemInstruction"]["parts"][0]["text"], toon::SPEC);
+}
+
+fn bundled_builder(client: &Gemini) -> crate::ContentBuilder {
+    client
+        .generate_content()
+        .with_system_instruction("Answer in French")
+        .with_user_message("What is in this picture?")
+        .with_inline_data("iVBORw0KGgo=", "image/png")
+        .with_message(crate::Message {
+            content: Content::file_data("video/mp4", "https://example.com/files/v"),
+            role: Role::User,
+        })
+        .with_message(crate::Message {
+            content: Content::function_call(FunctionCall::new("lookup", json!({"q": "x"}))),
+            role: Role::Model,
+        })
+        .with_function_response("lookup", json!({"value": 42}))
+        .unwrap()
+        .with_function(FunctionDeclaration::new(
+            "lookup",
+            "Looks something up",
+            None,
+        ))
+        .with_tool(Tool::google_search())
+        .with_function_calling_mode(crate::FunctionCallingMode::Auto)
+        .with_temperature(0.2)
+        .with_response_mime_type("application/json")
+        .with_response_schema(json!({"type": "OBJECT"}))
+        .with_thinking_budget(512)
+        .with_response_modalities(["TEXT"])
+        .auto_continue(2)
+        .fallback_to_text(true)
+        .with_transcript(true)
+        .abort_stream_on(FinishReason::Safety)
+}
+
+#[tokio::test]
+async fn test_prompt_bundle_round_trip() {
+    let server = MockServer::start().await;
+    server.push(answer("ok", 10)).push(answer("ok", 10));
+    let client = server
+        .builder()
+        .with_model("models/gemini-2.5-pro".to_string())
+        .build()
+        .unwrap();
+
+    let bundle = bundled_builder(&client).to_bundle();
+    let json = serde_json::to_value(&bundle).unwrap();
+    assert_eq!(json["bundleVersion"], 1);
+    assert_eq!(json["model"], "models/gemini-2.5-pro");
+    assert_eq!(
+        json["contents"][1]["parts"][0]["inlineData"]["data"],
+        "iVBORw0KGgo="
+    );
+    assert_eq!(
+        json["contents"][2]["parts"][0]["fileData"]["fileUri"],
+        "https://example.com/files/v"
+    );
+    assert_eq!(
+        json["flags"],
+        json!({
+            "maxContinuations": 2,
+            "fallbackToText": true,
+            "transcript": true,
+            "abortOn": ["SAFETY"]
+        })
+    );
+
+    // The bundle survives JSON, and the rebuilt builder bundles and sends the same request
+    let parsed: crate::PromptBundle = serde_json::from_value(json.clone()).unwrap();
+    let rebuilt = crate::ContentBuilder::from_bundle(&server.client(), parsed).unwrap();
+    assert_eq!(serde_json::to_value(rebuilt.to_bundle()).unwrap(), json);
+
+    bundled_builder(&client).execute().await.unwrap();
+    rebuilt.execute().await.unwrap();
+    let requests = server.requests();
+    assert_eq!(requests[0].path, requests[1].path);
+    assert!(requests[1].path.contains("gemini-2.5-pro"));
+    assert_eq!(requests[0].json(), requests[1].json());
+}
+
+#[test]
+fn test_prompt_bundle_preserves_unknown_fields_and_checks_version() {
+    let json = json!({
+        "bundleVersion": 1,
+        "model": "models/gemini-2.5-flash",
+        "contents": [{"role": "user", "parts": [{"text": "hi"}]}],
+        "flags": {"maxContinuations": 1, "futureFlag": "on"},
+        "provenance": {"service": "python-eval"}
+    });
+    let bundle: crate::PromptBundle = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(bundle.flags.max_continuations, 1);
+    assert_eq!(bundle.extra["provenance"]["service"], "python-eval");
+    assert_eq!(serde_json::to_value(&bundle).unwrap(), json);
+
+    let minimal: crate::PromptBundle = serde_json::from_value(json!({
+        "bundleVersion": 1,
+        "model": "models/gemini-2.5-flash",
+        "contents": []
+    }))
+    .unwrap();
+    assert_eq!(minimal.flags, crate::BundleFlags::default());
+
+    let mut newer = bundle;
+    newer.bundle_version = 2;
+    let client = Gemini::new("test-key").unwrap();
+    let error = crate::ContentBuilder::from_bundle(&client, newer)
+        .err()
+        .unwrap();
+    assert!(
+        matches!(
+            error,
+            ClientError::UnsupportedBundleVersion {
+                version: 2,
+                supported: 1
+            }
+        ),
+        "{error:?}"
+    );
+}
+
+/// A base URL naming `host` on the port of `server`
+fn url_with_host(server: &MockServer, host: &str) -> url::Url {
+    let mut url = server.url();
+    url.set_host(Some(host)).unwrap();
+    url
+}
+
+#[tokio::test]
+async fn test_resolve_pins_host_to_static_address() {
+    let server = MockServer::start().await;
+    server.push(answer("ok", 10));
+    let url = url_with_host(&server, "gemini.test");
+
+    let client = server
+        .builder()
+        .with_base_url(url.clone())
+        .resolve("gemini.test", ["127.0.0.1:0".parse().unwrap()])
+        .build()
+        .unwrap();
+    let response = client
+        .generate_content()
+        .with_user_message("hi")
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(response.text(), "ok");
+    // The request still names the logical host
+    let host = format!("gemini.test:{}", url.port().unwrap());
+    assert_eq!(server.requests()[0].header("host"), Some(host.as_str()));
+}
+
+#[tokio::test]
+async fn test_connect_error_names_addresses_tried() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    let url = url::Url::parse(&format!("http://gemini.test:{port}/v1beta/")).unwrap();
+
+    let client = GeminiBuilder::new("test-key")
+        .with_base_url(url)
+        .resolve("gemini.test", ["127.0.0.1:0".parse().unwrap()])
+        .build()
+        .unwrap();
+    let error = client
+        .generate_content()
+        .with_user_message("hi")
+        .execute()
+        .await
+        .unwrap_err();
+    let expected: std::net::SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+    assert!(
+        matches!(
+            &error,
+            ClientError::Connect { host, addresses, .. }
+                if host == "gemini.test" && addresses == &[expected]
+        ),
+        "{error:?}"
+    );
+    assert!(error.to_string().contains(&expected.to_string()));
+}
+
+#[cfg(feature = "custom-dns")]
+#[tokio::test]
+async fn test_custom_dns_resolver() {
+    use crate::dns::{Addrs, Name, Resolve, Resolving};
+
+    struct Loopback(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl Resolve for Loopback {
+        fn resolve(&self, name: Name) -> Resolving {
+            self.0.lock().unwrap().push(name.as_str().to_string());
+            let addrs: Addrs = Box::new(std::iter::once("127.0.0.1:0".parse().unwrap()));
+            Box::pin(async move { Ok(addrs) })
+        }
+    }
+
+    let server = MockServer::start().await;
+    server.push(answer("ok", 10));
+    let names = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let client = server
+        .builder()
+        .with_base_url(url_with_host(&server, "gemini.test"))
+        .dns_resolver(Loopback(names.clone()))
+        .build()
+        .unwrap();
+    client
+        .generate_content()
+        .with_user_message("hi")
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(*names.lock().unwrap(), ["gemini.test"]);
+}
+
+#[test]
+fn test_generation_config_merges_field_wise() {
+    let base = crate::GenerationConfig {
+        temperature: Some(0.7),
+        max_output_tokens: Some(256),
+        thinking_config: Some(crate::ThinkingConfig {
+            thinking_budget: Some(1024),
+            include_thoughts: Some(true),
+        }),
+        ..Default::default()
+    };
+    let merged = base.merged_with(&crate::GenerationConfig {
+        temperature: Some(0.0),
+        response_mime_type: Some("application/json".to_string()),
+        thinking_config: Some(crate::ThinkingConfig {
+            thinking_budget: Some(0),
+            include_thoughts: None,
+        }),
+        ..Default::default()
+    });
+    assert_eq!(
+        serde_json::to_value(merged).unwrap(),
+        json!({
+            "temperature": 0.0,
+            "maxOutputTokens": 256,
+            "responseMimeType": "application/json",
+            "thinkingConfig": {"thinkingBudget": 0, "includeThoughts": true}
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_chat_overrides_apply_to_one_turn() {
+    let server = MockServer::start().await;
+    for reply in ["a", "b", "c", "d"] {
+        server.push(answer(reply, 10));
+    }
+
+    let config = crate::GenerationConfig {
+        temperature: Some(0.7),
+        max_output_tokens: Some(256),
+        ..Default::default()
+    };
+    let mut chat = server.client().start_chat().with_generation_config(config);
+    let registry = ToolRegistry::new().with_function(
+        FunctionDeclaration::new("lookup", "Looks something up", None),
+        |_| async { Ok(json!({"value": 42})) },
+    );
+    let exact = crate::GenerationConfig {
+        temperature: Some(0.0),
+        ..Default::default()
+    };
+    chat.send_message_with(exact, "Give me exact JSON")
+        .await
+        .unwrap();
+    chat.send_message("And now?").await.unwrap();
+    let no_calls = crate::ToolConfig {
+        function_calling_config: Some(crate::FunctionCallingConfig {
+            mode: crate::FunctionCallingMode::None,
+        }),
+        retrieval_config: None,
+    };
+    chat.send_message_with_tools_config(no_calls, "Just answer", &registry)
+        .await
+        .unwrap();
+    chat.send_message_with_tools("Look it up", &registry)
+        .await
+        .unwrap();
+
+    let bodies: Vec<_> = server.requests().iter().map(|r| r.json()).collect();
+    assert_eq!(
+        bodies[0]["generationConfig"],
+        json!({"temperature": 0.0, "maxOutputTokens": 256})
+    );
+    assert_eq!(
+        bodies[1]["generationConfig"],
+        json!({"temperature": 0.7, "maxOutputTokens": 256})
+    );
+    assert_eq!(
+        bodies[2]["toolConfig"],
+        json!({"function_calling_config": {"mode": "NONE"}})
+    );
+    assert!(bodies[2]["tools"].is_array());
+    assert!(bodies[3].get("toolConfig").is_none());
+    assert_eq!(chat.history().len(), 8);
+}
+
+/// An empty directory for spill files of one test
+fn spill_dir(test: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("gemini-spill-{}-{test}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn text_event(text: &str) -> serde_json::Value {
+    json!({"candidates": [{"content": {"role": "model", "parts": [{"text": text}]}}]})
+}
+
+#[tokio::test]
+async fn test_stream_aggregated_failing_midway_carries_the_chunks_received() {
+    use crate::AggregatePolicy;
+
+    let server = MockServer::start().await;
+    let events = vec![
+        text_event("The quick "),
+        json!({"candidates": [{"content": {"role": "model", "parts": [
+            {"functionCall": {"name": "lookup", "args": {"animal": "fox"}}}
+        ]}}]}),
+        text_event("brown fox"),
+        text_event(" jumps"),
+    ];
+    server
+        .push(MockResponse::sse(events.clone()).reset_after(3))
+        .push(MockResponse::sse(events).reset_after(0));
+    let client = server.client();
+    let stream = || {
+        client
+            .generate_content()
+            .with_user_message("Tell me about foxes")
+            .execute_stream_aggregated(AggregatePolicy::new())
+    };
+
+    let error = stream().await.unwrap_err();
+    let ClientError::PartialStream { received, source } = &error else {
+        panic!("expected a partial stream, got {error:?}");
+    };
+    assert_eq!(received.chunks, 3);
+    assert_eq!(received.text(), "The quick brown fox");
+    assert_eq!(received.function_calls.len(), 1);
+    assert_eq!(received.function_calls[0].name, "lookup");
+    assert_eq!(received.function_calls[0].args, json!({"animal": "fox"}));
+    assert!(!matches!(**source, ClientError::PartialStream { .. }));
+    assert_eq!(error.to_string(), "stream failed after 3 chunk(s)");
+
+    // Nothing received, nothing to salvage
+    let error = stream().await.unwrap_err();
+    assert!(!matches!(error, ClientError::PartialStream { .. }));
+}
+
+#[tokio::test]
+async fn test_stream_aggregated_caps_each_candidate() {
+    use crate::{AggregatePolicy, AggregatedResponse};
+    use std::sync::{Arc, Mutex};
+
+    let fixture = |name: &str| -> serde_json::Value {
+        let path = format!(
+            "{}/tests/fixtures/stream_chunk/{name}.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    };
+    let first = fixture("multi_candidate_first");
+    let mut chunks = vec![first; 4];
+    chunks.push(fixture("multi_candidate_final"));
+    let aggregated = async |policy: AggregatePolicy| -> AggregatedResponse {
+        let server = MockServer::start().await;
+        server.push(MockResponse::sse(chunks.clone()));
+        server
+            .client()
+            .generate_content()
+            .with_user_message("Two haiku about rust")
+            .with_candidate_count(2)
+            .execute_stream_aggregated(policy)
+            .await
+            .unwrap()
+    };
+
+    let truncations = Arc::new(Mutex::new(Vec::new()));
+    let recorded = truncations.clone();
+    let policy = AggregatePolicy::new()
+        .with_max_candidate_bytes(44)
+        .on_truncate(move |index, dropped| recorded.lock().unwrap().push((index, dropped)));
+    let response = aggregated(policy).await;
+    let [first, second] = &response.candidates[..] else {
+        panic!("expected two candidates, got {:?}", response.candidates);
+    };
+    // Nothing past the cap is kept, and the cut never splits a character
+    assert_eq!(first.text, "Orange bloom on steel, Orange bloom on steel");
+    assert_eq!(second.text, "Iron slowly sleeps — Iron slowly sleeps ");
+    assert!(first.text.len() <= 44 && second.text.len() <= 44);
+    assert!(first.truncated && second.truncated);
+    assert_eq!((first.dropped_bytes, second.dropped_bytes), (52, 54));
+    assert_eq!(*truncations.lock().unwrap(), vec![(0, 52), (1, 54)]);
+    // Client-side truncation is independent of the finish reason
+    assert_eq!(first.finish_reason, Some(FinishReason::Stop));
+    assert_eq!(second.finish_reason, Some(FinishReason::MaxTokens));
+
+    let best = response.best_by(|c| c.text.len()).unwrap();
+    assert_eq!(best.candidate.index, 0);
+    assert!(best.ranked_truncated);
+
+    let response = aggregated(AggregatePolicy::new()).await;
+    assert!(response.candidates.iter().all(|c| !c.truncated));
+    assert_eq!(response.candidates[0].text.len(), 4 * 23 + 4);
+    assert!(!response.best_by(|c| c.text.len()).unwrap().ranked_truncated);
+}
+
+#[test]
+fn test_normalize_merges_adjacent_text_parts() {
+    let mut response: GenerationResponse = serde_json::from_value(json!({
+        "candidates": [{
+            "content": {"role": "model", "parts": [
+                {"text": "Plan ", "thought": true},
+                {"text": "the call", "thought": true},
+                {"text": "Checking "},
+                {"text": "the weather", "thought": false},
+                {"functionCall": {"name": "weather", "args": {}}},
+                {"text": "It is "},
+                {"text": "sunny", "thoughtSignature": "c2lnbmVk"},
+                {"text": " today"},
+                {"text": "."},
+            ]},
+            "index": 0,
+        }],
+        "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 9, "totalTokenCount": 13},
+    }))
+    .unwrap();
+    let (text, thoughts) = (response.text(), response.thoughts().concat());
+    let usage = response.usage_metadata.clone();
+
+    assert_eq!(response.normalize(), 4);
+    let parts = response.candidates[0].content.parts.clone().unwrap();
+    assert_eq!(
+        serde_json::to_value(&parts).unwrap(),
+        json!([
+            // Thoughts and answers stay apart
+            {"text": "Plan the call", "thought": true},
+            {"text": "Checking the weather"},
+            // Nothing is merged across a function call
+            {"functionCall": {"name": "weather", "args": {}}},
+            // A signature ends a run
+            {"text": "It is sunny", "thoughtSignature": "c2lnbmVk"},
+            {"text": " today."},
+        ])
+    );
+    assert_eq!(response.text(), text);
+    assert_eq!(response.thoughts().concat(), thoughts);
+    assert_eq!(response.usage_metadata, usage);
+    assert_eq!(response.normalize(), 0);
+}
+
+#[tokio::test]
+async fn test_stream_aggregated_normalizes_parts() {
+    use crate::AggregatePolicy;
+
+    let chunk = |part: serde_json::Value| json!({"candidates": [{"content": {"role": "model", "parts": [part]}, "index": 0}]});
+    let chunks = vec![
+        chunk(json!({"text": "Plan", "thought": true})),
+        chunk(json!({"text": " more", "thought": true})),
+        chunk(json!({"text": "Hel"})),
+        chunk(json!({"text": "lo"})),
+        chunk(json!({"functionCall": {"name": "wave", "args": {}}})),
+        chunk(json!({"text": "!"})),
+    ];
+    let aggregated = async |policy: AggregatePolicy| {
+        let server = MockServer::start().await;
+        server.push(MockResponse::sse(chunks.clone()));
+        let response = server
+            .client()
+            .generate_content()
+            .with_user_message("hi")
+            .execute_stream_aggregated(policy)
+            .await
+            .unwrap();
+        response.candidates.into_iter().next().unwrap()
+    };
+
+    let candidate = aggregated(AggregatePolicy::new().with_parts(true)).await;
+    assert_eq!(candidate.text, "Hello!");
+    assert_eq!(
+        serde_json::to_value(&candidate.parts).unwrap(),
+        json!([
+            {"text": "Plan more", "thought": true},
+            {"text": "Hello"},
+            {"functionCall": {"name": "wave", "args": {}}},
+            {"text": "!"},
+        ])
+    );
+
+    let candidate = aggregated(AggregatePolicy::new().with_parts(false)).await;
+    assert_eq!(candidate.parts.len(), 6);
+
+    // Parts hold the same capped text, and fully dropped text leaves no part
+    let policy = AggregatePolicy::new()
+        .with_max_candidate_bytes(4)
+        .with_parts(true);
+    let candidate = aggregated(policy).await;
+    assert_eq!(candidate.text, "Hell");
+    assert_eq!(
+        serde_json::to_value(&candidate.parts).unwrap(),
+        json!([
+            {"text": "Plan more", "thought": true},
+            {"text": "Hell"},
+            {"functionCall": {"name": "wave", "args": {}}},
+        ])
+    );
+
+    let candidate = aggregated(AggregatePolicy::new()).await;
+    assert!(candidate.parts.is_empty());
+}
+
+#[tokio::test]
+async fn test_stream_collected_spills_past_memory_cap() {
+    let server = MockServer::start().await;
+    let events = || {
+        vec![
+            text_event("Hello, "),
+            text_event("streamed "),
+            json!({
+                "candidates": [{
+                    "content": {"role": "model", "parts": [{"text": "world"}]},
+                    "finishReason": "STOP"
+                }]
+            }),
+        ]
+    };
+    server
+        .push(MockResponse::sse(events()))
+        .push(MockResponse::sse(events()));
+    let dir = spill_dir("cap");
+
+    let collected = server
+        .client()
+        .generate_content()
+        .with_user_message("hi")
+        .execute_stream_collected(crate::SpillPolicy::new(10).with_dir(&dir))
+        .await
+        .unwrap();
+    let crate::TextSource::File(file) = &collected.text else {
+        panic!("expected a spill file, got {:?}", collected.text);
+    };
+    let path = file.path().to_path_buf();
+    assert!(path.starts_with(&dir));
+    assert_eq!(
+        collected.text.read_to_string().await.unwrap(),
+        "Hello, streamed world"
+    );
+    assert_eq!(collected.summary.finish_reason, Some(FinishReason::Stop));
+    drop(collected);
+    assert!(!path.exists());
+
+    let collected = server
+        .client()
+        .generate_content()
+        .with_user_message("hi")
+        .execute_stream_collected(crate::SpillPolicy::new(1024).with_dir(&dir))
+        .await
+        .unwrap();
+    assert!(
+        matches!(&collected.text, crate::TextSource::Memory(text) if text == "Hello, streamed world")
+    );
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_stream_collected_removes_spill_file_on_error() {
+    let server = MockServer::start().await;
+    let body = format!(
+        "data: {}\r\n\r\ndata: {}\r\n\r\ndata: {{\"candidates\": [\r\n\r\n",
+        text_event("0123456789"),
+        text_event("abcdef")
+    );
+    server.push(MockResponse::text(200, "text/event-stream", body));
+    let dir = spill_dir("error");
+
+    let error = server
+        .client()
+        .generate_content()
+        .with_user_message("hi")
+        .execute_stream_collected(crate::SpillPolicy::new(4).with_dir(&dir))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, ClientError::Deserialize { .. }),
+        "{error:?}"
+    );
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+/// URLs, credentials, and bodies of generate, count tokens, and embeddings requests on Vertex
+/// AI express mode
+#[tokio::test]
+async fn test_vertex_express_requests() {
+    use crate::embedding::TaskType;
+
+    let server = MockServer::start().await;
+    let client = GeminiBuilder::vertex_express("vx-key")
+        .with_base_url(server.url())
+        .build()
+        .unwrap();
+    let text_basic = std::fs::read_to_string(format!(
+        "{}/tests/fixtures/generate_content/text_basic.json",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .unwrap();
+    server.push(MockResponse::json(
+        200,
+        serde_json::from_str(&text_basic).unwrap(),
+    ));
+    server.push(MockResponse::json(200, json!({"totalTokens": 3})));
+    server.push(MockResponse::json(
+        200,
+        json!({"predictions": [{"embeddings": {"values": [0.5, 0.25]}}]}),
+    ));
+    server.push(MockResponse::json(
+        200,
+        json!({"predictions": [
+            {"embeddings": {"values": [1.0]}},
+            {"embeddings": {"values": [2.0]}},
+        ]}),
+    ));
+
+    client
+        .generate_content()
+        .with_user_message("hi")
+        .with_label("team", "search")
+        .execute()
+        .await
+        .unwrap();
+    let tokens = client
+        .generate_content()
+        .with_user_message("hi")
+        .count_tokens()
+        .await
+        .unwrap();
+    assert_eq!(tokens.total_tokens, 3);
+    let embedding = client
+        .embed_content()
+        .with_text("one")
+        .with_task_type(TaskType::RetrievalDocument)
+        .with_output_dimensionality(2)
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(embedding.embedding.values, vec![0.5, 0.25]);
+    let batch = client
+        .embed_content()
+        .with_chunks(vec!["one", "two"])
+        .execute_batch()
+        .await
+        .unwrap();
+    assert_eq!(batch.embeddings.len(), 2);
+
+    let requests = server.requests();
+    let model = "/v1beta/publishers/google/models/gemini-2.5-flash";
+    let paths: Vec<&str> = requests.iter().map(|r| r.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        [
+            format!("{model}:generateContent"),
+            format!("{model}:countTokens"),
+            format!("{model}:predict"),
+            format!("{model}:predict"),
+        ]
+    );
+    for request in &requests {
+        assert_eq!(request.header("x-goog-api-key"), Some("vx-key"));
+    }
+    assert_eq!(requests[0].json()["labels"], json!({"team": "search"}));
+    assert_eq!(
+        requests[1].json(),
+        json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]})
+    );
+    assert_eq!(
+        requests[2].json(),
+        json!({
+            "instances": [{"content": "one", "task_type": "RETRIEVAL_DOCUMENT"}],
+            "parameters": {"outputDimensionality": 2},
+        })
+    );
+    assert_eq!(
+        requests[3].json()["instances"],
+        json!([{"content": "one"}, {"content": "two"}])
+    );
+}
+
+/// Model paths and credentials of the Gemini API and of Vertex AI in a project
+#[tokio::test]
+async fn test_vertex_project_requests() {
+    use crate::backend::{vertex_base_url, vertex_parent};
+
+    let server = MockServer::start().await;
+    let gemini = server.client();
+    let vertex = GeminiBuilder::vertex("my-project", "europe-west4", String::from("ya29.token"))
+        .with_base_url(server.url())
+        .with_model(Model::Gemini25Pro)
+        .build()
+        .unwrap();
+    server.push(answer("Hi", 3)).push(answer("Hi", 3));
+    for client in [&gemini, &vertex] {
+        client
+            .generate_content()
+            .with_user_message("hi")
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    let requests = server.requests();
+    let paths: Vec<&str> = requests.iter().map(|r| r.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        [
+            "/v1beta/models/gemini-2.5-flash:generateContent",
+            "/v1beta/projects/my-project/locations/europe-west4/publishers/google/models/gemini-2.5-pro:generateContent",
+        ]
+    );
+    assert_eq!(requests[0].header("x-goog-api-key"), Some("test-key"));
+    assert_eq!(requests[0].header("authorization"), None);
+    assert_eq!(
+        requests[1].header("authorization"),
+        Some("Bearer ya29.token")
+    );
+    assert_eq!(requests[1].header("x-goog-api-key"), None);
+
+    // Tuned models already carry their project
+    let tuned = GeminiBuilder::vertex("my-project", "europe-west4", String::from("ya29.token"))
+        .with_base_url(server.url())
+        .with_model(String::from(
+            "projects/my-project/locations/europe-west4/endpoints/42",
+        ))
+        .build()
+        .unwrap();
+    server.push(MockResponse::json(200, json!({"totalTokens": 3})));
+    tuned
+        .generate_content()
+        .with_user_message("hi")
+        .count_tokens()
+        .await
+        .unwrap();
+    assert_eq!(
+        server.requests()[2].path,
+        "/v1beta/projects/my-project/locations/europe-west4/endpoints/42:countTokens"
+    );
+
+    assert_eq!(
+        vertex_base_url("us-central1").unwrap().as_str(),
+        "https://us-central1-aiplatform.googleapis.com/v1/"
+    );
+    assert_eq!(
+        vertex_base_url("global").unwrap().as_str(),
+        "https://aiplatform.googleapis.com/v1/"
+    );
+    assert_eq!(
+        vertex_parent("my-project", "global").as_deref(),
+        Some("projects/my-project/locations/global")
+    );
+    let error = GeminiBuilder::vertex("my-project/../x", "us-central1", String::new())
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "invalid Vertex AI project 'my-project/../x' or location 'us-central1'"
+    );
+    assert!(Gemini::with_vertex("my-project", "us central1", String::new()).is_err());
+}
+
+/// Preflight checks reject features the backend does not offer before sending anything
+#[tokio::test]
+async fn test_backend_preflight_rejects_unsupported_features() {
+    use crate::backend::Backend;
+
+    let server = MockServer::start().await;
+    let vertex = GeminiBuilder::vertex_express("vx-key")
+        .with_base_url(server.url())
+        .with_preflight(true)
+        .build()
+        .unwrap();
+    let cache = vertex.get_cached_content("cachedContents/abc");
+    let error = vertex
+        .generate_content()
+        .with_user_message("hi")
+        .with_cached_content(&cache)
+        .execute()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            error,
+            ClientError::UnsupportedOnBackend {
+                feature: "cached content",
+                backend: Backend::VertexExpress,
+            }
+        ),
+        "{error:?}"
+    );
+    let error = vertex
+        .generate_content()
+        .with_message(crate::Message {
+            content: Content::file_data(
+                "image/png",
+                "https://generativelanguage.googleapis.com/v1beta/files/abc",
+            ),
+            role: Role::User,
+        })
+        .execute()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, ClientError::UnsupportedOnBackend { .. }),
+        "{error:?}"
+    );
+
+    let gemini = server.builder().with_preflight(true).build().unwrap();
+    let error = gemini
+        .generate_content()
+        .with_user_message("hi")
+        .with_label("team", "search")
+        .execute()
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "request labels is not supported on the Gemini API"
+    );
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn test_dataset_packing_boundaries() {
+    use crate::generation::dataset::{Batch, Packer};
+    use crate::TokenBudget;
+
+    // One token per byte; the template costs 6 tokens, leaving 14 for rows
+    let budget = TokenBudget::new(20).with_estimator(|text: &str| text.len());
+    let dataset = [
+        "aaaa".to_string(),
+        "bbbbbb".to_string(),
+        "cccc".to_string(),
+        "d".to_string(),
+        "x".repeat(15),
+        "e".repeat(14),
+        "ff".to_string(),
+    ];
+    let batches: Vec<Batch> = Packer::new(
+        dataset.iter(),
+        |row: &&String| row.to_string(),
+        budget,
+        "Rows:\n{rows}",
+    )
+    .collect();
+    let rows = |rows: &[&str]| rows.iter().map(ToString::to_string).collect::<Vec<_>>();
+    assert_eq!(
+        batches,
+        [
+            Batch::Rows {
+                range: 0..3,
+                rows: rows(&["aaaa", "bbbbbb", "cccc"]),
+            },
+            Batch::Rows {
+                range: 3..4,
+                rows: rows(&["d"]),
+            },
+            Batch::Oversized {
+                row: 4,
+                estimated_tokens: 21,
+                max_tokens: 20,
+            },
+            // Exactly fills the budget
+            Batch::Rows {
+                range: 5..6,
+                rows: vec!["e".repeat(14)],
+            },
+            Batch::Rows {
+                range: 6..7,
+                rows: rows(&["ff"]),
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_process_dataset_preserves_order() {
+    let server = MockServer::start().await;
+    // The first response to arrive is the slowest
+    server.push(
+        model_parts(json!([{"text": "slow"}]), "STOP").with_chunk_delay(Duration::from_millis(100)),
+    );
+    for _ in 0..3 {
+        server.push(model_parts(json!([{"text": "fast"}]), "STOP"));
+    }
+    let budget = crate::TokenBudget::new(12).with_estimator(|text: &str| text.len());
+
+    let stream = server
+        .client()
+        .process_dataset(1..=7, |n: &i32| format!("row {n}"), budget, "{rows}")
+        .with_concurrency(3)
+        .execute();
+    let results: Vec<_> = futures::StreamExt::collect(stream).await;
+
+    let ranges: Vec<_> = results.iter().map(|(range, _)| range.clone()).collect();
+    assert_eq!(ranges, [0..2, 2..4, 4..6, 6..7]);
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+    let mut prompts: Vec<_> = server
+        .requests()
+        .iter()
+        .map(|r| {
+            r.json()["contents"][0]["parts"][0]["text"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    prompts.sort();
+    assert_eq!(
+        prompts,
+        ["row 1\nrow 2", "row 3\nrow 4", "row 5\nrow 6", "row 7"]
+    );
+}
+
+/// The responses to parallel calls go in one turn, in call order, echoing the calls' IDs
+#[tokio::test]
+async fn test_parallel_function_call_ids_are_echoed_in_order() {
+    let path = format!(
+        "{}/tests/fixtures/generate_content/parallel_function_calls_with_ids.json",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    let calls: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    let response: GenerationResponse = serde_json::from_value(calls.clone()).unwrap();
+    let ids: Vec<_> = response
+        .function_calls()
+        .iter()
+        .map(|call| call.id.as_deref())
+        .collect();
+    assert_eq!(ids, [Some("call-lights-1"), None, Some("call-music-3")]);
+
+    let expected = json!({
+        "role": "user",
+        "parts": [
+            {"functionResponse": {
+                "id": "call-lights-1",
+                "name": "turn_on_lights",
+                "response": {"on": true},
+            }},
+            {"functionResponse": {
+                "name": "set_thermostat",
+                "response": {"temperature": 21.5},
+            }},
+            {"functionResponse": {
+                "id": "call-music-3",
+                "name": "play_music",
+                "response": {"playing": "jazz"},
+            }},
+        ],
+    });
+
+    // Built by hand
+    let outputs = [
+        json!({"on": true}),
+        json!({"temperature": 21.5}),
+        json!({"playing": "jazz"}),
+    ];
+    let mut builder = Gemini::new("test-key")
+        .unwrap()
+        .generate_content()
+        .with_user_message("Set the mood");
+    for (call, output) in response.function_calls().into_iter().zip(&outputs) {
+        builder = builder.with_function_response_for(call, output).unwrap();
+    }
+    let contents = builder.build().contents;
+    assert_eq!(contents.len(), 2);
+    assert_eq!(serde_json::to_value(&contents[1]).unwrap(), expected);
+
+    // Run by the tool loop, with handlers finishing in reverse call order
+    let server = MockServer::start().await;
+    server
+        .push(MockResponse::json(200, calls))
+        .push(model_parts(json!([{"text": "Done"}]), "STOP"));
+    let handler = |delay: u64, output: serde_json::Value| {
+        move |_| {
+            let output = output.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                Ok(output)
+            }
+        }
+    };
+    let declaration = |name: &str| FunctionDeclaration::new(name, "Controls the home", None);
+    let registry = ToolRegistry::new()
+        .with_function(
+            declaration("turn_on_lights"),
+            handler(30, outputs[0].clone()),
+        )
+        .with_function(
+            declaration("set_thermostat"),
+            handler(15, outputs[1].clone()),
+        )
+        .with_function(declaration("play_music"), handler(0, outputs[2].clone()));
+    server
+        .client()
+        .generate_content()
+        .with_user_message("Set the mood")
+        .execute_with_tools(&registry)
+        .await
+        .unwrap();
+    assert_eq!(server.requests()[1].json()["contents"][2], expected);
+}
+
+#[derive(Debug, Deserialize)]
+struct Agenda {
+    #[allow(dead_code)]
+    title: String,
+    events: Vec<AgendaEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AgendaEvent {
+    #[serde(with = "time::serde::rfc3339")]
+    start: time::OffsetDateTime,
+    #[allow(dead_code)]
+    attendees: Option<Vec<u32>>,
+}
+
+fn agenda_error(answer: serde_json::Value) -> (String, Option<String>) {
+    match crate::common::path::from_value::<Agenda>(&answer) {
+        Err(error) => (error.path, error.raw),
+        Ok(agenda) => panic!("{agenda:?} should not parse"),
+    }
+}
+
+#[test]
+fn test_structured_output_error_paths() {
+    let event = |start: &str| json!({"start": start, "attendees": [1, 2]});
+    let (path, raw) = agenda_error(json!({
+        "title": "Offsite",
+        "events": [event("2026-10-16T09:00:00Z"), event("tomorrow at nine")],
+    }));
+    assert_eq!(path, "$.events[1].start");
+    assert_eq!(raw.as_deref(), Some("tomorrow at nine"));
+
+    let (path, raw) = agenda_error(json!({
+        "title": "Offsite",
+        "events": [{"start": "2026-10-16T09:00:00Z", "attendees": [1, "two"]}],
+    }));
+    assert_eq!(path, "$.events[0].attendees[1]");
+    assert_eq!(raw.as_deref(), Some("two"));
+
+    let (path, raw) = agenda_error(json!({"title": "Offsite", "events": {}}));
+    assert_eq!(path, "$.events");
+    assert_eq!(raw, None);
+
+    let (path, _) = agenda_error(json!({"events": []}));
+    assert_eq!(path, "$");
+
+    let agenda = crate::common::path::from_value::<Agenda>(&json!({
+        "title": "Offsite",
+        "events": [event("2026-10-16T09:00:00Z")],
+    }))
+    .unwrap();
+    assert_eq!(agenda.events[0].start.hour(), 9);
+}
+
+#[tokio::test]
+async fn test_execute_as_names_failed_field() {
+    let server = MockServer::start().await;
+    let answer = json!({"title": "Offsite", "events": [{"start": "next Friday"}]});
+    server.push(model_parts(json!([{"text": answer.to_string()}]), "STOP"));
+    let error = server
+        .client()
+        .generate_content()
+        .with_user_message("Plan the offsite")
+        .execute_as::<Agenda>()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            ClientError::StructuredOutput { path, raw: Some(raw), .. }
+                if path == "$.events[0].start" && raw == "next Friday"
+        ),
+        "{error:?}"
+    );
+    assert_eq!(
+        error.to_string(),
+        r#"structured output could not be parsed at $.events[0].start from "next Friday""#
+    );
+
+    server.push(model_parts(json!([{"text": "not json"}]), "STOP"));
+    let error = server
+        .client()
+        .generate_content()
+        .with_user_message("Plan the offsite")
+        .execute_as::<Agenda>()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, ClientError::StructuredOutput { path, raw: None, .. } if path == "$"),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn test_adaptive_limiter_trajectory() {
+    use crate::{AdaptiveLimiter, ConcurrencyLimiter, RequestOutcome, RequestSample};
+
+    let clock = ManualClock::new();
+    let limiter = AdaptiveLimiter::new(4)
+        .with_bounds(2, 6)
+        .with_increase_after(3)
+        .with_smoothing(0.5);
+    let dashboard = limiter.clone();
+    let run = |millis: u64, outcome: RequestOutcome| {
+        let started = clock.now();
+        let latency = Duration::from_millis(millis);
+        clock.advance(latency);
+        limiter.record(RequestSample {
+            started,
+            latency,
+            outcome,
+        });
+        limiter.limit()
+    };
+
+    // Fast successes raise the limit by one every three, up to the maximum
+    let trajectory: Vec<_> = (0..12).map(|_| run(100, RequestOutcome::Success)).collect();
+    assert_eq!(trajectory, [4, 4, 5, 5, 5, 6, 6, 6, 6, 6, 6, 6]);
+
+    // Overload halves the limit once for the requests sent before the decrease
+    let sent_before = clock.now();
+    assert_eq!(run(100, RequestOutcome::Overloaded), 3);
+    limiter.record(RequestSample {
+        started: sent_before,
+        latency: Duration::from_millis(100),
+        outcome: RequestOutcome::Overloaded,
+    });
+    assert_eq!(limiter.limit(), 3);
+
+    // A latency past twice the 90th percentile halves it again, down to the minimum
+    assert_eq!(run(500, RequestOutcome::Success), 2);
+    assert_eq!(
+        dashboard.smoothed_latency(),
+        Some(Duration::from_millis(300))
+    );
+    assert_eq!(
+        dashboard.percentile_latency(),
+        Some(Duration::from_millis(100))
+    );
+
+    // Other failures end the run of fast successes without lowering the limit
+    let trajectory: Vec<_> = [
+        RequestOutcome::Success,
+        RequestOutcome::Success,
+        RequestOutcome::Failed,
+        RequestOutcome::Success,
+        RequestOutcome::Success,
+        RequestOutcome::Success,
+    ]
+    .into_iter()
+    .map(|outcome| run(100, outcome))
+    .collect();
+    assert_eq!(trajectory, [2, 2, 2, 2, 2, 3]);
+    assert_eq!(dashboard.limit(), 3);
+}
+
+#[tokio::test]
+async fn test_process_dataset_with_adaptive_limiter() {
+    let server = MockServer::start().await;
+    server.push(MockResponse::json(
+        503,
+        json!({"error": {"code": 503, "message": "overloaded", "status": "UNAVAILABLE"}}),
+    ));
+    for _ in 0..3 {
+        server.push(model_parts(json!([{"text": "done"}]), "STOP"));
+    }
+    let limiter = crate::AdaptiveLimiter::new(2).with_bounds(1, 2);
+    let budget = crate::TokenBudget::new(5).with_estimator(|text: &str| text.len());
+
+    let stream = server
+        .client()
+        .process_dataset(1..=4, |n: &i32| format!("row {n}"), budget, "{rows}")
+        .with_limiter(limiter.clone())
+        .execute();
+    let results: Vec<_> = futures::StreamExt::collect(stream).await;
+
+    let ranges: Vec<_> = results.iter().map(|(range, _)| range.clone()).collect();
+    assert_eq!(ranges, [0..1, 1..2, 2..3, 3..4]);
+    let overloaded = results
+        .iter()
+        .filter(|(_, result)| matches!(result, Err(ClientError::BadResponse { code: 503, .. })))
+        .count();
+    assert_eq!(overloaded, 1);
+    assert_eq!(crate::ConcurrencyLimiter::limit(&limiter), 1);
+    assert!(limiter.smoothed_latency().is_some());
+}
+
+#[tokio::test]
+async fn test_adaptive_limiter_halves_after_exhausted_retries() {
+    let server = MockServer::start().await;
+    let too_many_requests = || {
+        MockResponse::json(
+            429,
+            json!({"error": {"code": 429, "message": "slow down", "status": "RESOURCE_EXHAUSTED"}}),
+        )
+    };
+    // Two rows are in flight: the first two requests get a 429, and so does the first retry
+    for _ in 0..3 {
+        server.push(too_many_requests());
+    }
+    for _ in 0..3 {
+        server.push(model_parts(json!([{"text": "done"}]), "STOP"));
+    }
+    let client = server
+        .builder()
+        .with_retry_policy(RetryPolicy::new(2))
+        .with_clock(ManualClock::new())
+        .build()
+        .unwrap();
+    let limiter = crate::AdaptiveLimiter::new(2).with_bounds(1, 2);
+    let budget = crate::TokenBudget::new(5).with_estimator(|text: &str| text.len());
+
+    let stream = client
+        .process_dataset(1..=4, |n: &i32| format!("row {n}"), budget, "{rows}")
+        .with_limiter(limiter.clone())
+        .execute();
+    let results: Vec<_> = futures::StreamExt::collect(stream).await;
+
+    let exhausted = results
+        .iter()
+        .filter(|(_, result)| matches!(result, Err(ClientError::RetriesExhausted { .. })))
+        .count();
+    assert_eq!(exhausted, 1);
+    assert_eq!(crate::ConcurrencyLimiter::limit(&limiter), 1);
+}
+
+#[test]
+fn test_adaptive_limiter_spike_factor_is_finite_and_at_least_one() {
+    use crate::{AdaptiveLimiter, ConcurrencyLimiter, RequestOutcome, RequestSample};
+
+    let clock = ManualClock::new();
+    for factor in [f64::NAN, f64::INFINITY, -3.0, 0.5] {
+        let limiter = AdaptiveLimiter::new(4)
+            .with_increase_after(100)
+            .with_latency_spike(0.9, factor);
+        for millis in [100; 10].into_iter().chain([10_000]) {
+            limiter.record(RequestSample {
+                started: clock.now(),
+                latency: Duration::from_millis(millis),
+                outcome: RequestOutcome::Success,
+            });
+        }
+        let expected = if factor == f64::INFINITY { 4 } else { 2 };
+        assert_eq!(limiter.limit(), expected, "factor {factor}");
+    }
+}
+
+fn usage(prompt: Option<i32>, cached: Option<i32>, output: Option<i32>) -> crate::UsageMetadata {
+    crate::UsageMetadata {
+        prompt_token_count: prompt,
+        cached_content_token_count: cached,
+        candidates_token_count: output,
+        ..Default::default()
+    }
+}
+
+fn assert_dollars(actual: f64, expected: f64) {
+    assert!(
+        (actual - expected).abs() < 1e-12,
+        "${actual} differs from ${expected}"
+    );
+}
+
+#[test]
+fn test_cost_estimate_arithmetic() {
+    use crate::{CostEstimate, ModelPrice, PriceTable};
+
+    let table = PriceTable::new("test").with_price(
+        "models/gemini-test",
+        ModelPrice {
+            input: 2.0,
+            cached_input: 0.5,
+            output: 8.0,
+            thinking: 10.0,
+        },
+    );
+    let mut with_thoughts = usage(Some(10_000), Some(4_000), Some(1_000));
+    with_thoughts.thoughts_token_count = Some(500);
+    with_thoughts.tool_use_prompt_token_count = Some(1_000);
+
+    // Cached tokens are part of the prompt count, priced at the cached price
+    let cost = table.estimate("gemini-test", &with_thoughts).unwrap();
+    assert_eq!(
+        (
+            cost.input_tokens,
+            cost.cached_tokens,
+            cost.output_tokens,
+            cost.thinking_tokens
+        ),
+        (7_000, 4_000, 1_000, 500)
+    );
+    assert_dollars(cost.input_cost, 0.014);
+    assert_dollars(cost.cached_cost, 0.002);
+    assert_dollars(cost.output_cost, 0.008);
+    assert_dollars(cost.thinking_cost, 0.005);
+    assert_dollars(cost.total(), 0.029);
+    assert_eq!(
+        cost.to_string(),
+        "$0.029000 (input: 7000 tokens $0.014000, cached: 4000 tokens $0.002000, \
+         output: 1000 tokens $0.008000, thinking: 500 tokens $0.005000)"
+    );
+
+    // Versioned names fall back to the longest known prefix
+    let flash = usage(Some(1_000_000), None, Some(1_000_000));
+    let lite = CostEstimate::from_usage("gemini-2.5-flash-lite-preview-06-17", &flash).unwrap();
+    assert_dollars(lite.total(), 0.10 + 0.40);
+    let flash = CostEstimate::from_usage("models/gemini-2.5-flash", &flash).unwrap();
+    assert_dollars(flash.total(), 0.30 + 2.50);
+
+    // Missing counts are zero, cached counts are capped by the prompt
+    let cost = table
+        .estimate("gemini-test", &usage(None, None, Some(1_000)))
+        .unwrap();
+    assert_eq!((cost.input_tokens, cost.cached_tokens), (0, 0));
+    assert_dollars(cost.total(), 0.008);
+    let cost = table
+        .estimate("gemini-test", &usage(Some(100), Some(500), None))
+        .unwrap();
+    assert_eq!((cost.input_tokens, cost.cached_tokens), (0, 100));
+    assert_dollars(cost.total(), 0.00005);
+    assert_eq!(table.estimate("gemini-other", &with_thoughts), None);
+}
+
+#[test]
+fn test_usage_ledger_sums_per_tag() {
+    use crate::{ModelPrice, PriceTable};
+
+    let negotiated = PriceTable::new("contract-2026").with_price(
+        "gemini-2.5-flash",
+        ModelPrice {
+            input: 0.20,
+            cached_input: 0.02,
+            output: 2.0,
+            thinking: 2.0,
+        },
+    );
+    let client = GeminiBuilder::new("test-key")
+        .with_price_table(negotiated)
+        .build()
+        .unwrap();
+    let ledger = client.usage_ledger();
+
+    let response: GenerationResponse = serde_json::from_value(json!({
+        "candidates": [],
+        "modelVersion": "gemini-2.5-flash",
+        "usageMetadata": {"promptTokenCount": 1000000, "candidatesTokenCount": 500000},
+    }))
+    .unwrap();
+    assert_dollars(client.estimate_cost(&response).unwrap().total(), 1.2);
+    ledger.record_response("search", "unused", &response);
+    ledger.record(
+        "search",
+        "gemini-2.5-flash",
+        &usage(Some(1_000_000), Some(1_000_000), None),
+    );
+    ledger.record(
+        "summaries",
+        "gemini-9-ultra",
+        &usage(Some(10), None, Some(10)),
+    );
+
+    let search = ledger.entry("search").unwrap();
+    assert_eq!(search.requests, 2);
+    assert_eq!(search.usage.prompt_token_count, Some(2_000_000));
+    assert_dollars(search.cost.total(), 1.2 + 0.02);
+    let summaries = ledger.entry("summaries").unwrap();
+    assert_eq!((summaries.requests, summaries.unpriced_requests), (1, 1));
+    assert_eq!(summaries.usage.candidates_token_count, Some(10));
+    assert_dollars(ledger.total_cost(), 1.22);
+    assert!(ledger
+        .to_string()
+        .ends_with("1 unpriced\ntotal: $1.220000 (prices of contract-2026)"));
+
+    // Clones share the totals
+    ledger
+        .clone()
+        .record("summaries", "gemini-2.5-flash", &usage(None, None, Some(1)));
+    assert_eq!(ledger.entry("summaries").unwrap().requests, 2);
+}
+
+#[tokio::test]
+async fn test_stream_surfaces_mid_stream_errors() {
+    let server = MockServer::start().await;
+    server.push(MockResponse::sse([
+        text_event("Once upon"),
+        json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [{"text": " a time"}]},
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 3, "totalTokenCount": 7}
+        }),
+    ]));
+    server.push(MockResponse::sse([
+        text_event("Once upon"),
+        json!({"error": {"code": 503, "message": "The model is overloaded", "status": "UNAVAILABLE"}}),
+    ]));
+    let client = server.client();
+
+    // The final chunk carries the usage metadata
+    let stream = client
+        .generate_content()
+        .with_user_message("Tell a story")
+        .execute_stream()
+        .await
+        .unwrap();
+    let chunks: Vec<GenerationResponse> = futures::TryStreamExt::try_collect(stream).await.unwrap();
+    let texts: Vec<_> = chunks.iter().map(GenerationResponse::text).collect();
+    assert_eq!(texts, ["Once upon", " a time"]);
+    assert!(chunks[0].usage_metadata.is_none());
+    let usage = chunks[1].usage_metadata.as_ref().unwrap();
+    assert_eq!(usage.total_token_count, Some(7));
+
+    // An error event ends the stream with an error, after the chunks before it
+    let stream = client
+        .generate_content()
+        .with_user_message("Tell a story")
+        .execute_stream()
+        .await
+        .unwrap();
+    let items: Vec<Result<GenerationResponse, ClientError>> =
+        futures::StreamExt::collect(futures::TryStreamExt::into_stream(stream)).await;
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].as_ref().unwrap().text(), "Once upon");
+    let error = items[1].as_ref().unwrap_err();
+    assert!(
+        matches!(error, ClientError::BadResponse { code: 503, .. }),
+        "{error:?}"
+    );
+    assert_eq!(
+        error.api_status().unwrap().status.as_deref(),
+        Some("UNAVAILABLE")
+    );
+}
+
+/// The code, content type, and reason of an unexpected response, with its body excerpt
+fn unexpected(error: ClientError) -> (u16, Option<String>, String, String) {
+    match error {
+        ClientError::UnexpectedResponse {
+            code,
+            content_type,
+            reason,
+            body,
+        } => (code, content_type, reason, body),
+        error => panic!("{error:?} is not an unexpected response"),
+    }
+}
+
+#[tokio::test]
+async fn test_undecodable_bodies_are_diagnosed() {
+    let server = MockServer::start().await;
+    let page = format!(
+        "<html>\n  <head><title>Access denied</title></head>\n  <body>\r\n\t{}</body>\n</html>",
+        "Blocked by policy. ".repeat(100)
+    );
+    server
+        .push(MockResponse::text(200, "text/html; charset=utf-8", page))
+        .push(MockResponse::text(200, "application/json", ""))
+        .push(MockResponse::text(
+            200,
+            "application/json; charset=UTF-8",
+            r#"{"candidates": [{"content": {"parts": [{"te"#,
+        ))
+        .push(MockResponse::text(200, "text/html", "<p>Proxy login</p>"));
+    let client = server.client();
+    let request = || client.generate_content().with_user_message("hi").execute();
+
+    let error = request().await.unwrap_err();
+    let message = error.to_string();
+    let (code, content_type, reason, body) = unexpected(error);
+    assert_eq!(code, 200);
+    assert_eq!(content_type.as_deref(), Some("text/html; charset=utf-8"));
+    assert_eq!(reason, "expected JSON, got text/html; charset=utf-8");
+    assert!(
+        body.starts_with("<html> <head><title>Access denied</title></head> <body> Blocked by"),
+        "{body}"
+    );
+    assert_eq!(
+        body.chars().count(),
+        crate::client::UNEXPECTED_BODY_CHARS + 1
+    );
+    assert!(body.ends_with('…'));
+    assert!(!message.contains("test-key") && message.contains("Access denied"));
+
+    let (_, _, reason, body) = unexpected(request().await.unwrap_err());
+    assert_eq!((reason.as_str(), body.as_str()), ("empty body", ""));
+
+    let (_, content_type, reason, body) = unexpected(request().await.unwrap_err());
+    assert_eq!(
+        content_type.as_deref(),
+        Some("application/json; charset=UTF-8")
+    );
+    assert!(
+        reason.starts_with("invalid JSON: EOF while parsing"),
+        "{reason}"
+    );
+    assert_eq!(body, r#"{"candidates": [{"content": {"parts": [{"te"#);
+
+    // Streams check the content type before reading events
+    let error = client
+        .generate_content()
+        .with_user_message("hi")
+        .execute_stream()
+        .await
+        .err()
+        .unwrap();
+    let (code, _, reason, body) = unexpected(error);
+    assert_eq!(code, 200);
+    assert_eq!(reason, "expected server-sent events, got text/html");
+    assert_eq!(body, "<p>Proxy login</p>");
+}
+
+#[tokio::test]
+async fn test_prefer_http3_records_protocol_of_responses() {
+    use crate::{Http3Status, HttpVersion, ResponseMeta};
+
+    let server = MockServer::start().await;
+    assert_eq!(server.client().http3_status(), Http3Status::Off);
+
+    let client = server.builder().prefer_http3(true).build().unwrap();
+    let preferred = if cfg!(feature = "http3") {
+        Http3Status::Untried
+    } else {
+        Http3Status::Unsupported
+    };
+    assert_eq!(client.http3_status(), preferred);
+
+    server.push(model_parts(json!([{"text": "Hello"}]), "STOP"));
+    let response = client
+        .generate_content()
+        .with_user_message("Hi")
+        .execute()
+        .await
+        .unwrap();
+    // The mock server speaks plain HTTP/1.1, which HTTP/3 never replaces
+    let http1 = |meta: &ResponseMeta| {
+        meta.http_version == Some(HttpVersion::HTTP_11) && !meta.http3_fallback
+    };
+    assert!(http1(&response.meta));
+    assert_eq!(client.http3_status(), preferred);
+
+    // Every chunk of a stream carries the metadata of the response it arrived in
+    server.push(MockResponse::sse([
+        text_event("Once upon"),
+        text_event(" a time"),
+    ]));
+    let stream = client
+        .generate_content()
+        .with_user_message("Tell a story")
+        .execute_stream()
+        .await
+        .unwrap();
+    let chunks: Vec<GenerationResponse> = futures::TryStreamExt::try_collect(stream).await.unwrap();
+    assert_eq!(chunks.len(), 2);
+    assert!(chunks.iter().all(|chunk| http1(&chunk.meta)));
+}
+
+#[test]
+fn test_json_schema_translation() {
+    use crate::Schema;
+    use std::collections::HashMap;
+
+    /// How urgent a ticket is
+    #[derive(schemars::JsonSchema)]
+    #[allow(dead_code)]
+    enum Priority {
+        /// Needs attention today
+        High,
+        Low,
+    }
+
+    #[derive(schemars::JsonSchema)]
+    #[allow(dead_code)]
+    enum Action {
+        Close,
+        Assign { to: String },
+    }
+
+    #[derive(schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Label {
+        name: String,
+        weight: u8,
+    }
+
+    /// A triaged ticket
+    #[derive(schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Ticket {
+        title: String,
+        estimate_hours: Option<f32>,
+        priority: Priority,
+        fallback: Option<Priority>,
+        labels: Vec<Label>,
+        owner: Option<Label>,
+        action: Action,
+        id: u64,
+    }
+
+    // schemars lists documented unit variants after the others
+    let schema = serde_json::Value::from(Schema::of::<Ticket>().unwrap());
+    let label = json!({
+        "type": "OBJECT",
+        "properties": {
+            "name": {"type": "STRING"},
+            "weight": {"type": "INTEGER", "format": "int32"}
+        },
+        "required": ["name", "weight"]
+    });
+    let mut owner = label.clone();
+    owner["nullable"] = json!(true);
+    assert_eq!(
+        schema,
+        json!({
+            "type": "OBJECT",
+            "description": "A triaged ticket",
+            "properties": {
+                "title": {"type": "STRING"},
+                "estimate_hours": {"type": "NUMBER", "format": "float", "nullable": true},
+                "priority": {
+                    "type": "STRING",
+                    "description": "How urgent a ticket is",
+                    "enum": ["Low", "High"]
+                },
+                "fallback": {
+                    "type": "STRING",
+                    "description": "How urgent a ticket is",
+                    "nullable": true,
+                    "enum": ["Low", "High"]
+                },
+                "labels": {"type": "ARRAY", "items": label},
+                "owner": owner,
+                "action": {"anyOf": [
+                    {"type": "STRING", "enum": ["Close"]},
+                    {
+                        "type": "OBJECT",
+                        "properties": {"Assign": {
+                            "type": "OBJECT",
+                            "properties": {"to": {"type": "STRING"}},
+                            "required": ["to"]
+                        }},
+                        "required": ["Assign"]
+                    }
+                ]},
+                "id": {"type": "INTEGER", "format": "int64"}
+            },
+            "required": ["title", "priority", "labels", "action", "id"]
+        })
+    );
+
+    // Constructs the API rejects are reported where they occur
+    #[derive(schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Scores {
+        by_name: HashMap<String, u32>,
+    }
+    #[derive(schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Tree {
+        children: Vec<Tree>,
+    }
+    #[derive(schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Pairs {
+        pairs: Vec<(String, u32)>,
+    }
+    #[derive(schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Raw {
+        payload: serde_json::Value,
+    }
+    let error = |result: Result<Schema, crate::SchemaError>| result.unwrap_err().to_string();
+    assert_eq!(
+        error(Schema::of::<Scores>()),
+        "unsupported schema at $.by_name: maps with arbitrary keys are not supported"
+    );
+    assert_eq!(
+        error(Schema::of::<Tree>()),
+        "unsupported schema at $.children[]: references, e.g. of recursive types, are not supported"
+    );
+    assert_eq!(
+        error(Schema::of::<Pairs>()),
+        "unsupported schema at $.pairs[]: tuples are not supported"
+    );
+    assert_eq!(
+        error(Schema::of::<Raw>()),
+        "unsupported schema at $.payload: arbitrary JSON values cannot be described"
+    );
+    assert_eq!(
+        error(Schema::from_json_schema(&json!({
+            "type": "object",
+            "properties": {"mode": {"enum": [1, 2]}}
+        }))),
+        "unsupported schema at $.mode: enum value `1` is not a string"
+    );
+    assert_eq!(
+        error(Schema::from_json_schema(
+            &json!({"allOf": [{"type": "string"}]})
+        )),
+        "unsupported schema at $: `allOf` is not supported"
+    );
+}
+
+#[tokio::test]
+async fn test_response_schema_of_type() {
+    #[derive(Debug, PartialEq, Deserialize, schemars::JsonSchema)]
+    struct City {
+        name: String,
+        population_millions: f64,
+        country: Option<String>,
+    }
+
+    let server = MockServer::start().await;
+    let answer = json!({"name": "Lisbon", "population_millions": 0.5, "country": "Portugal"});
+    server.push(model_parts(json!([{"text": answer.to_string()}]), "STOP"));
+    let city = server
+        .client()
+        .generate_content()
+        .with_user_message("Name a coastal capital")
+        .with_response_schema_of::<City>()
+        .unwrap()
+        .execute_as::<City>()
+        .await
+        .unwrap();
+    assert_eq!(
+        city,
+        City {
+            name: "Lisbon".into(),
+            population_millions: 0.5,
+            country: Some("Portugal".into()),
+        }
+    );
+
+    let config = &server.requests()[0].json()["generationConfig"];
+    assert_eq!(config["responseMimeType"], "application/json");
+    assert_eq!(
+        config["responseSchema"],
+        json!({
+            "type": "OBJECT",
+            "properties": {
+                "name": {"type": "STRING"},
+                "population_millions": {"type": "NUMBER", "format": "double"},
+                "country": {"type": "STRING", "nullable": true}
+            },
+            "required": ["name", "population_millions"]
+        })
+    );
+
+    // Unsupported types fail before anything is sent
+    #[derive(schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Census {
+        cities: std::collections::BTreeMap<String, City>,
+    }
+    let Err(error) = server
+        .client()
+        .generate_content()
+        .with_response_schema_of::<Census>()
+    else {
+        panic!("maps are not supported");
+    };
+    assert_eq!(error.path, "$.cities");
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_function_call_round_trip_with_tools() {
+    use crate::Tool;
+
+    let server = MockServer::start().await;
+    server
+        .push(model_parts(
+            json!([{"functionCall": {"name": "get_weather", "args": {"city": "Oslo"}}}]),
+            "STOP",
+        ))
+        .push(model_parts(json!([{"text": "It is 4°C in Oslo."}]), "STOP"));
+    let client = server.client();
+    let weather = FunctionDeclaration::new("get_weather", "Gets the weather of a city", None);
+    let tools = [Tool::new(weather), Tool::google_search()];
+
+    let response = client
+        .generate_content()
+        .with_user_message("Weather in Oslo?")
+        .with_tools(tools.clone())
+        .execute()
+        .await
+        .unwrap();
+    let calls = response.function_calls();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].get::<String>("city").unwrap(), "Oslo");
+
+    let response = client
+        .generate_content()
+        .with_user_message("Weather in Oslo?")
+        .with_message(crate::Message {
+            content: crate::Content::function_call(calls[0].clone()),
+            role: crate::Role::Model,
+        })
+        .with_function_response("get_weather", json!({"celsius": 4}))
+        .unwrap()
+        .with_tools(tools)
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(response.text(), "It is 4°C in Oslo.");
+
+    let body = server.requests()[1].json();
+    assert_eq!(
+        body["tools"],
+        json!([
+            {"function_declarations": [{
+                "name": "get_weather",
+                "description": "Gets the weather of a city"
+            }]},
+            {"google_search": {}}
+        ])
+    );
+    assert_eq!(
+        body["contents"][1]["parts"][0]["functionCall"],
+        json!({"name": "get_weather", "args": {"city": "Oslo"}})
+    );
+    assert_eq!(
+        body["contents"][2],
+        json!({
+            "role": "user",
+            "parts": [{"functionResponse": {"name": "get_weather", "response": {"celsius": 4}}}]
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_chat_stream_appends_merged_reply() {
+    use futures::TryStreamExt;
+
+    let server = MockServer::start().await;
+    let mut last = text_event("bonjour.");
+    last["candidates"][0]["finishReason"] = json!("STOP");
+    last["usageMetadata"] = json!({"promptTokenCount": 4, "totalTokenCount": 9});
+    server
+        .push(MockResponse::sse([
+            text_event("Hello, "),
+            text_event("and "),
+            last,
+        ]))
+        .push(MockResponse::sse([text_event("Partial")]));
+
+    let mut chat = server.client().start_chat();
+    let chunks: Vec<_> = chat
+        .send_message_stream("Greet me")
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chat.history().len(), 2);
+    assert_eq!(chat.history()[1].role, Some(Role::Model));
+    assert_eq!(
+        chat.history()[1].parts,
+        Some(vec![Part::Text {
+            text: "Hello, and bonjour.".to_string(),
+            thought: None,
+            thought_signature: None,
+        }])
+    );
+    assert_eq!(chat.turns()[0].finish_reason, Some(FinishReason::Stop));
+    assert_eq!(
+        chat.turns()[0]
+            .usage_metadata
+            .as_ref()
+            .unwrap()
+            .total_token_count,
+        Some(9)
+    );
+
+    // A stream dropped before its end leaves the history as it was
+    let mut stream = chat.send_message_stream("Again").await.unwrap();
+    stream.try_next().await.unwrap().unwrap();
+    drop(stream);
+    assert_eq!(chat.history().len(), 2);
+    assert_eq!(chat.turns().len(), 1);
+}
+
+#[tokio::test]
+async fn test_chat_seeded_history_truncate_and_clear() {
+    use crate::Message;
+
+    let server = MockServer::start().await;
+    server.push(answer("Sunny all week", 10));
+
+    let call = FunctionCall::new("get_weather", json!({"city": "Lisbon"}));
+    let history = [
+        Message::user("Weather in Lisbon?").content,
+        Content::function_call(call).with_role(Role::Model),
+        Content::function_response_json("get_weather", json!({"sky": "clear"}))
+            .with_role(Role::User),
+        Message::model("Clear skies.").content,
+        Message::user("Thanks!").content,
+        Message::model("You're welcome.").content,
+    ];
+    let mut chat = server.client().start_chat().with_history(history.clone());
+    assert_eq!(chat.turns().len(), 2);
+    assert_eq!(chat.turns()[1].turn, 1);
+
+    chat.send_message("And next week?").await.unwrap();
+    let body = server.requests()[0].json();
+    assert_eq!(body["contents"].as_array().unwrap().len(), 7);
+    assert!(body["contents"][1]["parts"][0]["functionCall"].is_object());
+    assert_eq!(chat.turns().len(), 3);
+
+    chat.truncate(1);
+    assert_eq!(chat.history(), &history[..4]);
+    assert_eq!(chat.turns().len(), 1);
+    // Keeping more turns than there are changes nothing
+    chat.truncate(5);
+    assert_eq!(chat.history().len(), 4);
+
+    chat.clear();
+    assert!(chat.history().is_empty());
+    assert!(chat.turns().is_empty());
+}
+
+#[test]
+fn test_system_instruction_serializes_outside_contents() {
+    let client = Gemini::new("test-key").unwrap();
+    let request = client
+        .generate_content()
+        .with_system_instruction("Be terse")
+        .with_user_message("Hello")
+        .with_system_instruction("Answer in French")
+        .build();
+    let body = serde_json::to_value(&request).unwrap();
+    // A second call replaces the first instruction and leaves the conversation alone
+    assert_eq!(
+        body["systemInstruction"],
+        json!({"parts": [{"text": "Answer in French"}]})
+    );
+    assert_eq!(
+        body["contents"],
+        json!([{"role": "user", "parts": [{"text": "Hello"}]}])
+    );
+
+    let request = client
+        .generate_content()
+        .with_system_instruction_parts([
+            Part::Text {
+                text: "You are a librarian.".to_string(),
+                thought: None,
+                thought_signature: None,
+            },
+            Part::Text {
+                text: "Cite page numbers.".to_string(),
+                thought: None,
+                thought_signature: None,
+            },
+        ])
+        .with_user_message("Where is the atlas?")
+        .build();
+    let body = serde_json::to_value(&request).unwrap();
+    assert_eq!(
+        body["systemInstruction"],
+        json!({"parts": [{"text": "You are a librarian."}, {"text": "Cite page numbers."}]})
+    );
+    assert_eq!(body["contents"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn test_attributed_answer_keeps_system_instruction() {
+    let client = Gemini::new("test-key").unwrap();
+    let documents = [Document::new("bridge", "The bridge was opened in 1932.")];
+    let with_persona = client
+        .generate_content()
+        .with_system_instruction("You are a tour guide.")
+        .attributed_answer(documents.clone(), "When did the bridge open?");
+    let body = serde_json::to_value(with_persona.content_builder().clone().build()).unwrap();
+    let parts = body["systemInstruction"]["parts"].as_array().unwrap();
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0]["text"], "You are a tour guide.");
+    assert!(parts[1]["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("Answer the question using only the documents provided."));
+
+    let plain = client
+        .generate_content()
+        .attributed_answer(documents, "When did the bridge open?");
+    let body = serde_json::to_value(plain.content_builder().clone().build()).unwrap();
+    assert_eq!(
+        body["systemInstruction"]["parts"].as_array().unwrap().len(),
+        1
+    );
+}
+
+/// A metrics sink keeping every sample
+#[derive(Clone, Default)]
+struct RecordingSink(std::sync::Arc<std::sync::Mutex<Vec<(&'static str, f64)>>>);
+
+impl crate::MetricsSink for RecordingSink {
+    fn record_histogram(&self, name: &'static str, value: f64) {
+        self.0.lock().unwrap().push((name, value));
+    }
+}
+
+#[tokio::test]
+async fn test_payload_sizes_match_wire_bodies() {
+    use crate::metrics::{REQUEST_BODY_BYTES, RESPONSE_BODY_BYTES};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    let server = MockServer::start().await;
+    let reply = json!({
+        "candidates": [{"content": {"role": "model", "parts": [{"text": "A red square"}]}, "finishReason": "STOP"}]
+    });
+    for _ in 0..3 {
+        server.push(MockResponse::json(200, reply.clone()));
+    }
+    let sink = RecordingSink::default();
+    let client = server
+        .builder()
+        .with_metrics_sink(sink.clone())
+        .build()
+        .unwrap();
+
+    let image = STANDARD.encode([0x89, b'P', b'N', b'G'].repeat(2048));
+    let requests = [
+        client
+            .generate_content()
+            .with_system_instruction("Describe images briefly")
+            .with_user_message("Hello \"world\" — ünïcode"),
+        client
+            .generate_content()
+            .with_user_message("What is in this image?")
+            .with_inline_data(image, "image/png"),
+        client
+            .generate_content()
+            .with_user_message("What's the weather?")
+            .with_function(FunctionDeclaration::new(
+                "get_weather",
+                "Gets the weather of a city",
+                None,
+            )),
+    ];
+    let mut estimates = Vec::new();
+    for request in requests {
+        estimates.push(request.estimated_size());
+        let response = request.execute().await.unwrap();
+        assert_eq!(
+            response.meta.response_bytes,
+            Some(reply.to_string().len() as u64)
+        );
+        assert_eq!(
+            response.meta.request_bytes,
+            Some(*estimates.last().unwrap() as u64)
+        );
+    }
+
+    let sent: Vec<usize> = server.requests().iter().map(|r| r.body.len()).collect();
+    assert_eq!(estimates, sent);
+    // The base64 image dominates its request
+    assert!(sent[1] > 8192 * 4 / 3);
+    let samples = sink.0.lock().unwrap().clone();
+    let of = |name| -> Vec<f64> {
+        samples
+            .iter()
+            .filter(|(n, _)| *n == name)
+            .map(|(_, v)| *v)
+            .collect()
+    };
+    assert_eq!(
+        of(REQUEST_BODY_BYTES),
+        sent.iter().map(|&len| len as f64).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        of(RESPONSE_BODY_BYTES),
+        vec![reply.to_string().len() as f64; 3]
+    );
+}
+
+#[tokio::test]
+async fn test_streamed_response_bytes_grow_per_chunk() {
+    use crate::metrics::RESPONSE_BODY_BYTES;
+    use futures::TryStreamExt;
+
+    let server = MockServer::start().await;
+    let events = [text_event("one"), text_event("two")];
+    let total: usize = events
+        .iter()
+        .map(|event| format!("data: {event}\r\n\r\n").len())
+        .sum();
+    server.push(MockResponse::sse(events));
+    let sink = RecordingSink::default();
+    let client = server
+        .builder()
+        .with_metrics_sink(sink.clone())
+        .build()
+        .unwrap();
+
+    let request = client.generate_content().with_user_message("Count");
+    let estimate = request.estimated_size() as u64;
+    let chunks: Vec<GenerationResponse> = request
+        .execute_stream()
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    let received: Vec<u64> = chunks
+        .iter()
+        .map(|chunk| chunk.meta.response_bytes.unwrap())
+        .collect();
+    assert!(received.is_sorted());
+    assert_eq!(*received.last().unwrap(), total as u64);
+    assert!(chunks
+        .iter()
+        .all(|c| c.meta.request_bytes == Some(estimate)));
+    assert!(sink
+        .0
+        .lock()
+        .unwrap()
+        .contains(&(RESPONSE_BODY_BYTES, total as f64)));
+}
+
+#[tokio::test]
+async fn test_resource_guards_delete_on_close_and_drop() {
+    let server = MockServer::start().await;
+    let upload_url = server.url().join("/upload-session").unwrap();
+    server
+        .push(
+            MockResponse::json(200, json!({}))
+                .with_header("x-goog-upload-url", upload_url.as_str()),
+        )
+        .push(MockResponse::json(
+            200,
+            json!({"file": {"name": "files/scratch", "state": "ACTIVE"}}),
+        ))
+        .push(MockResponse::json(
+            200,
+            json!({
+                "name": "cachedContents/scratch",
+                "model": "models/gemini-2.5-flash",
+                "createTime": "2025-06-02T08:00:00Z",
+                "updateTime": "2025-06-02T08:00:00Z",
+                "expireTime": "2025-06-02T09:00:00Z",
+                "usageMetadata": {"totalTokenCount": 4096}
+            }),
+        ));
+    let client = server.client();
+
+    let (file, file_guard) = client
+        .create_file(b"scratch".to_vec())
+        .display_name("tmp-test-scratch")
+        .upload_guarded()
+        .await
+        .unwrap();
+    let (_cache, cache_guard) = client
+        .create_cache()
+        .with_system_instruction("Scratch context")
+        .with_ttl(Duration::from_secs(3600))
+        .execute_guarded()
+        .await
+        .unwrap();
+    assert_eq!(file_guard.name(), file.name());
+    assert_eq!(
+        client.guarded_resources(),
+        vec!["cachedContents/scratch", "files/scratch"]
+    );
+
+    server.push(MockResponse::json(200, json!({})));
+    file_guard.close().await.unwrap();
+    assert_eq!(client.guarded_resources(), vec!["cachedContents/scratch"]);
+
+    // A dropped guard deletes in the background
+    server.push(MockResponse::json(200, json!({})));
+    drop(cache_guard);
+    for _ in 0..100 {
+        if client.guarded_resources().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    assert!(client.guarded_resources().is_empty());
+    let deletes: Vec<String> = server
+        .requests()
+        .iter()
+        .filter(|r| r.method == "DELETE")
+        .map(|r| r.path.clone())
+        .collect();
+    assert_eq!(
+        deletes,
+        vec!["/v1beta/files/scratch", "/v1beta/cachedContents/scratch"]
+    );
+}
+
+#[tokio::test]
+async fn test_cleanup_orphans_deletes_old_prefixed_resources() {
+    let server = MockServer::start().await;
+    let file = |name: &str, display_name: &str, created: &str| json!({"name": name, "displayName": display_name, "createTime": created});
+    server
+        .push(MockResponse::json(
+            200,
+            json!({
+                "files": [
+                    file("files/old", "tmp-ci-1", "2025-06-01T08:00:00Z"),
+                    file("files/recent", "tmp-ci-2", "2025-06-02T11:30:00Z"),
+                    file("files/report", "report", "2025-06-01T08:00:00Z"),
+                    file("files/stuck", "tmp-ci-3", "2025-06-01T09:00:00Z"),
+                ],
+                "nextPageToken": "page-2"
+            }),
+        ))
+        .push(MockResponse::json(
+            200,
+            json!({"files": [{"name": "files/unnamed"}]}),
+        ))
+        .push(MockResponse::json(
+            200,
+            json!({"cachedContents": [{
+                "name": "cachedContents/old",
+                "displayName": "tmp-ci-cache",
+                "model": "models/gemini-2.5-flash",
+                "createTime": "2025-06-01T08:00:00Z",
+                "updateTime": "2025-06-01T08:00:00Z",
+                "expireTime": "2025-06-03T08:00:00Z",
+                "usageMetadata": {"totalTokenCount": 4096}
+            }]}),
+        ))
+        .push(MockResponse::json(200, json!({})))
+        .push(MockResponse::json(
+            404,
+            json!({"error": {"code": 404, "message": "File not found", "status": "NOT_FOUND"}}),
+        ))
+        .push(MockResponse::json(200, json!({})));
+
+    let now = time::OffsetDateTime::parse(
+        "2025-06-02T12:00:00Z",
+        &time::format_description::well_known::Rfc3339,
+    )
+    .unwrap();
+    let clock = ManualClock::starting_at(now);
+    let client = server.builder().with_clock(clock).build().unwrap();
+    let sweep = client
+        .cleanup_orphans("tmp-ci-", Duration::from_secs(3600))
+        .await
+        .unwrap();
+
+    assert_eq!(sweep.deleted, vec!["files/old", "cachedContents/old"]);
+    let [(name, error)] = &sweep.failed[..] else {
+        panic!("expected one failure, got {:?}", sweep.failed);
+    };
+    assert_eq!(name, "files/stuck");
+    assert_eq!(error.api_status().map(|status| status.code), Some(404));
+    let requests = server.requests();
+    assert!(requests[1].path.contains("pageToken=page-2"));
+    let deletes: Vec<&str> = requests
+        .iter()
+        .filter(|r| r.method == "DELETE")
+        .map(|r| r.path.as_str())
+        .collect();
+    assert_eq!(
+        deletes,
+        vec![
+            "/v1beta/files/old",
+            "/v1beta/files/stuck",
+            "/v1beta/cachedContents/old"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_generation_config_serializes_and_rejects_out_of_range() {
+    let server = MockServer::start().await;
+    server.push(answer("Bonjour", 10)).push(answer("Salut", 10));
+    let client = server.client();
+
+    client
+        .generate_content()
+        .with_user_message("Greet me")
+        .with_temperature(0.4)
+        .with_top_p(0.9)
+        .with_top_k(32)
+        .with_max_output_tokens(128)
+        .with_stop_sequences(vec!["END".to_string()])
+        .execute()
+        .await
+        .unwrap();
+    let shared = crate::GenerationConfig {
+        temperature: Some(1.5),
+        ..Default::default()
+    };
+    client
+        .generate_content()
+        .with_user_message("Greet me again")
+        .with_generation_config(shared.clone())
+        .execute()
+        .await
+        .unwrap();
+
+    let requests = server.requests();
+    assert_eq!(
+        requests[0].json()["generationConfig"],
+        json!({
+            "temperature": 0.4,
+            "topP": 0.9,
+            "topK": 32,
+            "maxOutputTokens": 128,
+            "stopSequences": ["END"]
+        })
+    );
+    assert_eq!(
+        requests[1].json()["generationConfig"],
+        json!({"temperature": 1.5})
+    );
+
+    let rejected = |builder: crate::ContentBuilder| async move {
+        match builder.with_user_message("Hi").execute().await {
+            Err(ClientError::InvalidGenerationConfig { field, value, .. }) => (field, value),
+            other => panic!("expected an invalid config, got {other:?}"),
+        }
+    };
+    assert_eq!(
+        rejected(client.generate_content().with_temperature(5.0)).await,
+        ("temperature", "5".to_string())
+    );
+    assert_eq!(
+        rejected(client.generate_content().with_top_p(1.2)).await,
+        ("topP", "1.2".to_string())
+    );
+    assert_eq!(
+        rejected(client.generate_content().with_top_k(0)).await,
+        ("topK", "0".to_string())
+    );
+    assert_eq!(
+        rejected(client.generate_content().with_max_output_tokens(-1)).await,
+        ("maxOutputTokens", "-1".to_string())
+    );
+    let stops = (0..6).map(|i| format!("STOP{i}")).collect();
+    assert_eq!(
+        rejected(client.generate_content().with_stop_sequences(stops)).await,
+        ("stopSequences", "6".to_string())
+    );
+    let error = client
+        .generate_content()
+        .with_temperature(f32::NAN)
+        .with_user_message("Hi")
+        .execute_stream()
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "generationConfig.temperature is NaN, expected a number from 0.0 to 2.0"
+    );
+    assert!(shared.validate().is_ok());
+    // Nothing out of range reached the server
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[test]
+fn test_safety_setting_wire_names_round_trip() {
+    use crate::{HarmBlockThreshold, HarmCategory};
+
+    let categories = [
+        (HarmCategory::Unspecified, "HARM_CATEGORY_UNSPECIFIED"),
+        (HarmCategory::Derogatory, "HARM_CATEGORY_DEROGATORY"),
+        (HarmCategory::Toxicity, "HARM_CATEGORY_TOXICITY"),
+        (HarmCategory::Violence, "HARM_CATEGORY_VIOLENCE"),
+        (HarmCategory::Sexual, "HARM_CATEGORY_SEXUAL"),
+        (HarmCategory::Medical, "HARM_CATEGORY_MEDICAL"),
+        (HarmCategory::Dangerous, "HARM_CATEGORY_DANGEROUS"),
+        (HarmCategory::Harassment, "HARM_CATEGORY_HARASSMENT"),
+        (HarmCategory::HateSpeech, "HARM_CATEGORY_HATE_SPEECH"),
+        (
+            HarmCategory::SexuallyExplicit,
+            "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+        ),
+        (
+            HarmCategory::DangerousContent,
+            "HARM_CATEGORY_DANGEROUS_CONTENT",
+        ),
+    ];
+    for (category, name) in categories {
+        assert_eq!(serde_json::to_value(&category).unwrap(), json!(name));
+        assert_eq!(
+            serde_json::from_value::<HarmCategory>(json!(name)).unwrap(),
+            category
+        );
+    }
+    let thresholds = [
+        (
+            HarmBlockThreshold::HarmBlockThresholdUnspecified,
+            "HARM_BLOCK_THRESHOLD_UNSPECIFIED",
+        ),
+        (HarmBlockThreshold::BlockLowAndAbove, "BLOCK_LOW_AND_ABOVE"),
+        (
+            HarmBlockThreshold::BlockMediumAndAbove,
+            "BLOCK_MEDIUM_AND_ABOVE",
+        ),
+        (HarmBlockThreshold::BlockOnlyHigh, "BLOCK_ONLY_HIGH"),
+        (HarmBlockThreshold::BlockNone, "BLOCK_NONE"),
+        (HarmBlockThreshold::Off, "OFF"),
+    ];
+    for (threshold, name) in thresholds {
+        assert_eq!(serde_json::to_value(&threshold).unwrap(), json!(name));
+        assert_eq!(
+            serde_json::from_value::<HarmBlockThreshold>(json!(name)).unwrap(),
+            threshold
+        );
+    }
+}
+
+#[test]
+fn test_safety_settings_keep_last_threshold_per_category() {
+    use crate::{HarmBlockThreshold, HarmCategory, SafetySetting};
+
+    let client = Gemini::new("test-key").unwrap();
+    let builder = client
+        .generate_content()
+        .with_user_message("Classify this comment")
+        .with_safety_setting(HarmCategory::Harassment, HarmBlockThreshold::BlockNone)
+        .with_safety_settings(vec![
+            SafetySetting {
+                category: HarmCategory::HateSpeech,
+                threshold: HarmBlockThreshold::BlockLowAndAbove,
+            },
+            SafetySetting {
+                category: HarmCategory::HateSpeech,
+                threshold: HarmBlockThreshold::BlockOnlyHigh,
+            },
+        ])
+        .with_safety_setting(HarmCategory::Harassment, HarmBlockThreshold::Off);
+    let bundle = builder.to_bundle();
+    let body = serde_json::to_value(builder.build()).unwrap();
+    assert_eq!(
+        body["safetySettings"],
+        json!([
+            {"category": "HARM_CATEGORY_HATE_SPEECH", "threshold": "BLOCK_ONLY_HIGH"},
+            {"category": "HARM_CATEGORY_HARASSMENT", "threshold": "OFF"}
+        ])
+    );
+    let rebuilt = crate::ContentBuilder::from_bundle(&client, bundle).unwrap();
+    assert_eq!(
+        serde_json::to_value(rebuilt.build()).unwrap()["safetySettings"],
+        body["safetySettings"]
+    );
+
+    let plain = client.generate_content().with_user_message("Hi").build();
+    assert!(serde_json::to_value(plain)
+        .unwrap()
+        .get("safetySettings")
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_system_fragments_compose_in_priority_order() {
+    use crate::{
+        client::Error, ContentBuilder, FragmentError, RunEvent, RunLogOptions, SystemPromptComposer,
+    };
+
+    let mut composer = SystemPromptComposer::new();
+    composer
+        .register("tone.v2", 10, "  Be warm and concise.\n")
+        .unwrap()
+        .register("compliance.eu", 50, "Never give legal advice.")
+        .unwrap()
+        .register("product.facts", 10, "Plans start at 9 EUR a month.")
+        .unwrap()
+        .register("empty", 0, "   ")
+        .unwrap();
+    assert_eq!(
+        composer.register("tone.v2", 1, "Be terse.").unwrap_err(),
+        FragmentError::DuplicateFragment {
+            id: "tone.v2".to_string()
+        }
+    );
+
+    // Priority first, then id, whatever the requested order; duplicates and empty text dropped
+    let composed = composer
+        .compose(&[
+            "tone.v2",
+            "empty",
+            "product.facts",
+            "compliance.eu",
+            "tone.v2",
+        ])
+        .unwrap();
+    assert_eq!(
+        composed.text,
+        "Never give legal advice.\n\nPlans start at 9 EUR a month.\n\nBe warm and concise."
+    );
+    assert_eq!(
+        composed.fragments,
+        ["compliance.eu", "product.facts", "tone.v2"]
+    );
+    assert!(!composed.over_limit);
+    assert_eq!(
+        composer.compose(&["tone.v2", "tone.v3"]),
+        Err(FragmentError::UnknownFragment {
+            id: "tone.v3".to_string()
+        })
+    );
+
+    let strict = composer
+        .clone()
+        .with_separator("\n---\n")
+        .with_estimator(|text: &str| text.len())
+        .with_token_warning(40);
+    let composed = strict.compose(&["compliance.eu", "tone.v2"]).unwrap();
+    assert_eq!(
+        composed.text,
+        "Never give legal advice.\n---\nBe warm and concise."
+    );
+    assert_eq!(composed.estimated_tokens, composed.text.len());
+    assert!(composed.over_limit);
+
+    let server = MockServer::start().await;
+    server.push(answer("Happy to help!", 4));
+    let client = server.client();
+    let builder = client
+        .generate_content()
+        .with_system_fragments(&composer, &["tone.v2", "compliance.eu"])
+        .unwrap()
+        .with_user_message("Can I cancel anytime?");
+    assert_eq!(builder.system_fragments(), ["compliance.eu", "tone.v2"]);
+    assert!(builder.debug_outline().starts_with(
+        "system_instruction: 1 part(s) <- with_system_fragments [compliance.eu, tone.v2]\n"
+    ));
+    assert!(matches!(
+        client
+            .generate_content()
+            .with_system_fragments(&composer, &["legal.us"]),
+        Err(FragmentError::UnknownFragment { .. })
+    ));
+
+    // Errors about the instruction name the fragments it was composed from
+    let body = json!({
+        "error": {
+            "code": 400,
+            "message": "Invalid systemInstruction",
+            "status": "INVALID_ARGUMENT",
+            "details": [{
+                "@type": "type.googleapis.com/google.rpc.BadRequest",
+                "fieldViolations": [{
+                    "field": "systemInstruction.parts[0].text",
+                    "description": "Too long"
+                }]
+            }]
+        }
+    });
+    let error = Error::BadResponse {
+        code: 400,
+        description: Some(body.to_string()),
+        retry_after: None,
+    };
+    assert_eq!(
+        ContentBuilder::enrich_error(&builder.provenance, error).to_string(),
+        "bad request; code 400; systemInstruction.parts[0].text: Too long \
+         (added by with_system_fragments [compliance.eu, tone.v2])"
+    );
+
+    let registry = ToolRegistry::new();
+    let (result, log) = builder
+        .execute_with_tools_logged(&registry, RunLogOptions::new())
+        .await;
+    assert_eq!(result.unwrap().text(), "Happy to help!");
+    assert_eq!(
+        server.requests()[0].json()["systemInstruction"]["parts"][0]["text"],
+        "Never give legal advice.\n\nBe warm and concise."
+    );
+    let Some(RunEvent::Request {
+        system_fragments, ..
+    }) = log.events.first()
+    else {
+        panic!("unexpected events: {:?}", log.events);
+    };
+    assert_eq!(system_fragments, &["compliance.eu", "tone.v2"]);
+    assert!(log.to_json().contains("\"system_fragments\""));
+
+    // Setting the instruction otherwise forgets the fragments
+    let plain = client
+        .generate_content()
+        .with_system_fragments(&composer, &["tone.v2"])
+        .unwrap()
+        .with_system_instruction("Be brief.");
+    assert!(plain.system_fragments().is_empty());
+}
+
+#[test]
+fn test_requests_serialize_identically_whatever_the_insertion_order() {
+    use std::collections::{hash_map::RandomState, HashMap};
+
+    #[derive(schemars::JsonSchema, Serialize)]
+    #[allow(dead_code)]
+    struct Booking {
+        restaurant: String,
+        guests: u8,
+        extras: HashMap<String, bool>,
+        notes: Option<String>,
+    }
+
+    let client = Gemini::new("test-key").unwrap();
+    // Every build iterates its maps in the order of a freshly seeded hasher, as two processes
+    // would, and inserts in reverse on odd builds
+    let build = |round: usize| {
+        let entries = |pairs: &[(&'static str, serde_json::Value)]| {
+            let mut map: HashMap<_, _, RandomState> = HashMap::default();
+            map.extend(pairs.iter().cloned());
+            let mut entries: Vec<_> = map.into_iter().collect();
+            if round % 2 == 1 {
+                entries.reverse();
+            }
+            entries
+        };
+        let object = |pairs: &[(&'static str, serde_json::Value)]| {
+            let map: serde_json::Map<String, serde_json::Value> = entries(pairs)
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect();
+            serde_json::Value::Object(map)
+        };
+        let args = object(&[
+            ("restaurant", json!("Taberna")),
+            ("guests", json!(4)),
+            (
+                "extras",
+                object(&[("terrace", json!(true)), ("cake", json!(false))]),
+            ),
+            ("when", json!({"day": "friday", "at": "20:00"})),
+        ]);
+        let schema = object(&[
+            ("type", json!("OBJECT")),
+            ("required", json!(["confirmed"])),
+            (
+                "properties",
+                object(&[
+                    ("confirmed", json!({"type": "BOOLEAN"})),
+                    ("reference", json!({"type": "STRING"})),
+                    (
+                        "alternatives",
+                        json!({"type": "ARRAY", "items": {"type": "STRING"}}),
+                    ),
+                ]),
+            ),
+        ]);
+        let mut builder = client
+            .generate_content()
+            .with_function(
+                FunctionDeclaration::new("book_table", "Books a table", None)
+                    .with_parameters::<Booking>()
+                    .with_response::<Booking>(),
+            )
+            .with_function(
+                FunctionDeclaration::new("get_menu", "Gets the menu", None)
+                    .with_parameters::<Booking>(),
+            )
+            .with_user_message("Book a table for four on Friday")
+            .with_message(crate::Message {
+                content: Content::function_call(FunctionCall::new("book_table", args))
+                    .with_role(Role::Model),
+                role: Role::Model,
+            })
+            .with_function_response("book_table", {
+                object(&[("confirmed", json!(true)), ("reference", json!("T-42"))])
+            })
+            .unwrap()
+            .with_response_schema(schema)
+            .with_response_mime_type("application/json");
+        for (key, value) in entries(&[
+            ("team", json!("bookings")),
+            ("env", json!("prod")),
+            ("tenant", json!("acme")),
+        ]) {
+            builder = builder.with_label(key, value.as_str().unwrap());
+        }
+        let request = builder.build();
+        (
+            serde_json::to_vec(&request).unwrap(),
+            request.canonical_hash(),
+        )
+    };
+
+    let (bytes, hash) = build(0);
+    for round in 1..8 {
+        let (other_bytes, other_hash) = build(round);
+        assert_eq!(
+            String::from_utf8(other_bytes).unwrap(),
+            String::from_utf8(bytes.clone()).unwrap()
+        );
+        assert_eq!(other_hash, hash);
+    }
+}
+
+#[tokio::test]
+async fn test_count_tokens_builder_sends_the_generate_request() {
+    use crate::{CountTokensBuilder, Modality};
+
+    #[derive(Serialize)]
+    struct Row {
+        id: u32,
+        city: &'static str,
+    }
+
+    let server = MockServer::start().await;
+    server.push(answer("Two rows.", 3)).push(MockResponse::json(
+        200,
+        json!({
+            "totalTokens": 57,
+            "promptTokensDetails": [
+                {"modality": "TEXT", "tokenCount": 41},
+                {"modality": "IMAGE", "tokenCount": 16}
+            ]
+        }),
+    ));
+    let client = server.client();
+    let rows = [
+        Row {
+            id: 1,
+            city: "Lisbon",
+        },
+        Row {
+            id: 2,
+            city: "Porto",
+        },
+    ];
+    let weather = FunctionDeclaration::new("get_weather", "Gets the weather", None);
+
+    client
+        .generate_content()
+        .with_system_instruction("Answer in one sentence")
+        .with_toon_table_message(&rows)
+        .unwrap()
+        .with_inline_data("iVBORw0KGgo=", "image/png")
+        .with_user_message("How many rows are there?")
+        .with_function(weather.clone())
+        .execute()
+        .await
+        .unwrap();
+    let count = client
+        .count_tokens()
+        .with_system_instruction("Answer in one sentence")
+        .with_toon_table_message(&rows)
+        .unwrap()
+        .with_inline_data("iVBORw0KGgo=", "image/png")
+        .with_user_message("How many rows are there?")
+        .with_function(weather)
+        .execute()
+        .await
+        .unwrap();
+
+    assert_eq!(count.total_tokens, 57);
+    let details = count.prompt_tokens_details.unwrap();
+    assert_eq!(details[1].modality, Modality::Image);
+    assert_eq!(details[1].token_count, 16);
+
+    let requests = server.requests();
+    assert!(requests[1].path.ends_with(":countTokens"));
+    let mut counted = requests[1].json()["generateContentRequest"].take();
+    assert_eq!(counted["model"], "models/gemini-2.5-flash");
+    counted.as_object_mut().unwrap().remove("model");
+    assert_eq!(counted, requests[0].json());
+
+    // A request built for generation can be counted as is
+    let builder = client.generate_content().with_user_message("Hi");
+    server.push(MockResponse::json(200, json!({"totalTokens": 2})));
+    let count = CountTokensBuilder::from(builder).execute().await.unwrap();
+    assert_eq!(count.total_tokens, 2);
+}
+
+#[tokio::test]
+async fn test_embed_content_with_model_override() {
+    use crate::TaskType;
+
+    let server = MockServer::start().await;
+    server
+        .push(MockResponse::json(
+            200,
+            json!({"embedding": {"values": [0.125, -0.5, 1.0]}}),
+        ))
+        .push(MockResponse::json(
+            200,
+            json!({"embeddings": [{"values": [0.5]}]}),
+        ));
+    let client = server.client();
+
+    let response = client
+        .embed_content()
+        .with_model(Model::TextEmbedding004)
+        .with_text("What is the capital of Portugal?")
+        .with_task_type(TaskType::RetrievalQuery)
+        .with_title("Geography")
+        .with_output_dimensionality(3)
+        .execute()
+        .await
+        .unwrap();
+    let values: Vec<f32> = response.embedding.values;
+    assert_eq!(values, [0.125, -0.5, 1.0]);
+    let document = client
+        .embed_document(
+            "Lisbon is the capital. Porto is in the north.",
+            ChunkingConfig::default(),
+        )
+        .unwrap()
+        .with_model(Model::TextEmbedding004)
+        .execute()
+        .await;
+    assert!(document.is_complete());
+
+    let requests = server.requests();
+    assert!(requests[0]
+        .path
+        .ends_with("/models/text-embedding-004:embedContent"));
+    assert_eq!(
+        requests[0].json(),
+        json!({
+            "model": "models/text-embedding-004",
+            "content": {"parts": [{"text": "What is the capital of Portugal?"}]},
+            "taskType": "RETRIEVAL_QUERY",
+            "title": "Geography",
+            "outputDimensionality": 3
+        })
+    );
+    assert!(requests[1]
+        .path
+        .ends_with("/models/text-embedding-004:batchEmbedContents"));
+    assert_eq!(
+        requests[1].json()["requests"][0]["model"],
+        "models/text-embedding-004"
+    );
+
+    // The client itself keeps its generation model
+    server.push(answer("Lisbon", 2));
+    client
+        .generate_content()
+        .with_user_message("Capital of Portugal?")
+        .execute()
+        .await
+        .unwrap();
+    assert!(server.requests()[2]
+        .path
+        .ends_with("/models/gemini-2.5-flash:generateContent"));
+}
+
+#[tokio::test]
+async fn test_embed_batch_splits_texts_and_reports_failed_ranges() {
+    let embeddings = |range: std::ops::Range<usize>| {
+        let embeddings: Vec<_> = range.map(|i| json!({"values": [i as f32]})).collect();
+        MockResponse::json(200, json!({ "embeddings": embeddings }))
+    };
+    let server = MockServer::start().await;
+    server
+        .push(embeddings(0..100))
+        .push(MockResponse::json(
+            400,
+            json!({"error": {"code": 400, "message": "bad batch"}}),
+        ))
+        .push(embeddings(200..205));
+
+    let texts: Vec<String> = (0..205).map(|i| format!("text {i}")).collect();
+    let batch = server
+        .client()
+        .embed_batch(texts)
+        .with_concurrency(1)
+        .execute()
+        .await;
+
+    assert!(!batch.is_complete());
+    assert_eq!(batch.embeddings.len(), 205);
+    for (i, embedding) in batch.embeddings.iter().enumerate() {
+        match i {
+            100..200 => assert_eq!(embedding, &None),
+            _ => assert_eq!(embedding, &Some(vec![i as f32])),
+        }
+    }
+    assert_eq!(batch.failed.len(), 1);
+    assert_eq!(batch.failed[0].range, 100..200);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert!(requests[0].path.ends_with(":batchEmbedContents"));
+    let sizes: Vec<_> = requests
+        .iter()
+        .map(|r| r.json()["requests"].as_array().unwrap().len())
+        .collect();
+    assert_eq!(sizes, [100, 100, 5]);
+    assert_eq!(
+        requests[2].json()["requests"][4]["content"]["parts"][0]["text"],
+        "text 204"
+    );
+}
+
+#[tokio::test]
+async fn test_embed_batch_rejects_responses_with_the_wrong_count() {
+    let server = MockServer::start().await;
+    server.push(MockResponse::json(
+        200,
+        json!({"embeddings": [{"values": [1.0]}]}),
+    ));
+
+    let batch = server.client().embed_batch(["a", "b"]).execute().await;
+
+    assert_eq!(batch.embeddings, [None, None]);
+    assert_eq!(batch.failed[0].range, 0..2);
+    assert!(matches!(
+        batch.failed[0].error,
+        ClientError::EmbeddingCountMismatch {
+            expected: 2,
+            actual: 1
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_files_upload_from_path_waits_until_active() {
+    let dir = spill_dir("files-upload");
+    let path = dir.join("talk.mp4");
+    std::fs::write(&path, b"not really a video").unwrap();
+
+    let server = MockServer::start().await;
+    let upload_url = server.url().join("/upload-session").unwrap();
+    let file = |state: &str| {
+        json!({
+            "name": "files/talk",
+            "uri": "https://generativelanguage.googleapis.com/v1beta/files/talk",
+            "mimeType": "video/mp4",
+            "expirationTime": "2025-06-04T08:00:00Z",
+            "state": state
+        })
+    };
+    server
+        .push(
+            MockResponse::json(200, json!({}))
+                .with_header("x-goog-upload-url", upload_url.as_str()),
+        )
+        .push(MockResponse::json(200, json!({"file": file("PROCESSING")})))
+        .push(MockResponse::json(200, file("PROCESSING")))
+        .push(MockResponse::json(200, file("ACTIVE")))
+        .push(answer("A talk about rust.", 12));
+    let clock = ManualClock::new();
+    let client = server.builder().with_clock(clock.clone()).build().unwrap();
+
+    let video = client
+        .files()
+        .with_poll_interval(Duration::from_secs(10))
+        .upload(path.as_path(), "video/mp4".parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(video.state(), Some(&FileState::Active));
+    assert_eq!(video.mime_type(), Some("video/mp4"));
+    assert_eq!(
+        video.expiration_time().unwrap().to_string(),
+        "2025-06-04 8:00:00.0 +00:00:00"
+    );
+    assert_eq!(clock.elapsed(), Duration::from_secs(20));
+
+    client
+        .generate_content()
+        .with_file(&video)
+        .with_user_message("Summarize this talk")
+        .execute()
+        .await
+        .unwrap();
+
+    let requests = server.requests();
+    assert_eq!(
+        requests[0].header("x-goog-upload-header-content-type"),
+        Some("video/mp4")
+    );
+    assert_eq!(requests[0].json()["file"]["displayName"], "talk.mp4");
+    assert_eq!(requests[1].body, b"not really a video");
+    assert_eq!(
+        requests[4].json()["contents"][0]["parts"][0],
+        json!({"fileData": {
+            "mimeType": "video/mp4",
+            "fileUri": "https://generativelanguage.googleapis.com/v1beta/files/talk"
+        }})
+    );
+
+    let missing = client
+        .files()
+        .upload(dir.join("missing.mp4"), mime::APPLICATION_OCTET_STREAM)
+        .await;
+    assert!(matches!(missing, Err(crate::files::Error::ReadFile { .. })));
+    assert_eq!(server.requests().len(), 5);
+}
+
+#[tokio::test]
+async fn test_context_guard_estimates_against_the_limits_table() {
+    use crate::ContextGuard;
+
+    let server = MockServer::start().await;
+    server.push(answer("Short enough.", 12));
+    let client = server
+        .builder()
+        .with_model("models/gemini-2.5-flash-preview-tts".to_string())
+        .with_context_guard(ContextGuard::new().with_estimator(|text: &str| text.len()))
+        .build()
+        .unwrap();
+
+    let error = client
+        .generate_content()
+        .with_user_message("a".repeat(9000))
+        .execute()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            ClientError::ContextWindowExceeded {
+                estimated: 9000,
+                limit: 8192,
+                model,
+            } if model == "models/gemini-2.5-flash-preview-tts"
+        ),
+        "{error}"
+    );
+    assert!(server.requests().is_empty());
+
+    client
+        .generate_content()
+        .with_system_prompt("Read this aloud.")
+        .with_user_message("a".repeat(4000))
+        .with_max_output_tokens(100)
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_context_guard_counts_tokens_against_cached_live_limits() {
+    use crate::ContextGuard;
+
+    let server = MockServer::start().await;
+    let model = |input: u64| {
+        MockResponse::json(
+            200,
+            json!({
+                "name": "models/gemini-2.5-flash",
+                "inputTokenLimit": input,
+                "outputTokenLimit": 10
+            }),
+        )
+    };
+    let count = |tokens: i32| MockResponse::json(200, json!({ "totalTokens": tokens }));
+    server
+        .push(model(100))
+        .push(count(150))
+        .push(count(50))
+        .push(answer("Fits.", 60))
+        .push(model(40))
+        .push(count(50));
+    let clock = ManualClock::new();
+    let client = server
+        .builder()
+        .with_clock(clock.clone())
+        .with_context_guard(
+            ContextGuard::new()
+                .with_live_limits()
+                .with_count_tokens()
+                .with_cache_ttl(Duration::from_secs(600)),
+        )
+        .build()
+        .unwrap();
+    let request = || client.generate_content().with_user_message("Hello");
+
+    let error = request().execute().await.unwrap_err();
+    assert!(
+        matches!(
+            error,
+            ClientError::ContextWindowExceeded {
+                estimated: 150,
+                limit: 100,
+                ..
+            }
+        ),
+        "{error}"
+    );
+    // The limits are cached, so only the prompt is counted
+    request().execute().await.unwrap();
+    // Once the TTL ran out, the limits are fetched again
+    clock.advance(Duration::from_secs(601));
+    let error = request().execute().await.unwrap_err();
+    assert!(
+        matches!(error, ClientError::ContextWindowExceeded { limit: 40, .. }),
+        "{error}"
+    );
+
+    let paths: Vec<_> = server.requests().iter().map(|r| r.path.clone()).collect();
+    assert_eq!(
+        paths,
+        [
+            "/v1beta/models/gemini-2.5-flash",
+            "/v1beta/models/gemini-2.5-flash:countTokens",
+            "/v1beta/models/gemini-2.5-flash:countTokens",
+            "/v1beta/models/gemini-2.5-flash:generateContent",
+            "/v1beta/models/gemini-2.5-flash",
+            "/v1beta/models/gemini-2.5-flash:countTokens",
+        ]
+    );
+
+    server.push(model(2_000_000));
+    let limits = client.refresh_model_limits().await.unwrap().unwrap();
+    assert_eq!(limits.input_token_limit, 2_000_000);
+}
+
+/// Listing follows page tokens, and model metadata tolerates fields added later
+#[tokio::test]
+async fn test_list_and_get_models() {
+    use futures::TryStreamExt;
+
+    let server = MockServer::start().await;
+    let client = server.client();
+    server.push(MockResponse::json(
+        200,
+        json!({
+            "models": [{
+                "name": "models/gemini-2.5-pro",
+                "baseModelId": "gemini-2.5-pro",
+                "version": "2.5",
+                "displayName": "Gemini 2.5 Pro",
+                "inputTokenLimit": 1048576,
+                "outputTokenLimit": 65536,
+                "supportedGenerationMethods": ["generateContent", "countTokens"],
+                "temperature": 1.0,
+                "maxTemperature": 2.0,
+                "topP": 0.95,
+                "topK": 64,
+                "thinking": true,
+                "someFutureField": {"nested": [1, 2]},
+            }],
+            "nextPageToken": "page-2",
+        }),
+    ));
+    server.push(MockResponse::json(
+        200,
+        json!({"models": [{"name": "models/text-embedding-004"}]}),
+    ));
+
+    let models: Vec<_> = client.list_models(50).try_collect().await.unwrap();
+    let names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["models/gemini-2.5-pro", "models/text-embedding-004"]
+    );
+    let pro = &models[0];
+    assert_eq!(pro.display_name.as_deref(), Some("Gemini 2.5 Pro"));
+    assert_eq!(
+        pro.limits(),
+        Some(crate::ModelLimits::new(1_048_576, 65_536))
+    );
+    assert_eq!(
+        pro.supported_generation_methods,
+        ["generateContent", "countTokens"]
+    );
+    assert_eq!(
+        (pro.temperature, pro.max_temperature, pro.top_p, pro.top_k),
+        (Some(1.0), Some(2.0), Some(0.95), Some(64))
+    );
+    assert_eq!(models[1].limits(), None);
+
+    server.push(MockResponse::json(
+        200,
+        json!({"name": "models/gemini-2.5-pro", "inputTokenLimit": 1048576, "outputTokenLimit": 65536}),
+    ));
+    let info = client.get_model(pro.name.clone()).await.unwrap();
+    assert_eq!(info.input_token_limit, Some(1_048_576));
+
+    let paths: Vec<_> = server.requests().iter().map(|r| r.path.clone()).collect();
+    assert_eq!(
+        paths,
+        [
+            "/v1beta/models?pageSize=50",
+            "/v1beta/models?pageSize=50&pageToken=page-2",
+            "/v1beta/models/gemini-2.5-pro",
+        ]
+    );
+}
+
+#[test]
+fn test_images_join_the_preceding_user_turn() {
+    use crate::{generation::image::MAX_INLINE_IMAGE_BYTES, ImageError};
+
+    let dir = spill_dir("images");
+    let path = dir.join("Photo.PNG");
+    std::fs::write(&path, b"\x89PNG not really").unwrap();
+    let client = Gemini::new("test-key").unwrap();
+
+    let request = client
+        .generate_content()
+        .with_user_message("Describe this image")
+        .with_image_from_path(&path)
+        .unwrap()
+        .with_image_bytes(b"jpeg", "image/JPEG")
+        .unwrap()
+        .with_model_message("A photo.")
+        .with_image_bytes(b"webp", "image/webp")
+        .unwrap()
+        .build();
+    let contents = serde_json::to_value(&request.contents).unwrap();
+    assert_eq!(
+        contents,
+        json!([
+            {"role": "user", "parts": [
+                {"text": "Describe this image"},
+                {"inlineData": {"mimeType": "image/png", "data": "iVBORyBub3QgcmVhbGx5"}},
+                {"inlineData": {"mimeType": "image/jpeg", "data": "anBlZw=="}}
+            ]},
+            {"role": "model", "parts": [{"text": "A photo."}]},
+            {"role": "user", "parts": [
+                {"inlineData": {"mimeType": "image/webp", "data": "d2VicA=="}}
+            ]}
+        ])
+    );
+
+    let gif = dir.join("anim.gif");
+    std::fs::write(&gif, b"GIF89a").unwrap();
+    let error = client
+        .generate_content()
+        .with_image_from_path(&gif)
+        .err()
+        .unwrap();
+    assert!(
+        matches!(&error, ImageError::UnsupportedImageType { image_type } if image_type == "gif"),
+        "{error}"
+    );
+    let error = client
+        .generate_content()
+        .with_image_from_path(dir.join("missing.jpg"))
+        .err()
+        .unwrap();
+    assert!(matches!(error, ImageError::ReadImage { .. }), "{error}");
+    let error = client
+        .generate_content()
+        .with_image_bytes(vec![0; MAX_INLINE_IMAGE_BYTES + 1], "image/png")
+        .err()
+        .unwrap();
+    assert!(matches!(error, ImageError::ImageTooLarge { .. }), "{error}");
+
+    // The limit applies to the encoded image, a third larger than its bytes
+    let largest = MAX_INLINE_IMAGE_BYTES / 4 * 3;
+    assert!(client
+        .generate_content()
+        .with_image_bytes(vec![0; largest], "image/png")
+        .is_ok());
+    let error = client
+        .generate_content()
+        .with_image_bytes(vec![0; largest + 1], "image/png")
+        .err()
+        .unwrap();
+    assert!(
+        matches!(
+            error,
+            ImageError::ImageTooLarge { size, limit: MAX_INLINE_IMAGE_BYTES }
+                if size == MAX_INLINE_IMAGE_BYTES + 4
+        ),
+        "{error}"
+    );
+}
+
+/// Serves `body` over one end of an in-memory pipe, as an HTTP server writes a response body,
+/// and returns the end the browser reads
+#[cfg(feature = "relay")]
+fn serve_relayed(
+    body: impl futures::Stream<Item = Result<bytes::Bytes, std::convert::Infallible>> + Send + 'static,
+) -> tokio::io::DuplexStream {
+    use futures::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    let (browser, mut server) = tokio::io::duplex(64);
+    tokio::spawn(async move {
+        futures::pin_mut!(body);
+        while let Some(Ok(event)) = body.next().await {
+            if server.write_all(&event).await.is_err() {
+                // The browser hung up; dropping the body cancels the upstream request
+                return;
+            }
+        }
+    });
+    browser
+}
+
+#[cfg(feature = "relay")]
+#[tokio::test]
+async fn test_relay_frames_chunks_and_ends_with_done() {
+    use tokio::io::AsyncReadExt;
+
+    let server = MockServer::start().await;
+    server.push(MockResponse::sse(vec![
+        text_event("Hello,\nworld"),
+        json!({
+            "candidates": [{"content": {"role": "model", "parts": [{"text": "!"}]}, "finishReason": "STOP"}],
+            "usageMetadata": {"promptTokenCount": 4, "totalTokenCount": 9}
+        }),
+    ]));
+    let stream = server
+        .client()
+        .generate_content()
+        .with_user_message("Greet the world")
+        .execute_stream()
+        .await
+        .unwrap();
+
+    let mut browser = serve_relayed(crate::relay::relay(stream));
+    let mut body = String::new();
+    browser.read_to_string(&mut body).await.unwrap();
+
+    let events: Vec<(&str, serde_json::Value)> = body
+        .strip_suffix("\n\n")
+        .unwrap()
+        .split("\n\n")
+        .map(|event| {
+            let (name, data) = event.split_once('\n').unwrap();
+            let data = data.strip_prefix("data: ").unwrap();
+            assert!(!data.contains('\n'), "data spans lines: {data:?}");
+            (
+                name.strip_prefix("event: ").unwrap(),
+                serde_json::from_str(data).unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(events.len(), 3, "{body}");
+    assert_eq!(events[0].0, "chunk");
+    assert_eq!(events[0].1["text"], "Hello,\nworld");
+    assert_eq!(
+        events[0].1["response"]["candidates"][0]["content"]["parts"][0]["text"],
+        "Hello,\nworld"
+    );
+    assert_eq!(events[1].0, "chunk");
+    assert_eq!(
+        events[1].1["response"]["candidates"][0]["finishReason"],
+        "STOP"
+    );
+    assert_eq!(events[2].0, "done");
+    assert_eq!(
+        events[2].1,
+        json!({"chunks": 2, "usageMetadata": {"promptTokenCount": 4, "totalTokenCount": 9}})
+    );
+}
+
+#[cfg(feature = "relay")]
+#[tokio::test]
+async fn test_relay_ends_a_failed_stream_with_an_error_event() {
+    use tokio::io::AsyncReadExt;
+
+    let server = MockServer::start().await;
+    server.push(
+        MockResponse::sse(vec![text_event("The quick "), text_event("brown fox")]).reset_after(1),
+    );
+    let stream = server
+        .client()
+        .generate_content()
+        .with_user_message("Tell me about foxes")
+        .execute_stream()
+        .await
+        .unwrap();
+
+    // The browser sees the end of the body right after the error event
+    let mut browser = serve_relayed(crate::relay::relay(stream));
+    let mut body = String::new();
+    browser.read_to_string(&mut body).await.unwrap();
+
+    let (chunk, error) = body
+        .strip_suffix("\n\n")
+        .unwrap()
+        .split_once("\n\n")
+        .unwrap();
+    assert!(chunk.starts_with("event: chunk\ndata: {\"text\":\"The quick \""));
+    let error = error.strip_prefix("event: error\ndata: ").unwrap();
+    let error: serde_json::Value = serde_json::from_str(error).unwrap();
+    assert!(error["error"]["message"].is_string(), "{error}");
+    assert!(!body.contains("event: done"));
+
+    // An error the API reports keeps its code and status
+    let status = serde_json::json!({
+        "error": {"code": 429, "message": "Quota exceeded", "status": "RESOURCE_EXHAUSTED"}
+    });
+    let upstream = futures::stream::iter(vec![Err(ClientError::BadResponse {
+        code: 429,
+        description: Some(status.to_string()),
+        retry_after: None,
+    })]);
+    let mut browser = serve_relayed(crate::relay::relay(upstream));
+    let mut body = String::new();
+    browser.read_to_string(&mut body).await.unwrap();
+    assert_eq!(body, format!("event: error\ndata: {}\n\n", status));
+}
+
+#[cfg(feature = "relay")]
+#[tokio::test]
+async fn test_relay_cancels_the_upstream_request_when_the_browser_disconnects() {
+    use tokio::io::AsyncReadExt;
+
+    let server = MockServer::start().await;
+    let events: Vec<_> = (0..40).map(|i| text_event(&format!("word {i} "))).collect();
+    let total: usize = events
+        .iter()
+        .map(|event| format!("data: {event}\r\n\r\n").len())
+        .sum();
+    server.push(MockResponse::sse(events).with_chunk_delay(Duration::from_millis(25)));
+    let stream = server
+        .client()
+        .generate_content()
+        .with_user_message("Count slowly")
+        .execute_stream()
+        .await
+        .unwrap();
+
+    let mut browser = serve_relayed(crate::relay::relay(stream));
+    let mut first = [0u8; 32];
+    browser.read_exact(&mut first).await.unwrap();
+    assert!(first.starts_with(b"event: chunk\n"));
+    drop(browser);
+
+    // The relay stops reading from the API once the browser is gone
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let sent = server.body_bytes_sent();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(server.body_bytes_sent(), sent);
+    assert!(sent < total / 2, "{sent} of {total} bytes sent");
+}
+
+#[test]
+fn test_audio_joins_the_preceding_user_turn() {
+    use crate::{
+        generation::audio::{audio_mime_type, AUDIO_MIME_TYPES, MAX_INLINE_AUDIO_BYTES},
+        AudioError,
+    };
+
+    let dir = spill_dir("audio");
+    let path = dir.join("Clip.AIF");
+    std::fs::write(&path, b"FORM").unwrap();
+    let request = Gemini::new("test-key")
+        .unwrap()
+        .generate_content()
+        .with_user_message("Transcribe both clips")
+        .with_audio_from_path(&path)
+        .unwrap()
+        .with_audio_bytes(b"fLaC", "audio/FLAC")
+        .unwrap()
+        .build();
+    assert_eq!(
+        serde_json::to_value(&request.contents).unwrap(),
+        json!([{"role": "user", "parts": [
+            {"text": "Transcribe both clips"},
+            {"inlineData": {"mimeType": "audio/aiff", "data": "Rk9STQ=="}},
+            {"inlineData": {"mimeType": "audio/flac", "data": "ZkxhQw=="}}
+        ]}])
+    );
+
+    // Every extension maps to an accepted type
+    for extension in ["wav", "mp3", "aiff", "aac", "ogg", "flac"] {
+        let mime_type = audio_mime_type(format!("clip.{extension}")).unwrap();
+        assert!(AUDIO_MIME_TYPES.contains(&mime_type), "{mime_type}");
+    }
+    assert_eq!(audio_mime_type("clip.m4a"), None);
+
+    let client = Gemini::new("test-key").unwrap();
+    let error = client
+        .generate_content()
+        .with_audio_bytes(b"opus", "audio/opus")
+        .err()
+        .unwrap();
+    assert!(
+        matches!(&error, AudioError::UnsupportedAudioType { audio_type } if audio_type == "audio/opus"),
+        "{error}"
+    );
+    let error = client
+        .generate_content()
+        .with_audio_from_path(dir.join("missing.wav"))
+        .err()
+        .unwrap();
+    assert!(matches!(error, AudioError::ReadAudio { .. }), "{error}");
+    // The limit applies to the encoded size
+    assert!(client
+        .generate_content()
+        .with_audio_bytes(vec![0; MAX_INLINE_AUDIO_BYTES / 4 * 3], "audio/wav")
+        .is_ok());
+    let error = client
+        .generate_content()
+        .with_audio_bytes(vec![0; MAX_INLINE_AUDIO_BYTES / 4 * 3 + 1], "audio/wav")
+        .err()
+        .unwrap();
+    assert!(
+        matches!(error, AudioError::AudioTooLarge { size, limit }
+            if size == MAX_INLINE_AUDIO_BYTES + 4 && limit == MAX_INLINE_AUDIO_BYTES),
+        "{error}"
+    );
+}
+
+#[test]
+fn test_documents_keep_their_place_between_messages() {
+    use crate::{
+        generation::documents::{
+            document_mime_type, pdf_page_count, MAX_DOCUMENT_PAGES, MAX_INLINE_DOCUMENT_BYTES,
+        },
+        DocumentError,
+    };
+
+    let pdf = |pages: usize| {
+        let mut pdf = b"%PDF-1.7\n1 0 obj << /Type /Pages /Count 1 >> endobj\n".to_vec();
+        for page in 0..pages {
+            pdf.extend(format!("{} 0 obj <</Type/Page /Parent 1 0 R>> endobj\n", page + 2).bytes());
+        }
+        pdf
+    };
+    let dir = spill_dir("documents");
+    let path = dir.join("contract.PDF");
+    std::fs::write(&path, pdf(2)).unwrap();
+    let request = Gemini::new("test-key")
+        .unwrap()
+        .generate_content()
+        .with_user_message("Here is the contract:")
+        .with_document_from_path(&path)
+        .unwrap()
+        .with_document_bytes("a,b\n1,2\n", "text/CSV")
+        .unwrap()
+        .with_user_message("When can either party terminate it?")
+        .build();
+    let contents = serde_json::to_value(&request.contents).unwrap();
+    assert_eq!(
+        contents[0]["parts"][0],
+        json!({"text": "Here is the contract:"})
+    );
+    assert_eq!(
+        contents[0]["parts"][1]["inlineData"]["mimeType"],
+        "application/pdf"
+    );
+    assert_eq!(
+        contents[0]["parts"][2],
+        json!({"inlineData": {"mimeType": "text/csv", "data": "YSxiCjEsMgo="}})
+    );
+    assert_eq!(
+        contents[1],
+        json!({"role": "user", "parts": [{"text": "When can either party terminate it?"}]})
+    );
+
+    assert_eq!(pdf_page_count(&pdf(3)), Some(3));
+    assert_eq!(pdf_page_count(b"%PDF-1.7 compressed"), None);
+    for extension in ["pdf", "txt", "md", "markdown", "csv"] {
+        assert!(document_mime_type(format!("notes.{extension}")).is_some());
+    }
+
+    let client = Gemini::new("test-key").unwrap();
+    let add = |bytes: Vec<u8>, mime_type: &str| {
+        client
+            .generate_content()
+            .with_document_bytes(bytes, mime_type)
+            .err()
+            .unwrap()
+    };
+    let error = add(b"<html>".to_vec(), "text/html");
+    assert!(
+        matches!(&error, DocumentError::UnsupportedDocumentType { document_type } if document_type == "text/html"),
+        "{error}"
+    );
+    let error = add(b"PK\x03\x04".to_vec(), "application/pdf");
+    assert!(matches!(error, DocumentError::NotAPdf), "{error}");
+    let error = add(pdf(MAX_DOCUMENT_PAGES + 1), "application/pdf");
+    assert!(
+        matches!(error, DocumentError::TooManyPages { pages, .. } if pages == MAX_DOCUMENT_PAGES + 1),
+        "{error}"
+    );
+    // The limit applies to the encoded size
+    assert!(client
+        .generate_content()
+        .with_document_bytes(vec![b'a'; MAX_INLINE_DOCUMENT_BYTES / 4 * 3], "text/plain")
+        .is_ok());
+    let error = add(
+        vec![b'a'; MAX_INLINE_DOCUMENT_BYTES / 4 * 3 + 1],
+        "text/plain",
+    );
+    assert!(
+        matches!(error, DocumentError::DocumentTooLarge { size, .. } if size == MAX_INLINE_DOCUMENT_BYTES + 4),
+        "{error}"
+    );
+    assert!(
+        error.to_string().contains("upload it with the Files API"),
+        "{error}"
+    );
+    let error = client
+        .generate_content()
+        .with_document_from_path(dir.join("slides.pptx"))
+        .err()
+        .unwrap();
+    assert!(
+        matches!(&error, DocumentError::UnsupportedDocumentType { document_type } if document_type == "pptx"),
+        "{error}"
+    );
+    let error = client
+        .generate_content()
+        .with_document_from_path(dir.join("missing.pdf"))
+        .err()
+        .unwrap();
+    assert!(
+        matches!(error, DocumentError::ReadDocument { .. }),
+        "{error}"
+    );
+}
+
+#[test]
+fn test_video_clip_and_frame_rate_reach_the_wire() {
+    use crate::{
+        generation::video::{format_offset, parse_offset},
+        Video, VideoError,
+    };
+
+    let request = Gemini::new("test-key")
+        .unwrap()
+        .generate_content()
+        .with_user_message("Summarize both clips")
+        .with_video(
+            Video::new("https://example.com/files/abc", "video/mp4")
+                .with_clip(Duration::from_secs(90), Duration::from_millis(120_500))
+                .with_fps(2.5),
+        )
+        .unwrap()
+        .with_video(Video::youtube(
+            "https://www.youtube.com/watch?v=9hE5-98ZeCg",
+        ))
+        .unwrap()
+        .build();
+    assert_eq!(
+        serde_json::to_value(&request.contents).unwrap(),
+        json!([{"role": "user", "parts": [
+            {"text": "Summarize both clips"},
+            {
+                "fileData": {"mimeType": "video/mp4", "fileUri": "https://example.com/files/abc"},
+                "videoMetadata": {"startOffset": "90s", "endOffset": "120.5s", "fps": 2.5}
+            },
+            {"fileData": {"fileUri": "https://www.youtube.com/watch?v=9hE5-98ZeCg"}}
+        ]}])
+    );
+
+    let add = |video: Video| {
+        Gemini::new("test-key")
+            .unwrap()
+            .generate_content()
+            .with_video(video)
+            .err()
+            .unwrap()
+    };
+    let video = || Video::new("https://example.com/files/abc", "video/mp4");
+    for offset in ["1m30s", "1.s", ".5s", "abc", "90", "1.0000000001s"] {
+        assert_eq!(
+            add(video().with_start_offset(offset)),
+            VideoError::InvalidOffset {
+                offset: offset.to_string()
+            }
+        );
+    }
+    assert_eq!(
+        add(video().with_start_offset("60s").with_end_offset("60.0s")),
+        VideoError::EmptyClip {
+            start: "60s".to_string(),
+            end: "60.0s".to_string()
+        }
+    );
+    for fps in [0.0, -1.0, f64::NAN] {
+        assert!(matches!(
+            add(video().with_fps(fps)),
+            VideoError::InvalidFps { .. }
+        ));
+    }
+
+    for offset in [
+        Duration::ZERO,
+        Duration::from_secs(3600),
+        Duration::from_millis(1_250),
+        Duration::from_nanos(1),
+    ] {
+        assert_eq!(parse_offset(&format_offset(offset)), Ok(offset));
+    }
+    assert_eq!(format_offset(Duration::from_millis(1_250)), "1.25s");
+
+    // Metadata in a response survives a round trip
+    let part = json!({
+        "fileData": {"mimeType": "video/mp4", "fileUri": "files/abc"},
+        "videoMetadata": {"startOffset": "5s", "fps": 1.0}
+    });
+    let parsed: Part = serde_json::from_value(part.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&parsed).unwrap(), part);
+}
+
+#[tokio::test]
+async fn test_policy_layers_interact_in_order() {
+    use crate::{Policy, RateLimit};
+    use futures::TryStreamExt;
+
+    let policy = || {
+        Policy::new()
+            .with_retry(RetryPolicy::new(2).with_initial_backoff(Duration::from_secs(1)))
+            .with_fallback(Model::Gemini25FlashLite)
+    };
+    let client = |server: &MockServer, policy: Policy, clock: &ManualClock| {
+        server
+            .builder()
+            .with_policy(policy)
+            .with_clock(clock.clone())
+            .build()
+            .unwrap()
+    };
+    let ask = async |client: &Gemini| {
+        client
+            .generate_content()
+            .with_user_message("hi")
+            .execute()
+            .await
+    };
+    let paths = |server: &MockServer| -> Vec<String> {
+        server
+            .requests()
+            .iter()
+            .map(|request| request.path.rsplit('/').next().unwrap().to_string())
+            .collect()
+    };
+
+    // Retries run inside a fallback: the fallback model is tried once retries gave up
+    let server = MockServer::start().await;
+    server
+        .push(unavailable())
+        .push(unavailable())
+        .push(answer("fallback answer", 5));
+    let response = ask(&client(&server, policy(), &ManualClock::new()))
+        .await
+        .unwrap();
+    assert_eq!(response.text(), "fallback answer");
+    assert_eq!(
+        paths(&server),
+        [
+            "gemini-2.5-flash:generateContent",
+            "gemini-2.5-flash:generateContent",
+            "gemini-2.5-flash-lite:generateContent"
+        ]
+    );
+    let layers: Vec<_> = response.attempts.attempts.iter().map(|a| a.layer).collect();
+    assert_eq!(
+        layers,
+        [RetryLayer::Initial, RetryLayer::Http, RetryLayer::Fallback]
+    );
+
+    // Errors other than transient ones neither retry nor fall back
+    let server = MockServer::start().await;
+    server.push(MockResponse::json(
+        400,
+        json!({"error": {"code": 400, "message": "bad", "status": "INVALID_ARGUMENT"}}),
+    ));
+    let error = ask(&client(&server, policy(), &ManualClock::new()))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, ClientError::BadResponse { code: 400, .. }),
+        "{error}"
+    );
+    assert_eq!(server.requests().len(), 1);
+
+    // The budget is outermost: once retries spent it, nothing falls back
+    let server = MockServer::start().await;
+    server.push(unavailable()).push(unavailable());
+    let error = ask(&client(
+        &server,
+        policy().with_budget(RetryBudget::new(2)),
+        &ManualClock::new(),
+    ))
+    .await
+    .unwrap_err();
+    let ClientError::RetryBudgetExhausted { usage, .. } = &error else {
+        panic!("unexpected error: {error}");
+    };
+    assert_eq!(usage.attempts[&RetryLayer::Initial], 1);
+    assert_eq!(usage.attempts[&RetryLayer::Http], 1);
+    assert!(!usage.attempts.contains_key(&RetryLayer::Fallback));
+    assert_eq!(server.requests().len(), 2);
+
+    // A call's own budget replaces the policy's
+    let server = MockServer::start().await;
+    server
+        .push(unavailable())
+        .push(unavailable())
+        .push(answer("fallback answer", 5));
+    let response = client(
+        &server,
+        policy().with_budget(RetryBudget::new(2)),
+        &ManualClock::new(),
+    )
+    .generate_content()
+    .with_user_message("hi")
+    .with_retry_budget(RetryBudget::new(3))
+    .execute()
+    .await
+    .unwrap();
+    assert_eq!(response.text(), "fallback answer");
+
+    // Streams fall back until one starts
+    let server = MockServer::start().await;
+    server
+        .push(unavailable())
+        .push(unavailable())
+        .push(MockResponse::sse(vec![text_event("streamed")]));
+    let chunks: Vec<_> = client(&server, policy(), &ManualClock::new())
+        .generate_content()
+        .with_user_message("hi")
+        .execute_stream()
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(chunks[0].text(), "streamed");
+    assert!(paths(&server)[2].starts_with("gemini-2.5-flash-lite:streamGenerateContent"));
+
+    // The rate limit is innermost: retries wait for it too, and the wait is not charged to
+    // the budget's delay
+    let server = MockServer::start().await;
+    server
+        .push(unavailable())
+        .push(answer("first", 5))
+        .push(answer("second", 5));
+    let clock = ManualClock::new();
+    let client = client(
+        &server,
+        policy()
+            .with_rate_limit(RateLimit::new(1, Duration::from_secs(10)))
+            .with_budget(RetryBudget::new(4).with_max_added_delay(Duration::from_secs(2))),
+        &clock,
+    );
+    assert_eq!(ask(&client).await.unwrap().text(), "first");
+    assert_eq!(ask(&client).await.unwrap().text(), "second");
+    assert_eq!(
+        clock.sleeps(),
+        [
+            Duration::from_secs(1),  // backoff
+            Duration::from_secs(9),  // rate limit of the retry
+            Duration::from_secs(10), // rate limit of the second call
+        ]
+    );
+}
+
+#[test]
+fn test_policy_rejects_combinations_that_cannot_work() {
+    use crate::{Policy, PolicyError, RateLimit};
+
+    let build = |builder: GeminiBuilder, policy: Policy| -> Option<PolicyError> {
+        match builder.with_policy(policy).build() {
+            Ok(_) => None,
+            Err(ClientError::InvalidPolicy { source }) => Some(source),
+            Err(error) => panic!("unexpected error: {error}"),
+        }
+    };
+    let builder = || GeminiBuilder::new("test-key");
+
+    assert_eq!(
+        build(
+            builder(),
+            Policy::new().with_rate_limit(RateLimit::new(0, Duration::from_secs(1)))
+        ),
+        Some(PolicyError::EmptyRateLimit)
+    );
+    assert_eq!(
+        build(builder(), Policy::new().with_retry(RetryPolicy::new(0))),
+        Some(PolicyError::NoRetryAttempts)
+    );
+    assert_eq!(
+        build(
+            builder().with_retry_policy(RetryPolicy::new(3)),
+            Policy::new().with_retry(RetryPolicy::new(3))
+        ),
+        Some(PolicyError::ConflictingRetryPolicy)
+    );
+    assert_eq!(
+        build(builder(), Policy::new().with_fallback(Model::Gemini25Flash)),
+        Some(PolicyError::RepeatedFallback {
+            model: Model::Gemini25Flash
+        })
+    );
+    assert_eq!(
+        build(
+            builder(),
+            Policy::new()
+                .with_fallback(Model::Gemini25FlashLite)
+                .with_fallback(Model::Gemini25FlashLite)
+        ),
+        Some(PolicyError::RepeatedFallback {
+            model: Model::Gemini25FlashLite
+        })
+    );
+    assert_eq!(
+        build(
+            builder(),
+            Policy::new()
+                .with_fallback(Model::Gemini25FlashLite)
+                .with_budget(RetryBudget::new(1))
+        ),
+        Some(PolicyError::UnreachableFallback {
+            budget: 1,
+            fallbacks: 1
+        })
+    );
+    assert_eq!(
+        build(builder(), Policy::new().with_budget(RetryBudget::new(0))),
+        Some(PolicyError::NoBudgetAttempts)
+    );
+
+    // A retry policy set on the builder works with the other layers
+    assert_eq!(
+        build(
+            builder().with_retry_policy(RetryPolicy::new(3)),
+            Policy::new()
+                .with_fallback(Model::Gemini25FlashLite)
+                .with_budget(RetryBudget::new(4))
+        ),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_session_title_and_summary_leave_the_history_alone() {
+    use crate::{chat::title::clean_title, Blob};
+
+    let server = MockServer::start().await;
+    server
+        .push(answer(
+            "**Title:** \"Planning a spring trip to Kyoto.\"\nExtra line",
+            5,
+        ))
+        .push(answer(
+            "The user plans a trip to Kyoto. The model suggests temples.\n\n- It also covers food.",
+            5,
+        ));
+    let photo = Content {
+        parts: Some(vec![
+            Part::Text {
+                text: "Where was this taken? I want to go in spring.".into(),
+                thought: None,
+                thought_signature: None,
+            },
+            Part::InlineData {
+                inline_data: Blob::new("image/png", "AAAA"),
+            },
+        ]),
+        role: Some(Role::User),
+    };
+    let session = server.client().start_chat().with_history([
+        photo,
+        Content::text("Kyoto, at Fushimi Inari.").with_role(Role::Model),
+        Content::text("Which temples should I see?").with_role(Role::User),
+        Content::text("Kinkaku-ji and Kiyomizu-dera.").with_role(Role::Model),
+    ]);
+    let history = session.history().to_vec();
+
+    let title = session.generate_title(20).await.unwrap();
+    assert_eq!(title, "Planning a spring…");
+    let summary = session.generate_summary(2).await.unwrap();
+    assert_eq!(
+        summary,
+        "The user plans a trip to Kyoto. The model suggests temples."
+    );
+    assert_eq!(session.history(), history.as_slice());
+    assert_eq!(session.turns().len(), 2);
+
+    let requests = server.requests();
+    assert!(requests[0]
+        .path
+        .ends_with("gemini-2.5-flash-lite:generateContent"));
+    let body = requests[0].json();
+    let instruction = body["systemInstruction"]["parts"][0]["text"]
+        .as_str()
+        .unwrap();
+    assert!(
+        instruction.contains("at most 20 characters"),
+        "{instruction}"
+    );
+    assert_eq!(
+        body["contents"],
+        json!([{"role": "user", "parts": [{"text":
+            "User: Where was this taken? I want to go in spring.\n[image: previously shared image/png]\n\n\
+             Model: Kyoto, at Fushimi Inari."
+        }]}])
+    );
+    let body = requests[1].json();
+    let instruction = body["systemInstruction"]["parts"][0]["text"]
+        .as_str()
+        .unwrap();
+    assert!(
+        instruction.contains("at most 2 sentence(s)"),
+        "{instruction}"
+    );
+    let transcript = body["contents"][0]["parts"][0]["text"].as_str().unwrap();
+    assert!(transcript.ends_with("Model: Kinkaku-ji and Kiyomizu-dera."));
+
+    // Nothing to describe before the first exchange
+    let error = server
+        .client()
+        .start_chat()
+        .generate_title(40)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ClientError::EmptyConversation), "{error}");
+    assert_eq!(server.requests().len(), 2);
+
+    // Post-processing
+    assert_eq!(clean_title("  'Rust lifetimes'.  ", 40), "Rust lifetimes");
+    assert_eq!(
+        clean_title("# Borrow checker tips", 40),
+        "Borrow checker tips"
+    );
+    assert_eq!(clean_title("Supercalifragilistic", 8), "Superca…");
+    assert_eq!(clean_title("One two three", 8), "One two…");
+    assert_eq!(clean_title("Anything", 0), "");
+}
+
+#[tokio::test]
+async fn test_sanitize_output_strips_controls_across_stream_chunks() {
+    use crate::SanitizePolicy;
+    use futures::TryStreamExt;
+    use std::collections::BTreeMap;
+
+    let server = MockServer::start().await;
+    let raw = "Hi\u{0}!\r\nsee \u{202E}txt.exe\u{7}";
+    server.push(answer(raw, 10)).push(answer(raw, 10));
+    let sanitized =
+        |policy: SanitizePolicy| server.builder().sanitize_output(policy).build().unwrap();
+
+    // Off by default: the text is exactly what the model produced
+    let response = server
+        .client()
+        .generate_content()
+        .with_user_message("Hi")
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(response.text(), raw);
+    assert!(response.meta.sanitized.is_empty());
+
+    let response = sanitized(SanitizePolicy::new().strip_bidi(true))
+        .generate_content()
+        .with_user_message("Hi")
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(response.text(), "Hi!\r\nsee txt.exe");
+    assert_eq!(
+        response.meta.sanitized,
+        BTreeMap::from([('\u{0}', 1), ('\u{7}', 1), ('\u{202E}', 1)])
+    );
+
+    // A carriage return ending a chunk waits for the next one; a lone one is replaced, one
+    // before a newline is kept, and one ending the stream comes in a last chunk
+    server.push(MockResponse::sse([
+        text_event("one\r"),
+        text_event("\ntwo\r"),
+        text_event("three\u{9B}\r"),
+        text_event(""),
+        text_event("\r"),
+    ]));
+    let client = sanitized(SanitizePolicy::new().replace_controls('\u{FFFD}'));
+    let chunks: Vec<_> = client
+        .generate_content()
+        .with_user_message("Count")
+        .execute_stream()
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    let texts: Vec<String> = chunks.iter().map(|chunk| chunk.text()).collect();
+    assert_eq!(
+        texts,
+        [
+            "one",
+            "\r\ntwo",
+            "\u{FFFD}three\u{FFFD}",
+            "",
+            "\u{FFFD}",
+            "\u{FFFD}"
+        ]
+    );
+    assert_eq!(
+        chunks[2].meta.sanitized,
+        BTreeMap::from([('\r', 1), ('\u{9B}', 1)])
+    );
+    assert_eq!(chunks[5].meta.sanitized, BTreeMap::from([('\r', 1)]));
+    // Chunked the same as whole
+    let whole = SanitizePolicy::new()
+        .replace_controls('\u{FFFD}')
+        .sanitize("one\r\ntwo\rthree\u{9B}\r\r");
+    assert_eq!(texts.concat(), whole);
+}
+
+#[tokio::test]
+async fn test_user_data_follows_every_request_of_a_concurrent_batch() {
+    use futures::TryStreamExt;
+
+    #[derive(Debug, PartialEq)]
+    struct Row(usize);
+    struct Batch(&'static str);
+
+    let server = MockServer::start().await;
+    // Rows 0, 7, and 14 fail before they are sent; of the 17 others, 5 are rejected
+    let rejected = || {
+        MockResponse::json(
+            400,
+            json!({"error": {"code": 400, "message": "bad request"}}),
+        )
+    };
+    for i in 0..17 {
+        server.push(if i < 5 { rejected() } else { answer("ok", 5) });
+    }
+    let client = server.client();
+    let requests = (0..20).map(|i| {
+        let temperature = if i % 7 == 0 { 5.0 } else { 0.5 };
+        client
+            .generate_content()
+            .with_user_message(format!("row {i}"))
+            .with_temperature(temperature)
+            .with_user_data(Row(i))
+            .with_user_data(Batch("nightly"))
+            .execute()
+    });
+    let results = futures::future::join_all(requests).await;
+    let mut failures = 0;
+    for (i, result) in results.iter().enumerate() {
+        match result {
+            Ok(response) => {
+                assert_eq!(response.meta.user_data::<Row>(), Some(&Row(i)));
+                assert_eq!(response.meta.user_data.get::<Batch>().unwrap().0, "nightly");
+            }
+            Err(error) => {
+                failures += 1;
+                assert_eq!(error.user_data::<Row>(), Some(&Row(i)));
+                assert_eq!(error.user_data::<Batch>().unwrap().0, "nightly");
+                match error.inner() {
+                    ClientError::InvalidGenerationConfig { .. } => assert_eq!(i % 7, 0),
+                    ClientError::BadResponse { code: 400, .. } => {}
+                    other => panic!("unexpected error: {other}"),
+                }
+            }
+        }
+    }
+    assert_eq!(failures, 3 + 5);
+    // Never sent to the API
+    assert!(server
+        .requests()
+        .iter()
+        .all(|request| !String::from_utf8_lossy(&request.body).contains("nightly")));
+
+    // Every chunk of a stream and a failing stream carry it too
+    server.push(MockResponse::sse([text_event("a"), text_event("b")]));
+    server.push(rejected());
+    let chunks: Vec<GenerationResponse> = client
+        .generate_content()
+        .with_user_message("stream")
+        .with_user_data(Row(20))
+        .execute_stream()
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(chunks.len(), 2);
+    assert!(chunks
+        .iter()
+        .all(|chunk| chunk.meta.user_data::<Row>() == Some(&Row(20))));
+    let error = client
+        .generate_content()
+        .with_user_message("stream")
+        .with_user_data(Row(21))
+        .execute_stream()
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(error.user_data::<Row>(), Some(&Row(21)));
+    assert!(!error.is_retryable());
+
+    // Without user data, errors are not wrapped
+    server.push(rejected());
+    let error = client
+        .generate_content()
+        .with_user_message("plain")
+        .execute()
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ClientError::BadResponse { code: 400, .. }));
+}
+
+#[tokio::test]
+async fn test_retries_wait_as_long_as_the_api_asks() {
+    use crate::retry::policy::parse_retry_after;
+
+    let quota_exceeded = |delay: &str| {
+        MockResponse::json(
+            429,
+            json!({"error": {
+                "code": 429,
+                "message": "Quota exceeded",
+                "status": "RESOURCE_EXHAUSTED",
+                "details": [{
+                    "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                    "retryDelay": delay
+                }]
+            }}),
+        )
+    };
+    let server = MockServer::start().await;
+    server
+        .push(quota_exceeded("2.5s"))
+        .push(unavailable().with_header("retry-after", "7"))
+        .push(answer("ok", 5));
+    let clock = ManualClock::new();
+    let client = server
+        .builder()
+        .with_retry_policy(RetryPolicy::new(3))
+        .with_clock(clock.clone())
+        .build()
+        .unwrap();
+    let generate = || client.generate_content().with_user_message("hi").execute();
+    assert_eq!(generate().await.unwrap().text(), "ok");
+    assert_eq!(
+        clock.sleeps(),
+        [Duration::from_millis(2500), Duration::from_secs(7)]
+    );
+
+    // A date is read by the client's clock
+    let date = (clock.now_utc() + Duration::from_secs(20))
+        .format(&time::format_description::well_known::Rfc2822)
+        .unwrap();
+    server
+        .push(unavailable().with_header("retry-after", &date))
+        .push(answer("ok", 5));
+    generate().await.unwrap();
+    // The date drops the fraction of the second
+    assert!((Duration::from_secs(19)..=Duration::from_secs(20)).contains(&clock.sleeps()[2]));
+
+    // Client errors and codes other than 429, 500, 502, 503, and 504 fail right away
+    for code in [400, 401, 403, 501] {
+        let requests = server.requests().len();
+        server.push(MockResponse::json(
+            code,
+            json!({"error": {"code": code, "message": "no"}}),
+        ));
+        let error = generate().await.unwrap_err();
+        assert!(matches!(error, ClientError::BadResponse { code: c, .. } if c == code));
+        assert_eq!(server.requests().len(), requests + 1);
+    }
+
+    // The final error reports every attempt and what the API asked for
+    server
+        .push(unavailable())
+        .push(unavailable())
+        .push(quota_exceeded("30s"));
+    let error = generate().await.unwrap_err();
+    let ClientError::RetriesExhausted { attempts, .. } = &error else {
+        panic!("unexpected error: {error:?}");
+    };
+    assert_eq!(attempts.attempts.len(), 3);
+    assert_eq!(error.retry_delay(), Some(Duration::from_secs(30)));
+
+    // Jitter takes up to its share off the backoff
+    let clock = ManualClock::new();
+    let client = server
+        .builder()
+        .with_retry_policy(RetryPolicy::new(3).with_jitter(0.5))
+        .with_clock(clock.clone())
+        .build()
+        .unwrap();
+    server
+        .push(unavailable())
+        .push(unavailable())
+        .push(answer("ok", 5));
+    client
+        .generate_content()
+        .with_user_message("hi")
+        .execute()
+        .await
+        .unwrap();
+    let sleeps = clock.sleeps();
+    assert!((Duration::from_millis(250)..=Duration::from_millis(500)).contains(&sleeps[0]));
+    assert!((Duration::from_millis(500)..=Duration::from_secs(1)).contains(&sleeps[1]));
+
+    let now = time::OffsetDateTime::from_unix_timestamp(1_792_152_000).unwrap();
+    assert_eq!(
+        parse_retry_after("Fri, 16 Oct 2026 12:00:42 GMT", now),
+        Some(Duration::from_secs(42))
+    );
+    assert_eq!(
+        parse_retry_after("Fri, 16 Oct 2026 11:00:00 GMT", now),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(parse_retry_after("soon", now), None);
+}
+
+#[test]
+fn test_backoff_caps_before_overflowing() {
+    let policy = RetryPolicy::new(100)
+        .with_initial_backoff(Duration::from_secs(1))
+        .with_max_backoff(Duration::from_secs(30))
+        .with_multiplier(1e300);
+    assert_eq!(policy.backoff(0), Duration::from_secs(1));
+    assert_eq!(policy.backoff(1), Duration::from_secs(30));
+    assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(30));
+    assert_eq!(
+        RetryPolicy::new(3).with_multiplier(10.0).backoff(1_000),
+        Duration::from_secs(30)
+    );
+
+    // Negative and NaN factors keep the delay constant
+    for multiplier in [-2.0, f64::NAN] {
+        let policy = RetryPolicy::new(3).with_multiplier(multiplier);
+        assert_eq!(policy.multiplier, 1.0);
+        assert_eq!(policy.backoff(5), Duration::from_millis(500));
+    }
+    // Set directly, they still never panic
+    let policy = RetryPolicy {
+        multiplier: f64::NAN,
+        ..RetryPolicy::new(3)
+    };
+    assert_eq!(policy.backoff(3), Duration::ZERO);
+}
+
+#[test]
+fn test_jitter_is_clamped_and_ignores_non_finite_shares() {
+    assert_eq!(RetryPolicy::new(3).with_jitter(2.0).jitter, 1.0);
+    assert_eq!(RetryPolicy::new(3).with_jitter(-1.0).jitter, 0.0);
+    let error = ClientError::BadResponse {
+        code: 503,
+        description: None,
+        retry_after: None,
+    };
+    for jitter in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        let policy = RetryPolicy::new(3).with_jitter(jitter);
+        assert_eq!(policy.jitter, 0.0);
+        assert_eq!(policy.delay(0, &error), Some(Duration::from_millis(500)));
+        // Set directly, the delay is still the plain backoff
+        let policy = RetryPolicy {
+            jitter,
+            ..RetryPolicy::new(3)
+        };
+        assert_eq!(policy.delay(1, &error), Some(Duration::from_secs(1)));
+    }
+}
+
+#[tokio::test]
+async fn test_retries_give_up_when_the_api_asks_to_wait_too_long() {
+    let server = MockServer::start().await;
+    server.push(unavailable().with_header("retry-after", "3600"));
+    let clock = ManualClock::new();
+    let client = server
+        .builder()
+        .with_retry_policy(RetryPolicy::new(3).with_max_backoff(Duration::from_secs(60)))
+        .with_clock(clock.clone())
+        .build()
+        .unwrap();
+
+    let error = client
+        .generate_content()
+        .with_user_message("hi")
+        .execute()
+        .await
+        .unwrap_err();
+    let ClientError::RetriesExhausted { attempts, .. } = &error else {
+        panic!("unexpected error: {error:?}");
+    };
+    assert_eq!(attempts.attempts.len(), 1);
+    assert_eq!(error.retry_delay(), Some(Duration::from_secs(3600)));
+    assert!(clock.sleeps().is_empty());
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_requests_creating_resources_retry_only_when_asked() {
+    let cached_content = || {
+        MockResponse::json(
+            200,
+            json!({
+                "name": "cachedContents/manual",
+                "model": "models/gemini-2.5-flash",
+                "createTime": "2025-06-02T08:00:00Z",
+                "updateTime": "2025-06-02T08:00:00Z",
+                "expireTime": "2025-06-02T09:00:00Z",
+                "usageMetadata": {"totalTokenCount": 4}
+            }),
+        )
+    };
+    let server = MockServer::start().await;
+    let client = server
+        .builder()
+        .with_retry_policy(RetryPolicy::new(3))
+        .with_clock(ManualClock::new())
+        .build()
+        .unwrap();
+    let create_cache = || {
+        client
+            .create_cache()
+            .with_user_message("manual")
+            .with_ttl(Duration::from_secs(3600))
+    };
+
+    // A failure after the API created the cached content would create a second one
+    server.push(unavailable());
+    let Err(error) = create_cache().execute().await else {
+        panic!("the cached content was created");
+    };
+    let CacheError::Client { source } = &error else {
+        panic!("unexpected error: {error:?}");
+    };
+    assert!(matches!(
+        **source,
+        ClientError::BadResponse { code: 503, .. }
+    ));
+    assert_eq!(server.requests().len(), 1);
+
+    server.push(unavailable()).push(cached_content());
+    create_cache().with_retries().execute().await.unwrap();
+    assert_eq!(server.requests().len(), 3);
+
+    // Starting an upload session
+    server.push(unavailable());
+    let Err(error) = client.create_file(b"bytes".to_vec()).upload().await else {
+        panic!("the file was uploaded");
+    };
+    assert!(
+        matches!(
+            error,
+            crate::files::Error::Client {
+                source: ClientError::BadResponse { code: 503, .. }
+            }
+        ),
+        "{error:?}"
+    );
+    assert_eq!(server.requests().len(), 4);
+
+    // Idempotent requests still retry
+    server.push(unavailable()).push(answer("ok", 5));
+    client
+        .generate_content()
+        .with_user_message("hi")
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(server.requests().len(), 6);
+}
+
+#[tokio::test]
+async fn test_rate_limit_paces_requests_and_tokens() {
+    use crate::{Policy, PolicyError, RateLimit};
+
+    let server = MockServer::start().await;
+    let clock = ManualClock::new();
+    let client = server
+        .builder()
+        .with_rate_limit(60, 1000)
+        .with_clock(clock.clone())
+        .build()
+        .unwrap();
+    let ask = |client: &Gemini| client.generate_content().with_user_message("hi").execute();
+
+    // 1500 of 1000 tokens per minute used: the next request waits until 500 are paid back,
+    // for 30s, on every clone of the client
+    server.push(answer("big", 1500)).push(answer("small", 100));
+    assert_eq!(ask(&client).await.unwrap().text(), "big");
+    assert_eq!(ask(&client.clone()).await.unwrap().text(), "small");
+    assert_eq!(clock.sleeps(), [Duration::from_secs(30)]);
+
+    // A call can fail instead of waiting
+    let error = client
+        .generate_content()
+        .with_user_message("hi")
+        .without_rate_limit_wait()
+        .execute()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, ClientError::RateLimited { wait } if wait == Duration::from_secs(6)),
+        "{error}"
+    );
+    assert_eq!(server.requests().len(), 2);
+
+    // Requests are served in the order they arrive, one slot per period
+    let clock = ManualClock::new();
+    let client = server
+        .builder()
+        .with_rate_limit(1, None)
+        .with_clock(clock.clone())
+        .build()
+        .unwrap();
+    for i in 0..3 {
+        server.push(answer(&format!("answer {i}"), 5));
+    }
+    let calls = (0..3).map(|i| {
+        client
+            .generate_content()
+            .with_user_message(format!("question {i}"))
+            .execute()
+    });
+    let answers: Vec<String> = futures::future::join_all(calls)
+        .await
+        .into_iter()
+        .map(|response| response.unwrap().text())
+        .collect();
+    assert_eq!(answers, ["answer 0", "answer 1", "answer 2"]);
+    let questions: Vec<_> = server.requests()[2..]
+        .iter()
+        .map(|request| request.json()["contents"][0]["parts"][0]["text"].clone())
+        .collect();
+    assert_eq!(questions, ["question 0", "question 1", "question 2"]);
+    assert_eq!(
+        clock.sleeps(),
+        [Duration::from_secs(60), Duration::from_secs(60)]
+    );
+
+    // The limit is set in one place
+    let error = server
+        .builder()
+        .with_rate_limit(15, None)
+        .with_policy(Policy::new().with_rate_limit(RateLimit::per_minute(15)))
+        .build()
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error,
+        ClientError::InvalidPolicy {
+            source: PolicyError::ConflictingRateLimit
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_prompt_linting() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let kinds = |warnings: Vec<LintWarning>| -> Vec<LintKind> {
+        warnings.into_iter().map(|warning| warning.kind).collect()
+    };
+
+    // A clean request has nothing to report
+    let clean = client
+        .generate_content()
+        .with_system_instruction("You are a terse assistant.")
+        .with_user_message("What is the capital of France?");
+    assert!(clean.lint().is_empty());
+
+    let empty = client.generate_content().with_user_message("  ").lint();
+    assert_eq!(kinds(empty.clone()), [LintKind::EmptyMessage]);
+    assert_eq!(empty[0].severity, LintSeverity::Error);
+    assert_eq!(empty[0].location.as_deref(), Some("contents[0]"));
+
+    let placeholder = client
+        .generate_content()
+        .with_user_message("Summarize the ticket of {{ customer.name }}")
+        .lint();
+    assert_eq!(
+        kinds(placeholder.clone()),
+        [LintKind::UnresolvedPlaceholder]
+    );
+    assert_eq!(
+        placeholder[0].to_string(),
+        "error[unresolved-placeholder] at contents[0].parts[0]: \
+         unresolved placeholder(s) {{ customer.name }}"
+    );
+
+    let duplicate = client
+        .generate_content()
+        .with_user_message("Translate to German")
+        .with_model_message("Sure, send the text.")
+        .with_user_message("Translate to German")
+        .lint();
+    assert_eq!(kinds(duplicate.clone()), [LintKind::DuplicateTurn]);
+    assert_eq!(duplicate[0].location.as_deref(), Some("contents[2]"));
+
+    let instruction = client
+        .generate_content()
+        .with_user_message("You are a helpful assistant. Answer briefly.")
+        .lint();
+    assert_eq!(
+        kinds(instruction),
+        [LintKind::SystemInstructionAsUserMessage]
+    );
+
+    let prose = client
+        .generate_content()
+        .with_user_message("Describe the weather in full sentences.")
+        .with_response_mime_type("application/json")
+        .lint();
+    assert_eq!(kinds(prose), [LintKind::JsonModeWithProse]);
+
+    // Denied kinds fail the request before it is sent, others do not
+    let error = client
+        .generate_content()
+        .with_user_message("Hello {{name}}")
+        .deny_lints(&[LintKind::UnresolvedPlaceholder])
+        .execute()
+        .await
+        .unwrap_err();
+    let ClientError::LintDenied { warnings } = &error else {
+        panic!("expected LintDenied, got {error:?}");
+    };
+    assert_eq!(kinds(warnings.clone()), [LintKind::UnresolvedPlaceholder]);
+    assert!(server.requests().is_empty());
+
+    server.push(answer("Hi", 3));
+    let response = client
+        .generate_content()
+        .with_user_message("You are a pirate. Say hi.")
+        .deny_lints(&[LintKind::UnresolvedPlaceholder])
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(response.text(), "Hi");
+
+    // Custom rules run after the default ones
+    struct MentionsProduct;
+    impl LintRule for MentionsProduct {
+        fn check(&self, request: &GenerateContentRequest) -> Vec<LintWarning> {
+            let text = serde_json::to_string(&request.contents).unwrap();
+            match text.contains("Acme") {
+                true => Vec::new(),
+                false => vec![LintWarning::new(
+                    LintKind::Custom("product-name".into()),
+                    LintSeverity::Warning,
+                    "the prompt does not name the product",
+                )],
+            }
+        }
+    }
+    let custom = client
+        .generate_content()
+        .with_user_message("  ")
+        .with_lint_rule(MentionsProduct)
+        .lint();
+    assert_eq!(
+        kinds(custom),
+        [
+            LintKind::EmptyMessage,
+            LintKind::Custom("product-name".into())
+        ]
+    );
+    let error = client
+        .generate_content()
+        .with_user_message("Write a slogan")
+        .with_lint_rule(MentionsProduct)
+        .deny_lints(&[LintKind::Custom("product-name".into())])
+        .execute_stream()
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(error, ClientError::LintDenied { .. }));
+    assert_eq!(server.requests().len(), 1);
+}
+
+/// Inline media are saved to files named by their content, with extensions from their types
+#[tokio::test]
+async fn test_save_media_to_dir() {
+    use crate::{generation::media::extension_for, MediaError, SavedMedia};
+    use base64::{engine::general_purpose, Engine as _};
+
+    let dir = std::env::temp_dir()
+        .join(format!("gemini-media-{}", std::process::id()))
+        .join("nested");
+    let inline = |mime_type: &str, bytes: &[u8]| {
+        json!({"inlineData": {
+            "mimeType": mime_type,
+            "data": general_purpose::STANDARD.encode(bytes),
+        }})
+    };
+    let response: GenerationResponse = serde_json::from_value(json!({"candidates": [
+        {"content": {"role": "model", "parts": [
+            inline("image/png", b"png bytes"),
+            {"text": "a caption"},
+            inline("audio/L16;codec=pcm;rate=24000", b"pcm bytes"),
+        ]}},
+        {"content": {"role": "model", "parts": [
+            inline("image/jpeg", b"jpeg bytes"),
+            inline("application/x-unknown", b"other bytes"),
+            inline("image/png", b"png bytes"),
+        ]}},
+    ]}))
+    .unwrap();
+
+    // The directory is created, and identical content is saved once
+    let saved = response.save_all_media(&dir).await.unwrap();
+    let names: Vec<String> = saved
+        .iter()
+        .map(|media| {
+            media
+                .path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+    assert_eq!(
+        names[0],
+        "d013614dc14a37ee20fe92005737ab7d3427e7e93580ad56ef8a42205e7f7a4e.png"
+    );
+    let extensions: Vec<&str> = names.iter().map(|name| &name[65..]).collect();
+    assert_eq!(extensions, ["png", "pcm", "jpg", "bin", "png"]);
+    assert_eq!(saved[0], saved[4]);
+    assert_eq!(
+        saved[1],
+        SavedMedia {
+            path: dir.join(&names[1]),
+            mime_type: "audio/L16;codec=pcm;rate=24000".into(),
+            size: 9,
+        }
+    );
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 4);
+    assert_eq!(std::fs::read(&saved[2].path).unwrap(), b"jpeg bytes");
+
+    // Saving again finds the same files
+    let part = &response.candidates[1].content.parts.as_ref().unwrap()[0];
+    assert_eq!(part.save_to_dir(&dir).await.unwrap(), saved[2].path);
+    let text = &response.candidates[0].content.parts.as_ref().unwrap()[1];
+    let error = text.save_to_dir(&dir).await.unwrap_err();
+    assert!(matches!(error, MediaError::NotInlineData));
+
+    // A file with other content at the path is never overwritten
+    std::fs::write(&saved[3].path, b"tampered").unwrap();
+    let error = response.save_all_media(&dir).await.unwrap_err();
+    assert!(
+        matches!(&error, MediaError::ContentMismatch { path } if *path == saved[3].path),
+        "{error:?}"
+    );
+    assert_eq!(std::fs::read(&saved[3].path).unwrap(), b"tampered");
+
+    assert_eq!(extension_for("IMAGE/WEBP"), "webp");
+    assert_eq!(extension_for("video/webm"), "webm");
+    assert_eq!(extension_for("application/octet-stream"), "bin");
+    std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+}
+
+/// The malformed call of a candidate, from its finish message or else its text
+#[test]
+fn test_malformed_function_call_accessor() {
+    let fixture = |name: &str| -> GenerationResponse {
+        let path = format!(
+            "{}/tests/fixtures/generate_content/{name}.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    };
+
+    let with_message = fixture("malformed_function_call_with_message");
+    assert_eq!(
+        with_message.malformed_function_call().as_deref(),
+        Some("print(default_api.lookup(city=Paris))")
+    );
+    assert_eq!(
+        fixture("malformed_function_call")
+            .malformed_function_call()
+            .as_deref(),
+        Some("")
+    );
+    assert_eq!(fixture("text_basic").malformed_function_call(), None);
+
+    let in_text: GenerationResponse = serde_json::from_value(json!({"candidates": [{
+        "content": {"role": "model", "parts": [{"text": "lookup(city=\"Paris\""}]},
+        "finishReason": "MALFORMED_FUNCTION_CALL",
+    }]}))
+    .unwrap();
+    assert_eq!(
+        in_text.candidates[0].malformed_function_call().as_deref(),
+        Some("lookup(city=\"Paris\"")
+    );
+}
+
+fn malformed_call(raw: &str) -> MockResponse {
+    MockResponse::json(
+        200,
+        json!({"candidates": [{
+            "content": {"role": "model"},
+            "finishReason": "MALFORMED_FUNCTION_CALL",
+            "finishMessage": format!("Malformed function call: {raw}"),
+        }]}),
+    )
+}
+
+/// With recovery, a malformed call is answered with a corrective turn quoting it and listing
+/// the declared functions, until the model calls correctly or runs out of retries
+#[tokio::test]
+async fn test_malformed_function_call_recovery() {
+    #[derive(schemars::JsonSchema, Serialize)]
+    #[allow(dead_code)]
+    struct City {
+        city: String,
+    }
+
+    let lookup = || {
+        FunctionDeclaration::new("lookup", "Looks up the weather of a city", None)
+            .with_parameters::<City>()
+    };
+    let registry = ToolRegistry::new()
+        .with_function(lookup(), |_| async { Ok(json!({"weather": "sunny"})) })
+        .with_malformed_call_recovery(2);
+
+    // Malformed first, then correct
+    let server = MockServer::start().await;
+    server
+        .push(malformed_call("print(default_api.lookup(city=Paris))"))
+        .push(model_parts(
+            json!([{"functionCall": {"name": "lookup", "args": {"city": "Paris"}}}]),
+            "STOP",
+        ))
+        .push(answer("It is sunny in Paris.", 30));
+    let response = server
+        .client()
+        .generate_content()
+        .with_user_message("Weather in Paris?")
+        .execute_with_tools(&registry)
+        .await
+        .unwrap();
+    assert_eq!(response.text(), "It is sunny in Paris.");
+    assert_eq!(response.tool_transcript.len(), 1);
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    let contents = requests[1].json()["contents"].clone();
+    assert_eq!(contents.as_array().unwrap().len(), 2);
+    assert_eq!(contents[1]["role"], "user");
+    let correction = contents[1]["parts"][0]["text"].as_str().unwrap();
+    assert!(
+        correction.contains("print(default_api.lookup(city=Paris))"),
+        "{correction}"
+    );
+    assert!(
+        correction.ends_with(
+            r#"- lookup({"properties":{"city":{"type":"string"}},"required":["city"],"type":"object"}): Looks up the weather of a city
+"#
+        ),
+        "{correction}"
+    );
+    // The corrective turn stays in the conversation
+    assert_eq!(requests[2].json()["contents"].as_array().unwrap().len(), 4);
+
+    // Always malformed: the loop gives up after two corrections
+    let server = MockServer::start().await;
+    for attempt in 0..3 {
+        server.push(malformed_call(&format!("lookup(city=Paris{attempt}")));
+    }
+    let error = server
+        .client()
+        .generate_content()
+        .with_user_message("Weather in Paris?")
+        .execute_with_tools(&registry)
+        .await
+        .unwrap_err();
+    let ClientError::MalformedFunctionCall { raw } = &error else {
+        panic!("expected MalformedFunctionCall, got {error:?}");
+    };
+    assert_eq!(raw, "lookup(city=Paris2");
+    assert_eq!(
+        error.to_string(),
+        "model kept making malformed function calls, last: lookup(city=Paris2"
+    );
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[2].json()["contents"].as_array().unwrap().len(), 3);
+
+    // Without recovery the step is re-sent unchanged until the steps run out
+    let server = MockServer::start().await;
+    for _ in 0..2 {
+        server.push(malformed_call("lookup("));
+    }
+    let error = server
+        .client()
+        .generate_content()
+        .with_user_message("Weather in Paris?")
+        .execute_with_tools(
+            &ToolRegistry::new()
+                .with_function(lookup(), |_| async { Ok(json!({})) })
+                .with_max_steps(2),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ClientError::ToolLoopExhausted { steps: 2 }));
+    let requests = server.requests();
+    assert_eq!(
+        requests[0].json()["contents"],
+        requests[1].json()["contents"]
+    );
+}
+
+/// The typed outcome of every recorded code execution, and its output split on the traceback
+#[test]
+fn test_code_execution_outcomes() {
+    let fixture = |name: &str| -> crate::CodeExecutionResult {
+        let path = format!(
+            "{}/tests/fixtures/generate_content/{name}.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let response: GenerationResponse =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        response.candidates[0]
+            .content
+            .parts
+            .iter()
+            .flatten()
+            .find_map(|part| match part {
+                Part::CodeExecutionResult {
+                    code_execution_result,
+                } => Some(code_execution_result.clone()),
+                _ => None,
+            })
+            .unwrap()
+    };
+
+    let ok = fixture("code_execution");
+    assert_eq!(ok.outcome, Outcome::OutcomeOk);
+    assert!(ok.is_success());
+    assert_eq!(ok.stdout(), "5117\n");
+    assert_eq!(ok.error_output(), None);
+
+    let failed = fixture("code_execution_error");
+    assert_eq!(failed.outcome, Outcome::OutcomeFailed);
+    assert!(!failed.is_success());
+    assert_eq!(failed.stdout(), "");
+    assert_eq!(
+        failed.error_output(),
+        Some("Traceback (most recent call last):\nZeroDivisionError: division by zero\n")
+    );
+
+    let with_output = fixture("code_execution_error_with_output");
+    assert_eq!(with_output.stdout(), "2 rows\n");
+    assert!(with_output
+        .error_output()
+        .unwrap()
+        .ends_with("KeyError: 'name'\n"));
+
+    let deadline = fixture("code_execution_deadline");
+    assert_eq!(deadline.outcome, Outcome::OutcomeDeadlineExceeded);
+    assert!(!deadline.is_success());
+    assert_eq!(deadline.stdout(), "10000000\n20000000\n");
+    assert_eq!(deadline.error_output(), None);
+
+    let unknown: crate::CodeExecutionResult =
+        serde_json::from_value(json!({"outcome": "OUTCOME_CANCELLED"})).unwrap();
+    assert_eq!(
+        unknown.outcome,
+        Outcome::Unknown("OUTCOME_CANCELLED".into())
+    );
+    assert_eq!(
+        serde_json::to_value(&unknown).unwrap(),
+        json!({"outcome": "OUTCOME_CANCELLED"})
+    );
+    assert_eq!(Outcome::from("outcome_failed"), Outcome::OutcomeFailed);
+    assert_eq!(unknown.stdout(), "");
+}
+
+/// The traceback marker only splits failed output, at the start of its last line
+#[test]
+fn test_code_execution_output_split() {
+    let result = |outcome: Outcome, output: &str| crate::CodeExecutionResult {
+        outcome,
+        output: Some(output.to_string()),
+    };
+    const MARKER: &str = "Traceback (most recent call last):";
+
+    // Printed by the code, then a real traceback: the last one is the error
+    let printed = format!("{MARKER}\nlooks scary\n{MARKER}\nValueError: bad\n");
+    let failed = result(Outcome::OutcomeFailed, &printed);
+    assert_eq!(failed.stdout(), format!("{MARKER}\nlooks scary\n"));
+    assert_eq!(
+        failed.error_output(),
+        Some(format!("{MARKER}\nValueError: bad\n").as_str())
+    );
+
+    // Within a line, the marker is printed text
+    let inline = format!("log: {MARKER}\nSyntaxError: invalid syntax\n");
+    let failed = result(Outcome::OutcomeFailed, &inline);
+    assert_eq!(failed.stdout(), "");
+    assert_eq!(failed.error_output(), Some(inline.as_str()));
+
+    // Successful code may print the marker
+    let ok = result(Outcome::OutcomeOk, &printed);
+    assert_eq!(ok.stdout(), printed);
+    assert_eq!(ok.error_output(), None);
+
+    let empty = crate::CodeExecutionResult {
+        outcome: Outcome::OutcomeFailed,
+        output: None,
+    };
+    assert_eq!(empty.stdout(), "");
+    assert_eq!(empty.error_output(), Some(""));
+}
+
+fn code_result(outcome: &str, output: &str, text: &str) -> MockResponse {
+    model_parts(
+        json!([
+            {"executableCode": {"language": "PYTHON", "code": "print(rows[0]['name'])\n"}},
+            {"codeExecutionResult": {"outcome": outcome, "output": output}},
+            {"text": text},
+        ]),
+        "STOP",
+    )
+}
+
+/// With the retry set, an answer whose code failed is answered once with the error
+#[tokio::test]
+async fn test_code_execution_retry() {
+    let registry = ToolRegistry::new().with_code_execution_retry();
+    let failure = "2 rows\nTraceback (most recent call last):\nKeyError: 'name'\n";
+
+    let server = MockServer::start().await;
+    server
+        .push(code_result(
+            "OUTCOME_FAILED",
+            failure,
+            "The rows have no name.",
+        ))
+        .push(code_result(
+            "OUTCOME_OK",
+            "Alice\n",
+            "The first row is Alice.",
+        ));
+    let response = server
+        .client()
+        .generate_content()
+        .with_tool(Tool::code_execution())
+        .with_user_message("Name of the first row?")
+        .execute_with_tools(&registry)
+        .await
+        .unwrap();
+    assert!(response.text().ends_with("The first row is Alice."));
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].json()["tools"], json!([{"code_execution": {}}]));
+    let contents = requests[1].json()["contents"].clone();
+    assert_eq!(contents.as_array().unwrap().len(), 3);
+    assert_eq!(contents[1]["role"], "model");
+    assert_eq!(
+        contents[2]["parts"][0]["text"],
+        "Your code failed with this error:\n\n\
+         Traceback (most recent call last):\nKeyError: 'name'\n\n\
+         Fix the code and run it again."
+    );
+
+    // Retried once only: a second failure is returned
+    let server = MockServer::start().await;
+    server
+        .push(code_result("OUTCOME_DEADLINE_EXCEEDED", "", "Too slow."))
+        .push(code_result("OUTCOME_FAILED", failure, "Still failing."));
+    let response = server
+        .client()
+        .generate_content()
+        .with_user_message("Name of the first row?")
+        .execute_with_tools(&registry)
+        .await
+        .unwrap();
+    assert!(response.text().ends_with("Still failing."));
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1].json()["contents"][2]["parts"][0]["text"]
+        .as_str()
+        .unwrap()
+        .contains("execution deadline"));
+
+    // Without the retry the failed answer is returned
+    let server = MockServer::start().await;
+    server.push(code_result(
+        "OUTCOME_FAILED",
+        failure,
+        "The rows have no name.",
+    ));
+    let response = server
+        .client()
+        .generate_content()
+        .with_user_message("Name of the first row?")
+        .execute_with_tools(&ToolRegistry::new())
+        .await
+        .unwrap();
+    assert!(response.candidates[0].failed_code_execution().is_some());
+    assert_eq!(server.requests().len(), 1);
+}
+
+const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+/// A traceparent set on the request is sent as is; malformed ones and absent ones send none
+#[tokio::test]
+async fn test_traceparent_override() {
+    let server = MockServer::start().await;
+    for _ in 0..3 {
+        server.push(answer("ok", 10));
+    }
+    let send = |builder: crate::ContentBuilder| builder.with_user_message("Hi").execute();
+
+    send(
+        server
+            .client()
+            .generate_content()
+            .with_traceparent(TRACEPARENT),
+    )
+    .await
+    .unwrap();
+    send(server.client().generate_content()).await.unwrap();
+    send(
+        server
+            .client()
+            .generate_content()
+            .with_traceparent("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01"),
+    )
+    .await
+    .unwrap();
+    let requests = server.requests();
+    assert_eq!(requests[0].header("traceparent"), Some(TRACEPARENT));
+    assert_eq!(requests[1].header("traceparent"), None);
+    assert_eq!(requests[2].header("traceparent"), None);
+
+    use crate::trace_context::is_valid;
+    assert!(is_valid(TRACEPARENT));
+    assert!(!is_valid(
+        "00-00000000000000000000000000000000-00f067aa0ba902b7-01"
+    ));
+    assert!(!is_valid(
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01"
+    ));
+    assert!(!is_valid(
+        "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+    ));
+    assert!(!is_valid(
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7"
+    ));
+    assert!(!is_valid(
+        "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01"
+    ));
+}
+
+/// With an OpenTelemetry layer, the context of the current span is sent, and only when it
+/// holds a span
+#[cfg(feature = "otel")]
+#[tokio::test]
+async fn test_traceparent_from_otel_context() {
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use tracing::Instrument;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let server = MockServer::start().await;
+    for _ in 0..4 {
+        server.push(answer("ok", 10));
+    }
+    let send = || {
+        server
+            .client()
+            .generate_content()
+            .with_user_message("Hi")
+            .execute()
+    };
+
+    // No layer: no context
+    send().await.unwrap();
+
+    let subscriber = tracing_subscriber::registry().with(tracing_opentelemetry::layer());
+    let _guard = tracing::subscriber::set_default(subscriber);
+    let remote = SpanContext::new(
+        TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+        SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+        TraceFlags::SAMPLED,
+        true,
+        TraceState::default(),
+    );
+    let span = tracing::info_span!("checkout");
+    span.set_parent(opentelemetry::Context::new().with_remote_span_context(remote))
+        .unwrap();
+    send().instrument(span.clone()).await.unwrap();
+
+    // A span outside of any trace
+    send()
+        .instrument(tracing::info_span!("orphan"))
+        .await
+        .unwrap();
+
+    // An explicit traceparent wins over the context
+    let explicit = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00";
+    server
+        .client()
+        .generate_content()
+        .with_user_message("Hi")
+        .with_traceparent(explicit)
+        .execute()
+        .instrument(span)
+        .await
+        .unwrap();
+
+    let requests = server.requests();
+    assert_eq!(requests[0].header("traceparent"), None);
+    let traceparent = requests[1].header("traceparent").unwrap();
+    assert!(crate::trace_context::is_valid(traceparent), "{traceparent}");
+    assert!(
+        traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"),
+        "{traceparent}"
+    );
+    assert!(traceparent.ends_with("-01"), "{traceparent}");
+    assert_eq!(requests[2].header("traceparent"), None);
+    assert_eq!(requests[3].header("traceparent"), Some(explicit));
+}
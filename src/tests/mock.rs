@@ -0,0 +1,279 @@
+//! A minimal in-process HTTP/1.1 server that replays scripted responses and records requests.
+
+#![allow(dead_code)]
+
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use url::Url;
+
+use crate::{Gemini, GeminiBuilder};
+
+/// A request received by the mock server
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).expect("request body is not JSON")
+    }
+}
+
+/// A scripted response
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// Body chunks written one after another, each preceded by its delay
+    pub chunks: Vec<(Duration, Vec<u8>)>,
+    /// Whether the body length is announced; otherwise the body ends when the connection closes
+    pub content_length: bool,
+    /// Number of body chunks written before the connection is closed mid-body
+    pub reset_after: Option<usize>,
+}
+
+impl MockResponse {
+    pub fn json(status: u16, body: Value) -> Self {
+        Self {
+            status,
+            headers: vec![("content-type".into(), "application/json".into())],
+            chunks: vec![(Duration::ZERO, body.to_string().into_bytes())],
+            content_length: true,
+            reset_after: None,
+        }
+    }
+
+    pub fn text(status: u16, content_type: &str, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            headers: vec![("content-type".into(), content_type.into())],
+            chunks: vec![(Duration::ZERO, body.into().into_bytes())],
+            content_length: true,
+            reset_after: None,
+        }
+    }
+
+    /// Server-sent events, one `data:` event per chunk
+    pub fn sse(events: impl IntoIterator<Item = Value>) -> Self {
+        Self {
+            status: 200,
+            headers: vec![("content-type".into(), "text/event-stream".into())],
+            chunks: events
+                .into_iter()
+                .map(|event| {
+                    (
+                        Duration::ZERO,
+                        format!("data: {event}\r\n\r\n").into_bytes(),
+                    )
+                })
+                .collect(),
+            content_length: true,
+            reset_after: None,
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Leaves out the `content-length` header and closes the connection after the body
+    pub fn close_delimited(mut self) -> Self {
+        self.content_length = false;
+        self
+    }
+
+    /// Closes the connection after the first `chunks` body chunks, short of the announced
+    /// length
+    pub fn reset_after(mut self, chunks: usize) -> Self {
+        self.reset_after = Some(chunks);
+        self
+    }
+
+    /// Delays every body chunk by `delay`
+    pub fn with_chunk_delay(mut self, delay: Duration) -> Self {
+        for chunk in &mut self.chunks {
+            chunk.0 = delay;
+        }
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct MockServer {
+    url: Url,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    responses: Arc<Mutex<VecDeque<MockResponse>>>,
+    body_bytes_sent: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Self {
+            url: Url::parse(&format!("http://{addr}/v1beta/")).unwrap(),
+            requests: Default::default(),
+            responses: Default::default(),
+            body_bytes_sent: Default::default(),
+        };
+        let handle = server.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handle = handle.clone();
+                tokio::spawn(async move { handle.serve(stream).await });
+            }
+        });
+        server
+    }
+
+    pub fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    pub fn push(&self, response: MockResponse) -> &Self {
+        self.responses.lock().unwrap().push_back(response);
+        self
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Number of response body bytes written to clients so far
+    pub fn body_bytes_sent(&self) -> usize {
+        self.body_bytes_sent.load(Ordering::SeqCst)
+    }
+
+    pub fn builder(&self) -> GeminiBuilder {
+        GeminiBuilder::new("test-key").with_base_url(self.url())
+    }
+
+    pub fn client(&self) -> Gemini {
+        self.builder().build().unwrap()
+    }
+
+    async fn serve(&self, mut stream: TcpStream) {
+        let mut buffer = Vec::new();
+        loop {
+            let Some(request) = read_request(&mut stream, &mut buffer).await else {
+                return;
+            };
+            self.requests.lock().unwrap().push(request);
+            let response = self
+                .responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or_else(|| {
+                    MockResponse::json(500, serde_json::json!({"error": "no scripted response"}))
+                });
+            let close = !response.content_length || response.reset_after.is_some();
+            if write_response(&mut stream, response, &self.body_bytes_sent)
+                .await
+                .is_err()
+                || close
+            {
+                return;
+            }
+        }
+    }
+}
+
+async fn read_request(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> Option<RecordedRequest> {
+    let header_end = loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        let mut chunk = [0u8; 4096];
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(n, v)| (n.trim().to_lowercase(), v.trim().to_string()))
+        .collect();
+    let length: usize = headers
+        .iter()
+        .find(|(n, _)| n == "content-length")
+        .and_then(|(_, v)| v.parse().ok())
+        .unwrap_or(0);
+
+    let body_start = header_end + 4;
+    while buffer.len() < body_start + length {
+        let mut chunk = [0u8; 65536];
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let body = buffer[body_start..body_start + length].to_vec();
+    buffer.drain(..body_start + length);
+
+    Some(RecordedRequest {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+async fn write_response(
+    stream: &mut TcpStream,
+    response: MockResponse,
+    sent: &AtomicUsize,
+) -> std::io::Result<()> {
+    let length: usize = response.chunks.iter().map(|(_, c)| c.len()).sum();
+    let mut head = format!("HTTP/1.1 {} Mock\r\n", response.status);
+    match response.content_length {
+        true => head.push_str(&format!("content-length: {length}\r\n")),
+        false => head.push_str("connection: close\r\n"),
+    }
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    let written = response.reset_after.unwrap_or(usize::MAX);
+    for (delay, chunk) in response.chunks.into_iter().take(written) {
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        stream.write_all(&chunk).await?;
+        stream.flush().await?;
+        sent.fetch_add(chunk.len(), Ordering::SeqCst);
+    }
+    Ok(())
+}
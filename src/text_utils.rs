@@ -0,0 +1,459 @@
+//! Plain-text rendering of model output.
+//!
+//! Model answers are usually Markdown with typographic quotes, which is the wrong shape for
+//! text-to-speech, SMS, or search indexing. [`strip_markdown()`] removes the markup while
+//! keeping the text, [`normalize_whitespace()`] collapses stray spacing, and [`ascii_quotes()`]
+//! replaces typographic quotes. [`GenerationResponse::plain_text()`](crate::GenerationResponse::plain_text)
+//! applies all three.
+
+/// Removes Markdown markup, keeping the text it decorates.
+///
+/// - Emphasis markers (`*`, `_`, `~~`, nested or not) are removed; unmatched or intraword
+///   markers such as `2 * 3` or `snake_case` are kept.
+/// - Links become their text, autolinks their target; images are dropped.
+/// - Headings, block quotes and horizontal rules lose their markers.
+/// - List items are rendered with a `- ` bullet (ordered items keep their number) and task
+///   list check boxes are dropped. Indentation of nested items is kept.
+/// - Fenced code blocks keep their contents verbatim; inline code keeps its contents.
+/// - Tables are rendered one row per line with cells separated by `, `.
+pub fn strip_markdown(markdown: &str) -> String {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut out: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if let Some((fence_char, fence_len)) = opening_fence(line) {
+            i += 1;
+            while i < lines.len() && !closes_fence(lines[i], fence_char, fence_len) {
+                out.push(lines[i].to_string());
+                i += 1;
+            }
+            i += 1;
+            continue;
+        }
+        if is_table_row(line) && lines.get(i + 1).is_some_and(|l| is_delimiter_row(l)) {
+            out.push(render_row(line));
+            i += 2;
+            while i < lines.len() && is_table_row(lines[i]) && !lines[i].trim().is_empty() {
+                out.push(render_row(lines[i]));
+                i += 1;
+            }
+            continue;
+        }
+
+        let trimmed = line.trim();
+        let setext_underline = !trimmed.is_empty()
+            && trimmed.chars().all(|c| c == '=')
+            && out.last().is_some_and(|l| !l.is_empty());
+        if !(is_rule(trimmed) || setext_underline || is_reference_definition(trimmed)) {
+            out.push(strip_block_line(line));
+        }
+        i += 1;
+    }
+    out.join("\n")
+}
+
+/// Collapses runs of whitespace within lines, trims every line, and collapses consecutive
+/// blank lines into one. Leading and trailing blank lines are removed.
+pub fn normalize_whitespace(text: &str) -> String {
+    let mut out: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.is_empty() && out.last().is_none_or(|l| l.is_empty()) {
+            continue;
+        }
+        out.push(line);
+    }
+    while out.last().is_some_and(|l| l.is_empty()) {
+        out.pop();
+    }
+    out.join("\n")
+}
+
+/// Replaces typographic single and double quotes (and primes) with their ASCII equivalents
+pub fn ascii_quotes(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' | '\u{2032}' => '\'',
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' | '\u{2033}' => '"',
+            c => c,
+        })
+        .collect()
+}
+
+/// Returns the fence character and length if `line` opens a fenced code block
+fn opening_fence(line: &str) -> Option<(char, usize)> {
+    let trimmed = line.trim_start();
+    let fence_char = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let fence_len = trimmed.chars().take_while(|c| *c == fence_char).count();
+    let info = &trimmed[fence_len..];
+    // Backtick fences cannot have backticks in their info string
+    (fence_len >= 3 && !(fence_char == '`' && info.contains('`')))
+        .then_some((fence_char, fence_len))
+}
+
+fn closes_fence(line: &str, fence_char: char, fence_len: usize) -> bool {
+    let trimmed = line.trim();
+    trimmed.chars().count() >= fence_len && trimmed.chars().all(|c| c == fence_char)
+}
+
+/// A thematic break such as `---`, `* * *` or `___`
+fn is_rule(trimmed: &str) -> bool {
+    let Some(first) = trimmed
+        .chars()
+        .next()
+        .filter(|c| matches!(c, '-' | '*' | '_'))
+    else {
+        return false;
+    };
+    trimmed.chars().all(|c| c == first || c == ' ')
+        && trimmed.chars().filter(|c| *c == first).count() >= 3
+}
+
+/// A link reference definition such as `[docs]: https://example.com`
+fn is_reference_definition(trimmed: &str) -> bool {
+    trimmed.starts_with('[')
+        && trimmed
+            .find("]:")
+            .is_some_and(|end| end > 1 && !trimmed[end + 2..].trim().is_empty())
+}
+
+/// Splits a table row into its cells, honouring escaped pipes
+fn table_cells(line: &str) -> Vec<String> {
+    let trimmed = line.trim();
+    let trimmed = trimmed.strip_prefix('|').unwrap_or(trimmed);
+    let trimmed = match trimmed.strip_suffix('|') {
+        Some(rest) if !rest.ends_with('\\') => rest,
+        _ => trimmed,
+    };
+    let mut cells = vec![String::new()];
+    let mut chars = trimmed.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cells.last_mut().unwrap().push(chars.next().unwrap());
+            }
+            '|' => cells.push(String::new()),
+            c => cells.last_mut().unwrap().push(c),
+        }
+    }
+    cells.into_iter().map(|c| c.trim().to_string()).collect()
+}
+
+fn is_table_row(line: &str) -> bool {
+    line.contains('|')
+}
+
+/// The `| --- | :---: |` line separating a table header from its body
+fn is_delimiter_row(line: &str) -> bool {
+    is_table_row(line)
+        && table_cells(line).iter().all(|cell| {
+            let cell = cell.strip_prefix(':').unwrap_or(cell);
+            let cell = cell.strip_suffix(':').unwrap_or(cell);
+            !cell.is_empty() && cell.chars().all(|c| c == '-')
+        })
+}
+
+fn render_row(line: &str) -> String {
+    table_cells(line)
+        .iter()
+        .map(|cell| strip_inline(cell))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Strips the block-level markers of a single line, then its inline markup
+fn strip_block_line(line: &str) -> String {
+    let content = line.trim_start();
+    let indent = &line[..line.len() - content.len()];
+
+    let mut content = content;
+    while let Some(rest) = content.strip_prefix('>') {
+        content = rest.strip_prefix(' ').unwrap_or(rest);
+    }
+
+    let hashes = content.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes)
+        && content[hashes..]
+            .chars()
+            .next()
+            .is_none_or(|c| c.is_whitespace())
+    {
+        let heading = content[hashes..].trim();
+        let heading = heading.trim_end_matches('#').trim_end();
+        return strip_inline(heading);
+    }
+
+    if let Some(item) = ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|marker| content.strip_prefix(marker))
+    {
+        return format!("{indent}- {}", strip_inline(strip_task_box(item)));
+    }
+    let digits = content.chars().take_while(char::is_ascii_digit).count();
+    if (1..=9).contains(&digits) {
+        let rest = &content[digits..];
+        if let Some(item) = [". ", ") "].iter().find_map(|m| rest.strip_prefix(m)) {
+            return format!(
+                "{indent}{}. {}",
+                &content[..digits],
+                strip_inline(strip_task_box(item))
+            );
+        }
+    }
+
+    strip_inline(content)
+}
+
+fn strip_task_box(item: &str) -> &str {
+    ["[ ] ", "[x] ", "[X] "]
+        .iter()
+        .find_map(|check_box| item.strip_prefix(check_box))
+        .unwrap_or(item)
+}
+
+/// A piece of inline content
+enum Inline {
+    Text(String),
+    /// A run of emphasis delimiters; `len` shrinks as delimiters are matched
+    Delimiter {
+        ch: char,
+        len: usize,
+        can_open: bool,
+        can_close: bool,
+    },
+}
+
+/// Removes inline markup: emphasis, links, images, code spans and escapes
+fn strip_inline(text: &str) -> String {
+    let mut inlines = tokenize(text);
+    match_delimiters(&mut inlines);
+    inlines
+        .into_iter()
+        .map(|inline| match inline {
+            Inline::Text(text) => text,
+            Inline::Delimiter { ch, len, .. } => ch.to_string().repeat(len),
+        })
+        .collect()
+}
+
+fn tokenize(text: &str) -> Vec<Inline> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut inlines = Vec::new();
+    let mut buffer = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if chars.get(i + 1).is_some_and(|n| n.is_ascii_punctuation()) => {
+                buffer.push(chars[i + 1]);
+                i += 2;
+            }
+            '`' => {
+                let run = run_length(&chars, i);
+                match find_code_span_end(&chars, i + run, run) {
+                    Some(end) => {
+                        let code: String = chars[i + run..end].iter().collect();
+                        let code = match code.strip_prefix(' ').and_then(|c| c.strip_suffix(' ')) {
+                            Some(inner) if !inner.trim().is_empty() => inner.to_string(),
+                            _ => code,
+                        };
+                        buffer.push_str(&code);
+                        i = end + run;
+                    }
+                    None => {
+                        buffer.extend(&chars[i..i + run]);
+                        i += run;
+                    }
+                }
+            }
+            '!' if chars.get(i + 1) == Some(&'[') => match parse_link(&chars, i + 1) {
+                Some((_, end)) => i = end,
+                None => {
+                    buffer.push(c);
+                    i += 1;
+                }
+            },
+            '[' => match parse_link(&chars, i) {
+                Some((label, end)) => {
+                    flush(&mut buffer, &mut inlines);
+                    inlines.extend(tokenize(&label));
+                    i = end;
+                }
+                None => {
+                    buffer.push(c);
+                    i += 1;
+                }
+            },
+            '<' => match parse_autolink(&chars, i) {
+                Some((target, end)) => {
+                    buffer.push_str(&target);
+                    i = end;
+                }
+                None => {
+                    buffer.push(c);
+                    i += 1;
+                }
+            },
+            '*' | '_' | '~' => {
+                let run = run_length(&chars, i);
+                let before = if i == 0 { ' ' } else { chars[i - 1] };
+                let after = chars.get(i + run).copied().unwrap_or(' ');
+                let left_flanking = !after.is_whitespace()
+                    && (!is_punctuation(after) || before.is_whitespace() || is_punctuation(before));
+                let right_flanking = !before.is_whitespace()
+                    && (!is_punctuation(before) || after.is_whitespace() || is_punctuation(after));
+                let (can_open, can_close) = match c {
+                    // Underscores do not emphasize inside words
+                    '_' => (
+                        left_flanking && (!right_flanking || is_punctuation(before)),
+                        right_flanking && (!left_flanking || is_punctuation(after)),
+                    ),
+                    _ => (left_flanking, right_flanking),
+                };
+                flush(&mut buffer, &mut inlines);
+                inlines.push(Inline::Delimiter {
+                    ch: c,
+                    len: run,
+                    can_open,
+                    can_close,
+                });
+                i += run;
+            }
+            c => {
+                buffer.push(c);
+                i += 1;
+            }
+        }
+    }
+    flush(&mut buffer, &mut inlines);
+    inlines
+}
+
+fn flush(buffer: &mut String, inlines: &mut Vec<Inline>) {
+    if !buffer.is_empty() {
+        inlines.push(Inline::Text(std::mem::take(buffer)));
+    }
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || (!c.is_alphanumeric() && !c.is_whitespace())
+}
+
+fn run_length(chars: &[char], start: usize) -> usize {
+    chars[start..]
+        .iter()
+        .take_while(|c| **c == chars[start])
+        .count()
+}
+
+/// Finds the start of the backtick run of exactly `len` closing a code span
+fn find_code_span_end(chars: &[char], from: usize, len: usize) -> Option<usize> {
+    let mut i = from;
+    while i < chars.len() {
+        if chars[i] == '`' {
+            let run = run_length(chars, i);
+            if run == len {
+                return Some(i);
+            }
+            i += run;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Finds the bracket closing the one at `open`, skipping nested brackets
+fn matching_bracket(chars: &[char], open: usize, (left, right): (char, char)) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            c if c == left => depth += 1,
+            c if c == right => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Parses `[label](target)` or `[label][reference]` at `open`, returning the label and the
+/// index just past the link
+fn parse_link(chars: &[char], open: usize) -> Option<(String, usize)> {
+    let close = matching_bracket(chars, open, ('[', ']'))?;
+    let label: String = chars[open + 1..close].iter().collect();
+    let end = match chars.get(close + 1) {
+        Some('(') => matching_bracket(chars, close + 1, ('(', ')'))?,
+        Some('[') => matching_bracket(chars, close + 1, ('[', ']'))?,
+        _ => return None,
+    };
+    Some((label, end + 1))
+}
+
+/// Parses `<https://example.com>` or `<user@example.com>` at `open`
+fn parse_autolink(chars: &[char], open: usize) -> Option<(String, usize)> {
+    let close = open + chars[open..].iter().position(|c| *c == '>')?;
+    let target: String = chars[open + 1..close].iter().collect();
+    let is_link = !target.is_empty()
+        && !target.contains(char::is_whitespace)
+        && (target.contains("://") || target.contains('@') || target.starts_with("mailto:"));
+    is_link.then_some((target, close + 1))
+}
+
+/// Pairs emphasis delimiters, removing matched ones; unmatched delimiters stay literal
+fn match_delimiters(inlines: &mut [Inline]) {
+    let mut openers: Vec<usize> = Vec::new();
+    for closer in 0..inlines.len() {
+        let Inline::Delimiter {
+            ch,
+            can_open,
+            can_close,
+            ..
+        } = inlines[closer]
+        else {
+            continue;
+        };
+        if can_close {
+            while delimiter_len(&inlines[closer]) > 0 {
+                let Some(position) = openers.iter().rposition(
+                    |o| matches!(inlines[*o], Inline::Delimiter { ch: c, .. } if c == ch),
+                ) else {
+                    break;
+                };
+                let opener = openers[position];
+                // Openers between the pair can no longer be matched
+                openers.truncate(position + 1);
+                let used = delimiter_len(&inlines[opener]).min(delimiter_len(&inlines[closer]));
+                shrink(&mut inlines[opener], used);
+                shrink(&mut inlines[closer], used);
+                if delimiter_len(&inlines[opener]) == 0 {
+                    openers.pop();
+                }
+            }
+        }
+        if can_open && delimiter_len(&inlines[closer]) > 0 {
+            openers.push(closer);
+        }
+    }
+}
+
+fn delimiter_len(inline: &Inline) -> usize {
+    match inline {
+        Inline::Delimiter { len, .. } => *len,
+        Inline::Text(_) => 0,
+    }
+}
+
+fn shrink(inline: &mut Inline, by: usize) {
+    if let Inline::Delimiter { len, .. } = inline {
+        *len -= by;
+    }
+}
@@ -1,2 +1,11 @@
 pub mod model;
+#[cfg(feature = "tools")]
+pub mod registry;
+#[cfg(feature = "tools")]
+pub mod run_log;
+
 pub use model::*;
+#[cfg(feature = "tools")]
+pub use registry::{ToolLoopState, ToolRegistry, ToolResult};
+#[cfg(feature = "tools")]
+pub use run_log::{RunEvent, RunLog, RunLogError, RunLogOptions, RunOutcome, ToolOutcome};
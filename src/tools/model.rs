@@ -1,8 +1,13 @@
-use schemars::{generate::SchemaSettings, JsonSchema, SchemaGenerator};
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use snafu::{ResultExt, Snafu};
 
+#[cfg(feature = "schema")]
+use crate::schema::json_schema_of;
+use crate::{common::serde::sorted_json, schema::ToSchema};
+
 /// Tool that can be used by the model
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(untagged)]
@@ -25,6 +30,11 @@ pub enum Tool {
         /// The Google Maps configuration
         google_maps: GoogleMapsConfig,
     },
+    /// Code execution tool, running Python code the model writes on the server
+    CodeExecution {
+        /// The code execution configuration
+        code_execution: CodeExecutionConfig,
+    },
 }
 
 /// Empty configuration for Google Search tool
@@ -35,6 +45,10 @@ pub struct GoogleSearchConfig {}
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct URLContextConfig {}
 
+/// Empty configuration for code execution tool
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CodeExecutionConfig {}
+
 /// Configuration for Google Maps grounding tool
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -79,6 +93,16 @@ impl Tool {
             google_maps: GoogleMapsConfig { enable_widget },
         }
     }
+
+    /// Create a new code execution tool.
+    ///
+    /// The model answers with [`Part::ExecutableCode`](crate::Part::ExecutableCode) and
+    /// [`Part::CodeExecutionResult`](crate::Part::CodeExecutionResult) parts alongside its text.
+    pub fn code_execution() -> Self {
+        Self::CodeExecution {
+            code_execution: CodeExecutionConfig {},
+        }
+    }
 }
 
 /// Defines the function behavior
@@ -106,33 +130,21 @@ pub struct FunctionDeclaration {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub behavior: Option<Behavior>,
     /// `Optional` The parameters for the function
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "sorted_json::optional::serialize"
+    )]
     pub(crate) parameters: Option<Value>,
     /// `Optional` Describes the output from this function in JSON Schema format. Reflects the
     /// Open API 3.03 Response Object. The Schema defines the type used for the response value
     /// of the function.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "sorted_json::optional::serialize"
+    )]
     pub(crate) response: Option<Value>,
 }
 
-/// Returns JSON Schema for the given parameters
-fn generate_parameters_schema<Parameters>() -> Value
-where
-    Parameters: JsonSchema + Serialize,
-{
-    // Create SchemaSettings with Gemini-optimized settings, see: https://ai.google.dev/api/caching#Schema
-    let schema_generator = SchemaGenerator::new(SchemaSettings::openapi3().with(|s| {
-        s.inline_subschemas = true;
-        s.meta_schema = None;
-    }));
-
-    let mut schema = schema_generator.into_root_schema_for::<Parameters>();
-
-    // Root schemas always include a title field, which we don't want or need
-    schema.remove("title");
-    schema.to_value()
-}
-
 impl FunctionDeclaration {
     /// Create a new function declaration
     pub fn new(
@@ -148,21 +160,36 @@ impl FunctionDeclaration {
         }
     }
 
+    /// Create a function declaration whose parameters are described by `Parameters`.
+    ///
+    /// The type's doc comment, if any, becomes the function description.
+    pub fn from_type<Parameters: ToSchema>(name: impl Into<String>) -> Self {
+        let mut parameters = Parameters::schema();
+        Self {
+            name: name.into(),
+            description: parameters.description.take().unwrap_or_default(),
+            parameters: Some(parameters.into()),
+            ..Default::default()
+        }
+    }
+
     /// Set the parameters for the function using a struct that implements `JsonSchema`
+    #[cfg(feature = "schema")]
     pub fn with_parameters<Parameters>(mut self) -> Self
     where
         Parameters: JsonSchema + Serialize,
     {
-        self.parameters = Some(generate_parameters_schema::<Parameters>());
+        self.parameters = Some(json_schema_of::<Parameters>());
         self
     }
 
     /// Set the response schema for the function using a struct that implements `JsonSchema`
+    #[cfg(feature = "schema")]
     pub fn with_response<Response>(mut self) -> Self
     where
         Response: JsonSchema + Serialize,
     {
-        self.response = Some(generate_parameters_schema::<Response>());
+        self.response = Some(json_schema_of::<Response>());
         self
     }
 }
@@ -173,10 +200,14 @@ pub struct FunctionCall {
     /// The name of the function
     pub name: String,
     /// The arguments for the function
+    #[serde(serialize_with = "sorted_json::serialize")]
     pub args: serde_json::Value,
     /// The thought signature for the function call (Gemini 2.5 series only)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thought_signature: Option<String>,
+    /// The ID of the call, echoed by its [`FunctionResponse`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
 }
 
 #[derive(Debug, Snafu)]
@@ -204,6 +235,7 @@ impl FunctionCall {
             name: name.into(),
             args,
             thought_signature: None,
+            id: None,
         }
     }
 
@@ -217,6 +249,7 @@ impl FunctionCall {
             name: name.into(),
             args,
             thought_signature: Some(thought_signature.into()),
+            id: None,
         }
     }
 
@@ -251,8 +284,14 @@ pub struct FunctionResponse {
     pub name: String,
     /// The response from the function
     /// This must be a valid JSON object
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "sorted_json::optional::serialize"
+    )]
     pub response: Option<serde_json::Value>,
+    /// The ID of the [`FunctionCall`] this responds to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
 }
 
 impl FunctionResponse {
@@ -261,10 +300,20 @@ impl FunctionResponse {
         Self {
             name: name.into(),
             response: Some(response),
+            id: None,
+        }
+    }
+
+    /// Create the response to `call`, echoing its name and ID
+    pub fn for_call(call: &FunctionCall, response: serde_json::Value) -> Self {
+        Self {
+            id: call.id.clone(),
+            ..Self::new(call.name.clone(), response)
         }
     }
 
     /// Create a new function response from a serializable type that will be parsed as JSON
+    #[cfg(feature = "schema")]
     pub fn from_schema<Response>(
         name: impl Into<String>,
         response: Response,
@@ -276,6 +325,7 @@ impl FunctionResponse {
         Ok(Self {
             name: name.into(),
             response: Some(json),
+            id: None,
         })
     }
 
@@ -288,10 +338,23 @@ impl FunctionResponse {
         Ok(Self {
             name: name.into(),
             response: Some(json),
+            id: None,
         })
     }
 }
 
+/// A function call of a tool loop and the response fed back to the model, see
+/// [`GenerationResponse::tool_transcript`](crate::GenerationResponse::tool_transcript)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolExchange {
+    /// Zero-based round trip of the loop the call was made in
+    pub step: usize,
+    /// The call the model made
+    pub call: FunctionCall,
+    /// The handler's response, or the error reported to the model
+    pub response: FunctionResponse,
+}
+
 /// Configuration for tools
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 pub struct ToolConfig {
@@ -0,0 +1,350 @@
+//! Registry of callable tools for the automatic tool-execution loop.
+//!
+//! A [`ToolRegistry`] maps function declarations to async handlers. Passing it to
+//! [`ContentBuilder::execute_with_tools()`](crate::ContentBuilder::execute_with_tools) sends
+//! the declarations with the request, runs the handler of every function call the model
+//! makes, feeds the results back, and repeats until the model answers without calling a
+//! function.
+//!
+//! Large registries need not expose every function on every request:
+//! [`subset()`](ToolRegistry::subset) narrows a registry to a fixed set of functions, and
+//! [`with_tool_selector()`](ToolRegistry::with_tool_selector) picks the functions to expose
+//! before each round trip of the loop.
+//!
+//! Functions already declared on the request only need a handler, registered by name with
+//! [`with_handler()`](ToolRegistry::with_handler). Every call and response of a loop is kept in
+//! the [`tool_transcript`](crate::GenerationResponse::tool_transcript) of its final response.
+
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, fmt, future::Future, sync::Arc};
+
+use super::run_log::ToolOutcome;
+use crate::{
+    CodeExecutionResult, Content, FunctionCall, FunctionDeclaration, FunctionResponse, Role, Tool,
+};
+
+/// Result of a tool handler; errors are reported back to the model
+pub type ToolResult = Result<Value, Box<dyn std::error::Error + Send + Sync>>;
+
+type Handler = dyn Fn(FunctionCall) -> BoxFuture<'static, ToolResult> + Send + Sync;
+
+type Selector = dyn Fn(&ToolLoopState<'_>) -> Vec<String> + Send + Sync;
+
+/// State of a tool loop, passed to the tool selector before each round trip
+#[derive(Debug, Clone, Copy)]
+pub struct ToolLoopState<'a> {
+    /// Zero-based index of the upcoming round trip
+    pub step: usize,
+    /// The conversation so far, including earlier function calls and their responses
+    pub contents: &'a [Content],
+}
+
+#[derive(Clone)]
+struct RegisteredTool {
+    /// `None` for handlers of functions declared on the request itself
+    declaration: Option<FunctionDeclaration>,
+    handler: Arc<Handler>,
+}
+
+/// Function declarations paired with the handlers that execute them
+#[derive(Clone)]
+pub struct ToolRegistry {
+    tools: BTreeMap<String, RegisteredTool>,
+    max_steps: usize,
+    malformed_call_retries: Option<usize>,
+    code_execution_retry: bool,
+    selector: Option<Arc<Selector>>,
+}
+
+impl Default for ToolRegistry {
+    fn default() -> Self {
+        Self {
+            tools: BTreeMap::new(),
+            max_steps: 10,
+            malformed_call_retries: None,
+            code_execution_retry: false,
+            selector: None,
+        }
+    }
+}
+
+impl fmt::Debug for ToolRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ToolRegistry")
+            .field("tools", &self.tools.keys().collect::<Vec<_>>())
+            .field("max_steps", &self.max_steps)
+            .field("malformed_call_retries", &self.malformed_call_retries)
+            .field("code_execution_retry", &self.code_execution_retry)
+            .field("selector", &self.selector.is_some())
+            .finish()
+    }
+}
+
+impl ToolRegistry {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a function and its handler, replacing any function with the same name
+    pub fn register<F, Fut>(&mut self, declaration: FunctionDeclaration, handler: F) -> &mut Self
+    where
+        F: Fn(FunctionCall) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ToolResult> + Send + 'static,
+    {
+        let handler: Arc<Handler> = Arc::new(move |call| Box::pin(handler(call)));
+        self.tools.insert(
+            declaration.name.clone(),
+            RegisteredTool {
+                declaration: Some(declaration),
+                handler,
+            },
+        );
+        self
+    }
+
+    /// Registers a handler of the arguments of calls to the function `name`, replacing any
+    /// function with the same name.
+    ///
+    /// The function is not declared by the registry: declare it on the request, e.g. with
+    /// [`ContentBuilder::with_function()`](crate::ContentBuilder::with_function).
+    pub fn register_handler<F, Fut>(&mut self, name: impl Into<String>, handler: F) -> &mut Self
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ToolResult> + Send + 'static,
+    {
+        let handler: Arc<Handler> = Arc::new(move |call| Box::pin(handler(call.args)));
+        self.tools.insert(
+            name.into(),
+            RegisteredTool {
+                declaration: None,
+                handler,
+            },
+        );
+        self
+    }
+
+    /// Like [`register_handler()`](Self::register_handler), in builder style
+    pub fn with_handler<F, Fut>(mut self, name: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ToolResult> + Send + 'static,
+    {
+        self.register_handler(name, handler);
+        self
+    }
+
+    /// Like [`register()`](Self::register), in builder style
+    pub fn with_function<F, Fut>(mut self, declaration: FunctionDeclaration, handler: F) -> Self
+    where
+        F: Fn(FunctionCall) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ToolResult> + Send + 'static,
+    {
+        self.register(declaration, handler);
+        self
+    }
+
+    /// Sets the maximum number of model round trips of a tool loop (defaults to 10)
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// The maximum number of model round trips of a tool loop
+    pub fn max_steps(&self) -> usize {
+        self.max_steps
+    }
+
+    /// Answers a malformed function call with a corrective turn, up to `retries` times in a
+    /// row.
+    ///
+    /// By default a step the model answers with
+    /// [`FinishReason::MalformedFunctionCall`](crate::FinishReason::MalformedFunctionCall) is
+    /// re-sent unchanged. With recovery, the loop instead adds a user turn quoting the
+    /// malformed call and listing the signatures of the declared functions, and sends that.
+    /// Once the model makes `retries` malformed calls in a row and then one more, the loop
+    /// fails with [`ClientError::MalformedFunctionCall`](crate::ClientError::MalformedFunctionCall).
+    /// Corrective turns stay in the conversation and count as steps.
+    pub fn with_malformed_call_recovery(mut self, retries: usize) -> Self {
+        self.malformed_call_retries = Some(retries);
+        self
+    }
+
+    /// The number of corrective turns sent for malformed function calls in a row, if
+    /// recovery is enabled
+    pub(crate) fn malformed_call_retries(&self) -> Option<usize> {
+        self.malformed_call_retries
+    }
+
+    /// Answers a final answer whose code failed with a corrective turn, once per run.
+    ///
+    /// With the [code execution tool](crate::Tool::code_execution), the model runs code on the
+    /// server and answers with its result, even when the code failed. With this set, a step
+    /// that calls no function but whose [last code failed](crate::Candidate::failed_code_execution)
+    /// is not returned: the loop adds the model's answer and a user turn quoting the
+    /// [error](crate::CodeExecutionResult::error_output), and sends that. The answer to the
+    /// corrective turn is returned whatever its outcome. The corrective turn counts as a step.
+    pub fn with_code_execution_retry(mut self) -> Self {
+        self.code_execution_retry = true;
+        self
+    }
+
+    /// Whether a failed code execution is retried once
+    pub(crate) fn code_execution_retry(&self) -> bool {
+        self.code_execution_retry
+    }
+
+    /// Returns a registry exposing only the named functions.
+    ///
+    /// Handlers are shared with `self`, so subsets are cheap to create per request. Names that
+    /// are not registered are ignored. The maximum number of steps, malformed call recovery,
+    /// code execution retry, and the tool selector are kept; a selector then picks among the
+    /// functions of the subset.
+    pub fn subset(&self, names: &[&str]) -> ToolRegistry {
+        let mut subset = ToolRegistry {
+            tools: BTreeMap::new(),
+            max_steps: self.max_steps,
+            malformed_call_retries: self.malformed_call_retries,
+            code_execution_retry: self.code_execution_retry,
+            selector: self.selector.clone(),
+        };
+        for name in names {
+            match self.tools.get(*name) {
+                Some(tool) => {
+                    subset.tools.insert(name.to_string(), tool.clone());
+                }
+                None => tracing::debug!(function = name, "ignoring unregistered function"),
+            }
+        }
+        subset
+    }
+
+    /// Sets a callback choosing the functions to expose before each round trip of a tool loop.
+    ///
+    /// The selector sees the conversation so far and returns function names, so later rounds
+    /// can expose different functions than the first. Calls to functions outside the selected
+    /// set are answered with an error.
+    pub fn with_tool_selector<F, I, S>(mut self, selector: F) -> Self
+    where
+        F: Fn(&ToolLoopState<'_>) -> I + Send + Sync + 'static,
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.selector = Some(Arc::new(move |state| {
+            selector(state)
+                .into_iter()
+                .map(|name| name.as_ref().to_string())
+                .collect()
+        }));
+        self
+    }
+
+    /// Returns the functions to expose in the round trip described by `state`
+    pub(crate) fn select(&self, state: &ToolLoopState<'_>) -> ToolRegistry {
+        let Some(selector) = &self.selector else {
+            return self.clone();
+        };
+        let names = selector(state);
+        let mut selected = self.subset(&names.iter().map(String::as_str).collect::<Vec<_>>());
+        selected.selector = None;
+        selected
+    }
+
+    /// Returns the declarations of all registered functions, sorted by name; functions
+    /// registered with [`with_handler()`](Self::with_handler) have none
+    pub fn declarations(&self) -> Vec<FunctionDeclaration> {
+        self.tools
+            .values()
+            .filter_map(|t| t.declaration.clone())
+            .collect()
+    }
+
+    /// Returns the declared functions as a single tool, if any are declared
+    pub fn tool(&self) -> Option<Tool> {
+        let declarations = self.declarations();
+        (!declarations.is_empty()).then(|| Tool::with_functions(declarations))
+    }
+
+    /// Runs the handler of a function call.
+    ///
+    /// Unknown functions and handler errors are turned into an `{"error": ...}` response so
+    /// that the model can recover.
+    pub async fn call(&self, call: FunctionCall) -> FunctionResponse {
+        self.call_with_outcome(call).await.0
+    }
+
+    /// Runs the handler of a function call, also reporting how the handler did
+    pub(crate) async fn call_with_outcome(
+        &self,
+        call: FunctionCall,
+    ) -> (FunctionResponse, ToolOutcome) {
+        let name = call.name.clone();
+        let id = call.id.clone();
+        let Some(tool) = self.tools.get(&name) else {
+            let response = FunctionResponse {
+                id,
+                ..FunctionResponse::new(
+                    name.clone(),
+                    json!({ "error": format!("unknown function '{name}'") }),
+                )
+            };
+            return (response, ToolOutcome::UnknownFunction);
+        };
+        let (response, outcome) = match (tool.handler)(call).await {
+            Ok(Value::Object(object)) => (Value::Object(object), ToolOutcome::Ok),
+            // The API requires the response to be an object
+            Ok(value) => (json!({ "result": value }), ToolOutcome::Ok),
+            Err(error) => {
+                tracing::debug!(function = name, error = %error, "tool handler failed");
+                let message = error.to_string();
+                (json!({ "error": message }), ToolOutcome::Error { message })
+            }
+        };
+        let response = FunctionResponse {
+            id,
+            ..FunctionResponse::new(name, response)
+        };
+        (response, outcome)
+    }
+}
+
+/// The user turn answering a malformed function call `raw`, listing the functions of `tools`
+pub(crate) fn malformed_call_correction(raw: &str, tools: &[Tool]) -> Content {
+    let mut text = format!(
+        "Your last function call was malformed and could not be parsed:\n\n{raw}\n\n\
+         Call a function again with a single well-formed call whose arguments are a JSON \
+         object matching the function's parameters. The available functions are:\n"
+    );
+    let declarations = tools.iter().flat_map(|tool| match tool {
+        Tool::Function {
+            function_declarations,
+        } => function_declarations.as_slice(),
+        _ => &[],
+    });
+    for declaration in declarations {
+        let parameters = declaration
+            .parameters
+            .as_ref()
+            .map_or_else(|| "{}".to_string(), Value::to_string);
+        text.push_str(&format!(
+            "- {}({parameters}): {}\n",
+            declaration.name, declaration.description
+        ));
+    }
+    Content::text(text).with_role(Role::User)
+}
+
+/// The user turn answering code that failed with `result`
+pub(crate) fn failed_code_correction(result: &CodeExecutionResult) -> Content {
+    let text = match result.error_output() {
+        Some(error) => format!(
+            "Your code failed with this error:\n\n{}\n\nFix the code and run it again.",
+            error.trim_end()
+        ),
+        None => "Your code did not finish before the execution deadline. Make it faster and \
+                 run it again."
+            .to_string(),
+    };
+    Content::text(text).with_role(Role::User)
+}
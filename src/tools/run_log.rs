@@ -0,0 +1,370 @@
+//! Machine-readable logs of tool-loop runs for offline analysis.
+//!
+//! [`ContentBuilder::execute_with_tools_logged()`](crate::ContentBuilder::execute_with_tools_logged)
+//! and [`ChatSession::send_message_with_tools_logged()`](crate::ChatSession::send_message_with_tools_logged)
+//! return a [`RunLog`] alongside the result: every request of the loop (its canonical hash
+//! and size), failed network attempts, every response (finish reason and usage), every tool
+//! invocation (name, arguments, duration, and outcome), and how the run ended, in order.
+//!
+//! Logs serialize to JSON in a format versioned by [`RUN_LOG_VERSION`]; the version is bumped
+//! whenever a field is renamed or removed, so stored logs can be told apart. Durations are
+//! whole milliseconds measured by the client's clock, and request hashes are hexadecimal
+//! strings so they survive JSON readers that parse numbers as doubles.
+//!
+//! Arguments of tool calls often hold secrets or personal data. [`RunLogOptions`] replaces
+//! named fields with [`SCRUBBED`] wherever they occur in the arguments, and runs custom
+//! scrubbers over them, before they are logged.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::{ResultExt, Snafu};
+use std::{fmt, sync::Arc, time::Duration};
+
+use crate::{
+    generation::GenerateContentRequest, retry::AttemptsReport, FinishReason, FunctionCall,
+    GenerationResponse, UsageMetadata,
+};
+
+/// Version of the serialized [`RunLog`] format
+pub const RUN_LOG_VERSION: u32 = 1;
+
+/// The value scrubbed argument fields are replaced with
+pub const SCRUBBED: &str = "[scrubbed]";
+
+/// Error reading a serialized [`RunLog`]
+#[derive(Debug, Snafu)]
+pub enum RunLogError {
+    #[snafu(display("failed to parse run log"))]
+    Parse { source: serde_json::Error },
+
+    #[snafu(display("run log has format version {version}, expected {RUN_LOG_VERSION}"))]
+    UnsupportedVersion { version: u32 },
+}
+
+/// Ordered log of a tool-loop run, see the [module documentation](self)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunLog {
+    /// Format version, [`RUN_LOG_VERSION`] for logs written by this crate
+    pub version: u32,
+    /// What happened, in order
+    pub events: Vec<RunEvent>,
+}
+
+impl Default for RunLog {
+    fn default() -> Self {
+        Self {
+            version: RUN_LOG_VERSION,
+            events: Vec::new(),
+        }
+    }
+}
+
+impl RunLog {
+    /// Reads a log serialized as JSON, rejecting logs of another format version
+    pub fn from_json(json: &str) -> Result<Self, RunLogError> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+        let Versioned { version } = serde_json::from_str(json).context(ParseSnafu)?;
+        snafu::ensure!(
+            version == RUN_LOG_VERSION,
+            UnsupportedVersionSnafu { version }
+        );
+        serde_json::from_str(json).context(ParseSnafu)
+    }
+
+    /// Serializes the log as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("run logs serialize to JSON")
+    }
+
+    /// The number of model round trips made
+    pub fn steps(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|event| matches!(event, RunEvent::Request { .. }))
+            .count()
+    }
+}
+
+/// One thing that happened during a tool-loop run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RunEvent {
+    /// A request was sent to the model
+    Request {
+        /// Zero-based round trip of the loop
+        step: usize,
+        /// [`GenerateContentRequest::canonical_hash()`] in hexadecimal
+        hash: String,
+        /// Number of contents in the request
+        contents: usize,
+        /// Size of the serialized request in bytes
+        bytes: usize,
+        /// Ids of the fragments the system instruction was composed from, see
+        /// [`ContentBuilder::with_system_fragments()`](crate::ContentBuilder::with_system_fragments)
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        system_fragments: Vec<String>,
+    },
+    /// A network attempt failed; it was retried unless it was the last attempt
+    FailedAttempt {
+        /// Zero-based round trip of the loop
+        step: usize,
+        /// Number of the attempt within the run, starting at 1
+        attempt: u32,
+        /// Why the attempt failed
+        error: String,
+        /// Backoff delay waited before the attempt
+        backoff_ms: u64,
+        /// Time from sending the attempt to its failure
+        duration_ms: u64,
+    },
+    /// The model responded
+    Response {
+        /// Zero-based round trip of the loop
+        step: usize,
+        /// Finish reason of the first candidate
+        finish_reason: Option<FinishReason>,
+        /// Token usage of the response
+        usage: Option<UsageMetadata>,
+        /// Number of function calls in the first candidate
+        function_calls: usize,
+        /// Time from sending the request to the response, retries included
+        duration_ms: u64,
+    },
+    /// A function call of the model was run
+    ToolCall {
+        /// Zero-based round trip of the loop
+        step: usize,
+        /// Name of the function
+        name: String,
+        /// ID of the call, if the model set one
+        id: Option<String>,
+        /// Arguments of the call, scrubbed
+        args: Value,
+        /// Time the handler took
+        duration_ms: u64,
+        /// How the handler did
+        outcome: ToolOutcome,
+    },
+    /// The run ended
+    Finished {
+        /// Number of model round trips made
+        steps: usize,
+        /// How the run ended
+        outcome: RunOutcome,
+    },
+}
+
+/// How a tool handler did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ToolOutcome {
+    /// The handler returned a result
+    Ok,
+    /// The handler failed; the error was reported to the model
+    Error {
+        /// The error message
+        message: String,
+    },
+    /// No handler is registered or selected for the function
+    UnknownFunction,
+}
+
+/// How a tool-loop run ended
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RunOutcome {
+    /// The model answered without calling a function
+    Answer {
+        /// Finish reason of the first candidate
+        finish_reason: Option<FinishReason>,
+    },
+    /// The run failed
+    Error {
+        /// The error message
+        message: String,
+    },
+}
+
+type Scrubber = dyn Fn(&str, &mut Value) + Send + Sync;
+
+/// What a [`RunLog`] leaves out of the arguments of tool calls
+#[derive(Clone, Default)]
+pub struct RunLogOptions {
+    scrubbed_fields: Vec<String>,
+    scrubbers: Vec<Arc<Scrubber>>,
+}
+
+impl fmt::Debug for RunLogOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RunLogOptions")
+            .field("scrubbed_fields", &self.scrubbed_fields)
+            .field("scrubbers", &self.scrubbers.len())
+            .finish()
+    }
+}
+
+impl RunLogOptions {
+    /// Options logging arguments as they are
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the value of every object field named `field` in the arguments of every call,
+    /// at any depth, with [`SCRUBBED`]
+    pub fn scrub_field(mut self, field: impl Into<String>) -> Self {
+        self.scrubbed_fields.push(field.into());
+        self
+    }
+
+    /// Runs `scrubber` over the arguments of every call, with the function's name, after the
+    /// fields set with [`scrub_field()`](Self::scrub_field) were scrubbed
+    pub fn with_scrubber(
+        mut self,
+        scrubber: impl Fn(&str, &mut Value) + Send + Sync + 'static,
+    ) -> Self {
+        self.scrubbers.push(Arc::new(scrubber));
+        self
+    }
+
+    fn scrub(&self, function: &str, mut args: Value) -> Value {
+        if !self.scrubbed_fields.is_empty() {
+            scrub_fields(&mut args, &self.scrubbed_fields);
+        }
+        for scrubber in &self.scrubbers {
+            scrubber(function, &mut args);
+        }
+        args
+    }
+}
+
+fn scrub_fields(value: &mut Value, fields: &[String]) {
+    match value {
+        Value::Object(object) => {
+            for (name, value) in object {
+                if fields.contains(name) {
+                    *value = Value::String(SCRUBBED.to_string());
+                } else {
+                    scrub_fields(value, fields);
+                }
+            }
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| scrub_fields(value, fields)),
+        _ => {}
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// Records the events of a tool-loop run
+pub(crate) struct RunRecorder {
+    options: RunLogOptions,
+    log: RunLog,
+    /// Number of network attempts of the run already looked at
+    attempts_seen: usize,
+}
+
+impl RunRecorder {
+    pub(crate) fn new(options: RunLogOptions) -> Self {
+        Self {
+            options,
+            log: RunLog::default(),
+            attempts_seen: 0,
+        }
+    }
+
+    pub(crate) fn request(
+        &mut self,
+        step: usize,
+        request: &GenerateContentRequest,
+        system_fragments: &[String],
+    ) {
+        self.log.events.push(RunEvent::Request {
+            step,
+            hash: format!("{:016x}", request.canonical_hash()),
+            contents: request.contents.len(),
+            bytes: serde_json::to_vec(request).map_or(0, |bytes| bytes.len()),
+            system_fragments: system_fragments.to_vec(),
+        });
+    }
+
+    /// Records the failed attempts among those of the run not looked at before
+    pub(crate) fn attempts(&mut self, step: usize, report: &AttemptsReport) {
+        let new = report.attempts.iter().skip(self.attempts_seen);
+        for attempt in new {
+            if let Some(error) = &attempt.error_summary {
+                self.log.events.push(RunEvent::FailedAttempt {
+                    step,
+                    attempt: attempt.attempt_number,
+                    error: error.clone(),
+                    backoff_ms: millis(attempt.backoff_applied),
+                    duration_ms: millis(attempt.duration),
+                });
+            }
+        }
+        self.attempts_seen = report.attempts.len();
+    }
+
+    pub(crate) fn response(&mut self, step: usize, response: &GenerationResponse, took: Duration) {
+        let candidate = response.candidates.first();
+        self.log.events.push(RunEvent::Response {
+            step,
+            finish_reason: candidate.and_then(|c| c.finish_reason.clone()),
+            usage: response.usage_metadata.clone(),
+            function_calls: candidate.map_or(0, |c| {
+                c.content
+                    .parts
+                    .iter()
+                    .flatten()
+                    .filter(|part| matches!(part, crate::Part::FunctionCall { .. }))
+                    .count()
+            }),
+            duration_ms: millis(took),
+        });
+    }
+
+    pub(crate) fn tool_call(
+        &mut self,
+        step: usize,
+        call: FunctionCall,
+        took: Duration,
+        outcome: ToolOutcome,
+    ) {
+        let args = self.options.scrub(&call.name, call.args);
+        self.log.events.push(RunEvent::ToolCall {
+            step,
+            name: call.name,
+            id: call.id,
+            args,
+            duration_ms: millis(took),
+            outcome,
+        });
+    }
+
+    /// Records how the run ended and returns the log
+    pub(crate) fn finish<E: fmt::Display>(
+        mut self,
+        result: &Result<GenerationResponse, E>,
+    ) -> RunLog {
+        let outcome = match result {
+            Ok(response) => RunOutcome::Answer {
+                finish_reason: response
+                    .candidates
+                    .first()
+                    .and_then(|c| c.finish_reason.clone()),
+            },
+            Err(error) => RunOutcome::Error {
+                message: error.to_string(),
+            },
+        };
+        let steps = self.log.steps();
+        self.log.events.push(RunEvent::Finished { steps, outcome });
+        self.log
+    }
+}
@@ -0,0 +1,1220 @@
+//! TOON (Token-Oriented Object Notation) for sending datasets to the model.
+//!
+//! A list of records as JSON repeats every key in every row. TOON's tabular form states the
+//! keys once, in a header, followed by one comma-separated row per record:
+//!
+//! ```text
+//! [2]{id,name,role}:
+//!   1,Alice,admin
+//!   2,"Smith, Jane",user
+//! ```
+//!
+//! [`to_table_string()`] always produces this form; [`from_table_str()`] parses it back, and
+//! [`ContentBuilder::with_toon_table_message()`](crate::ContentBuilder::with_toon_table_message)
+//! sends a table as a user message.
+//!
+//! Every row must serialize to an object with the same fields, in the same order, holding
+//! only strings, numbers, booleans, or nulls. Rows with missing or extra fields fail with
+//! [`Error::Serialize`] rather than being padded with nulls, so a `null` in a table always
+//! means the field was present and null: serialize `Option` fields without
+//! `skip_serializing_if` to keep them in every row.
+//!
+//! Strings are quoted only when they would otherwise be read as something else: when empty,
+//! padded with whitespace, equal to `true`, `false`, or `null`, numeric, starting with `-`, or
+//! containing a delimiter, quote, bracket, brace, backslash, or control character. Quoted
+//! strings escape `\\`, `"`, `\n`, `\r`, and `\t`.
+//!
+//! ## Documents
+//!
+//! [`to_json_string()`] and [`from_json_str()`] convert whole documents between TOON and JSON
+//! text, e.g. to inspect logged payloads; `cargo run --example toon_convert` wraps them for the
+//! command line. Objects are written as `key: value` lines, nested objects indented by two
+//! spaces under `key:`, and arrays with their length:
+//!
+//! ```text
+//! service: checkout
+//! limits:
+//!   zones[3]: a,b,c
+//!   rows[2]{sku,qty}:
+//!     A-1,2
+//!     B-7,1
+//! events[2]:
+//!   - kind: created
+//!     tags[1]: web
+//!   - 42
+//! ```
+//!
+//! Arrays of primitives are written inline, arrays of objects with the same primitive fields
+//! as tables, and other arrays as lists of `- ` items, an item's further fields indented under
+//! its first. Keys keep their order in both directions, and numbers keep their form.
+//!
+//! [`to_string_with()`] and [`from_str_with()`] take [`ToonOptions`] to change that layout:
+//! the [`Delimiter`] between the values of inline arrays and table rows, the indentation
+//! width, and whether arrays of objects become tables. A tab or pipe delimiter is marked in
+//! the array's header, e.g. `rows[2|]{sku|qty}:`, so documents are read with whichever
+//! delimiter they were written with. The default options give the layout above.
+//!
+//! ## Typed values
+//!
+//! [`to_string()`] and [`from_str()`] convert any serializable value, going through its JSON
+//! form. [`ContentBuilder::with_toon_message()`](crate::ContentBuilder::with_toon_message)
+//! sends a value as a user message. Parse the answer of a model asked to reply in TOON with
+//! [`from_str()`], after [`extract()`]ing it from code fences or prose; the unstable
+//! `toon_wip` feature adds `ContentBuilder::execute_toon()`, which does both.
+//!
+//! Models know JSON far better than TOON and, merely asked for TOON, often answer with
+//! something close that does not parse.
+//! [`ContentBuilder::using_toon()`](crate::ContentBuilder::using_toon) adds [`SPEC`], a short
+//! grammar with an example, to the system instruction;
+//! [`ContentBuilder::using_toon_with_spec()`](crate::ContentBuilder::using_toon_with_spec)
+//! adds a spec of your own instead.
+//!
+//! ## Errors
+//!
+//! Every function fails with an [`Error`]: [`Error::Serialize`] for values that cannot be
+//! written, [`Error::Deserialize`] for text that does not parse or does not fit the type read
+//! from it, located at the line and column of the offending token, and [`Error::Io`] for the
+//! readers and writers of [`from_reader()`] and [`to_writer()`].
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use snafu::{ResultExt, Snafu};
+use std::{fmt, io};
+
+/// The TOON format specification added to the system instruction by
+/// [`ContentBuilder::using_toon()`](crate::ContentBuilder::using_toon): the syntax this
+/// module parses, and an example answer
+pub const SPEC: &str = r#"Answer with a single TOON (Token-Oriented Object Notation) document in a ```toon code fence, and nothing else. TOON rules:
+- Write one `key: value` field per line. Keys are bare identifiers, or quoted.
+- Nest an object under `key:`, its fields indented by exactly two more spaces than the key.
+- Give every array its length in brackets, and make it match the number of items:
+  - primitives inline, comma-separated: `tags[3]: red,green,blue`
+  - objects whose fields are all primitives, as a table: a header `rows[2]{id,name}:`, then one line per object, indented two spaces, with the values in header order
+  - anything else as a list: `items[2]:`, then one item per line starting with `- ` indented two spaces; further fields of an item are indented under its first field
+- Values are strings, numbers, `true`, `false`, or `null`. Quote a string with `"` when it is empty, has leading or trailing spaces, reads as a number, `true`, `false`, or `null`, starts with `-`, or contains `,` `:` `"` `\` `[` `]` `{` or `}`. Inside quotes, escape `\` `"` `
+` `
+` `	`.
+- No comments, no braces around objects, no trailing commas, no JSON.
+
+Example:
+```toon
+order: A-17
+customer:
+  name: "Smith, Jane"
+  vip: true
+lines[2]{sku,qty,price}:
+  P-1,2,9.5
+  P-7,1,null
+tags[2]: gift,express
+events[2]:
+  - kind: created
+    at: "2024-05-01T10:00:00Z"
+  - kind: shipped
+    carriers[1]: DHL
+```"#;
+
+/// An error writing or reading TOON
+#[derive(Debug, Snafu)]
+pub enum Error {
+    /// A value cannot be written as TOON, e.g. a table row that is not an object
+    #[snafu(display("failed to serialize TOON: {message}"))]
+    Serialize { message: String },
+
+    /// The text does not parse, or does not fit the type read from it
+    ///
+    /// `line` and `column` are one-based, the column counting characters, and point at the
+    /// token where the error was detected: an unterminated string at its opening quote, a
+    /// missing or extra value at the end of its row or at the extra value. Both are 0 when the
+    /// error has no place in the text, as when a document parses but does not fit its type.
+    #[snafu(display("failed to deserialize{}: {message}", Location(*line, *column)))]
+    Deserialize {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    /// Reading or writing the text failed
+    #[snafu(display("failed to read or write TOON"))]
+    Io { source: io::Error },
+}
+
+/// ` at line L, column C`, or nothing for an error without a place in the text
+struct Location(usize, usize);
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Location(0, _) => Ok(()),
+            Location(line, column) => write!(f, " at line {line}, column {column}"),
+        }
+    }
+}
+
+fn serialize_error(message: impl Into<String>) -> Error {
+    Error::Serialize {
+        message: message.into(),
+    }
+}
+
+/// A deserialization error at `at`, a slice of `source`
+fn error_at(source: &str, at: &str, message: impl Into<String>) -> Error {
+    let (line, column) = position(source, at);
+    Error::Deserialize {
+        line,
+        column,
+        message: message.into(),
+    }
+}
+
+/// A deserialization error at the line and column serde_json reports for invalid JSON
+fn json_error(error: serde_json::Error) -> Error {
+    let message = error.to_string();
+    let location = format!(" at line {} column {}", error.line(), error.column());
+    Error::Deserialize {
+        line: error.line(),
+        column: error.column(),
+        message: message
+            .strip_suffix(&location)
+            .unwrap_or(&message)
+            .to_string(),
+    }
+}
+
+/// A deserialization error without a place in the text
+fn type_error(error: serde_json::Error) -> Error {
+    Error::Deserialize {
+        line: 0,
+        column: 0,
+        message: error.to_string(),
+    }
+}
+
+/// The one-based line and column, in characters, where `at`, a slice of `source`, starts
+fn position(source: &str, at: &str) -> (usize, usize) {
+    let offset = (at.as_ptr() as usize).wrapping_sub(source.as_ptr() as usize);
+    debug_assert!(offset <= source.len(), "`at` is not a slice of `source`");
+    let Some(before) = source.get(..offset) else {
+        return (0, 0);
+    };
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// The character between the values of inline arrays and table rows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Delimiter {
+    /// `,`, the default
+    #[default]
+    Comma,
+    /// A tab character
+    Tab,
+    /// `|`
+    Pipe,
+}
+
+impl Delimiter {
+    fn char(self) -> char {
+        match self {
+            Delimiter::Comma => ',',
+            Delimiter::Tab => '\t',
+            Delimiter::Pipe => '|',
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Delimiter::Comma => ",",
+            Delimiter::Tab => "\t",
+            Delimiter::Pipe => "|",
+        }
+    }
+
+    /// The marker after an array's length naming the delimiter, none for commas
+    fn marker(self) -> &'static str {
+        match self {
+            Delimiter::Comma => "",
+            Delimiter::Tab => "\t",
+            Delimiter::Pipe => "|",
+        }
+    }
+}
+
+/// How [`to_string_with()`] lays out a document and [`from_str_with()`] reads it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToonOptions {
+    delimiter: Delimiter,
+    indent: usize,
+    tables: bool,
+}
+
+impl Default for ToonOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: Delimiter::Comma,
+            indent: 2,
+            tables: true,
+        }
+    }
+}
+
+impl ToonOptions {
+    /// The layout of [`to_string()`]: commas, two-space indentation, and tables
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Separates the values of inline arrays and table rows with `delimiter`.
+    ///
+    /// Strings containing the delimiter are quoted.
+    pub fn with_delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Indents nested fields, list items, and table rows by `width` spaces per level.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `width` is zero.
+    pub fn with_indent(mut self, width: usize) -> Self {
+        assert!(width > 0, "indentation width must be positive");
+        self.indent = width;
+        self
+    }
+
+    /// Whether arrays of objects with the same primitive fields are written as tables, rather
+    /// than as lists of `- ` items; `true` by default
+    pub fn with_tables(mut self, tables: bool) -> Self {
+        self.tables = tables;
+        self
+    }
+}
+
+/// An object with its fields in serialization order
+struct Fields(Vec<(String, Value)>);
+
+impl<'de> Deserialize<'de> for Fields {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Fields;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("an object")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<Fields, A::Error> {
+                let mut fields = Vec::new();
+                while let Some(field) = map.next_entry()? {
+                    fields.push(field);
+                }
+                Ok(Fields(fields))
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+/// Serializes `rows` as a TOON table.
+///
+/// The header lists the fields of the first row in the order they are serialized, so struct
+/// rows keep their declaration order. An empty slice yields `[0]:`.
+pub fn to_table_string<T: Serialize>(rows: &[T]) -> Result<String, Error> {
+    let mut keys: Option<Vec<String>> = None;
+    let mut lines = Vec::with_capacity(rows.len() + 1);
+    for (row, value) in rows.iter().enumerate() {
+        let json = serde_json::to_string(value)
+            .map_err(|error| serialize_error(format!("row {row}: {error}")))?;
+        let Fields(fields) = serde_json::from_str(&json)
+            .map_err(|_| serialize_error(format!("row {row} is not an object")))?;
+        let keys = keys.get_or_insert_with(|| fields.iter().map(|(k, _)| k.clone()).collect());
+        let names: Vec<&String> = fields.iter().map(|(field, _)| field).collect();
+        // A missing field, an extra field, or the same fields in another order
+        let mismatch = keys
+            .iter()
+            .find(|key| !names.contains(key))
+            .or_else(|| names.iter().copied().find(|name| !keys.contains(name)))
+            .or_else(|| {
+                keys.iter()
+                    .zip(&names)
+                    .find(|(k, n)| *k != **n)
+                    .map(|(k, _)| k)
+            });
+        if let Some(field) = mismatch {
+            return Err(serialize_error(format!(
+                "row {row} does not have the fields of the first row (field '{field}')"
+            )));
+        }
+
+        let values = fields
+            .iter()
+            .map(|(field, value)| match value {
+                Value::Null => Ok("null".to_string()),
+                Value::Bool(value) => Ok(value.to_string()),
+                Value::Number(value) => Ok(value.to_string()),
+                Value::String(value) => Ok(encode_string(value, ',')),
+                Value::Array(_) | Value::Object(_) => Err(serialize_error(format!(
+                    "field '{field}' of row {row} is not a string, number, boolean, or null"
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        lines.push(format!("  {}", values.join(",")));
+    }
+
+    let keys: Vec<String> = keys.iter().flatten().map(|key| encode_key(key)).collect();
+    let header = match keys.is_empty() {
+        true => format!("[{}]:", rows.len()),
+        false => format!("[{}]{{{}}}:", rows.len(), keys.join(",")),
+    };
+    lines.insert(0, header);
+    Ok(lines.join("\n"))
+}
+
+/// Parses a TOON table into rows.
+///
+/// Unquoted values are read as `null`, booleans, or numbers where they look like one, and as
+/// strings otherwise; each row is then deserialized from the object of its fields.
+pub fn from_table_str<T: DeserializeOwned>(table: &str) -> Result<Vec<T>, Error> {
+    let mut lines = table.lines().filter(|line| !line.trim().is_empty());
+    let Some(header) = lines.next().map(str::trim) else {
+        return Err(error_at(table, table, "missing header"));
+    };
+    let (expected, keys) = parse_header(header).ok_or_else(|| {
+        error_at(
+            table,
+            header,
+            format!("expected a header such as `[2]{{id,name}}:`, found `{header}`"),
+        )
+    })?;
+
+    let mut rows = Vec::with_capacity(expected);
+    for text in lines.map(str::trim) {
+        let values = split_row(text, ',')
+            .map_err(|quote| error_at(table, quote, "unterminated quoted string"))?;
+        check_width(table, text, &values, keys.len())?;
+        let object: Map<String, Value> = keys
+            .iter()
+            .cloned()
+            .zip(values.into_iter().map(|(_, value)| value))
+            .collect();
+        let row = rows.len();
+        rows.push(
+            serde_json::from_value(Value::Object(object))
+                .map_err(|error| error_at(table, text, format!("row {row}: {error}")))?,
+        );
+    }
+    if rows.len() != expected {
+        return Err(error_at(
+            table,
+            header,
+            format!("expected {expected} rows, found {}", rows.len()),
+        ));
+    }
+    Ok(rows)
+}
+
+/// Fails unless `row`, a line of `source`, has `width` values, pointing at its end when it
+/// has fewer and at the first extra value when it has more
+fn check_width(
+    source: &str,
+    row: &str,
+    values: &[(&str, Value)],
+    width: usize,
+) -> Result<(), Error> {
+    if values.len() == width {
+        return Ok(());
+    }
+    let at = values.get(width).map_or(&row[row.len()..], |(at, _)| at);
+    Err(error_at(
+        source,
+        at,
+        format!("expected {width} values, found {}", values.len()),
+    ))
+}
+
+/// Parses `[N]{a,b}:` (or `[0]:`) into the row count and field names
+fn parse_header(header: &str) -> Option<(usize, Vec<String>)> {
+    let rest = header.strip_prefix('[')?;
+    let (count, rest) = rest.split_once(']')?;
+    let count = count.parse().ok()?;
+    let rest = rest.strip_suffix(':')?;
+    if rest.is_empty() {
+        return (count == 0).then(Vec::new).map(|keys| (count, keys));
+    }
+    let fields = rest.strip_prefix('{')?.strip_suffix('}')?;
+    Some((count, keys(fields, ',')?))
+}
+
+/// The field names of a table header, between its braces
+fn keys(fields: &str, delimiter: char) -> Option<Vec<String>> {
+    let keys = split_row(fields, delimiter).ok()?;
+    let keys = keys.into_iter().map(|(_, key)| match key {
+        Value::String(key) => key,
+        other => other.to_string(),
+    });
+    Some(keys.collect())
+}
+
+/// Splits a row at the delimiters outside quoted strings and decodes each value, paired with
+/// the text it starts at; an unterminated quoted string fails with the text from its quote
+fn split_row(row: &str, delimiter: char) -> Result<Vec<(&str, Value)>, &str> {
+    let mut values = Vec::new();
+    let mut current = String::new();
+    // Where the current value and its opening quote start
+    let (mut start, mut quote) = (0, 0);
+    // Whether the current value was quoted, and whether its closing quote is still ahead
+    let (mut quoted, mut in_quotes) = (false, false);
+    let mut chars = row.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' if in_quotes => in_quotes = false,
+            '\\' if in_quotes => current.push(match chars.next().ok_or(&row[quote..])?.1 {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                other => other,
+            }),
+            c if in_quotes => current.push(c),
+            '"' if !quoted && current.trim().is_empty() => {
+                current.clear();
+                quote = index;
+                (quoted, in_quotes) = (true, true);
+            }
+            c if c == delimiter => {
+                let value = decode(std::mem::take(&mut current), quoted);
+                values.push((row[start..].trim_start(), value));
+                start = index + c.len_utf8();
+                quoted = false;
+            }
+            // Whitespace after a closing quote
+            _ if quoted => {}
+            c => current.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(&row[quote..]);
+    }
+    values.push((row[start..].trim_start(), decode(current, quoted)));
+    Ok(values)
+}
+
+fn decode(raw: String, quoted: bool) -> Value {
+    if quoted {
+        return Value::String(raw);
+    }
+    match raw.trim() {
+        "null" => Value::Null,
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        raw => serde_json::from_str::<serde_json::Number>(raw)
+            .map(Value::Number)
+            .unwrap_or_else(|_| Value::String(raw.to_string())),
+    }
+}
+
+fn encode_string(value: &str, delimiter: char) -> String {
+    let needs_quotes = value.is_empty()
+        || value.trim() != value
+        || matches!(value, "true" | "false" | "null")
+        || value.parse::<f64>().is_ok()
+        || value.starts_with('-')
+        || value.chars().any(|c| {
+            matches!(c, ',' | ':' | '"' | '\\' | '[' | ']' | '{' | '}')
+                || c == delimiter
+                || c.is_control()
+        });
+    match needs_quotes {
+        true => quote(value),
+        false => value.to_string(),
+    }
+}
+
+fn encode_key(key: &str) -> String {
+    let mut chars = key.chars();
+    let plain = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    match plain {
+        true => key.to_string(),
+        false => quote(key),
+    }
+}
+
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A JSON value keeping the keys of its objects in document order
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Null,
+    Bool(bool),
+    Number(serde_json::Number),
+    String(String),
+    Array(Vec<Node>),
+    Object(Vec<(String, Node)>),
+}
+
+impl From<Value> for Node {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => Node::Null,
+            Value::Bool(value) => Node::Bool(value),
+            Value::Number(value) => Node::Number(value),
+            Value::String(value) => Node::String(value),
+            Value::Array(items) => Node::Array(items.into_iter().map(Node::from).collect()),
+            Value::Object(fields) => {
+                Node::Object(fields.into_iter().map(|(k, v)| (k, v.into())).collect())
+            }
+        }
+    }
+}
+
+impl Serialize for Node {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        match self {
+            Node::Null => serializer.serialize_unit(),
+            Node::Bool(value) => serializer.serialize_bool(*value),
+            Node::Number(value) => value.serialize(serializer),
+            Node::String(value) => serializer.serialize_str(value),
+            Node::Array(items) => serializer.collect_seq(items),
+            Node::Object(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (key, value) in fields {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Node;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a JSON value")
+            }
+
+            fn visit_unit<E>(self) -> Result<Node, E> {
+                Ok(Node::Null)
+            }
+
+            fn visit_bool<E>(self, value: bool) -> Result<Node, E> {
+                Ok(Node::Bool(value))
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<Node, E> {
+                Ok(Node::Number(value.into()))
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Node, E> {
+                Ok(Node::Number(value.into()))
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Node, E> {
+                serde_json::Number::from_f64(value)
+                    .map(Node::Number)
+                    .ok_or_else(|| E::custom("number is not finite"))
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Node, E> {
+                Ok(Node::String(value.to_string()))
+            }
+
+            fn visit_string<E>(self, value: String) -> Result<Node, E> {
+                Ok(Node::String(value))
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+                let mut items = Vec::new();
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(Node::Array(items))
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+                let mut fields = Vec::new();
+                while let Some(field) = map.next_entry()? {
+                    fields.push(field);
+                }
+                Ok(Node::Object(fields))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// Converts a TOON document to pretty-printed JSON.
+///
+/// Object keys keep their order in the document, and numbers their form: integers stay
+/// integers. Integers beyond the range of `i64` and `u64` are read as floating-point numbers.
+pub fn to_json_string(toon: &str) -> Result<String, Error> {
+    let node = parse(toon, 2)?;
+    Ok(serde_json::to_string_pretty(&node).expect("JSON values serialize"))
+}
+
+/// Converts a JSON document to TOON.
+///
+/// Object keys keep their order in the JSON text. Arrays of objects sharing the same
+/// primitive fields become tables, arrays of primitives are written inline, and other arrays
+/// as lists of `- ` items.
+pub fn from_json_str(json: &str) -> Result<String, Error> {
+    let node: Node = serde_json::from_str(json).map_err(json_error)?;
+    Ok(encode_document(&node, &ToonOptions::default()))
+}
+
+/// Serializes `value` as a TOON document, see [`from_json_str()`] for its layout.
+pub fn to_string<T: Serialize>(value: &T) -> Result<String, Error> {
+    to_string_with(value, &ToonOptions::default())
+}
+
+/// Serializes `value` as a TOON document laid out as `options` say.
+pub fn to_string_with<T: Serialize>(value: &T, options: &ToonOptions) -> Result<String, Error> {
+    let json = serde_json::to_string(value).map_err(|error| serialize_error(error.to_string()))?;
+    let node: Node =
+        serde_json::from_str(&json).map_err(|error| serialize_error(error.to_string()))?;
+    Ok(encode_document(&node, options))
+}
+
+/// Parses a TOON document into `T`.
+///
+/// The document is read as by [`to_json_string()`], so `T` is deserialized as it would be
+/// from the equivalent JSON.
+pub fn from_str<T: DeserializeOwned>(toon: &str) -> Result<T, Error> {
+    from_str_with(toon, &ToonOptions::default())
+}
+
+/// Parses a TOON document indented as `options` say into `T`.
+///
+/// Only the indentation width is taken from `options`: each array names its own delimiter
+/// in its header, and tables are read whether or not `options` writes them.
+pub fn from_str_with<T: DeserializeOwned>(toon: &str, options: &ToonOptions) -> Result<T, Error> {
+    let node = parse(toon, options.indent)?;
+    let value = serde_json::to_value(&node).expect("JSON values serialize");
+    serde_json::from_value(value).map_err(type_error)
+}
+
+/// Serializes `value` as a TOON document into `writer`.
+pub fn to_writer<W: io::Write, T: Serialize>(mut writer: W, value: &T) -> Result<(), Error> {
+    writer
+        .write_all(to_string(value)?.as_bytes())
+        .context(IoSnafu)
+}
+
+/// Reads a TOON document from `reader` and parses it into `T`.
+///
+/// Text that is not UTF-8 fails with [`Error::Io`].
+pub fn from_reader<R: io::Read, T: DeserializeOwned>(mut reader: R) -> Result<T, Error> {
+    let mut toon = String::new();
+    reader.read_to_string(&mut toon).context(IoSnafu)?;
+    from_str(&toon)
+}
+
+/// Extracts the TOON document from a model's answer text.
+///
+/// Models often wrap their answer in a code fence or in prose. The contents of the first
+/// fenced block are returned, whatever its language tag; without a fence, the first block of
+/// lines starting with a `key:` field, an array header, or a `- ` item, up to the first
+/// unindented line that starts with none of them. Text with no such line is returned trimmed.
+pub fn extract(text: &str) -> &str {
+    if let Some(fenced) = fenced_block(text) {
+        return fenced;
+    }
+    let mut start = None;
+    let mut end = text.len();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let content = line.trim_end();
+        let top_level = !content.is_empty() && !content.starts_with(' ');
+        match start {
+            None if top_level && looks_like_toon(content) => start = Some(offset),
+            Some(_) if top_level && !looks_like_toon(content) => {
+                end = offset;
+                break;
+            }
+            _ => {}
+        }
+        offset += line.len();
+    }
+    match start {
+        Some(start) => text[start..end].trim_end(),
+        None => text.trim(),
+    }
+}
+
+/// The contents of the first block fenced by lines starting with three backticks, if any
+fn fenced_block(text: &str) -> Option<&str> {
+    let mut offset = 0;
+    let mut start = None;
+    for line in text.split_inclusive('\n') {
+        if line.trim_start().starts_with("```") {
+            match start {
+                None => start = Some(offset + line.len()),
+                Some(start) => return Some(text[start..offset].trim_matches(['\n', '\r'])),
+            }
+        }
+        offset += line.len();
+    }
+    // An unclosed fence, as in a truncated answer, runs to the end of the text
+    start.map(|start| text[start..].trim_end())
+}
+
+/// Whether an unindented line starts like a TOON field, array header, or list item
+fn looks_like_toon(line: &str) -> bool {
+    line.starts_with("- ")
+        || array_header(line).is_some()
+        || field(line).is_some_and(|(key, _)| line.starts_with('"') || encode_key(&key) == key)
+}
+
+/// The lines of a document being encoded, and the options laying it out
+struct Encoder<'a> {
+    options: &'a ToonOptions,
+    lines: Vec<String>,
+}
+
+fn encode_document(node: &Node, options: &ToonOptions) -> String {
+    let mut encoder = Encoder {
+        options,
+        lines: Vec::new(),
+    };
+    match node {
+        Node::Object(fields) => encoder.fields(fields, 0),
+        Node::Array(items) => encoder.array("", items, 0),
+        primitive => {
+            let line = encoder.primitive(primitive);
+            encoder.lines.push(line);
+        }
+    }
+    encoder.lines.join("\n")
+}
+
+impl Encoder<'_> {
+    fn push(&mut self, depth: usize, text: &str) {
+        self.lines
+            .push(format!("{}{text}", " ".repeat(depth * self.options.indent)));
+    }
+
+    fn primitive(&self, node: &Node) -> String {
+        match node {
+            Node::Null => "null".to_string(),
+            Node::Bool(value) => value.to_string(),
+            Node::Number(value) => value.to_string(),
+            Node::String(value) => encode_string(value, self.options.delimiter.char()),
+            Node::Array(_) | Node::Object(_) => unreachable!("not a primitive"),
+        }
+    }
+
+    /// The primitives `values` joined by the delimiter
+    fn row<'n>(&self, values: impl Iterator<Item = &'n Node>) -> String {
+        let values: Vec<String> = values.map(|value| self.primitive(value)).collect();
+        values.join(self.options.delimiter.as_str())
+    }
+
+    fn fields(&mut self, fields: &[(String, Node)], depth: usize) {
+        for (key, value) in fields {
+            let key = encode_key(key);
+            match value {
+                Node::Object(fields) => {
+                    self.push(depth, &format!("{key}:"));
+                    self.fields(fields, depth + 1);
+                }
+                Node::Array(items) => self.array(&key, items, depth),
+                primitive => {
+                    let value = self.primitive(primitive);
+                    self.push(depth, &format!("{key}: {value}"));
+                }
+            }
+        }
+    }
+
+    /// Encodes an array whose header line starts with `head`, e.g. its key
+    fn array(&mut self, head: &str, items: &[Node], depth: usize) {
+        let len = items.len();
+        let marker = self.options.delimiter.marker();
+        let is_primitive = |node: &Node| !matches!(node, Node::Array(_) | Node::Object(_));
+        let table = match self.options.tables {
+            true => table_keys(items),
+            false => None,
+        };
+        if items.is_empty() {
+            self.push(depth, &format!("{head}[0]:"));
+        } else if items.iter().all(is_primitive) {
+            let values = self.row(items.iter());
+            self.push(depth, &format!("{head}[{len}{marker}]: {values}"));
+        } else if let Some(keys) = table {
+            let header: Vec<String> = keys.iter().map(|key| encode_key(key)).collect();
+            let header = header.join(self.options.delimiter.as_str());
+            self.push(depth, &format!("{head}[{len}{marker}]{{{header}}}:"));
+            for item in items {
+                let Node::Object(fields) = item else {
+                    unreachable!("table rows are objects")
+                };
+                let values = self.row(fields.iter().map(|(_, value)| value));
+                self.push(depth + 1, &values);
+            }
+        } else {
+            self.push(depth, &format!("{head}[{len}]:"));
+            for item in items {
+                self.item(item, depth + 1);
+            }
+        }
+    }
+
+    /// Encodes a list item: its first line is encoded one level deeper, then marked with `- `
+    fn item(&mut self, item: &Node, depth: usize) {
+        let start = self.lines.len();
+        match item {
+            Node::Object(fields) if fields.is_empty() => {
+                self.push(depth, "-");
+                return;
+            }
+            Node::Object(fields) => self.fields(fields, depth + 1),
+            Node::Array(items) => self.array("", items, depth + 1),
+            primitive => {
+                let line = self.primitive(primitive);
+                self.push(depth + 1, &line);
+            }
+        }
+        let indent = self.options.indent;
+        self.lines[start].replace_range(depth * indent..(depth + 1) * indent, "- ");
+    }
+}
+
+/// The keys shared, in the same order, by objects `items` holding only primitives
+fn table_keys(items: &[Node]) -> Option<Vec<&str>> {
+    let mut keys: Option<Vec<&str>> = None;
+    for item in items {
+        let Node::Object(fields) = item else {
+            return None;
+        };
+        if fields
+            .iter()
+            .any(|(_, value)| matches!(value, Node::Array(_) | Node::Object(_)))
+        {
+            return None;
+        }
+        let names: Vec<&str> = fields.iter().map(|(key, _)| key.as_str()).collect();
+        match &keys {
+            Some(keys) if *keys != names => return None,
+            Some(_) => {}
+            None => keys = Some(names),
+        }
+    }
+    keys.filter(|keys| !keys.is_empty())
+}
+
+/// A line of a TOON document
+struct Line<'a> {
+    /// Indentation level, in units of the indentation width
+    depth: usize,
+    /// The line without its indentation, a slice of the document to locate errors
+    text: &'a str,
+}
+
+/// The header of a field, after its key
+enum FieldHeader<'a> {
+    /// `key: value`, or `key:` opening a nested object
+    Value(&'a str),
+    /// `key[N]...`
+    Array(ArrayHeader<'a>),
+}
+
+/// `[N]: a,b`, `[N]{x,y}:`, or `[N]:`, with `|` or a tab after `N` for those delimiters
+struct ArrayHeader<'a> {
+    /// The header from its opening bracket, where item count errors point
+    at: &'a str,
+    len: usize,
+    delimiter: char,
+    fields: Option<Vec<String>>,
+    inline: &'a str,
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    lines: Vec<Line<'a>>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str, width: usize) -> Result<Self, Error> {
+        let mut lines = Vec::new();
+        for raw in source.lines() {
+            let text = raw.trim_start_matches(' ');
+            if text.trim().is_empty() {
+                continue;
+            }
+            let indent = raw.len() - text.len();
+            if indent % width != 0 {
+                return Err(error_at(
+                    source,
+                    text,
+                    format!("indentation is not a multiple of {width} spaces"),
+                ));
+            }
+            lines.push(Line {
+                depth: indent / width,
+                text: text.trim_end(),
+            });
+        }
+        Ok(Self {
+            source,
+            lines,
+            position: 0,
+        })
+    }
+
+    /// Fails with `message` at `at`, a slice of the document
+    fn error<T>(&self, at: &str, message: impl Into<String>) -> Result<T, Error> {
+        Err(error_at(self.source, at, message))
+    }
+
+    fn document(mut self) -> Result<Node, Error> {
+        let node = match self.lines.first() {
+            None => Node::Object(Vec::new()),
+            Some(line) if line.depth > 0 => return self.error(line.text, "unexpected indentation"),
+            Some(line) => match array_header(line.text) {
+                Some(header) => {
+                    self.position += 1;
+                    self.array(header, 0)?
+                }
+                None if self.lines.len() == 1 && field(line.text).is_none() => {
+                    self.position += 1;
+                    self.primitive(line.text)?
+                }
+                None => Node::Object(self.fields(0)?),
+            },
+        };
+        match self.lines.get(self.position) {
+            Some(line) => self.error(line.text, "unexpected indentation"),
+            None => Ok(node),
+        }
+    }
+
+    /// The fields of an object at `depth`
+    fn fields(&mut self, depth: usize) -> Result<Vec<(String, Node)>, Error> {
+        let mut fields = Vec::new();
+        while let Some(line) = self.lines.get(self.position) {
+            if line.depth < depth {
+                break;
+            }
+            let text = line.text;
+            if line.depth > depth {
+                return self.error(text, "unexpected indentation");
+            }
+            if text == "-" || text.starts_with("- ") {
+                return self.error(text, "list item outside of a list");
+            }
+            let Some((key, header)) = field(text) else {
+                return self.error(text, "expected `key: value`");
+            };
+            self.position += 1;
+            fields.push((key, self.value(header, depth)?));
+        }
+        Ok(fields)
+    }
+
+    /// The value of a field at `depth`
+    fn value(&mut self, header: FieldHeader<'a>, depth: usize) -> Result<Node, Error> {
+        match header {
+            FieldHeader::Value("") => Ok(Node::Object(self.fields(depth + 1)?)),
+            FieldHeader::Value(text) => self.primitive(text),
+            FieldHeader::Array(header) => self.array(header, depth),
+        }
+    }
+
+    /// The items of an array whose header is at `depth`
+    fn array(&mut self, header: ArrayHeader<'a>, depth: usize) -> Result<Node, Error> {
+        let ArrayHeader {
+            at,
+            len,
+            delimiter,
+            fields,
+            inline,
+        } = header;
+        if let Some(keys) = fields {
+            let mut rows = Vec::with_capacity(len);
+            for found in 0..len {
+                let Some(line) = self
+                    .lines
+                    .get(self.position)
+                    .filter(|l| l.depth == depth + 1)
+                else {
+                    return self.error(at, format!("expected {len} rows, found {found}"));
+                };
+                let values = split_row(line.text, delimiter)
+                    .or_else(|quote| self.error(quote, "unterminated quoted string"))?;
+                check_width(self.source, line.text, &values, keys.len())?;
+                self.position += 1;
+                let values = values.into_iter().map(|(_, value)| Node::from(value));
+                rows.push(Node::Object(keys.iter().cloned().zip(values).collect()));
+            }
+            return Ok(Node::Array(rows));
+        }
+        if !inline.is_empty() {
+            let values = split_row(inline, delimiter)
+                .or_else(|quote| self.error(quote, "unterminated quoted string"))?;
+            if values.len() != len {
+                let at = values.get(len).map_or(at, |(extra, _)| extra);
+                return self.error(at, format!("expected {len} items, found {}", values.len()));
+            }
+            let values = values.into_iter().map(|(_, value)| Node::from(value));
+            return Ok(Node::Array(values.collect()));
+        }
+        let mut items = Vec::with_capacity(len);
+        for found in 0..len {
+            let Some(line) = self
+                .lines
+                .get(self.position)
+                .filter(|l| l.depth == depth + 1)
+            else {
+                return self.error(at, format!("expected {len} items, found {found}"));
+            };
+            let line = line.text;
+            self.position += 1;
+            if line == "-" {
+                items.push(Node::Object(Vec::new()));
+                continue;
+            }
+            let Some(text) = line.strip_prefix("- ") else {
+                return self.error(line, "expected a list item starting with `- `");
+            };
+            // The item continues one level deeper than its marker
+            let item = match (array_header(text), field(text)) {
+                (Some(header), _) => self.array(header, depth + 2)?,
+                (None, Some((key, header))) => {
+                    let mut fields = vec![(key, self.value(header, depth + 2)?)];
+                    fields.extend(self.fields(depth + 2)?);
+                    Node::Object(fields)
+                }
+                (None, None) => self.primitive(text)?,
+            };
+            items.push(item);
+        }
+        Ok(Node::Array(items))
+    }
+
+    /// Parses a primitive value, reading unquoted text with commas as a string
+    fn primitive(&self, text: &str) -> Result<Node, Error> {
+        match split_row(text, ',') {
+            Ok(mut values) if values.len() == 1 => Ok(values.remove(0).1.into()),
+            Ok(_) => Ok(Node::String(text.to_string())),
+            Err(quote) => self.error(quote, "unterminated quoted string"),
+        }
+    }
+}
+
+/// Parses a TOON document indented by `width`
+fn parse(toon: &str, width: usize) -> Result<Node, Error> {
+    Parser::new(toon, width)?.document()
+}
+
+/// Parses `key: value`, `key:`, or `key[N]...`, with a bare or quoted key
+fn field(text: &str) -> Option<(String, FieldHeader<'_>)> {
+    let (key, rest) = match text.strip_prefix('"') {
+        Some(quoted) => unquote(quoted)?,
+        None => {
+            let end = text.find([':', '['])?;
+            let key = text[..end].trim_end();
+            if key.is_empty() || key.contains(['"', ',']) {
+                return None;
+            }
+            (key.to_string(), &text[end..])
+        }
+    };
+    let header = match rest.strip_prefix(':') {
+        Some(value) => FieldHeader::Value(value.trim()),
+        None => FieldHeader::Array(array_header(rest)?),
+    };
+    Some((key, header))
+}
+
+/// Parses `[N]: a,b`, `[N]{x,y}:`, or `[N]:`, with a delimiter marker after `N`
+fn array_header(text: &str) -> Option<ArrayHeader<'_>> {
+    let (len, rest) = text.strip_prefix('[')?.split_once(']')?;
+    let (len, delimiter) = match len.strip_suffix(['|', '\t']) {
+        Some(digits) => (digits, len[digits.len()..].chars().next()?),
+        None => (len, ','),
+    };
+    let len = len.parse().ok()?;
+    let (fields, rest) = match rest.strip_prefix('{') {
+        Some(rest) => {
+            let (fields, rest) = split_braces(rest)?;
+            (Some(keys(fields, delimiter)?), rest)
+        }
+        None => (None, rest),
+    };
+    let inline = rest.strip_prefix(':')?.trim();
+    Some(ArrayHeader {
+        at: text,
+        len,
+        delimiter,
+        fields,
+        inline,
+    })
+}
+
+/// Splits `x,y}rest` at the closing brace outside quoted keys
+fn split_braces(text: &str) -> Option<(&str, &str)> {
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '}' if !in_quotes => return Some((&text[..index], &text[index + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Reads a quoted string whose opening quote was stripped, returning it and the rest
+fn unquote(text: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[index + 1..])),
+            '\\' => value.push(match chars.next()?.1 {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                other => other,
+            }),
+            c => value.push(c),
+        }
+    }
+    None
+}
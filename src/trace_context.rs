@@ -0,0 +1,74 @@
+//! W3C trace context sent with requests.
+//!
+//! Requests carry a [`traceparent`](https://www.w3.org/TR/trace-context/#traceparent-header)
+//! header, so that proxies, gateways, and tracing backends can tie them to the trace of the
+//! caller, when there is a trace to join:
+//!
+//! - the one set with
+//!   [`ContentBuilder::with_traceparent()`](crate::ContentBuilder::with_traceparent), for
+//!   callers that propagate trace context without OpenTelemetry;
+//! - otherwise, with the `otel` feature, the OpenTelemetry context of the current `tracing`
+//!   span, as attached by a [`tracing-opentelemetry`](https://docs.rs/tracing-opentelemetry)
+//!   layer. The header names the span of the HTTP request, a child of the caller's span.
+//!
+//! Without either, e.g. with no OpenTelemetry layer installed or outside of any trace, no
+//! header is sent. The `responseId` of a generation is recorded in the `gemini.response_id`
+//! field of its span, which the layer exports as a span attribute.
+
+use reqwest::RequestBuilder;
+
+/// The name of the header
+const TRACEPARENT: &str = "traceparent";
+
+/// Whether `value` is a version `00` traceparent, `00-<trace id>-<parent id>-<flags>`, in
+/// lowercase hex and with ids that are not all zeros
+pub(crate) fn is_valid(value: &str) -> bool {
+    let hex = |field: &str, len: usize| {
+        field.len() == len
+            && field
+                .bytes()
+                .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    let non_zero = |field: &str| field.bytes().any(|b| b != b'0');
+    match value.split('-').collect::<Vec<_>>().as_slice() {
+        ["00", trace_id, parent_id, flags] => {
+            hex(trace_id, 32)
+                && hex(parent_id, 16)
+                && hex(flags, 2)
+                && non_zero(trace_id)
+                && non_zero(parent_id)
+        }
+        _ => false,
+    }
+}
+
+/// The traceparent of the OpenTelemetry context of the current span, if it holds a valid span
+#[cfg(feature = "otel")]
+fn current() -> Option<String> {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    span_context.is_valid().then(|| {
+        format!(
+            "00-{}-{}-{:02x}",
+            span_context.trace_id(),
+            span_context.span_id(),
+            span_context.trace_flags().to_u8()
+        )
+    })
+}
+
+/// Adds the traceparent header to `request`: `explicit`, if set, or else that of the current
+/// span; none without either
+pub(crate) fn inject(request: RequestBuilder, explicit: Option<&str>) -> RequestBuilder {
+    let traceparent = explicit.map(str::to_string);
+    #[cfg(feature = "otel")]
+    let traceparent = traceparent.or_else(current);
+    match traceparent {
+        Some(traceparent) => request.header(TRACEPARENT, traceparent),
+        None => request,
+    }
+}
@@ -0,0 +1,260 @@
+//! HTTP versions of the connections to the API.
+//!
+//! Requests go over HTTP/2 or HTTP/1.1 by default. With
+//! [`GeminiBuilder::prefer_http3()`](crate::GeminiBuilder::prefer_http3), requests to `https`
+//! base URLs are sent over HTTP/3 (QUIC) first. When a request over QUIC fails, e.g. because a
+//! firewall drops UDP, it is sent again over TCP and the client keeps using TCP from then on; [`Gemini::http3_status()`](crate::Gemini::http3_status) reports
+//! which way it went.
+//!
+//! HTTP/3 needs the `http3` feature, which enables reqwest's unstable HTTP/3 support and so
+//! also requires building with `RUSTFLAGS="--cfg reqwest_unstable"`. Without the feature,
+//! preferring HTTP/3 sends every request over TCP. Requests whose body is streamed, such as
+//! file uploads, always go over TCP since they cannot be sent again.
+//!
+//! The HTTP version a response was received over is recorded in
+//! [`GenerationResponse::meta`](crate::GenerationResponse::meta) and in the `http.version`
+//! field of the request's tracing span, along with the sizes of the request and response
+//! bodies.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+};
+
+pub use reqwest::Version as HttpVersion;
+use reqwest::{Client, ClientBuilder, Request, Response};
+
+use crate::{
+    metrics::{MetricsSink, RESPONSE_BODY_BYTES},
+    SafetyFlag, UserData,
+};
+
+/// Details the client recorded about the response a value was decoded from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseMeta {
+    /// The HTTP version of the response, e.g. [`HttpVersion::HTTP_2`]
+    pub http_version: Option<HttpVersion>,
+    /// Whether HTTP/3 was preferred but the request was sent over TCP because QUIC failed
+    pub http3_fallback: bool,
+    /// Safety ratings at or above the threshold set with
+    /// [`ContentBuilder::flag_safety_above()`](crate::ContentBuilder::flag_safety_above), see
+    /// [`safety::threshold`](crate::safety::threshold)
+    pub flags: Vec<SafetyFlag>,
+    /// Size in bytes of the serialized request body, with inline media base64-encoded; `None`
+    /// for requests without a body or with a streamed one
+    pub request_bytes: Option<u64>,
+    /// Size in bytes of the response body, as received: the client does not ask for
+    /// compressed responses.
+    ///
+    /// For a streamed response, the bytes received up to the chunk carrying this value.
+    /// `None` if the body was not read.
+    pub response_bytes: Option<u64>,
+    /// Characters removed or replaced by the policy set with
+    /// [`GeminiBuilder::sanitize_output()`](crate::GeminiBuilder::sanitize_output), with how
+    /// often each occurred; for a streamed response, those of this chunk
+    pub sanitized: BTreeMap<char, usize>,
+    /// The values attached to the request with
+    /// [`ContentBuilder::with_user_data()`](crate::ContentBuilder::with_user_data)
+    pub user_data: UserData,
+}
+
+impl ResponseMeta {
+    /// The value of type `T` attached to the request, if any, see [`user_data`](crate::user_data)
+    pub fn user_data<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.user_data.get()
+    }
+}
+
+/// Counts the bytes of a response body as it is read, reporting the total to the metrics
+/// sink once the body has been read in full.
+///
+/// Attached to responses as an extension, so that every body reader can find it.
+#[derive(Clone, Default)]
+pub(crate) struct BodyMeter {
+    received: Arc<Mutex<Option<u64>>>,
+    metrics: Option<Arc<dyn MetricsSink>>,
+}
+
+impl BodyMeter {
+    pub(crate) fn new(metrics: Option<Arc<dyn MetricsSink>>) -> Self {
+        Self {
+            received: Arc::default(),
+            metrics,
+        }
+    }
+
+    /// The meter attached to `response`, or a detached one
+    pub(crate) fn of(response: &Response) -> Self {
+        response.extensions().get().cloned().unwrap_or_default()
+    }
+
+    /// Counts `len` more bytes of the body
+    pub(crate) fn add(&self, len: usize) {
+        let mut received = self.received.lock().expect("body meter lock poisoned");
+        *received = Some(received.unwrap_or(0) + len as u64);
+    }
+
+    /// Marks the body as read in full
+    pub(crate) fn finish(&self) {
+        let received = *self
+            .received
+            .lock()
+            .expect("body meter lock poisoned")
+            .get_or_insert(0);
+        if let Some(metrics) = &self.metrics {
+            metrics.record_histogram(RESPONSE_BODY_BYTES, received as f64);
+        }
+    }
+
+    /// The bytes of the body read so far, `None` if reading has not started
+    pub(crate) fn bytes(&self) -> Option<u64> {
+        *self.received.lock().expect("body meter lock poisoned")
+    }
+}
+
+impl std::fmt::Debug for BodyMeter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BodyMeter")
+            .field("received", &self.bytes())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Whether requests are sent over HTTP/3, see the [module documentation](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Http3Status {
+    /// HTTP/3 is not preferred
+    Off,
+    /// HTTP/3 is preferred, but the crate was built without the `http3` feature
+    Unsupported,
+    /// HTTP/3 is preferred and no request was sent over it yet
+    Untried,
+    /// A response was received over HTTP/3
+    Active,
+    /// Sending a request over HTTP/3 failed; requests are sent over TCP
+    FellBack,
+}
+
+impl Http3Status {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Http3Status::Off,
+            1 => Http3Status::Unsupported,
+            2 => Http3Status::Untried,
+            3 => Http3Status::Active,
+            _ => Http3Status::FellBack,
+        }
+    }
+}
+
+/// The HTTP/3 status of a client, shared by its clones
+#[derive(Debug)]
+pub(crate) struct Http3State {
+    status: AtomicU8,
+    /// Sends requests over HTTP/3 only; reqwest offers HTTP/3 on a client only when it is
+    /// the sole version the client speaks
+    #[cfg(feature = "http3")]
+    client: Option<Client>,
+}
+
+impl Http3State {
+    /// The state of a client not preferring HTTP/3
+    pub(crate) fn off() -> Self {
+        Self {
+            status: AtomicU8::new(Http3Status::Off as u8),
+            #[cfg(feature = "http3")]
+            client: None,
+        }
+    }
+
+    /// The state of a client preferring HTTP/3, sending HTTP/3 requests with a client built
+    /// by `builder`.
+    ///
+    /// If that client cannot be built, e.g. because no UDP socket can be bound, requests are
+    /// sent over TCP.
+    pub(crate) fn preferred(builder: ClientBuilder) -> Self {
+        #[cfg(feature = "http3")]
+        {
+            let client = builder
+                .http3_prior_knowledge()
+                .build()
+                .inspect_err(|error| tracing::debug!(%error, "HTTP/3 unavailable, using TCP"))
+                .ok();
+            let status = match client {
+                Some(_) => Http3Status::Untried,
+                None => Http3Status::FellBack,
+            };
+            Self {
+                status: AtomicU8::new(status as u8),
+                client,
+            }
+        }
+        #[cfg(not(feature = "http3"))]
+        {
+            drop(builder);
+            Self {
+                status: AtomicU8::new(Http3Status::Unsupported as u8),
+            }
+        }
+    }
+
+    pub(crate) fn status(&self) -> Http3Status {
+        Http3Status::from_u8(self.status.load(Ordering::Relaxed))
+    }
+
+    #[cfg(feature = "http3")]
+    fn set(&self, status: Http3Status) {
+        self.status.store(status as u8, Ordering::Relaxed);
+    }
+
+    /// Sends `request`, over HTTP/3 if preferred and not known to fail, falling back to
+    /// `client` over TCP
+    pub(crate) async fn execute(
+        &self,
+        client: &Client,
+        request: Request,
+    ) -> Result<(Response, ResponseMeta), reqwest::Error> {
+        #[cfg(feature = "http3")]
+        if let Some(h3_client) = self.client.as_ref().filter(|_| {
+            matches!(self.status(), Http3Status::Untried | Http3Status::Active)
+                && request.url().scheme() == "https"
+        }) {
+            // Requests with streamed bodies cannot be sent again over TCP
+            if let Some(fallback) = request.try_clone() {
+                let mut request = request;
+                *request.version_mut() = HttpVersion::HTTP_3;
+                match h3_client.execute(request).await {
+                    Ok(response) => {
+                        self.set(Http3Status::Active);
+                        return Ok(Self::with_meta(response, false));
+                    }
+                    Err(error) => {
+                        tracing::debug!(%error, "HTTP/3 request failed, falling back to TCP");
+                        self.set(Http3Status::FellBack);
+                        let response = client.execute(fallback).await?;
+                        return Ok(Self::with_meta(response, true));
+                    }
+                }
+            }
+        }
+        let fallback = self.status() == Http3Status::FellBack;
+        let response = client.execute(request).await?;
+        Ok(Self::with_meta(response, fallback))
+    }
+
+    fn with_meta(response: Response, http3_fallback: bool) -> (Response, ResponseMeta) {
+        let meta = ResponseMeta {
+            http_version: Some(response.version()),
+            http3_fallback,
+            flags: Vec::new(),
+            request_bytes: None,
+            response_bytes: None,
+            sanitized: BTreeMap::new(),
+            user_data: UserData::default(),
+        };
+        (response, meta)
+    }
+}
@@ -0,0 +1,112 @@
+//! Typed user data carried from a request to its outcome.
+//!
+//! A pipeline sending many requests at once has to match each outcome with the item it was
+//! sent for. [`ContentBuilder::with_user_data()`](crate::ContentBuilder::with_user_data)
+//! attaches any value to a request; it is never sent to the API. It is kept across retries,
+//! fallbacks, continuations, and tool loop steps, and is returned with the outcome:
+//!
+//! - on a response, in [`ResponseMeta::user_data`](crate::ResponseMeta::user_data), and on
+//!   every chunk of a stream;
+//! - on a failure, by [`ClientError::user_data()`](crate::ClientError::user_data), the
+//!   error being wrapped in [`ClientError::WithUserData`](crate::ClientError::WithUserData).
+//!
+//! ```no_run
+//! # async fn run(client: gemini_rust::Gemini, rows: Vec<(u64, String)>) {
+//! #[derive(Debug)]
+//! struct RowId(u64);
+//!
+//! let requests = rows.into_iter().map(|(id, text)| {
+//!     client
+//!         .generate_content()
+//!         .with_user_message(text)
+//!         .with_user_data(RowId(id))
+//!         .execute()
+//! });
+//! for result in futures::future::join_all(requests).await {
+//!     match result {
+//!         Ok(response) => println!("{:?} answered", response.meta.user_data::<RowId>()),
+//!         Err(error) => println!("{:?} failed: {error}", error.user_data::<RowId>()),
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! Values are stored by type, so several of them, e.g. a row ID and a tracing context, can be
+//! attached to the same request; attaching a value of a type already attached replaces it.
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+};
+
+/// Values attached to a request, one per type, see the [module documentation](self)
+#[derive(Clone, Default)]
+pub struct UserData {
+    entries: HashMap<TypeId, Entry>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    type_name: &'static str,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+impl UserData {
+    /// Attaches `value`, replacing the value of its type, if any
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        let entry = Entry {
+            type_name: std::any::type_name::<T>(),
+            value: Arc::new(value),
+        };
+        self.entries.insert(TypeId::of::<T>(), entry);
+    }
+
+    /// The value of type `T`, if one is attached
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.entries
+            .get(&TypeId::of::<T>())
+            .and_then(|entry| entry.value.downcast_ref())
+    }
+
+    /// Whether a value of type `T` is attached
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.entries.contains_key(&TypeId::of::<T>())
+    }
+
+    /// The number of values attached
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no value is attached
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Lists the types of the values, which need not implement `Debug`
+impl fmt::Debug for UserData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut types: Vec<_> = self.entries.values().map(|entry| entry.type_name).collect();
+        types.sort_unstable();
+        f.debug_set().entries(types).finish()
+    }
+}
+
+/// User data are equal if they hold the very same values, i.e. were attached to the same
+/// request
+impl PartialEq for UserData {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries.len() == other.entries.len()
+            && self.entries.iter().all(|(type_id, entry)| {
+                other
+                    .entries
+                    .get(type_id)
+                    .is_some_and(|other| Arc::ptr_eq(&entry.value, &other.value))
+            })
+    }
+}
+
+impl Eq for UserData {}
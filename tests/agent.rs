@@ -0,0 +1,60 @@
+//! Offline run of the research agent example over the cassette in `tests/cassettes/`.
+//!
+//! The agent combines a chat session, registry tools, a streamed reply, and a structured
+//! answer; replaying it verifies that the pieces still work together, and that every request
+//! carries what the recorded responses were given, e.g. the results of earlier tool calls.
+
+#[path = "../examples/agent.rs"]
+#[allow(dead_code)]
+mod agent;
+
+use gemini_rust::{Gemini, GeminiBuilder};
+use std::path::PathBuf;
+
+async fn replay_client() -> Gemini {
+    let cassette = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/cassettes/agent.json");
+    let url = agent::replay(&cassette).await.unwrap();
+    GeminiBuilder::new("replay")
+        .with_base_url(url)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn agent_replays_offline() {
+    let client = replay_client().await;
+    let mut out = Vec::new();
+    let report = agent::run(
+        &client,
+        "What is the population density of Lisbon?",
+        &mut out,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(report.question, "What is the population density of Lisbon?");
+    assert_eq!(report.sources.len(), 2);
+    assert!(report.answer.contains("5,455"));
+
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("tool calculate("), "{out}");
+    assert!(out.contains("\"result\":5455.2323838080965"), "{out}");
+    let thinking = out.find("── thinking ──").expect("thoughts are shown");
+    let answer = out.find("── answer ──").expect("the answer is shown");
+    assert!(thinking < answer);
+    assert!(out[answer..].contains("Growth plans should weigh"));
+    assert!(!out[answer..].contains("what it rests on"));
+}
+
+#[tokio::test]
+async fn agent_fails_on_cassette_mismatch() {
+    let client = replay_client().await;
+    let error = agent::run(
+        &client,
+        "What is the population density of Porto?",
+        &mut Vec::new(),
+    )
+    .await
+    .unwrap_err();
+    assert!(error.to_string().contains("cassette mismatch"), "{error}");
+}
@@ -0,0 +1,508 @@
+//! Compile-time checks that the public API keeps its shape.
+//!
+//! Nothing here is sent: the functions below only have to compile. Non-generic methods are
+//! coerced to function pointers of their exact signature, generic ones are called with the
+//! argument types users pass, enums are matched without a wildcard arm, and plain structs are
+//! destructured without `..`, except where the type is `#[non_exhaustive]`. Renaming a
+//! method, changing a parameter or return type, removing or adding an enum variant, or adding
+//! a public field then fails to build this test rather than a user's code.
+//!
+//! `tests/public_api.rs` complements these checks with a listing of the whole surface.
+
+#![allow(dead_code, unused_variables, clippy::let_underscore_future)]
+
+use gemini_rust::{
+    toon, AudioError, Blob, BlockReason, CacheBuilder, CachedContentHandle, Caches, Candidate,
+    ChatSession, ClientError, CodeExecutionResult, Content, ContentBuilder, ControlAction,
+    CountTokensBuilder, DocumentError, FileData, FinishReason, FunctionCall, FunctionDeclaration,
+    FunctionResponse, Gemini, GeminiBuilder, GenerateContentRequest, GenerationConfig,
+    GenerationResponse, HarmCategory, HarmProbability, ImageError, LintKind, LintWarning,
+    MediaError, Message, Model, ModelInfo, Outcome, Part, Policy, PolicyError, RateLimit,
+    RetryBudget, RetryPolicy, Role, SanitizePolicy, SavedMedia, SessionExport, StreamEvent, Tool,
+    ToolRegistry, UsageMetadata, UserData, Video, VideoError, VideoMetadata,
+};
+use std::time::Duration;
+
+fn client() -> Gemini {
+    Gemini::new("test-key").unwrap()
+}
+
+fn client_construction() -> Result<(), ClientError> {
+    let _: Gemini = Gemini::new("key")?;
+    let _: Gemini = Gemini::pro(String::from("key"))?;
+    let _: Gemini = Gemini::with_model("key", Model::Gemini25FlashLite)?;
+    let _: Gemini = Gemini::with_model("key", "models/gemini-2.5-pro".to_string())?;
+    let _: fn() -> GeminiBuilder = Gemini::builder;
+    let _: fn(GeminiBuilder) -> Result<Gemini, ClientError> = GeminiBuilder::build;
+    let _: fn(GeminiBuilder, bool) -> GeminiBuilder = GeminiBuilder::prefer_http3;
+    let _: fn(GeminiBuilder, bool) -> GeminiBuilder = GeminiBuilder::with_preflight;
+    let _: fn(GeminiBuilder, usize) -> GeminiBuilder = GeminiBuilder::with_max_response_bytes;
+    let _: fn(GeminiBuilder, usize) -> GeminiBuilder = GeminiBuilder::with_max_inline_data_bytes;
+    let _: fn(GeminiBuilder, RetryPolicy) -> GeminiBuilder = GeminiBuilder::with_retry_policy;
+    let _: fn(GeminiBuilder, Policy) -> GeminiBuilder = GeminiBuilder::with_policy;
+    let _: fn(GeminiBuilder, SanitizePolicy) -> GeminiBuilder = GeminiBuilder::sanitize_output;
+    let _: fn(GeminiBuilder) -> GeminiBuilder = GeminiBuilder::no_auth;
+    let _: GeminiBuilder = GeminiBuilder::new("key").with_rate_limit(15, 250_000);
+    let _: GeminiBuilder = GeminiBuilder::new("key").with_rate_limit(15, None);
+    let _: Gemini = GeminiBuilder::new("key")
+        .with_model(Model::Gemini25Flash)
+        .with_policy(
+            Policy::new()
+                .with_rate_limit(RateLimit::new(60, Duration::from_secs(60)))
+                .with_retry(
+                    RetryPolicy::new(3)
+                        .with_max_backoff(Duration::from_secs(20))
+                        .with_jitter(0.5),
+                )
+                .with_fallback(Model::Gemini25FlashLite)
+                .with_budget(RetryBudget::new(8)),
+        )
+        .sanitize_output(
+            SanitizePolicy::new()
+                .replace_controls('\u{FFFD}')
+                .strip_bidi(true),
+        )
+        .build()?;
+    let _: GeminiBuilder = GeminiBuilder::vertex_express(String::from("key"));
+    let _: GeminiBuilder = GeminiBuilder::vertex("project", "us-central1", String::from("token"));
+    let _: Result<Gemini, ClientError> =
+        Gemini::with_vertex("project", "global", String::from("token"));
+    Ok(())
+}
+
+fn client_entry_points() {
+    let _: fn(&Gemini) -> ContentBuilder = Gemini::generate_content;
+    let _: fn(&Gemini) -> CountTokensBuilder = Gemini::count_tokens;
+    let _: fn(&Gemini) -> ChatSession = Gemini::start_chat;
+    let _: fn(&Gemini, SessionExport) -> ChatSession = Gemini::resume_chat;
+    let _: fn(&Gemini) -> Caches = Gemini::caches;
+    let _: fn(&Gemini) -> CacheBuilder = Gemini::create_cache;
+    let _: fn(&Gemini, &str) -> CachedContentHandle = Gemini::get_cached_content;
+    let _: fn(&Gemini) -> Vec<String> = Gemini::guarded_resources;
+    let _: fn(&Gemini, &GenerationResponse) -> Option<gemini_rust::CostEstimate> =
+        Gemini::estimate_cost;
+}
+
+fn content_builder(path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let _: fn(ContentBuilder) -> GenerateContentRequest = ContentBuilder::build;
+    let _: fn(ContentBuilder, Message) -> ContentBuilder = ContentBuilder::with_message;
+    let _: fn(ContentBuilder, GenerationConfig) -> ContentBuilder =
+        ContentBuilder::with_generation_config;
+    let _: fn(ContentBuilder, f32) -> ContentBuilder = ContentBuilder::with_temperature;
+    let _: fn(ContentBuilder, f32) -> ContentBuilder = ContentBuilder::with_top_p;
+    let _: fn(ContentBuilder, i32) -> ContentBuilder = ContentBuilder::with_top_k;
+    let _: fn(ContentBuilder, i32) -> ContentBuilder = ContentBuilder::with_max_output_tokens;
+    let _: fn(ContentBuilder, i32) -> ContentBuilder = ContentBuilder::with_candidate_count;
+    let _: fn(ContentBuilder, i32) -> ContentBuilder = ContentBuilder::with_seed;
+    let _: fn(ContentBuilder, Vec<String>) -> ContentBuilder = ContentBuilder::with_stop_sequences;
+    let _: fn(ContentBuilder, Tool) -> ContentBuilder = ContentBuilder::with_tool;
+    let _: fn(ContentBuilder, FunctionDeclaration) -> ContentBuilder =
+        ContentBuilder::with_function;
+    let _: fn(ContentBuilder, &CachedContentHandle) -> ContentBuilder =
+        ContentBuilder::with_cached_content;
+    let _: fn(ContentBuilder, RetryBudget) -> ContentBuilder = ContentBuilder::with_retry_budget;
+    let _: fn(ContentBuilder, HarmProbability) -> ContentBuilder =
+        ContentBuilder::flag_safety_above;
+    let _: fn(ContentBuilder, HarmProbability) -> ContentBuilder =
+        ContentBuilder::reject_safety_above;
+    let _: fn(ContentBuilder, FinishReason) -> ContentBuilder = ContentBuilder::abort_stream_on;
+    let _: fn(ContentBuilder, u32) -> ContentBuilder = ContentBuilder::auto_continue;
+    let _: fn(ContentBuilder) -> ContentBuilder = ContentBuilder::without_rate_limit_wait;
+    let _: fn(ContentBuilder, &str) -> ContentBuilder = ContentBuilder::with_traceparent;
+    let _: fn(&ContentBuilder) -> usize = ContentBuilder::estimated_size;
+    let _: fn(&ContentBuilder) -> Vec<LintWarning> = ContentBuilder::lint;
+    let _: fn(ContentBuilder, &[LintKind]) -> ContentBuilder = ContentBuilder::deny_lints;
+    let _: fn(&ContentBuilder) -> String = ContentBuilder::debug_outline;
+    let _: fn(ContentBuilder) -> ContentBuilder = ContentBuilder::using_toon;
+    let _: ContentBuilder = client()
+        .generate_content()
+        .using_toon_with_spec("Reply in TOON");
+    let _: fn(ContentBuilder, toon::ToonOptions) -> ContentBuilder =
+        ContentBuilder::with_toon_options;
+    let _: fn(toon::ToonOptions, toon::Delimiter) -> toon::ToonOptions =
+        toon::ToonOptions::with_delimiter;
+    let _: fn(toon::ToonOptions, usize) -> toon::ToonOptions = toon::ToonOptions::with_indent;
+    let _: fn(toon::ToonOptions, bool) -> toon::ToonOptions = toon::ToonOptions::with_tables;
+    let _: fn(&String, &toon::ToonOptions) -> Result<String, toon::Error> =
+        toon::to_string_with::<String>;
+    let _: fn(&str, &toon::ToonOptions) -> Result<String, toon::Error> =
+        toon::from_str_with::<String>;
+    match toon::Delimiter::Comma {
+        toon::Delimiter::Comma | toon::Delimiter::Tab | toon::Delimiter::Pipe => {}
+    }
+
+    let builder = client()
+        .generate_content()
+        .with_system_instruction("Be brief")
+        .with_user_message("Hello")
+        .with_user_message(String::from("Hello"))
+        .with_model_message("Hi")
+        .with_inline_data("aGk=", "text/plain")
+        .with_file_uri("files/abc", "application/pdf")
+        .with_response_mime_type("application/json")
+        .with_response_schema(serde_json::json!({"type": "string"}))
+        .with_label("team", "search")
+        .with_tools([Tool::google_search()])
+        .with_function_response_str("lookup", "{}")?
+        .with_function_response("lookup", serde_json::json!({"ok": true}))?;
+    let _: Result<ContentBuilder, ImageError> = builder.clone().with_image_from_path(path);
+    let _: Result<ContentBuilder, ImageError> =
+        builder.clone().with_image_bytes([0u8; 4], "image/png");
+    let _: Result<ContentBuilder, AudioError> = builder.clone().with_audio_from_path(path);
+    let _: Result<ContentBuilder, AudioError> =
+        builder.clone().with_audio_bytes(vec![0u8; 4], "audio/wav");
+    let _: Result<ContentBuilder, DocumentError> = builder.clone().with_document_from_path(path);
+    let _: Result<ContentBuilder, DocumentError> =
+        builder.clone().with_document_bytes(b"a,b", "text/csv");
+    let _: Result<ContentBuilder, VideoError> = builder.clone().with_video(
+        Video::new("files/abc", "video/mp4")
+            .with_start_offset("10s")
+            .with_end_offset("20s")
+            .with_clip(Duration::from_secs(10), Duration::from_secs(20))
+            .with_fps(2.0),
+    );
+    let _: Result<ContentBuilder, VideoError> =
+        builder.with_video(Video::youtube("https://www.youtube.com/watch?v=abc"));
+    Ok(())
+}
+
+/// Futures are only created, never awaited
+fn execution(builder: ContentBuilder, registry: &ToolRegistry) {
+    let _ = builder.clone().execute();
+    let _ = builder.clone().execute_stream();
+    let _ = builder.clone().execute_stream_events();
+    let _ = builder.clone().execute_as::<serde_json::Value>();
+    #[cfg(feature = "toon_wip")]
+    let _ = builder.clone().execute_toon::<serde_json::Value>();
+    let _ = builder.clone().count_tokens();
+    let _ = builder.execute_with_tools(registry);
+}
+
+fn sessions(session: ChatSession, registry: &ToolRegistry) {
+    let _: fn(&ChatSession) -> &[Content] = ChatSession::history;
+    let _: fn(&ChatSession) -> SessionExport = ChatSession::export;
+    let _: fn(&ChatSession) -> ChatSession = ChatSession::fork;
+    let _: fn(&mut ChatSession, usize) = ChatSession::truncate;
+    let _: fn(&mut ChatSession) = ChatSession::clear;
+    let _: fn(ChatSession, GenerationConfig) -> ChatSession = ChatSession::with_generation_config;
+    let session = session
+        .with_system_instruction("Be brief")
+        .with_summary_model(Model::Gemini25FlashLite)
+        .with_history([Content::text("Hello").with_role(Role::User)]);
+    let _ = session.generate_title(40);
+    let _ = session.generate_summary(2);
+}
+
+fn send(session: &mut ChatSession, registry: &ToolRegistry) {
+    let _ = session.send_message("Hello");
+    let _ = session.send_message(String::from("Hello"));
+}
+
+fn tools() -> ToolRegistry {
+    let _: fn() -> ToolRegistry = ToolRegistry::new;
+    let _: fn(&ToolRegistry) -> Vec<FunctionDeclaration> = ToolRegistry::declarations;
+    let _: fn(&ToolRegistry) -> Option<Tool> = ToolRegistry::tool;
+    let _: fn(ToolRegistry, usize) -> ToolRegistry = ToolRegistry::with_max_steps;
+    let _: fn(ToolRegistry, usize) -> ToolRegistry = ToolRegistry::with_malformed_call_recovery;
+    let _: fn(ToolRegistry) -> ToolRegistry = ToolRegistry::with_code_execution_retry;
+    ToolRegistry::new().with_function(
+        FunctionDeclaration::new("lookup", "Looks something up", None),
+        |call: FunctionCall| async move { Ok(call.args) },
+    )
+}
+
+fn parts(part: &Part) -> &'static str {
+    match part {
+        Part::Text {
+            text,
+            thought,
+            thought_signature,
+        } => "text",
+        Part::InlineData { inline_data } => {
+            let Blob { mime_type, data } = inline_data;
+            "inline data"
+        }
+        Part::FileData {
+            file_data,
+            video_metadata,
+        } => {
+            let FileData {
+                mime_type,
+                file_uri,
+            } = file_data;
+            if let Some(VideoMetadata {
+                start_offset,
+                end_offset,
+                fps,
+            }) = video_metadata
+            {}
+            "file data"
+        }
+        Part::FunctionCall {
+            function_call,
+            thought_signature,
+        } => "function call",
+        Part::FunctionResponse { function_response } => {
+            let _: &FunctionResponse = function_response;
+            "function response"
+        }
+        Part::ExecutableCode { executable_code } => "executable code",
+        Part::CodeExecutionResult {
+            code_execution_result,
+        } => "code execution result",
+    }
+}
+
+fn content(content: Content) {
+    let Content { parts, role } = content;
+    match role {
+        Some(Role::User) | Some(Role::Model) | None => {}
+    }
+}
+
+fn finish_reasons(reason: FinishReason) {
+    match reason {
+        FinishReason::FinishReasonUnspecified
+        | FinishReason::Stop
+        | FinishReason::MaxTokens
+        | FinishReason::Safety
+        | FinishReason::Recitation
+        | FinishReason::Language
+        | FinishReason::Other
+        | FinishReason::Blocklist
+        | FinishReason::ProhibitedContent
+        | FinishReason::Spii
+        | FinishReason::MalformedFunctionCall
+        | FinishReason::ImageSafety
+        | FinishReason::UnexpectedToolCall
+        | FinishReason::TooManyToolCalls
+        | FinishReason::ClientStop => {}
+    }
+}
+
+fn block_reasons(reason: BlockReason) {
+    match reason {
+        BlockReason::BlockReasonUnspecified
+        | BlockReason::Safety
+        | BlockReason::Other
+        | BlockReason::Blocklist
+        | BlockReason::ProhibitedContent
+        | BlockReason::ImageSafety => {}
+        BlockReason::Unknown(reason) => {}
+        // `#[non_exhaustive]`: the listing in `tests/public_api.rs` guards its variants
+        _ => {}
+    }
+}
+
+fn safety(category: HarmCategory, probability: HarmProbability) {
+    match category {
+        HarmCategory::Unspecified
+        | HarmCategory::Derogatory
+        | HarmCategory::Toxicity
+        | HarmCategory::Violence
+        | HarmCategory::Sexual
+        | HarmCategory::Medical
+        | HarmCategory::Dangerous
+        | HarmCategory::Harassment
+        | HarmCategory::HateSpeech
+        | HarmCategory::SexuallyExplicit
+        | HarmCategory::DangerousContent => {}
+    }
+    match probability {
+        HarmProbability::HarmProbabilityUnspecified
+        | HarmProbability::Negligible
+        | HarmProbability::Low
+        | HarmProbability::Medium
+        | HarmProbability::High => {}
+    }
+}
+
+fn stream_events(event: StreamEvent) {
+    match event {
+        StreamEvent::Chunk(chunk) => {
+            let _: Box<GenerationResponse> = chunk;
+        }
+        StreamEvent::Finished(reason) => {}
+    }
+}
+
+fn tool_kinds(tool: Tool) {
+    match tool {
+        Tool::Function {
+            function_declarations,
+        } => {}
+        Tool::GoogleSearch { google_search } => {}
+        Tool::GoogleMaps { google_maps } => {}
+        Tool::URLContext { url_context } => {}
+        Tool::CodeExecution { code_execution } => {}
+    }
+    let _: fn() -> Tool = Tool::code_execution;
+}
+
+fn code_execution(candidate: &Candidate, result: CodeExecutionResult) {
+    let _: Option<&CodeExecutionResult> = candidate.failed_code_execution();
+    let CodeExecutionResult { outcome, output } = result.clone();
+    let _: Outcome = outcome;
+    let _: fn(&Outcome) -> bool = Outcome::is_success;
+    let _: fn(&CodeExecutionResult) -> bool = CodeExecutionResult::is_success;
+    let _: fn(&CodeExecutionResult) -> &str = CodeExecutionResult::stdout;
+    let _: fn(&CodeExecutionResult) -> Option<&str> = CodeExecutionResult::error_output;
+}
+
+fn user_data(builder: ContentBuilder, response: &GenerationResponse, error: &ClientError) {
+    let _: ContentBuilder = builder.with_user_data(7u64).with_user_data("row");
+    let _: Option<&u64> = response.meta.user_data::<u64>();
+    let _: &UserData = &response.meta.user_data;
+    let _: Option<&u64> = error.user_data::<u64>();
+    let _: fn(&ClientError) -> &ClientError = ClientError::inner;
+    let _: fn(&ClientError) -> Option<Duration> = ClientError::retry_delay;
+}
+
+async fn models(client: &Gemini) -> Result<(), ClientError> {
+    use futures::TryStreamExt;
+
+    let models: Vec<ModelInfo> = client.list_models(None).try_collect().await?;
+    let info: ModelInfo = client.get_model(Model::Gemini25Pro).await?;
+    let _ = (
+        &info.name,
+        &info.display_name,
+        info.input_token_limit,
+        info.output_token_limit,
+        &info.supported_generation_methods,
+        info.temperature,
+        info.top_p,
+        info.top_k,
+    );
+    Ok(())
+}
+
+async fn save_media(response: &GenerationResponse, part: &Part) -> Result<(), MediaError> {
+    let saved: Vec<SavedMedia> = response.save_all_media("out").await?;
+    let _: (&std::path::Path, &str, usize) = (&saved[0].path, &saved[0].mime_type, saved[0].size);
+    let _: std::path::PathBuf = part.save_to_dir("out").await?;
+    Ok(())
+}
+
+fn usage(usage: &UsageMetadata) -> Option<i32> {
+    usage.cached_content_token_count
+}
+
+fn client_errors(error: &ClientError) -> &'static str {
+    match error {
+        ClientError::BadPart { .. } => "bad part",
+        ClientError::BadResponse { .. } => "bad response",
+        ClientError::BearerToken { .. } => "bearer token",
+        ClientError::CachedContentConflict { .. } => "cached content conflict",
+        ClientError::Connect { .. } => "connect",
+        ClientError::ConstructUrl { .. } => "construct url",
+        ClientError::ContextWindowExceeded { .. } => "context window exceeded",
+        ClientError::DecodeAudio { .. } => "decode audio",
+        ClientError::DecodeResponse { .. } => "decode response",
+        ClientError::Deserialize { .. } => "deserialize",
+        ClientError::EmbeddingCountMismatch { .. } => "embedding count mismatch",
+        ClientError::EmptyConversation => "empty conversation",
+        ClientError::InlineDataTooLarge { .. } => "inline data too large",
+        ClientError::InvalidApiKey { .. } => "invalid api key",
+        ClientError::InvalidChunking { .. } => "invalid chunking",
+        ClientError::InvalidFields { .. } => "invalid fields",
+        ClientError::InvalidGenerationConfig { .. } => "invalid generation config",
+        ClientError::InvalidPolicy { .. } => "invalid policy",
+        ClientError::InvalidVertexProject { .. } => "invalid vertex project",
+        ClientError::Io { .. } => "io",
+        ClientError::LintDenied { .. } => "lint denied",
+        ClientError::MalformedFunctionCall { .. } => "malformed function call",
+        ClientError::MissingEmbedding => "missing embedding",
+        ClientError::MissingResponseHeader { .. } => "missing response header",
+        ClientError::NoAcceptableResponse { .. } => "no acceptable response",
+        ClientError::OutputBlocked { .. } => "output blocked",
+        ClientError::PartialStream { .. } => "partial stream",
+        ClientError::PerformRequest { .. } => "perform request",
+        ClientError::PerformRequestNew { .. } => "perform request",
+        ClientError::PromptBlocked { .. } => "prompt blocked",
+        ClientError::RateLimited { .. } => "rate limited",
+        ClientError::ResponseTooLarge { .. } => "response too large",
+        ClientError::RetriesExhausted { .. } => "retries exhausted",
+        ClientError::RetryBudgetExhausted { .. } => "retry budget exhausted",
+        ClientError::RowExceedsBudget { .. } => "row exceeds budget",
+        ClientError::SafetyThresholdExceeded { .. } => "safety threshold exceeded",
+        ClientError::Spill { .. } => "spill",
+        ClientError::StructuredOutput { .. } => "structured output",
+        ClientError::ToolLoopExhausted { .. } => "tool loop exhausted",
+        ClientError::ToonInput { .. } => "toon input",
+        #[cfg(feature = "toon_wip")]
+        ClientError::ToonOutput { .. } => "toon output",
+        ClientError::UnauthenticatedDefaultEndpoint { .. } => "unauthenticated default endpoint",
+        ClientError::UnexpectedResponse { .. } => "unexpected response",
+        ClientError::UnsupportedBundleVersion { .. } => "unsupported bundle version",
+        ClientError::UnsupportedModalities { .. } => "unsupported modalities",
+        ClientError::UnsupportedOnBackend { .. } => "unsupported on backend",
+        ClientError::UrlParse { .. } => "url parse",
+        ClientError::WithUserData { .. } => "with user data",
+    }
+}
+
+fn input_errors(
+    image: ImageError,
+    audio: AudioError,
+    document: DocumentError,
+    video: VideoError,
+    policy: PolicyError,
+) {
+    match audio {
+        AudioError::ReadAudio { path, source } => {}
+        AudioError::UnsupportedAudioType { audio_type } => {}
+        AudioError::AudioTooLarge { size, limit } => {}
+    }
+    match document {
+        DocumentError::ReadDocument { path, source } => {}
+        DocumentError::UnsupportedDocumentType { document_type } => {}
+        DocumentError::DocumentTooLarge { size, limit } => {}
+        DocumentError::NotAPdf => {}
+        DocumentError::TooManyPages { pages, limit } => {}
+    }
+    match video {
+        VideoError::InvalidOffset { offset } => {}
+        VideoError::EmptyClip { start, end } => {}
+        VideoError::InvalidFps { fps } => {}
+    }
+    match policy {
+        PolicyError::EmptyRateLimit
+        | PolicyError::ConflictingRateLimit
+        | PolicyError::NoRetryAttempts
+        | PolicyError::NoBudgetAttempts
+        | PolicyError::ConflictingRetryPolicy => {}
+        PolicyError::RepeatedFallback { model } => {}
+        PolicyError::UnreachableFallback { budget, fallbacks } => {}
+    }
+    let _: &dyn std::error::Error = &image;
+}
+
+fn policies(limit: RateLimit, action: ControlAction) {
+    let RateLimit {
+        requests,
+        per,
+        tokens,
+    } = limit;
+    match action {
+        ControlAction::Strip => {}
+        ControlAction::Replace(replacement) => {}
+    }
+}
+
+#[test]
+fn public_api_compiles() {
+    // The checks above are done by the compiler; this only exercises what runs offline
+    client_construction().unwrap();
+    assert_eq!(
+        parts(&Part::Text {
+            text: "hi".into(),
+            thought: None,
+            thought_signature: None,
+        }),
+        "text"
+    );
+    assert_eq!(
+        client_errors(&ClientError::EmptyConversation),
+        "empty conversation"
+    );
+}
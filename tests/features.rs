@@ -0,0 +1,272 @@
+//! Feature flags and their combinations.
+//!
+//! The minimal build, without default features, is the REST text path; every feature adds a
+//! module or methods on top of it. Each module below names the items its feature adds and is
+//! compiled only with that feature, so building this test with a set of features checks both
+//! that the library compiles with exactly that set and that the set exposes what it promises.
+//! The matrix is run with [cargo-hack](https://github.com/taiki-e/cargo-hack), with the
+//! dev-dependencies removed since they enable every feature:
+//!
+//! ```text
+//! cargo hack check --each-feature --no-dev-deps --lib
+//! cargo hack test --each-feature --remove-dev-deps --test features
+//! ```
+//!
+//! This test uses no dev-dependency for that reason.
+//!
+//! [`minimal_build_dependencies`] records how many crates the minimal build pulls in, so
+//! that a dependency added to it is a deliberate change.
+
+#![allow(dead_code, clippy::let_underscore_future)]
+
+use std::{collections::BTreeSet, process::Command};
+
+/// The number of crates the minimal build depends on, the library itself excluded
+const MINIMAL_DEPENDENCIES: usize = 128;
+
+#[test]
+fn minimal_build_dependencies() {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    let output = Command::new(cargo)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["tree", "--locked", "--package", "gemini-rust"])
+        .args([
+            "--edges",
+            "normal",
+            "--prefix",
+            "none",
+            "--no-default-features",
+        ])
+        .output()
+        .expect("cargo tree should run");
+    assert!(
+        output.status.success(),
+        "cargo tree failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let crates: BTreeSet<_> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| line.trim_end_matches(" (*)").to_string())
+        .filter(|line| !line.starts_with("gemini-rust "))
+        .collect();
+    assert_eq!(
+        crates.len(),
+        MINIMAL_DEPENDENCIES,
+        "the minimal build depends on {} crates, not {MINIMAL_DEPENDENCIES}; update the \
+         reference if the change is intended:\n{}",
+        crates.len(),
+        crates.into_iter().collect::<Vec<_>>().join("\n")
+    );
+}
+
+/// The REST text path, available in every build
+mod core {
+    use gemini_rust::{
+        ChatSession, ClientError, ContentBuilder, Gemini, GenerationResponse, Schema, ToolConfig,
+        ToolExchange,
+    };
+
+    fn surface(mut session: ChatSession) {
+        let _: fn(Gemini) -> ContentBuilder = |client| client.generate_content();
+        let _ = ContentBuilder::execute;
+        let _ = ContentBuilder::execute_as::<String>;
+        let _: fn(ContentBuilder, Schema) -> ContentBuilder = ContentBuilder::with_response_schema;
+        let _: fn(ContentBuilder, ToolConfig) -> ContentBuilder = ContentBuilder::with_tool_config;
+        let _ = session.send_message("hi");
+        let _: fn(&GenerationResponse) -> String = GenerationResponse::text;
+        let _: fn(&GenerationResponse) -> &[ToolExchange] = |r| &r.tool_transcript;
+        let _: fn(&ClientError) -> Option<&String> = ClientError::user_data::<String>;
+    }
+}
+
+#[cfg(feature = "tools")]
+mod tools {
+    use gemini_rust::{ChatSession, ClientError, ContentBuilder, RunLog, ToolRegistry};
+
+    fn surface(mut session: ChatSession, registry: ToolRegistry, error: ClientError) {
+        let _: fn() -> ToolRegistry = ToolRegistry::new;
+        let _ = ContentBuilder::execute_with_tools;
+        let _ = ContentBuilder::execute_with_tools_logged;
+        let _: fn(ToolRegistry) -> ToolRegistry = ToolRegistry::with_code_execution_retry;
+        let _ = session.send_message_with_tools("hi", &registry);
+        let _: Option<RunLog> = None;
+        if let ClientError::ToolLoopExhausted { .. } = error {}
+    }
+}
+
+#[cfg(feature = "schema")]
+mod schema {
+    use gemini_rust::{ContentBuilder, GeminiSchema, Schema, SchemaError, ToSchema};
+
+    /// A derived schema
+    #[derive(GeminiSchema)]
+    struct Derived {
+        name: String,
+    }
+
+    fn surface() {
+        let _: fn() -> Schema = Derived::schema;
+        let _: fn() -> Result<Schema, SchemaError> = Schema::of::<String>;
+        let _ = ContentBuilder::with_response_schema_of::<String>;
+    }
+}
+
+#[cfg(feature = "files")]
+mod files {
+    use gemini_rust::{files::builder::FileBuilder, ContentBuilder, FileHandle, Files, Gemini};
+
+    fn surface() {
+        let _: fn(&Gemini) -> Files = Gemini::files;
+        let _: fn(ContentBuilder, &FileHandle) -> ContentBuilder = ContentBuilder::with_file;
+        let _ = |client: &Gemini| client.list_files(10);
+        let _: fn(FileBuilder) -> FileBuilder = FileBuilder::with_retries;
+    }
+}
+
+#[cfg(feature = "caching")]
+mod caching {
+    use gemini_rust::{CacheBuilder, CachedContentHandle, Caches, ContentBuilder, Gemini};
+
+    fn surface() {
+        let _: fn(&Gemini) -> Caches = Gemini::caches;
+        let _: fn(&Gemini) -> CacheBuilder = Gemini::create_cache;
+        let _: fn(CacheBuilder) -> CacheBuilder = CacheBuilder::with_retries;
+        let _: fn(ContentBuilder, &CachedContentHandle) -> ContentBuilder =
+            ContentBuilder::with_cached_content;
+    }
+}
+
+#[cfg(all(feature = "files", feature = "caching"))]
+mod files_and_caching {
+    use gemini_rust::{CacheFromFileBuilder, Gemini, OrphanSweep};
+
+    fn surface() {
+        let _: fn(&Gemini, Vec<u8>, mime::Mime) -> CacheFromFileBuilder =
+            Gemini::create_cache_from_file::<Vec<u8>>;
+        let _: Option<OrphanSweep> = None;
+    }
+}
+
+#[cfg(feature = "embeddings")]
+mod embeddings {
+    use gemini_rust::{
+        ChunkingConfig, ChunkingError, ClientError, EmbedBuilder, Gemini, TaskType, TextChunker,
+    };
+
+    fn surface(error: ClientError) {
+        let _: fn(&Gemini) -> EmbedBuilder = Gemini::embed_content;
+        let _: fn(ChunkingConfig) -> Result<TextChunker, ChunkingError> = TextChunker::new;
+        let _ = EmbedBuilder::execute;
+        let _ = TaskType::RetrievalDocument;
+        if let ClientError::MissingEmbedding | ClientError::InvalidChunking { .. } = error {}
+    }
+}
+
+#[cfg(feature = "batch")]
+mod batch {
+    use gemini_rust::{BatchBuilder, BatchHandle, Gemini};
+
+    fn surface() {
+        let _: fn(&Gemini) -> BatchBuilder = Gemini::batch_generate_content;
+        let _: fn(&Gemini, &str) -> BatchHandle = Gemini::get_batch;
+        let _ = BatchBuilder::execute;
+        let _: fn(BatchBuilder) -> BatchBuilder = BatchBuilder::with_retries;
+    }
+}
+
+#[cfg(feature = "streaming")]
+mod streaming {
+    use gemini_rust::{
+        AggregatePolicy, ChatSession, ClientError, ContentBuilder, StopPattern, StreamEvent,
+    };
+
+    fn surface(mut session: ChatSession, error: ClientError) {
+        let _ = ContentBuilder::execute_stream;
+        let _ = ContentBuilder::execute_stream_events;
+        let _: fn(ContentBuilder, AggregatePolicy) -> _ = ContentBuilder::execute_stream_aggregated;
+        let _: fn(ContentBuilder, StopPattern) -> ContentBuilder = ContentBuilder::with_client_stop;
+        let _ = session.send_message_stream("hi");
+        let _: Option<StreamEvent> = None;
+        if let ClientError::PartialStream { .. } = error {}
+    }
+}
+
+#[cfg(feature = "toon")]
+mod toon {
+    use gemini_rust::{toon, ClientError, ContentBuilder, ToonError};
+
+    fn surface(error: ClientError) {
+        let _: fn(&str) -> &str = toon::extract;
+        let _: fn(&str) -> Result<String, toon::Error> = toon::to_json_string;
+        let _ = ContentBuilder::with_toon_table_message::<String>;
+        let _: fn(ContentBuilder) -> ContentBuilder = ContentBuilder::using_toon;
+        let _: &str = toon::SPEC;
+        let _: fn(ContentBuilder, toon::ToonOptions) -> ContentBuilder =
+            ContentBuilder::with_toon_options;
+        let _ = toon::to_string_with::<String>;
+        let _: fn(&str) -> Result<String, ToonError> = toon::to_json_string;
+        let _: fn(ContentBuilder, &String) -> Result<ContentBuilder, ClientError> =
+            ContentBuilder::with_toon_message::<String>;
+        let _ = toon::to_writer::<Vec<u8>, String>;
+        let _ = toon::from_reader::<&[u8], String>;
+        if let ToonError::Deserialize { line, column, .. } = toon::from_str::<u8>("").unwrap_err() {
+            let _: (usize, usize) = (line, column);
+        }
+        if let ClientError::ToonInput { .. } = error {}
+    }
+}
+
+#[cfg(feature = "toon_wip")]
+mod toon_wip {
+    use gemini_rust::{ClientError, ContentBuilder};
+
+    fn surface(error: ClientError) {
+        let _ = ContentBuilder::execute_toon::<String>;
+        if let ClientError::ToonOutput { .. } = error {}
+    }
+}
+
+#[cfg(feature = "live")]
+mod live {
+    use gemini_rust::live::{LiveServerMessage, LiveSetup};
+
+    fn surface() {
+        let _: Option<(LiveSetup, LiveServerMessage)> = None;
+        let _ = gemini_rust::live::spoken_turns::<futures::stream::Empty<_>, ()>;
+    }
+}
+
+#[cfg(feature = "image")]
+mod image {
+    use gemini_rust::{ContentBuilder, GenerationResponse, ImageError, MediaError, SavedMedia};
+
+    fn surface(response: GenerationResponse) {
+        let _: fn(ContentBuilder) -> Result<ContentBuilder, ImageError> =
+            |builder| builder.with_image_from_path("photo.png");
+        let _ = response.save_all_media("out");
+        let _: Option<(SavedMedia, MediaError)> = None;
+    }
+}
+
+#[cfg(feature = "proto")]
+mod proto {
+    use gemini_rust::{compat::proto, Content};
+
+    fn surface() {
+        let _: fn(proto::Content) -> Content = Content::from;
+        let _: fn(Content) -> Result<proto::Content, proto::Error> = proto::Content::try_from;
+        let _: fn(proto::Content) -> (Content, proto::Attachment) = proto::Content::split;
+        let _: fn(Content, proto::Attachment) -> Result<proto::Content, proto::Error> =
+            proto::Content::join;
+    }
+}
+
+#[cfg(feature = "testing")]
+mod testing {
+    use gemini_rust::clock::ManualClock;
+
+    fn surface() {
+        let _: fn() -> ManualClock = ManualClock::new;
+    }
+}
@@ -0,0 +1,200 @@
+//! Golden tests over the recorded response corpus in `tests/fixtures/`.
+//!
+//! Every `*.json` file under `tests/fixtures/<kind>/` is deserialized into the typed model for
+//! `<kind>`, re-serialized, and compared with the original body. Comparison is semantic:
+//! key order and number formatting do not matter, numbers only need to agree to `f32` precision
+//! (embeddings are single-precision), timestamps are compared as instants, and keys holding
+//! proto3 default values (`null`, `false`, `0`, `""`, `[]`, `{}`) may be omitted on either side,
+//! as the API does. Any other difference, in particular a field the model drops, fails the test.
+//!
+//! Fixtures are decoded with every [`JsonBackend`] the build supports, so running the tests
+//! with `--features simd-json` covers simd-json as well as serde_json.
+//!
+//! New fixture files are picked up automatically; new kinds must be registered in
+//! [`roundtrip()`]. Use `cargo run --example capture_fixture` to record new fixtures.
+
+use gemini_rust::{
+    BatchContentEmbeddingResponse, BatchOperation, CachedContent, ContentEmbeddingResponse, File,
+    GenerationResponse, JsonBackend,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// Deserializes `body` as `T` with `backend` and serializes it back
+fn typed_roundtrip<T: Serialize + DeserializeOwned>(
+    backend: JsonBackend,
+    body: &[u8],
+) -> Result<Value, String> {
+    let typed: T = backend.from_slice(body).map_err(|e| e.to_string())?;
+    serde_json::to_value(&typed).map_err(|e| e.to_string())
+}
+
+/// Round-trips a fixture through the typed model registered for its kind
+fn roundtrip(kind: &str, backend: JsonBackend, body: &[u8]) -> Result<Value, String> {
+    match kind {
+        "generate_content" | "stream_chunk" => typed_roundtrip::<GenerationResponse>(backend, body),
+        "embed_content" => typed_roundtrip::<ContentEmbeddingResponse>(backend, body),
+        "batch_embed_contents" => typed_roundtrip::<BatchContentEmbeddingResponse>(backend, body),
+        "file" => typed_roundtrip::<File>(backend, body),
+        "cached_content" => typed_roundtrip::<CachedContent>(backend, body),
+        "batch_operation" => typed_roundtrip::<BatchOperation>(backend, body),
+        _ => Err(format!(
+            "no typed model registered for fixture kind '{kind}'"
+        )),
+    }
+}
+
+/// Whether `value` is a proto3 default, which the API omits from responses
+fn is_default(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Bool(b) => !b,
+        Value::Number(n) => n.as_f64() == Some(0.0),
+        Value::String(s) => s.is_empty(),
+        Value::Array(array) => array.is_empty(),
+        Value::Object(object) => object.is_empty(),
+    }
+}
+
+fn same_instant(a: &str, b: &str) -> bool {
+    match (
+        OffsetDateTime::parse(a, &Rfc3339),
+        OffsetDateTime::parse(b, &Rfc3339),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Collects every semantic difference between the raw and the round-tripped value
+fn diff(raw: &Value, typed: &Value, path: &str, differences: &mut Vec<String>) {
+    match (raw, typed) {
+        (Value::Object(raw), Value::Object(typed)) => {
+            for (key, value) in raw {
+                let child = format!("{path}.{key}");
+                match typed.get(key) {
+                    Some(typed) => diff(value, typed, &child, differences),
+                    None if is_default(value) => {}
+                    None => differences.push(format!("{child}: dropped by the model")),
+                }
+            }
+            for (key, value) in typed {
+                if !raw.contains_key(key) && !is_default(value) {
+                    differences.push(format!("{path}.{key}: added by the model ({value})"));
+                }
+            }
+        }
+        (Value::Array(raw), Value::Array(typed)) => {
+            if raw.len() != typed.len() {
+                differences.push(format!(
+                    "{path}: {} element(s) became {}",
+                    raw.len(),
+                    typed.len()
+                ));
+            }
+            for (i, (raw, typed)) in raw.iter().zip(typed).enumerate() {
+                diff(raw, typed, &format!("{path}[{i}]"), differences);
+            }
+        }
+        (Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (
+                a.as_f64().unwrap_or(f64::NAN),
+                b.as_f64().unwrap_or(f64::NAN),
+            );
+            if (a - b).abs() > f32::EPSILON as f64 * a.abs().max(f64::MIN_POSITIVE) {
+                differences.push(format!("{path}: {a} became {b}"));
+            }
+        }
+        (Value::String(a), Value::String(b)) if a == b || same_instant(a, b) => {}
+        (a, b) if a == b => {}
+        (a, b) => differences.push(format!("{path}: {a} became {b}")),
+    }
+}
+
+/// Finds every fixture file, grouped by kind (the name of its directory)
+fn discover(root: &Path) -> Vec<(String, PathBuf)> {
+    let mut fixtures = Vec::new();
+    let mut kinds: Vec<PathBuf> = std::fs::read_dir(root)
+        .expect("fixture directory is readable")
+        .map(|entry| entry.expect("fixture directory entry").path())
+        .filter(|path| path.is_dir())
+        .collect();
+    kinds.sort();
+    for kind_dir in kinds {
+        let kind = kind_dir.file_name().unwrap().to_string_lossy().to_string();
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&kind_dir)
+            .expect("fixture kind directory is readable")
+            .map(|entry| entry.expect("fixture file entry").path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        files.sort();
+        fixtures.extend(files.into_iter().map(|file| (kind.clone(), file)));
+    }
+    fixtures
+}
+
+#[test]
+fn fixtures_roundtrip_through_typed_models() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let fixtures = discover(&root);
+    assert!(
+        fixtures.len() >= 20,
+        "expected at least 20 fixtures, found {}",
+        fixtures.len()
+    );
+
+    let mut failures = Vec::new();
+    for (kind, path) in &fixtures {
+        let name = path.strip_prefix(&root).unwrap().display().to_string();
+        let body = std::fs::read_to_string(path).expect("fixture is readable");
+        let raw: Value = match serde_json::from_str(&body) {
+            Ok(raw) => raw,
+            Err(e) => {
+                failures.push(format!("{name}: invalid JSON: {e}"));
+                continue;
+            }
+        };
+        for backend in JsonBackend::available() {
+            match roundtrip(kind, *backend, body.as_bytes()) {
+                Ok(typed) => {
+                    let mut differences = Vec::new();
+                    diff(&raw, &typed, "$", &mut differences);
+                    failures.extend(
+                        differences
+                            .into_iter()
+                            .map(|d| format!("{name} ({backend:?}): {d}")),
+                    );
+                }
+                Err(e) => failures.push(format!("{name} ({backend:?}): {e}")),
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} fixture difference(s):\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn diff_reports_dropped_and_changed_fields() {
+    let raw =
+        serde_json::json!({"a": 1, "b": {"c": "x", "d": null}, "t": "2025-01-01T00:00:00.500Z"});
+    let typed =
+        serde_json::json!({"a": 1.0, "b": {"c": "y"}, "t": "2025-01-01T00:00:00.5Z", "e": []});
+    let mut differences = Vec::new();
+    diff(&raw, &typed, "$", &mut differences);
+    assert_eq!(differences, vec![r#"$.b.c: "x" became "y""#.to_string()]);
+
+    let mut differences = Vec::new();
+    diff(
+        &serde_json::json!({"new": true}),
+        &serde_json::json!({}),
+        "$",
+        &mut differences,
+    );
+    assert_eq!(differences, vec!["$.new: dropped by the model".to_string()]);
+}
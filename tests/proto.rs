@@ -0,0 +1,313 @@
+//! Round trips of contents through the protobuf messages of the Generative Language API.
+//!
+//! Each fixture is a protobuf content holding one kind of part. It is encoded, decoded,
+//! converted to a [`Content`] that must equal the API's JSON form of the same content, and
+//! converted back, which must give the fixture and its bytes again.
+
+use gemini_rust::{
+    compat::proto::{self, code_execution_result, executable_code, part},
+    Content,
+};
+use prost::Message;
+use prost_types::{value::Kind, ListValue, Struct};
+use serde_json::{json, Value};
+
+fn round_trip(fixture: proto::Part, expected: Value) {
+    let fixture = proto::Content {
+        parts: vec![fixture],
+        role: "model".to_string(),
+        ..Default::default()
+    };
+    let bytes = fixture.encode_to_vec();
+    let content = Content::from(proto::Content::decode(bytes.as_slice()).unwrap());
+    assert_eq!(
+        serde_json::to_value(&content).unwrap(),
+        json!({ "parts": [expected], "role": "model" })
+    );
+
+    let converted = proto::Content::try_from(content).unwrap();
+    assert_eq!(converted, fixture);
+    assert_eq!(converted.encode_to_vec(), bytes);
+}
+
+fn data(data: part::Data) -> proto::Part {
+    proto::Part {
+        data: Some(data),
+        ..Default::default()
+    }
+}
+
+fn value(kind: Kind) -> prost_types::Value {
+    prost_types::Value { kind: Some(kind) }
+}
+
+#[test]
+fn text() {
+    round_trip(
+        data(part::Data::Text("Hello".into())),
+        json!({ "text": "Hello" }),
+    );
+}
+
+#[test]
+fn thought() {
+    let part = proto::Part {
+        thought: true,
+        thought_signature: b"signature".to_vec(),
+        ..data(part::Data::Text("Thinking".into()))
+    };
+    round_trip(
+        part,
+        json!({ "text": "Thinking", "thought": true, "thoughtSignature": "c2lnbmF0dXJl" }),
+    );
+}
+
+#[test]
+fn inline_data() {
+    let blob = proto::Blob {
+        mime_type: "image/png".into(),
+        data: vec![0x89, b'P', b'N', b'G'],
+    };
+    round_trip(
+        data(part::Data::InlineData(blob)),
+        json!({ "inlineData": { "mimeType": "image/png", "data": "iVBORw==" } }),
+    );
+}
+
+#[test]
+fn file_data() {
+    let file = proto::FileData {
+        mime_type: "video/mp4".into(),
+        file_uri: "https://generativelanguage.googleapis.com/v1beta/files/abc".into(),
+    };
+    let part = proto::Part {
+        metadata: Some(part::Metadata::VideoMetadata(proto::VideoMetadata {
+            start_offset: Some(prost_types::Duration {
+                seconds: 40,
+                nanos: 0,
+            }),
+            end_offset: Some(prost_types::Duration {
+                seconds: 80,
+                nanos: 500_000_000,
+            }),
+            fps: 2.0,
+        })),
+        ..data(part::Data::FileData(file))
+    };
+    round_trip(
+        part,
+        json!({
+            "fileData": {
+                "mimeType": "video/mp4",
+                "fileUri": "https://generativelanguage.googleapis.com/v1beta/files/abc"
+            },
+            "videoMetadata": { "startOffset": "40s", "endOffset": "80.5s", "fps": 2.0 }
+        }),
+    );
+}
+
+#[test]
+fn function_call() {
+    let args = Struct {
+        fields: [
+            (
+                "city".to_string(),
+                value(Kind::StringValue("Lisbon".into())),
+            ),
+            ("days".to_string(), value(Kind::NumberValue(3.0))),
+            ("budget".to_string(), value(Kind::NumberValue(1250.5))),
+            ("flexible".to_string(), value(Kind::BoolValue(true))),
+            ("notes".to_string(), value(Kind::NullValue(0))),
+            (
+                "stops".to_string(),
+                value(Kind::ListValue(ListValue {
+                    values: vec![
+                        value(Kind::StringValue("Belém".into())),
+                        value(Kind::StructValue(Struct {
+                            fields: [(
+                                "name".to_string(),
+                                value(Kind::StringValue("Alfama".into())),
+                            )]
+                            .into(),
+                        })),
+                    ],
+                })),
+            ),
+        ]
+        .into(),
+    };
+    let part = proto::Part {
+        thought_signature: b"signature".to_vec(),
+        ..data(part::Data::FunctionCall(proto::FunctionCall {
+            id: "call_1".into(),
+            name: "plan_trip".into(),
+            args: Some(args),
+        }))
+    };
+    round_trip(
+        part,
+        json!({
+            "functionCall": {
+                "name": "plan_trip",
+                "args": {
+                    "budget": 1250.5,
+                    "city": "Lisbon",
+                    "days": 3,
+                    "flexible": true,
+                    "notes": null,
+                    "stops": ["Belém", { "name": "Alfama" }]
+                },
+                "id": "call_1"
+            },
+            "thoughtSignature": "c2lnbmF0dXJl"
+        }),
+    );
+}
+
+#[test]
+fn function_response() {
+    let response = Struct {
+        fields: [("temperature".to_string(), value(Kind::NumberValue(21.0)))].into(),
+    };
+    round_trip(
+        data(part::Data::FunctionResponse(proto::FunctionResponse {
+            id: "call_1".into(),
+            name: "get_weather".into(),
+            response: Some(response),
+        })),
+        json!({
+            "functionResponse": {
+                "name": "get_weather",
+                "response": { "temperature": 21 },
+                "id": "call_1"
+            }
+        }),
+    );
+}
+
+#[test]
+fn executable_code() {
+    round_trip(
+        data(part::Data::ExecutableCode(proto::ExecutableCode {
+            language: executable_code::Language::Python as i32,
+            code: "print(1 + 1)".into(),
+        })),
+        json!({ "executableCode": { "language": "PYTHON", "code": "print(1 + 1)" } }),
+    );
+}
+
+#[test]
+fn code_execution_result() {
+    round_trip(
+        data(part::Data::CodeExecutionResult(
+            proto::CodeExecutionResult {
+                outcome: code_execution_result::Outcome::Failed as i32,
+                output: "ZeroDivisionError: division by zero".into(),
+            },
+        )),
+        json!({
+            "codeExecutionResult": {
+                "outcome": "OUTCOME_FAILED",
+                "output": "ZeroDivisionError: division by zero"
+            }
+        }),
+    );
+}
+
+#[test]
+fn unknown_enum_values_are_kept() {
+    round_trip(
+        data(part::Data::CodeExecutionResult(
+            proto::CodeExecutionResult {
+                outcome: 7,
+                output: String::new(),
+            },
+        )),
+        json!({ "codeExecutionResult": { "outcome": "7" } }),
+    );
+}
+
+#[test]
+fn wire_format() {
+    // `parts { text: "hi" } role: "user"`, as encoded by protoc
+    let bytes = [
+        0x0a, 0x04, 0x12, 0x02, b'h', b'i', 0x12, 0x04, b'u', b's', b'e', b'r',
+    ];
+    let content = Content::from(proto::Content::decode(bytes.as_slice()).unwrap());
+    assert_eq!(
+        content,
+        Content::text("hi").with_role(gemini_rust::Role::User)
+    );
+    assert_eq!(
+        proto::Content::try_from(content).unwrap().encode_to_vec(),
+        bytes
+    );
+}
+
+#[test]
+fn parts_without_data_are_skipped() {
+    let content = proto::Content {
+        parts: vec![
+            proto::Part::default(),
+            data(part::Data::Text("kept".into())),
+        ],
+        ..Default::default()
+    };
+    assert_eq!(Content::from(content), Content::text("kept"));
+}
+
+#[test]
+fn unknown_fields_are_kept() {
+    // `parts { text: "hi" 99: 7 } parts { 98: "new" } role: "user" 50: 1.5f`
+    let bytes = [
+        0x0a, 0x07, 0x12, 0x02, b'h', b'i', 0x98, 0x06, 0x07, // text part, field 99
+        0x0a, 0x06, 0x92, 0x06, 0x03, b'n', b'e', b'w', // part of an unknown kind
+        0x12, 0x04, b'u', b's', b'e', b'r', // role
+        0x95, 0x03, 0x00, 0x00, 0xc0, 0x3f, // field 50
+    ];
+    let decoded = proto::Content::decode(bytes.as_slice()).unwrap();
+    assert_eq!(decoded.encode_to_vec(), bytes);
+    assert_eq!(
+        decoded.parts[0].unknown_fields.as_bytes(),
+        [0x98, 0x06, 0x07]
+    );
+
+    let (content, attachment) = decoded.clone().split();
+    assert_eq!(
+        content,
+        Content::text("hi").with_role(gemini_rust::Role::User)
+    );
+    assert_eq!(attachment.parts.len(), 1);
+
+    // The unknown part is encoded after the role, which decodes to the same content
+    let joined = proto::Content::join(content.clone(), attachment).unwrap();
+    assert_eq!(joined.parts[0], decoded.parts[0]);
+    assert_eq!(
+        proto::Content::decode(joined.encode_to_vec().as_slice()).unwrap(),
+        decoded
+    );
+
+    // The plain conversions drop them
+    let plain = proto::Content::try_from(content).unwrap();
+    assert!(plain.unknown_fields.is_empty());
+    assert!(plain.parts[0].unknown_fields.is_empty());
+}
+
+#[test]
+fn invalid_base64_is_an_error() {
+    let content = Content::text("hi").with_role(gemini_rust::Role::User);
+    let mut parts = content.parts.clone().unwrap();
+    parts.push(gemini_rust::Part::InlineData {
+        inline_data: gemini_rust::Blob::new("image/png", "not base64!"),
+    });
+    let error = proto::Content::try_from(Content {
+        parts: Some(parts),
+        ..content
+    })
+    .unwrap_err();
+    assert!(matches!(error, proto::Error::InvalidBase64 { .. }));
+    assert_eq!(
+        error.to_string(),
+        "`parts[1].inlineData.data` is not valid base64"
+    );
+}
@@ -0,0 +1,452 @@
+//! Golden listing of the public API in `tests/fixtures/public_api.txt`.
+//!
+//! Every `pub` item of the crate's source is listed, one line each, in the spirit of
+//! `cargo public-api`: modules, types with their derives, public fields and enum variants,
+//! functions and methods with their full signatures, traits with their items, trait
+//! implementations, constants, and re-exports. Items of private modules are listed too, since
+//! they reach users through re-exports. Every module is walked whatever its features, so the
+//! listing does not depend on the features the tests are run with.
+//!
+//! The listing is compared with the fixture. A line missing from the current source is a
+//! potentially breaking change, a new line an addition. When a change is intended, regenerate
+//! the fixture and commit it with the change:
+//!
+//! ```text
+//! UPDATE_PUBLIC_API=1 cargo test --test public_api
+//! ```
+//!
+//! `tests/api_usage.rs` complements the listing by compiling code against the API.
+
+use quote::ToTokens;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+use syn::{
+    Attribute, Fields, FnArg, ImplItem, Item, ItemImpl, Pat, Signature, TraitItem, Type, UseTree,
+    Visibility,
+};
+
+const CRATE: &str = "gemini_rust";
+const FIXTURE: &str = "tests/fixtures/public_api.txt";
+
+/// Renders tokens on one line, without the spaces `TokenStream`'s `Display` puts around
+/// punctuation
+fn render(tokens: &impl ToTokens) -> String {
+    let mut text = tokens.to_token_stream().to_string();
+    for (from, to) in [
+        (" :: ", "::"),
+        (":: ", "::"),
+        (" ::", "::"),
+        (" < ", "<"),
+        ("< ", "<"),
+        (" <", "<"),
+        (" >", ">"),
+        (" ,", ","),
+        ("& ", "&"),
+        (" (", "("),
+        ("( ", "("),
+        (" )", ")"),
+        (" [", "["),
+        ("[ ", "["),
+        (" ]", "]"),
+        (" ;", ";"),
+        (" : ", ": "),
+        ("# [", "#["),
+        ("? ", "?"),
+        ("! (", "!("),
+        ("->(", "-> ("),
+        ("=(", "= ("),
+        (":(", ": ("),
+        // Trailing commas of signatures rustfmt spread over several lines
+        (",)", ")"),
+        (",>", ">"),
+    ] {
+        text = text.replace(from, to);
+    }
+    text
+}
+
+/// The attributes worth listing: derives, `non_exhaustive`, `deprecated`, and `cfg`
+fn attributes(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|attr| {
+            ["derive", "non_exhaustive", "deprecated", "cfg"]
+                .iter()
+                .any(|name| attr.path().is_ident(name))
+        })
+        .map(|attr| format!("{} ", render(attr)))
+        .collect()
+}
+
+fn is_public(vis: &Visibility) -> bool {
+    matches!(vis, Visibility::Public(_))
+}
+
+fn is_test_only(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .any(|attr| attr.path().is_ident("cfg") && render(&attr.meta).contains("test"))
+}
+
+/// Renders a signature with its function name qualified by `path`, without the `mut` of
+/// parameters, which is not part of the API
+fn signature(path: &str, sig: &Signature) -> String {
+    let mut sig = sig.clone();
+    for input in &mut sig.inputs {
+        match input {
+            FnArg::Receiver(receiver) if receiver.reference.is_none() => {
+                receiver.mutability = None;
+            }
+            FnArg::Typed(typed) => {
+                if let Pat::Ident(ident) = typed.pat.as_mut() {
+                    ident.mutability = None;
+                }
+            }
+            FnArg::Receiver(_) => {}
+        }
+    }
+    let rendered = render(&sig);
+    let name = format!("fn {}", sig.ident);
+    rendered.replacen(&name, &format!("fn {path}::{}", sig.ident), 1)
+}
+
+/// The name of a `Self` type such as `Foo<T>`, `Foo`, for qualifying its methods
+fn type_name(ty: &Type) -> String {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .map_or_else(|| render(ty), |segment| segment.ident.to_string()),
+        _ => render(ty),
+    }
+}
+
+/// Renders the fields of an enum variant, without their attributes
+fn variant_fields(fields: &Fields) -> String {
+    let rendered: Vec<String> = fields
+        .iter()
+        .map(|field| match &field.ident {
+            Some(name) => format!("{name}: {}", render(&field.ty)),
+            None => render(&field.ty),
+        })
+        .collect();
+    match fields {
+        Fields::Named(_) => format!(" {{ {} }}", rendered.join(", ")),
+        Fields::Unnamed(_) => format!("({})", rendered.join(", ")),
+        Fields::Unit => String::new(),
+    }
+}
+
+/// Lists the leaves of a re-export
+fn use_leaves(prefix: &str, tree: &UseTree, leaves: &mut Vec<(String, String)>) {
+    let join = |name: &str| match prefix {
+        "" => name.to_string(),
+        prefix => format!("{prefix}::{name}"),
+    };
+    match tree {
+        UseTree::Path(path) => use_leaves(&join(&path.ident.to_string()), &path.tree, leaves),
+        UseTree::Name(name) => {
+            let name = name.ident.to_string();
+            leaves.push((name.clone(), join(&name)));
+        }
+        UseTree::Rename(rename) => {
+            leaves.push((rename.rename.to_string(), join(&rename.ident.to_string())))
+        }
+        UseTree::Glob(_) => leaves.push(("*".to_string(), join("*"))),
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                use_leaves(prefix, tree, leaves);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Listing {
+    lines: BTreeSet<String>,
+    /// Names of the public types, whose impls are listed
+    types: BTreeSet<String>,
+    /// Impls with the module they are in, listed once every type is known
+    impls: Vec<(String, ItemImpl)>,
+}
+
+impl Listing {
+    fn add(&mut self, line: String) {
+        self.lines.insert(line);
+    }
+
+    /// Lists the items of the module at `path`, whose source lives in `dir`
+    fn items(&mut self, path: &str, dir: &Path, items: &[Item]) {
+        for item in items {
+            match item {
+                Item::Mod(module) if !is_test_only(&module.attrs) => {
+                    let name = module.ident.to_string();
+                    let module_path = format!("{path}::{name}");
+                    if is_public(&module.vis) {
+                        self.add(format!(
+                            "{}pub mod {module_path}",
+                            attributes(&module.attrs)
+                        ));
+                    }
+                    match &module.content {
+                        Some((_, items)) => self.items(&module_path, &dir.join(&name), items),
+                        None => self.module_file(&module_path, dir, &name),
+                    }
+                }
+                Item::Struct(item) if is_public(&item.vis) => {
+                    self.types.insert(item.ident.to_string());
+                    let name = format!("{path}::{}", item.ident);
+                    self.add(format!(
+                        "{}pub struct {name}{}{}",
+                        attributes(&item.attrs),
+                        render(&item.generics),
+                        item.generics
+                            .where_clause
+                            .as_ref()
+                            .map_or_else(String::new, |clause| format!(" {}", render(clause)))
+                    ));
+                    self.fields(&name, &item.fields);
+                }
+                Item::Enum(item) if is_public(&item.vis) => {
+                    self.types.insert(item.ident.to_string());
+                    let name = format!("{path}::{}", item.ident);
+                    self.add(format!(
+                        "{}pub enum {name}{}",
+                        attributes(&item.attrs),
+                        render(&item.generics)
+                    ));
+                    for variant in &item.variants {
+                        self.add(format!(
+                            "{}{name}::{}{}",
+                            attributes(&variant.attrs),
+                            variant.ident,
+                            variant_fields(&variant.fields)
+                        ));
+                    }
+                }
+                Item::Fn(item) if is_public(&item.vis) => {
+                    self.add(format!(
+                        "{}pub {}",
+                        attributes(&item.attrs),
+                        signature(path, &item.sig)
+                    ));
+                }
+                Item::Const(item) if is_public(&item.vis) => {
+                    self.add(format!(
+                        "pub const {path}::{}: {}",
+                        item.ident,
+                        render(&item.ty)
+                    ));
+                }
+                Item::Static(item) if is_public(&item.vis) => {
+                    self.add(format!(
+                        "pub static {path}::{}: {}",
+                        item.ident,
+                        render(&item.ty)
+                    ));
+                }
+                Item::Type(item) if is_public(&item.vis) => {
+                    self.add(format!(
+                        "pub type {path}::{}{} = {}",
+                        item.ident,
+                        render(&item.generics),
+                        render(&item.ty)
+                    ));
+                }
+                Item::Trait(item) if is_public(&item.vis) => self.r#trait(path, item),
+                Item::Impl(item) if !is_test_only(&item.attrs) => {
+                    self.impls.push((path.to_string(), item.clone()));
+                }
+                Item::Use(item) if is_public(&item.vis) => {
+                    let mut leaves = Vec::new();
+                    use_leaves("", &item.tree, &mut leaves);
+                    for (name, source) in leaves {
+                        self.add(format!(
+                            "{}pub use {path}::{name} = {source}",
+                            attributes(&item.attrs)
+                        ));
+                    }
+                }
+                Item::Macro(item)
+                    if item
+                        .attrs
+                        .iter()
+                        .any(|attr| attr.path().is_ident("macro_export")) =>
+                {
+                    if let Some(name) = &item.ident {
+                        self.add(format!("macro {CRATE}::{name}!"));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn fields(&mut self, name: &str, fields: &Fields) {
+        for (position, field) in fields.iter().enumerate() {
+            if !is_public(&field.vis) {
+                continue;
+            }
+            let field_name = field
+                .ident
+                .as_ref()
+                .map_or_else(|| position.to_string(), ToString::to_string);
+            self.add(format!(
+                "{}pub {name}::{field_name}: {}",
+                attributes(&field.attrs),
+                render(&field.ty)
+            ));
+        }
+    }
+
+    fn r#trait(&mut self, path: &str, item: &syn::ItemTrait) {
+        let name = format!("{path}::{}", item.ident);
+        let supertraits = match item.supertraits.is_empty() {
+            true => String::new(),
+            false => format!(": {}", render(&item.supertraits)),
+        };
+        self.add(format!(
+            "{}pub {}trait {name}{}{supertraits}",
+            attributes(&item.attrs),
+            if item.unsafety.is_some() {
+                "unsafe "
+            } else {
+                ""
+            },
+            render(&item.generics)
+        ));
+        for trait_item in &item.items {
+            match trait_item {
+                TraitItem::Fn(method) => {
+                    let provided = if method.default.is_some() {
+                        " (provided)"
+                    } else {
+                        ""
+                    };
+                    self.add(format!("{}{provided}", signature(&name, &method.sig)));
+                }
+                TraitItem::Type(ty) => {
+                    self.add(format!("type {name}::{}{}", ty.ident, render(&ty.generics)));
+                }
+                TraitItem::Const(constant) => {
+                    self.add(format!(
+                        "const {name}::{}: {}",
+                        constant.ident,
+                        render(&constant.ty)
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn r#impl(&mut self, path: &str, item: &ItemImpl) {
+        let type_name = type_name(&item.self_ty);
+        if !self.types.contains(&type_name) {
+            return;
+        }
+        let self_name = format!("{path}::{type_name}");
+        if let Some((negative, trait_path, _)) = &item.trait_ {
+            self.add(format!(
+                "{}impl{} {}{} for {path}::{}",
+                attributes(&item.attrs),
+                render(&item.generics),
+                if negative.is_some() { "!" } else { "" },
+                render(trait_path),
+                render(&item.self_ty)
+            ));
+            return;
+        }
+        for impl_item in &item.items {
+            match impl_item {
+                ImplItem::Fn(method) if is_public(&method.vis) => {
+                    let receiver = match method.sig.inputs.first() {
+                        Some(FnArg::Receiver(_)) => "",
+                        _ => " (associated)",
+                    };
+                    self.add(format!(
+                        "{}pub {}{receiver}",
+                        attributes(&method.attrs),
+                        signature(&self_name, &method.sig)
+                    ));
+                }
+                ImplItem::Const(constant) if is_public(&constant.vis) => {
+                    self.add(format!(
+                        "pub const {self_name}::{}: {}",
+                        constant.ident,
+                        render(&constant.ty)
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Lists the module `name` declared in a file of `dir`
+    fn module_file(&mut self, path: &str, dir: &Path, name: &str) {
+        let candidates = [
+            dir.join(format!("{name}.rs")),
+            dir.join(name).join("mod.rs"),
+        ];
+        let file = candidates
+            .iter()
+            .find(|candidate| candidate.exists())
+            .unwrap_or_else(|| panic!("no source file for module {path}"));
+        self.file(path, file, &dir.join(name));
+    }
+
+    fn file(&mut self, path: &str, file: &Path, dir: &Path) {
+        let source = std::fs::read_to_string(file).unwrap();
+        let parsed = syn::parse_file(&source)
+            .unwrap_or_else(|error| panic!("parsing {} failed: {error}", file.display()));
+        self.items(path, dir, &parsed.items);
+    }
+}
+
+/// Lists the public API of the crate's current source
+fn public_api() -> String {
+    let src = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src");
+    let mut listing = Listing::default();
+    listing.file(CRATE, &src.join("lib.rs"), &src);
+    for (path, item) in std::mem::take(&mut listing.impls) {
+        listing.r#impl(&path, &item);
+    }
+    let mut text = listing.lines.into_iter().collect::<Vec<_>>().join("\n");
+    text.push('\n');
+    text
+}
+
+#[test]
+fn public_api_matches_the_golden_listing() {
+    let current = public_api();
+    let fixture = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(FIXTURE);
+    if std::env::var_os("UPDATE_PUBLIC_API").is_some() {
+        std::fs::write(&fixture, &current).unwrap();
+        return;
+    }
+    let golden = std::fs::read_to_string(&fixture).unwrap_or_default();
+    if golden == current {
+        return;
+    }
+    let golden_lines: BTreeSet<&str> = golden.lines().collect();
+    let current_lines: BTreeSet<&str> = current.lines().collect();
+    let removed: Vec<_> = golden_lines.difference(&current_lines).collect();
+    let added: Vec<_> = current_lines.difference(&golden_lines).collect();
+    let mut report = String::new();
+    for line in &removed {
+        report.push_str(&format!("- {line}\n"));
+    }
+    for line in &added {
+        report.push_str(&format!("+ {line}\n"));
+    }
+    panic!(
+        "the public API differs from {FIXTURE}: {} item(s) removed or changed, which may break \
+         users, and {} added\n\n{report}\nIf the change is intended, regenerate the listing with \
+         `UPDATE_PUBLIC_API=1 cargo test --test public_api` and commit it.",
+        removed.len(),
+        added.len(),
+    );
+}
@@ -0,0 +1,164 @@
+//! Golden tests of `#[derive(GeminiSchema)]`.
+//!
+//! The schema of every type below is compared with `tests/schemas/<name>.json`. Set
+//! `UPDATE_SCHEMAS=1` to rewrite the files after an intended change.
+
+#![allow(dead_code)]
+
+use gemini_rust::{FunctionDeclaration, GeminiSchema, Schema, ToSchema};
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// A postal address
+#[derive(GeminiSchema)]
+struct Address {
+    /// Street and house number
+    street: String,
+    /// Postal code, kept as text
+    /// to preserve leading zeros
+    #[gemini(rename = "postalCode")]
+    postal_code: String,
+    country: Option<String>,
+}
+
+/// How a contact prefers to be reached
+#[derive(GeminiSchema)]
+enum Channel {
+    /// Electronic mail
+    Email,
+    #[gemini(rename = "phone")]
+    Phone,
+    #[gemini(skip)]
+    Pigeon,
+}
+
+/// A person extracted from a document.
+///
+/// Only people mentioned by name are extracted.
+#[derive(GeminiSchema)]
+struct Person {
+    /// Full name as written
+    name: String,
+    age: Option<u8>,
+    /// Every address mentioned
+    addresses: Vec<Address>,
+    channel: Channel,
+    score: f64,
+    #[gemini(skip)]
+    internal_id: u64,
+    r#type: Option<Box<[String]>>,
+}
+
+/// What the model found
+#[derive(GeminiSchema)]
+enum Finding {
+    /// Nothing relevant
+    None,
+    Person(Person),
+    /// A reference to another document
+    Reference {
+        /// The document title
+        title: String,
+        page: Option<u32>,
+    },
+}
+
+/// Documents are identified by their hash
+#[derive(GeminiSchema)]
+struct DocumentId(String);
+
+#[derive(GeminiSchema)]
+struct Page<T> {
+    items: Vec<T>,
+    next: Option<DocumentId>,
+}
+
+/// Compares `schema` with the golden file `name`
+fn golden(name: &str, schema: Schema) {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "schemas", name]
+        .iter()
+        .collect::<PathBuf>()
+        .with_extension("json");
+    let actual = Value::from(schema);
+    if std::env::var_os("UPDATE_SCHEMAS").is_some() {
+        let text = serde_json::to_string_pretty(&actual).unwrap() + "\n";
+        std::fs::write(&path, text).unwrap();
+        return;
+    }
+    let expected: Value = serde_json::from_str(
+        &std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("cannot read {}: {e}", path.display())),
+    )
+    .unwrap();
+    assert_eq!(
+        actual,
+        expected,
+        "schema of {name} differs from {}:\n{}",
+        path.display(),
+        serde_json::to_string_pretty(&actual).unwrap()
+    );
+}
+
+#[test]
+fn test_struct_schema() {
+    golden("address", Address::schema());
+    golden("person", Person::schema());
+}
+
+#[test]
+fn test_enum_schemas() {
+    golden("channel", Channel::schema());
+    golden("finding", Finding::schema());
+}
+
+#[test]
+fn test_newtype_and_generic_schemas() {
+    golden("page_of_addresses", Page::<Address>::schema());
+    assert_eq!(
+        DocumentId::schema().description.as_deref(),
+        Some("Documents are identified by their hash")
+    );
+    assert!(<Option<DocumentId>>::optional());
+}
+
+#[test]
+fn test_function_declaration_from_type() {
+    let declaration = FunctionDeclaration::from_type::<Address>("save_address");
+    let value = serde_json::to_value(&declaration).unwrap();
+    assert_eq!(value["description"], "A postal address");
+    assert!(value["parameters"].get("description").is_none());
+    assert_eq!(
+        value["parameters"]["required"],
+        serde_json::json!(["street", "postalCode"])
+    );
+}
+
+/// A meeting on the calendar
+#[cfg(all(feature = "chrono", feature = "uuid"))]
+#[derive(GeminiSchema, serde::Serialize, schemars::JsonSchema)]
+struct Meeting {
+    id: uuid::Uuid,
+    /// When the meeting starts
+    start: chrono::DateTime<chrono::Utc>,
+    day: chrono::NaiveDate,
+    reminder: Option<chrono::NaiveTime>,
+    /// Parsed by the application
+    #[gemini(format = "date-time")]
+    end: String,
+}
+
+#[cfg(all(feature = "chrono", feature = "uuid"))]
+#[test]
+fn test_formatted_field_schemas() {
+    golden("meeting", Meeting::schema());
+
+    // Parameters described by schemars carry the same formats
+    let declaration =
+        FunctionDeclaration::new("book", "Books a meeting", None).with_parameters::<Meeting>();
+    let value = serde_json::to_value(&declaration).unwrap();
+    assert_eq!(value["parameters"]["properties"]["id"]["format"], "uuid");
+    assert_eq!(
+        value["parameters"]["properties"]["start"]["format"],
+        "date-time"
+    );
+}
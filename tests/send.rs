@@ -0,0 +1,173 @@
+//! Compile-time checks that the public futures and streams are `Send`.
+//!
+//! Nothing here is awaited: a future holding a non-`Send` value across an `.await` fails to
+//! compile, so a regression breaks the build of this test rather than a caller's
+//! `tokio::spawn`.
+
+use futures::TryStreamExt;
+use gemini_rust::{
+    AggregatePolicy, ChunkingConfig, Document, FunctionCall, FunctionDeclaration, Gemini,
+    GenerationResponse, RunLogOptions, SpillPolicy, TokenBudget, ToolRegistry,
+};
+use serde_json::json;
+use std::time::Duration;
+
+fn assert_send<T: Send>(_: T) {}
+
+fn client() -> Gemini {
+    Gemini::new("test-key").unwrap()
+}
+
+fn registry() -> ToolRegistry {
+    ToolRegistry::new().with_function(
+        FunctionDeclaration::new("get_weather", "Gets the weather", None),
+        |_call: FunctionCall| async { Ok(json!({"sky": "clear"})) },
+    )
+}
+
+#[test]
+fn generation_futures_are_send() {
+    let client = client();
+    let request = || client.generate_content().with_user_message("Hello");
+    assert_send(request().execute());
+    assert_send(request().execute_as::<serde_json::Value>());
+    assert_send(request().execute_spoken());
+    assert_send(request().execute_stream());
+    assert_send(request().execute_stream_events());
+    assert_send(request().execute_stream_as_items::<serde_json::Value>());
+    assert_send(request().execute_stream_collected(SpillPolicy::default()));
+    assert_send(request().execute_stream_aggregated(AggregatePolicy::default()));
+    assert_send(request().count_tokens());
+    assert_send(client.count_tokens().with_user_message("Hello").execute());
+    assert_send(client.generate_content_raw("gemini-2.5-flash", json!({})));
+    assert_send(client.generate_content_raw_stream("gemini-2.5-flash", json!({})));
+    assert_send(client.race([request(), request()], |_: &GenerationResponse| true));
+    assert_send(
+        request()
+            .attributed_answer([Document::new("notes", "Notes")], "What is noted?")
+            .execute(),
+    );
+    assert_send(
+        client
+            .process_dataset(
+                0..3,
+                |n: &i32| n.to_string(),
+                TokenBudget::new(100),
+                "{rows}",
+            )
+            .execute(),
+    );
+    assert_send(client.health_check());
+}
+
+#[test]
+fn tool_loop_futures_are_send() {
+    let client = client();
+    let registry = registry();
+    assert_send(
+        client
+            .generate_content()
+            .with_user_message("Weather?")
+            .execute_with_tools(&registry),
+    );
+    assert_send(
+        client
+            .generate_content()
+            .with_user_message("Weather?")
+            .execute_with_tools_logged(&registry, RunLogOptions::new()),
+    );
+    assert_send(registry.call(FunctionCall::new("get_weather", json!({}))));
+
+    let mut chat = client.start_chat();
+    assert_send(chat.send_message_with_tools("Weather?", &registry));
+    let mut chat = client.start_chat();
+    assert_send(chat.send_message_with_tools_logged("Weather?", &registry, RunLogOptions::new()));
+    let mut chat = client.start_chat();
+    assert_send(chat.send_message("Hello"));
+    let mut chat = client.start_chat();
+    assert_send(chat.send_message_stream("Hello"));
+}
+
+#[test]
+fn file_cache_batch_and_embedding_futures_are_send() {
+    let client = client();
+    assert_send(client.create_file(b"hello".to_vec()).upload());
+    assert_send(client.create_file(b"hello".to_vec()).upload_guarded());
+    assert_send(
+        client
+            .files()
+            .upload(b"hello".to_vec(), mime::APPLICATION_OCTET_STREAM),
+    );
+    assert_send(client.cleanup_orphans("tmp-", Duration::from_secs(3600)));
+    assert_send(client.get_file("files/abc"));
+    assert_send(client.list_files(None));
+    assert_send(
+        client
+            .create_cache_from_file(b"hello".to_vec(), mime::TEXT_PLAIN)
+            .execute(),
+    );
+    let cache = client.get_cached_content("cachedContents/abc");
+    assert_send(cache.get());
+    assert_send(cache.delete());
+    assert_send(client.list_cached_contents(None));
+    assert_send(client.create_cache().with_user_message("Context").execute());
+    assert_send(
+        client
+            .create_cache()
+            .with_user_message("Context")
+            .execute_guarded(),
+    );
+    let batch = client.get_batch("batches/abc");
+    assert_send(batch.status());
+    assert_send(batch.cancel());
+    assert_send(client.list_batches(None));
+    assert_send(
+        client
+            .batch_generate_content()
+            .with_request(client.generate_content().with_user_message("Hi").build())
+            .execute(),
+    );
+    assert_send(client.embed_content().with_text("hello").execute());
+    assert_send(
+        client
+            .embed_document("hello world", ChunkingConfig::default())
+            .unwrap()
+            .execute(),
+    );
+    assert_send(client.embed_batch(["hello", "world"]).execute());
+}
+
+/// Streams and borrows held across awaits inside a caller's async fn, as moved into
+/// `tokio::spawn`
+#[test]
+fn futures_compose_into_spawnable_async_fns() {
+    async fn run(
+        client: Gemini,
+        registry: ToolRegistry,
+    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let mut texts = Vec::new();
+        let response = client
+            .generate_content()
+            .with_user_message("Weather?")
+            .execute_with_tools(&registry)
+            .await?;
+        texts.push(response.text());
+        let mut stream = Box::pin(
+            client
+                .generate_content()
+                .with_user_message("Tell a story")
+                .execute_stream()
+                .await?,
+        );
+        while let Some(chunk) = stream.try_next().await? {
+            texts.push(chunk.text());
+        }
+        let mut chat = client.start_chat();
+        chat.send_message_with_tools("And tomorrow?", &registry)
+            .await?;
+        let file = client.create_file(b"hello".to_vec()).upload().await?;
+        file.download().await?;
+        Ok(texts.len())
+    }
+    assert_send(run(client(), registry()));
+}
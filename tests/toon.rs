@@ -0,0 +1,262 @@
+//! Round-trip tests of TOON <-> JSON conversion over the corpus in `tests/toon/`.
+//!
+//! Every `<name>.toon` file is paired with `<name>.json`, its expected pretty-printed JSON.
+//! Both directions must reproduce the other file exactly, so key order, number formatting,
+//! and string quoting are all pinned. The corpus also round-trips under other
+//! [`toon::ToonOptions`].
+
+use gemini_rust::toon::{self, Delimiter, ToonOptions};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+fn corpus() -> Vec<(PathBuf, String, String)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/toon");
+    let mut pairs: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("cannot read {}: {e}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toon"))
+        .map(|path| {
+            let toon = std::fs::read_to_string(&path).unwrap();
+            let json = std::fs::read_to_string(path.with_extension("json"))
+                .unwrap_or_else(|e| panic!("missing JSON for {}: {e}", path.display()));
+            (
+                path,
+                toon.trim_end().to_string(),
+                json.trim_end().to_string(),
+            )
+        })
+        .collect();
+    pairs.sort();
+    assert!(pairs.len() >= 4, "corpus is missing from {}", dir.display());
+    pairs
+}
+
+#[test]
+fn corpus_converts_both_ways() {
+    for (path, toon, json) in corpus() {
+        let path = path.display();
+        assert_eq!(toon::to_json_string(&toon).unwrap(), json, "{path} to JSON");
+        assert_eq!(toon::from_json_str(&json).unwrap(), toon, "{path} to TOON");
+    }
+}
+
+#[test]
+fn default_options_keep_the_default_layout() {
+    for (path, toon, _) in corpus() {
+        let path = path.display();
+        let value: Value = toon::from_str(&toon).unwrap();
+        let options = ToonOptions::default();
+        assert_eq!(
+            toon::to_string_with(&value, &options).unwrap(),
+            toon::to_string(&value).unwrap(),
+            "{path}"
+        );
+        assert_eq!(
+            toon::from_str_with::<Value>(&toon, &options).unwrap(),
+            value,
+            "{path}"
+        );
+    }
+}
+
+#[test]
+fn corpus_round_trips_with_options() {
+    let combinations = [
+        ToonOptions::new()
+            .with_delimiter(Delimiter::Pipe)
+            .with_indent(4),
+        ToonOptions::new()
+            .with_delimiter(Delimiter::Tab)
+            .with_tables(false),
+        ToonOptions::new().with_indent(1),
+    ];
+    for options in combinations {
+        for (path, toon, _) in corpus() {
+            let path = path.display();
+            let value: Value = toon::from_str(&toon).unwrap();
+            let written = toon::to_string_with(&value, &options).unwrap();
+            let read: Value = toon::from_str_with(&written, &options)
+                .unwrap_or_else(|e| panic!("{path} with {options:?}: {e}\n{written}"));
+            assert_eq!(read, value, "{path} with {options:?}:\n{written}");
+        }
+    }
+}
+
+#[test]
+fn options_change_the_layout() {
+    #[derive(Serialize)]
+    struct Order {
+        id: &'static str,
+        tags: [&'static str; 2],
+        lines: [Line; 2],
+    }
+
+    #[derive(Serialize)]
+    struct Line {
+        sku: &'static str,
+        note: &'static str,
+    }
+
+    let order = Order {
+        id: "A-17",
+        tags: ["gift", "a|b"],
+        lines: [
+            Line {
+                sku: "P-1",
+                note: "fragile, handle with care",
+            },
+            Line {
+                sku: "P-7",
+                note: "none",
+            },
+        ],
+    };
+    let pipes = ToonOptions::new()
+        .with_delimiter(Delimiter::Pipe)
+        .with_indent(4);
+    assert_eq!(
+        toon::to_string_with(&order, &pipes).unwrap(),
+        "id: A-17\n\
+         tags[2|]: gift|\"a|b\"\n\
+         lines[2|]{sku|note}:\n    P-1|\"fragile, handle with care\"\n    P-7|none"
+    );
+
+    let lists = ToonOptions::new()
+        .with_delimiter(Delimiter::Tab)
+        .with_tables(false);
+    assert_eq!(
+        toon::to_string_with(&order, &lists).unwrap(),
+        "id: A-17\n\
+         tags[2\t]: gift\ta|b\n\
+         lines[2]:\n  - sku: P-1\n    note: \"fragile, handle with care\"\n  - sku: P-7\n    note: none"
+    );
+
+    // Parsing with the wrong width fails rather than misreading the nesting
+    let written = toon::to_string_with(&order, &pipes).unwrap();
+    assert!(matches!(
+        toon::from_str::<Value>(&written),
+        Err(toon::Error::Deserialize {
+            line: 3,
+            column: 6,
+            message,
+        }) if message == "expected 2 rows, found 0"
+    ));
+}
+
+#[test]
+fn tables_written_by_to_table_string_convert() {
+    #[derive(Serialize)]
+    struct Row {
+        id: u32,
+        city: &'static str,
+        rain_mm: f64,
+    }
+
+    let rows = [
+        Row {
+            id: 2,
+            city: "Porto",
+            rain_mm: 1.5,
+        },
+        Row {
+            id: 1,
+            city: "Lisbon",
+            rain_mm: 0.0,
+        },
+    ];
+    let table = toon::to_table_string(&rows).unwrap();
+    let json = toon::to_json_string(&table).unwrap();
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+        serde_json::to_value(&rows).unwrap()
+    );
+    assert_eq!(toon::from_json_str(&json).unwrap(), table);
+}
+
+#[test]
+fn errors_are_thread_safe() {
+    fn assert_error<E: std::error::Error + Send + Sync + 'static>() {}
+    assert_error::<toon::Error>();
+    assert_error::<gemini_rust::ToonError>();
+}
+
+#[test]
+fn malformed_documents_are_rejected() {
+    let error_at = |toon: &str| match toon::to_json_string(toon) {
+        Err(toon::Error::Deserialize { line, column, .. }) => (line, column),
+        other => panic!("expected a deserialization error for {toon:?}, got {other:?}"),
+    };
+    assert_eq!(error_at("a:\n   b: 1"), (2, 4));
+    assert_eq!(error_at("a: 1\n  b: 2"), (2, 3));
+    assert_eq!(error_at("a: 1\n- b"), (2, 1));
+    // Errors inside a line point at the offending token, not where the line starts
+    assert_eq!(error_at("a: \"open"), (1, 4));
+    assert_eq!(error_at("a: 1\nb: x, \"é\", \"open"), (2, 12));
+    assert_eq!(error_at("rows[1]{x,y}:\n  1"), (2, 4));
+    assert_eq!(error_at("rows[1]{x,y}:\n  1,2,3"), (2, 7));
+    assert_eq!(error_at("a:\n  tags[2]: x,y,z"), (2, 16));
+    assert_eq!(
+        toon::to_json_string("a:\n  b: \"open")
+            .unwrap_err()
+            .to_string(),
+        "failed to deserialize at line 2, column 6: unterminated quoted string"
+    );
+
+    // Missing items are reported at the header of their array
+    assert!(matches!(
+        toon::to_json_string("items[3]:\n  - 1\n  - 2"),
+        Err(toon::Error::Deserialize {
+            line: 1,
+            column: 6,
+            message,
+        }) if message == "expected 3 items, found 2"
+    ));
+    assert!(matches!(
+        toon::to_json_string("[2]: 1,2,3"),
+        Err(toon::Error::Deserialize {
+            line: 1,
+            column: 10,
+            message,
+        }) if message == "expected 2 items, found 3"
+    ));
+    assert!(matches!(
+        toon::from_json_str("{\"a\": }"),
+        Err(toon::Error::Deserialize {
+            line: 1,
+            column: 7,
+            message,
+        }) if message == "expected value"
+    ));
+}
+
+#[test]
+fn documents_that_do_not_fit_their_type_have_no_location() {
+    let error = toon::from_str::<Vec<u32>>("a: 1").unwrap_err();
+    assert!(
+        matches!(
+            error,
+            toon::Error::Deserialize {
+                line: 0,
+                column: 0,
+                ..
+            }
+        ),
+        "{error:?}"
+    );
+    assert!(error.to_string().starts_with("failed to deserialize: "));
+}
+
+#[test]
+fn readers_and_writers() {
+    let mut written = Vec::new();
+    toon::to_writer(&mut written, &json!({ "tags": ["a", "b"] })).unwrap();
+    assert_eq!(written, b"tags[2]: a,b");
+    let read: Value = toon::from_reader(written.as_slice()).unwrap();
+    assert_eq!(read, json!({ "tags": ["a", "b"] }));
+
+    assert!(matches!(
+        toon::from_reader::<_, Value>(&[0xff, 0xfe][..]),
+        Err(toon::Error::Io { .. })
+    ));
+}